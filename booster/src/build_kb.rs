@@ -4,6 +4,8 @@ pub struct Plugin {
     pub name: String,
     pub rust_name: String,
     pub source: PluginSource,
+    /// Plugins whose `init` must run before this one's, declared with `--after <name>`
+    pub after: Vec<String>,
 }
 
 pub enum PluginSource {
@@ -20,6 +22,8 @@ pub struct ParseError {
 pub fn parse(content: &str) -> Result<Vec<Plugin>, Vec<ParseError>> {
     let mut plugins = Vec::new();
     let mut errors = Vec::new();
+    // The build.kb line each plugin was declared on, parallel to `plugins`
+    let mut lines = Vec::new();
 
     for (i, raw_line) in content.lines().enumerate() {
         let line_num = i + 1;
@@ -29,18 +33,23 @@ pub fn parse(content: &str) -> Result<Vec<Plugin>, Vec<ParseError>> {
             continue;
         }
 
-        let tokens: Vec<&str> = trimmed
-            .split_whitespace()
-            .collect();
+        let all_tokens: Vec<&str> = trimmed.split_whitespace().collect();
 
-        if tokens[0] != "plugin" {
+        if all_tokens[0] != "plugin" {
             errors.push(ParseError {
                 line: line_num,
-                message: format!("unexpected token '{}'; expected 'plugin'", tokens[0]),
+                message: format!("unexpected token '{}'; expected 'plugin'", all_tokens[0]),
             });
             continue;
         }
 
+        // Flags trail the positional part of the declaration
+        let flag_start = all_tokens
+            .iter()
+            .position(|t| t.starts_with("--"))
+            .unwrap_or(all_tokens.len());
+        let (tokens, flags) = all_tokens.split_at(flag_start);
+
         if tokens.len() < 3 {
             errors.push(ParseError {
                 line: line_num,
@@ -49,12 +58,28 @@ pub fn parse(content: &str) -> Result<Vec<Plugin>, Vec<ParseError>> {
             continue;
         }
 
+        let after = match parse_flags(flags) {
+            Ok(after) => after,
+            Err(message) => {
+                errors.push(ParseError {
+                    line: line_num,
+                    message,
+                });
+                continue;
+            }
+        };
+
         let plugin_type = tokens[1];
         match plugin_type {
             "core" => {
                 let name = tokens[2].to_string();
                 let rust_name = name.replace('-', "_");
-                plugins.push(Plugin { name, rust_name, source: PluginSource::Core });
+                plugins.push(Plugin {
+                    name,
+                    rust_name,
+                    source: PluginSource::Core,
+                    after,
+                });
             }
             "git" => {
                 if tokens.len() < 4 {
@@ -67,7 +92,12 @@ pub fn parse(content: &str) -> Result<Vec<Plugin>, Vec<ParseError>> {
                 let name = tokens[2].to_string();
                 let url = tokens[3].to_string();
                 let rust_name = name.replace('-', "_");
-                plugins.push(Plugin { name, rust_name, source: PluginSource::Git(url) });
+                plugins.push(Plugin {
+                    name,
+                    rust_name,
+                    source: PluginSource::Git(url),
+                    after,
+                });
             }
             "path" => {
                 let path = PathBuf::from(tokens[2]);
@@ -89,7 +119,12 @@ pub fn parse(content: &str) -> Result<Vec<Plugin>, Vec<ParseError>> {
                 match extract_cargo_name(&cargo_content) {
                     Some(name) => {
                         let rust_name = name.replace('-', "_");
-                        plugins.push(Plugin { name, rust_name, source: PluginSource::Path(path) });
+                        plugins.push(Plugin {
+                            name,
+                            rust_name,
+                            source: PluginSource::Path(path),
+                            after,
+                        });
                     }
                     None => {
                         errors.push(ParseError {
@@ -112,13 +147,84 @@ pub fn parse(content: &str) -> Result<Vec<Plugin>, Vec<ParseError>> {
                 });
             }
         }
+
+        lines.resize(plugins.len(), line_num);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    order_plugins(plugins, &lines)
+}
+
+fn parse_flags(flags: &[&str]) -> Result<Vec<String>, String> {
+    let mut after = Vec::new();
+    let mut iter = flags.iter();
+    while let Some(flag) = iter.next() {
+        match *flag {
+            "--after" => match iter.next() {
+                Some(name) if !name.starts_with("--") => after.push(name.to_string()),
+                _ => return Err("'--after' requires a plugin name".to_string()),
+            },
+            other => return Err(format!("unknown flag '{}'; expected '--after'", other)),
+        }
+    }
+    Ok(after)
+}
+
+/// Orders plugins so each one comes after everything it declared with `--after`,
+/// otherwise keeping the order they were declared in. `lines` holds the build.kb line
+/// of each plugin for error reporting.
+fn order_plugins(plugins: Vec<Plugin>, lines: &[usize]) -> Result<Vec<Plugin>, Vec<ParseError>> {
+    let mut errors = Vec::new();
+    for (plugin, &line) in plugins.iter().zip(lines) {
+        for dep in &plugin.after {
+            if !plugins.iter().any(|p| &p.name == dep) {
+                errors.push(ParseError {
+                    line,
+                    message: format!(
+                        "plugin '{}' must run after '{}', which is not declared",
+                        plugin.name, dep
+                    ),
+                });
+            }
+        }
     }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut pending: Vec<(Plugin, usize)> =
+        plugins.into_iter().zip(lines.iter().copied()).collect();
+    let mut ordered: Vec<Plugin> = Vec::with_capacity(pending.len());
 
-    if errors.is_empty() {
-        Ok(plugins)
-    } else {
-        Err(errors)
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|(plugin, _)| {
+            plugin
+                .after
+                .iter()
+                .all(|dep| ordered.iter().any(|p| &p.name == dep))
+        });
+
+        match ready {
+            Some(idx) => ordered.push(pending.remove(idx).0),
+            None => {
+                return Err(pending
+                    .iter()
+                    .map(|(plugin, line)| ParseError {
+                        line: *line,
+                        message: format!(
+                            "plugin '{}' is part of an '--after' cycle",
+                            plugin.name
+                        ),
+                    })
+                    .collect());
+            }
+        }
     }
+
+    Ok(ordered)
 }
 
 fn extract_cargo_name(content: &str) -> Option<String> {
@@ -239,3 +345,74 @@ pub fn generate_lib_rs(plugins: &[Plugin]) -> String {
 
     lib
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(plugins: &[Plugin]) -> Vec<&str> {
+        plugins.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    fn messages(content: &str) -> Vec<(usize, String)> {
+        match parse(content) {
+            Ok(_) => panic!("expected build.kb to fail"),
+            Err(errors) => errors.into_iter().map(|e| (e.line, e.message)).collect(),
+        }
+    }
+
+    #[test]
+    fn plugins_keep_declared_order_without_dependencies() {
+        let plugins = parse("plugin core a\nplugin core b\nplugin core c").ok().unwrap();
+        assert_eq!(names(&plugins), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn after_moves_plugins_behind_their_dependencies() {
+        let plugins = parse(
+            "plugin core kerbin-lsp --after kerbin-tree-sitter\n\
+             plugin core tutor\n\
+             plugin core kerbin-tree-sitter\n\
+             plugin git extra https://example.com/extra --after kerbin-lsp --after tutor",
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(
+            names(&plugins),
+            vec!["tutor", "kerbin-tree-sitter", "kerbin-lsp", "extra"]
+        );
+        assert_eq!(plugins[2].after, vec!["kerbin-tree-sitter"]);
+    }
+
+    #[test]
+    fn missing_dependency_is_an_error() {
+        assert_eq!(
+            messages("plugin core a\nplugin core b --after c"),
+            vec![(
+                2,
+                "plugin 'b' must run after 'c', which is not declared".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn dependency_cycles_are_errors() {
+        let errors = messages("plugin core a --after b\nplugin core b --after a\nplugin core c");
+        assert_eq!(
+            errors.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn bad_flags_are_errors() {
+        assert_eq!(
+            messages("plugin core a --after"),
+            vec![(1, "'--after' requires a plugin name".to_string())]
+        );
+        assert_eq!(
+            messages("plugin core a --before b"),
+            vec![(1, "unknown flag '--before'; expected '--after'".to_string())]
+        );
+    }
+}
@@ -17,8 +17,9 @@ use std::{
 };
 
 #[derive(Parser)]
-#[clap(version, about)]
-/// Kerbin's custom designed booster to launch you into editing
+#[clap(version, about, infer_subcommands = true)]
+/// Kerbin's custom designed booster to launch you into editing.
+/// Subcommands can be shortened to any prefix that names only one of them
 pub struct Args {
     /// Path to the config directory (overrides the saved path)
     #[clap(short, long, global = true)]
@@ -48,9 +49,12 @@ pub enum SubCommand {
 
     /// Send a command to a running kerbin session
     Exec {
-        /// The session ID to target
+        /// The session ID or name to target
         #[clap(short, long)]
         session: String,
+        /// Milliseconds to wait for the command to run before giving up
+        #[clap(long, default_value_t = 5000)]
+        timeout: u64,
         /// The command to execute
         #[clap(num_args = 1.., required = true)]
         command: Vec<String>,
@@ -80,13 +84,13 @@ pub enum SessionCommand {
 
     /// Gracefully close a session (sends :q!)
     Close {
-        /// The session ID to close
+        /// The session ID or name to close
         session: String,
     },
 
     /// Open a file in a running session
     Open {
-        /// The session ID to target
+        /// The session ID or name to target
         #[clap(short, long)]
         session: String,
         /// The file to open
@@ -101,7 +105,7 @@ pub enum SessionCommand {
 
     /// Assign a human-readable name to a session
     Rename {
-        /// The session ID to rename
+        /// The session ID or name to rename
         session: String,
         /// The new name
         name: String,
@@ -439,17 +443,31 @@ fn compile_kerbin(build_dir: &Path, cargo_toml_path: &Path, generated_config_pat
     }
     println!("✓ Updated config path in Cargo.toml");
 
-    let style = ProgressStyle::default_spinner()
-        .template("{spinner:.green} {msg}")
-        .unwrap();
-
-    let build_bar = ProgressBar::new_spinner()
-        .with_message("Building Kerbin with Cargo".to_string())
-        .with_style(style);
+    // A real bar needs the number of crates up front. The last build's count is exact, the
+    // metadata estimate runs a little high; without either, fall back to a spinner.
+    let units_path = build_dir.join("target/booster-build-units");
+    let known_units = std::fs::read_to_string(&units_path)
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .or_else(|| count_build_units(build_dir));
+    let build_bar = match known_units {
+        Some(total) => ProgressBar::new(total).with_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{bar:30.green/white}] {pos}/{len} {msg}")
+                .unwrap()
+                .progress_chars("=> "),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} {msg}")
+                .unwrap(),
+        ),
+    };
+    build_bar.set_message("Building Kerbin with Cargo".to_string());
     build_bar.enable_steady_tick(Duration::from_millis(100));
 
     let mut child = match std::process::Command::new("cargo")
-        .args(["build", "--release", "-p", "kerbin"])
+        .args(["build", "--release", "-p", "kerbin", "--message-format", "json"])
         .current_dir(build_dir)
         .stderr(Stdio::piped())
         .stdout(Stdio::piped())
@@ -466,26 +484,63 @@ fn compile_kerbin(build_dir: &Path, cargo_toml_path: &Path, generated_config_pat
         }
     };
 
-    if let Some(stderr) = child.stderr.take() {
-        let mut reader = BufReader::new(stderr);
+    // Cargo's own output (downloads, summaries) arrives on stderr alongside the JSON on stdout
+    let stderr_thread = child.stderr.take().map(|stderr| {
+        let build_bar = build_bar.clone();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(stderr);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) != 0 {
+                let clean_line = line.trim();
+                if clean_line.contains("Downloading") || clean_line.contains("Updating") {
+                    build_bar.set_message(format!("Cargo: {}", clean_line));
+                } else if clean_line.starts_with("error") {
+                    build_bar.suspend(|| eprintln!("{}", clean_line));
+                }
+                line.clear();
+            }
+        })
+    });
+
+    let mut units = 0;
+    if let Some(stdout) = child.stdout.take() {
+        let mut reader = BufReader::new(stdout);
         let mut line = String::new();
         while reader.read_line(&mut line).unwrap_or(0) != 0 {
-            let clean_line = line.trim();
-            if clean_line.starts_with("Compiling") {
-                if let Some(pkg) = clean_line.split_whitespace().nth(1) {
-                    build_bar.set_message(format!("Building: {}", pkg));
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(&line) else {
+                line.clear();
+                continue;
+            };
+
+            match json["reason"].as_str() {
+                Some("compiler-artifact") if !is_build_script(&json) => {
+                    units += 1;
+                    build_bar.set_position(units);
+                    if known_units.is_some_and(|total| units > total) {
+                        build_bar.set_length(units);
+                    }
+                    if let Some(name) = json["target"]["name"].as_str() {
+                        build_bar.set_message(format!("Building: {}", name));
+                    }
+                }
+                Some("compiler-message") if json["message"]["level"] == "error" => {
+                    if let Some(rendered) = json["message"]["rendered"].as_str() {
+                        build_bar.suspend(|| eprint!("{}", rendered));
+                    }
+                }
+                Some("build-finished") => {
+                    build_bar.set_message("Finalizing build...".to_string());
                 }
-            } else if clean_line.starts_with("Finished") {
-                build_bar.set_message("Finalizing build...".to_string());
-            } else if clean_line.contains("Downloading") || clean_line.contains("Updating") {
-                build_bar.set_message(format!("Cargo: {}", clean_line));
-            } else if clean_line.contains("error") || clean_line.starts_with("error:") {
-                eprintln!("{}", clean_line);
+                _ => {}
             }
             line.clear();
         }
     }
 
+    if let Some(thread) = stderr_thread {
+        let _ = thread.join();
+    }
+
     let status = match child.wait() {
         Ok(s) => s,
         Err(e) => {
@@ -503,9 +558,130 @@ fn compile_kerbin(build_dir: &Path, cargo_toml_path: &Path, generated_config_pat
         std::process::exit(1);
     }
 
+    let _ = std::fs::write(&units_path, units.to_string());
     println!("✓ Successfully built Kerbin");
 }
 
+/// Estimates the crates a release build of `kerbin` compiles from `cargo metadata`'s resolve
+/// graph for the host. Returns `None` if the metadata can't be read.
+fn count_build_units(build_dir: &Path) -> Option<u64> {
+    let host = std::process::Command::new("rustc")
+        .arg("-vV")
+        .output()
+        .ok()
+        .and_then(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .lines()
+                .find_map(|l| l.strip_prefix("host: ").map(str::to_string))
+        })?;
+
+    let output = std::process::Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--filter-platform", &host])
+        .current_dir(build_dir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+
+    let root = metadata["packages"]
+        .as_array()?
+        .iter()
+        .find(|p| p["name"] == "kerbin")?["id"]
+        .as_str()?;
+
+    let nodes: std::collections::HashMap<&str, &serde_json::Value> = metadata["resolve"]["nodes"]
+        .as_array()?
+        .iter()
+        .filter_map(|n| Some((n["id"].as_str()?, n)))
+        .collect();
+
+    // Walk normal and build dependencies; dev dependencies aren't part of the build
+    let mut seen = std::collections::HashSet::from([root]);
+    let mut stack = vec![root];
+    while let Some(id) = stack.pop() {
+        let Some(deps) = nodes.get(id).and_then(|n| n["deps"].as_array()) else {
+            continue;
+        };
+
+        for dep in deps {
+            let is_dev = dep["dep_kinds"]
+                .as_array()
+                .is_some_and(|kinds| kinds.iter().all(|k| k["kind"] == "dev"));
+            if let Some(pkg) = dep["pkg"].as_str()
+                && !is_dev
+                && seen.insert(pkg)
+            {
+                stack.push(pkg);
+            }
+        }
+    }
+
+    Some(seen.len() as u64)
+}
+
+/// Build scripts get their own artifact message, but aren't a crate of their own
+fn is_build_script(artifact: &serde_json::Value) -> bool {
+    artifact["target"]["kind"]
+        .as_array()
+        .is_some_and(|kinds| kinds.iter().any(|k| k == "custom-build"))
+}
+
+/// Oldest Rust that builds Kerbin (edition 2024 with let chains)
+const MIN_RUST_VERSION: (u32, u32) = (1, 88);
+
+/// Checks that cargo and a new enough rustc are installed and that `kerbin_dir` is
+/// writable, so a broken setup fails here with instructions instead of midway through a build.
+/// Exits on failure.
+fn preflight(kerbin_dir: &Path) {
+    let version_of = |tool: &str| {
+        std::process::Command::new(tool)
+            .arg("--version")
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+    };
+
+    let (Some(_), Some(rustc)) = (version_of("cargo"), version_of("rustc")) else {
+        eprintln!("✗ Couldn't find cargo and rustc");
+        eprintln!("  Kerbin is built from source, so it needs a Rust toolchain");
+        eprintln!("  Install one with rustup: https://rustup.rs");
+        eprintln!("  Then make sure ~/.cargo/bin is in your PATH");
+        std::process::exit(1);
+    };
+
+    // "rustc 1.88.0 (6b00bc388 2025-06-23)"
+    let version = rustc.split_whitespace().nth(1).and_then(|v| {
+        let mut parts = v.split('.').map(|p| p.parse::<u32>().ok());
+        Some((parts.next()??, parts.next()??))
+    });
+
+    match version {
+        Some(version) if version >= MIN_RUST_VERSION => {}
+        Some(_) => {
+            eprintln!("✗ {} is too old", rustc);
+            eprintln!(
+                "  Kerbin needs Rust {}.{} or newer",
+                MIN_RUST_VERSION.0, MIN_RUST_VERSION.1
+            );
+            eprintln!("  Update with: rustup update stable");
+            std::process::exit(1);
+        }
+        None => eprintln!("[!] Warning: Couldn't read the Rust version from '{}'", rustc),
+    }
+
+    let probe = kerbin_dir.join(".booster-write-check");
+    let writable = std::fs::create_dir_all(kerbin_dir)
+        .and_then(|_| std::fs::write(&probe, ""))
+        .and_then(|_| std::fs::remove_file(&probe));
+    if let Err(e) = writable {
+        eprintln!("✗ Can't write to {}", kerbin_dir.display());
+        eprintln!("  Error: {}", e);
+        eprintln!("  Kerbin is built and installed there, so check its permissions");
+        std::process::exit(1);
+    }
+}
+
 /// Copies the compiled kerbin binary into `kerbin_dir/bin/`. Exits on failure.
 fn install_binary(kerbin_dir: &Path, build_dir: &Path) -> PathBuf {
     let bin_dir = kerbin_dir.join("bin");
@@ -562,6 +738,45 @@ fn build_kerbin(kerbin_dir: &Path, config_path: Option<PathBuf>, info: &mut Kerb
     info.save(kerbin_dir);
 }
 
+/// Whether the editor that owns a session is still running, judged by its pid file
+fn session_alive(session_id: &str) -> bool {
+    let Ok(pid) = std::fs::read_to_string(session_pid_path(session_id)) else {
+        return false;
+    };
+
+    std::process::Command::new("kill")
+        .args(["-0", pid.trim()])
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Removes the files of sessions whose editor exited without cleaning up (a crash or
+/// `session kill`), returning how many were removed
+fn remove_stale_sessions() -> usize {
+    let dir = sessions_dir();
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return 0;
+    };
+
+    let stale: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let filename = e.file_name().into_string().ok()?;
+            Some(filename.strip_suffix(".in")?.to_string())
+        })
+        .filter(|id| !session_alive(id))
+        .collect();
+
+    for id in &stale {
+        let _ = std::fs::remove_file(format!("{}/{}.in", dir, id));
+        let _ = std::fs::remove_file(session_pid_path(id));
+        let _ = std::fs::remove_file(session_name_path(id));
+    }
+
+    stale.len()
+}
+
 /// Resolve a session argument that may be either a UUID or a human-readable name
 fn resolve_session(input: &str) -> String {
     let dir = sessions_dir();
@@ -703,6 +918,8 @@ fn main() {
             }
         }
         SubCommand::Install => {
+            preflight(&kerbin_dir);
+
             println!("Fetching available versions from GitHub...");
             let tags_output = std::process::Command::new("git")
                 .args([
@@ -873,15 +1090,35 @@ fn main() {
             println!("✓ Installation complete!");
             println!("  Ensure to add ~/.kerbin/bin to your PATH");
         }
-        SubCommand::Exec { session, command } => {
+        SubCommand::Exec {
+            session,
+            timeout,
+            command,
+        } => {
+            let session = resolve_session(&session);
             let full_command = command.join(" ");
-            if let Err(e) = ClientIpc::send_command(&session, full_command) {
-                eprintln!("{}", e);
-                std::process::exit(1);
+            match ClientIpc::run_command(&session, full_command, Duration::from_millis(timeout)) {
+                Ok(reply) => {
+                    for line in &reply.output {
+                        println!("{}", line);
+                    }
+                    if !reply.success {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
             }
         }
         SubCommand::Session { command } => match command {
             SessionCommand::List => {
+                let removed = remove_stale_sessions();
+                if removed > 0 {
+                    println!("Removed {} stale session(s).", removed);
+                }
+
                 let dir = sessions_dir();
                 let mut sessions: Vec<(String, Option<String>)> = std::fs::read_dir(&dir)
                     .map(|rd| {
@@ -1037,6 +1274,8 @@ fn main() {
                 std::process::exit(1);
             }
 
+            preflight(&kerbin_dir);
+
             let mut info = KerbinInfo::load(&kerbin_dir)
                 .expect("Failed to load kerbin-info.json. Installation may be corrupted.");
 
@@ -1103,3 +1342,44 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> std::result::Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("booster").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn unique_prefixes_pick_their_subcommand() {
+        assert!(matches!(parse(&["inst"]).unwrap().command, SubCommand::Install));
+        assert!(matches!(parse(&["reb"]).unwrap().command, SubCommand::Rebuild));
+        assert!(matches!(
+            parse(&["sess", "ren", "1", "work"]).unwrap().command,
+            SubCommand::Session {
+                command: SessionCommand::Rename { .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn ambiguous_prefixes_list_the_candidates() {
+        let err = parse(&["in"]).err().unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidSubcommand);
+        let msg = err.to_string();
+        assert!(msg.contains("info") && msg.contains("install"), "{msg}");
+
+        assert!(parse(&["session", "k", "1"]).is_ok());
+        assert!(parse(&["nope"]).is_err());
+    }
+
+    #[test]
+    fn exact_names_win() {
+        assert!(matches!(parse(&["info"]).unwrap().command, SubCommand::Info));
+        assert!(matches!(
+            parse(&["list"]).unwrap().command,
+            SubCommand::List { .. }
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+[
+  (mod_item)
+  (foreign_mod_item)
+  (trait_item)
+  (impl_item)
+  (struct_item)
+  (enum_item)
+  (union_item)
+  (function_item)
+  (match_expression)
+  (closure_expression)
+  (macro_definition)
+  (macro_invocation)
+  (use_declaration)
+  (block)
+  (block_comment)
+] @fold
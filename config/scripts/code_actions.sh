@@ -0,0 +1,13 @@
+#!/usr/bin/env bash
+SESSION="$1"
+
+selection=$(printf '%s\n' "${@:2}" | fzf \
+  --delimiter=': ' \
+  --with-nth='2..' \
+  --prompt='Code action > ')
+
+if [[ -n "$selection" ]]; then
+  index=$(echo "$selection" | cut -d: -f1)
+
+  booster exec -s "$SESSION" "lsp-code-action-apply $index"
+fi
@@ -1,13 +1,18 @@
+use std::str::FromStr;
+
 use crate::Token;
 
 /// Type alias for a command parsing function generic over state type `S`.
 pub type CommandFn<S> =
-    Box<dyn Fn(&[Token]) -> Option<Result<Box<dyn Command<S>>, String>> + Send + Sync>;
+    Box<dyn Fn(&[Token]) -> Option<Result<Box<dyn Command<S>>, ArgError>> + Send + Sync>;
 
 /// Represents a set of registered commands, including its parser and command information.
 pub struct RegisteredCommandSet<S: Send + Sync + 'static> {
     pub parser: CommandFn<S>,
     pub infos: Vec<CommandInfo>,
+    /// Commands taken out of `infos` so they stop parsing, with the index each had there,
+    /// kept to be put back later
+    pub unregistered: Vec<(usize, CommandInfo)>,
 }
 
 /// Represents a command prefix configuration.
@@ -17,6 +22,77 @@ pub struct CommandPrefix {
     pub prefix_cmd: String,
     pub include: bool,
     pub list: Vec<String>,
+    /// How entries of `list` are matched against command names
+    pub match_kind: PrefixMatch,
+}
+
+/// How a `CommandPrefix` list entry is matched against a command's names
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrefixMatch {
+    /// The entry is one of the command's names
+    #[default]
+    Exact,
+    /// A name of the command starts with the entry
+    Prefix,
+    /// A name of the command matches the entry, where `*` matches any run of chars
+    /// and `?` matches a single char
+    Glob,
+}
+
+impl PrefixMatch {
+    pub fn matches(&self, pattern: &str, name: &str) -> bool {
+        match self {
+            Self::Exact => pattern == name,
+            Self::Prefix => name.starts_with(pattern),
+            Self::Glob => glob_match(pattern, name),
+        }
+    }
+}
+
+impl FromStr for PrefixMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Self::Exact),
+            "prefix" => Ok(Self::Prefix),
+            "glob" => Ok(Self::Glob),
+            _ => Err(format!("Expected `exact`, `prefix`, or `glob`, found: {s}")),
+        }
+    }
+}
+
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Last `*` seen and the name index it currently covers up to
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more char and retry
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
 /// Allows downcasting a `Box<dyn Command<S>>` to a concrete type for typed interception.
@@ -30,6 +106,118 @@ pub trait Command<S: Send + Sync + 'static>: CommandAny + Send + Sync {
     async fn apply(&self, state: &mut S) -> bool;
 }
 
+/// Completes the value of a command argument in the palette.
+/// Set on a field with `#[command(complete = "path")]`, `"buffer"`, or a path to a
+/// `fn(&str) -> Vec<String>`.
+#[derive(Debug, Clone, Copy)]
+pub enum ArgCompleter {
+    /// Filesystem paths, relative to the working directory
+    Path,
+    /// Titles of the open buffers
+    Buffer,
+    /// Candidates for the partial argument from a custom function
+    Custom(fn(&str) -> Vec<String>),
+}
+
+/// Inclusive bounds for an integer argument.
+/// Set on a field with `#[command(range = "1..=10")]`; open ends like `"1.."` are allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgRange {
+    pub min: Option<i128>,
+    pub max: Option<i128>,
+}
+
+impl ArgRange {
+    /// Checks `value` against the bounds
+    pub fn check(&self, value: i128) -> Result<(), String> {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if value < min || value > max => {
+                Err(format!("must be between {min} and {max}, got {value}"))
+            }
+            (Some(min), None) if value < min => Err(format!("must be at least {min}, got {value}")),
+            (None, Some(max)) if value > max => Err(format!("must be at most {max}, got {value}")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An argument a matched command couldn't parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgError {
+    /// Name of the argument (e.g. `"count"` or `"--count"`), if known
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl ArgError {
+    pub fn new(field: impl ToString, message: impl ToString) -> Self {
+        Self {
+            field: Some(field.to_string()),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Errors from custom `parser` functions don't name an argument
+impl From<String> for ArgError {
+    fn from(message: String) -> Self {
+        Self {
+            field: None,
+            message,
+        }
+    }
+}
+
+/// Why a list of tokens couldn't be turned into a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// There were no tokens to parse
+    Empty,
+    /// No registered command has this name
+    UnknownCommand(String),
+    /// The command got too few or too many positional arguments
+    ArityMismatch {
+        command: String,
+        min: usize,
+        max: usize,
+        found: usize,
+    },
+    /// The argument count fit, but a required flag was missing or an argument had the
+    /// wrong shape (e.g. a word where a list was expected)
+    BadArguments { command: String },
+    /// An argument failed to parse or was out of range
+    InvalidArgument { command: String, error: ArgError },
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "No command given"),
+            Self::UnknownCommand(name) => write!(f, "Unknown command `{name}`"),
+            Self::ArityMismatch {
+                command,
+                min,
+                max,
+                found,
+            } => {
+                let expected = match (min, max) {
+                    (min, max) if min == max => min.to_string(),
+                    (min, max) => format!("{min} to {max}"),
+                };
+                let plural = if *max == 1 { "" } else { "s" };
+                write!(f, "`{command}` takes {expected} argument{plural}, got {found}")
+            }
+            Self::BadArguments { command } => write!(f, "Invalid arguments for `{command}`"),
+            Self::InvalidArgument { command, error } => match &error.field {
+                Some(field) => write!(f, "Invalid `{field}` for `{command}`: {}", error.message),
+                None => write!(f, "Invalid arguments for `{command}`: {}", error.message),
+            },
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 #[derive(Debug)]
 pub struct CommandInfo {
     pub valid_names: Vec<String>,
@@ -39,29 +227,74 @@ pub struct CommandInfo {
     pub ignore_positional: Vec<usize>,
     /// Flag CLI names (e.g. `"--raw"`) whose values should NOT be expanded.
     pub ignore_flags: Vec<String>,
+    /// Completers for positional slots (0-based, excluding command name).
+    pub completers: Vec<(usize, ArgCompleter)>,
+    /// Bounds for integer arguments, keyed by the name used in `args`.
+    pub ranges: Vec<(String, ArgRange)>,
+    /// Fewest and most positional arguments the command accepts.
+    pub arity: (usize, usize),
+    /// Whether `key=value` words set the named flags (`--key`), in any order.
+    pub keyword: bool,
+    /// Group the command is listed under by `commands`, like `buffer` or `lsp`.
+    pub category: String,
 }
 
+/// Category of commands that weren't given one
+pub const UNCATEGORIZED: &str = "uncategorized";
+
 impl CommandInfo {
     pub fn new(
         names: impl IntoIterator<Item = impl ToString>,
         args: impl IntoIterator<Item = (impl ToString, impl ToString)>,
         desc: impl IntoIterator<Item = impl ToString>,
     ) -> Self {
+        let args: Vec<(String, String)> = args
+            .into_iter()
+            .map(|x| (x.0.to_string(), x.1.to_string()))
+            .collect();
+        let positional = args.iter().filter(|(name, _)| !name.starts_with("--")).count();
+
         Self {
             valid_names: names.into_iter().map(|x| x.to_string()).collect(),
-            args: args
-                .into_iter()
-                .map(|x| (x.0.to_string(), x.1.to_string()))
-                .collect(),
+            args,
             desc: desc.into_iter().map(|x| x.to_string()).collect(),
             ignore_positional: vec![],
             ignore_flags: vec![],
+            completers: vec![],
+            ranges: vec![],
+            arity: (positional, positional),
+            keyword: false,
+            category: UNCATEGORIZED.to_string(),
         }
     }
 
     pub fn check_name(&self, name: impl ToString) -> bool {
         self.valid_names.contains(&name.to_string())
     }
+
+    /// Returns the completer for the positional argument at `arg_idx`, if it has one
+    pub fn completer(&self, arg_idx: usize) -> Option<ArgCompleter> {
+        self.completers
+            .iter()
+            .find(|(idx, _)| *idx == arg_idx)
+            .map(|(_, completer)| *completer)
+    }
+
+    /// Returns the bounds of the argument named `arg` (e.g. `"count"` or `"--count"`)
+    pub fn range(&self, arg: &str) -> Option<ArgRange> {
+        self.ranges
+            .iter()
+            .find(|(name, _)| name == arg)
+            .map(|(_, range)| *range)
+    }
+
+    /// Returns whether `flag` (e.g. `"--extend"`) takes a value, based on its type name
+    pub fn flag_takes_value(&self, flag: &str) -> bool {
+        self.args
+            .iter()
+            .find(|(name, _)| name == flag)
+            .is_some_and(|(_, ty)| ty != "bool")
+    }
 }
 
 /// Provides command metadata (names, args, description). Not generic — metadata is
@@ -74,7 +307,63 @@ pub trait AsCommandInfo: CommandAny + Send + Sync {
 
 /// Allows a command type to be parsed from a token list for a specific state `S`.
 pub trait CommandFromStr<S: Send + Sync + 'static>: Command<S> + AsCommandInfo {
-    fn from_str(val: &[Token]) -> Option<Result<Box<dyn Command<S>>, String>>
+    fn from_str(val: &[Token]) -> Option<Result<Box<dyn Command<S>>, ArgError>>
     where
         Self: Sized;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_wildcards() {
+        let glob = PrefixMatch::Glob;
+
+        assert!(glob.matches("lsp-*", "lsp-hover"));
+        assert!(glob.matches("lsp-*", "lsp-"));
+        assert!(!glob.matches("lsp-*", "ls"));
+        assert!(glob.matches("*-file", "write-file"));
+        assert!(glob.matches("w?ite*", "write_file"));
+        assert!(glob.matches("*a*b", "xaxxab"));
+        assert!(!glob.matches("*a*b", "xaxxa"));
+    }
+
+    #[test]
+    fn range_reports_failed_bound() {
+        let range = ArgRange {
+            min: Some(1),
+            max: Some(10),
+        };
+
+        assert!(range.check(5).is_ok());
+        assert_eq!(range.check(0), Err("must be between 1 and 10, got 0".to_string()));
+
+        let open = ArgRange {
+            min: Some(1),
+            max: None,
+        };
+        assert!(open.check(i128::from(u64::MAX)).is_ok());
+        assert!(open.check(-1).is_err());
+    }
+
+    #[test]
+    fn parse_errors_explain_failure() {
+        let arity = ParseError::ArityMismatch {
+            command: "open".into(),
+            min: 1,
+            max: 2,
+            found: 0,
+        };
+        assert_eq!(arity.to_string(), "`open` takes 1 to 2 arguments, got 0");
+
+        let invalid = ParseError::InvalidArgument {
+            command: "repeat".into(),
+            error: ArgError::new("count", "must be at least 1, got 0"),
+        };
+        assert_eq!(
+            invalid.to_string(),
+            "Invalid `count` for `repeat`: must be at least 1, got 0"
+        );
+    }
+}
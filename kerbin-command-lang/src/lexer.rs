@@ -703,4 +703,35 @@ mod tests {
             panic!("Expected outer List");
         }
     }
+
+    #[test]
+    fn test_quoted_arg_between_words() {
+        assert_eq!(
+            tokenize(r#"a "b c" d"#).unwrap(),
+            vec![Token::from("a"), Token::from("b c"), Token::from("d")]
+        );
+        assert_eq!(
+            tokenize("open 'my file.rs'").unwrap(),
+            vec![Token::from("open"), Token::from("my file.rs")]
+        );
+    }
+
+    #[test]
+    fn test_escaped_quotes() {
+        assert_eq!(
+            tokenize(r#"echo "say \"hi\"" it\'s my\ file"#).unwrap(),
+            vec![
+                Token::from("echo"),
+                Token::from(r#"say "hi""#),
+                Token::from("it's"),
+                Token::from("my file"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_quotes_error() {
+        assert_eq!(tokenize(r#"open "my file"#), Err(LexError::UnclosedString));
+        assert_eq!(tokenize("open 'my file"), Err(LexError::UnclosedString));
+    }
 }
@@ -10,6 +10,25 @@ pub struct CommandState {
 
 impl CommandState {
     pub fn parse(val: &[Token]) -> Option<Self> {
+        Self::parse_with(val, false)
+    }
+
+    /// Parses like `parse`, but also reads `key=value` words as the flag `--key` set to
+    /// `value`, so named fields can be given in any order
+    pub fn parse_keywords(val: &[Token]) -> Option<Self> {
+        Self::parse_with(val, true)
+    }
+
+    /// Whether a bool flag is on: it was passed, and not given the value `false`
+    pub fn switch(&self, flag: &str) -> bool {
+        match self.flags.get(flag) {
+            Some(Some(Token::Word(v))) => v != "false",
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    fn parse_with(val: &[Token], keywords: bool) -> Option<Self> {
         let name = match val.first() {
             Some(Token::Word(s)) => s.clone(),
             _ => return None,
@@ -19,13 +38,26 @@ impl CommandState {
         let mut flags: BTreeMap<String, Option<Token>> = BTreeMap::new();
         let mut i = 1usize;
 
+        let keyword = |token: Option<&Token>| match token {
+            Some(Token::Word(s)) if keywords => {
+                split_keyword(s).map(|(k, v)| (k.to_string(), v.to_string()))
+            }
+            _ => None,
+        };
+
         while i < val.len() {
+            if let Some((key, value)) = keyword(val.get(i)) {
+                flags.insert(format!("--{key}"), Some(Token::Word(value)));
+                i += 1;
+                continue;
+            }
+
             match &val[i] {
                 Token::Word(s) if s.starts_with("--") => {
                     let flag_name = s.clone();
                     let has_value = match val.get(i + 1) {
                         Some(Token::Word(v)) if v.starts_with("--") => false,
-                        Some(_) => true,
+                        next @ Some(_) => keyword(next).is_none(),
                         None => false,
                     };
                     if has_value {
@@ -50,3 +82,13 @@ impl CommandState {
         })
     }
 }
+
+/// Splits a `key=value` word, where the key is a field name (letters, digits, `-`, `_`)
+fn split_keyword(word: &str) -> Option<(&str, &str)> {
+    let (key, value) = word.split_once('=')?;
+    let is_name = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    is_name.then_some((key, value))
+}
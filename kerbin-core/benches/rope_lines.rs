@@ -0,0 +1,32 @@
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use kerbin_core::RopeExts;
+use ropey::Rope;
+
+/// 200k lines of code-like text, with a few wide characters on each
+fn large_rope() -> Rope {
+    let line = "let value = some_function(argument, 漢字) + 42;\n";
+    Rope::from_str(&line.repeat(200_000))
+}
+
+fn lines_to_string(c: &mut Criterion) {
+    let rope = large_rope();
+    let lines = 50_000..150_000;
+
+    let mut group = c.benchmark_group("copy 100k lines");
+    group.bench_function("lines_to_string", |b| {
+        b.iter(|| black_box(&rope).lines_to_string(lines.clone()))
+    });
+    group.bench_function("naive per-line join", |b| {
+        b.iter(|| {
+            lines
+                .clone()
+                .map(|l| black_box(&rope).line(l).to_string())
+                .collect::<Vec<_>>()
+                .join("")
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lines_to_string);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use kerbin_core::{PickerState, Theme};
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Style},
+};
+
+/// A theme the size of the bundled ones
+fn theme() -> Theme {
+    let mut theme = Theme::default();
+    for i in 0..300 {
+        theme.register(format!("ts.scope{i}"), Style::default().fg(Color::Indexed(i as u8)));
+    }
+    theme.register("ui.text".to_string(), Style::default().fg(Color::White));
+    theme
+}
+
+/// Draws a palette list of 200 suggestions, each looking up its styles by fallback
+fn palette_suggestions(c: &mut Criterion) {
+    let theme = theme();
+    let items = (0..200).map(|i| format!("suggestion_{i}")).collect();
+    let suggestions = PickerState::<String>::new(items);
+    let area = Rect::new(0, 0, 60, 200);
+
+    c.bench_function("render 200 palette suggestions", |b| {
+        b.iter(|| {
+            let mut buf = Buffer::empty(area);
+            suggestions.render_rows(area, &mut buf, black_box(&theme));
+            buf
+        })
+    });
+}
+
+criterion_group!(benches, palette_suggestions);
+criterion_main!(benches);
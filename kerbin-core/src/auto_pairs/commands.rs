@@ -1,6 +1,7 @@
 use crate::*;
 
 #[derive(Clone, Debug, Command)]
+#[command(category = "editing")]
 pub enum AutoPairsCommand {
     #[command(drop_ident, name = "auto_pairs_add")]
     Add { open: char, close: char },
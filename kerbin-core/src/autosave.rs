@@ -0,0 +1,330 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crate::*;
+
+/// How far a dirty buffer is toward its next auto-save
+struct Pending {
+    version: u128,
+    changed_at: Instant,
+    changes: u128,
+}
+
+/// Progress of dirty buffers toward auto-saving, and when backups were last written.
+/// Updated each frame by `update_autosave`
+#[derive(State, Default)]
+pub struct AutoSave {
+    pending: HashMap<String, Pending>,
+    last_backup: Option<Instant>,
+}
+
+impl AutoSave {
+    /// Records that the buffer at `path` is at `version`, returning whether it should be
+    /// saved now: once `idle` passed since its last change, or after `max_changes` edits.
+    /// A zero `idle` or `max_changes` turns that trigger off. Clean buffers are forgotten
+    pub fn due(
+        &mut self,
+        path: &str,
+        version: u128,
+        dirty: bool,
+        now: Instant,
+        idle: Duration,
+        max_changes: usize,
+    ) -> bool {
+        if !dirty {
+            self.pending.remove(path);
+            return false;
+        }
+
+        let pending = self.pending.entry(path.to_string()).or_insert(Pending {
+            version,
+            changed_at: now,
+            changes: 1,
+        });
+        if pending.version != version {
+            pending.changes += version.abs_diff(pending.version);
+            pending.version = version;
+            pending.changed_at = now;
+        }
+
+        let due = (max_changes > 0 && pending.changes >= max_changes as u128)
+            || (!idle.is_zero() && now.duration_since(pending.changed_at) >= idle);
+        if due {
+            self.pending.remove(path);
+        }
+        due
+    }
+
+    /// Whether `interval` passed since backups were last written, starting the next
+    /// interval if it has
+    pub fn backup_due(&mut self, now: Instant, interval: Duration) -> bool {
+        let last = *self.last_backup.get_or_insert(now);
+        if now.duration_since(last) < interval {
+            return false;
+        }
+        self.last_backup = Some(now);
+        true
+    }
+}
+
+/// Folder holding the crash-recovery copies of unsaved buffers. Tests get a folder of
+/// their own under the temp dir, so they never touch the user's copies
+pub fn recovery_dir() -> PathBuf {
+    if cfg!(test) {
+        return std::env::temp_dir().join("kerbin-test-recovery");
+    }
+    let data_dir = dirs::data_dir()
+        .or_else(|| dirs::home_dir().map(|h| h.join(".local/share")))
+        .unwrap_or_else(|| PathBuf::from("/tmp"));
+    data_dir.join("kerbin").join("recovery")
+}
+
+/// Where the recovery copy of the file at `path` is kept. The path is flattened into the
+/// file name with its separators percent-encoded, so distinct paths never share a copy
+pub fn recovery_path(path: &str) -> PathBuf {
+    let name = path
+        .replace('%', "%25")
+        .replace('/', "%2F")
+        .replace('\\', "%5C");
+    recovery_dir().join(name)
+}
+
+/// Removes the recovery copy of `path`, once its changes are saved or thrown away
+pub fn discard_recovery(path: &str) {
+    let _ = std::fs::remove_file(recovery_path(path));
+}
+
+/// Contents of the recovery copy of `path`, when it holds changes the file doesn't have
+pub fn recovered_text(path: &str) -> Option<String> {
+    let recovered = std::fs::read_to_string(recovery_path(path)).ok()?;
+    let on_disk = std::fs::read_to_string(path).unwrap_or_default();
+    (recovered != on_disk).then_some(recovered)
+}
+
+/// Saves dirty buffers once `CoreConfig::autosave_ms` passed since their last change or
+/// after `CoreConfig::autosave_changes` edits, and every `CoreConfig::backup_ms` copies
+/// unsaved buffers to the recovery folder while `CoreConfig::backup` is set. Read-only and
+/// special buffers are left alone, and ones whose file changed on disk aren't saved. Saves
+/// skip the `SaveEvent`, so nothing reformats the text while it's being typed
+pub async fn update_autosave(
+    buffers: Res<Buffers>,
+    config: Res<CoreConfig>,
+    autosave: ResMut<AutoSave>,
+    log: Res<LogSender>,
+) {
+    get!(buffers, config, mut autosave, log);
+
+    let idle = Duration::from_millis(config.autosave_ms);
+    let autosaving = !idle.is_zero() || config.autosave_changes > 0;
+    let now = Instant::now();
+    let backing_up =
+        config.backup && autosave.backup_due(now, Duration::from_millis(config.backup_ms));
+    if !autosaving && !backing_up {
+        return;
+    }
+
+    for buf in &buffers.buffers {
+        let mut guard = buf.write().await;
+        let Some(text) = guard.downcast_mut::<TextBuffer>() else {
+            continue;
+        };
+        if (text.path.starts_with('<') && text.path.ends_with('>')) || text.is_readonly() {
+            continue;
+        }
+
+        // Files changed on disk are left for the user to reload or overwrite
+        if autosaving
+            && !text.stale
+            && autosave.due(
+                &text.path,
+                *text.version(),
+                text.dirty,
+                now,
+                idle,
+                config.autosave_changes,
+            )
+        {
+            if text.check_external_change(&text.path).is_err() {
+                // The watcher missed the change, so the buffer is marked like it would be
+                text.stale = true;
+            } else if let Err(e) = text.write_file_bare() {
+                log.high("core::autosave", format!("Failed to save {}: {e}", text.path));
+            }
+        }
+
+        if backing_up {
+            if !text.dirty {
+                // Saved, so there's nothing to recover anymore
+                discard_recovery(&text.path);
+            } else if let Err(e) = std::fs::create_dir_all(recovery_dir())
+                .and_then(|()| write_atomic(&recovery_path(&text.path), text.get_rope()))
+            {
+                log.high("core::backup", format!("Failed to back up {}: {e}", text.path));
+            }
+        }
+    }
+}
+
+/// Tells the user when a file that was just opened has unsaved changes to recover
+pub async fn offer_recovery(event: EventData<BufferOpenEvent>, log: Res<LogSender>) {
+    get!(Some(event), log);
+
+    if recovered_text(&event.path).is_some() {
+        log.high(
+            "core::backup",
+            format!(
+                "{} has unsaved changes from an earlier session, run `recover` to restore them",
+                event.path
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_after_enough_changes() {
+        let mut autosave = AutoSave::default();
+        let start = Instant::now();
+        let no_idle = Duration::ZERO;
+
+        assert!(!autosave.due("a.rs", 1, true, start, no_idle, 3));
+        assert!(!autosave.due("a.rs", 2, true, start, no_idle, 3));
+        // Nothing new happened, so the count stays put
+        assert!(!autosave.due("a.rs", 2, true, start, no_idle, 3));
+        assert!(autosave.due("a.rs", 3, true, start, no_idle, 3));
+
+        // Counting starts over after a save
+        assert!(!autosave.due("a.rs", 4, true, start, no_idle, 3));
+        assert!(autosave.due("a.rs", 6, true, start, no_idle, 3));
+
+        // Saving by hand resets the count too
+        assert!(!autosave.due("b.rs", 1, true, start, no_idle, 3));
+        assert!(!autosave.due("b.rs", 1, false, start, no_idle, 3));
+        assert!(!autosave.due("b.rs", 2, true, start, no_idle, 3));
+    }
+
+    #[test]
+    fn saves_once_idle() {
+        let mut autosave = AutoSave::default();
+        let start = Instant::now();
+        let idle = Duration::from_millis(500);
+
+        assert!(!autosave.due("a.rs", 1, true, start, idle, 0));
+        assert!(!autosave.due("a.rs", 2, true, start + idle / 2, idle, 0));
+        // The last change restarted the wait
+        assert!(!autosave.due("a.rs", 2, true, start + idle, idle, 0));
+        assert!(autosave.due("a.rs", 2, true, start + idle * 3 / 2, idle, 0));
+    }
+
+    fn temp_file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, text).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn files_changed_on_disk_are_not_autosaved() {
+        let path = temp_file("kerbin-autosave-changed-on-disk.txt", "on disk");
+
+        let mut buffer = TextBuffer::open(path.clone(), 4).unwrap();
+        buffer.rope = "in buffer".into();
+        buffer.dirty = true;
+        // As if the file was written by something else after it was read
+        buffer.changed = Some(std::time::SystemTime::UNIX_EPOCH);
+        let mut buffers = Buffers::default();
+        buffers.push_new(buffer).await;
+
+        let (_, log_sender) = LogState::new_with_channel();
+        let mut state = State::new();
+        state
+            .state(buffers)
+            .state(CoreConfig {
+                autosave_changes: 1,
+                backup: false,
+                ..CoreConfig::default()
+            })
+            .state(AutoSave::default())
+            .state(log_sender);
+
+        state.call(update_autosave).await;
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "on disk");
+        let buffers = state.lock_state::<Buffers>().await;
+        assert!(buffers.cur_text_buffer().await.unwrap().stale);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn recovery_is_offered_for_every_file_opened_in_a_frame() {
+        let paths = ["kerbin-recover-a.txt", "kerbin-recover-b.txt"]
+            .map(|name| temp_file(name, "saved"));
+        std::fs::create_dir_all(recovery_dir()).unwrap();
+        for path in &paths {
+            std::fs::write(recovery_path(path), "unsaved").unwrap();
+        }
+
+        let (log_state, log_sender) = LogState::new_with_channel();
+        let mut state = State::new();
+        state
+            .state(EventStorage::default())
+            .state(log_state)
+            .state(log_sender);
+
+        let recovery = EVENT_BUS
+            .subscribe::<BufferOpenEvent>()
+            .await
+            .system(offer_recovery);
+
+        // Like `kerbin a b`, both files open before the first frame resolves events
+        let mut buffers = Buffers::default();
+        for path in &paths {
+            buffers.open(path.clone(), 4, u64::MAX).await.unwrap();
+        }
+        EVENT_BUS.resolve(&mut state).await;
+        recovery.unsubscribe().await;
+
+        for path in &paths {
+            discard_recovery(path);
+        }
+
+        let mut log = state.lock_state::<LogState>().await;
+        log.poll_messages();
+        for path in &paths {
+            assert!(
+                log.history().iter().any(|e| e.message.starts_with(path.as_str())),
+                "no recovery offered for {path}"
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn saving_or_closing_discards_the_recovery_copy() {
+        let path = temp_file("kerbin-recover-saved.txt", "saved");
+        std::fs::create_dir_all(recovery_dir()).unwrap();
+
+        std::fs::write(recovery_path(&path), "older").unwrap();
+        let mut buffer = TextBuffer::open(path.clone(), 4).unwrap();
+        buffer.rope = "newer".into();
+        buffer.write_file_bare().unwrap();
+        assert!(recovered_text(&path).is_none());
+        assert!(!recovery_path(&path).exists());
+
+        std::fs::write(recovery_path(&path), "older").unwrap();
+        let mut buffers = Buffers::default();
+        buffers.open(path.clone(), 4, u64::MAX).await.unwrap();
+        buffers.close_buffer(buffers.selected_buffer).await;
+        assert!(!recovery_path(&path).exists());
+    }
+
+    #[test]
+    fn recovery_paths_stay_distinct() {
+        assert_ne!(recovery_path("/a//b"), recovery_path("/a%b"));
+        assert_ne!(recovery_path("/a/b"), recovery_path("/a%2Fb"));
+        assert_eq!(recovery_path("/a/b").parent(), Some(recovery_dir().as_path()));
+    }
+}
@@ -10,10 +10,8 @@ fn shift_cursors(
         if i == primary {
             continue;
         }
-        let start = *cursor.sel.start();
-        let end = *cursor.sel.end();
-        let (new_start, new_end) = f(start, end);
-        cursor.sel = new_start..=new_end;
+        let (new_start, new_end) = f(*cursor.sel().start(), *cursor.sel().end());
+        cursor.set_sel(new_start..=new_end);
     }
 }
 
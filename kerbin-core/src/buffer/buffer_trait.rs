@@ -1,15 +1,22 @@
 use crossterm::event::{KeyEvent, MouseEvent};
-use ratatui::prelude::{Rect, StatefulWidget, Widget};
-use std::any::Any;
+use ratatui::prelude::{Rect, StatefulWidget, Style, Widget};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+};
 
 use crate::{
-    CoreConfig, CursorRenderState, GutterWidget, InnerChunk, SafeRopeAccess, TextBuffer,
-    TextBufferWidget, Theme,
+    CoreConfig, CursorRenderState, ExtmarkKind, GutterConfig, GutterWidget, InnerChunk,
+    LineNumbers, SIGN_WIDTH, SafeRopeAccess, ShownWhitespace, SignColumn, TextBuffer,
+    TextBufferWidget, Theme, WhitespaceConfig,
 };
 
 pub struct RenderContext<'a> {
     pub theme: &'a Theme,
     pub core_config: &'a CoreConfig,
+    pub whitespace: &'a WhitespaceConfig,
+    pub gutter: &'a GutterConfig,
 }
 
 pub trait KerbinBuffer: Send + Sync + 'static {
@@ -33,6 +40,19 @@ pub trait KerbinBuffer: Send + Sync + 'static {
     /// Render the left gutter (optional — default renders nothing)
     fn render_gutter(&self, _area: Rect, _chunk: &mut InnerChunk, _ctx: &RenderContext) {}
 
+    /// Identifies everything `render` and `render_gutter` draw for a view of `area`, so an
+    /// unchanged buffer skips drawing (see [`InnerChunk::draw_version`]).
+    /// `None` (the default) draws every frame
+    fn render_version(&self, _area: Rect, _focused: bool, _ctx: &RenderContext) -> Option<u64> {
+        None
+    }
+
+    /// Width the gutter needs to render `height` rows, sign column included.
+    /// 0 leaves the buffer without a gutter.
+    fn gutter_width(&self, _height: u16, _config: &CoreConfig, _gutter: &GutterConfig) -> u16 {
+        0
+    }
+
     /// Handle a key event while this buffer is focused.
     /// Return `true` if the event was consumed.
     fn handle_key(&mut self, _event: &KeyEvent) -> bool {
@@ -76,15 +96,48 @@ impl KerbinBuffer for TextBuffer {
     }
 
     fn render(&mut self, area: Rect, chunk: &mut InnerChunk, focused: bool, ctx: &RenderContext) {
-        let tab_style = ctx.theme.get_fallback_default(["ui.text.tabs", "ui.text"]);
+        let mut tab_style = ctx.theme.get_fallback_default(["ui.text.tabs", "ui.text"]);
+        let mut tab_unit = ctx.core_config.tab_display_unit.clone();
+        let mut whitespace = None;
+
+        if ctx.core_config.list {
+            let style = ctx.theme.get_fallback_default(["ui.whitespace", "ui.text"]);
+            whitespace = Some(ShownWhitespace {
+                trailing: ctx.whitespace.trailing.chars().next(),
+                eol: ctx.whitespace.eol.clone(),
+                style,
+            });
+
+            // The glyph replaces the start of the tab without changing its width
+            if !ctx.whitespace.tab.is_empty() {
+                let width = tab_unit.chars().count();
+                tab_unit = ctx.whitespace.tab.chars().chain(std::iter::repeat(' ')).take(width).collect();
+                tab_style = style;
+            }
+        }
+
         let mut cursor_state = CursorRenderState::default();
         TextBufferWidget::new(self)
-            .with_vertical_scroll(self.renderer.byte_scroll)
+            .with_vertical_scroll(self.renderer.visual_scroll)
             .with_horizontal_scroll(self.renderer.h_scroll)
-            .with_tab_display_unit(ctx.core_config.tab_display_unit.clone())
+            .with_tab_display_unit(tab_unit)
             .with_tab_style(tab_style)
+            .with_whitespace(whitespace)
             .with_reveal_conceal_on_cursor_line(ctx.core_config.reveal_conceal_on_cursor_line)
+            .with_soft_wrap(ctx.core_config.wrap)
+            .with_cursorline(
+                ctx.core_config
+                    .cursorline
+                    .then(|| ctx.theme.get_fallback_default(["ui.cursorline"])),
+            )
+            .with_cursorcolumn(
+                ctx.core_config
+                    .cursorcolumn
+                    .then(|| ctx.theme.get_fallback_default(["ui.cursorcolumn", "ui.cursorline"])),
+            )
+            .with_fold_style(ctx.theme.get_fallback_default(["ui.fold", "ui.gutter"]))
             .render(area, chunk, &mut cursor_state);
+        self.renderer.screen_rows = cursor_state.rows;
         if focused {
             if let Some((cx, cy, shape)) = cursor_state.cursor {
                 chunk.set_cursor(0, cx, cy, shape);
@@ -96,8 +149,174 @@ impl KerbinBuffer for TextBuffer {
         }
     }
 
+    fn render_version(&self, area: Rect, focused: bool, ctx: &RenderContext) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        (self.version, focused, area.height).hash(&mut hasher);
+        (self.renderer.visual_scroll, self.renderer.h_scroll).hash(&mut hasher);
+        (&self.cursors, self.primary_cursor, &self.folds).hash(&mut hasher);
+
+        // Highlights, diagnostics and the cursors themselves all draw from extmarks
+        let scroll = self.folds.visible_line(self.renderer.visual_scroll);
+        let start = self.line_to_byte_clamped(scroll);
+        let end = self.line_to_byte_clamped(self.folds.lines_down(scroll, area.height as usize));
+        for mark in self.renderer.query_extmarks(start..end + 1) {
+            (&mark.namespace, &mark.byte_range, &mark.kind).hash(&mut hasher);
+        }
+        self.renderer.has_signs().hash(&mut hasher);
+
+        let core = ctx.core_config;
+        ctx.theme.revision().hash(&mut hasher);
+        (&core.tab_display_unit, core.list, core.wrap, &core.wrap_indicator).hash(&mut hasher);
+        (core.cursorline, core.cursorcolumn, core.reveal_conceal_on_cursor_line).hash(&mut hasher);
+        core.line_numbers.hash(&mut hasher);
+        (&ctx.whitespace.tab, &ctx.whitespace.trailing, &ctx.whitespace.eol).hash(&mut hasher);
+        (ctx.gutter.signcolumn, ctx.gutter.numberwidth).hash(&mut hasher);
+
+        Some(hasher.finish())
+    }
+
     fn render_gutter(&self, area: Rect, chunk: &mut InnerChunk, ctx: &RenderContext) {
-        GutterWidget::new(self.renderer.byte_scroll, self.len_lines(), ctx.theme)
+        let cursor_line = self.byte_to_line_clamped(self.primary_cursor().get_cursor_byte());
+        GutterWidget::new(self.renderer.visual_scroll, self.len_lines(), ctx.theme)
+            .with_line_numbers(ctx.core_config.line_numbers, cursor_line)
+            .with_signs(self.gutter_signs(area.height, ctx.gutter), self.sign_column(ctx.gutter))
+            .with_folds(self.gutter_folds(area.height, ctx.gutter))
+            .with_rows(&self.renderer.screen_rows, &ctx.core_config.wrap_indicator)
             .render(area, chunk);
     }
+
+    fn gutter_width(&self, height: u16, config: &CoreConfig, gutter: &GutterConfig) -> u16 {
+        // Absolute numbers size to the whole file, so the width holds while scrolling
+        let scroll = self.renderer.visual_scroll;
+        let lines = match config.line_numbers {
+            LineNumbers::Absolute => 0..self.len_lines(),
+            _ => scroll..(scroll + height as usize).min(self.len_lines()),
+        };
+        let cursor_line = self.byte_to_line_clamped(self.primary_cursor().get_cursor_byte());
+        let digits = config.line_numbers.max_digits(lines, cursor_line);
+
+        let signs = self.sign_column(gutter)
+            || !self.gutter_signs(height, gutter).is_empty()
+            || !self.gutter_folds(height, gutter).is_empty();
+        digits.max(gutter.numberwidth) + if signs { SIGN_WIDTH } else { 0 }
+    }
+}
+
+impl TextBuffer {
+    /// Whether `gutter` reserves the sign column for this buffer, visible signs or not
+    fn sign_column(&self, gutter: &GutterConfig) -> bool {
+        match gutter.signcolumn {
+            SignColumn::Yes => true,
+            SignColumn::No => false,
+            SignColumn::Auto => self.renderer.has_signs(),
+        }
+    }
+
+    /// Signs the gutter draws on the `height` visible lines, none when the column is off
+    fn gutter_signs(&self, height: u16, gutter: &GutterConfig) -> HashMap<usize, (String, Style)> {
+        if gutter.signcolumn == SignColumn::No {
+            return HashMap::new();
+        }
+        self.visible_signs(height)
+    }
+
+    /// First lines of the closed folds on the `height` visible lines, marked in the sign
+    /// column unless it's off
+    fn gutter_folds(&self, height: u16, gutter: &GutterConfig) -> HashSet<usize> {
+        if gutter.signcolumn == SignColumn::No {
+            return HashSet::new();
+        }
+        let scroll = self.renderer.visual_scroll;
+        let end = self.folds.lines_down(scroll, height as usize);
+        self.folds
+            .closed()
+            .iter()
+            .map(|r| *r.start())
+            .filter(|line| (scroll..end).contains(line) && !self.folds.is_hidden(*line))
+            .collect()
+    }
+
+    /// Sign extmarks on the `height` lines from the top of the view, keeping the
+    /// highest-priority sign on each line
+    fn visible_signs(&self, height: u16) -> HashMap<usize, (String, Style)> {
+        let scroll = self.renderer.visual_scroll;
+        let end_line = self.folds.lines_down(scroll, height as usize);
+        let start = self.line_to_byte_clamped(scroll);
+        let end = self.line_to_byte_clamped(end_line);
+
+        let mut signs = HashMap::new();
+        // Marks come back in ascending priority, so later ones replace earlier ones
+        for mark in self.renderer.query_extmarks(start..end.max(start + 1)) {
+            let ExtmarkKind::Sign { text, style } = &mark.kind else {
+                continue;
+            };
+
+            let line = self.byte_to_line_clamped(mark.byte_range.start);
+            if (scroll..end_line).contains(&line) && !self.folds.is_hidden(line) {
+                signs.insert(line, (text.clone(), *style));
+            }
+        }
+        signs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExtmarkBuilder, Insert};
+
+    #[test]
+    fn render_version_follows_what_is_drawn() {
+        let core_config = CoreConfig::default();
+        let whitespace = WhitespaceConfig::default();
+        let gutter = GutterConfig::default();
+        let area = Rect::new(0, 0, 20, 2);
+
+        let version = |buf: &TextBuffer, theme: &Theme| {
+            let ctx = RenderContext {
+                theme,
+                core_config: &core_config,
+                whitespace: &whitespace,
+                gutter: &gutter,
+            };
+            buf.render_version(area, true, &ctx).unwrap()
+        };
+
+        let mut theme = Theme::default();
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, "one\ntwo\nthree\n");
+
+        let first = version(&buf, &theme);
+        assert_eq!(version(&buf, &theme), first);
+        let mut seen = vec![first];
+
+        buf.primary_cursor_mut().set_sel(4..=6);
+        seen.push(version(&buf, &theme));
+
+        buf.renderer.visual_scroll = 1;
+        seen.push(version(&buf, &theme));
+
+        buf.action(Insert { byte: 0, content: "zero\n".to_string() });
+        seen.push(version(&buf, &theme));
+
+        theme.register("ui.text".to_string(), Style::new());
+        seen.push(version(&buf, &theme));
+
+        // Marks past the bottom of the view don't draw
+        let mark = |byte| {
+            let style = Style::new();
+            ExtmarkBuilder::new("test", byte).with_kind(ExtmarkKind::Highlight { style })
+        };
+        let end = buf.len();
+        buf.renderer.add_extmark(0, mark(end - 1));
+        assert_eq!(version(&buf, &theme), *seen.last().unwrap());
+
+        buf.renderer.add_extmark(0, mark(5));
+        seen.push(version(&buf, &theme));
+
+        let mut distinct = seen.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), seen.len());
+    }
 }
@@ -1,6 +1,9 @@
 use std::sync::Arc;
 
-use crate::{CloseEvent, EVENT_BUS, KerbinBuffer, Theme, get_canonical_path_with_non_existent};
+use crate::{
+    BufferCloseEvent, BufferOpenEvent, CloseEvent, EVENT_BUS, KerbinBuffer, Theme,
+    UnicodeWidthChar, UnicodeWidthStr, discard_recovery, get_canonical_path_with_non_existent,
+};
 
 use super::TextBuffer;
 use kerbin_macros::State;
@@ -10,12 +13,20 @@ use tokio::sync::{
     OwnedRwLockMappedWriteGuard, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock,
 };
 
-/// Stores all text buffers managed by the editor
-#[derive(Default, State)]
+/// Inactive tabs with longer names are shortened to their file name
+const MAX_TAB_NAME_WIDTH: usize = 24;
+
+/// Stores all text buffers managed by the editor.
+/// Always holds at least one buffer: a scratch buffer takes the place of the last one closed.
+#[derive(State)]
 pub struct Buffers {
     /// The index of the currently selected buffer in the `buffers` vector
     pub selected_buffer: usize,
 
+    /// The index of the buffer selected before the current one, which `buffer #` switches
+    /// back to. `None` until the selection first changes, or once that buffer is closed
+    pub alternate_index: Option<usize>,
+
     /// The horizontal scroll offset of the tab-bar (bufferline) in characters
     pub tab_scroll: usize,
 
@@ -26,7 +37,29 @@ pub struct Buffers {
     pub buffer_paths: Vec<String>,
 }
 
+impl Default for Buffers {
+    fn default() -> Self {
+        Self {
+            selected_buffer: 0,
+            alternate_index: None,
+            tab_scroll: 0,
+            buffers: vec![scratch_buffer()],
+            buffer_paths: vec![],
+        }
+    }
+}
+
+fn scratch_buffer() -> Arc<RwLock<dyn KerbinBuffer>> {
+    Arc::new(RwLock::new(TextBuffer::scratch()))
+}
+
 impl Buffers {
+    /// Returns a read lock to the currently selected buffer, or `None` if `buffers`
+    /// was emptied by hand or `selected_buffer` is out of range
+    pub async fn cur_buffer_opt(&self) -> Option<OwnedRwLockReadGuard<dyn KerbinBuffer>> {
+        Some(self.buffers.get(self.selected_buffer)?.clone().read_owned().await)
+    }
+
     /// Returns a read lock to the currently selected buffer
     pub async fn cur_buffer(&self) -> OwnedRwLockReadGuard<dyn KerbinBuffer> {
         self.buffers[self.selected_buffer]
@@ -102,62 +135,134 @@ impl Buffers {
 
     /// Changes the selected buffer by a given signed distance
     pub fn change_buffer(&mut self, dist: isize) {
-        self.selected_buffer = self
-            .selected_buffer
-            .saturating_add_signed(dist)
-            .clamp(0, self.buffers.len() - 1);
+        self.set_selected_buffer(self.selected_buffer.saturating_add_signed(dist));
     }
 
-    /// Sets the selected buffer to a specific index
+    /// Sets the selected buffer to a specific index. The previous one becomes the
+    /// alternate if the selection changed
     pub fn set_selected_buffer(&mut self, id: usize) {
-        self.selected_buffer = id.clamp(0, self.buffers.len() - 1);
+        let id = id.min(self.buffers.len().saturating_sub(1));
+        if id != self.selected_buffer {
+            self.alternate_index = Some(self.selected_buffer);
+        }
+        self.selected_buffer = id;
+    }
+
+    /// Selects the alternate buffer, making the current one the new alternate.
+    /// Returns the selected index, or `None` if there is no alternate
+    pub fn switch_to_alternate(&mut self) -> Option<usize> {
+        let alternate = self.alternate_index?;
+        self.set_selected_buffer(alternate);
+        Some(self.selected_buffer)
+    }
+
+    /// Moves the buffer at `from` to index `to`, shifting the buffers in between.
+    /// The same buffer stays selected. Returns `false` if either index is out of range.
+    pub fn move_buffer(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.buffers.len() || to >= self.buffers.len() {
+            return false;
+        }
+
+        let buf = self.buffers.remove(from);
+        self.buffers.insert(to, buf);
+        if from < self.buffer_paths.len() && to < self.buffer_paths.len() {
+            let path = self.buffer_paths.remove(from);
+            self.buffer_paths.insert(to, path);
+        }
+
+        self.selected_buffer = moved_index(self.selected_buffer, from, to);
+        self.alternate_index = self.alternate_index.map(|idx| moved_index(idx, from, to));
+        true
     }
 
     /// Closes the buffer at the given index
     pub async fn close_buffer(&mut self, idx: usize) {
         let buf = self.buffers.remove(idx);
+        let (path, dirty) = {
+            let guard = buf.read().await;
+            (guard.title(), guard.is_dirty())
+        };
+        if !dirty {
+            discard_recovery(&path);
+        }
 
         EVENT_BUS.emit(CloseEvent { buffer: buf }).await;
+        EVENT_BUS.emit(BufferCloseEvent { path }).await;
 
         if self.buffers.is_empty() {
-            self.buffers.push(
-                Arc::new(RwLock::new(TextBuffer::scratch())) as Arc<RwLock<dyn KerbinBuffer>>,
-            );
+            self.buffers.push(scratch_buffer());
         }
 
-        self.change_buffer(0); // Adjust selected_buffer to remain valid
+        // Adjust selected_buffer to remain valid
+        self.selected_buffer = self.selected_buffer.min(self.buffers.len() - 1);
+
+        // The alternate follows its buffer, and is forgotten with it
+        self.alternate_index = match self.alternate_index {
+            Some(alt) if alt == idx => None,
+            Some(alt) if alt > idx => Some(alt - 1),
+            alt => alt,
+        }
+        .filter(|&alt| alt != self.selected_buffer && alt < self.buffers.len());
+    }
+
+    /// Opens a buffer with the given file path, or selects it if it's already open.
+    /// Files over `big_file_threshold` bytes open in big file mode.
+    pub async fn open(
+        &mut self,
+        path: String,
+        default_tab_unit: usize,
+        big_file_threshold: u64,
+    ) -> std::io::Result<usize> {
+        if let Some(buffer_id) = self.find_path(&path).await {
+            self.set_selected_buffer(buffer_id);
+            Ok(buffer_id)
+        } else {
+            self.open_new(path, default_tab_unit, big_file_threshold).await
+        }
     }
 
-    /// Opens a buffer with the given file path
-    pub async fn open(&mut self, path: String, default_tab_unit: usize) -> std::io::Result<usize> {
-        let check_path = get_canonical_path_with_non_existent(&path)
+    /// Index of the buffer already holding the file at `path`
+    pub async fn find_path(&self, path: &str) -> Option<usize> {
+        let check_path = get_canonical_path_with_non_existent(path)
             .to_string_lossy()
             .into_owned();
 
-        let mut found_buffer_id: Option<usize> = None;
-
         for (i, buffer_arc) in self.buffers.iter().enumerate() {
-            let buffer_read = buffer_arc.read().await;
-
-            if buffer_read.title() == check_path {
-                found_buffer_id = Some(i);
-                break;
+            if buffer_arc.read().await.title() == check_path {
+                return Some(i);
             }
         }
 
-        if let Some(buffer_id) = found_buffer_id {
-            self.set_selected_buffer(buffer_id);
-            Ok(buffer_id)
-        } else {
-            let new_buffer =
-                Arc::new(RwLock::new(TextBuffer::open(path, default_tab_unit)?))
-                    as Arc<RwLock<dyn KerbinBuffer>>;
-            self.buffers.push(new_buffer);
-            let new_buffer_id = self.buffers.len() - 1;
-            self.set_selected_buffer(new_buffer_id);
-
-            Ok(new_buffer_id)
-        }
+        None
+    }
+
+    /// Opens the file at `path` in a new buffer and selects it, even if it's already open.
+    /// Prefer `open` unless a duplicate is intended.
+    pub async fn open_new(
+        &mut self,
+        path: String,
+        default_tab_unit: usize,
+        big_file_threshold: u64,
+    ) -> std::io::Result<usize> {
+        let buffer = TextBuffer::open_async(path, default_tab_unit, big_file_threshold).await?;
+        Ok(self.push_opened(buffer).await)
+    }
+
+    /// Adds a buffer read with `TextBuffer::open_async` and selects it, even if its file is
+    /// already open. Lets the file be read before the buffers are locked
+    pub async fn push_opened(&mut self, buffer: TextBuffer) -> usize {
+        let path = buffer.path.clone();
+        let new_buffer = Arc::new(RwLock::new(buffer)) as Arc<RwLock<dyn KerbinBuffer>>;
+        self.buffers.push(new_buffer);
+        let new_buffer_id = self.buffers.len() - 1;
+        self.set_selected_buffer(new_buffer_id);
+
+        let ext = std::path::Path::new(&path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().into_owned());
+        EVENT_BUS.emit(BufferOpenEvent { path, ext }).await;
+
+        new_buffer_id
     }
 
     /// Inserts a `TextBuffer` safely into the buffers, deduplicating by title
@@ -219,16 +324,8 @@ impl Buffers {
         let mut current_char_offset = 0;
 
         for (display_i, &global_i) in displayed_global_indices.iter().enumerate() {
-            let short_path = match self.buffer_paths.get(global_i) {
-                Some(p) => p,
-                None => continue,
-            };
-            let dirty = match self.buffers.get(global_i) {
-                Some(b) => b.read().await.is_dirty(),
-                None => continue,
-            };
-
-            let title = format!("   {} {} ", short_path, if dirty { "*" } else { " " });
+            let active = display_i == active_display_idx;
+            let Some(title) = self.tab_label(global_i, active).await else { continue; };
             let title_width = title.chars().count();
 
             let visible_range_start = tab_scroll;
@@ -236,10 +333,15 @@ impl Buffers {
             let tab_range_start = current_char_offset;
             let tab_range_end = current_char_offset + title_width;
 
-            let style = if display_i == active_display_idx {
-                theme.get_fallback_default(["ui.bufferline.selected", "ui.bufferline", "ui.text"])
+            let style = if active {
+                theme.get_fallback_default([
+                    "ui.bufferline.active",
+                    "ui.bufferline.selected",
+                    "ui.bufferline",
+                    "ui.text",
+                ])
             } else {
-                theme.get_fallback_default(["ui.bufferline", "ui.text"])
+                theme.get_fallback_default(["ui.bufferline.inactive", "ui.bufferline", "ui.text"])
             };
 
             let overlap_start = visible_range_start.max(tab_range_start);
@@ -264,6 +366,21 @@ impl Buffers {
         }
     }
 
+    /// The bufferline tab text for the buffer at `idx`, with a `●` when it has unsaved changes.
+    /// The active tab shows the buffer's full path, other tabs their unique path, shortened
+    /// to the file name when it is long.
+    pub async fn tab_label(&self, idx: usize, active: bool) -> Option<String> {
+        let buf = self.buffers.get(idx)?.read().await;
+        let name = if active {
+            buf.title()
+        } else {
+            ellipsize_path(self.buffer_paths.get(idx)?, MAX_TAB_NAME_WIDTH)
+        };
+        let marker = if buf.is_dirty() { '●' } else { ' ' };
+
+        Some(format!("   {name} {marker} "))
+    }
+
     /// Updates the unique, shortened paths for all currently open buffers
     pub async fn update_paths(&mut self) {
         let mut paths: Vec<String> = Vec::with_capacity(self.buffers.len());
@@ -283,6 +400,46 @@ impl Buffers {
     }
 }
 
+/// Where the element at `idx` ends up after the element at `from` is moved to `to`
+pub fn moved_index(idx: usize, from: usize, to: usize) -> usize {
+    if idx == from {
+        to
+    } else if from < idx && idx <= to {
+        idx - 1
+    } else if to <= idx && idx < from {
+        idx + 1
+    } else {
+        idx
+    }
+}
+
+/// Shortens `path` to `…/<file name>` when it is wider than `max_width`,
+/// cutting the file name itself short if that is still too wide
+fn ellipsize_path(path: &str, max_width: usize) -> String {
+    if path.width() <= max_width {
+        return path.to_string();
+    }
+
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let shortened = format!("…/{name}");
+    if shortened.width() <= max_width {
+        return shortened;
+    }
+
+    let mut out = String::new();
+    let mut width = 0;
+    for ch in name.chars() {
+        let ch_width = ch.width().unwrap_or(0);
+        if width + ch_width + 1 > max_width {
+            break;
+        }
+        out.push(ch);
+        width += ch_width;
+    }
+    out.push('…');
+    out
+}
+
 fn get_unique_paths(paths: impl Iterator<Item = String>, len: usize) -> Vec<String> {
     if len == 0 {
         return vec![];
@@ -337,3 +494,207 @@ fn get_unique_paths(paths: impl Iterator<Item = String>, len: usize) -> Vec<Stri
 
     truncated_paths
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[derive(State, Default)]
+    struct Lifecycle(Vec<String>);
+
+    async fn record_open(event: EventData<BufferOpenEvent>, lifecycle: ResMut<Lifecycle>) {
+        get!(Some(event), mut lifecycle);
+        let ext = event.ext.clone().unwrap_or_default();
+        lifecycle.0.push(format!("open {} ({ext})", event.path));
+    }
+
+    async fn record_close(event: EventData<BufferCloseEvent>, lifecycle: ResMut<Lifecycle>) {
+        get!(Some(event), mut lifecycle);
+        lifecycle.0.push(format!("close {}", event.path));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn opening_and_closing_emit_lifecycle_events() {
+        let mut state = State::new();
+        state
+            .state(EventStorage::default())
+            .state(Lifecycle::default());
+
+        let open = EVENT_BUS.subscribe::<BufferOpenEvent>().await.system(record_open);
+        let close = EVENT_BUS.subscribe::<BufferCloseEvent>().await.system(record_close);
+
+        let path = std::env::temp_dir().join("kerbin-lifecycle-events.rs");
+        std::fs::write(&path, "fn main() {}").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let mut buffers = Buffers::default();
+        let id = buffers.open(path.clone(), 4, u64::MAX).await.unwrap();
+        let stored = buffers.buffers[id].read().await.title();
+
+        // Focusing the already open file opens nothing new
+        buffers.open(path, 4, u64::MAX).await.unwrap();
+        EVENT_BUS.resolve(&mut state).await;
+
+        buffers.close_buffer(id).await;
+        EVENT_BUS.resolve(&mut state).await;
+
+        open.unsubscribe().await;
+        close.unsubscribe().await;
+
+        // Other tests open buffers too, so only this file's events are checked
+        let events: Vec<String> = state
+            .lock_state::<Lifecycle>()
+            .await
+            .0
+            .iter()
+            .filter(|event| event.contains("kerbin-lifecycle-events"))
+            .cloned()
+            .collect();
+        assert_eq!(events, [format!("open {stored} (rs)"), format!("close {stored}")]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn every_buffer_opened_in_a_frame_emits_its_event() {
+        let mut state = State::new();
+        state
+            .state(EventStorage::default())
+            .state(Lifecycle::default());
+
+        let open = EVENT_BUS.subscribe::<BufferOpenEvent>().await.system(record_open);
+        let close = EVENT_BUS.subscribe::<BufferCloseEvent>().await.system(record_close);
+
+        // Like `kerbin a.rs b.rs`, both files open before the first frame resolves events
+        let mut buffers = Buffers::default();
+        let mut stored = vec![];
+        for name in ["kerbin-frame-open-a.rs", "kerbin-frame-open-b.rs"] {
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, "fn main() {}").unwrap();
+            let id = buffers.open(path.to_string_lossy().into_owned(), 4, u64::MAX).await.unwrap();
+            stored.push(buffers.buffers[id].read().await.title());
+        }
+        EVENT_BUS.resolve(&mut state).await;
+
+        let last = buffers.buffers.len() - 1;
+        buffers.close_buffer(last).await;
+        buffers.close_buffer(last - 1).await;
+        EVENT_BUS.resolve(&mut state).await;
+
+        open.unsubscribe().await;
+        close.unsubscribe().await;
+
+        let events: Vec<String> = state
+            .lock_state::<Lifecycle>()
+            .await
+            .0
+            .iter()
+            .filter(|event| event.contains("kerbin-frame-open"))
+            .cloned()
+            .collect();
+        assert_eq!(
+            events,
+            [
+                format!("open {} (rs)", stored[0]),
+                format!("open {} (rs)", stored[1]),
+                format!("close {}", stored[1]),
+                format!("close {}", stored[0]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn closing_every_buffer_leaves_a_scratch() {
+        let mut buffers = Buffers::default();
+        buffers.push_new(TextBuffer { path: "a.rs".into(), ..TextBuffer::scratch() }).await;
+        buffers.push_new(TextBuffer { path: "b.rs".into(), ..TextBuffer::scratch() }).await;
+        assert_eq!(buffers.buffers.len(), 3);
+
+        for _ in 0..5 {
+            buffers.close_buffer(buffers.selected_buffer).await;
+        }
+
+        assert_eq!(buffers.buffers.len(), 1);
+        assert_eq!(buffers.selected_buffer, 0);
+        assert!(buffers.cur_buffer_opt().await.is_some());
+        assert!(buffers.cur_text_buffer().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn alternate_is_the_previously_selected_buffer() {
+        let mut buffers = Buffers::default();
+        for path in ["a.rs", "b.rs", "c.rs"] {
+            buffers.push_new(TextBuffer { path: path.into(), ..TextBuffer::scratch() }).await;
+        }
+        // Opening c.rs left b.rs as the alternate
+        assert_eq!((buffers.selected_buffer, buffers.alternate_index), (3, Some(2)));
+
+        buffers.set_selected_buffer(1);
+        assert_eq!(buffers.alternate_index, Some(3));
+        assert_eq!(buffers.switch_to_alternate(), Some(3));
+        assert_eq!(buffers.alternate_index, Some(1));
+        assert_eq!(buffers.switch_to_alternate(), Some(1));
+
+        // Reselecting the current buffer keeps the alternate
+        buffers.set_selected_buffer(1);
+        assert_eq!(buffers.alternate_index, Some(3));
+
+        // Closing an earlier buffer shifts the alternate with its buffer
+        buffers.close_buffer(0).await;
+        assert_eq!(buffers.alternate_index, Some(2));
+
+        // Closing the alternate forgets it
+        buffers.close_buffer(2).await;
+        assert_eq!(buffers.alternate_index, None);
+        assert_eq!(buffers.switch_to_alternate(), None);
+    }
+
+    #[tokio::test]
+    async fn opening_an_open_file_focuses_it() {
+        let path = std::env::temp_dir()
+            .join("kerbin-opening-an-open-file.rs")
+            .to_string_lossy()
+            .into_owned();
+        let mut buffers = Buffers::default();
+
+        let first = buffers.open(path.clone(), 4, u64::MAX).await.unwrap();
+        buffers.set_selected_buffer(0);
+        let second = buffers.open(path.clone(), 4, u64::MAX).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(buffers.selected_buffer, first);
+        assert_eq!(buffers.buffers.len(), 2);
+
+        let duplicate = buffers.open_new(path, 4, u64::MAX).await.unwrap();
+        assert_ne!(duplicate, first);
+        assert_eq!(buffers.buffers.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn moving_a_buffer_keeps_it_selected() {
+        let mut buffers = Buffers::default();
+        for path in ["a.rs", "b.rs", "c.rs"] {
+            buffers.push_new(TextBuffer { path: path.into(), ..TextBuffer::scratch() }).await;
+        }
+        buffers.update_paths().await;
+        buffers.set_selected_buffer(1);
+
+        assert!(buffers.move_buffer(1, 3));
+        assert_eq!(buffers.selected_buffer, 3);
+        assert_eq!(buffers.buffer_paths[1..], ["b.rs", "c.rs", "a.rs"]);
+        assert_eq!(buffers.cur_buffer().await.title(), "a.rs");
+
+        assert!(!buffers.move_buffer(3, 4));
+        assert_eq!(moved_index(2, 3, 0), 3);
+        assert_eq!(moved_index(0, 3, 0), 1);
+        assert_eq!(moved_index(3, 0, 2), 3);
+    }
+
+    #[test]
+    fn long_tab_names_shorten_to_the_file_name() {
+        assert_eq!(ellipsize_path("src/main.rs", 24), "src/main.rs");
+        assert_eq!(
+            ellipsize_path("kerbin-core/src/buffer/render/mod.rs", 24),
+            "…/mod.rs"
+        );
+        assert_eq!(ellipsize_path("a/very_long_file_name_indeed.rs", 12), "very_long_f…");
+    }
+}
@@ -1,52 +1,129 @@
 use std::ops::RangeInclusive;
 
-/// A selected point of text within a `TextBuffer`
-#[derive(Clone, Debug)]
+/// A selected point of text within a `TextBuffer`.
+///
+/// The selection runs from an anchor, which stays put while the selection is extended,
+/// to the head the caret sits on. Both ends are inclusive, and `sel` orders them, so the
+/// head is at its start once the selection was extended back past the anchor.
+#[derive(Clone, Debug, Hash)]
 pub struct Cursor {
-    pub(crate) at_start: bool,
-    pub(crate) sel: RangeInclusive<usize>,
+    anchor: usize,
+    head: usize,
+    /// `anchor` and `head` in ascending order
+    sel: RangeInclusive<usize>,
 }
 
 impl Default for Cursor {
     fn default() -> Self {
-        Self {
-            at_start: false,
-            sel: 0..=0, // Default to a collapsed selection at byte 0
-        }
+        // Default to a collapsed selection at byte 0
+        Self::new(0, 0)
     }
 }
 
 impl Cursor {
+    /// Creates a selection from `anchor` to `head`, in either order
+    pub fn new(anchor: usize, head: usize) -> Self {
+        Self {
+            anchor,
+            head,
+            sel: anchor.min(head)..=anchor.max(head),
+        }
+    }
+
     /// Returns the byte position of where the actual cursor (caret) would be
     pub fn get_cursor_byte(&self) -> usize {
-        match self.at_start {
-            true => *self.sel.start(),
-            false => *self.sel.end(),
-        }
+        self.head
+    }
+
+    /// Returns the byte position of the end of the selection opposite the caret
+    pub fn anchor(&self) -> usize {
+        self.anchor
     }
 
+    /// Whether the caret is on the start of its selection, having been extended back
+    /// past the anchor. Collapsed selections are never at the start
     pub fn at_start(&self) -> bool {
-        self.at_start
+        self.head < self.anchor
     }
 
     /// Sets whether the cursor's caret should be at the start or end of its selection
     pub fn set_at_start(&mut self, at_start: bool) {
-        self.at_start = at_start
+        let (start, end) = (*self.sel.start(), *self.sel.end());
+        (self.anchor, self.head) = match at_start {
+            true => (end, start),
+            false => (start, end),
+        };
     }
 
     pub fn sel(&self) -> &RangeInclusive<usize> {
         &self.sel
     }
 
+    /// Sets the selected range, keeping the caret on the same side of it
     pub fn set_sel(&mut self, range: RangeInclusive<usize>) {
-        self.sel = range;
+        let at_start = self.at_start();
+        let (start, end) = (*range.start(), *range.end());
+        self.sel = start.min(end)..=start.max(end);
+        self.set_at_start(at_start);
+    }
+
+    /// Moves the caret to `byte`, keeping the anchor. The selection flips direction
+    /// once the caret crosses the anchor
+    pub fn extend_to(&mut self, byte: usize) {
+        *self = Self::new(self.anchor, byte);
+    }
+
+    /// Collapses the selection onto `byte`
+    pub fn move_to(&mut self, byte: usize) {
+        *self = Self::new(byte, byte);
     }
 
     /// Collapses the selection into the location of the cursor's caret
     pub fn collapse_sel(&mut self) {
-        match self.at_start {
-            true => self.sel = *self.sel.start()..=*self.sel.start(),
-            false => self.sel = *self.sel.end()..=*self.sel.end(),
+        self.move_to(self.head);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extending_across_the_anchor_flips_direction() {
+        let mut cursor = Cursor::default();
+        cursor.move_to(5);
+
+        let mut steps = vec![];
+        for head in [6, 7, 5, 4, 3, 5, 6] {
+            cursor.extend_to(head);
+            steps.push((cursor.sel().clone(), cursor.at_start(), cursor.get_cursor_byte()));
         }
+
+        assert_eq!(
+            steps,
+            vec![
+                (5..=6, false, 6),
+                (5..=7, false, 7),
+                (5..=5, false, 5),
+                (4..=5, true, 4),
+                (3..=5, true, 3),
+                (5..=5, false, 5),
+                (5..=6, false, 6),
+            ]
+        );
+        assert_eq!(cursor.anchor(), 5);
+    }
+
+    #[test]
+    fn set_sel_keeps_the_caret_side() {
+        let mut cursor = Cursor::new(10, 4);
+        cursor.set_sel(2..=8);
+        assert_eq!((cursor.anchor(), cursor.get_cursor_byte()), (8, 2));
+
+        cursor.set_at_start(false);
+        assert_eq!((cursor.anchor(), cursor.get_cursor_byte()), (2, 8));
+
+        cursor.collapse_sel();
+        assert_eq!(*cursor.sel(), 8..=8);
     }
 }
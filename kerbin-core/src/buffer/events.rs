@@ -4,6 +4,13 @@ use tokio::sync::RwLock;
 
 use crate::KerbinBuffer;
 
+/// Is emitted through `emit_collecting` before a buffer is saved.
+/// Subscribers reply with `EventReply<Result<(), String>>`, and any `Err` cancels the save.
+pub struct PreSaveEvent {
+    /// The path the file is about to be saved to
+    pub path: String,
+}
+
 /// Is emitted when a buffer is saved
 pub struct SaveEvent {
     /// The path the file was saved to
@@ -15,3 +22,24 @@ pub struct CloseEvent {
     /// The closed buffer (still locked in the Arc)
     pub buffer: Arc<RwLock<dyn KerbinBuffer>>,
 }
+
+/// Is emitted when a file is opened in a new buffer, so plugins can set up per-buffer
+/// state once instead of checking every frame
+pub struct BufferOpenEvent {
+    /// The path of the opened file, as stored in its buffer
+    pub path: String,
+    /// The file's extension, if it has one
+    pub ext: Option<String>,
+}
+
+/// Is emitted when a buffer is closed, after its `CloseEvent`
+pub struct BufferCloseEvent {
+    /// The path of the closed buffer, as stored in it
+    pub path: String,
+}
+
+/// Is emitted when open files change on disk. Their buffers are marked `stale`.
+pub struct FileChangedEvent {
+    /// The paths of the changed files, as stored in their buffers
+    pub paths: Vec<String>,
+}
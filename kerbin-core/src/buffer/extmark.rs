@@ -1,4 +1,8 @@
-use std::{ops::Range, sync::Arc};
+use std::{
+    hash::{Hash, Hasher},
+    ops::Range,
+    sync::Arc,
+};
 
 use ratatui::{buffer::Buffer as RatatuiBuffer, layout::Rect, style::Style};
 
@@ -56,7 +60,7 @@ pub enum ExtmarkAdjustment {
 }
 
 /// The shape of a terminal cursor.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
 pub enum CursorShape {
     #[default]
     Block,
@@ -82,14 +86,14 @@ impl CursorShape {
 }
 
 /// A chunk of styled text used for virtual text display.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Hash)]
 pub struct StyledChunk {
     pub text: String,
     pub style: Style,
 }
 
 /// Where virtual text is rendered relative to its anchor position.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum VirtTextPos {
     /// After the end of the line
     Eol,
@@ -102,7 +106,7 @@ pub enum VirtTextPos {
 }
 
 /// Controls when a `Conceal` extmark is suppressed by marks from other namespaces.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
 pub enum ConcealScope {
     /// Suppress if another namespace's mark overlaps the same bytes.
     #[default]
@@ -112,7 +116,7 @@ pub enum ConcealScope {
 }
 
 /// Controls how an overlay popup is positioned relative to its anchor.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Hash)]
 pub enum OverlayPosition {
     /// Fixed offset (in screen cells) from the anchor character's screen position.
     Fixed { offset_x: i32, offset_y: i32 },
@@ -153,6 +157,33 @@ pub enum ExtmarkKind {
         widget: Arc<dyn OverlayWidget>,
         position: OverlayPosition,
     },
+
+    /// A glyph drawn in the gutter beside the line containing `byte_range.start`.
+    /// When several signs share a line, the highest-priority namespace wins.
+    Sign { text: String, style: Style },
+}
+
+impl Hash for ExtmarkKind {
+    /// Hashes what the mark draws. Overlays can't be looked into, so they hash by identity
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Cursor { style, shape } => (style, shape).hash(state),
+            Self::Highlight { style } => style.hash(state),
+            Self::VirtualText { chunks, pos } => (chunks, pos).hash(state),
+            Self::Conceal {
+                replacement,
+                style,
+                scope,
+                trim_before,
+                trim_after,
+            } => (replacement, style, scope, trim_before, trim_after).hash(state),
+            Self::Overlay { widget, position } => {
+                (Arc::as_ptr(widget) as *const (), position).hash(state)
+            }
+            Self::Sign { text, style } => (text, style).hash(state),
+        }
+    }
 }
 
 /// An anchored "mark" in a buffer, augmented with a decoration kind
@@ -0,0 +1,220 @@
+use std::ops::RangeInclusive;
+
+/// Code folds of a buffer, as inclusive line ranges. A provider (like tree-sitter) fills in
+/// the ranges that can fold, and closing one hides every line of it but the first, which
+/// the renderer draws with a summary of the hidden lines.
+#[derive(Debug, Clone, Default, Hash)]
+pub struct Folds {
+    available: Vec<RangeInclusive<usize>>,
+    closed: Vec<RangeInclusive<usize>>,
+}
+
+impl Folds {
+    /// Replaces the foldable ranges. Ranges within a single line can't fold and are dropped.
+    /// Closed folds stay closed, as edits already moved them along with the text
+    pub fn set_available(&mut self, mut ranges: Vec<RangeInclusive<usize>>) {
+        ranges.retain(|r| r.end() > r.start());
+        ranges.sort_by_key(|r| (*r.start(), std::cmp::Reverse(*r.end())));
+        ranges.dedup();
+        self.available = ranges;
+    }
+
+    /// Foldable ranges, sorted by start line with outer ranges first
+    pub fn available(&self) -> &[RangeInclusive<usize>] {
+        &self.available
+    }
+
+    /// Closed folds, in the order they were closed
+    pub fn closed(&self) -> &[RangeInclusive<usize>] {
+        &self.closed
+    }
+
+    pub fn has_closed(&self) -> bool {
+        !self.closed.is_empty()
+    }
+
+    /// Closes the innermost open fold containing `line`, returning the range closed
+    pub fn close_at(&mut self, line: usize) -> Option<RangeInclusive<usize>> {
+        let range = self
+            .available
+            .iter()
+            .filter(|r| r.contains(&line) && !self.closed.contains(r))
+            .min_by_key(|r| r.end() - r.start())?
+            .clone();
+        self.closed.push(range.clone());
+        Some(range)
+    }
+
+    /// Opens the outermost closed fold containing `line`, returning whether one was open
+    pub fn open_at(&mut self, line: usize) -> bool {
+        let Some(idx) = self
+            .closed
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.contains(&line))
+            .max_by_key(|(_, r)| r.end() - r.start())
+            .map(|(idx, _)| idx)
+        else {
+            return false;
+        };
+        self.closed.remove(idx);
+        true
+    }
+
+    /// Opens every closed fold hiding `line`, so it shows again
+    pub fn reveal(&mut self, line: usize) -> bool {
+        let before = self.closed.len();
+        self.closed.retain(|r| !(r.start() < &line && &line <= r.end()));
+        self.closed.len() != before
+    }
+
+    pub fn close_all(&mut self) {
+        self.closed = self.available.clone();
+    }
+
+    pub fn open_all(&mut self) {
+        self.closed.clear();
+    }
+
+    /// Last line hidden by the closed folds starting at `line`, if any start there
+    pub fn folded_end(&self, line: usize) -> Option<usize> {
+        self.closed
+            .iter()
+            .filter(|r| *r.start() == line)
+            .map(|r| *r.end())
+            .max()
+    }
+
+    /// Whether a closed fold hides `line`
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.closed.iter().any(|r| r.start() < &line && &line <= r.end())
+    }
+
+    /// The line drawn for `line`: the first line of the outermost closed fold hiding it, or
+    /// `line` itself when it isn't hidden
+    pub fn visible_line(&self, line: usize) -> usize {
+        self.closed
+            .iter()
+            .filter(|r| r.start() < &line && &line <= r.end())
+            .map(|r| *r.start())
+            .min()
+            .unwrap_or(line)
+    }
+
+    /// The line `count` visible lines below or above `line`, stepping over closed folds and
+    /// staying within `0..=last`
+    pub fn step_lines(&self, line: usize, count: isize, last: usize) -> usize {
+        let mut line = self.visible_line(line.min(last));
+        for _ in 0..count.unsigned_abs() {
+            let next = if count > 0 {
+                self.folded_end(line).unwrap_or(line) + 1
+            } else if line == 0 {
+                break;
+            } else {
+                self.visible_line(line - 1)
+            };
+            if next > last {
+                break;
+            }
+            line = next;
+        }
+        line
+    }
+
+    /// The first line after `rows` visible lines starting at `line`, so `line..end` covers
+    /// what a view of `rows` unwrapped rows shows
+    pub fn lines_down(&self, line: usize, rows: usize) -> usize {
+        let mut line = line;
+        for _ in 0..rows {
+            line = self.folded_end(line).unwrap_or(line) + 1;
+        }
+        line
+    }
+
+    /// Moves the folds for an edit that replaced rows `start..=old_end` with
+    /// `start..=new_end`. Folds past the edit shift with it and folds around it grow or
+    /// shrink, while folds the edit only partly covers are dropped
+    pub fn edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+        let shift = |folds: &mut Vec<RangeInclusive<usize>>| {
+            folds.retain_mut(|r| {
+                let (s, e) = (*r.start(), *r.end());
+                if start > e {
+                    return true;
+                }
+                let (s, e) = if old_end < s {
+                    ((s + new_end).checked_sub(old_end), (e + new_end).checked_sub(old_end))
+                } else if start >= s && old_end <= e {
+                    (Some(s), (e + new_end).checked_sub(old_end))
+                } else {
+                    return false;
+                };
+                match (s, e) {
+                    (Some(s), Some(e)) if e > s => {
+                        *r = s..=e;
+                        true
+                    }
+                    _ => false,
+                }
+            });
+        };
+        shift(&mut self.available);
+        shift(&mut self.closed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folds(ranges: &[RangeInclusive<usize>]) -> Folds {
+        let mut folds = Folds::default();
+        folds.set_available(ranges.to_vec());
+        folds
+    }
+
+    #[test]
+    fn closes_innermost_and_opens_outermost() {
+        let mut folds = folds(&[0..=10, 2..=4, 6..=6]);
+        assert_eq!(folds.available(), [0..=10, 2..=4]);
+
+        assert_eq!(folds.close_at(3), Some(2..=4));
+        assert_eq!(folds.close_at(3), Some(0..=10));
+        assert_eq!(folds.close_at(3), None);
+
+        assert!(folds.open_at(3));
+        assert_eq!(folds.closed(), [2..=4]);
+        assert!(!folds.open_at(8));
+    }
+
+    #[test]
+    fn steps_over_closed_folds() {
+        let mut folds = folds(&[2..=5, 8..=9]);
+        folds.close_all();
+
+        assert!(folds.is_hidden(4));
+        assert!(!folds.is_hidden(2));
+        assert_eq!(folds.visible_line(4), 2);
+        assert_eq!(folds.step_lines(1, 2, 20), 6);
+        assert_eq!(folds.step_lines(7, -2, 20), 2);
+        assert_eq!(folds.step_lines(8, 5, 10), 10);
+        assert_eq!(folds.lines_down(0, 4), 7);
+    }
+
+    #[test]
+    fn edits_move_folds() {
+        let mut folds = folds(&[2..=5, 8..=12]);
+        folds.close_all();
+
+        // Two lines added before the first fold
+        folds.edit(0, 0, 2);
+        assert_eq!(folds.closed(), [4..=7, 10..=14]);
+
+        // A line removed inside the second fold
+        folds.edit(11, 12, 11);
+        assert_eq!(folds.closed(), [4..=7, 10..=13]);
+
+        // Joining a fold's first line onto the line above drops it
+        folds.edit(3, 4, 3);
+        assert_eq!(folds.closed(), [9..=12]);
+    }
+}
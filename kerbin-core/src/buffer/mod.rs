@@ -34,10 +34,19 @@ pub use render::*;
 pub mod text_rope_handlers;
 pub use text_rope_handlers::*;
 
+pub mod text_object;
+pub use text_object::*;
+
+pub mod word_chars;
+pub use word_chars::*;
+
+pub mod folds;
+pub use folds::*;
+
 use ropey::Rope;
 use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
 
-use crate::EVENT_BUS;
+use crate::{EVENT_BUS, RopeExts, RopeyCursor, cached, diff_ropes, discard_recovery};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum IndentStyle {
@@ -69,6 +78,11 @@ impl IndentStyle {
 
 /// Detect the indentation style of a rope by analysing leading-whitespace deltas.
 pub fn detect_indent(rope: &Rope, default_spaces: usize) -> IndentStyle {
+    detect_indent_or(rope, IndentStyle::Spaces(default_spaces))
+}
+
+/// Like [`detect_indent`], returning `fallback` when the rope has no indented lines
+pub fn detect_indent_or(rope: &Rope, fallback: IndentStyle) -> IndentStyle {
     for line in rope.lines() {
         if line.chars().next() == Some('\t') {
             return IndentStyle::Tabs;
@@ -95,17 +109,26 @@ pub fn detect_indent(rope: &Rope, default_spaces: usize) -> IndentStyle {
         prev_indent = indent;
     }
 
-    let tab_size = counts[1..]
+    counts[1..]
         .iter()
         .enumerate()
         .max_by_key(|&(_, &n)| n)
         .filter(|&(_, &n)| n > 0)
-        .map(|(i, _)| i + 1)
-        .unwrap_or(default_spaces);
-
-    IndentStyle::Spaces(tab_size)
+        .map(|(i, _)| IndentStyle::Spaces(i + 1))
+        .unwrap_or(fallback)
 }
 
+/// Flag of buffers that refuse edits and saves, toggled with `set readonly`/`set noreadonly`
+pub const READONLY_FLAG: &str = "readonly";
+
+/// Flag set when an edit or save was refused because the buffer is read-only, cleared once
+/// the refusal has been reported
+pub const READONLY_REFUSED_FLAG: &str = "readonly_refused";
+
+/// Flag set once a refusal was reported, so typing into a read-only buffer warns only once
+/// until `set_readonly` is called again
+pub const READONLY_WARNED_FLAG: &str = "readonly_warned";
+
 /// Used internally for defining a set of actions that were applied together as a single undo/redo unit
 #[derive(Default)]
 pub struct ChangeGroup(Vec<Cursor>, Vec<Box<dyn BufferAction>>);
@@ -117,6 +140,12 @@ pub struct TextBuffer {
     pub save_point: usize,
     pub version: u128,
     pub changed: Option<SystemTime>,
+    /// Set when the file was over `CoreConfig::big_file_threshold` on open.
+    /// Big files are read-only, and highlighting and other per-line passes skip them.
+    pub big_file: bool,
+    /// Set when the file changed on disk after it was read or saved.
+    /// Cleared by saving or reloading.
+    pub stale: bool,
 
     pub(crate) rope: Rope,
 
@@ -124,6 +153,9 @@ pub struct TextBuffer {
     pub ext: String,
     pub filetype: Option<String>,
     pub indent_style: IndentStyle,
+    pub word_chars: WordChars,
+    /// Foldable line ranges and the folds closed by `fold` and friends
+    pub folds: Folds,
 
     pub cursors: Vec<Cursor>,
     pub primary_cursor: usize,
@@ -135,6 +167,8 @@ pub struct TextBuffer {
     pub byte_changes: Vec<[((usize, usize), usize); 3]>,
 
     pub current_change: Option<ChangeGroup>,
+    /// Depth of nested `begin_undo_batch` calls; while above zero, change groups don't split
+    pub(crate) undo_batch: usize,
 
     /// Past changes that can be undone
     pub undo_stack: Vec<ChangeGroup>,
@@ -151,6 +185,8 @@ impl Default for TextBuffer {
             save_point: 0,
             version: 0,
             changed: None,
+            big_file: false,
+            stale: false,
 
             rope: Rope::new(),
 
@@ -158,6 +194,8 @@ impl Default for TextBuffer {
             ext: "".into(),
             filetype: None,
             indent_style: IndentStyle::default(),
+            word_chars: WordChars::default(),
+            folds: Folds::default(),
 
             cursors: vec![Cursor::default()],
             primary_cursor: 0,
@@ -168,6 +206,7 @@ impl Default for TextBuffer {
             byte_changes: vec![],
 
             current_change: None,
+            undo_batch: 0,
 
             undo_stack: vec![],
             redo_stack: vec![],
@@ -183,17 +222,44 @@ impl TextBuffer {
         Self::default()
     }
 
-    /// Opens a file with the provided path, loading its content into the buffer
-    pub fn open(path_str: String, default_tab_unit: usize) -> io::Result<Self> {
-        let mut found_ext = "".to_string();
+    /// Creates a read-only buffer holding generated `text`, like a preview or a listing.
+    /// `path` names it and should be a special `<name>` path, since no file backs it
+    pub fn generated(path: impl Into<String>, text: &str) -> Self {
+        let mut buf = Self {
+            path: path.into(),
+            ..Self::default()
+        };
+        buf.set_generated_text(text);
+        buf
+    }
 
+    /// Replaces the text of a generated buffer, leaving it read-only and clean with
+    /// nothing to undo
+    pub fn set_generated_text(&mut self, text: &str) {
+        self.set_readonly(false);
+        self.start_change_group();
+        self.replace_text(text);
+        self.commit_change_group();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.save_point = 0;
+        self.dirty = false;
+        self.set_readonly(true);
+    }
+
+    /// Opens a file with the provided path, loading its content into the buffer.
+    /// Blocks while the file is read; see `open_async` for use from systems and commands.
+    pub fn open(path_str: String, default_tab_unit: usize) -> io::Result<Self> {
         let path = get_canonical_path_with_non_existent(&path_str);
 
         let mut changed = None;
+        let mut readonly = false;
 
         let rope = match std::fs::File::open(&path) {
             Ok(f) => {
-                changed = f.metadata()?.modified().ok();
+                let metadata = f.metadata()?;
+                changed = metadata.modified().ok();
+                readonly = !is_writable(&path);
                 Rope::from_reader(BufReader::new(f))?
             }
             Err(e) => {
@@ -204,30 +270,109 @@ impl TextBuffer {
             }
         };
 
-        if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
-            found_ext = ext.to_string();
-        }
+        let mut buf = Self::from_file(path, rope, changed, default_tab_unit, false);
+        buf.set_readonly(readonly);
+        Ok(buf)
+    }
 
-        let indent_style = detect_indent(&rope, default_tab_unit);
+    /// Opens a file without blocking the runtime. Files over `big_file_threshold` bytes
+    /// open in big file mode (see `big_file`).
+    pub async fn open_async(
+        path_str: String,
+        default_tab_unit: usize,
+        big_file_threshold: u64,
+    ) -> io::Result<Self> {
+        let path = get_canonical_path_with_non_existent(&path_str);
+
+        let (bytes, metadata) = match tokio::fs::read(&path).await {
+            Ok(bytes) => (bytes, Some(tokio::fs::metadata(&path).await?)),
+            Err(e) => {
+                if e.kind() != ErrorKind::NotFound {
+                    tracing::error!("{e} when opening file, {path_str}");
+                    return Err(e);
+                }
+                (vec![], None)
+            }
+        };
 
-        Ok(Self {
+        let changed = metadata.as_ref().and_then(|m| m.modified().ok());
+        let big_file = metadata.as_ref().is_some_and(|m| m.len() > big_file_threshold);
+        let readonly = metadata.is_some() && !is_writable(&path);
+
+        let rope = tokio::task::spawn_blocking(move || Rope::from_reader(bytes.as_slice()))
+            .await
+            .map_err(io::Error::other)??;
+
+        let mut buf = Self::from_file(path, rope, changed, default_tab_unit, big_file);
+        buf.set_readonly(readonly);
+        Ok(buf)
+    }
+
+    fn from_file(
+        path: PathBuf,
+        rope: Rope,
+        changed: Option<SystemTime>,
+        default_tab_unit: usize,
+        big_file: bool,
+    ) -> Self {
+        let ext = path
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string();
+
+        // Scanning every line for indentation is too slow on big files
+        let indent_style = if big_file {
+            IndentStyle::Spaces(default_tab_unit)
+        } else {
+            detect_indent(&rope, default_tab_unit)
+        };
+
+        Self {
             save_point: 0,
 
             changed,
+            big_file,
 
             rope,
             path: path.to_str().map(|x| x.to_string()).unwrap_or_default(),
-            ext: found_ext,
+            ext,
             indent_style,
 
             ..Default::default()
-        })
+        }
     }
 
     pub fn version(&self) -> &u128 {
         &self.version
     }
 
+    /// Whether edits and saves are refused, either through `READONLY_FLAG` or because the
+    /// buffer is a big file
+    pub fn is_readonly(&self) -> bool {
+        self.big_file || self.flags.contains(READONLY_FLAG)
+    }
+
+    /// Sets or clears `READONLY_FLAG`. Big files stay read-only either way.
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.flags.remove(READONLY_WARNED_FLAG);
+        if readonly {
+            self.flags.insert(READONLY_FLAG);
+        } else {
+            self.flags.remove(READONLY_FLAG);
+        }
+    }
+
+    /// Returns `true` if the buffer is read-only, marking the refusal so it gets reported
+    fn refuse_readonly(&mut self) -> bool {
+        if !self.is_readonly() {
+            return false;
+        }
+        tracing::warn!("Refused to change read-only buffer {}", self.path);
+        self.flags.insert(READONLY_REFUSED_FLAG);
+        true
+    }
+
     /// Adds an extmark, stamping it with the current file version
     pub fn add_extmark(&mut self, builder: ExtmarkBuilder) -> u64 {
         let file_ver = self.version;
@@ -244,8 +389,16 @@ impl TextBuffer {
         self.states.contains_key(&T::static_name())
     }
 
-    pub fn remove_state<T: StateName + StaticState>(&mut self) {
-        self.states.remove(&T::static_name());
+    /// Removes the state of type `T`, returning whether it was attached
+    pub fn remove_state<T: StateName + StaticState>(&mut self) -> bool {
+        self.states.remove(&T::static_name()).is_some()
+    }
+
+    /// Returns the names of all states attached to this buffer, sorted
+    pub fn state_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.states.keys().map(|x| x.as_str()).collect();
+        names.sort_unstable();
+        names
     }
 
     /// Inserts the state produced by `func` only if the type is not already present
@@ -311,10 +464,15 @@ impl TextBuffer {
         old_end: ((usize, usize), usize),
         new_end: ((usize, usize), usize),
     ) {
+        self.folds.edit(start.0.0, old_end.0.0, new_end.0.0);
         self.byte_changes.push([start, old_end, new_end]);
     }
 
     pub fn action(&mut self, action: impl BufferAction) -> bool {
+        if self.refuse_readonly() {
+            return false;
+        }
+
         if self.current_change.is_none() {
             self.start_change_group();
         }
@@ -334,6 +492,36 @@ impl TextBuffer {
         res.success
     }
 
+    /// Replaces the whole text with `text` through the edits [`diff_ropes`] finds, as
+    /// actions in the current change group. Only the changed spans are registered as
+    /// input edits, so highlighting and language servers stay incremental across
+    /// formatters and reloads. Returns `false` if the buffer can't be edited, leaving the
+    /// caller to decide whether that's worth reporting
+    pub fn replace_text(&mut self, text: &str) -> bool {
+        if self.is_readonly() {
+            return false;
+        }
+
+        let new = Rope::from_str(text);
+        for edit in diff_ropes(&self.rope, &new).into_iter().rev() {
+            let removed = self.rope.byte_slice(edit.old.clone()).len_chars();
+            if removed > 0 {
+                self.action(Delete {
+                    byte: edit.old.start,
+                    len: removed,
+                });
+            }
+            if !edit.new.is_empty() {
+                self.action(Insert {
+                    byte: edit.old.start,
+                    content: new.byte_slice(edit.new).to_string(),
+                });
+            }
+        }
+
+        true
+    }
+
     /// Creates a new cursor at the same location as the current primary cursor
     pub fn create_cursor(&mut self) {
         self.cursors.push(self.primary_cursor().clone());
@@ -349,6 +537,294 @@ impl TextBuffer {
         self.cursors.push(cursor);
     }
 
+    /// Replaces the cursors with one per line between the primary cursor's anchor and caret,
+    /// each selecting the same visual columns. Lines ending before the block get no cursor,
+    /// and lines ending inside it are selected up to their last character.
+    pub fn block_select(&mut self, tab_w: usize) {
+        let cursor = self.primary_cursor().clone();
+        let (anchor, caret) = (cursor.anchor(), cursor.get_cursor_byte());
+
+        let col_of = |byte: usize| {
+            let line = self.byte_to_line_clamped(byte);
+            let col = self
+                .rope
+                .visual_col_of_byte(line, byte - self.line_to_byte_clamped(line), tab_w);
+            (line, col)
+        };
+        let (anchor_line, anchor_col) = col_of(anchor);
+        let (caret_line, caret_col) = col_of(caret);
+
+        let (left, right) = (anchor_col.min(caret_col), anchor_col.max(caret_col));
+
+        let mut cursors = vec![];
+        for line in anchor_line.min(caret_line)..=anchor_line.max(caret_line) {
+            let line_start = self.line_to_byte_clamped(line);
+            let content_end = line_start
+                + self.rope.get_line(line).map_or(0, |l| {
+                    l.to_string().trim_end_matches(['\n', '\r']).len()
+                });
+
+            let start = line_start + self.rope.byte_of_visual_col(line, left, tab_w);
+            // The lines the block was drawn between keep their cursor wherever it sits
+            if start >= content_end && line != anchor_line && line != caret_line {
+                continue;
+            }
+
+            let mut end = line_start + self.rope.byte_of_visual_col(line, right, tab_w);
+            if end >= content_end {
+                end = self.rope.prev_grapheme_boundary(content_end).max(start);
+            }
+
+            if line == caret_line {
+                self.primary_cursor = cursors.len();
+            }
+            cursors.push(match caret_col < anchor_col {
+                true => Cursor::new(end, start),
+                false => Cursor::new(start, end),
+            });
+        }
+        self.cursors = cursors;
+    }
+
+    /// Selects the next occurrence of the primary selection's text with a new primary
+    /// cursor, searching on from the primary cursor and wrapping around the end of the
+    /// buffer. With `skip`, the primary cursor moves there instead. A single-char
+    /// selection first grows to the word under the caret. Returns false once every
+    /// occurrence already has a cursor.
+    pub fn select_next_occurrence(&mut self, skip: bool) -> bool {
+        let sel = self.primary_cursor().sel().clone();
+        if sel.start() == sel.end() {
+            let caret = self.primary_cursor().get_cursor_byte();
+            return match self.text_object(TextObjectKind::Word, false, caret) {
+                Some(word) if !word.is_empty() => {
+                    *self.primary_cursor_mut() = Cursor::new(word.start, word.end - 1);
+                    true
+                }
+                _ => false,
+            };
+        }
+
+        // The selection's end is inclusive, so the text runs through its last char
+        let end = self.char_to_byte_clamped(self.byte_to_char_clamped(*sel.end()) + 1);
+        let needle = self.rope.byte_slice(*sel.start()..end).to_string();
+        let Ok(regex) = cached(&::regex::escape(&needle)) else {
+            return false;
+        };
+
+        let find_in = |range: std::ops::Range<usize>| {
+            let slice = self.rope.byte_slice(range.clone());
+            regex
+                .find_iter(regex_cursor::Input::new(RopeyCursor::new(slice)))
+                .map(move |m| range.start + m.start()..range.start + m.end())
+        };
+        let found = find_in(end..self.len())
+            .chain(find_in(0..end))
+            .find(|m| !self.cursors.iter().any(|c| *c.sel() == (m.start..=m.end - 1)));
+        let Some(found) = found else {
+            return false;
+        };
+
+        let cursor = Cursor::new(found.start, found.end - 1);
+        if skip {
+            *self.primary_cursor_mut() = cursor;
+        } else {
+            self.cursors.push(cursor);
+            self.primary_cursor = self.cursors.len() - 1;
+        }
+        self.merge_overlapping_cursors();
+        true
+    }
+
+    /// Lines touched by any cursor's selection, ascending and deduplicated
+    fn selected_lines(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = self
+            .cursors
+            .iter()
+            .flat_map(|c| {
+                self.byte_to_line_clamped(*c.sel().start())..=self.byte_to_line_clamped(*c.sel().end())
+            })
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// Applies `(byte, bytes removed, inserted text)` edits, given in ascending pre-edit
+    /// offsets, as a single change. Selections are shifted to stay on the same text.
+    fn apply_line_edits(&mut self, edits: Vec<(usize, usize, String)>) -> bool {
+        if edits.is_empty() {
+            return false;
+        }
+
+        let map = |byte: usize| {
+            let mut shifted = byte as isize;
+            for (start, removed, inserted) in &edits {
+                if byte >= start + removed {
+                    shifted += inserted.len() as isize - *removed as isize;
+                } else if byte >= *start {
+                    shifted -= (byte - start) as isize;
+                }
+            }
+            shifted as usize
+        };
+        let sels: Vec<_> = self
+            .cursors
+            .iter()
+            .map(|c| map(*c.sel().start())..=map(*c.sel().end()))
+            .collect();
+
+        self.start_change_group();
+        // Bottom-up, so earlier offsets stay valid
+        for (start, removed, inserted) in edits.into_iter().rev() {
+            if removed > 0 {
+                let len = self.rope.byte_slice(start..start + removed).len_chars();
+                self.action(Delete { byte: start, len });
+            }
+            if !inserted.is_empty() {
+                self.action(Insert {
+                    byte: start,
+                    content: inserted,
+                });
+            }
+        }
+        self.commit_change_group();
+
+        for (cursor, sel) in self.cursors.iter_mut().zip(sels) {
+            cursor.set_sel(sel);
+        }
+        true
+    }
+
+    /// Indents (or dedents) every line touched by a cursor's selection by one indent unit,
+    /// as a single change. Dedenting removes one tab or up to one unit of leading spaces.
+    /// Blank lines aren't indented, and selections stay on the same text.
+    pub fn shift_indent(&mut self, dedent: bool) -> bool {
+        let unit = self.indent_style.tab_string();
+        let width = self.indent_style.tab_width();
+
+        let mut edits = vec![];
+        for line in self.selected_lines() {
+            let text = self.line_clamped(line).to_string();
+            let start = self.line_to_byte_clamped(line);
+
+            if !dedent {
+                if !text.trim_end_matches(['\n', '\r']).is_empty() {
+                    edits.push((start, 0, unit.clone()));
+                }
+                continue;
+            }
+
+            let removed = if text.starts_with('\t') {
+                1
+            } else {
+                text.chars().take(width).take_while(|c| *c == ' ').count()
+            };
+            if removed > 0 {
+                edits.push((start, removed, String::new()));
+            }
+        }
+
+        self.apply_line_edits(edits)
+    }
+
+    /// Comments every selected line with `token`, or uncomments them all if every
+    /// non-blank one is already commented. The token goes after the line's indentation,
+    /// followed by a space.
+    pub fn toggle_comment(&mut self, token: &str) -> bool {
+        let lines: Vec<(usize, String)> = self
+            .selected_lines()
+            .into_iter()
+            .map(|line| {
+                let text = self.line_clamped(line).to_string();
+                let indent = text.len() - text.trim_start_matches([' ', '\t']).len();
+                (self.line_to_byte_clamped(line) + indent, text[indent..].to_string())
+            })
+            .filter(|(_, rest)| !rest.trim_end_matches(['\n', '\r']).is_empty())
+            .collect();
+
+        let uncomment = !lines.is_empty() && lines.iter().all(|(_, rest)| rest.starts_with(token));
+
+        let edits = lines
+            .into_iter()
+            .map(|(start, rest)| {
+                if !uncomment {
+                    return (start, 0, format!("{token} "));
+                }
+
+                let after = &rest[token.len()..];
+                let removed = token.len() + usize::from(after.starts_with(' '));
+                (start, removed, String::new())
+            })
+            .collect();
+
+        self.apply_line_edits(edits)
+    }
+
+    /// Joins each cursor's line with the next, or every line its selection touches when it
+    /// spans several, as a single change. Each line break and the indentation after it
+    /// become `separator`, which is left out after trailing whitespace, before a blank line
+    /// and before a character of `no_space_before` (so `foo(\n)` joins to `foo()`).
+    /// The primary cursor lands on its last join point. Joining the last line does nothing.
+    pub fn join_lines(&mut self, separator: &str, no_space_before: &str) -> bool {
+        // A trailing newline doesn't start a line that can be joined
+        let mut last_line = self.len_lines().saturating_sub(1);
+        if last_line > 0 && self.line_clamped(last_line).len_chars() == 0 {
+            last_line -= 1;
+        }
+
+        let primary = self.primary_cursor;
+        let mut joins: Vec<(usize, bool)> = vec![];
+        for (i, cursor) in self.cursors.iter().enumerate() {
+            let first = self.byte_to_line_clamped(*cursor.sel().start());
+            let last = self.byte_to_line_clamped(*cursor.sel().end()).max(first + 1);
+            joins.extend((first..last.min(last_line)).map(|line| (line, i == primary)));
+        }
+        joins.sort_unstable();
+        joins.dedup_by(|later, kept| {
+            let same = later.0 == kept.0;
+            kept.1 |= same && later.1;
+            same
+        });
+
+        let mut edits = vec![];
+        let mut primary_join = None;
+        for (line, is_primary) in joins {
+            let text = self.line_clamped(line).to_string();
+            let content = text.trim_end_matches(['\n', '\r']);
+            let next = self.line_clamped(line + 1).to_string();
+            let indent = next.len() - next.trim_start_matches([' ', '\t']).len();
+            let rest = next[indent..].trim_end_matches(['\n', '\r']);
+
+            let bare = content.ends_with([' ', '\t'])
+                || rest.is_empty()
+                || rest.starts_with(|c| no_space_before.contains(c));
+            let inserted = if bare { String::new() } else { separator.to_string() };
+
+            let start = self.line_to_byte_clamped(line) + content.len();
+            if is_primary {
+                primary_join = Some(edits.len());
+            }
+            edits.push((start, text.len() - content.len() + indent, inserted));
+        }
+
+        // Where the primary's last join lands once the edits before it shift the text
+        let caret = primary_join.map(|idx| {
+            let shift: isize = edits[..idx]
+                .iter()
+                .map(|(_, removed, inserted)| inserted.len() as isize - *removed as isize)
+                .sum();
+            edits[idx].0.saturating_add_signed(shift)
+        });
+
+        if !self.apply_line_edits(edits) {
+            return false;
+        }
+        if let Some(caret) = caret {
+            self.primary_cursor_mut().set_sel(caret..=caret);
+        }
+        true
+    }
+
     pub fn drop_primary_cursor(&mut self) {
         if self.cursors.len() <= 1 {
             return;
@@ -378,7 +854,10 @@ impl TextBuffer {
     }
 
     pub fn undo(&mut self) {
-        self.commit_change_group();
+        if self.refuse_readonly() {
+            return;
+        }
+        self.push_change_group();
         if let Some(group) = self.undo_stack.pop() {
             let mut redo_group = vec![];
 
@@ -406,7 +885,10 @@ impl TextBuffer {
     }
 
     pub fn redo(&mut self) {
-        self.commit_change_group();
+        if self.refuse_readonly() {
+            return;
+        }
+        self.push_change_group();
         if let Some(group) = self.redo_stack.pop() {
             let mut undo_group = vec![];
 
@@ -435,12 +917,22 @@ impl TextBuffer {
     }
 
     pub fn start_change_group(&mut self) {
-        self.commit_change_group();
+        if self.undo_batch > 0 && self.current_change.is_some() {
+            return;
+        }
+        self.push_change_group();
         self.current_change = Some(ChangeGroup(self.cursors.clone(), vec![]));
     }
 
-    /// Commits the current `ChangeGroup` to the undo stack, if it's not empty
+    /// Commits the current `ChangeGroup` to the undo stack, if it's not empty.
+    /// Waits for the end of an undo batch when one is open
     pub fn commit_change_group(&mut self) {
+        if self.undo_batch == 0 {
+            self.push_change_group();
+        }
+    }
+
+    fn push_change_group(&mut self) {
         if let Some(group) = self.current_change.take()
             && !group.1.is_empty()
         {
@@ -448,7 +940,53 @@ impl TextBuffer {
         }
     }
 
+    /// Opens an undo batch: every edit until the matching `end_undo_batch` lands in one
+    /// change group, even when the commands making them start and commit their own.
+    /// Batches nest, the outermost one deciding where the group ends
+    pub fn begin_undo_batch(&mut self) {
+        if self.undo_batch == 0 {
+            self.start_change_group();
+        }
+        self.undo_batch += 1;
+    }
+
+    /// Closes a batch opened with `begin_undo_batch`, committing its change group once
+    /// the outermost batch closes
+    pub fn end_undo_batch(&mut self) {
+        self.undo_batch = self.undo_batch.saturating_sub(1);
+        self.commit_change_group();
+    }
+
+    /// Checks that the file at `path` wasn't modified since the buffer last read or saved
+    /// it, returning a message describing the conflict if it was. Missing files pass
+    pub fn check_external_change(&self, path: &str) -> Result<(), String> {
+        match std::fs::metadata(path) {
+            Ok(metadata) => {
+                if let Some(disk_time) = metadata.modified().ok()
+                    && let Some(buffer_time) = self.changed
+                    && disk_time != buffer_time
+                {
+                    return Err(format!(
+                        "File has been modified externally since last read/save: {}. Disk time: {:?}, Buffer time: {:?}",
+                        path, disk_time, buffer_time
+                    ));
+                }
+                Ok(())
+            }
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("Failed to read metadata for file {}: {}", path, e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Saves the buffer, to `path` when given (which becomes the buffer's path).
+    /// Read-only buffers can only be written to a new path.
     pub async fn write_file(&mut self, path: Option<String>) -> Result<(), std::io::Error> {
+        if path.is_none() && self.refuse_readonly() {
+            return Err(readonly_error(&self.path));
+        }
+
         if let Some(new_path) = path {
             let path = Path::new(&new_path);
 
@@ -483,35 +1021,20 @@ impl TextBuffer {
             })
             .await;
 
-        if !std::fs::exists(&self.path)? {
-            if let Some(dir_path) = Path::new(&self.path).parent() {
-                std::fs::create_dir_all(dir_path)?;
-            }
-            std::fs::File::create(&self.path)?.flush()?;
+        if let Some(dir_path) = Path::new(&self.path).parent() {
+            std::fs::create_dir_all(dir_path)?;
         }
 
         self.dirty = false;
 
-        let write_result = self.rope.write_to(
-            match std::fs::OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .open(&self.path)
-            {
-                Ok(f) => BufWriter::new(f),
-                Err(e) => {
-                    tracing::error!("Failed to write to {}: {e}", self.path);
-                    return Err(e);
-                }
-            },
-        );
-
-        if let Err(e) = write_result {
-            tracing::error!("Failed to write rope content to file: {:?}", e);
-            return Err(std::io::Error::other(e.to_string()));
+        if let Err(e) = write_atomic(Path::new(&self.path), &self.rope) {
+            tracing::error!("Failed to write to {}: {e}", self.path);
+            return Err(e);
         }
 
         self.dirty = false;
+        self.stale = false;
+        discard_recovery(&self.path);
 
         self.save_point = self.undo_stack.len();
 
@@ -534,29 +1057,19 @@ impl TextBuffer {
     /// Writes the buffer contents to disk and updates dirty/save_point/changed,
     /// without emitting SaveEvent. Used after format-on-save edits are applied.
     pub fn write_file_bare(&mut self) -> Result<(), std::io::Error> {
-        if !std::fs::exists(&self.path)? {
-            if let Some(dir_path) = Path::new(&self.path).parent() {
-                std::fs::create_dir_all(dir_path)?;
-            }
-            std::fs::File::create(&self.path)?.flush()?;
+        if self.refuse_readonly() {
+            return Err(readonly_error(&self.path));
         }
 
-        let write_result = self.rope.write_to(
-            match std::fs::OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .open(&self.path)
-            {
-                Ok(f) => BufWriter::new(f),
-                Err(e) => return Err(e),
-            },
-        );
-
-        if let Err(e) = write_result {
-            return Err(std::io::Error::other(e.to_string()));
+        if let Some(dir_path) = Path::new(&self.path).parent() {
+            std::fs::create_dir_all(dir_path)?;
         }
 
+        write_atomic(Path::new(&self.path), &self.rope)?;
+
         self.dirty = false;
+        self.stale = false;
+        discard_recovery(&self.path);
         self.save_point = self.undo_stack.len();
 
         match std::fs::metadata(&self.path) {
@@ -574,10 +1087,29 @@ impl TextBuffer {
         let new_scroll =
             (self.renderer.byte_scroll as isize + lines).clamp(0, max_scroll as isize) as usize;
         self.renderer.byte_scroll = new_scroll;
-        self.renderer.visual_scroll = 0;
         self.renderer.cursor_drag = true;
     }
 
+    /// Moves the primary cursor to the start of the 0-based `line`, clamped to the last line,
+    /// and scrolls so that line sits in the middle of the view.
+    /// The selection collapses unless `extend_selection` is set.
+    pub fn goto_line(&mut self, line: usize, extend_selection: bool) -> bool {
+        let line = line.min(self.len_lines().saturating_sub(1));
+        let target_byte = self.line_to_byte_clamped(line);
+        let old_sel = self.primary_cursor().sel().clone();
+
+        let cursor_mut = self.primary_cursor_mut();
+        if extend_selection {
+            cursor_mut.extend_to(target_byte);
+        } else {
+            cursor_mut.move_to(target_byte);
+        }
+
+        self.renderer.center_cursor = true;
+
+        *self.primary_cursor().sel() != old_sel
+    }
+
     pub fn move_bytes(&mut self, bytes: isize, extend_selection: bool) -> bool {
         if bytes == 0 {
             return false;
@@ -592,19 +1124,9 @@ impl TextBuffer {
         let cursor_mut = self.primary_cursor_mut();
 
         if extend_selection {
-            let anchor_byte = if cursor_mut.at_start {
-                *cursor_mut.sel.end()
-            } else {
-                *cursor_mut.sel.start()
-            };
-
-            let start = anchor_byte.min(new_caret_byte);
-            let end = anchor_byte.max(new_caret_byte);
-            cursor_mut.set_sel(start..=end);
-            cursor_mut.set_at_start(new_caret_byte < anchor_byte);
+            cursor_mut.extend_to(new_caret_byte);
         } else {
-            cursor_mut.set_sel(new_caret_byte..=new_caret_byte);
-            cursor_mut.set_at_start(false);
+            cursor_mut.move_to(new_caret_byte);
         }
         new_caret_byte != current_caret_byte
     }
@@ -618,34 +1140,27 @@ impl TextBuffer {
         let current_line_idx = self.byte_to_line_clamped(current_caret_byte);
         let line_start_byte = self.line_to_byte_clamped(current_line_idx);
 
-        let line_prefix = self.slice_to_string(line_start_byte, current_caret_byte).unwrap_or_default();
-        let current_visual_col = byte_offset_to_display_col(&line_prefix, line_prefix.len(), tab_w);
+        let current_visual_col = self.rope.visual_col_of_byte(
+            current_line_idx,
+            current_caret_byte.saturating_sub(line_start_byte),
+            tab_w,
+        );
 
-        let total_lines = self.len_lines();
-        let target_line_idx = current_line_idx
-            .saturating_add_signed(rows)
-            .clamp(0, total_lines.saturating_sub(1));
+        // Closed folds count as a single line
+        let last_line = self.len_lines().saturating_sub(1);
+        let target_line_idx = self.folds.step_lines(current_line_idx, rows, last_line);
 
-        let target_line_text = self.line_clamped(target_line_idx).to_string();
-        let target_byte_offset = display_col_to_byte_offset(&target_line_text, current_visual_col, tab_w);
+        let target_byte_offset = self
+            .rope
+            .byte_of_visual_col(target_line_idx, current_visual_col, tab_w);
         let new_caret_byte = self.line_to_byte_clamped(target_line_idx) + target_byte_offset;
 
         let cursor_mut = self.primary_cursor_mut();
 
         if extend_selection {
-            let anchor_byte = if cursor_mut.at_start {
-                *cursor_mut.sel.end()
-            } else {
-                *cursor_mut.sel.start()
-            };
-
-            let start = anchor_byte.min(new_caret_byte);
-            let end = anchor_byte.max(new_caret_byte);
-            cursor_mut.set_sel(start..=end);
-            cursor_mut.set_at_start(new_caret_byte < anchor_byte);
+            cursor_mut.extend_to(new_caret_byte);
         } else {
-            cursor_mut.set_sel(new_caret_byte..=new_caret_byte);
-            cursor_mut.set_at_start(false);
+            cursor_mut.move_to(new_caret_byte);
         }
         new_caret_byte != current_caret_byte
     }
@@ -666,21 +1181,43 @@ impl TextBuffer {
         let cursor_mut = self.primary_cursor_mut();
 
         if extend_selection {
-            let anchor_byte = if cursor_mut.at_start {
-                *cursor_mut.sel.end()
+            cursor_mut.extend_to(new_caret_byte);
+        } else {
+            cursor_mut.move_to(new_caret_byte);
+        }
+        new_caret_byte != current_cursor.get_cursor_byte()
+    }
+
+    /// Moves the primary cursor by extended grapheme clusters rather than chars,
+    /// so combining sequences and ZWJ emoji are always crossed as a whole
+    pub fn move_graphemes(&mut self, count: isize, extend_selection: bool) -> bool {
+        if count == 0 {
+            return false;
+        }
+
+        let current_caret_byte = self.primary_cursor().get_cursor_byte().min(self.len());
+
+        let mut new_caret_byte = current_caret_byte;
+        for _ in 0..count.unsigned_abs() {
+            let next = if count > 0 {
+                self.rope.next_grapheme_boundary(new_caret_byte)
             } else {
-                *cursor_mut.sel.start()
+                self.rope.prev_grapheme_boundary(new_caret_byte)
             };
+            if next == new_caret_byte {
+                break;
+            }
+            new_caret_byte = next;
+        }
+
+        let cursor_mut = self.primary_cursor_mut();
 
-            let start = anchor_byte.min(new_caret_byte);
-            let end = anchor_byte.max(new_caret_byte);
-            cursor_mut.set_sel(start..=end);
-            cursor_mut.set_at_start(new_caret_byte < anchor_byte);
+        if extend_selection {
+            cursor_mut.extend_to(new_caret_byte);
         } else {
-            cursor_mut.set_sel(new_caret_byte..=new_caret_byte);
-            cursor_mut.set_at_start(false);
+            cursor_mut.move_to(new_caret_byte);
         }
-        new_caret_byte != current_cursor.get_cursor_byte()
+        new_caret_byte != current_caret_byte
     }
 
     pub fn merge_overlapping_cursors(&mut self) {
@@ -734,11 +1271,21 @@ impl TextBuffer {
         self.byte_changes.clear();
     }
 
+    /// Inserts `text` into the rope directly, outside of undo history.
+    /// Does nothing to read-only buffers.
     pub fn insert(&mut self, byte: usize, text: &str) {
+        if self.refuse_readonly() {
+            return;
+        }
         self.rope.insert(self.rope.byte_to_char(byte), text);
     }
 
+    /// Removes `range` from the rope directly, outside of undo history.
+    /// Does nothing to read-only buffers.
     pub fn remove_range(&mut self, range: std::ops::Range<usize>) {
+        if self.refuse_readonly() {
+            return;
+        }
         self.rope
             .remove(self.rope.byte_to_char(range.start)..self.rope.byte_to_char(range.end));
     }
@@ -748,6 +1295,18 @@ impl TextBuffer {
     }
 }
 
+fn readonly_error(path: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::PermissionDenied,
+        format!("{path} is read-only, use `set noreadonly` to allow saving"),
+    )
+}
+
+/// Whether the file at `path` can be opened for writing, which doesn't touch its contents
+fn is_writable(path: &Path) -> bool {
+    std::fs::OpenOptions::new().append(true).open(path).is_ok()
+}
+
 /// Computes the canonicalized path even if parts do not exist
 pub fn get_canonical_path_with_non_existent(path_str: &str) -> PathBuf {
     let path = PathBuf::from(path_str);
@@ -782,3 +1341,368 @@ pub fn get_canonical_path_with_non_existent(path_str: &str) -> PathBuf {
 
     resolved_path
 }
+
+/// Writes `rope` to a temporary file next to `path` and renames it over `path`, so a
+/// crash mid-write leaves the original intact. The original's permissions carry over,
+/// and a symlinked `path` has its target replaced rather than the link itself.
+pub(crate) fn write_atomic(path: &Path, rope: &Rope) -> io::Result<()> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "path has no file name"))?;
+
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".kerbin-{}.tmp", std::process::id()));
+    let tmp = path.with_file_name(tmp_name);
+
+    let write_tmp = || -> io::Result<()> {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        let mut writer = BufWriter::new(file);
+        rope.write_to(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            std::fs::set_permissions(&tmp, metadata.permissions())?;
+        }
+        Ok(())
+    };
+
+    let result = write_tmp().and_then(|()| match std::fs::rename(&tmp, &path) {
+        // Renames can't cross filesystems, so fall back to copying the contents over
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => std::fs::copy(&tmp, &path).map(|_| ()),
+        res => res,
+    });
+
+    // Nothing left to clean up after a successful rename
+    let _ = std::fs::remove_file(&tmp);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_with(text: &str) -> TextBuffer {
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, text);
+        buf
+    }
+
+    #[test]
+    fn select_next_occurrence_adds_cursors_and_wraps() {
+        let mut buf = buffer_with("foo bar foo\nfoo baz");
+        let sels = |buf: &TextBuffer| {
+            buf.cursors
+                .iter()
+                .map(|c| c.sel().clone())
+                .collect::<Vec<_>>()
+        };
+
+        // An empty selection grabs the word under the caret first
+        buf.primary_cursor_mut().move_to(9);
+        assert!(buf.select_next_occurrence(false));
+        assert_eq!(sels(&buf), vec![8..=10]);
+
+        assert!(buf.select_next_occurrence(false));
+        assert_eq!(sels(&buf), vec![8..=10, 12..=14]);
+
+        // Wraps around to the first one, then runs out
+        assert!(buf.select_next_occurrence(false));
+        assert_eq!(sels(&buf), vec![8..=10, 12..=14, 0..=2]);
+        assert!(!buf.select_next_occurrence(false));
+
+        // Skipping moves the primary cursor on instead of adding one
+        buf.drop_other_cursors();
+        buf.primary_cursor_mut().set_sel(0..=2);
+        assert!(buf.select_next_occurrence(true));
+        assert_eq!(sels(&buf), vec![8..=10]);
+        assert_eq!(buf.primary_cursor().sel(), &(8..=10));
+    }
+
+    #[test]
+    fn block_select_spans_columns_and_clamps_short_lines() {
+        let mut buf = buffer_with("abcd\nab\nabcdef");
+        buf.primary_cursor_mut().set_sel(1..=11);
+
+        buf.block_select(4);
+
+        // The short line stops at its last character instead of taking the newline
+        let sels: Vec<_> = buf.cursors.iter().map(|c| c.sel().clone()).collect();
+        assert_eq!(sels, vec![1..=3, 6..=6, 9..=11]);
+        assert_eq!(buf.primary_cursor, 2);
+    }
+
+    #[test]
+    fn block_select_skips_lines_ending_before_the_block() {
+        let mut buf = buffer_with("abcd\na\n\nabcdef");
+        buf.primary_cursor_mut().set_sel(2..=12);
+
+        buf.block_select(4);
+
+        let sels: Vec<_> = buf.cursors.iter().map(|c| c.sel().clone()).collect();
+        assert_eq!(sels, vec![2..=3, 10..=12]);
+        assert_eq!(buf.primary_cursor, 1);
+    }
+
+    #[test]
+    fn indents_selected_lines() {
+        let mut buf = buffer_with("a\nb\nc\nd");
+        buf.indent_style = IndentStyle::Spaces(2);
+        buf.primary_cursor_mut().set_sel(0..=4);
+
+        assert!(buf.shift_indent(false));
+        assert_eq!(buf.rope.to_string(), "  a\n  b\n  c\nd");
+        assert_eq!(*buf.primary_cursor().sel(), 2..=10);
+
+        buf.undo();
+        assert_eq!(buf.rope.to_string(), "a\nb\nc\nd");
+    }
+
+    #[test]
+    fn dedent_stops_at_column_zero() {
+        let mut buf = buffer_with("\tx\n      y\nz");
+        buf.indent_style = IndentStyle::Spaces(4);
+        buf.primary_cursor_mut().set_sel(0..=12);
+
+        assert!(buf.shift_indent(true));
+        assert_eq!(buf.rope.to_string(), "x\n  y\nz");
+
+        assert!(buf.shift_indent(true));
+        assert_eq!(buf.rope.to_string(), "x\ny\nz");
+
+        assert!(!buf.shift_indent(true));
+        assert_eq!(buf.rope.to_string(), "x\ny\nz");
+    }
+
+    #[test]
+    fn toggles_comments_after_indentation() {
+        let mut buf = buffer_with("fn a() {\n    b();\n\n}");
+        buf.primary_cursor_mut().set_sel(0..=20);
+
+        assert!(buf.toggle_comment("//"));
+        assert_eq!(buf.rope.to_string(), "// fn a() {\n    // b();\n\n// }");
+
+        assert!(buf.toggle_comment("//"));
+        assert_eq!(buf.rope.to_string(), "fn a() {\n    b();\n\n}");
+    }
+
+    #[test]
+    fn joins_the_line_below() {
+        let mut buf = buffer_with("let a =\n    1;\nb\n");
+
+        assert!(buf.join_lines(" ", ")"));
+        assert_eq!(buf.rope.to_string(), "let a = 1;\nb\n");
+        assert_eq!(*buf.primary_cursor().sel(), 7..=7);
+
+        // The trailing newline doesn't end a line that can be joined
+        buf.primary_cursor_mut().set_sel(11..=11);
+        assert!(!buf.join_lines(" ", ")"));
+        assert_eq!(buf.rope.to_string(), "let a = 1;\nb\n");
+
+        buf.undo();
+        assert_eq!(buf.rope.to_string(), "let a =\n    1;\nb\n");
+    }
+
+    #[test]
+    fn joins_every_selected_line_as_one_change() {
+        let mut buf = buffer_with("foo(\n  a,\n  b\n)\nend");
+        buf.primary_cursor_mut().set_sel(0..=14);
+
+        assert!(buf.join_lines(" ", ")"));
+        assert_eq!(buf.rope.to_string(), "foo( a, b)\nend");
+        assert_eq!(*buf.primary_cursor().sel(), 9..=9);
+
+        buf.undo();
+        assert_eq!(buf.rope.to_string(), "foo(\n  a,\n  b\n)\nend");
+    }
+
+    #[test]
+    fn undo_batches_swallow_inner_change_groups() {
+        let mut buf = buffer_with("a\nb\nc");
+        buf.primary_cursor_mut().set_sel(0..=0);
+
+        buf.begin_undo_batch();
+        buf.begin_undo_batch();
+        assert!(buf.join_lines(" ", ""));
+        buf.end_undo_batch();
+        buf.start_change_group();
+        assert!(buf.join_lines(" ", ""));
+        buf.commit_change_group();
+        assert!(buf.undo_stack.is_empty());
+        buf.end_undo_batch();
+        assert_eq!(buf.rope.to_string(), "a b c");
+
+        buf.undo();
+        assert_eq!(buf.rope.to_string(), "a\nb\nc");
+        assert!(buf.undo_stack.is_empty());
+
+        // Undoing inside a batch still undoes what came before it
+        buf.redo();
+        buf.begin_undo_batch();
+        buf.undo();
+        buf.action(Insert { byte: 0, content: "x".to_string() });
+        buf.end_undo_batch();
+        assert_eq!(buf.rope.to_string(), "xa\nb\nc");
+        buf.undo();
+        assert_eq!(buf.rope.to_string(), "a\nb\nc");
+    }
+
+    #[test]
+    fn write_file_bare_replaces_the_file_and_keeps_permissions() {
+        let dir = std::env::temp_dir().join(format!("kerbin-atomic-write-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "old contents that are longer").unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let mut buf = buffer_with("new");
+        buf.path = path.to_string_lossy().into_owned();
+        buf.write_file_bare().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(buf.changed, std::fs::metadata(&path).unwrap().modified().ok());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+
+        // Only the target is left behind, no temporary files
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn big_files_open_read_only() {
+        let path = std::env::temp_dir().join(format!("kerbin-big-file-{}.txt", std::process::id()));
+        std::fs::write(&path, "0123456789\n".repeat(10)).unwrap();
+        let path_str = path.to_string_lossy().into_owned();
+
+        let small = TextBuffer::open_async(path_str.clone(), 4, 1024).await.unwrap();
+        assert!(!small.big_file);
+
+        let mut big = TextBuffer::open_async(path_str, 4, 64).await.unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert!(big.big_file);
+        assert_eq!(big.len_lines(), 11);
+        assert!(!big.action(Insert {
+            byte: 0,
+            content: "x".into()
+        }));
+        assert!(!big.dirty);
+    }
+
+    #[tokio::test]
+    async fn readonly_buffers_refuse_edits_until_cleared() {
+        let mut buf = buffer_with("abc");
+        buf.action(Delete { byte: 2, len: 1 });
+        buf.commit_change_group();
+
+        buf.set_readonly(true);
+        assert!(!buf.action(Insert {
+            byte: 0,
+            content: "x".into()
+        }));
+        buf.insert(0, "x");
+        buf.remove_range(0..1);
+        buf.undo();
+        assert_eq!(buf.get_rope().to_string(), "ab");
+        assert!(buf.flags.contains(READONLY_REFUSED_FLAG));
+        assert_eq!(
+            buf.write_file(None).await.unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+
+        buf.set_readonly(false);
+        assert!(buf.action(Insert {
+            byte: 0,
+            content: "x".into()
+        }));
+        assert_eq!(buf.get_rope().to_string(), "xab");
+        buf.undo();
+        buf.undo();
+        assert_eq!(buf.get_rope().to_string(), "abc");
+    }
+
+    #[test]
+    fn generated_buffers_are_read_only() {
+        let mut buf = TextBuffer::generated("<listing>", "one\n");
+        assert!(buf.is_readonly() && !buf.dirty && buf.undo_stack.is_empty());
+        assert!(!buf.action(Insert {
+            byte: 0,
+            content: "x".into()
+        }));
+
+        // Regenerating replaces the text without making it editable
+        buf.set_generated_text("two\n");
+        assert_eq!(buf.get_rope().to_string(), "two\n");
+        assert!(buf.is_readonly() && !buf.dirty && buf.undo_stack.is_empty());
+        buf.undo();
+        assert_eq!(buf.get_rope().to_string(), "two\n");
+    }
+
+    #[test]
+    fn test_move_graphemes_crosses_combining_sequence() {
+        // "e" followed by U+0301 COMBINING ACUTE ACCENT renders as a single "é"
+        let mut buf = buffer_with("e\u{301}x");
+
+        assert!(buf.move_graphemes(1, false));
+        assert_eq!(buf.primary_cursor().get_cursor_byte(), 3);
+
+        assert!(buf.move_graphemes(-1, false));
+        assert_eq!(buf.primary_cursor().get_cursor_byte(), 0);
+    }
+
+    #[test]
+    fn test_move_graphemes_crosses_zwj_sequence() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let mut buf = buffer_with(&format!("a{family}b"));
+
+        buf.move_graphemes(1, false);
+        assert!(buf.move_graphemes(1, true));
+        assert_eq!(buf.primary_cursor().get_cursor_byte(), 1 + family.len());
+        assert_eq!(*buf.primary_cursor().sel(), 1..=1 + family.len());
+
+        buf.move_graphemes(-1, false);
+        assert_eq!(buf.primary_cursor().get_cursor_byte(), 1);
+    }
+
+    #[test]
+    fn test_move_graphemes_clamps_at_buffer_edges() {
+        let mut buf = buffer_with("ab");
+
+        assert!(!buf.move_graphemes(-3, false));
+        assert!(buf.move_graphemes(10, false));
+        assert_eq!(buf.primary_cursor().get_cursor_byte(), 2);
+    }
+
+    #[derive(Default, kerbin_macros::State)]
+    struct TestState;
+
+    #[test]
+    fn test_state_names_and_remove() {
+        let mut buf = TextBuffer::scratch();
+        assert!(buf.state_names().is_empty());
+
+        buf.set_state(TestState);
+        assert_eq!(buf.state_names(), vec![TestState::static_name().as_str()]);
+
+        assert!(buf.remove_state::<TestState>());
+        assert!(!buf.remove_state::<TestState>());
+        assert!(buf.state_names().is_empty());
+    }
+}
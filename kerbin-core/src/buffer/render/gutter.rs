@@ -1,35 +1,243 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
 use ratatui::{prelude::*, widgets::Paragraph};
 
 use crate::Theme;
 
+/// How the gutter numbers each line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LineNumbers {
+    /// Every line shows its own number
+    #[default]
+    Absolute,
+    /// Every line shows its distance from the cursor line, which shows 0
+    Relative,
+    /// Like `Relative`, but the cursor line shows its own number
+    Hybrid,
+}
+
+impl LineNumbers {
+    /// Number shown beside the 0-based `line`
+    pub fn label(&self, line: usize, cursor_line: usize) -> usize {
+        match self {
+            Self::Absolute => line + 1,
+            Self::Hybrid if line == cursor_line => line + 1,
+            Self::Relative | Self::Hybrid => line.abs_diff(cursor_line),
+        }
+    }
+
+    /// Digits needed for the largest number shown while `lines` are visible
+    pub fn max_digits(&self, lines: std::ops::Range<usize>, cursor_line: usize) -> u16 {
+        let Some(last) = lines.end.checked_sub(1).filter(|l| *l >= lines.start) else {
+            return 1;
+        };
+
+        let largest = match self {
+            Self::Absolute => last + 1,
+            _ => {
+                let furthest = self
+                    .label(lines.start, cursor_line)
+                    .max(self.label(last, cursor_line));
+                if lines.contains(&cursor_line) {
+                    furthest.max(self.label(cursor_line, cursor_line))
+                } else {
+                    furthest
+                }
+            }
+        };
+
+        largest.max(1).ilog10() as u16 + 1
+    }
+}
+
+impl std::fmt::Display for LineNumbers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Absolute => "absolute",
+            Self::Relative => "relative",
+            Self::Hybrid => "hybrid",
+        })
+    }
+}
+
+impl FromStr for LineNumbers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "absolute" => Ok(Self::Absolute),
+            "relative" => Ok(Self::Relative),
+            "hybrid" => Ok(Self::Hybrid),
+            _ => Err(format!("Expected `absolute`, `relative`, or `hybrid`, found: {s}")),
+        }
+    }
+}
+
+/// When the gutter draws its sign column
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SignColumn {
+    /// Drawn while the buffer has any sign, so the numbers don't shift while scrolling
+    #[default]
+    Auto,
+    /// Always drawn
+    Yes,
+    /// Never drawn, hiding signs
+    No,
+}
+
+impl std::fmt::Display for SignColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Yes => "yes",
+            Self::No => "no",
+        })
+    }
+}
+
+impl FromStr for SignColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "yes" => Ok(Self::Yes),
+            "no" => Ok(Self::No),
+            _ => Err(format!("Expected `auto`, `yes`, or `no`, found: {s}")),
+        }
+    }
+}
+
 /// Widget that renders line numbers into a gutter area
 pub struct GutterWidget {
     line_scroll: usize,
     total_lines: usize,
     style: Style,
+
+    numbers: LineNumbers,
+    cursor_line: usize,
+    cursor_style: Style,
+
+    /// Sign glyph and style per line
+    signs: HashMap<usize, (String, Style)>,
+    /// Whether the sign column is drawn even without visible signs
+    sign_column: bool,
+    /// First lines of closed folds, marked in the sign column when they have no sign
+    folds: HashSet<usize>,
+    fold_style: Style,
+
+    /// Line drawn on each row, when known. Rows repeating the line above are wrapped
+    /// continuations, which show `wrap_indicator` instead of a number.
+    rows: Option<Vec<usize>>,
+    wrap_indicator: String,
 }
 
+/// Columns taken by the sign column (glyph and a space) when any sign is visible
+pub const SIGN_WIDTH: u16 = 2;
+
+/// Sign column glyph for the first line of a closed fold
+pub const FOLD_SIGN: &str = "▸";
+
 impl GutterWidget {
     pub fn new(line_scroll: usize, total_lines: usize, theme: &Theme) -> Self {
         Self {
             line_scroll,
             total_lines,
             style: theme.get_fallback_default(["ui.gutter"]),
+            numbers: LineNumbers::default(),
+            cursor_line: 0,
+            cursor_style: theme.get_fallback_default(["ui.gutter.current", "ui.gutter"]),
+            signs: HashMap::new(),
+            sign_column: false,
+            folds: HashSet::new(),
+            fold_style: theme.get_fallback_default(["ui.gutter.fold", "ui.gutter"]),
+            rows: None,
+            wrap_indicator: String::new(),
         }
     }
+
+    /// Numbers the rows a render drew, given as `(line, start column)` per row
+    pub fn with_rows(mut self, rows: &[(usize, usize)], wrap_indicator: &str) -> Self {
+        if !rows.is_empty() {
+            self.rows = Some(rows.iter().map(|(line, _)| *line).collect());
+        }
+        self.wrap_indicator = wrap_indicator.to_string();
+        self
+    }
+
+    /// Draws a sign column left of the numbers, keyed by 0-based line. With `reserve`,
+    /// the column is drawn even when none of the signs are visible
+    pub fn with_signs(mut self, signs: HashMap<usize, (String, Style)>, reserve: bool) -> Self {
+        self.signs = signs;
+        self.sign_column = reserve;
+        self
+    }
+
+    /// Marks the first lines of closed folds with `FOLD_SIGN`, keyed by 0-based line
+    pub fn with_folds(mut self, folds: HashSet<usize>) -> Self {
+        self.folds = folds;
+        self
+    }
+
+    /// Numbers lines relative to `cursor_line` according to `numbers`
+    pub fn with_line_numbers(mut self, numbers: LineNumbers, cursor_line: usize) -> Self {
+        self.numbers = numbers;
+        self.cursor_line = cursor_line;
+        self
+    }
 }
 
 impl Widget for GutterWidget {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let width = area.width as usize;
+        let sign_width = if self.sign_column || !self.signs.is_empty() || !self.folds.is_empty() {
+            SIGN_WIDTH as usize
+        } else {
+            0
+        };
+        let width = (area.width as usize).saturating_sub(sign_width);
         let lines: Vec<Line<'static>> = (0..area.height)
             .map(|row| {
-                let line_num = self.line_scroll + row as usize + 1;
-                if line_num > self.total_lines {
-                    Line::default()
-                } else {
-                    Line::raw(format!("{:>width$}", line_num))
+                let row = row as usize;
+                let line = match &self.rows {
+                    Some(rows) => match rows.get(row) {
+                        Some(line) => *line,
+                        None => return Line::default(),
+                    },
+                    None => self.line_scroll + row,
+                };
+                if line >= self.total_lines {
+                    return Line::default();
+                }
+
+                let continued = row > 0
+                    && self
+                        .rows
+                        .as_ref()
+                        .is_some_and(|rows| rows.get(row - 1) == Some(&line));
+                if continued {
+                    let padding = " ".repeat(sign_width);
+                    return Line::from(format!("{padding}{:>width$}", self.wrap_indicator));
                 }
+
+                let sign = match self.signs.get(&line) {
+                    Some((text, style)) => Span::styled(format!("{text:<sign_width$}"), *style),
+                    None if self.folds.contains(&line) => {
+                        Span::styled(format!("{FOLD_SIGN:<sign_width$}"), self.fold_style)
+                    }
+                    None => Span::raw(" ".repeat(sign_width)),
+                };
+
+                let label = format!("{:>width$}", self.numbers.label(line, self.cursor_line));
+                let label = if line == self.cursor_line {
+                    Span::styled(label, self.cursor_style)
+                } else {
+                    Span::raw(label)
+                };
+
+                Line::from(vec![sign, label])
             })
             .collect();
         Paragraph::new(Text::from(lines))
@@ -37,3 +245,27 @@ impl Widget for GutterWidget {
             .render(area, buf);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_follow_mode() {
+        assert_eq!(LineNumbers::Absolute.label(4, 9), 5);
+        assert_eq!(LineNumbers::Relative.label(4, 9), 5);
+        assert_eq!(LineNumbers::Relative.label(9, 9), 0);
+        assert_eq!(LineNumbers::Hybrid.label(9, 9), 10);
+        assert_eq!(LineNumbers::Hybrid.label(12, 9), 3);
+    }
+
+    #[test]
+    fn digits_fit_largest_visible_number() {
+        assert_eq!(LineNumbers::Absolute.max_digits(0..9, 0), 1);
+        assert_eq!(LineNumbers::Absolute.max_digits(95..140, 100), 3);
+        assert_eq!(LineNumbers::Relative.max_digits(95..140, 100), 2);
+        assert_eq!(LineNumbers::Hybrid.max_digits(95..140, 100), 3);
+        // Cursor scrolled out of view
+        assert_eq!(LineNumbers::Relative.max_digits(0..5, 2000), 4);
+    }
+}
@@ -26,20 +26,32 @@ pub async fn render_buffer_default(
 
     theme: Res<Theme>,
     core_config: Res<CoreConfig>,
+    whitespace: Res<WhitespaceConfig>,
+    gutter_config: Res<GutterConfig>,
 ) {
     let Some(mut chunk) = chunk.get().await else {
         return;
     };
 
-    get!(buffers, theme, core_config);
+    get!(buffers, theme, core_config, whitespace, gutter_config);
 
-    let ctx = RenderContext { theme: &theme, core_config: &core_config };
+    let ctx = RenderContext {
+        theme: &theme,
+        core_config: &core_config,
+        whitespace: &whitespace,
+        gutter: &gutter_config,
+    };
     let area = chunk.area();
     let buf_arc = buffers.buffers[buffers.selected_buffer].clone();
     let mut buf = buf_arc.write_owned().await;
-    buf.render(area, &mut chunk, true, &ctx);
+    let version = buf.render_version(area, true, &ctx);
+    if version.is_none_or(|v| chunk.draw_version(v)) {
+        buf.render(area, &mut chunk, true, &ctx);
+    }
 
-    if let Some(mut gutter) = gutter_chunk.get().await {
+    if let Some(mut gutter) = gutter_chunk.get().await
+        && version.is_none_or(|v| gutter.draw_version(v))
+    {
         let gutter_area = gutter.area();
         buf.render_gutter(gutter_area, &mut gutter, &ctx);
     }
@@ -53,14 +65,21 @@ pub async fn render_splits(
     buffers: Res<Buffers>,
     theme: Res<Theme>,
     core_config: Res<CoreConfig>,
+    whitespace: Res<WhitespaceConfig>,
+    gutter_config: Res<GutterConfig>,
 ) {
-    get!(chunks, split, buffers, theme, core_config);
+    get!(chunks, split, buffers, theme, core_config, whitespace, gutter_config);
 
     if split.pane_count() <= 1 {
         return;
     }
 
-    let ctx = RenderContext { theme: &theme, core_config: &core_config };
+    let ctx = RenderContext {
+        theme: &theme,
+        core_config: &core_config,
+        whitespace: &whitespace,
+        gutter: &gutter_config,
+    };
     let focused_id = split.focused_id;
     for (i, pane) in split.leaves().iter().enumerate() {
         if pane.id == focused_id {
@@ -87,12 +106,17 @@ pub async fn render_splits(
             };
             let mut chunk = chunk_arc.write_owned().await;
             let area = chunk.area();
-            buf.render(area, &mut chunk, false, &ctx);
+            let version = buf.render_version(area, false, &ctx);
+            if version.is_none_or(|v| chunk.draw_version(v)) {
+                buf.render(area, &mut chunk, false, &ctx);
+            }
 
             if let Some(gutter_arc) = chunks.get_indexed_chunk::<BufferGutterChunk>(i) {
                 let mut gutter = gutter_arc.write_owned().await;
-                let gutter_area = gutter.area();
-                buf.render_gutter(gutter_area, &mut gutter, &ctx);
+                if version.is_none_or(|v| gutter.draw_version(v)) {
+                    let gutter_area = gutter.area();
+                    buf.render_gutter(gutter_area, &mut gutter, &ctx);
+                }
             }
         }
 
@@ -17,18 +17,27 @@ pub struct BufferRenderer {
     /// Namespaces not in this map default to priority 0.
     namespace_priorities: HashMap<String, i32>,
 
-    /// The byte based scroll of the window
+    /// The line the view is scrolled to, which the cursor logic works against
     pub byte_scroll: usize,
 
-    /// The visual scroll, marks where rendered items should be offset based on the byte_scroll
+    /// The line actually drawn at the top of the view.
+    /// Trails `byte_scroll` over a few frames when smooth scrolling is enabled.
     pub visual_scroll: usize,
 
     /// The scroll horizontally of the lines
     pub h_scroll: usize,
 
+    /// The line and first display column drawn on each row of the view by the last render.
+    /// Rows differ from lines once lines wrap.
+    pub screen_rows: Vec<(usize, usize)>,
+
     /// Set by `scroll_lines` to tell the update loop to clamp the cursor into the viewport
     /// (rather than scrolling to follow the cursor).
     pub cursor_drag: bool,
+
+    /// Set by `goto_line` to tell the update loop to scroll the cursor's line into the middle
+    /// of the viewport
+    pub center_cursor: bool,
 }
 
 
@@ -191,6 +200,13 @@ impl BufferRenderer {
         marks
     }
 
+    /// Whether any extmark is a gutter sign
+    pub fn has_signs(&self) -> bool {
+        self.extmarks
+            .values()
+            .any(|mark| matches!(mark.kind, ExtmarkKind::Sign { .. }))
+    }
+
     /// Process all byte changes from the buffer
     pub fn process_byte_changes(
         &mut self,
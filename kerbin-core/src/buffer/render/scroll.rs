@@ -1,4 +1,8 @@
 use crate::*;
+use ratatui::{
+    crossterm::event::Event,
+    layout::{Constraint, Layout, Rect},
+};
 use unicode_segmentation::UnicodeSegmentation;
 
 /// Converts a display column to the byte offset based on visual positions
@@ -59,8 +63,10 @@ pub async fn update_buffer_horizontal_scroll(
     chunks: Res<Chunks>,
     split: Res<SplitState>,
     buffers: ResMut<Buffers>,
+    core_config: Res<CoreConfig>,
 ) {
-    get!(chunks, split, mut buffers);
+    get!(chunks, split, mut buffers, core_config);
+    let tab_w = core_config.tab_display_unit.chars().count();
 
     let viewport_width = split
         .focused_leaf_idx()
@@ -77,37 +83,27 @@ pub async fn update_buffer_horizontal_scroll(
         return;
     };
 
+    // Wrapped lines always fit the view's width
+    if core_config.wrap {
+        buf.renderer.h_scroll = 0;
+        return;
+    }
+
     let cursor_byte = buf.primary_cursor().get_cursor_byte().min(buf.len());
     let cursor_line_idx = buf.byte_to_line_clamped(cursor_byte);
     let line_start_byte = buf.line_to_byte_clamped(cursor_line_idx);
 
-    let line_text = buf
-        .slice_to_string(line_start_byte, cursor_byte)
-        .unwrap_or_default();
-    let cursor_col: usize = line_text.graphemes(true).map(grapheme_display_width).sum();
-
-    const H_SCROLL_PADDING: usize = 5;
-
-    let cursor_viewport_col = cursor_col.saturating_sub(buf.renderer.h_scroll);
-
-    if cursor_viewport_col >= viewport_width.saturating_sub(H_SCROLL_PADDING) {
-        let target_col = viewport_width.saturating_sub(H_SCROLL_PADDING + 1);
-        let needed_scroll = cursor_viewport_col - target_col;
-        buf.renderer.h_scroll += needed_scroll;
-    }
-
-    if cursor_viewport_col < H_SCROLL_PADDING {
-        let needed_scroll = H_SCROLL_PADDING - cursor_viewport_col;
-        if buf.renderer.h_scroll >= needed_scroll {
-            buf.renderer.h_scroll -= needed_scroll;
-        } else {
-            buf.renderer.h_scroll = 0;
-        }
-    }
+    let cursor_col = buf
+        .rope
+        .visual_col_of_byte(cursor_line_idx, cursor_byte - line_start_byte, tab_w);
 
-    if cursor_col < buf.renderer.h_scroll {
-        buf.renderer.h_scroll = cursor_col.saturating_sub(H_SCROLL_PADDING);
-    }
+    buf.renderer.h_scroll = follow_cursor(
+        cursor_col,
+        buf.renderer.h_scroll,
+        viewport_width,
+        core_config.sidescrolloff,
+        usize::MAX,
+    );
 }
 
 pub async fn update_buffer_vertical_scroll(
@@ -119,12 +115,12 @@ pub async fn update_buffer_vertical_scroll(
     get!(chunks, split, mut buffers, core_config);
     let tab_w = core_config.tab_display_unit.chars().count();
 
-    let viewport_height = split
+    let (viewport_width, viewport_height) = split
         .focused_leaf_idx()
         .and_then(|i| chunks.rect_for_indexed_chunk::<BufferChunk>(i))
         .or_else(|| chunks.rect_for_chunk(&BufferChunk::static_name()))
-        .map(|r| r.height as usize)
-        .unwrap_or(0);
+        .map(|r| (r.width as usize, r.height as usize))
+        .unwrap_or((0, 0));
 
     if viewport_height == 0 {
         return;
@@ -137,23 +133,40 @@ pub async fn update_buffer_vertical_scroll(
     let cursor_byte = buf.primary_cursor().get_cursor_byte().min(buf.len());
     let cursor_line_idx = buf.byte_to_line_clamped(cursor_byte);
 
-    const SCROLL_PADDING: usize = 3;
+    // Jumps and searches that land in a closed fold open it
+    buf.folds.reveal(cursor_line_idx);
+
+    let scrolloff = clamp_scrolloff(core_config.scrolloff, viewport_height);
 
     let max_byte_scroll = buf.len_lines().saturating_sub(1);
     buf.renderer.byte_scroll = buf.renderer.byte_scroll.min(max_byte_scroll);
 
+    // When goto_line jumped, put the cursor's line in the middle of the viewport
+    // before following it, which then only has to account for wrapping
+    if buf.renderer.center_cursor {
+        buf.renderer.center_cursor = false;
+        buf.renderer.byte_scroll = cursor_line_idx
+            .saturating_sub(viewport_height / 2)
+            .min(max_byte_scroll);
+    }
+
     // When scroll_lines moved the viewport, clamp the cursor into the visible area
     // (with symmetric padding) rather than scrolling to follow the cursor.
     if buf.renderer.cursor_drag {
         buf.renderer.cursor_drag = false;
-        buf.renderer.visual_scroll = 0;
 
         let scroll = buf.renderer.byte_scroll;
-        let top_bound = (scroll + SCROLL_PADDING).min(max_byte_scroll);
-        let bottom_bound = scroll
-            .saturating_add(viewport_height)
-            .saturating_sub(SCROLL_PADDING + 1)
-            .min(max_byte_scroll);
+        // No padding is needed against the start or end of the file
+        let top_bound = if scroll == 0 {
+            0
+        } else {
+            (scroll + scrolloff).min(max_byte_scroll)
+        };
+        let bottom_bound = if scroll + viewport_height > max_byte_scroll {
+            max_byte_scroll
+        } else {
+            (scroll + viewport_height).saturating_sub(scrolloff + 1)
+        };
         // bottom_bound can only be less than top_bound on very small viewports; clamp defensively.
         let bottom_bound = bottom_bound.max(top_bound);
 
@@ -165,35 +178,345 @@ pub async fn update_buffer_vertical_scroll(
         return;
     }
 
-    // Normal case: scroll follows the cursor.
-    if cursor_line_idx < buf.renderer.byte_scroll {
-        buf.renderer.byte_scroll = cursor_line_idx.saturating_sub(SCROLL_PADDING);
-        buf.renderer.visual_scroll = 0;
+    // Normal case: scroll follows the cursor. The view can't start inside a closed fold
+    let scroll = buf.folds.visible_line(follow_cursor(
+        cursor_line_idx,
+        buf.renderer.byte_scroll,
+        viewport_height,
+        core_config.scrolloff,
+        max_byte_scroll,
+    ));
+
+    let wrap = core_config.wrap && viewport_width > 0;
+    buf.renderer.byte_scroll = if wrap || buf.folds.has_closed() {
+        // Lines hidden by folds take no rows
+        let rows = |line: usize| {
+            if buf.folds.is_hidden(line) {
+                return 0;
+            }
+            if !wrap {
+                return 1;
+            }
+            let len = buf.line_to_byte_clamped(line + 1) - buf.line_to_byte_clamped(line);
+            let width = buf.rope.visual_col_of_byte(line, len, tab_w);
+            width.max(1).div_ceil(viewport_width)
+        };
+
+        let cursor_row = if wrap {
+            let line_start_byte = buf.line_to_byte_clamped(cursor_line_idx);
+            let cursor_col = buf
+                .rope
+                .visual_col_of_byte(cursor_line_idx, cursor_byte - line_start_byte, tab_w);
+            cursor_col / viewport_width
+        } else {
+            0
+        };
+
+        let scroll = follow_wrapped_cursor(
+            cursor_line_idx,
+            cursor_row,
+            scroll,
+            viewport_height,
+            core_config.scrolloff,
+            max_byte_scroll,
+            rows,
+        );
+        // Pushed past a fold's first line, so the view starts after the fold
+        if buf.folds.is_hidden(scroll) {
+            buf.folds.step_lines(scroll, 1, max_byte_scroll)
+        } else {
+            scroll
+        }
+    } else {
+        scroll
+    };
+}
+
+/// Handles a terminal resize during the update, before the frame is laid out in the new
+/// size: everything is composited again, and the buffer in every pane scrolls to keep its
+/// cursor inside the pane's new height, dropping any smooth scroll aimed at the old one
+pub async fn handle_resize(
+    events: Res<CrosstermEvents>,
+    chunks: ResMut<Chunks>,
+    split: Res<SplitState>,
+    buffers: Res<Buffers>,
+    layout: Res<LayoutConfig>,
+    core_config: Res<CoreConfig>,
+) {
+    get!(events, mut chunks, split, buffers, layout, core_config);
+
+    let Some((width, height)) = events.0.iter().rev().find_map(|event| match event {
+        Event::Resize(width, height) => Some((*width, *height)),
+        _ => None,
+    }) else {
         return;
+    };
+    tracing::debug!("terminal resized to {width}x{height}");
+
+    chunks.force_redraw();
+
+    let [main_area, _] = Layout::vertical([
+        Constraint::Fill(1),
+        Constraint::Length(layout.statusline_height),
+    ])
+    .areas(Rect::new(0, 0, width, height));
+
+    for (pane_id, rect) in collect_leaf_rects(&split.root, main_area) {
+        let Some(pane) = split.root.find_pane(pane_id) else {
+            continue;
+        };
+        let viewport = rect.height.saturating_sub(layout.bufferline_height) as usize;
+
+        let buf_idx = if !split.unique_buffers {
+            Some(pane.selected_local)
+        } else {
+            pane.buffer_indices.get(pane.selected_local).copied()
+        };
+        let Some(buf) = buf_idx.and_then(|i| buffers.buffers.get(i)) else {
+            continue;
+        };
+        let mut buf = buf.write().await;
+        let Some(buf) = buf.downcast_mut::<TextBuffer>() else {
+            continue;
+        };
+
+        let max_byte_scroll = buf.len_lines().saturating_sub(1);
+        if viewport > 0 {
+            let cursor_byte = buf.primary_cursor().get_cursor_byte().min(buf.len());
+            let cursor_line = buf.byte_to_line_clamped(cursor_byte);
+            buf.renderer.byte_scroll = buf.folds.visible_line(follow_cursor(
+                cursor_line,
+                buf.renderer.byte_scroll.min(max_byte_scroll),
+                viewport,
+                core_config.scrolloff,
+                max_byte_scroll,
+            ));
+        }
+        buf.renderer.visual_scroll = buf.renderer.byte_scroll;
     }
+}
 
-    let cursor_viewport_position = cursor_line_idx - buf.renderer.byte_scroll;
+/// Moves each text buffer's `visual_scroll` towards its `byte_scroll`, a step per frame
+/// when `smooth_scroll` is enabled and straight there otherwise
+pub async fn animate_buffer_scroll(
+    buffers: ResMut<Buffers>,
+    core_config: Res<CoreConfig>,
+    activity: Res<FrameActivity>,
+) {
+    get!(mut buffers, core_config, activity);
+
+    let steps = if core_config.smooth_scroll {
+        core_config.smooth_scroll_steps
+    } else {
+        1
+    };
+
+    for buf in &buffers.buffers {
+        let mut buf = buf.write().await;
+        let Some(buf) = buf.downcast_mut::<TextBuffer>() else {
+            continue;
+        };
+
+        let max_scroll = buf.len_lines().saturating_sub(1);
+        let current = buf.renderer.visual_scroll.min(max_scroll);
+        buf.renderer.visual_scroll = step_scroll(current, buf.renderer.byte_scroll, steps);
 
-    if cursor_viewport_position >= viewport_height.saturating_sub(SCROLL_PADDING) {
-        buf.renderer.byte_scroll =
-            cursor_line_idx.saturating_sub(viewport_height.saturating_sub(SCROLL_PADDING + 1));
+        if buf.renderer.visual_scroll != buf.renderer.byte_scroll {
+            activity.mark();
+        }
     }
+}
 
-    buf.renderer.visual_scroll = 0;
-    buf.renderer.byte_scroll = buf.renderer.byte_scroll.min(max_byte_scroll);
+/// Returns the next scroll on the way from `current` to `target` when the move is spread
+/// over `steps` frames. Moves of a single line aren't animated, and the last step always
+/// lands exactly on `target`.
+fn step_scroll(current: usize, target: usize, steps: usize) -> usize {
+    let distance = current.abs_diff(target);
+    if steps <= 1 || distance <= 1 {
+        return target;
+    }
+
+    let step = distance.div_ceil(steps);
+    if current < target {
+        current + step
+    } else {
+        current - step
+    }
+}
+
+/// Limits `scrolloff` so the padding above and below the cursor fits in `viewport` rows
+fn clamp_scrolloff(scrolloff: usize, viewport: usize) -> usize {
+    scrolloff.min(viewport.saturating_sub(1) / 2)
+}
+
+/// Returns the scroll that keeps `cursor` at least `scrolloff` rows (or columns) inside a
+/// `viewport` starting at `scroll`, moving it as little as possible. Padding past `last`,
+/// the final line or column, isn't kept, so the view doesn't scroll into empty space.
+fn follow_cursor(cursor: usize, scroll: usize, viewport: usize, scrolloff: usize, last: usize) -> usize {
+    let scrolloff = clamp_scrolloff(scrolloff, viewport);
+
+    if cursor < scroll + scrolloff {
+        return cursor.saturating_sub(scrolloff);
+    }
+
+    let padded_end = cursor.saturating_add(scrolloff).min(last.max(cursor));
+    if padded_end >= scroll + viewport {
+        return padded_end + 1 - viewport;
+    }
+
+    scroll
+}
+
+/// Pushes `scroll` down until the cursor's row, and `scrolloff` rows below it, fit in the
+/// `viewport` once lines wrap. `rows` gives how many rows a line wraps onto, and the cursor
+/// is on row `cursor_row` of its line. Works on the line scroll from `follow_cursor`.
+fn follow_wrapped_cursor(
+    cursor_line: usize,
+    cursor_row: usize,
+    scroll: usize,
+    viewport: usize,
+    scrolloff: usize,
+    last: usize,
+    rows: impl Fn(usize) -> usize,
+) -> usize {
+    let scrolloff = clamp_scrolloff(scrolloff, viewport);
+
+    let mut below = rows(cursor_line).saturating_sub(cursor_row + 1);
+    let mut line = cursor_line + 1;
+    while below < scrolloff && line <= last {
+        below += rows(line);
+        line += 1;
+    }
+    let needed_below = cursor_row + 1 + below.min(scrolloff);
+
+    let mut scroll = scroll.min(cursor_line);
+    let mut above: usize = (scroll..cursor_line).map(&rows).sum();
+    while scroll < cursor_line && above + needed_below > viewport {
+        above -= rows(scroll);
+        scroll += 1;
+    }
+    scroll
 }
 
 /// Moves the primary cursor to `target_line`, preserving the current visual column.
 fn drag_cursor_to_line(buf: &mut TextBuffer, cursor_byte: usize, cursor_line: usize, target_line: usize, tab_w: usize) {
     let line_start_byte = buf.line_to_byte_clamped(cursor_line);
-    let line_prefix = buf.slice_to_string(line_start_byte, cursor_byte).unwrap_or_default();
-    let visual_col = byte_offset_to_display_col(&line_prefix, line_prefix.len(), tab_w);
+    let visual_col = buf
+        .rope
+        .visual_col_of_byte(cursor_line, cursor_byte.saturating_sub(line_start_byte), tab_w);
 
-    let target_line_text = buf.line_clamped(target_line).to_string();
-    let target_byte_offset = display_col_to_byte_offset(&target_line_text, visual_col, tab_w);
+    let target_byte_offset = buf.rope.byte_of_visual_col(target_line, visual_col, tab_w);
     let new_caret_byte = buf.line_to_byte_clamped(target_line) + target_byte_offset;
 
     let cursor_mut = buf.primary_cursor_mut();
     cursor_mut.set_sel(new_caret_byte..=new_caret_byte);
     cursor_mut.set_at_start(false);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_scrolloff_rows_around_cursor() {
+        // 10 rows, 3 rows of padding, 100 lines
+        assert_eq!(follow_cursor(15, 10, 10, 3, 99), 10);
+        assert_eq!(follow_cursor(12, 10, 10, 3, 99), 9);
+        assert_eq!(follow_cursor(17, 10, 10, 3, 99), 11);
+        // Jumps far away land with the padding intact
+        assert_eq!(follow_cursor(60, 0, 10, 3, 99), 54);
+    }
+
+    #[test]
+    fn clamps_at_file_edges() {
+        assert_eq!(follow_cursor(1, 0, 10, 3, 99), 0);
+        // The last line can reach the bottom row instead of scrolling into empty space
+        assert_eq!(follow_cursor(99, 85, 10, 3, 99), 90);
+        assert_eq!(follow_cursor(97, 90, 10, 3, 99), 90);
+    }
+
+    #[test]
+    fn scrolloff_larger_than_viewport_centers() {
+        assert_eq!(clamp_scrolloff(100, 11), 5);
+        assert_eq!(follow_cursor(50, 0, 11, 100, 99), 45);
+        assert_eq!(follow_cursor(50, 45, 11, 100, 99), 45);
+    }
+
+    #[test]
+    fn wrapped_lines_push_scroll_down() {
+        // Every line wraps onto 3 rows, so 7 rows only fit 2 lines and a bit
+        let rows = |_| 3;
+        assert_eq!(follow_wrapped_cursor(5, 0, 3, 7, 0, 99, rows), 3);
+        assert_eq!(follow_wrapped_cursor(5, 2, 3, 7, 0, 99, rows), 4);
+        // Padding below the cursor is counted in rows
+        assert_eq!(follow_wrapped_cursor(5, 2, 3, 7, 2, 99, rows), 5);
+
+        // Unwrapped lines leave the scroll alone
+        assert_eq!(follow_wrapped_cursor(15, 0, 10, 10, 3, 99, |_| 1), 10);
+    }
+
+    #[test]
+    fn smooth_scroll_settles_on_target() {
+        assert_eq!(step_scroll(10, 11, 4), 11);
+        assert_eq!(step_scroll(0, 40, 1), 40);
+
+        let mut scroll = 0;
+        let mut frames = 0;
+        while scroll != 40 {
+            scroll = step_scroll(scroll, 40, 4);
+            frames += 1;
+        }
+        assert!(frames > 1);
+        assert_eq!(step_scroll(40, 0, 4), 30);
+    }
+
+    #[tokio::test]
+    async fn resizing_keeps_every_panes_cursor_in_view() {
+        let mut buffers = Buffers::default();
+        for cursor_line in [50, 80] {
+            let mut buf = TextBuffer::scratch();
+            buf.rope = "line\n".repeat(100).as_str().into();
+            let byte = buf.line_to_byte_clamped(cursor_line);
+            buf.primary_cursor_mut().set_sel(byte..=byte);
+            // Scrolled to fit a 40 row pane
+            buf.renderer.byte_scroll = 30;
+            buf.renderer.visual_scroll = 35;
+            buffers.push_buffer(buf).await;
+        }
+
+        let mut split = SplitState::default();
+        split.leaves_mut()[0].selected_local = 1;
+        split.split_focused(SplitDir::Horizontal);
+        split.leaves_mut()[1].selected_local = 2;
+
+        let mut state = State::new();
+        state
+            .state(CrosstermEvents(vec![]))
+            .state(Chunks::default())
+            .state(split)
+            .state(buffers)
+            .state(LayoutConfig::default())
+            .state(CoreConfig::default());
+
+        let scrolls = async |state: &State| {
+            let buffers = state.lock_state::<Buffers>().await;
+            let mut scrolls = vec![];
+            for buf in &buffers.buffers[1..] {
+                let buf = buf.read().await;
+                let buf = buf.downcast::<TextBuffer>().unwrap();
+                scrolls.push((buf.renderer.byte_scroll, buf.renderer.visual_scroll));
+            }
+            scrolls
+        };
+
+        // Nothing changes without a resize
+        state.call(handle_resize).await;
+        assert_eq!(scrolls(&state).await, [(30, 35), (30, 35)]);
+
+        // 12 rows leave each pane 4 rows under its bufferline, and 1 row of scrolloff
+        state.lock_state::<CrosstermEvents>().await.0.push(Event::Resize(80, 12));
+        state.call(handle_resize).await;
+        assert_eq!(scrolls(&state).await, [(48, 48), (78, 78)]);
+        assert_eq!(state.lock_state::<Chunks>().await.damage().await, Damage::Full);
+    }
+}
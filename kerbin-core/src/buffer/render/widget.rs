@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use crate::{
     ConcealScope, CursorShape, Extmark, ExtmarkKind, OverlayPosition, OverlayWidget, StyledChunk,
-    TextBuffer, VirtTextPos, grapheme_display_width,
+    TextBuffer, UnicodeWidthChar, UnicodeWidthStr, VirtTextPos, grapheme_display_width,
 };
 use ratatui::prelude::*;
 use ropey::{Rope, RopeSlice};
@@ -16,6 +16,21 @@ pub struct TextBufferWidget<'a> {
     tab_display_unit: String,
     tab_style: Style,
     reveal_conceal_on_cursor_line: bool,
+    soft_wrap: bool,
+    whitespace: Option<ShownWhitespace>,
+    cursorline: Option<Style>,
+    cursorcolumn: Option<Style>,
+    fold_style: Style,
+}
+
+/// How `TextBufferWidget` draws whitespace that's normally invisible
+#[derive(Debug, Clone)]
+pub struct ShownWhitespace {
+    /// Replaces each trailing space
+    pub trailing: Option<char>,
+    /// Drawn after the last character of lines ending in a newline, unless empty
+    pub eol: String,
+    pub style: Style,
 }
 
 impl<'a> TextBufferWidget<'a> {
@@ -27,6 +42,11 @@ impl<'a> TextBufferWidget<'a> {
             tab_display_unit: "    ".to_string(),
             tab_style: Style::default(),
             reveal_conceal_on_cursor_line: true,
+            soft_wrap: false,
+            whitespace: None,
+            cursorline: None,
+            cursorcolumn: None,
+            fold_style: Style::default(),
         }
     }
 
@@ -55,6 +75,44 @@ impl<'a> TextBufferWidget<'a> {
         self
     }
 
+    /// Wraps lines wider than the area onto extra rows instead of scrolling sideways.
+    /// The horizontal scroll is ignored while wrapping.
+    pub fn with_soft_wrap(mut self, wrap: bool) -> Self {
+        self.soft_wrap = wrap;
+        self
+    }
+
+    /// Draws trailing whitespace and line ends with glyphs. Tabs are drawn with the tab
+    /// display unit, so the caller swaps that for a glyph too.
+    pub fn with_whitespace(mut self, whitespace: Option<ShownWhitespace>) -> Self {
+        self.whitespace = whitespace;
+        self
+    }
+
+    /// Puts `style` under the whole width of the primary cursor's line, so text
+    /// highlights still draw over it
+    pub fn with_cursorline(mut self, style: Option<Style>) -> Self {
+        self.cursorline = style;
+        self
+    }
+
+    /// Puts `style` under the primary cursor's column on every other row, wherever
+    /// nothing else set a background
+    pub fn with_cursorcolumn(mut self, style: Option<Style>) -> Self {
+        self.cursorcolumn = style;
+        self
+    }
+
+    /// Style of the summary drawn after the first line of a closed fold
+    pub fn with_fold_style(mut self, style: Style) -> Self {
+        self.fold_style = style;
+        self
+    }
+
+    fn h_scroll(&self) -> usize {
+        if self.soft_wrap { 0 } else { self.h_scroll }
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn render_marked_line(
         &self,
@@ -158,7 +216,35 @@ impl<'a> TextBufferWidget<'a> {
             result
         };
 
-        let (seg_list, col_ranges) = build_concealed_segments(&full_line_text, &effective_conceals);
+        // Trailing spaces are swapped one char for one char, so columns still line up
+        let trailing_start = chars[..visible_len.min(total_chars)]
+            .iter()
+            .rposition(|c| !c.is_whitespace())
+            .map_or(0, |i| i + 1);
+        let display_text: String = match self.whitespace.as_ref().and_then(|ws| ws.trailing) {
+            Some(glyph) => chars
+                .iter()
+                .enumerate()
+                .map(|(i, c)| match c {
+                    ' ' if (trailing_start..visible_len).contains(&i) => glyph,
+                    c => *c,
+                })
+                .collect(),
+            None => full_line_text.clone(),
+        };
+
+        if let Some(ws) = &self.whitespace
+            && trailing_start < visible_len
+        {
+            lm.highlights.push(HighlightMark {
+                start: trailing_start,
+                end: visible_len,
+                style: ws.style,
+                priority: i32::MIN,
+            });
+        }
+
+        let (seg_list, col_ranges) = build_concealed_segments(&display_text, &effective_conceals);
 
         let mut segments = apply_highlights(
             seg_list,
@@ -169,14 +255,21 @@ impl<'a> TextBufferWidget<'a> {
             visible_len + eof_extra,
         );
 
-        if !lm.eol_highlights.is_empty() {
+        // An empty line with a cursor already drew a cell for it
+        let eol_glyph = self
+            .whitespace
+            .as_ref()
+            .filter(|ws| !ws.eol.is_empty() && visible_len < line_char_count)
+            .filter(|_| visible_len > 0 || lm.newline_highlights.is_empty());
+
+        if !lm.eol_highlights.is_empty() || eol_glyph.is_some() {
             lm.eol_highlights.sort_by_key(|(_, p)| *p);
-            let mut eol_style = Style::default();
+            let mut eol_style = eol_glyph.map(|ws| ws.style).unwrap_or_default();
             for (s, _) in &lm.eol_highlights {
                 eol_style = eol_style.patch(*s);
             }
             segments.push(StyledSegment {
-                text: " ".to_string(),
+                text: eol_glyph.map_or(" ".to_string(), |ws| ws.eol.clone()),
                 style: eol_style,
             });
         }
@@ -187,9 +280,28 @@ impl<'a> TextBufferWidget<'a> {
             segments.insert_at(display_col, m.chunks);
         }
 
+        // Inline text pushes everything from its column on to the right, so overlays,
+        // cursors and popups placed by buffer column move past it. Returns how many chars
+        // and how many cells of it come before `char_col`.
+        let inline_sizes: Vec<(usize, usize, usize)> = lm
+            .inline_marks
+            .iter()
+            .map(|m| {
+                let chars = m.chunks.iter().map(|c| c.text.chars().count()).sum();
+                let width = m.chunks.iter().map(|c| c.text.width()).sum();
+                (buffer_to_display(m.col, &effective_conceals), chars, width)
+            })
+            .collect();
+        let inline_shift = |char_col: usize| -> (usize, usize) {
+            inline_sizes
+                .iter()
+                .filter(|(col, _, _)| *col <= char_col)
+                .fold((0, 0), |(chars, width), (_, c, w)| (chars + c, width + w))
+        };
+
         for m in &lm.overlay_marks {
             let display_col = buffer_to_display(m.col, &effective_conceals);
-            segments.overlay_at(display_col, m.chunks);
+            segments.overlay_at(display_col + inline_shift(display_col).0, m.chunks);
         }
 
         let cursor_display_cols: Vec<(usize, Style)> = lm
@@ -197,14 +309,16 @@ impl<'a> TextBufferWidget<'a> {
             .iter()
             .map(|cm| {
                 let char_col = buffer_to_display(cm.col, &effective_conceals);
-                let display_col = char_col_to_display_col(&full_line_text, char_col, &self.tab_display_unit);
+                let display_col = char_col_to_display_col(&full_line_text, char_col, &self.tab_display_unit)
+                    + inline_shift(char_col).1;
                 (display_col, cm.style)
             })
             .collect();
 
+        // Wrapped lines are rendered whole, then split into rows
         let mut spans = segments.into_spans(
-            self.h_scroll,
-            width,
+            self.h_scroll(),
+            if self.soft_wrap { usize::MAX } else { width },
             &self.tab_display_unit,
             self.tab_style,
             &cursor_display_cols,
@@ -221,12 +335,13 @@ impl<'a> TextBufferWidget<'a> {
             &mut lm.eol_chunks,
             &mut lm.right_align_chunks,
             width,
+            self.soft_wrap,
         );
 
         let mut popups = Vec::new();
         for pm in lm.popups {
             let display_col = buffer_to_display(pm.col, &effective_conceals);
-            popups.push((display_col, pm.widget, pm.position, pm.priority));
+            popups.push((display_col + inline_shift(display_col).0, pm.widget, pm.position, pm.priority));
         }
 
         LineRenderResult {
@@ -241,6 +356,8 @@ impl<'a> TextBufferWidget<'a> {
 #[derive(Debug, Default)]
 pub struct CursorRenderState {
     pub cursor: Option<(u16, u16, CursorShape)>,
+    /// The line and first display column drawn on each row
+    pub rows: Vec<(usize, usize)>,
 }
 
 struct LineRenderResult {
@@ -756,6 +873,8 @@ impl<'a> LineMarks<'a> {
                         result.popups.push(PopupMark { col, widget: widget.clone(), position: position.clone(), priority });
                     }
                 }
+                // Drawn by the gutter
+                ExtmarkKind::Sign { .. } => {}
             }
         }
 
@@ -927,10 +1046,25 @@ fn append_eol_and_right_align(
     eol_chunks: &mut Vec<ChunkMark<'_>>,
     right_align_chunks: &mut Vec<ChunkMark<'_>>,
     width: usize,
+    soft_wrap: bool,
 ) {
+    // End-of-line text only fills what is left of the line's last row, so it never wraps
+    // onto a row of its own and moves the text below
+    let used: usize = spans.iter().map(|s| s.content.width()).sum();
+    let mut room = match width {
+        0 => 0,
+        w if soft_wrap && used > 0 && used.is_multiple_of(w) => 0,
+        w if soft_wrap => w - used % w,
+        w => w.saturating_sub(used),
+    };
+
     eol_chunks.sort_by_key(|cm| cm.priority);
     for cm in eol_chunks.iter() {
-        spans.push(Span::styled(cm.chunk.text.clone(), cm.chunk.style));
+        let text = fit_to_width(&cm.chunk.text, room);
+        room -= text.width();
+        if !text.is_empty() {
+            spans.push(Span::styled(text, cm.chunk.style));
+        }
     }
 
     if !right_align_chunks.is_empty() {
@@ -947,6 +1081,75 @@ fn append_eol_and_right_align(
     }
 }
 
+/// Cuts `text` down to `width` columns, ending it with `…` when anything was dropped
+fn fit_to_width(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let mut fitted = String::new();
+    let mut used = 0;
+    for ch in text.chars() {
+        let ch_width = ch.width().unwrap_or(0);
+        if used + ch_width > width - 1 {
+            break;
+        }
+        fitted.push(ch);
+        used += ch_width;
+    }
+    fitted.push('…');
+    fitted
+}
+
+/// Splits a rendered line into rows at most `width` columns wide for soft wrap.
+/// Returns the rows and the display column each one starts at.
+fn wrap_line(line: Line<'static>, width: usize) -> (Vec<Line<'static>>, Vec<usize>) {
+    let mut rows = vec![];
+    let mut starts = vec![0];
+    let mut row: Vec<Span<'static>> = vec![];
+    let mut col = 0usize;
+    let mut row_col = 0usize;
+
+    for span in line.spans {
+        let mut text = String::new();
+        for g in span.content.graphemes(true) {
+            let g_w = grapheme_display_width(g);
+            if row_col > 0 && row_col + g_w > width {
+                if !text.is_empty() {
+                    row.push(Span::styled(std::mem::take(&mut text), span.style));
+                }
+                rows.push(Line::from(std::mem::take(&mut row)));
+                starts.push(col);
+                row_col = 0;
+            }
+            text.push_str(g);
+            col += g_w;
+            row_col += g_w;
+        }
+        if !text.is_empty() {
+            row.push(Span::styled(text, span.style));
+        }
+    }
+    rows.push(Line::from(row));
+
+    (rows, starts)
+}
+
+/// Row and column a display column lands on in a line wrapped at `starts`.
+/// Columns past the end of a full last row land on the row after it.
+fn wrapped_position(starts: &[usize], display_col: usize, width: usize) -> (usize, usize) {
+    let row = starts.partition_point(|s| *s <= display_col).saturating_sub(1);
+    let x = display_col - starts[row];
+    if x >= width && row + 1 == starts.len() {
+        (row + x / width, x % width)
+    } else {
+        (row, x.min(width.saturating_sub(1)))
+    }
+}
+
 impl<'a> StatefulWidget for TextBufferWidget<'a> {
     type State = CursorRenderState;
 
@@ -963,13 +1166,19 @@ impl<'a> StatefulWidget for TextBufferWidget<'a> {
         let mut pending_overlays = vec![];
 
         let total_lines = rope.len_lines();
+        // Scrolling animates through lines a fold hides, which draw as the fold
+        let line_scroll = self.buf.folds.visible_line(self.line_scroll);
 
-        let viewport_start_byte = if self.line_scroll < total_lines {
-            rope.char_to_byte(rope.line_to_char(self.line_scroll))
+        let viewport_start_byte = if line_scroll < total_lines {
+            rope.char_to_byte(rope.line_to_char(line_scroll))
         } else {
             rope.len_bytes()
         };
-        let viewport_end_line = (self.line_scroll + area.height as usize).min(total_lines);
+        let viewport_end_line = self
+            .buf
+            .folds
+            .lines_down(line_scroll, area.height as usize)
+            .min(total_lines);
         let viewport_end_byte = if viewport_end_line < total_lines {
             rope.char_to_byte(rope.line_to_char(viewport_end_line))
         } else {
@@ -981,12 +1190,27 @@ impl<'a> StatefulWidget for TextBufferWidget<'a> {
             .renderer
             .query_extmarks(viewport_start_byte..viewport_end_byte + 1);
 
-        while lines.len() < area.height as usize {
-            let line_idx = self.line_scroll + lines.len();
+        let cursor_line = rope.byte_to_line(
+            self.buf
+                .primary_cursor()
+                .get_cursor_byte()
+                .min(rope.len_bytes()),
+        );
+
+        let mut next_line = line_scroll;
+        loop {
+            let line_idx = next_line;
+            if lines.len() >= area.height as usize {
+                break;
+            }
             let Some(rope_line) = rope.get_line(line_idx) else {
                 break;
             };
 
+            // A closed fold shows its first line, and the lines it hides are skipped
+            let fold_end = self.buf.folds.folded_end(line_idx);
+            next_line = fold_end.unwrap_or(line_idx) + 1;
+
             let line_start_byte = rope.char_to_byte(rope.line_to_char(line_idx));
             let line_start_char = rope.byte_to_char(line_start_byte);
             let line_char_count = rope_line.len_chars();
@@ -1027,58 +1251,119 @@ impl<'a> StatefulWidget for TextBufferWidget<'a> {
                 .copied()
                 .collect();
 
-            if marks.is_empty() {
+            let mut result = if marks.is_empty() && self.whitespace.is_none() {
                 let line_str = rope_line.to_string();
                 let spans = render_plain_line(
                     &line_str,
-                    self.h_scroll,
-                    area.width as usize,
+                    self.h_scroll(),
+                    if self.soft_wrap { usize::MAX } else { area.width as usize },
                     &self.tab_display_unit,
                     self.tab_style,
                 );
-                lines.push(Line::from(spans));
-                continue;
+                LineRenderResult {
+                    line: Line::from(spans),
+                    cursors: vec![],
+                    popups: vec![],
+                }
+            } else {
+                self.render_marked_line(
+                    rope,
+                    &marks,
+                    rope_line,
+                    line_start_char,
+                    line_end_char,
+                    line_char_count,
+                    visible_len,
+                    extra_eof_space,
+                    area.width as usize,
+                    self.reveal_conceal_on_cursor_line,
+                )
+            };
+
+            if let Some(end) = fold_end {
+                let summary = format!(" ⋯ {} lines", end - line_idx);
+                result.line.push_span(Span::styled(summary, self.fold_style));
             }
 
-            let result = self.render_marked_line(
-                rope,
-                &marks,
-                rope_line,
-                line_start_char,
-                line_end_char,
-                line_char_count,
-                visible_len,
-                extra_eof_space,
-                area.width as usize,
-                self.reveal_conceal_on_cursor_line,
-            );
-
-            let current_line_index = lines.len();
+            let width = area.width as usize;
+            let (mut rows, starts) = if self.soft_wrap {
+                wrap_line(result.line, width)
+            } else {
+                (vec![result.line], vec![self.h_scroll])
+            };
+
+            let first_row = lines.len();
             for (display_col, shape) in &result.cursors {
-                if *display_col >= self.h_scroll
-                    && *display_col < self.h_scroll + area.width as usize
-                {
-                    let screen_x = area.x + (display_col - self.h_scroll) as u16;
-                    let screen_y = area.y + current_line_index as u16;
+                let (row, x) = if self.soft_wrap {
+                    wrapped_position(&starts, *display_col, width)
+                } else if *display_col >= self.h_scroll && *display_col < self.h_scroll + width {
+                    (0, display_col - self.h_scroll)
+                } else {
+                    continue;
+                };
+
+                // A cursor just past a full last row gets a row of its own
+                while rows.len() <= row {
+                    rows.push(Line::default());
+                }
+
+                if first_row + row < area.height as usize {
+                    let screen_x = area.x + x as u16;
+                    let screen_y = area.y + (first_row + row) as u16;
                     state.cursor = Some((screen_x, screen_y, *shape));
                 }
             }
 
             for (anchor_display_col, content, position, z_index) in result.popups {
-                let screen_x = if anchor_display_col >= self.h_scroll {
-                    area.x + (anchor_display_col - self.h_scroll) as u16
+                let (row, x) = if self.soft_wrap {
+                    wrapped_position(&starts, anchor_display_col, width)
                 } else {
-                    area.x
+                    (0, anchor_display_col.saturating_sub(self.h_scroll))
                 };
-                let screen_y = area.y + current_line_index as u16;
+                if first_row + row >= area.height as usize {
+                    continue;
+                }
+
+                let screen_x = area.x + x as u16;
+                let screen_y = area.y + (first_row + row) as u16;
                 pending_overlays.push((screen_x, screen_y, content, position, z_index));
             }
 
-            lines.push(result.line);
+            for (row, mut line) in rows.into_iter().enumerate() {
+                if lines.len() >= area.height as usize {
+                    break;
+                }
+                if let Some(style) = self.cursorline
+                    && line_idx == cursor_line
+                {
+                    line.style = style.patch(line.style);
+                }
+                let start = starts.get(row).copied().unwrap_or_else(|| starts[starts.len() - 1] + width);
+                state.rows.push((line_idx, start));
+                lines.push(line);
+            }
         }
 
+        // Backgrounds from before the text, to tell where the text set its own
+        let base_bgs: Vec<_> = match self.cursorcolumn {
+            Some(_) => area.positions().map(|pos| buf[pos].bg).collect(),
+            None => vec![],
+        };
+
         Text::from(lines).render(area, buf);
 
+        if let Some(style) = self.cursorcolumn
+            && let Some((x, _, _)) = state.cursor
+        {
+            for (row, (line_idx, _)) in state.rows.iter().enumerate() {
+                let pos = Position::new(x, area.y + row as u16);
+                let base = base_bgs[row * area.width as usize + (x - area.x) as usize];
+                if *line_idx != cursor_line && buf[pos].bg == base {
+                    buf[pos].set_style(style);
+                }
+            }
+        }
+
         pending_overlays.sort_by_key(|(_, _, _, _, z)| *z);
         for (anchor_x, anchor_y, widget, position, _) in pending_overlays {
             let (w, h) = widget.dimensions();
@@ -1117,3 +1402,178 @@ impl<'a> StatefulWidget for TextBufferWidget<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExtmarkBuilder, ExtmarkKind};
+
+    fn render_wrapped(text: &str, cursor_byte: usize, area: Rect) -> (Buffer, CursorRenderState) {
+        let mut text_buf = TextBuffer::scratch();
+        text_buf.rope = Rope::from_str(text);
+        text_buf.add_extmark(ExtmarkBuilder::new("inner::cursor", cursor_byte).with_kind(
+            ExtmarkKind::Cursor {
+                style: Style::default(),
+                shape: CursorShape::Block,
+            },
+        ));
+
+        let mut screen = Buffer::empty(area);
+        let mut state = CursorRenderState::default();
+        TextBufferWidget::new(&text_buf)
+            .with_soft_wrap(true)
+            .render(area, &mut screen, &mut state);
+        (screen, state)
+    }
+
+    #[test]
+    fn places_cursor_on_wrapped_row() {
+        let (screen, state) = render_wrapped("abcdefghijklmnop\nxy", 12, Rect::new(0, 0, 5, 5));
+
+        assert_eq!(state.cursor, Some((2, 2, CursorShape::Block)));
+        assert_eq!(state.rows, [(0, 0), (0, 5), (0, 10), (0, 15), (1, 0)]);
+        assert_eq!(screen.cell((0, 1)).unwrap().symbol(), "f");
+        assert_eq!(screen.cell((0, 4)).unwrap().symbol(), "x");
+    }
+
+    #[test]
+    fn draws_trailing_whitespace_and_line_ends() {
+        let mut text_buf = TextBuffer::scratch();
+        text_buf.rope = Rope::from_str("a b  \ncd");
+
+        let area = Rect::new(0, 0, 8, 2);
+        let mut screen = Buffer::empty(area);
+        TextBufferWidget::new(&text_buf)
+            .with_whitespace(Some(ShownWhitespace {
+                trailing: Some('·'),
+                eol: "$".to_string(),
+                style: Style::default(),
+            }))
+            .render(area, &mut screen, &mut CursorRenderState::default());
+
+        let row = |y| (0..8).map(|x| screen.cell((x, y)).unwrap().symbol().to_string()).collect::<String>();
+        assert_eq!(row(0), "a b··$  ");
+        assert_eq!(row(1), "cd      ");
+    }
+
+    #[test]
+    fn eol_text_is_cut_to_the_last_row() {
+        let mut text_buf = TextBuffer::scratch();
+        text_buf.rope = Rope::from_str("abcdefg\nxy");
+        text_buf.add_extmark(ExtmarkBuilder::new("test::eol", 0).with_kind(
+            ExtmarkKind::VirtualText {
+                chunks: vec![StyledChunk {
+                    text: " long message".to_string(),
+                    style: Style::default(),
+                }],
+                pos: VirtTextPos::Eol,
+            },
+        ));
+
+        let area = Rect::new(0, 0, 5, 3);
+        let mut screen = Buffer::empty(area);
+        let mut state = CursorRenderState::default();
+        TextBufferWidget::new(&text_buf)
+            .with_soft_wrap(true)
+            .render(area, &mut screen, &mut state);
+
+        let row = |y| (0..5).map(|x| screen.cell((x, y)).unwrap().symbol().to_string()).collect::<String>();
+        assert_eq!(row(0), "abcde");
+        assert_eq!(row(1), "fg l…");
+        assert_eq!(row(2), "xy   ");
+        assert_eq!(fit_to_width("日本語", 5), "日本…");
+    }
+
+    #[test]
+    fn cursorline_and_cursorcolumn_follow_the_primary_cursor() {
+        let mut text_buf = TextBuffer::scratch();
+        text_buf.rope = Rope::from_str("abc\nabcdef\nab");
+        text_buf.primary_cursor_mut().move_to(6);
+        text_buf.add_extmark(ExtmarkBuilder::new("inner::cursor", 6).with_kind(
+            ExtmarkKind::Cursor {
+                style: Style::default(),
+                shape: CursorShape::Block,
+            },
+        ));
+        let highlight = Style::default().bg(Color::Red);
+        text_buf.add_extmark(
+            ExtmarkBuilder::new_range("test::hl", 1..3)
+                .with_kind(ExtmarkKind::Highlight { style: highlight }),
+        );
+
+        let line_style = Style::default().bg(Color::Blue);
+        let column_style = Style::default().bg(Color::Green);
+        let area = Rect::new(0, 0, 8, 4);
+        let mut screen = Buffer::empty(area);
+        TextBufferWidget::new(&text_buf)
+            .with_cursorline(Some(line_style))
+            .with_cursorcolumn(Some(column_style))
+            .render(area, &mut screen, &mut CursorRenderState::default());
+
+        let bg = |x, y| screen.cell((x, y)).unwrap().bg;
+        // The whole cursor line, past the end of its text
+        assert!((0..8).all(|x| bg(x, 1) == Color::Blue));
+        // The column on other lines, under highlights and past short lines
+        assert_eq!(bg(2, 0), Color::Red);
+        assert_eq!(bg(2, 2), Color::Green);
+        assert_eq!(bg(1, 2), Color::Reset);
+        // Rows past the end of the buffer are left alone
+        assert_eq!(bg(2, 3), Color::Reset);
+    }
+
+    #[test]
+    fn inline_text_pushes_the_cursor_along() {
+        let mut text_buf = TextBuffer::scratch();
+        text_buf.rope = Rope::from_str("let x = 1;");
+        text_buf.add_extmark(ExtmarkBuilder::new("test::inline", 5).with_kind(
+            ExtmarkKind::VirtualText {
+                chunks: vec![StyledChunk {
+                    text: ": i32".to_string(),
+                    style: Style::default(),
+                }],
+                pos: VirtTextPos::Inline,
+            },
+        ));
+        text_buf.add_extmark(ExtmarkBuilder::new("inner::cursor", 8).with_kind(
+            ExtmarkKind::Cursor {
+                style: Style::default(),
+                shape: CursorShape::Block,
+            },
+        ));
+
+        let area = Rect::new(0, 0, 20, 1);
+        let mut screen = Buffer::empty(area);
+        let mut state = CursorRenderState::default();
+        TextBufferWidget::new(&text_buf).render(area, &mut screen, &mut state);
+
+        let row = (0..20).map(|x| screen.cell((x, 0)).unwrap().symbol().to_string()).collect::<String>();
+        assert_eq!(row, "let x: i32 = 1;     ");
+        assert_eq!(state.cursor, Some((13, 0, CursorShape::Block)));
+    }
+
+    #[test]
+    fn cursor_past_a_full_row_gets_its_own() {
+        let (_, state) = render_wrapped("abcde", 5, Rect::new(0, 0, 5, 3));
+
+        assert_eq!(state.cursor, Some((0, 1, CursorShape::Block)));
+        assert_eq!(state.rows, [(0, 0), (0, 5)]);
+    }
+
+    #[test]
+    fn closed_fold_draws_as_its_first_line() {
+        let mut text_buf = TextBuffer::scratch();
+        text_buf.rope = Rope::from_str("fn a() {\n    1\n    2\n}\nx");
+        text_buf.folds.set_available(vec![0..=3]);
+        text_buf.folds.close_at(0);
+
+        let area = Rect::new(0, 0, 20, 3);
+        let mut screen = Buffer::empty(area);
+        let mut state = CursorRenderState::default();
+        TextBufferWidget::new(&text_buf).render(area, &mut screen, &mut state);
+
+        let row = |y| (0..20).map(|x| screen.cell((x, y)).unwrap().symbol().to_string()).collect::<String>();
+        assert_eq!(row(0), "fn a() { ⋯ 3 lines  ");
+        assert_eq!(row(1), "x                   ");
+        assert_eq!(state.rows, [(0, 0), (4, 0)]);
+    }
+}
@@ -5,24 +5,10 @@ use ratatui::style::{Color, Style};
 
 const CURSOR_PRIORITY: i32 = 1000;
 
-pub async fn render_cursors_and_selections(
-    bufs: ResMut<Buffers>,
-    modes: Res<ModeStack>,
-    theme: Res<Theme>,
-) {
-    get!(mut bufs, modes, theme);
-
-    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
-        return;
-    };
-
-    buf.renderer
-        .set_namespace_priority("inner::cursor", CURSOR_PRIORITY + 1);
-    buf.renderer
-        .set_namespace_priority("inner::selection", CURSOR_PRIORITY);
-    buf.renderer.clear_extmark_ns("inner::cursor");
-    buf.renderer.clear_extmark_ns("inner::selection");
-
+/// Styles of the primary and secondary cursors. The primary takes the style of the
+/// deepest mode with a `ui.cursor.<modes>` entry, then `ui.cursor.primary`, then
+/// `ui.cursor`. Secondaries take `ui.cursor.secondary`, or the primary's style without one
+fn cursor_styles(theme: &Theme, modes: &ModeStack) -> (Style, Style) {
     let mut cursor_parts = modes
         .0
         .iter()
@@ -46,9 +32,33 @@ pub async fn render_cursors_and_selections(
         cursor_parts.pop_front();
     }
 
-    let cursor_style = cursor_style_theme
+    let primary = cursor_style_theme
+        .or_else(|| theme.get("ui.cursor.primary"))
         .or_else(|| theme.get("ui.cursor"))
         .unwrap_or_default();
+    let secondary = theme.get("ui.cursor.secondary").unwrap_or(primary);
+    (primary, secondary)
+}
+
+pub async fn render_cursors_and_selections(
+    bufs: ResMut<Buffers>,
+    modes: Res<ModeStack>,
+    theme: Res<Theme>,
+) {
+    get!(mut bufs, modes, theme);
+
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+        return;
+    };
+
+    buf.renderer
+        .set_namespace_priority("inner::cursor", CURSOR_PRIORITY + 1);
+    buf.renderer
+        .set_namespace_priority("inner::selection", CURSOR_PRIORITY);
+    buf.renderer.clear_extmark_ns("inner::cursor");
+    buf.renderer.clear_extmark_ns("inner::selection");
+
+    let (primary_style, secondary_style) = cursor_styles(&theme, &modes);
 
     let sel_style = theme
         .get("ui.selection")
@@ -67,19 +77,20 @@ pub async fn render_cursors_and_selections(
 
             buf.add_extmark(ExtmarkBuilder::new("inner::cursor", caret_byte).with_kind(
                 ExtmarkKind::Cursor {
-                    style: cursor_style,
+                    style: primary_style,
                     shape,
                 },
             ));
         } else {
+            // The terminal has a single cursor, so secondaries are drawn as a styled cell
             buf.add_extmark(ExtmarkBuilder::new("inner::cursor", caret_byte).with_kind(
                 ExtmarkKind::Highlight {
-                    style: cursor_style,
+                    style: secondary_style,
                 },
             ));
         }
 
-        if (cursor.at_start || !is_primary) && cursor.sel.start() != cursor.sel.end() {
+        if (cursor.at_start() || !is_primary) && cursor.sel().start() != cursor.sel().end() {
             buf.add_extmark(
                 ExtmarkBuilder::new_range(
                     "inner::selection",
@@ -167,11 +178,12 @@ pub async fn update_bufferline_scroll(
         return;
     }
 
-    let tab_widths: Vec<usize> = displayed_global_indices
-        .iter()
-        .filter_map(|&gi| buffers.buffer_paths.get(gi))
-        .map(|p| p.width() + 6)
-        .collect();
+    let mut tab_widths = Vec::with_capacity(displayed_global_indices.len());
+    for (display_i, &gi) in displayed_global_indices.iter().enumerate() {
+        if let Some(label) = buffers.tab_label(gi, display_i == active_display_idx).await {
+            tab_widths.push(label.chars().count());
+        }
+    }
 
     if tab_widths.is_empty() || active_display_idx >= tab_widths.len() {
         return;
@@ -231,6 +243,27 @@ pub async fn post_update_buffer(buffers: ResMut<Buffers>) {
     buf.post_update();
 }
 
+/// Warns about the first edit or save refused in each read-only buffer
+pub async fn warn_readonly_edits(buffers: Res<Buffers>, log: Res<LogSender>) {
+    get!(buffers, log);
+
+    for buf in &buffers.buffers {
+        let mut buf = buf.write().await;
+        let Some(text_buf) = buf.downcast_mut::<TextBuffer>() else { continue };
+        if !text_buf.flags.remove(READONLY_REFUSED_FLAG)
+            || !text_buf.flags.insert(READONLY_WARNED_FLAG)
+        {
+            continue;
+        }
+
+        let reason = if text_buf.big_file { "is a big file" } else { "is read-only" };
+        log.medium(
+            "buffer::readonly",
+            format!("{} {reason}, changes are refused", text_buf.path),
+        );
+    }
+}
+
 pub async fn update_tab_width_template(buffers: Res<Buffers>) {
     get!(buffers);
     if buffers.buffers.is_empty() {
@@ -276,10 +309,12 @@ pub async fn cleanup_buffers(buffers: ResMut<Buffers>) {
     buffer.update_cleanup();
 }
 
+/// Runs the mouse bindings for events routed to a buffer chunk.
+/// Clicking an unfocused pane focuses it, and `%mouse_line` and `%mouse_col` are set
+/// to the position under the pointer in that pane's buffer.
 #[allow(clippy::too_many_arguments)]
 pub async fn handle_mouse_events(
-    events: Res<CrosstermEvents>,
-    chunks: Res<Chunks>,
+    events: Res<ChunkMouseEvents>,
     buffers: Res<Buffers>,
     mouse_bindings: Res<MouseBindings>,
     command_registry: Res<CommandRegistry>,
@@ -289,92 +324,48 @@ pub async fn handle_mouse_events(
     core_config: Res<CoreConfig>,
     split: Res<SplitState>,
 ) {
-    get!(
-        events,
-        chunks,
-        buffers,
-        mouse_bindings,
-        modes,
-        core_config,
-        split
-    );
-
-    if events.0.is_empty() {
-        return;
-    }
+    get!(events, buffers, mouse_bindings, modes, core_config, split);
 
-    for event in &events.0 {
-        let crossterm::event::Event::Mouse(mouse_ev) = event else {
+    for event in events.0.iter().filter(|e| e.is::<BufferChunk>()) {
+        let Some(trigger) = mouse_trigger(event.kind) else {
             continue;
         };
 
-        let Some(trigger) = mouse_trigger(mouse_ev.kind) else {
+        let leaves = split.leaves();
+        let Some(pane) = event.index.and_then(|i| leaves.get(i)) else {
             continue;
         };
 
-        let col = mouse_ev.column;
-        let row = mouse_ev.row;
-
-        let mut area_opt = chunks.rect_for_chunk(&BufferChunk::static_name());
-        let mut clicked_pane_i: Option<usize> = None;
-        if matches!(trigger, MouseTrigger::LeftDown) && split.pane_count() > 1 {
-            let n = chunks.indexed_count::<BufferChunk>();
-            'pane_check: for pane_i in 0..n {
-                if let Some(pane_rect) = chunks.rect_for_indexed_chunk::<BufferChunk>(pane_i)
-                    && col >= pane_rect.x
-                    && col < pane_rect.x + pane_rect.width
-                    && row >= pane_rect.y
-                    && row < pane_rect.y + pane_rect.height
-                {
-                    area_opt = Some(pane_rect);
-                    clicked_pane_i = Some(pane_i);
-                    let is_focused =
-                        split.leaves().get(pane_i).map(|p| p.id) == Some(split.focused_id);
-                    if !is_focused {
-                        let focus_cmd: Box<dyn Command<State>> =
-                            Box::new(SplitCommand::FocusPane(pane_i));
-                        let _ = command_sender.get().await.send(focus_cmd);
-                    }
-                    break 'pane_check;
-                }
-            }
+        if matches!(trigger, MouseTrigger::LeftDown) && pane.id != split.focused_id {
+            let focus_cmd: Box<dyn Command<State>> =
+                Box::new(SplitCommand::FocusPane(event.index.unwrap_or_default()));
+            let _ = command_sender.get().await.send(focus_cmd);
         }
 
-        let clicked_buf_arc = if let Some(pane_i) = clicked_pane_i {
-            split.leaves().get(pane_i).and_then(|pane| {
-                let buf_idx = if !split.unique_buffers {
-                    pane.selected_local
-                } else {
-                    *pane.buffer_indices.get(pane.selected_local)?
-                };
-                buffers.buffers.get(buf_idx).cloned()
-            })
+        let buf_idx = if !split.unique_buffers {
+            Some(pane.selected_local)
         } else {
-            buffers.buffers.get(buffers.selected_buffer).cloned()
+            pane.buffer_indices.get(pane.selected_local).copied()
         };
 
-        if let Some(area) = area_opt
-            && let Some(buf_arc) = clicked_buf_arc
+        if let Some(buf_arc) = buf_idx.and_then(|i| buffers.buffers.get(i))
             && let Ok(buf_guard) = buf_arc.clone().try_read_owned()
             && let Some(buf) = buf_guard.as_any().downcast_ref::<TextBuffer>()
         {
-            let line_idx = (row.saturating_sub(area.y) as usize)
-                .saturating_add(buf.renderer.byte_scroll)
-                .min(buf.len_lines().saturating_sub(1));
-
-            let line_start_byte = buf.line_to_byte_clamped(line_idx);
-            let line_end_byte = buf
-                .line_to_byte(line_idx + 1)
-                .unwrap_or(buf.rope.len_bytes());
-            let target_display_col =
-                (col.saturating_sub(area.x) as usize).saturating_add(buf.renderer.h_scroll);
+            // Rows only match lines one to one until lines wrap
+            let (line_idx, row_start) = match buf.renderer.screen_rows.get(event.row as usize) {
+                Some(row) => *row,
+                None => (
+                    (event.row as usize).saturating_add(buf.renderer.visual_scroll),
+                    buf.renderer.h_scroll,
+                ),
+            };
+            let line_idx = line_idx.min(buf.len_lines().saturating_sub(1));
 
-            let line_text = buf
-                .slice_to_string(line_start_byte, line_end_byte)
-                .unwrap_or_default();
+            let target_display_col = (event.column as usize).saturating_add(row_start);
 
             let tab_w = core_config.tab_display_unit.chars().count();
-            let byte_offset = display_col_to_byte_offset(&line_text, target_display_col, tab_w);
+            let byte_offset = buf.rope.byte_of_visual_col(line_idx, target_display_col, tab_w);
 
             let mut engine = resolver_engine_mut().await;
             engine.set_template("mouse_line", line_idx.to_string());
@@ -407,3 +398,29 @@ pub async fn handle_mouse_events(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secondary_cursors_have_their_own_style() {
+        let mut theme = Theme::default();
+        theme.register("ui.cursor".into(), Style::default().bg(Color::Gray));
+        let normal = ModeStack(vec!['n']);
+        let insert = ModeStack(vec!['n', 'i']);
+
+        let gray = Style::default().bg(Color::Gray);
+        assert_eq!(cursor_styles(&theme, &normal), (gray, gray));
+
+        let blue = Style::default().bg(Color::Blue);
+        let green = Style::default().bg(Color::Green);
+        let red = Style::default().bg(Color::Red);
+        theme.register("ui.cursor.primary".into(), blue);
+        theme.register("ui.cursor.secondary".into(), red);
+        theme.register("ui.cursor.i".into(), green);
+        assert_eq!(cursor_styles(&theme, &normal), (blue, red));
+        // The mode's style is for the primary only
+        assert_eq!(cursor_styles(&theme, &insert), (green, red));
+    }
+}
@@ -0,0 +1,283 @@
+use std::ops::Range;
+
+use crate::{TextBuffer, WordChars};
+
+/// What a text object selects around the cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObjectKind {
+    /// A run of word characters, punctuation or whitespace
+    Word,
+    /// A run of non-whitespace characters
+    BigWord,
+    /// Text between two of the same quote character on the cursor's line
+    Quote(char),
+    /// Text between a balanced open and close bracket
+    Pair(char, char),
+}
+
+impl TextObjectKind {
+    /// Maps the key typed after `i`/`a` to a text object, Vim style
+    pub fn from_char(ch: char) -> Option<Self> {
+        Some(match ch {
+            'w' => Self::Word,
+            'W' => Self::BigWord,
+            '"' | '\'' | '`' => Self::Quote(ch),
+            '(' | ')' | 'b' => Self::Pair('(', ')'),
+            '[' | ']' => Self::Pair('[', ']'),
+            '{' | '}' | 'B' => Self::Pair('{', '}'),
+            '<' | '>' => Self::Pair('<', '>'),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum CharClass {
+    Word,
+    Punctuation,
+    Whitespace,
+}
+
+fn char_class(ch: char, big: bool, word_chars: &WordChars) -> CharClass {
+    if ch.is_whitespace() {
+        CharClass::Whitespace
+    } else if big || word_chars.contains(ch) {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+impl TextBuffer {
+    /// Resolves a text object around `cursor_byte` into a byte range (end exclusive).
+    /// `around` includes the delimiters of quotes and brackets, and the trailing (or
+    /// failing that, leading) whitespace of words. The range is empty for an empty
+    /// pair like `()`, and `None` when there is no object at the cursor.
+    ///
+    /// Quotes pair up left to right within the line, skipping ones escaped with a backslash.
+    /// Brackets are matched across lines, and a cursor on either bracket selects that pair.
+    pub fn text_object(
+        &self,
+        kind: TextObjectKind,
+        around: bool,
+        cursor_byte: usize,
+    ) -> Option<Range<usize>> {
+        let len_chars = self.rope.len_chars();
+        if len_chars == 0 {
+            return None;
+        }
+        let cursor = self
+            .rope
+            .byte_to_char(cursor_byte.min(self.rope.len_bytes()))
+            .min(len_chars - 1);
+
+        let chars = match kind {
+            TextObjectKind::Word => self.word_object(cursor, around, false),
+            TextObjectKind::BigWord => self.word_object(cursor, around, true),
+            TextObjectKind::Quote(quote) => self.quote_object(cursor, around, quote),
+            TextObjectKind::Pair(open, close) => self.pair_object(cursor, around, open, close),
+        }?;
+
+        Some(self.rope.char_to_byte(chars.start)..self.rope.char_to_byte(chars.end))
+    }
+
+    /// Char range of the run of same-class characters at `cursor`, kept on its line
+    fn word_object(&self, cursor: usize, around: bool, big: bool) -> Option<Range<usize>> {
+        let ch = |i: usize| self.rope.char(i);
+        if ch(cursor) == '\n' {
+            return None;
+        }
+
+        let line = self.rope.char_to_line(cursor);
+        let line_start = self.rope.line_to_char(line);
+        let line_end = line_start + self.rope.line(line).len_chars();
+        let in_line = |i: usize| i < line_end && ch(i) != '\n';
+        let class_of = |i: usize| char_class(ch(i), big, &self.word_chars);
+
+        let run = |start: usize| {
+            let class = class_of(start);
+            let mut end = start;
+            while in_line(end + 1) && class_of(end + 1) == class {
+                end += 1;
+            }
+            end + 1
+        };
+
+        let class = class_of(cursor);
+        let mut start = cursor;
+        while start > line_start && class_of(start - 1) == class {
+            start -= 1;
+        }
+        let end = run(cursor);
+
+        if !around {
+            return Some(start..end);
+        }
+
+        let is_space = |i: usize| class_of(i) == CharClass::Whitespace;
+        if class == CharClass::Whitespace {
+            // Whitespace takes the word after it along
+            return Some(start..if in_line(end) { run(end) } else { end });
+        }
+
+        if in_line(end) && is_space(end) {
+            return Some(start..run(end));
+        }
+
+        let mut lead = start;
+        while lead > line_start && is_space(lead - 1) {
+            lead -= 1;
+        }
+        Some(lead..end)
+    }
+
+    /// Char range inside the quotes around (or after) `cursor` on its line
+    fn quote_object(&self, cursor: usize, around: bool, quote: char) -> Option<Range<usize>> {
+        let line = self.rope.char_to_line(cursor);
+        let line_start = self.rope.line_to_char(line);
+
+        let mut quotes = vec![];
+        let mut escaped = false;
+        for (i, c) in self.rope.line(line).chars().enumerate() {
+            if c == quote && !escaped {
+                quotes.push(line_start + i);
+            }
+            escaped = c == '\\' && !escaped;
+        }
+
+        // The pair containing the cursor, or else the next one on the line
+        let (open, close) = quotes
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|(_, close)| *close >= cursor)?;
+
+        Some(if around { open..close + 1 } else { open + 1..close })
+    }
+
+    /// Char range inside the innermost balanced pair enclosing `cursor`
+    fn pair_object(
+        &self,
+        cursor: usize,
+        around: bool,
+        open: char,
+        close: char,
+    ) -> Option<Range<usize>> {
+        let ch = |i: usize| self.rope.char(i);
+
+        let open_idx = if ch(cursor) == open {
+            cursor
+        } else {
+            // Also finds the partner of a close bracket under the cursor
+            let mut depth = 0usize;
+            let mut i = cursor;
+            loop {
+                i = i.checked_sub(1)?;
+                match ch(i) {
+                    c if c == close => depth += 1,
+                    c if c == open && depth == 0 => break i,
+                    c if c == open => depth -= 1,
+                    _ => {}
+                }
+            }
+        };
+
+        let mut depth = 0usize;
+        let close_idx = (open_idx + 1..self.rope.len_chars()).find(|&i| match ch(i) {
+            c if c == open => {
+                depth += 1;
+                false
+            }
+            c if c == close && depth == 0 => true,
+            c if c == close => {
+                depth -= 1;
+                false
+            }
+            _ => false,
+        })?;
+
+        Some(if around {
+            open_idx..close_idx + 1
+        } else {
+            open_idx + 1..close_idx
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(text: &str, kind: TextObjectKind, around: bool, at: char) -> Option<String> {
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, text);
+        let cursor = text.find(at).unwrap();
+        buf.text_object(kind, around, cursor).map(|r| text[r].to_string())
+    }
+
+    #[test]
+    fn change_inside_nested_parens() {
+        let parens = TextObjectKind::Pair('(', ')');
+        let text = "f(a, (b c), d)";
+
+        assert_eq!(object(text, parens, false, 'b').as_deref(), Some("b c"));
+        assert_eq!(object(text, parens, false, 'a').as_deref(), Some("a, (b c), d"));
+        assert_eq!(object(text, parens, true, 'b').as_deref(), Some("(b c)"));
+
+        // On a bracket, that bracket's own pair is used
+        assert_eq!(object(text, parens, false, ')').as_deref(), Some("b c"));
+        assert_eq!(object("()", parens, false, '(').as_deref(), Some(""));
+        assert_eq!(object("a (b", parens, false, 'b'), None);
+    }
+
+    #[test]
+    fn delete_inside_quotes() {
+        let quotes = TextObjectKind::Quote('"');
+        let text = "say \"hello\" and \"bye\"";
+
+        assert_eq!(object(text, quotes, false, 'l').as_deref(), Some("hello"));
+        assert_eq!(object(text, quotes, true, 'l').as_deref(), Some("\"hello\""));
+        // Between pairs, the next pair on the line is used
+        assert_eq!(object(text, quotes, false, 'n').as_deref(), Some("bye"));
+        assert_eq!(object("no quotes", quotes, false, 'q'), None);
+    }
+
+    #[test]
+    fn escaped_quotes_are_skipped() {
+        let quotes = TextObjectKind::Quote('"');
+        let text = r#"x = "say \"hi\" now";"#;
+
+        assert_eq!(object(text, quotes, false, 'h').as_deref(), Some(r#"say \"hi\" now"#));
+        assert_eq!(object(text, quotes, true, 'n').as_deref(), Some(r#""say \"hi\" now""#));
+
+        // An escaped backslash doesn't escape the quote after it
+        let text = r#""a\\" "b""#;
+        assert_eq!(object(text, quotes, false, 'a').as_deref(), Some(r#"a\\"#));
+        assert_eq!(object(text, quotes, false, 'b').as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn word_objects() {
+        let text = "let foo_bar = 1;";
+
+        assert_eq!(object(text, TextObjectKind::Word, false, 'o').as_deref(), Some("foo_bar"));
+        assert_eq!(object(text, TextObjectKind::Word, true, 'o').as_deref(), Some("foo_bar "));
+        assert_eq!(object(text, TextObjectKind::Word, true, ';').as_deref(), Some(";"));
+        assert_eq!(object("a.b c", TextObjectKind::BigWord, false, 'b').as_deref(), Some("a.b"));
+    }
+
+    #[test]
+    fn word_objects_follow_the_word_chars() {
+        let text = "margin-top: 0;";
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, text);
+        let cursor = text.find('t').unwrap();
+
+        let word = |buf: &TextBuffer| {
+            buf.text_object(TextObjectKind::Word, false, cursor).map(|r| &text[r])
+        };
+        assert_eq!(word(&buf), Some("top"));
+
+        buf.word_chars = WordChars::new("_-");
+        assert_eq!(word(&buf), Some("margin-top"));
+    }
+}
@@ -206,7 +206,7 @@ impl SafeRopeAccess for TextBuffer {
 
     fn slice_to_string(&self, start: usize, end: usize) -> Option<String> {
         self.slice_bounds_valid(start, end)
-            .then(|| self.slice_inner(start, end).to_string())
+            .then(|| self.rope.byte_range_to_string(start..end))
     }
 
     fn slice(&self, start: usize, end: usize) -> Option<RopeSlice<'_>> {
@@ -0,0 +1,138 @@
+use std::{borrow::Cow, sync::LazyLock};
+
+use regex::Regex;
+
+/// The word characters of `\w` apart from `_`, which is only a word character when listed
+const BASE_ITEMS: &str = r"\p{Alphabetic}\p{M}[\p{Pc}--_]\p{N}\p{Join_Control}";
+
+static BASE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!("^[{BASE_ITEMS}]$")).expect("word class is valid"));
+
+/// The characters that make up a word for word motions and text objects, like Vim's
+/// `iskeyword`. Letters, digits and combining marks always count; the rest are set per
+/// filetype with `filetype <name> --iskeyword`, so `-` can join words in CSS and Lisp but
+/// not in Rust
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordChars {
+    extra: Vec<char>,
+}
+
+impl Default for WordChars {
+    fn default() -> Self {
+        Self::new("_")
+    }
+}
+
+impl WordChars {
+    /// Word characters made of letters, digits and each character of `extra`
+    pub fn new(extra: &str) -> Self {
+        let mut chars: Vec<char> = extra
+            .chars()
+            .filter(|c| !c.is_alphanumeric() && !c.is_whitespace())
+            .collect();
+        chars.sort_unstable();
+        chars.dedup();
+        Self { extra: chars }
+    }
+
+    pub fn contains(&self, ch: char) -> bool {
+        ch.is_alphanumeric()
+            || self.extra.contains(&ch)
+            || (!ch.is_ascii() && BASE.is_match(ch.encode_utf8(&mut [0; 4])))
+    }
+
+    /// The items of a regex character class matching exactly the word characters
+    pub fn class_items(&self) -> String {
+        let mut items = BASE_ITEMS.to_string();
+        for ch in &self.extra {
+            items.push_str(&format!(r"\x{{{:X}}}", *ch as u32));
+        }
+        items
+    }
+
+    /// Replaces each `\k` in `pattern` with the word characters, as a class of its own or
+    /// as items of the class it is written in, so `\k+|[^\k\s]+` splits words from
+    /// punctuation. Patterns without `\k` are returned as they are
+    pub fn expand<'a>(&self, pattern: &'a str) -> Cow<'a, str> {
+        if !pattern.contains(r"\k") {
+            return Cow::Borrowed(pattern);
+        }
+
+        let items = self.class_items();
+        let mut out = String::with_capacity(pattern.len() + items.len());
+        let mut class_depth = 0usize;
+        let mut chars = pattern.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' => match chars.next() {
+                    Some('k') if class_depth > 0 => out.push_str(&items),
+                    Some('k') => {
+                        out.push('[');
+                        out.push_str(&items);
+                        out.push(']');
+                    }
+                    Some(escaped) => {
+                        out.push('\\');
+                        out.push(escaped);
+                    }
+                    None => out.push('\\'),
+                },
+                '[' => {
+                    class_depth += 1;
+                    out.push(ch);
+                }
+                ']' if class_depth > 0 => {
+                    class_depth -= 1;
+                    out.push(ch);
+                }
+                _ => out.push(ch),
+            }
+        }
+        Cow::Owned(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RopeyCursor, cached};
+
+    fn words(chars: &WordChars, text: &str) -> Vec<String> {
+        let regex = cached(&chars.expand(r"\k+")).unwrap();
+        let rope = ropey::Rope::from_str(text);
+        let input = regex_cursor::Input::new(RopeyCursor::new(rope.slice(..)));
+        regex.find_iter(input).map(|m| text[m.start()..m.end()].to_string()).collect()
+    }
+
+    #[test]
+    fn dash_joins_words_only_when_listed() {
+        let rust = WordChars::default();
+        let css = WordChars::new("_-");
+
+        assert_eq!(words(&rust, "margin-top: foo_bar"), ["margin", "top", "foo_bar"]);
+        assert_eq!(words(&css, "margin-top: foo_bar"), ["margin-top", "foo_bar"]);
+        assert!(css.contains('-') && !rust.contains('-'));
+        assert!(!WordChars::new("-").contains('_'));
+    }
+
+    #[test]
+    fn combining_marks_stay_in_words() {
+        let chars = WordChars::default();
+        let text = "cafe\u{301} nai\u{308}ve x";
+        assert_eq!(words(&chars, text), ["cafe\u{301}", "nai\u{308}ve", "x"]);
+        assert!(chars.contains('\u{301}') && chars.contains('\u{203F}'));
+    }
+
+    #[test]
+    fn expands_inside_and_outside_classes() {
+        let chars = WordChars::new("-");
+        assert_eq!(
+            chars.expand(r"\k+|[^\k\s]+"),
+            r"[\p{Alphabetic}\p{M}[\p{Pc}--_]\p{N}\p{Join_Control}\x{2D}]+|"
+                .to_string()
+                + r"[^\p{Alphabetic}\p{M}[\p{Pc}--_]\p{N}\p{Join_Control}\x{2D}\s]+"
+        );
+        assert_eq!(chars.expand(r"\\k\w"), r"\\k\w");
+        assert!(matches!(chars.expand(r"\w+"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,96 @@
+use ratatui::{buffer::Buffer, layout::Rect, style::Style};
+
+use crate::{UnicodeWidthChar, UnicodeWidthStr};
+
+pub trait BufferExts {
+    /// Word-wraps `text` into `rect`, breaking words wider than the rect between characters.
+    /// Rows past the bottom of the rect are dropped. Returns the number of rows written.
+    fn write_wrapped(&mut self, rect: Rect, text: &str, style: Style) -> u16;
+}
+
+impl BufferExts for Buffer {
+    fn write_wrapped(&mut self, rect: Rect, text: &str, style: Style) -> u16 {
+        let rows = wrap_text(text, rect.width as usize);
+        let used = rows.len().min(rect.height as usize) as u16;
+
+        for (y, row) in (rect.y..rect.y + used).zip(rows) {
+            self.set_stringn(rect.x, y, row, rect.width as usize, style);
+        }
+
+        used
+    }
+}
+
+/// Splits `text` into rows at most `width` columns wide, breaking at spaces where possible.
+/// Newlines always start a new row. Use the row count to size a popup before drawing it.
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut rows = vec![];
+
+    for line in text.split('\n') {
+        let mut row = String::new();
+        let mut row_width = 0;
+
+        for word in line.split(' ') {
+            let word_width = word.width();
+            let gap = usize::from(!row.is_empty());
+
+            if row_width + gap + word_width <= width {
+                if gap == 1 {
+                    row.push(' ');
+                }
+                row.push_str(word);
+                row_width += gap + word_width;
+                continue;
+            }
+
+            if !row.is_empty() {
+                rows.push(std::mem::take(&mut row));
+                row_width = 0;
+            }
+
+            // Words wider than a row are split wherever they run out of room
+            for ch in word.chars() {
+                let ch_width = ch.width().unwrap_or(0);
+                if row_width + ch_width > width && !row.is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                    row_width = 0;
+                }
+                row.push(ch);
+                row_width += ch_width;
+            }
+        }
+
+        rows.push(row);
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_paragraph_into_narrow_area() {
+        let text = "The quick brown fox jumps over the lazy dog, then naps in the warm afternoon sun.";
+        let rect = Rect::new(0, 0, 20, 10);
+        let mut buf = Buffer::empty(rect);
+
+        assert_eq!(buf.write_wrapped(rect, text, Style::default()), 5);
+        assert_eq!(
+            wrap_text(text, 20),
+            [
+                "The quick brown fox",
+                "jumps over the lazy",
+                "dog, then naps in",
+                "the warm afternoon",
+                "sun.",
+            ]
+        );
+
+        // Wide characters count as two columns, and long words are split
+        assert_eq!(wrap_text("日本語のテキスト", 6), ["日本語", "のテキ", "スト"]);
+        assert_eq!(buf.write_wrapped(Rect::new(0, 0, 20, 2), text, Style::default()), 2);
+    }
+}
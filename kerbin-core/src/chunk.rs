@@ -28,6 +28,7 @@ impl<T: StateName + StaticState> StateName for Chunk<T> {
 }
 
 #[async_trait::async_trait]
+#[diagnostic::do_not_recommend]
 impl<T: StateName + StaticState> SystemParam for Chunk<T> {
     type Item<'new> = Chunk<T>;
     fn retrieve(resources: &StateStorage) -> Self::Item<'_> {
@@ -55,7 +56,7 @@ impl<T: StateName + StaticState> SystemParam for Chunk<T> {
         }
     }
 
-    fn desc() -> SystemParamDesc {
-        SystemParamDesc::new::<Chunks>(false)
+    fn desc() -> Vec<SystemParamDesc> {
+        vec![SystemParamDesc::new::<Chunks>(false)]
     }
 }
@@ -30,3 +30,6 @@ pub struct LogChunk;
 
 #[derive(State)]
 pub struct DialogueChunk;
+
+#[derive(State)]
+pub struct PickerChunk;
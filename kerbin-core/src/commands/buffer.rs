@@ -7,9 +7,48 @@ use crate::*;
 
 const SCRATCH_BUFFER_PATH: &str = "<scratch>";
 
+/// A line `goto_line` can jump to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineTarget {
+    /// A 1-based line number
+    Line(usize),
+    /// The last line, written `$`
+    Last,
+    /// A percentage through the file, written `N%`
+    Percent(usize),
+}
+
+impl LineTarget {
+    /// Parses `42`, `$` or `50%`
+    pub fn parse(text: &str) -> Option<Self> {
+        if text == "$" {
+            return Some(Self::Last);
+        }
+
+        match text.strip_suffix('%') {
+            Some(percent) => percent.parse().ok().map(Self::Percent),
+            None => text.parse().ok().map(Self::Line),
+        }
+    }
+
+    /// The 0-based line this points to in a buffer of `len_lines` lines
+    pub fn resolve(self, len_lines: usize) -> usize {
+        let last = len_lines.saturating_sub(1);
+        match self {
+            Self::Line(line) => line.saturating_sub(1).min(last),
+            Self::Last => last,
+            Self::Percent(percent) => {
+                (percent.min(100) * len_lines).div_ceil(100).saturating_sub(1).min(last)
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug, Command)]
+#[command(category = "buffer")]
 pub enum CommitCommand {
-    /// Wraps a command in a change group, committing it as a single undoable change.
+    /// Wraps a command in a change group, committing it as a single undoable change even
+    /// when it runs several commands that open their own.
     Commit(#[command(name = "cmd", type_name = "[command]?", ignore)] Option<Vec<Token>>),
 }
 
@@ -19,13 +58,7 @@ impl Command<State> for CommitCommand {
         match self {
             CommitCommand::Commit(after) => {
                 let mut res = true;
-
-                {
-                    let mut buffers = state.lock_state::<Buffers>().await;
-                    if let Some(mut tb) = buffers.cur_text_buffer_mut().await {
-                        tb.start_change_group();
-                    }
-                }
+                let batch = begin_undo_batch(state).await;
 
                 if let Some(after) = after {
                     let command = state.lock_state::<CommandRegistry>().await.parse_command(
@@ -43,13 +76,7 @@ impl Command<State> for CommitCommand {
                     }
                 }
 
-                {
-                    let mut buffers = state.lock_state::<Buffers>().await;
-                    if let Some(mut tb) = buffers.cur_text_buffer_mut().await {
-                        tb.commit_change_group();
-                    }
-                }
-
+                end_undo_batch(batch).await;
                 res
             }
         }
@@ -57,6 +84,7 @@ impl Command<State> for CommitCommand {
 }
 
 #[derive(Clone, Debug, Command)]
+#[command(category = "buffer")]
 pub enum BufferCommand {
     #[command(name = "mb")]
     /// Moves primary cursor by a given number of bytes
@@ -82,6 +110,15 @@ pub enum BufferCommand {
         extend: bool,
     },
 
+    #[command(name = "mg")]
+    /// Moves primary cursor by a given number of grapheme clusters
+    /// Combining sequences and emoji are crossed as a single step
+    MoveGraphemes {
+        graphemes: isize,
+        #[command(flag)]
+        extend: bool,
+    },
+
     #[command(drop_ident, name = "goto")]
     /// Navigates to the given row and column
     /// Clamps both values to the max positions
@@ -93,6 +130,17 @@ pub enum BufferCommand {
         extend: bool,
     },
 
+    #[command(drop_ident, name = "goto_line")]
+    /// Moves the primary cursor to a 1-based line number, centering it in the view
+    /// Takes `$` for the last line and `N%` for a percentage through the file
+    /// Line numbers past the end clamp to the last line
+    GotoLine {
+        line: String,
+
+        #[command(flag)]
+        extend: bool,
+    },
+
     #[command(name = "write", name = "w")]
     /// Writes the buffer to disk. An optional path overrides the current filename.
     ///
@@ -124,6 +172,11 @@ pub enum BufferCommand {
     /// See `reload_file` to respect unsaved changes.
     ReloadFileForce,
 
+    #[command(drop_ident, name = "recover")]
+    /// Replaces the buffer's text with the unsaved changes kept for its file by `core.backup`,
+    /// as one change that can be undone
+    Recover,
+
     /// Starts a committable change (allows for undo and redo).
     StartChange,
     /// Commits the active change. No-op if no change is active.
@@ -157,8 +210,23 @@ pub enum BufferCommand {
     /// Toggles the case of all characters in selection
     ToggleCase,
 
-    #[command(name = "jl")]
-    /// Joins the current line with the next by replacing the trailing newline with a space
+    #[command(drop_ident, name = "indent")]
+    /// Indents every line touched by a selection by one indent unit
+    IndentLines,
+
+    #[command(drop_ident, name = "dedent")]
+    /// Removes up to one indent unit from every line touched by a selection
+    DedentLines,
+
+    #[command(drop_ident, name = "toggle_comment", name = "tcm")]
+    /// Comments the selected lines with their filetype's comment token,
+    /// or uncomments them if they're all commented already
+    ToggleComment,
+
+    #[command(drop_ident, name = "join", name = "jl")]
+    /// Joins the current line with the next, or all lines of a multi-line selection, as one
+    /// change. Line breaks and the indentation after them become `core.join_separator`,
+    /// left out before the characters in `core.join_no_space_before`
     JoinLine,
 
     #[command(name = "scroll")]
@@ -180,6 +248,13 @@ impl Command<State> for BufferCommand {
             return true;
         }
 
+        // Subscribers may lock the buffers, so the veto runs before anything is held
+        if let BufferCommand::WriteFile { path } | BufferCommand::WriteFileForce { path } = self
+            && !pre_save_allowed(state, path.clone()).await
+        {
+            return false;
+        }
+
         let tab_w = state
             .lock_state::<CoreConfig>()
             .await
@@ -203,29 +278,41 @@ impl Command<State> for BufferCommand {
                 cur_buffer.move_lines(*lines, *extend, tab_w)
             }
             BufferCommand::MoveChars { chars, extend } => cur_buffer.move_chars(*chars, *extend),
+            BufferCommand::MoveGraphemes { graphemes, extend } => {
+                cur_buffer.move_graphemes(*graphemes, *extend)
+            }
             BufferCommand::GoTo { row, col, extend } => {
                 let line_byte = cur_buffer.line_to_byte_clamped(*row);
                 let target_byte = line_byte.saturating_add(*col).min(cur_buffer.len());
                 let cursor_mut = cur_buffer.primary_cursor_mut();
                 if *extend {
-                    let anchor_byte = if cursor_mut.at_start {
-                        *cursor_mut.sel.end()
-                    } else {
-                        *cursor_mut.sel.start()
-                    };
-                    let start = anchor_byte.min(target_byte);
-                    let end = anchor_byte.max(target_byte);
-                    cursor_mut.set_sel(start..=end);
-                    cursor_mut.set_at_start(target_byte < anchor_byte);
+                    cursor_mut.extend_to(target_byte);
                 } else {
-                    cursor_mut.set_sel(target_byte..=target_byte);
-                    cursor_mut.set_at_start(false);
+                    cursor_mut.move_to(target_byte);
                 }
 
                 // This can't be repeated anyways
                 false
             }
 
+            BufferCommand::GotoLine { line, extend } => {
+                let Some(target) = LineTarget::parse(line) else {
+                    // Empty while a line number is still being typed
+                    if line.is_empty() {
+                        return false;
+                    }
+                    log.medium(
+                        "command::goto_line",
+                        format!("Invalid line `{line}`, expected a number, `$` or `N%`"),
+                    );
+                    return false;
+                };
+
+                let line = target.resolve(cur_buffer.len_lines());
+                cur_buffer.goto_line(line, *extend);
+                false
+            }
+
             BufferCommand::WriteFile { path } => {
                 let current_path = if let Some(new_path) = path {
                     new_path.clone()
@@ -241,33 +328,10 @@ impl Command<State> for BufferCommand {
                     return false;
                 }
 
-                match std::fs::metadata(&current_path) {
-                    Ok(metadata) => {
-                        let disk_modified = metadata.modified().ok();
-                        let buffer_changed = cur_buffer.changed;
-
-                        if let Some(disk_time) = disk_modified
-                            && let Some(buffer_time) = buffer_changed
-                            && disk_time != buffer_time
-                        {
-                            let message = format!(
-                                "File has been modified externally since last read/save: {}. Disk time: {:?}, Buffer time: {:?}",
-                                current_path, disk_time, buffer_time
-                            );
-
-                            tracing::error!(message);
-                            log.high("command::write_file", message);
-                            return false;
-                        }
-                    }
-                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
-                        let message =
-                            format!("Failed to read metadata for file {}: {}", current_path, e);
-                        tracing::error!(message);
-                        log.high("command::write_file", message);
-                        return false;
-                    }
-                    _ => {}
+                if let Err(message) = cur_buffer.check_external_change(&current_path) {
+                    tracing::error!(message);
+                    log.high("command::write_file", message);
+                    return false;
                 }
 
                 if let Err(e) = cur_buffer.write_file(path.clone()).await {
@@ -299,6 +363,19 @@ impl Command<State> for BufferCommand {
 
             BufferCommand::ReloadFileForce => reload_file_inner(&mut cur_buffer, &log, true),
 
+            BufferCommand::Recover => {
+                let Some(text) = recovered_text(&cur_buffer.path) else {
+                    let message = format!("Nothing to recover for {}", cur_buffer.path);
+                    log.medium("command::recover", message);
+                    return false;
+                };
+
+                cur_buffer.start_change_group();
+                let recovered = cur_buffer.replace_text(&text);
+                cur_buffer.commit_change_group();
+                recovered
+            }
+
             BufferCommand::StartChange => {
                 cur_buffer.start_change_group();
                 true
@@ -309,6 +386,14 @@ impl Command<State> for BufferCommand {
                 true
             }
 
+            BufferCommand::IndentLines => cur_buffer.shift_indent(false),
+            BufferCommand::DedentLines => cur_buffer.shift_indent(true),
+            BufferCommand::ToggleComment => {
+                let filetypes = state.lock_state::<FiletypeRegistry>().await;
+                let token = filetypes.comment_token(cur_buffer.filetype.as_deref()).to_string();
+                cur_buffer.toggle_comment(&token)
+            }
+
             BufferCommand::Insert(text) => cur_buffer.action(Insert {
                 byte,
                 content: text.clone(),
@@ -369,23 +454,8 @@ impl Command<State> for BufferCommand {
             }
 
             BufferCommand::JoinLine => {
-                let line_idx = cur_buffer.byte_to_line_clamped(byte);
-                if line_idx + 1 >= cur_buffer.len_lines() {
-                    return false;
-                }
-                let next_line_start = cur_buffer.line_to_byte_clamped(line_idx + 1);
-                let newline_byte = next_line_start.saturating_sub(1);
-                if newline_byte < next_line_start {
-                    cur_buffer.action(Delete {
-                        byte: newline_byte,
-                        len: 1,
-                    });
-                    cur_buffer.action(Insert {
-                        byte: newline_byte,
-                        content: " ".to_string(),
-                    });
-                }
-                true
+                let config = state.lock_state::<CoreConfig>().await;
+                cur_buffer.join_lines(&config.join_separator, &config.join_no_space_before)
             }
 
             BufferCommand::Delete => {
@@ -416,6 +486,34 @@ impl Command<State> for BufferCommand {
     }
 }
 
+/// Emits `PreSaveEvent`, returning false if a subscriber vetoed the save
+pub(crate) async fn pre_save_allowed(state: &mut State, path: Option<String>) -> bool {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let mut buffers = state.lock_state::<Buffers>().await;
+            let Some(buf) = buffers.cur_text_buffer_mut().await else {
+                return true;
+            };
+            buf.path.clone()
+        }
+    };
+
+    let replies = EVENT_BUS
+        .emit_collecting::<_, Result<(), String>>(PreSaveEvent { path }, state)
+        .await;
+
+    if let Some(Err(reason)) = replies.into_iter().find(|r| r.is_err()) {
+        state
+            .lock_state::<LogSender>()
+            .await
+            .high("command::write_file", format!("Save cancelled: {reason}"));
+        return false;
+    }
+
+    true
+}
+
 fn reload_file_inner(buf: &mut TextBuffer, log: &LogSender, force: bool) -> bool {
     if !force && buf.dirty {
         let message = "Cannot reload file: buffer has unsaved changes. Use reload! to force.";
@@ -435,14 +533,25 @@ fn reload_file_inner(buf: &mut TextBuffer, log: &LogSender, force: bool) -> bool
     match std::fs::File::open(&path) {
         Ok(f) => match ropey::Rope::from_reader(std::io::BufReader::new(f)) {
             Ok(rope) => {
-                buf.rope = rope;
+                // Diffing in the file keeps the buffer states (like syntax trees) valid,
+                // they only see the lines that changed on disk
+                buf.start_change_group();
+                if !buf.replace_text(&rope.to_string()) {
+                    buf.rope = rope;
+                    buf.states.clear();
+                }
+                buf.commit_change_group();
+
                 buf.dirty = false;
+                buf.stale = false;
                 buf.undo_stack.clear();
                 buf.redo_stack.clear();
                 buf.save_point = 0;
 
-                buf.states.clear();
+                // Whether the buffer is read-only outlives a reload
+                let readonly = buf.flags.contains(READONLY_FLAG);
                 buf.flags.clear();
+                buf.set_readonly(readonly);
 
                 if let Ok(metadata) = std::fs::metadata(&path) {
                     buf.changed = metadata.modified().ok();
@@ -467,20 +576,46 @@ fn reload_file_inner(buf: &mut TextBuffer, log: &LogSender, force: bool) -> bool
 }
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "buffer")]
 pub enum BuffersCommand {
     #[command(name = "open", name = "o")]
     /// Opens the given filepath can be absolute or relative
+    /// A file that's already open is focused instead, unless --force-new is passed
     OpenFile {
+        #[command(complete = "path")]
         path: String,
         /// Override the detected filetype (e.g. --filetype rust)
         #[command(flag)]
         filetype: Option<String>,
+        /// Jump to a line once opened, as `goto_line` takes it (e.g. --line 42)
+        #[command(flag)]
+        line: Option<String>,
+        /// Open another buffer even if the file is already open
+        #[command(flag, name = "force-new")]
+        force_new: bool,
+    },
+
+    #[command(drop_ident, name = "buffer")]
+    /// Switches to an open buffer by its 1-based position in the bufferline,
+    /// or by path, falling back to the first buffer whose path contains the text.
+    /// `#` switches to the alternate buffer, the one selected before the current one
+    SelectBuffer {
+        #[command(complete = "buffer")]
+        path: String,
     },
 
     #[command(drop_ident, name = "move_buf", name = "bm")]
     /// Moves the currently active buffer based on an offset
     SwitchBuffer(isize),
 
+    #[command(drop_ident, name = "buffer-move-left", name = "bml")]
+    /// Moves the current buffer one place left in the bufferline
+    MoveBufferLeft,
+
+    #[command(drop_ident, name = "buffer-move-right", name = "bmr")]
+    /// Moves the current buffer one place right in the bufferline
+    MoveBufferRight,
+
     #[command(drop_ident, name = "buf_close", name = "bc")]
     /// Closes the current buffer unless an offset is passed
     CloseBufferOffset(Option<isize>),
@@ -489,18 +624,67 @@ pub enum BuffersCommand {
     /// Force closes the current buffer unless offset is passed (ignores dirty flag)
     /// for a command that respects the dirty flag, see `buf_close`
     CloseBufferOffsetForce(Option<isize>),
+
+    #[command(drop_ident, name = "reopen")]
+    /// Reopens the most recently closed file at its last cursor position
+    ReopenBuffer,
 }
 
 #[async_trait::async_trait]
 impl Command<State> for BuffersCommand {
     async fn apply(&self, state: &mut State) -> bool {
+        let (default_tab_unit, big_file_threshold) = {
+            let config = state.lock_state::<CoreConfig>().await;
+            (config.default_tab_unit, config.big_file_threshold)
+        };
+
+        // Read the file before locking the buffers, so a big file doesn't stall every system
+        let mut loaded = None;
+        if let Self::OpenFile {
+            path, force_new, ..
+        } = self
+        {
+            let already_open = !*force_new
+                && state.lock_state::<Buffers>().await.find_path(path).await.is_some();
+            if !already_open {
+                loaded = Some(
+                    TextBuffer::open_async(path.clone(), default_tab_unit, big_file_threshold)
+                        .await,
+                );
+            }
+        }
+
         let mut buffers = state.lock_state::<Buffers>().await;
         let log = state.lock_state::<LogSender>().await;
-        let default_tab_unit = state.lock_state::<CoreConfig>().await.default_tab_unit;
 
         match self {
-            Self::OpenFile { path, filetype } => {
-                let buffer_id = match buffers.open(path.clone(), default_tab_unit).await {
+            Self::OpenFile {
+                path,
+                filetype,
+                line,
+                force_new,
+            } => {
+                let open_idx = match *force_new {
+                    true => None,
+                    false => buffers.find_path(path).await,
+                };
+                let opened = match (open_idx, loaded) {
+                    (Some(idx), _) => {
+                        buffers.set_selected_buffer(idx);
+                        Ok(idx)
+                    }
+                    (None, Some(loaded)) => match loaded {
+                        Ok(buf) => Ok(buffers.push_opened(buf).await),
+                        Err(e) => Err(e),
+                    },
+                    // Closed again since it was looked up
+                    (None, None) => {
+                        buffers
+                            .open(path.clone(), default_tab_unit, big_file_threshold)
+                            .await
+                    }
+                };
+                let buffer_id = match opened {
                     Ok(t) => t,
                     Err(e) => {
                         match e.kind() {
@@ -531,6 +715,13 @@ impl Command<State> for BuffersCommand {
                 };
                 buffers.set_selected_buffer(buffer_id);
 
+                if buffers.cur_text_buffer().await.is_some_and(|b| b.big_file) {
+                    log.low(
+                        "command::open_file",
+                        format!("'{path}' is a big file, opened read-only without highlighting"),
+                    );
+                }
+
                 // Apply explicit filetype override, bypassing auto-detection
                 if let Some(ft) = filetype
                     && let Some(mut buf) = buffers.cur_text_buffer_mut().await
@@ -538,26 +729,46 @@ impl Command<State> for BuffersCommand {
                     buf.filetype = Some(ft.clone());
                 }
 
-                // Track the opened buffer in the focused pane
-                let mut split = state.lock_state::<SplitState>().await;
-                if !split.unique_buffers {
-                    if let Some(pane) = split.focused_pane_mut() {
-                        pane.selected_local = buffer_id;
-                    }
-                } else if let Some(pane) = split.focused_pane_mut() {
-                    if !pane.buffer_indices.contains(&buffer_id) {
-                        pane.buffer_indices.push(buffer_id);
+                if let Some(line) = line
+                    && let Some(mut buf) = buffers.cur_text_buffer_mut().await
+                {
+                    match LineTarget::parse(line) {
+                        Some(target) => {
+                            let line = target.resolve(buf.len_lines());
+                            buf.goto_line(line, false);
+                        }
+                        None => {
+                            log.medium(
+                                "command::open_file",
+                                format!("Invalid line `{line}`, expected a number, `$` or `N%`"),
+                            );
+                        }
                     }
-                    pane.selected_local = pane
-                        .buffer_indices
-                        .iter()
-                        .position(|&x| x == buffer_id)
-                        .unwrap_or(0);
                 }
 
+                // Track the opened buffer in the focused pane
+                track_in_focused_pane(state, buffer_id).await;
+
+                true
+            }
+
+            Self::SelectBuffer { path } => {
+                let Some(buffer_id) = find_buffer(state, &buffers, path).await else {
+                    log.medium(
+                        "command::select_buffer",
+                        format!("No open buffer named '{path}'"),
+                    );
+                    return false;
+                };
+                buffers.set_selected_buffer(buffer_id);
+                track_in_focused_pane(state, buffer_id).await;
+
                 true
             }
 
+            Self::MoveBufferLeft => move_current_buffer(state, &mut buffers, -1).await,
+            Self::MoveBufferRight => move_current_buffer(state, &mut buffers, 1).await,
+
             Self::SwitchBuffer(offset) => {
                 let mut split = state.lock_state::<SplitState>().await;
                 if !split.unique_buffers {
@@ -591,7 +802,104 @@ impl Command<State> for BuffersCommand {
             Self::CloseBufferOffsetForce(offset) => {
                 close_buffer_inner(state, &mut buffers, &log, offset.unwrap_or(0), true).await
             }
+
+            Self::ReopenBuffer => {
+                let Some(closed) = state.lock_state::<ClosedBuffers>().await.pop() else {
+                    log.low("command::reopen", "No closed buffers to reopen");
+                    return false;
+                };
+
+                // Restoring locks the buffers and log itself
+                drop(buffers);
+                drop(log);
+                restore_jump(state, closed).await
+            }
+        }
+    }
+}
+
+/// Resolves a `buffer` argument: `#` for the alternate buffer, a 1-based bufferline
+/// position, an exact path, or the first buffer whose path contains `query`
+async fn find_buffer(state: &State, buffers: &Buffers, query: &str) -> Option<usize> {
+    if query == "#" {
+        return buffers.alternate_index;
+    }
+
+    if let Ok(n) = query.parse::<usize>() {
+        let split = state.lock_state::<SplitState>().await;
+        return match split.focused_pane() {
+            Some(pane) if split.unique_buffers => pane.buffer_indices.get(n.checked_sub(1)?).copied(),
+            _ => n.checked_sub(1).filter(|&i| i < buffers.buffers.len()),
+        };
+    }
+
+    let check_path = get_canonical_path_with_non_existent(query)
+        .to_string_lossy()
+        .into_owned();
+
+    let mut titles = Vec::with_capacity(buffers.buffers.len());
+    for buf in &buffers.buffers {
+        titles.push(buf.read().await.title());
+    }
+
+    titles
+        .iter()
+        .position(|title| *title == query || *title == check_path)
+        .or_else(|| titles.iter().position(|title| title.contains(query)))
+}
+
+/// Swaps the current buffer with its neighbour `offset` places away in the focused
+/// pane's bufferline. Shared panes reorder the global buffer list, unique panes only
+/// their own tabs.
+async fn move_current_buffer(state: &State, buffers: &mut Buffers, offset: isize) -> bool {
+    let mut split = state.lock_state::<SplitState>().await;
+
+    if split.unique_buffers {
+        let Some(pane) = split.focused_pane_mut() else { return false; };
+        let from = pane.selected_local;
+        let Some(to) = from
+            .checked_add_signed(offset)
+            .filter(|&to| to < pane.buffer_indices.len())
+        else {
+            return false;
+        };
+
+        pane.buffer_indices.swap(from, to);
+        pane.selected_local = to;
+        return true;
+    }
+
+    let from = buffers.selected_buffer;
+    let Some(to) = from.checked_add_signed(offset) else { return false; };
+    if !buffers.move_buffer(from, to) {
+        return false;
+    }
+
+    for pane in split.leaves_mut() {
+        pane.selected_local = moved_index(pane.selected_local, from, to);
+        for idx in &mut pane.buffer_indices {
+            *idx = moved_index(*idx, from, to);
+        }
+    }
+    true
+}
+
+/// Shows `buffer_id` in the focused pane, adding it to the pane's list when buffers are unique
+pub(crate) async fn track_in_focused_pane(state: &State, buffer_id: usize) {
+    let mut split = state.lock_state::<SplitState>().await;
+    if !split.unique_buffers {
+        if let Some(pane) = split.focused_pane_mut() {
+            pane.selected_local = buffer_id;
+        }
+    } else if let Some(pane) = split.focused_pane_mut() {
+        if !pane.buffer_indices.contains(&buffer_id) {
+            pane.buffer_indices.push(buffer_id);
         }
+        pane.selected_local = pane
+            .buffer_indices
+            .iter()
+            .position(|&x| x == buffer_id)
+            .unwrap_or(0);
     }
 }
 
@@ -622,6 +930,7 @@ async fn close_buffer_inner(
 
     let shared = !state.lock_state::<SplitState>().await.unique_buffers;
     if shared {
+        remember_closed(state, buffers, buf_idx).await;
         buffers.close_buffer(buf_idx).await;
         fixup_shared_panes_after_close(state, buffers, buf_idx).await;
     } else {
@@ -643,6 +952,7 @@ async fn close_buffer_inner(
             (!referenced, sel)
         };
         if close_globally {
+            remember_closed(state, buffers, buf_idx).await;
             buffers.close_buffer(buf_idx).await;
             let mut split = state.lock_state::<SplitState>().await;
             for pane in split.leaves_mut() {
@@ -666,6 +976,14 @@ async fn close_buffer_inner(
     true
 }
 
+/// Records the buffer at `buf_idx` in `ClosedBuffers` so `reopen` can bring it back
+async fn remember_closed(state: &State, buffers: &Buffers, buf_idx: usize) {
+    let buf = buffers.buffers[buf_idx].read().await;
+    if let Some(buf) = buf.as_any().downcast_ref::<TextBuffer>() {
+        state.lock_state::<ClosedBuffers>().await.record(buf);
+    }
+}
+
 /// After closing a buffer at `buf_idx` in shared mode, adjusts every pane's
 /// `selected_local` so it remains a valid global buffer index, then syncs
 /// `Buffers.selected_buffer` to the focused pane.
@@ -686,3 +1004,79 @@ async fn fixup_shared_panes_after_close(state: &State, buffers: &mut Buffers, bu
         buffers.set_selected_buffer(sel);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_targets_clamp_to_the_last_line() {
+        assert_eq!(LineTarget::parse("12"), Some(LineTarget::Line(12)));
+        assert_eq!(LineTarget::parse("$"), Some(LineTarget::Last));
+        assert_eq!(LineTarget::parse("50%"), Some(LineTarget::Percent(50)));
+        assert_eq!(LineTarget::parse("x%"), None);
+        assert_eq!(LineTarget::parse("-3"), None);
+
+        assert_eq!(LineTarget::Line(1).resolve(10), 0);
+        assert_eq!(LineTarget::Line(0).resolve(10), 0);
+        assert_eq!(LineTarget::Line(10).resolve(10), 9);
+        assert_eq!(LineTarget::Line(500).resolve(10), 9);
+        assert_eq!(LineTarget::Last.resolve(10), 9);
+        assert_eq!(LineTarget::Percent(50).resolve(10), 4);
+        assert_eq!(LineTarget::Percent(0).resolve(10), 0);
+        assert_eq!(LineTarget::Percent(250).resolve(10), 9);
+    }
+
+    #[test]
+    fn goto_line_clamps_and_collapses_the_selection() {
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, "one\ntwo\nthree");
+        buf.primary_cursor_mut().set_sel(1..=5);
+
+        let last = LineTarget::Line(99).resolve(buf.len_lines());
+        assert!(buf.goto_line(last, false));
+        assert_eq!(buf.primary_cursor().sel().clone(), 8..=8);
+        assert!(buf.renderer.center_cursor);
+
+        buf.goto_line(1, true);
+        assert_eq!(buf.primary_cursor().sel().clone(), 4..=8);
+    }
+
+    /// Number of buffers and the selected one
+    async fn opened(state: &State) -> (usize, usize) {
+        let buffers = state.lock_state::<Buffers>().await;
+        (buffers.buffers.len(), buffers.selected_buffer)
+    }
+
+    #[tokio::test]
+    async fn open_file_focuses_an_open_file_unless_forced() {
+        let path = std::env::temp_dir().join(format!("kerbin-open-{}.txt", std::process::id()));
+        std::fs::write(&path, "text").unwrap();
+        let open = |force_new| BuffersCommand::OpenFile {
+            path: path.to_string_lossy().into_owned(),
+            filetype: None,
+            line: None,
+            force_new,
+        };
+
+        let (_log, log_sender) = LogState::new_with_channel();
+        let mut state = State::new();
+        state
+            .state(Buffers::default())
+            .state(log_sender)
+            .state(CoreConfig::default())
+            .state(SplitState::default());
+
+        assert!(open(false).apply(&mut state).await);
+        assert_eq!(opened(&state).await, (2, 1));
+
+        state.lock_state::<Buffers>().await.set_selected_buffer(0);
+        assert!(open(false).apply(&mut state).await);
+        assert_eq!(opened(&state).await, (2, 1));
+
+        assert!(open(true).apply(&mut state).await);
+        assert_eq!(opened(&state).await, (3, 2));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
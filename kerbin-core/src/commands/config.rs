@@ -2,14 +2,18 @@ use crate::*;
 use kerbin_macros::Command;
 use kerbin_state_machine::State;
 
-fn parse_key_tokens(keys: &[Token]) -> Vec<UnresolvedKeyBind> {
-    keys.iter()
-        .filter_map(|t| match t {
-            Token::Word(s) => s.parse().ok(),
-            Token::Variable(name) => format!("%{}", name).parse().ok(),
-            _ => None,
-        })
-        .collect()
+/// Parses the keys of a `bind` or `category`. A word may hold several keys in angle
+/// bracket notation (`<C-w>h`, `<leader>f`), with `<leader>` expanding to `leader`.
+fn parse_key_tokens(keys: &[Token], leader: &str) -> Result<Vec<UnresolvedKeyBind>, String> {
+    let mut res = vec![];
+    for token in keys {
+        match token {
+            Token::Word(s) => res.extend(parse_key_sequence(s, leader)?),
+            Token::Variable(name) => res.push(format!("%{}", name).parse()?),
+            _ => continue,
+        }
+    }
+    Ok(res)
 }
 
 fn tokens_to_mode_chars(tokens: &Option<Vec<Token>>) -> Vec<char> {
@@ -29,6 +33,23 @@ fn tokens_to_mode_chars(tokens: &Option<Vec<Token>>) -> Vec<char> {
         .unwrap_or_default()
 }
 
+/// Splits `[[a] [b]]` into one command string per inner list, or `[a]` into a single command
+fn command_strings(cmds: &[Token]) -> Vec<String> {
+    if cmds.iter().all(|t| matches!(t, Token::List(_))) {
+        cmds.iter()
+            .filter_map(|t| {
+                if let Token::List(items) = t {
+                    Some(tokens_to_command_string(items))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    } else {
+        vec![tokens_to_command_string(cmds)]
+    }
+}
+
 fn tokens_to_strings(tokens: &[Token]) -> Vec<String> {
     tokens
         .iter()
@@ -42,7 +63,27 @@ fn tokens_to_strings(tokens: &[Token]) -> Vec<String> {
         .collect()
 }
 
+const THEME_PREVIEW_PATH: &str = "<theme>";
+const THEME_PREVIEW_NS: &str = "core::theme_preview";
+
+/// Replaces the text of the generated `<theme>` buffer with `preview`
+fn fill_theme_preview(buf: &mut TextBuffer, preview: ThemePreview) {
+    buf.set_generated_text(&preview.text);
+
+    let marks = preview
+        .highlights
+        .into_iter()
+        .map(|(range, style)| {
+            ExtmarkBuilder::new_range(THEME_PREVIEW_NS, range)
+                .with_kind(ExtmarkKind::Highlight { style })
+        })
+        .collect();
+    let version = *buf.version();
+    buf.renderer.set_namespace(version, THEME_PREVIEW_NS, marks);
+}
+
 #[derive(Debug, Clone, Command)]
+#[command(category = "config")]
 pub enum ConfigCommand {
     /// Bind a key sequence to one or more commands.
     #[command(drop_ident, name = "bind")]
@@ -74,6 +115,15 @@ pub enum ConfigCommand {
         desc: Option<String>,
     },
 
+    /// Define a command that runs one or more commands in order (e.g. `alias wq [[w] [q]]`).
+    /// Aliases take priority over registered commands and can't expand into themselves.
+    #[command(drop_ident, name = "alias")]
+    Alias {
+        name: String,
+        #[command(ignore)]
+        cmds: Vec<Token>,
+    },
+
     /// Register a template expansion.
     #[command]
     Template { name: String, value: Token },
@@ -104,6 +154,8 @@ pub enum ConfigCommand {
     },
 
     /// Register a command prefix for a set of modes.
+    /// `--match` sets how the `include`/`exclude` names are matched: `exact` (default),
+    /// `prefix`, or `glob` (e.g. `lsp-*`).
     #[command(drop_ident, name = "prefix")]
     Prefix {
         cmd: String,
@@ -113,6 +165,8 @@ pub enum ConfigCommand {
         include: Option<Vec<Token>>,
         #[command(flag)]
         exclude: Option<Vec<Token>>,
+        #[command(flag, name = "match", type_name = "string?")]
+        match_kind: Option<String>,
     },
 
     /// Set a core editor setting (e.g. `core framerate 60`).
@@ -155,10 +209,30 @@ pub enum ConfigCommand {
     #[command(drop_ident, name = "reload_config")]
     ReloadConfig,
 
+    /// Open the `<theme>` buffer, listing every theme entry drawn in its own style next to
+    /// the colors it resolved to. Entries that style nothing or name unknown colors get a `!`
+    #[command(drop_ident, name = "theme_preview")]
+    ThemePreview,
+
+    /// Reload the config like `reload_config` to pick up theme changes, refreshing an open
+    /// `<theme>` buffer
+    #[command(drop_ident, name = "theme_reload")]
+    ThemeReload,
+
     /// Bind a command to a mouse event.
     /// Valid event names: left-down, left-up, right-down, right-up, middle, scroll-up, scroll-down
     #[command(drop_ident, name = "mouse_bind")]
     MouseBind { event: String, #[command(ignore)] cmds: Vec<Token> },
+
+    /// Drop every key and mouse bind registered so far, e.g. after sourcing the defaults
+    /// to build a custom modal scheme from scratch.
+    #[command(drop_ident, name = "clear_binds")]
+    ClearBinds,
+
+    /// Remove a registered command (by any of its names) from parsing and the palette,
+    /// freeing the name for an `alias`. `reload_config` brings it back.
+    #[command(drop_ident, name = "unregister")]
+    Unregister { name: String },
 }
 
 #[async_trait::async_trait]
@@ -174,24 +248,22 @@ impl Command<State> for ConfigCommand {
                 deny_repeat,
                 desc,
             } => {
-                let key_binds = parse_key_tokens(keys);
+                let leader = state.lock_state::<CoreConfig>().await.leader.clone();
+                let key_binds = match parse_key_tokens(keys, &leader) {
+                    Ok(k) => k,
+                    Err(e) => {
+                        state
+                            .lock_state::<LogSender>()
+                            .await
+                            .critical("commands::bind", format!("Invalid keys: {e}"));
+                        return false;
+                    }
+                };
                 let mode_chars = tokens_to_mode_chars(modes);
                 let invalid_chars = tokens_to_mode_chars(invalid);
                 let required_tpls = required.clone().unwrap_or_default();
 
-                let commands: Vec<String> = if cmds.iter().all(|t| matches!(t, Token::List(_))) {
-                    cmds.iter()
-                        .filter_map(|t| {
-                            if let Token::List(items) = t {
-                                Some(tokens_to_command_string(items))
-                            } else {
-                                None
-                            }
-                        })
-                        .collect()
-                } else {
-                    vec![tokens_to_command_string(cmds)]
-                };
+                let commands = command_strings(cmds);
 
                 let metadata = Metadata {
                     modes: mode_chars,
@@ -204,10 +276,7 @@ impl Command<State> for ConfigCommand {
                 let resolver_engine = resolver_engine().await;
                 let resolver = resolver_engine.as_resolver();
                 let mut inputs = state.lock_state::<InputState>().await;
-                if let Err(e) = inputs
-                    .tree
-                    .register(&resolver, key_binds, commands, Some(metadata))
-                {
+                if let Err(e) = inputs.bind(&resolver, key_binds, commands, metadata) {
                     tracing::error!("bind: failed to register keybind: {:?}", e);
                 }
             }
@@ -218,7 +287,17 @@ impl Command<State> for ConfigCommand {
                 invalid,
                 desc,
             } => {
-                let key_binds = parse_key_tokens(keys);
+                let leader = state.lock_state::<CoreConfig>().await.leader.clone();
+                let key_binds = match parse_key_tokens(keys, &leader) {
+                    Ok(k) => k,
+                    Err(e) => {
+                        state
+                            .lock_state::<LogSender>()
+                            .await
+                            .critical("commands::category", format!("Invalid keys: {e}"));
+                        return false;
+                    }
+                };
                 let metadata = Metadata {
                     modes: tokens_to_mode_chars(modes),
                     invalid_modes: tokens_to_mode_chars(invalid),
@@ -233,6 +312,14 @@ impl Command<State> for ConfigCommand {
                 }
             }
 
+            ConfigCommand::Alias { name, cmds } => {
+                let commands = command_strings(cmds);
+                let mut aliases = state.lock_state::<CommandAliasRegistry>().await;
+                if let Err(e) = aliases.register(name, commands) {
+                    state.lock_state::<LogSender>().await.critical("commands::alias", e);
+                }
+            }
+
             ConfigCommand::Template { name, value } => {
                 let items = match value {
                     Token::List(items) => items.clone(),
@@ -325,11 +412,18 @@ impl Command<State> for ConfigCommand {
                         )
                     };
 
+                let mut unresolved: Vec<String> = [eff_fg, eff_bg, eff_ul]
+                    .into_iter()
+                    .flatten()
+                    .filter(|c| resolve_color(c, &palette).is_none())
+                    .map(str::to_string)
+                    .collect();
+                unresolved.extend(eff_attrs.iter().filter(|a| attr_from_str(a).is_none()).cloned());
+
                 let style = build_style(eff_fg, eff_bg, eff_ul, &eff_attrs, &palette);
-                state
-                    .lock_state::<Theme>()
-                    .await
-                    .register(key.clone(), style);
+                let mut theme = state.lock_state::<Theme>().await;
+                theme.register(key.clone(), style);
+                theme.mark_unresolved(key.clone(), unresolved);
             }
 
             ConfigCommand::Prefix {
@@ -337,7 +431,20 @@ impl Command<State> for ConfigCommand {
                 modes,
                 include,
                 exclude,
+                match_kind,
             } => {
+                let match_kind = match match_kind.as_deref().map(str::parse::<PrefixMatch>) {
+                    Some(Ok(kind)) => kind,
+                    Some(Err(e)) => {
+                        state
+                            .lock_state::<LogSender>()
+                            .await
+                            .critical("commands::prefix", e);
+                        return false;
+                    }
+                    None => PrefixMatch::default(),
+                };
+
                 let mode_chars: Vec<char> = modes
                     .iter()
                     .filter_map(|t| {
@@ -365,6 +472,7 @@ impl Command<State> for ConfigCommand {
                         prefix_cmd: cmd.clone(),
                         include: include_bool,
                         list,
+                        match_kind,
                     });
             }
 
@@ -374,6 +482,11 @@ impl Command<State> for ConfigCommand {
                         state.lock_state::<CoreConfig>().await.framerate = n;
                     }
                 }
+                "idle_framerate" => {
+                    if let Ok(n) = value.parse::<u64>() {
+                        state.lock_state::<CoreConfig>().await.idle_framerate = n;
+                    }
+                }
                 "auto_pairs" => match value.as_str() {
                     "enable" => {
                         state.lock_state::<CoreConfig>().await.disable_auto_pairs = false;
@@ -408,6 +521,217 @@ impl Command<State> for ConfigCommand {
                         state.lock_state::<CoreConfig>().await.default_tab_unit = n;
                     }
                 }
+                "ambiguous_key_timeout" => {
+                    if let Ok(n) = value.parse::<u64>() {
+                        state.lock_state::<CoreConfig>().await.ambiguous_key_timeout_ms = n;
+                    }
+                }
+                "scrolloff" => {
+                    if let Ok(n) = value.parse::<usize>() {
+                        state.lock_state::<CoreConfig>().await.scrolloff = n;
+                    }
+                }
+                "sidescrolloff" => {
+                    if let Ok(n) = value.parse::<usize>() {
+                        state.lock_state::<CoreConfig>().await.sidescrolloff = n;
+                    }
+                }
+                "smooth_scroll" => match value.as_str() {
+                    "enable" => {
+                        state.lock_state::<CoreConfig>().await.smooth_scroll = true;
+                    }
+                    "disable" => {
+                        state.lock_state::<CoreConfig>().await.smooth_scroll = false;
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected `enable` or `disable`, found: {}", value),
+                        );
+                    }
+                },
+                "smooth_scroll_steps" => {
+                    if let Ok(n) = value.parse::<usize>() {
+                        state.lock_state::<CoreConfig>().await.smooth_scroll_steps = n;
+                    }
+                }
+                "big_file_threshold" => {
+                    if let Ok(n) = value.parse::<u64>() {
+                        state.lock_state::<CoreConfig>().await.big_file_threshold = n;
+                    }
+                }
+                "log_level" => match value.parse::<tracing::level_filters::LevelFilter>() {
+                    Ok(level) => {
+                        if !crate::set_log_level(level) {
+                            state.lock_state::<LogSender>().await.medium(
+                                "commands::core",
+                                "Log level was set by `--log-level` or `KERBIN_LOG`, ignoring",
+                            );
+                        }
+                    }
+                    Err(_) => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected a log level (trace|debug|info|warn|error|off), found: {value}"),
+                        );
+                    }
+                },
+                "leader" => match value.parse::<UnresolvedKeyBind>() {
+                    Ok(_) => state.lock_state::<CoreConfig>().await.leader = value.to_string(),
+                    Err(e) => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected a key for the leader, found `{value}`: {e}"),
+                        );
+                    }
+                },
+                "wrap" => match value.as_str() {
+                    "enable" => {
+                        state.lock_state::<CoreConfig>().await.wrap = true;
+                    }
+                    "disable" => {
+                        state.lock_state::<CoreConfig>().await.wrap = false;
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected `enable` or `disable`, found: {}", value),
+                        );
+                    }
+                },
+                "list" => match value.as_str() {
+                    "enable" => {
+                        state.lock_state::<CoreConfig>().await.list = true;
+                    }
+                    "disable" => {
+                        state.lock_state::<CoreConfig>().await.list = false;
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected `enable` or `disable`, found: {}", value),
+                        );
+                    }
+                },
+                "cursorline" => match value.as_str() {
+                    "enable" => {
+                        state.lock_state::<CoreConfig>().await.cursorline = true;
+                    }
+                    "disable" => {
+                        state.lock_state::<CoreConfig>().await.cursorline = false;
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected `enable` or `disable`, found: {}", value),
+                        );
+                    }
+                },
+                "yank_flash" => match value.as_str() {
+                    "enable" => {
+                        state.lock_state::<CoreConfig>().await.yank_flash = true;
+                    }
+                    "disable" => {
+                        state.lock_state::<CoreConfig>().await.yank_flash = false;
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected `enable` or `disable`, found: {}", value),
+                        );
+                    }
+                },
+                "yank_flash_ms" => {
+                    if let Ok(n) = value.parse::<u64>() {
+                        state.lock_state::<CoreConfig>().await.yank_flash_ms = n;
+                    }
+                }
+                "join_separator" => {
+                    state.lock_state::<CoreConfig>().await.join_separator = value.to_string();
+                }
+                "join_no_space_before" => {
+                    state.lock_state::<CoreConfig>().await.join_no_space_before = value.to_string();
+                }
+                "autosave_ms" => {
+                    if let Ok(n) = value.parse::<u64>() {
+                        state.lock_state::<CoreConfig>().await.autosave_ms = n;
+                    }
+                }
+                "autosave_changes" => {
+                    if let Ok(n) = value.parse::<usize>() {
+                        state.lock_state::<CoreConfig>().await.autosave_changes = n;
+                    }
+                }
+                "backup" => match value.as_str() {
+                    "enable" => {
+                        state.lock_state::<CoreConfig>().await.backup = true;
+                    }
+                    "disable" => {
+                        state.lock_state::<CoreConfig>().await.backup = false;
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected `enable` or `disable`, found: {}", value),
+                        );
+                    }
+                },
+                "backup_ms" => {
+                    if let Ok(n) = value.parse::<u64>() {
+                        state.lock_state::<CoreConfig>().await.backup_ms = n;
+                    }
+                }
+                "default_mouse_binds" => match value.as_str() {
+                    "enable" => {
+                        state.lock_state::<CoreConfig>().await.default_mouse_binds = true;
+                    }
+                    "disable" => {
+                        state.lock_state::<CoreConfig>().await.default_mouse_binds = false;
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected `enable` or `disable`, found: {}", value),
+                        );
+                    }
+                },
+                "cursorcolumn" => match value.as_str() {
+                    "enable" => {
+                        state.lock_state::<CoreConfig>().await.cursorcolumn = true;
+                    }
+                    "disable" => {
+                        state.lock_state::<CoreConfig>().await.cursorcolumn = false;
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected `enable` or `disable`, found: {}", value),
+                        );
+                    }
+                },
+                "wrap_indicator" => {
+                    state.lock_state::<CoreConfig>().await.wrap_indicator = value.to_string();
+                }
+                "line_numbers" => match value.parse::<LineNumbers>() {
+                    Ok(numbers) => state.lock_state::<CoreConfig>().await.line_numbers = numbers,
+                    Err(e) => {
+                        state.lock_state::<LogSender>().await.critical("commands::core", e);
+                    }
+                },
+                "which_key" => match value.as_str() {
+                    "enable" => {
+                        state.lock_state::<CoreConfig>().await.which_key = true;
+                    }
+                    "disable" => {
+                        state.lock_state::<CoreConfig>().await.which_key = false;
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.critical(
+                            "commands::core",
+                            format!("Expected `enable` or `disable`, found: {}", value),
+                        );
+                    }
+                },
                 "unique_split_buffers" => match value.as_str() {
                     "enable" => {
                         state.lock_state::<SplitState>().await.unique_buffers = true;
@@ -526,25 +850,82 @@ impl Command<State> for ConfigCommand {
                     .insert(trigger, commands);
             }
 
-            ConfigCommand::ReloadConfig => {
-                let config_path = state.lock_state::<ConfigFolder>().await.0.clone();
-                let kb_path = std::path::PathBuf::from(format!("{config_path}/init.kb"));
-
-                crate::reset_config_state(state).await;
-
-                let errors = crate::load_kb(&kb_path, state).await;
-
-                // Mirror the startup auto_pairs default logic (auto_pairs is on by default).
-                let disable_auto_pairs = state.lock_state::<CoreConfig>().await.disable_auto_pairs;
-                if !disable_auto_pairs {
-                    let mut registry = state.lock_state::<CommandInterceptorRegistry>().await;
-                    registry.remove_command_interceptor::<BufferCommand>("core::auto_pairs");
-                    registry.on_command_named::<BufferCommand>(
-                        "core::auto_pairs",
-                        0,
-                        |cmd, state| Box::pin(auto_pairs_intercept(cmd, state)),
+            ConfigCommand::ClearBinds => {
+                let mut input = state.lock_state::<InputState>().await;
+                input.tree = Default::default();
+                input.bindings.clear();
+                drop(input);
+                state.lock_state::<MouseBindings>().await.bindings.clear();
+            }
+
+            ConfigCommand::Unregister { name } => {
+                if !state.lock_state::<CommandRegistry>().await.unregister(name) {
+                    state.lock_state::<LogSender>().await.critical(
+                        "commands::unregister",
+                        format!("No command named `{name}` is registered"),
                     );
                 }
+            }
+
+            ConfigCommand::ThemePreview => {
+                let preview = state.lock_state::<Theme>().await.preview();
+                let mut buffers = state.lock_state::<Buffers>().await;
+
+                // Refresh an open preview in place rather than opening another
+                let mut open = None;
+                for (i, buf) in buffers.buffers.iter().enumerate() {
+                    let guard = buf.read().await;
+                    if let Some(text) = guard.downcast::<TextBuffer>()
+                        && text.path == THEME_PREVIEW_PATH
+                    {
+                        open = Some(i);
+                        break;
+                    }
+                }
+
+                match open {
+                    Some(i) => {
+                        if let Some(text) =
+                            buffers.buffers[i].write().await.downcast_mut::<TextBuffer>()
+                        {
+                            fill_theme_preview(text, preview);
+                        }
+                        buffers.set_selected_buffer(i);
+                    }
+                    None => {
+                        let mut buf = TextBuffer::generated(THEME_PREVIEW_PATH, "");
+                        fill_theme_preview(&mut buf, preview);
+                        buffers.push_buffer(buf).await;
+                    }
+                }
+            }
+
+            ConfigCommand::ThemeReload => {
+                let errors = crate::reload_config(state).await;
+                *state.lock_state::<ConfigErrors>().await = ConfigErrors(errors.clone());
+
+                let theme = state.lock_state::<Theme>().await;
+                let message = format!(
+                    "Theme reloaded with {} entries and {} config error(s)",
+                    theme.keys().len(),
+                    errors.len()
+                );
+
+                for buf in &state.lock_state::<Buffers>().await.buffers {
+                    let mut guard = buf.write().await;
+                    if let Some(text) = guard.downcast_mut::<TextBuffer>()
+                        && text.path == THEME_PREVIEW_PATH
+                    {
+                        fill_theme_preview(text, theme.preview());
+                    }
+                }
+                drop(theme);
+
+                state.lock_state::<LogSender>().await.medium("config", message);
+            }
+
+            ConfigCommand::ReloadConfig => {
+                let errors = crate::reload_config(state).await;
 
                 *state.lock_state::<ConfigErrors>().await = ConfigErrors(errors.clone());
                 let log = state.lock_state::<LogSender>().await;
@@ -564,3 +945,93 @@ impl Command<State> for ConfigCommand {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_leader_in_keys() {
+        let keys = |s: &str| parse_key_tokens(&tokenize(s).unwrap(), "space").unwrap();
+
+        assert_eq!(keys("<leader>w").len(), 2);
+        assert_eq!(keys("<leader>w"), keys("space w"));
+        assert_eq!(keys("<leader> f f"), keys("space f f"));
+        assert_eq!(keys("<C-w>h"), keys("ctrl-w h"));
+        assert_eq!(
+            parse_key_tokens(&tokenize("<leader>w").unwrap(), "ctrl-x").unwrap(),
+            keys("ctrl-x w")
+        );
+        assert!(parse_key_tokens(&tokenize("<C-nope>").unwrap(), "space").is_err());
+    }
+
+    #[tokio::test]
+    async fn core_sets_autosave_and_backup() {
+        let (_, log_sender) = LogState::new_with_channel();
+        let mut state = State::new();
+        state.state(CoreConfig::default()).state(log_sender);
+
+        let set = |key: &str, value: &str| ConfigCommand::Core {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        set("autosave_ms", "1500").apply(&mut state).await;
+        set("autosave_changes", "20").apply(&mut state).await;
+        set("backup", "enable").apply(&mut state).await;
+        set("backup_ms", "5000").apply(&mut state).await;
+
+        let config = state.lock_state::<CoreConfig>().await;
+        assert_eq!((config.autosave_ms, config.autosave_changes), (1500, 20));
+        assert!(config.backup);
+        assert_eq!(config.backup_ms, 5000);
+    }
+
+    #[tokio::test]
+    async fn core_sets_join_separators() {
+        let (_, log_sender) = LogState::new_with_channel();
+        let mut state = State::new();
+        state.state(CoreConfig::default()).state(log_sender);
+
+        let set = |key: &str, value: &str| ConfigCommand::Core {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        set("join_separator", ", ").apply(&mut state).await;
+        set("join_no_space_before", ")").apply(&mut state).await;
+
+        let config = state.lock_state::<CoreConfig>().await;
+        assert_eq!(config.join_separator, ", ");
+        assert_eq!(config.join_no_space_before, ")");
+    }
+
+    #[tokio::test]
+    async fn theme_preview_opens_a_read_only_listing() {
+        let mut theme = Theme::default();
+        theme.register("ui.text".to_string(), ratatui::style::Style::new().white());
+
+        let mut state = State::new();
+        state.state(theme).state(Buffers::default());
+
+        ConfigCommand::ThemePreview.apply(&mut state).await;
+        let buffers = state.lock_state::<Buffers>().await;
+        let buf = buffers.cur_text_buffer().await.unwrap();
+        assert_eq!(buf.path, THEME_PREVIEW_PATH);
+        assert_eq!(buf.to_string(), "# ui\n  ui.text  fg white\n");
+        assert!(buf.is_readonly() && !buf.dirty && buf.undo_stack.is_empty());
+        drop((buf, buffers));
+
+        // Running it again refreshes and selects the open preview
+        state.lock_state::<Buffers>().await.set_selected_buffer(0);
+        state
+            .lock_state::<Theme>()
+            .await
+            .register("ui.text".to_string(), ratatui::style::Style::new().red());
+        ConfigCommand::ThemePreview.apply(&mut state).await;
+
+        let buffers = state.lock_state::<Buffers>().await;
+        assert_eq!(buffers.buffers.len(), 2);
+        assert_eq!(buffers.selected_buffer, 1);
+        let buf = buffers.cur_text_buffer().await.unwrap();
+        assert_eq!(buf.to_string(), "# ui\n  ui.text  fg red\n");
+    }
+}
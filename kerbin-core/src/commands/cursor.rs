@@ -1,6 +1,7 @@
 use crate::*;
 
 #[derive(Clone, Command)]
+#[command(category = "cursor")]
 pub enum CursorCommand {
     #[command(name = "cc")]
     /// Duplicates the primary cursor and sets it as the new primary.
@@ -21,6 +22,21 @@ pub enum CursorCommand {
     /// Clears all cursors except the primary cursor.
     DropOtherCursors,
 
+    #[command(drop_ident, name = "select_next")]
+    /// Adds a cursor selecting the next occurrence of the primary selection's text,
+    /// wrapping around the end of the buffer. A single-char selection selects the word
+    /// under the caret first. `--skip` moves the primary cursor to the occurrence instead
+    SelectNext {
+        #[command(flag)]
+        skip: bool,
+    },
+
+    #[command(drop_ident, name = "block_select", name = "bs")]
+    /// Splits the primary selection into a column block, with one cursor per line
+    /// covering the columns between its anchor and caret. Lines ending before the block
+    /// are skipped.
+    BlockSelect,
+
     #[command(drop_ident, name = "apply_all_cursor", name = "aa")]
     /// Applies a command to every cursor, emulating a true multicursor environment.
     ApplyAll(#[command(name = "cmd", type_name = "[command]", ignore)] Vec<Token>),
@@ -64,6 +80,27 @@ impl Command<State> for CursorCommand {
                 true
             }
 
+            Self::SelectNext { skip } => {
+                let Some(mut tb) = cur_bufs.cur_text_buffer_mut().await else {
+                    return false;
+                };
+                tb.select_next_occurrence(*skip)
+            }
+
+            Self::BlockSelect => {
+                let tab_w = state
+                    .lock_state::<CoreConfig>()
+                    .await
+                    .tab_display_unit
+                    .chars()
+                    .count();
+                let Some(mut tb) = cur_bufs.cur_text_buffer_mut().await else {
+                    return false;
+                };
+                tb.block_select(tab_w);
+                true
+            }
+
             Self::ApplyAll(cmd) => {
                 let (primary_cursor, cursor_count) = {
                     match cur_bufs.cur_buffer_as::<TextBuffer>().await {
@@ -1,6 +1,7 @@
 use crate::*;
 
 #[derive(Command)]
+#[command(category = "debug")]
 pub enum DebugCommand {
     #[command]
     /// Outputs text/templates as raw text to the screen.
@@ -13,12 +14,43 @@ pub enum DebugCommand {
         #[command(flag)]
         level: Option<String>,
     },
+
+    #[command(drop_ident, name = "log-level")]
+    /// Hides on-screen messages below the given level.
+    ///
+    /// `level` can be: `low`|`medium`|`high`|`critical`
+    LogLevel(#[command(name = "level")] String),
+
+    #[command(drop_ident, name = "log-filter")]
+    /// Only shows on-screen messages whose source starts with `source`, like `lsp` or `lsp::*`.
+    /// Without a source, shows messages from every source again.
+    LogFilter(#[command(type_name = "String?", name = "source")] Option<String>),
+
+    #[command(drop_ident, name = "buffer-states")]
+    /// Logs the names of every state attached to the current buffer
+    BufferStates,
 }
 
 #[async_trait::async_trait]
 impl Command<State> for DebugCommand {
     async fn apply(&self, state: &mut State) -> bool {
         match self {
+            Self::LogLevel(level) => match level.parse::<Level>() {
+                Ok(level) => {
+                    state.lock_state::<LogFilter>().await.level = level;
+                    true
+                }
+                Err(e) => {
+                    state.lock_state::<LogSender>().await.high("command::log_level", e);
+                    false
+                }
+            },
+
+            Self::LogFilter(source) => {
+                state.lock_state::<LogFilter>().await.source = source.clone();
+                true
+            }
+
             Self::Echo { text, level } => {
                 let text = text.join(" ");
                 let log = state.lock_state::<LogSender>().await;
@@ -36,6 +68,28 @@ impl Command<State> for DebugCommand {
 
                 true
             }
+
+            Self::BufferStates => {
+                let bufs = state.lock_state::<Buffers>().await;
+                let log = state.lock_state::<LogSender>().await;
+
+                let Some(buf) = bufs.cur_text_buffer().await else {
+                    log.medium("command::buffer_states", "Current buffer is not a text buffer");
+                    return false;
+                };
+
+                let names = buf.state_names();
+                if names.is_empty() {
+                    log.low("command::buffer_states", format!("{} has no states", buf.path));
+                } else {
+                    log.low(
+                        "command::buffer_states",
+                        format!("{} states:\n{}", buf.path, names.join("\n")),
+                    );
+                }
+
+                true
+            }
         }
     }
 }
@@ -1,6 +1,7 @@
 use crate::*;
 
 #[derive(Command)]
+#[command(category = "dialogue")]
 pub enum DialogueCommand {
     #[command]
     /// Opens a modal input dialogue with a title, description, and on-submit commands.
@@ -11,6 +12,7 @@ pub enum DialogueCommand {
     /// `--var` names the resolver template variable set to the user's input on submit.
     /// `--commands` is a list of commands to execute on submit (each element is a [command] list).
     /// `--on-change` is an optional list of commands to execute on each input change.
+    /// `--on-cancel` is an optional list of commands to execute when the dialogue is cancelled.
     Dialogue {
         #[command(flag)]
         title: String,
@@ -24,6 +26,8 @@ pub enum DialogueCommand {
         commands: Vec<Token>,
         #[command(flag, name = "on-change", type_name = "[command_list]", ignore)]
         on_change: Option<Vec<Token>>,
+        #[command(flag, name = "on-cancel", type_name = "[command_list]", ignore)]
+        on_cancel: Option<Vec<Token>>,
     },
 
     #[command]
@@ -39,7 +43,7 @@ pub enum DialogueCommand {
     DialogueSubmit,
 
     #[command]
-    /// Cancels the dialogue without executing any commands
+    /// Cancels the dialogue, running only its on-cancel commands
     DialogueCancel,
 }
 
@@ -54,6 +58,7 @@ impl Command<State> for DialogueCommand {
                 var,
                 commands,
                 on_change,
+                on_cancel,
             } => {
                 let ik = match input_kind.as_str() {
                     "cmd" => InputKind::Cmd,
@@ -69,11 +74,12 @@ impl Command<State> for DialogueCommand {
                 dialogue.var_name = var.clone();
                 dialogue.commands = commands.clone();
                 dialogue.on_change = on_change.clone().unwrap_or_default();
+                dialogue.on_cancel = on_cancel.clone().unwrap_or_default();
                 dialogue.input.clear();
                 dialogue.input_valid = true;
                 drop(dialogue);
 
-                state.lock_state::<ModeStack>().await.push_mode('d');
+                state.lock_state::<ModeStack>().await.push_mode('d').await;
                 true
             }
 
@@ -141,7 +147,7 @@ impl Command<State> for DialogueCommand {
                     dialogue.input.clear();
                 }
 
-                state.lock_state::<ModeStack>().await.pop_mode();
+                state.lock_state::<ModeStack>().await.pop_mode().await;
 
                 resolver_engine_mut().await.set_template(&var_name, &input);
 
@@ -168,14 +174,21 @@ impl Command<State> for DialogueCommand {
             }
 
             Self::DialogueCancel => {
-                let mut dialogue = state.lock_state::<DialogueState>().await;
-                if !dialogue.active {
-                    return false;
-                }
-                dialogue.active = false;
-                dialogue.input.clear();
+                let (on_cancel, var_name, input) = {
+                    let mut dialogue = state.lock_state::<DialogueState>().await;
+                    if !dialogue.active {
+                        return false;
+                    }
+                    dialogue.active = false;
+                    (
+                        std::mem::take(&mut dialogue.on_cancel),
+                        dialogue.var_name.clone(),
+                        std::mem::take(&mut dialogue.input),
+                    )
+                };
 
-                state.lock_state::<ModeStack>().await.pop_mode();
+                state.lock_state::<ModeStack>().await.pop_mode().await;
+                run_dialogue_on_change(state, &on_cancel, &var_name, &input).await;
                 true
             }
         }
@@ -1,6 +1,7 @@
 use crate::*;
 
 #[derive(Command)]
+#[command(category = "config")]
 pub enum IfCommand {
     #[command]
     /// Executes commands only if the given check passes.
@@ -1,6 +1,7 @@
 use crate::*;
 
 #[derive(Command)]
+#[command(category = "input")]
 pub enum InputCommand {
     /// Pushes the digits onto the repeat string for repeating input commands
     #[command(name = "p_rep")]
@@ -0,0 +1,111 @@
+use crate::*;
+
+#[derive(Command)]
+#[command(category = "navigation")]
+pub enum JumpCommand {
+    #[command(drop_ident, name = "jump-push", name = "jp")]
+    /// Records the cursor position in the jump list.
+    /// Bind this before motions that should count as jumps.
+    Push,
+
+    #[command(drop_ident, name = "jump-back", name = "jb")]
+    /// Returns to the previous position in the jump list, reopening its buffer if needed
+    Back,
+
+    #[command(drop_ident, name = "jump-forward", name = "jf")]
+    /// Moves forward through the jump list after `jump-back`
+    Forward,
+}
+
+#[async_trait::async_trait]
+impl Command<State> for JumpCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        match self {
+            Self::Push => {
+                record_jump(state).await;
+                false
+            }
+
+            Self::Back => {
+                let Some(current) = current_jump(state).await else {
+                    return false;
+                };
+                let Some(target) = state.lock_state::<JumpList>().await.back(current) else {
+                    return false;
+                };
+                restore_jump(state, target).await
+            }
+
+            Self::Forward => {
+                let Some(target) = state.lock_state::<JumpList>().await.forward() else {
+                    return false;
+                };
+                restore_jump(state, target).await
+            }
+        }
+    }
+}
+
+async fn current_jump(state: &State) -> Option<Jump> {
+    let buffers = state.lock_state::<Buffers>().await;
+    let buf = buffers.cur_text_buffer().await?;
+    Some(Jump::from_buffer(&buf))
+}
+
+/// Pushes the primary cursor's position onto the jump list.
+/// Call this before moving the cursor somewhere far away.
+pub async fn record_jump(state: &State) {
+    if let Some(jump) = current_jump(state).await {
+        state.lock_state::<JumpList>().await.push(jump);
+    }
+}
+
+/// Focuses the jump's buffer, opening it again if it was closed, and moves the cursor
+/// to its position, clamped to the buffer's current length
+pub(crate) async fn restore_jump(state: &State, jump: Jump) -> bool {
+    let (default_tab_unit, big_file_threshold) = {
+        let config = state.lock_state::<CoreConfig>().await;
+        (config.default_tab_unit, config.big_file_threshold)
+    };
+    let mut buffers = state.lock_state::<Buffers>().await;
+
+    let mut found = None;
+    for (i, buf) in buffers.buffers.iter().enumerate() {
+        if buf.read().await.title() == jump.path {
+            found = Some(i);
+            break;
+        }
+    }
+
+    let buffer_id = match found {
+        Some(i) => i,
+        // Special buffers like `<scratch>` can't be reopened from disk
+        None if jump.path.starts_with('<') && jump.path.ends_with('>') => return false,
+        None => match buffers
+            .open(jump.path.clone(), default_tab_unit, big_file_threshold)
+            .await
+        {
+            Ok(i) => i,
+            Err(e) => {
+                state.lock_state::<LogSender>().await.high(
+                    "command::jump",
+                    format!("Failed to reopen {}: {e}", jump.path),
+                );
+                return false;
+            }
+        },
+    };
+
+    buffers.set_selected_buffer(buffer_id);
+    track_in_focused_pane(state, buffer_id).await;
+
+    let Some(mut buf) = buffers.cur_text_buffer_mut().await else {
+        return false;
+    };
+    let byte = jump.byte.min(buf.len());
+    let cursor = buf.primary_cursor_mut();
+    cursor.set_sel(byte..=byte);
+    cursor.set_at_start(false);
+
+    true
+}
@@ -14,6 +14,7 @@ fn tokens_to_strings(tokens: &[Token]) -> Vec<String> {
 }
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "language")]
 pub enum RegisterLanguageCommand {
     /// Register a language name and the file patterns that detect it.
     #[command(drop_ident, name = "register_language")]
@@ -25,6 +26,27 @@ pub enum RegisterLanguageCommand {
         filenames: Option<Vec<Token>>,
         #[command(flag)]
         regex: Option<String>,
+        /// Line comment token, as a list so tokens like `--` aren't read as flags
+        #[command(flag)]
+        comment: Option<Vec<Token>>,
+    },
+
+    /// Sets the profile of a filetype's buffers, merged into any set before.
+    /// `--tabstop` and `--expandtab` pick the indent used when the file has none to
+    /// detect, `--comment` replaces the line comment token. `--iskeyword` lists the
+    /// characters besides letters and digits that word motions and `iw` keep in a word
+    /// (`_` when unset). Binds can be limited to the filetype with `--required [ft_<name>]`
+    #[command(drop_ident, name = "filetype")]
+    Filetype {
+        name: String,
+        #[command(flag)]
+        tabstop: Option<usize>,
+        #[command(flag)]
+        expandtab: Option<bool>,
+        #[command(flag)]
+        comment: Option<Vec<Token>>,
+        #[command(flag)]
+        iskeyword: Option<String>,
     },
 }
 
@@ -37,6 +59,7 @@ impl Command<State> for RegisterLanguageCommand {
                 exts,
                 filenames,
                 regex,
+                comment,
             } => {
                 let ext_strings = exts.as_deref().map(tokens_to_strings).unwrap_or_default();
                 let filename_strings = filenames
@@ -54,6 +77,37 @@ impl Command<State> for RegisterLanguageCommand {
                 if let Some(pattern) = regex {
                     registry.register_first_line(pattern, name);
                 }
+                let comment = comment.as_deref().map(tokens_to_strings).unwrap_or_default();
+                if let Some(token) = comment.into_iter().next() {
+                    registry.register_comment(name, token);
+                }
+            }
+
+            Self::Filetype {
+                name,
+                tabstop,
+                expandtab,
+                comment,
+                iskeyword,
+            } => {
+                let profile = FiletypeProfile {
+                    tabstop: *tabstop,
+                    expandtab: *expandtab,
+                    iskeyword: iskeyword.clone(),
+                };
+                state
+                    .lock_state::<FiletypeConfig>()
+                    .await
+                    .set_profile(name, profile);
+
+                let comment = comment.as_deref().map(tokens_to_strings).unwrap_or_default();
+                if let Some(token) = comment.into_iter().next() {
+                    state
+                        .lock_state::<FiletypeRegistry>()
+                        .await
+                        .comment_tokens
+                        .insert(name.clone(), token);
+                }
             }
         }
         false
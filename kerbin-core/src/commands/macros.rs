@@ -0,0 +1,140 @@
+use crate::*;
+
+#[derive(Command)]
+#[command(category = "registers")]
+pub enum MacroCommand {
+    #[command(drop_ident, name = "macro-record", name = "mrec")]
+    /// Starts recording executed commands into a register.
+    /// Without a register, stops the active recording and stores it.
+    Record(#[command(type_name = "char?", name = "register")] Option<char>),
+
+    #[command(drop_ident, name = "macro-play", name = "mplay")]
+    /// Replays the commands recorded in a register, `count` times (defaults to 1)
+    Play {
+        #[command(type_name = "char")]
+        register: char,
+        #[command(type_name = "usize?", range = "1..")]
+        count: Option<usize>,
+    },
+}
+
+#[async_trait::async_trait]
+impl Command<State> for MacroCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        let log = state.lock_state::<LogSender>().await;
+        let mut macros = state.lock_state::<MacroState>().await;
+
+        match self {
+            Self::Record(Some(register)) => {
+                macros.start(*register);
+                log.low("command::macro_record", format!("Recording macro @{register}"));
+                true
+            }
+
+            Self::Record(None) => {
+                let Some((register, text)) = macros.stop() else {
+                    log.medium("command::macro_record", "No macro is being recorded");
+                    return false;
+                };
+
+                log.low("command::macro_record", format!("Recorded macro @{register}"));
+                state.lock_state::<Registers>().await.set(register, text);
+                true
+            }
+
+            Self::Play { register, count } => {
+                let text = state.lock_state::<Registers>().await.get(register).to_string();
+                if text.is_empty() {
+                    log.medium("command::macro_play", format!("Register @{register} is empty"));
+                    return false;
+                }
+
+                macros.enqueue(&text, count.unwrap_or(1), 0);
+
+                if !macros.replaying {
+                    macros.replaying = true;
+                    let _ = state.lock_state::<CommandSender>().await.send(Box::new(MacroReplay));
+                }
+                true
+            }
+        }
+    }
+}
+
+/// Runs one queued macro command, then re-queues itself behind anything that command sent,
+/// so each step is parsed against the modes left by the previous one.
+pub struct MacroReplay;
+
+impl CommandAny for MacroReplay {
+    fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Command<State> for MacroReplay {
+    async fn apply(&self, state: &mut State) -> bool {
+        let Some((line, depth)) = state.lock_state::<MacroState>().await.next_step() else {
+            state.lock_state::<MacroState>().await.replaying = false;
+            return true;
+        };
+
+        let command = {
+            let resolver_engine = resolver_engine().await;
+            state.lock_state::<CommandRegistry>().await.parse_command(
+                tokenize(&line).unwrap_or_default(),
+                true,
+                false,
+                Some(&resolver_engine.as_resolver()),
+                true,
+                &*state.lock_state::<CommandPrefixRegistry>().await,
+                &*state.lock_state::<ModeStack>().await,
+            )
+        };
+
+        // Only a bad command or runaway nesting stops the replay; `dispatch_command`'s result
+        // doesn't signal failure for every command
+        let (res, abort) = match command {
+            None => {
+                state
+                    .lock_state::<LogSender>()
+                    .await
+                    .high("command::macro_play", format!("Invalid command in macro: {line}"));
+                (false, true)
+            }
+
+            // Nested plays are expanded in place so they finish before the rest of this macro
+            Some(command) => match command.as_any().downcast_ref::<MacroCommand>() {
+                Some(MacroCommand::Play { .. }) if depth + 1 > MAX_MACRO_DEPTH => {
+                    state.lock_state::<LogSender>().await.high(
+                        "command::macro_play",
+                        format!("Macros nested deeper than {MAX_MACRO_DEPTH}, stopping replay"),
+                    );
+                    (false, true)
+                }
+                Some(MacroCommand::Play { register, count }) => {
+                    let text = state.lock_state::<Registers>().await.get(register).to_string();
+                    state
+                        .lock_state::<MacroState>()
+                        .await
+                        .enqueue(&text, count.unwrap_or(1), depth + 1);
+                    (true, false)
+                }
+                _ => (dispatch_command(command.as_ref(), state).await, false),
+            },
+        };
+
+        let mut macros = state.lock_state::<MacroState>().await;
+        if abort {
+            macros.abort();
+        }
+
+        if macros.has_steps() {
+            let _ = state.lock_state::<CommandSender>().await.send(Box::new(MacroReplay));
+        } else {
+            macros.replaying = false;
+        }
+
+        res
+    }
+}
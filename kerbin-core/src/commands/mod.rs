@@ -27,6 +27,12 @@ pub use palette::*;
 mod registers;
 pub use registers::*;
 
+mod macros;
+pub use macros::*;
+
+mod jump;
+pub use jump::*;
+
 mod input;
 pub use input::*;
 
@@ -48,6 +54,18 @@ pub use dialogue::*;
 mod language;
 pub use language::*;
 
+mod search;
+pub use search::*;
+
+mod operator;
+pub use operator::*;
+
+mod picker;
+pub use picker::*;
+
+mod quickfix;
+pub use quickfix::*;
+
 /// Registers all built-in core commands into a `CommandRegistry`.
 /// Plugins may register additional commands on top of these.
 pub fn register_core_commands(registry: &mut CommandRegistry) {
@@ -62,6 +80,8 @@ pub fn register_core_commands(registry: &mut CommandRegistry) {
     registry.register::<MotionCommand>();
     registry.register::<ShellCommand>();
     registry.register::<RegisterCommand>();
+    registry.register::<MacroCommand>();
+    registry.register::<JumpCommand>();
     registry.register::<ConfigCommand>();
     registry.register::<DebugCommand>();
     registry.register::<IfCommand>();
@@ -69,6 +89,9 @@ pub fn register_core_commands(registry: &mut CommandRegistry) {
     registry.register::<SplitCommand>();
     registry.register::<DialogueCommand>();
     registry.register::<RegisterLanguageCommand>();
+    registry.register::<SearchCommand>();
+    registry.register::<OperatorCommand>();
+    registry.register::<QuickfixCommand>();
 }
 
 /// Type alias for a state-specific command parsing function.
@@ -189,7 +212,7 @@ impl CommandInfoRender for CommandInfo {
     }
 }
 
-fn highlight_matches(
+pub(crate) fn highlight_matches(
     text: &str,
     search: &str,
     base_style: Style,
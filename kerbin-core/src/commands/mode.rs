@@ -1,6 +1,7 @@
 use crate::*;
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "mode")]
 pub enum ModeCommand {
     #[command(name = "cm")]
     /// Clears the mode stack and sets it to the given char.
@@ -23,15 +24,15 @@ impl Command<State> for ModeCommand {
         let mut modes = state.lock_state::<ModeStack>().await;
 
         match *self {
-            ModeCommand::ChangeMode(new) => modes.set_mode(new),
+            ModeCommand::ChangeMode(new) => modes.set_mode(new).await,
             ModeCommand::PushMode(new) => {
                 if modes.mode_on_stack(new) {
                     return false;
                 }
-                modes.push_mode(new);
+                modes.push_mode(new).await;
             }
             ModeCommand::PopMode => {
-                modes.pop_mode();
+                modes.pop_mode().await;
             }
         }
 
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use crate::*;
 use regex_cursor::engines::meta::Regex;
 
@@ -19,8 +21,13 @@ fn rope_input(slice: ropey::RopeSlice<'_>) -> regex_cursor::Input<RopeyCursor<'_
     regex_cursor::Input::new(RopeyCursor::new(slice))
 }
 
-fn try_compile_regex(pattern: &str, log: &LogSender) -> Option<Regex> {
-    match Regex::new(pattern) {
+/// Compiles `pattern`, with `\k` standing for the buffer's word characters
+fn try_compile_regex(
+    pattern: &str,
+    word_chars: &WordChars,
+    log: &LogSender,
+) -> Option<Arc<Regex>> {
+    match cached(&word_chars.expand(pattern)) {
         Ok(r) => Some(r),
         Err(e) => {
             log.high("command::motion", format!("Invalid regex: {e}"));
@@ -30,6 +37,7 @@ fn try_compile_regex(pattern: &str, log: &LogSender) -> Option<Regex> {
 }
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "motion")]
 pub enum MotionCommand {
     #[command(name = "rx")]
     /// Selects the first match of the regex in the buffer
@@ -285,7 +293,9 @@ impl Command<State> for MotionCommand {
             }
 
             Self::Regex { pattern, extend } => {
-                let Some(regex) = try_compile_regex(pattern, &log) else { return false; };
+                let Some(regex) = try_compile_regex(pattern, &cur_buffer.word_chars, &log) else {
+                    return false;
+                };
 
                 let len = cur_buffer.len();
                 if let Some(m) = regex.search(rope_input(cur_buffer.slice_clamped(0, len))) {
@@ -302,7 +312,9 @@ impl Command<State> for MotionCommand {
                 extend,
                 advance,
             } => {
-                let Some(regex) = try_compile_regex(pattern, &log) else { return false; };
+                let Some(regex) = try_compile_regex(pattern, &cur_buffer.word_chars, &log) else {
+                    return false;
+                };
 
                 let base_cursor = cur_buffer
                     .primary_cursor()
@@ -353,7 +365,9 @@ impl Command<State> for MotionCommand {
                 extend,
                 advance,
             } => {
-                let Some(regex) = try_compile_regex(pattern, &log) else { return false; };
+                let Some(regex) = try_compile_regex(pattern, &cur_buffer.word_chars, &log) else {
+                    return false;
+                };
 
                 let base_cursor = cur_buffer
                     .primary_cursor()
@@ -368,12 +382,12 @@ impl Command<State> for MotionCommand {
                     loop {
                         if search_ceil == 0 { return false; }
 
-                        let Some(x) = regex.find_iter(rope_input(cur_buffer.slice_clamped(0, search_ceil))).last() else {
+                        let slice = cur_buffer.get_rope().slice(..);
+                        let Some(x) = last_match_before(&regex, slice, search_ceil) else {
                             return false;
                         };
 
-                        let start = x.start();
-                        let end = x.end();
+                        let (start, end) = (x.start, x.end);
 
                         if start != original_start {
                             apply_match_sel(&mut cur_buffer, start, end, *extend);
@@ -384,8 +398,9 @@ impl Command<State> for MotionCommand {
                     }
                 }
 
-                if let Some(m) = regex.find_iter(rope_input(cur_buffer.slice_clamped(0, base_cursor))).last() {
-                    apply_match_sel(&mut cur_buffer, m.start(), m.end(), *extend);
+                let slice = cur_buffer.get_rope().slice(..);
+                if let Some(m) = last_match_before(&regex, slice, base_cursor) {
+                    apply_match_sel(&mut cur_buffer, m.start, m.end, *extend);
                     true
                 } else {
                     false
@@ -393,7 +408,9 @@ impl Command<State> for MotionCommand {
             }
 
             Self::RegexSel { pattern } => {
-                let Some(regex) = try_compile_regex(pattern, &log) else { return false; };
+                let Some(regex) = try_compile_regex(pattern, &cur_buffer.word_chars, &log) else {
+                    return false;
+                };
 
                 let start_idx = *cur_buffer.primary_cursor().sel().start();
                 let end_idx = *cur_buffer.primary_cursor().sel().end() + 1;
@@ -410,7 +427,9 @@ impl Command<State> for MotionCommand {
             }
 
             Self::RegexSelAll { pattern } => {
-                let Some(regex) = try_compile_regex(pattern, &log) else { return false; };
+                let Some(regex) = try_compile_regex(pattern, &cur_buffer.word_chars, &log) else {
+                    return false;
+                };
 
                 let start_idx = *cur_buffer.primary_cursor().sel().start();
                 let end_idx = *cur_buffer.primary_cursor().sel().end() + 1;
@@ -439,3 +458,48 @@ impl Command<State> for MotionCommand {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn state_with_text(text: &str, cursor: usize) -> State {
+        let (_, log_sender) = LogState::new_with_channel();
+
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, text);
+        buf.primary_cursor_mut().set_sel(cursor..=cursor);
+
+        let mut buffers = Buffers::default();
+        buffers.push_new(buf).await;
+
+        let mut state = State::new();
+        state.state(buffers).state(log_sender);
+        state
+    }
+
+    async fn sel_after(text: &str, cursor: usize, pattern: &str) -> String {
+        let mut state = state_with_text(text, cursor).await;
+        let motion = MotionCommand::RegexCursor {
+            pattern: pattern.to_string(),
+            offset: None,
+            extend: false,
+            advance: false,
+        };
+        assert!(motion.apply(&mut state).await);
+
+        let mut buffers = state.lock_state::<Buffers>().await;
+        let buf = buffers.cur_text_buffer_mut().await.unwrap();
+        let sel = buf.primary_cursor().sel().clone();
+        text[*sel.start()..=*sel.end()].to_string()
+    }
+
+    #[tokio::test]
+    async fn word_motions_keep_combining_marks() {
+        let word = r"\k+|[^\k\s]+|\s+";
+        let end = r"\k+|[^\k\s]+";
+
+        assert_eq!(sel_after("cafe\u{301} bar", 0, word).await, "cafe\u{301}");
+        assert_eq!(sel_after("x nai\u{308}ve!", 2, end).await, "nai\u{308}ve");
+    }
+}
@@ -0,0 +1,251 @@
+use crate::*;
+
+/// The mode pushed while an operator waits for its motion
+pub const OPERATOR_MODE: char = 'o';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperatorKind {
+    Delete,
+    Change,
+    Yank,
+}
+
+impl OperatorKind {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "delete" | "d" => Some(Self::Delete),
+            "change" | "c" => Some(Self::Change),
+            "yank" | "y" => Some(Self::Yank),
+            _ => None,
+        }
+    }
+}
+
+/// The operator waiting for a motion to define its range, if any
+#[derive(State, Default)]
+pub struct PendingOperator {
+    pub kind: Option<OperatorKind>,
+}
+
+#[derive(Debug, Clone, Command)]
+#[command(category = "editing")]
+pub enum OperatorCommand {
+    #[command(drop_ident, name = "operator", name = "op")]
+    /// Runs an operator (`delete`, `change` or `yank`) over the primary selection.
+    /// With a collapsed selection outside visual mode, enters operator-pending mode ('o')
+    /// instead, so the range comes from the next motion
+    Operator { kind: String },
+
+    #[command(name = "operator-apply", name = "opa")]
+    /// Applies the pending operator over the primary selection and leaves operator-pending
+    /// mode. Input handling runs this after a binding made for operator-pending mode, and
+    /// cancels the operator when any other binding fires
+    Apply,
+
+    #[command(name = "operator-object", name = "opo")]
+    /// Applies the pending operator over the text object named by `object` (as in Vim's
+    /// `iw`, `i"`, `i(`) around the primary cursor. `--around` includes delimiters and
+    /// surrounding whitespace. An empty object deletes nothing, but a change still inserts
+    ObjectOperator {
+        object: char,
+        #[command(flag)]
+        around: bool,
+    },
+
+    #[command(name = "operator-cancel", name = "opc")]
+    /// Drops the pending operator and leaves operator-pending mode
+    Cancel,
+}
+
+/// Pops operator-pending mode if it is on top of the stack
+async fn leave_operator_mode(state: &mut State) {
+    let mut modes = state.lock_state::<ModeStack>().await;
+    if modes.get_mode() == OPERATOR_MODE {
+        modes.pop_mode().await;
+    }
+}
+
+/// Runs `kind` over the primary selection as a single change group.
+/// A change keeps its group open so the text typed afterwards undoes with the deletion.
+async fn run_operator(kind: OperatorKind, state: &mut State) -> bool {
+    match kind {
+        OperatorKind::Yank => {
+            if !RegisterCommand::CopyRegister(None).apply(state).await {
+                return false;
+            }
+
+            let mut bufs = state.lock_state::<Buffers>().await;
+            let Some(mut buf) = bufs.cur_buffer_as_mut::<TextBuffer>().await else {
+                return false;
+            };
+            buf.primary_cursor_mut().set_at_start(true);
+            buf.primary_cursor_mut().collapse_sel();
+            true
+        }
+        OperatorKind::Delete | OperatorKind::Change => {
+            BufferCommand::CommitChange.apply(state).await;
+            BufferCommand::StartChange.apply(state).await;
+
+            let deleted = BufferCommand::Delete.apply(state).await;
+
+            if kind == OperatorKind::Change {
+                ModeCommand::PushMode('i').apply(state).await;
+            } else {
+                BufferCommand::CommitChange.apply(state).await;
+            }
+
+            deleted
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Command<State> for OperatorCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        match self {
+            Self::Operator { kind } => {
+                let Some(kind) = OperatorKind::parse(kind) else {
+                    state.lock_state::<LogSender>().await.critical(
+                        "commands::operator",
+                        format!("Unknown operator `{kind}`, expected delete, change or yank"),
+                    );
+                    return false;
+                };
+
+                let collapsed = {
+                    let bufs = state.lock_state::<Buffers>().await;
+                    let Some(buf) = bufs.cur_buffer_as::<TextBuffer>().await else {
+                        return false;
+                    };
+                    let sel = buf.primary_cursor().sel();
+                    sel.start() == sel.end()
+                };
+
+                // Visual mode already chose its range, even a single character
+                let visual = state.lock_state::<ModeStack>().await.mode_on_stack('v');
+                if !collapsed || visual {
+                    return run_operator(kind, state).await;
+                }
+
+                state.lock_state::<PendingOperator>().await.kind = Some(kind);
+                let mut modes = state.lock_state::<ModeStack>().await;
+                if modes.get_mode() != OPERATOR_MODE {
+                    modes.push_mode(OPERATOR_MODE).await;
+                }
+                true
+            }
+
+            Self::Apply => {
+                let Some(kind) = state.lock_state::<PendingOperator>().await.kind.take() else {
+                    return false;
+                };
+                leave_operator_mode(state).await;
+                run_operator(kind, state).await
+            }
+
+            Self::ObjectOperator { object, around } => {
+                let Some(kind) = state.lock_state::<PendingOperator>().await.kind.take() else {
+                    return false;
+                };
+                leave_operator_mode(state).await;
+
+                let Some(object) = TextObjectKind::from_char(*object) else {
+                    return false;
+                };
+
+                let range = {
+                    let mut bufs = state.lock_state::<Buffers>().await;
+                    let Some(mut buf) = bufs.cur_buffer_as_mut::<TextBuffer>().await else {
+                        return false;
+                    };
+                    let byte = buf.primary_cursor().get_cursor_byte();
+                    let Some(range) = buf.text_object(object, *around, byte) else {
+                        return false;
+                    };
+
+                    let cursor = buf.primary_cursor_mut();
+                    if range.is_empty() {
+                        cursor.set_sel(range.start..=range.start);
+                    } else {
+                        cursor.set_sel(range.start..=range.end - 1);
+                    }
+                    cursor.set_at_start(true);
+                    range
+                };
+
+                if !range.is_empty() {
+                    return run_operator(kind, state).await;
+                }
+
+                if kind == OperatorKind::Change {
+                    BufferCommand::CommitChange.apply(state).await;
+                    BufferCommand::StartChange.apply(state).await;
+                    ModeCommand::PushMode('i').apply(state).await;
+                }
+                true
+            }
+
+            Self::Cancel => {
+                state.lock_state::<PendingOperator>().await.kind = None;
+                leave_operator_mode(state).await;
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_operator_names() {
+        assert_eq!(OperatorKind::parse("delete"), Some(OperatorKind::Delete));
+        assert_eq!(OperatorKind::parse("c"), Some(OperatorKind::Change));
+        assert_eq!(OperatorKind::parse("yank"), Some(OperatorKind::Yank));
+        assert_eq!(OperatorKind::parse("move"), None);
+    }
+
+    async fn text_and_mode(state: &State) -> (String, char) {
+        let mut bufs = state.lock_state::<Buffers>().await;
+        let buf = bufs.cur_text_buffer_mut().await.unwrap();
+        let text = buf.slice_to_string(0, buf.len()).unwrap();
+        (text, state.lock_state::<ModeStack>().await.get_mode())
+    }
+
+    #[tokio::test]
+    async fn pending_operators_apply_or_cancel() {
+        let (_, log_sender) = LogState::new_with_channel();
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, "abc def");
+        let mut buffers = Buffers::default();
+        buffers.push_new(buf).await;
+
+        let mut state = State::new();
+        state
+            .state(buffers)
+            .state(log_sender)
+            .state(CoreConfig::default())
+            .state(ModeStack(vec!['n']))
+            .state(PendingOperator::default());
+
+        let delete = OperatorCommand::Operator { kind: "delete".to_string() };
+        assert!(delete.apply(&mut state).await);
+        assert_eq!(text_and_mode(&state).await, ("abc def".to_string(), OPERATOR_MODE));
+
+        // Cancelling leaves the text alone, and a stray apply has nothing to run
+        assert!(OperatorCommand::Cancel.apply(&mut state).await);
+        assert!(!OperatorCommand::Apply.apply(&mut state).await);
+        assert_eq!(text_and_mode(&state).await, ("abc def".to_string(), 'n'));
+
+        // The motion's selection is what gets deleted
+        assert!(delete.apply(&mut state).await);
+        {
+            let mut bufs = state.lock_state::<Buffers>().await;
+            let mut buf = bufs.cur_text_buffer_mut().await.unwrap();
+            buf.primary_cursor_mut().set_sel(0..=3);
+        }
+        assert!(OperatorCommand::Apply.apply(&mut state).await);
+        assert_eq!(text_and_mode(&state).await, ("def".to_string(), 'n'));
+    }
+}
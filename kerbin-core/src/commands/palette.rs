@@ -1,6 +1,7 @@
 use crate::*;
 
 #[derive(Command)]
+#[command(category = "palette")]
 pub enum PaletteCommand {
     #[command]
     /// Pushes the given string into the content of the palette
@@ -15,12 +16,29 @@ pub enum PaletteCommand {
     ClearPalette,
 
     #[command]
-    /// Executes the content in the command palette
+    /// Executes the content in the command palette, completed to the selected suggestion
+    /// when one was picked
     ExecutePalette,
 
     #[command]
-    /// Autocompletes the palette command
+    /// Autocompletes the palette input to the selected (or top) suggestion
     CompletePalette,
+
+    #[command]
+    /// Selects the next palette suggestion, scrolling the list past the visible ones
+    PaletteSelectNext,
+
+    #[command]
+    /// Selects the previous palette suggestion
+    PaletteSelectPrev,
+
+    #[command]
+    /// Replaces the palette content with the previous command from history
+    PaletteHistoryPrev,
+
+    #[command]
+    /// Replaces the palette content with the next command from history
+    PaletteHistoryNext,
 }
 
 #[async_trait::async_trait]
@@ -31,10 +49,12 @@ impl Command<State> for PaletteCommand {
         match self {
             Self::PushPalette(content) => {
                 palette.input.push_str(content.as_str());
+                state.lock_state::<CommandHistory>().await.reset_cycle();
                 true
             }
 
             Self::PopPalette(chars) => {
+                state.lock_state::<CommandHistory>().await.reset_cycle();
                 for _ in 0..*chars {
                     palette.input.pop();
                     if palette.input.is_empty() {
@@ -47,21 +67,31 @@ impl Command<State> for PaletteCommand {
 
             Self::ClearPalette => {
                 palette.input.clear();
+                state.lock_state::<CommandHistory>().await.reset_cycle();
                 true
             }
 
             Self::ExecutePalette => {
-                let content = palette.input.clone();
+                let content = match palette.suggestions.selected {
+                    Some(_) => palette.completion().unwrap_or(&palette.input).to_string(),
+                    None => palette.input.clone(),
+                };
                 drop(palette);
 
-                let tokens = tokenize(&content).unwrap_or_default();
+                // A bare line target such as `42`, `$` or `50%` jumps to that line
+                let tokens = match LineTarget::parse(content.trim()) {
+                    Some(_) => vec![
+                        Token::Word("goto_line".into()),
+                        Token::Word(content.trim().into()),
+                    ],
+                    None => tokenize(&content).unwrap_or_default(),
+                };
 
                 let resolver_engine = resolver_engine().await;
                 let resolver = resolver_engine.as_resolver();
 
-                let command = state.lock_state::<CommandRegistry>().await.parse_command(
+                let command = state.lock_state::<CommandRegistry>().await.try_parse_command(
                     tokens,
-                    true,
                     false,
                     Some(&resolver),
                     true,
@@ -71,29 +101,60 @@ impl Command<State> for PaletteCommand {
 
                 drop(resolver);
                 drop(resolver_engine);
-                if let Some(command) = command {
-                    if let Err(e) = state.lock_state::<CommandSender>().await.send(command) {
-                        state
-                            .lock_state::<LogSender>()
-                            .await
-                            .high("palette", format!("Failed to send command: {e}"));
+                match command {
+                    Ok(command) => {
+                        state.lock_state::<CommandHistory>().await.record(&content);
+                        if let Err(e) = state.lock_state::<CommandSender>().await.send(command) {
+                            state
+                                .lock_state::<LogSender>()
+                                .await
+                                .high("palette", format!("Failed to send command: {e}"));
+                        }
+                    }
+                    Err(e) => {
+                        state.lock_state::<LogSender>().await.medium("palette", e.to_string());
                     }
-                } else {
-                    state
-                        .lock_state::<LogSender>()
-                        .await
-                        .medium("palette", format!("Invalid command: {content}"));
                 }
                 false
             }
 
             Self::CompletePalette => {
-                if let Some(done) = &palette.completion {
+                if let Some(done) = palette.completion() {
                     palette.input = done.to_string()
                 }
 
                 false
             }
+
+            Self::PaletteSelectNext => {
+                palette.select_suggestion(1);
+                false
+            }
+
+            Self::PaletteSelectPrev => {
+                palette.select_suggestion(-1);
+                false
+            }
+
+            Self::PaletteHistoryPrev => {
+                let mut history = state.lock_state::<CommandHistory>().await;
+                let Some(entry) = history.older(&palette.input) else {
+                    return false;
+                };
+                palette.input = entry.to_string();
+
+                false
+            }
+
+            Self::PaletteHistoryNext => {
+                let mut history = state.lock_state::<CommandHistory>().await;
+                let Some(entry) = history.newer() else {
+                    return false;
+                };
+                palette.input = entry.to_string();
+
+                false
+            }
         }
     }
 }
@@ -0,0 +1,276 @@
+use crate::*;
+
+#[derive(Command)]
+#[command(category = "picker")]
+pub enum PickerCommand {
+    #[command]
+    /// Opens a fuzzy picker over `--items`. Picking one sets the `--var` template to it
+    /// (default `picked`) and runs `--commands` (each element is a [command] list).
+    Picker {
+        #[command(flag)]
+        title: String,
+        #[command(flag)]
+        items: Vec<Token>,
+        #[command(flag)]
+        var: Option<String>,
+        #[command(flag, name = "commands", type_name = "[command_list]", ignore)]
+        commands: Vec<Token>,
+    },
+
+    #[command(name = "find")]
+    /// Opens a picker over the files under the working directory and opens the picked one.
+    /// Hidden and gitignored files are left out. The list is walked once and cached;
+    /// `--refresh` walks the directory again. Limits are set with `set find.max-depth`
+    /// and `set find.max-files`
+    Find {
+        #[command(flag)]
+        refresh: bool,
+    },
+
+    #[command(name = "grep")]
+    /// Opens a picker over the lines matching a regex in the files under the working
+    /// directory, and opens the picked one at the match. The picker query is the pattern:
+    /// the search reruns as it is edited. Lowercase patterns ignore case, gitignored
+    /// files are skipped, and the list stops at the first 10000 matches
+    Grep(Option<String>),
+
+    #[command(name = "commands")]
+    /// Opens a picker over the registered commands, listed under a header for their
+    /// category and sorted by name within it. Given a category, only its commands are
+    /// listed. Picking one fills the command palette with its name
+    Commands(Option<String>),
+
+    #[command]
+    /// Appends a string to the picker query
+    PickerPush(String),
+
+    #[command]
+    /// Removes the last N characters from the picker query
+    PickerPop(usize),
+
+    #[command]
+    /// Selects the next match in the picker
+    PickerNext,
+
+    #[command]
+    /// Selects the previous match in the picker
+    PickerPrev,
+
+    #[command]
+    /// Picks the current match, closing the picker
+    PickerSubmit,
+
+    #[command]
+    /// Closes the picker without picking anything
+    PickerCancel,
+}
+
+#[async_trait::async_trait]
+impl Command<State> for PickerCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        match self {
+            Self::Picker {
+                title,
+                items,
+                var,
+                commands,
+            } => {
+                let items = items
+                    .iter()
+                    .filter_map(|t| match t {
+                        Token::Word(w) => Some(w.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                let action = PickerAction::Commands {
+                    var: var.clone().unwrap_or_else(|| "picked".to_string()),
+                    commands: commands.clone(),
+                };
+                Picker::open(state, title, items, action).await;
+                true
+            }
+
+            Self::Find { refresh } => {
+                let (files, walking) = {
+                    let mut finder = state.lock_state::<FileFinder>().await;
+                    if *refresh || (finder.files.is_empty() && !finder.walking()) {
+                        finder.refresh(&*state.lock_state::<FinderConfig>().await);
+                    }
+                    (finder.files.clone(), finder.walking())
+                };
+
+                let title = if walking { "Find (scanning)" } else { "Find" };
+                let action = PickerAction::Callback(Box::new(|state, _, path| {
+                    Box::pin(async move {
+                        BuffersCommand::OpenFile {
+                            path,
+                            filetype: None,
+                            line: None,
+                            force_new: false,
+                        }
+                        .apply(state)
+                        .await;
+                    })
+                }));
+                Picker::open(state, title, files, action).await;
+
+                let session = state.lock_state::<Picker>().await.session;
+                state.lock_state::<FileFinder>().await.session = Some(session);
+                true
+            }
+
+            Self::Grep(pattern) => {
+                let action = PickerAction::Callback(Box::new(|state, _, entry| {
+                    Box::pin(async move {
+                        open_grep_match(state, &entry).await;
+                    })
+                }));
+                Picker::open(state, "Grep", vec![], action).await;
+
+                let mut picker = state.lock_state::<Picker>().await;
+                let mut grep = state.lock_state::<WorkspaceGrep>().await;
+                let pattern = pattern.clone().unwrap_or_default();
+                picker.list.fuzzy = false;
+                picker.list.query = pattern.clone();
+                grep.session = Some(picker.session);
+                grep.restart(&mut picker, pattern);
+                true
+            }
+
+            Self::Commands(category) => {
+                let registry = state.lock_state::<CommandRegistry>().await;
+                let mut infos = registry
+                    .0
+                    .iter()
+                    .flat_map(|set| set.infos.iter())
+                    .filter(|info| category.as_ref().is_none_or(|c| &info.category == c))
+                    .collect::<Vec<_>>();
+
+                if infos.is_empty() {
+                    let category = category.clone().unwrap_or_default();
+                    state.lock_state::<LogSender>().await.high(
+                        "command::commands",
+                        format!("No commands in category `{category}`"),
+                    );
+                    return false;
+                }
+
+                infos.sort_by(|a, b| {
+                    (&a.category, &a.valid_names).cmp(&(&b.category, &b.valid_names))
+                });
+
+                let names: Vec<String> =
+                    infos.iter().map(|info| info.valid_names[0].clone()).collect();
+                let items = infos
+                    .iter()
+                    .map(|info| (info.category.clone(), command_label(info)))
+                    .collect();
+                drop(registry);
+
+                let action = PickerAction::Callback(Box::new(move |state, idx, _| {
+                    let name = names[idx].clone();
+                    Box::pin(async move {
+                        state.lock_state::<ModeStack>().await.push_mode('c').await;
+                        state.lock_state::<CommandPaletteState>().await.input = format!("{name} ");
+                    })
+                }));
+                Picker::open_grouped(state, "Commands", items, action).await;
+                true
+            }
+
+            Self::PickerPush(content) => {
+                let mut picker = state.lock_state::<Picker>().await;
+                let query = format!("{}{content}", picker.list.query);
+                picker.list.set_query(query);
+                true
+            }
+
+            Self::PickerPop(count) => {
+                let mut picker = state.lock_state::<Picker>().await;
+                let mut query = picker.list.query.clone();
+                for _ in 0..*count {
+                    if query.pop().is_none() {
+                        return false;
+                    }
+                }
+                picker.list.set_query(query);
+                true
+            }
+
+            Self::PickerNext => {
+                state
+                    .lock_state::<Picker>()
+                    .await
+                    .list
+                    .select(1, MAX_PICKER_ROWS);
+                true
+            }
+
+            Self::PickerPrev => {
+                state
+                    .lock_state::<Picker>()
+                    .await
+                    .list
+                    .select(-1, MAX_PICKER_ROWS);
+                true
+            }
+
+            Self::PickerSubmit => {
+                let Some((idx, item)) = state
+                    .lock_state::<Picker>()
+                    .await
+                    .list
+                    .current_item()
+                    .map(|(idx, item)| (idx, item.clone()))
+                else {
+                    return false;
+                };
+
+                match Picker::close(state).await {
+                    Some(PickerAction::Commands { var, commands }) => {
+                        resolver_engine_mut().await.set_template(&var, &item);
+                        for token in &commands {
+                            let Token::List(cmd_tokens) = token else {
+                                continue;
+                            };
+                            let command = state.lock_state::<CommandRegistry>().await.parse_command(
+                                cmd_tokens.clone(),
+                                true,
+                                false,
+                                Some(&resolver_engine().await.as_resolver()),
+                                true,
+                                &*state.lock_state::<CommandPrefixRegistry>().await,
+                                &*state.lock_state::<ModeStack>().await,
+                            );
+                            if let Some(cmd) = command
+                                && let Err(e) = state.lock_state::<CommandSender>().await.send(cmd)
+                            {
+                                tracing::error!("picker: failed to send command: {e}");
+                            }
+                        }
+                        true
+                    }
+                    Some(PickerAction::Callback(callback)) => {
+                        callback(state, idx, item).await;
+                        true
+                    }
+                    None => false,
+                }
+            }
+
+            Self::PickerCancel => Picker::close(state).await.is_some(),
+        }
+    }
+}
+
+/// A command's picker row: its name, any aliases, and the first line of its description
+fn command_label(info: &CommandInfo) -> String {
+    let mut label = info.valid_names[0].clone();
+    if info.valid_names.len() > 1 {
+        label.push_str(&format!(" ({})", info.valid_names[1..].join(", ")));
+    }
+    if let Some(desc) = info.desc.first() {
+        label.push_str(&format!(" - {desc}"));
+    }
+    label
+}
@@ -0,0 +1,106 @@
+use crate::*;
+
+#[derive(Command)]
+#[command(category = "navigation")]
+pub enum QuickfixCommand {
+    #[command(drop_ident, name = "cnext", name = "cn")]
+    /// Jumps to the next entry of the quickfix list, or the one `count` entries ahead.
+    /// The first jump goes to the first entry
+    Next(Option<usize>),
+
+    #[command(drop_ident, name = "cprev", name = "cp")]
+    /// Jumps to the previous entry of the quickfix list, or the one `count` entries back.
+    /// The first jump goes to the last entry
+    Prev(Option<usize>),
+
+    #[command(drop_ident, name = "cgoto")]
+    /// Jumps to the Nth (1-based) entry of the quickfix list, or back to the current one
+    Goto(Option<usize>),
+
+    #[command(drop_ident, name = "copen")]
+    /// Shows the quickfix list in the picker. Picking an entry jumps to it
+    Open,
+}
+
+#[async_trait::async_trait]
+impl Command<State> for QuickfixCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        let entry = {
+            let mut list = state.lock_state::<QuickfixList>().await;
+            if list.entries().is_empty() {
+                drop(list);
+                state
+                    .lock_state::<LogSender>()
+                    .await
+                    .low("command::quickfix", "The quickfix list is empty");
+                return false;
+            }
+
+            let last = list.entries().len() - 1;
+            match self {
+                Self::Next(count) => list.step(count.unwrap_or(1).max(1) as isize).cloned(),
+                Self::Prev(count) => list.step(-(count.unwrap_or(1).max(1) as isize)).cloned(),
+                Self::Goto(nth) => match nth.map(|n| n.saturating_sub(1)).or(list.current()) {
+                    Some(idx) => list.select(idx.min(last)).cloned(),
+                    None => list.step(1).cloned(),
+                },
+                Self::Open => {
+                    let title = format!("Quickfix: {}", list.title);
+                    let items = list.entries().iter().map(QuickfixEntry::label).collect();
+                    drop(list);
+
+                    let action = PickerAction::Callback(Box::new(|state, idx, _| {
+                        Box::pin(async move {
+                            let mut list = state.lock_state::<QuickfixList>().await;
+                            let entry = list.select(idx).cloned();
+                            drop(list);
+                            if let Some(entry) = entry {
+                                open_quickfix_entry(state, &entry).await;
+                            }
+                        })
+                    }));
+                    Picker::open(state, title, items, action).await;
+                    return true;
+                }
+            }
+        };
+
+        match entry {
+            Some(entry) => open_quickfix_entry(state, &entry).await,
+            None => {
+                state
+                    .lock_state::<LogSender>()
+                    .await
+                    .low("command::quickfix", "No more quickfix entries");
+                false
+            }
+        }
+    }
+}
+
+/// Opens the entry's file with its range selected, recording a jump first
+pub async fn open_quickfix_entry(state: &mut State, entry: &QuickfixEntry) -> bool {
+    record_jump(state).await;
+
+    let opened = BuffersCommand::OpenFile {
+        path: entry.path.clone(),
+        filetype: None,
+        line: None,
+        force_new: false,
+    }
+    .apply(state)
+    .await;
+    if !opened {
+        return false;
+    }
+
+    let mut bufs = state.lock_state::<Buffers>().await;
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+        return false;
+    };
+    let range = entry.byte_range(buf.get_rope());
+    buf.primary_cursor_mut()
+        .set_sel(range.start..=range.end.saturating_sub(1).max(range.start));
+
+    true
+}
@@ -1,6 +1,9 @@
+use std::ops::Range;
+
 use crate::*;
 
 #[derive(Command)]
+#[command(category = "registers")]
 pub enum RegisterCommand {
     #[command(name = "copy")]
     /// Copies the selected text into a register. Defaults to the `a` register.
@@ -8,11 +11,14 @@ pub enum RegisterCommand {
 
     #[command(name = "paste")]
     /// Pastes the contents of a register at the cursor. Defaults to the `a` register.
+    /// Whole copied lines go on a new line below the cursor's (above with `--before`),
+    /// and text copied with several cursors goes down a column from the cursor.
     ///
     /// Use `--extend` to extend the selection to include the pasted text.
     PasteRegister(
         #[command(type_name = "char?", name = "register")] Option<char>,
         #[command(flag, name = "extend")] bool,
+        #[command(flag, name = "before")] bool,
     ),
 
     #[command(name = "cbcopy")]
@@ -33,43 +39,76 @@ impl Command<State> for RegisterCommand {
 
         match self {
             Self::CopyRegister(register) => {
-                let bufs = state.lock_state::<Buffers>().await;
-                let Some(buf) = bufs.cur_buffer_as::<TextBuffer>().await else {
-                    return false;
-                };
+                let range = {
+                    let bufs = state.lock_state::<Buffers>().await;
+                    let Some(buf) = bufs.cur_buffer_as::<TextBuffer>().await else {
+                        return false;
+                    };
 
-                let byte_range = buf.primary_cursor().sel().clone();
-                let text = buf
-                    .slice_to_string(*byte_range.start(), (*byte_range.end() + 1).min(buf.len()))
-                    .unwrap_or_default();
+                    let range = sel_range(buf.primary_cursor(), buf.len());
+                    let (text, kind) = if buf.cursors.len() > 1 {
+                        let mut ranges: Vec<_> =
+                            buf.cursors.iter().map(|c| sel_range(c, buf.len())).collect();
+                        ranges.sort_by_key(|r| r.start);
+                        let rows: Vec<_> = ranges
+                            .into_iter()
+                            .map(|r| buf.slice_to_string(r.start, r.end).unwrap_or_default())
+                            .collect();
+                        (rows.join("\n"), RegisterKind::Block)
+                    } else {
+                        (
+                            buf.slice_to_string(range.start, range.end).unwrap_or_default(),
+                            RegisterKind::of_range(buf.get_rope(), range.clone()),
+                        )
+                    };
 
-                registers.set(register.unwrap_or('a'), text);
+                    registers.set_with_kind(register.unwrap_or('a'), text, kind);
+                    range
+                };
+
+                drop(registers);
+                flash_yank(state, range).await;
 
                 true
             }
-            Self::PasteRegister(register, extend) => {
-                let text = registers.get(&register.unwrap_or('a')).to_string();
+            Self::PasteRegister(register, extend, before) => {
+                let register = register.unwrap_or('a');
+                let kind = registers.kind(&register);
+                let text = registers.get(&register).to_string();
 
                 drop(registers);
 
-                BufferCommand::Append {
-                    text,
-                    extend: *extend,
+                if kind == RegisterKind::Charwise {
+                    return BufferCommand::Append {
+                        text,
+                        extend: *extend,
+                    }
+                    .apply(state)
+                    .await;
                 }
-                .apply(state)
-                .await
+
+                let mut bufs = state.lock_state::<Buffers>().await;
+                let Some(mut buf) = bufs.cur_buffer_as_mut::<TextBuffer>().await else {
+                    return false;
+                };
+                paste_with_kind(&mut buf, &text, kind, *before, *extend)
             }
             Self::ClipboardCopy => {
-                let bufs = state.lock_state::<Buffers>().await;
-                let Some(buf) = bufs.cur_buffer_as::<TextBuffer>().await else {
-                    return false;
+                let (range, text) = {
+                    let bufs = state.lock_state::<Buffers>().await;
+                    let Some(buf) = bufs.cur_buffer_as::<TextBuffer>().await else {
+                        return false;
+                    };
+                    let range = sel_range(buf.primary_cursor(), buf.len());
+                    let text = buf.slice_to_string(range.start, range.end).unwrap_or_default();
+                    (range, text)
                 };
-                let byte_range = buf.primary_cursor().sel().clone();
-                let text = buf
-                    .slice_to_string(*byte_range.start(), (*byte_range.end() + 1).min(buf.len()))
-                    .unwrap_or_default();
                 match arboard::Clipboard::new().and_then(|mut cb| cb.set_text(text)) {
-                    Ok(_) => true,
+                    Ok(_) => {
+                        drop(registers);
+                        flash_yank(state, range).await;
+                        true
+                    }
                     Err(e) => {
                         let logger = state.lock_state::<LogSender>().await;
                         logger.critical(
@@ -105,3 +144,145 @@ impl Command<State> for RegisterCommand {
         }
     }
 }
+
+/// Bytes covered by `cursor`'s (inclusive) selection, clamped to `len`
+fn sel_range(cursor: &Cursor, len: usize) -> Range<usize> {
+    let sel = cursor.sel();
+    *sel.start()..(*sel.end() + 1).min(len)
+}
+
+/// Pastes `text` of `kind` around the primary cursor, leaving the cursor at the start of
+/// the pasted text (selecting it with `extend`, or its first row for a block)
+pub fn paste_with_kind(
+    buf: &mut TextBuffer,
+    text: &str,
+    kind: RegisterKind,
+    before: bool,
+    extend: bool,
+) -> bool {
+    let byte = buf.primary_cursor().get_cursor_byte();
+    let line = buf.byte_to_line_clamped(byte);
+
+    let (start, first_row) = match kind {
+        RegisterKind::Charwise => {
+            buf.action(Insert {
+                byte,
+                content: text.to_string(),
+            });
+            (byte, text)
+        }
+        RegisterKind::Linewise if before => {
+            let start = buf.line_to_byte_clamped(line);
+            buf.action(Insert {
+                byte: start,
+                content: text.to_string(),
+            });
+            (start, text)
+        }
+        RegisterKind::Linewise => {
+            if line + 1 < buf.len_lines() {
+                let start = buf.line_to_byte_clamped(line + 1);
+                buf.action(Insert {
+                    byte: start,
+                    content: text.to_string(),
+                });
+                (start, text)
+            } else {
+                // The last line has no break to paste after, so it gets one
+                let end = buf.len();
+                let content = format!("\n{}", text.strip_suffix('\n').unwrap_or(text));
+                buf.action(Insert { byte: end, content });
+                (end + 1, text.strip_suffix('\n').unwrap_or(text))
+            }
+        }
+        RegisterKind::Block => {
+            let rope = buf.get_rope();
+            let col = rope.byte_to_char(byte) - rope.line_to_char(line);
+            let rows: Vec<&str> = text.split('\n').collect();
+
+            let missing = (line + rows.len()).saturating_sub(buf.len_lines());
+            if missing > 0 {
+                let end = buf.len();
+                buf.action(Insert {
+                    byte: end,
+                    content: "\n".repeat(missing),
+                });
+            }
+
+            // Bottom up, so the rows above keep their offsets
+            for (i, row) in rows.iter().enumerate().rev() {
+                let rope = buf.get_rope();
+                let line_start = rope.line_to_char(line + i);
+                let line_len = rope
+                    .line(line + i)
+                    .chars()
+                    .take_while(|c| !matches!(c, '\n' | '\r'))
+                    .count();
+
+                let padding = " ".repeat(col.saturating_sub(line_len));
+                let at = rope.char_to_byte(line_start + col.min(line_len));
+                buf.action(Insert {
+                    byte: at,
+                    content: format!("{padding}{row}"),
+                });
+            }
+            (byte, rows[0])
+        }
+    };
+
+    buf.primary_cursor_mut().move_to(start);
+    if extend && !first_row.is_empty() {
+        buf.move_chars(first_row.chars().count() as isize, true);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str, byte: usize) -> TextBuffer {
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, text);
+        buf.primary_cursor_mut().move_to(byte);
+        buf
+    }
+
+    fn text(buf: &TextBuffer) -> String {
+        buf.get_rope().to_string()
+    }
+
+    #[test]
+    fn charwise_paste_goes_at_the_cursor() {
+        let mut buf = buffer("one two", 4);
+        paste_with_kind(&mut buf, "new ", RegisterKind::Charwise, false, true);
+        assert_eq!(text(&buf), "one new two");
+        assert_eq!(buf.primary_cursor().sel().clone(), 4..=8);
+    }
+
+    #[test]
+    fn linewise_paste_goes_below_or_above_the_line() {
+        let mut buf = buffer("one\ntwo\n", 1);
+        paste_with_kind(&mut buf, "new\n", RegisterKind::Linewise, false, false);
+        assert_eq!(text(&buf), "one\nnew\ntwo\n");
+        assert_eq!(buf.primary_cursor().get_cursor_byte(), 4);
+
+        paste_with_kind(&mut buf, "top\n", RegisterKind::Linewise, true, false);
+        assert_eq!(text(&buf), "one\ntop\nnew\ntwo\n");
+        assert_eq!(buf.primary_cursor().get_cursor_byte(), 4);
+
+        // After the last line, which has no break of its own
+        let mut buf = buffer("one\ntwo", 5);
+        paste_with_kind(&mut buf, "new\n", RegisterKind::Linewise, false, true);
+        assert_eq!(text(&buf), "one\ntwo\nnew");
+        assert_eq!(buf.primary_cursor().sel().clone(), 8..=11);
+    }
+
+    #[test]
+    fn block_paste_fills_a_column() {
+        let mut buf = buffer("abcd\nx\nefgh\n", 2);
+        paste_with_kind(&mut buf, "1\n2\n3\n4", RegisterKind::Block, false, false);
+        assert_eq!(text(&buf), "ab1cd\nx 2\nef3gh\n  4");
+        assert_eq!(buf.primary_cursor().get_cursor_byte(), 2);
+    }
+}
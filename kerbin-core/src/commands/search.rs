@@ -0,0 +1,229 @@
+use std::ops::Range;
+
+use crate::*;
+use regex_cursor::engines::meta::Regex;
+
+const SEARCH_NS: &str = "core::search";
+/// Above diagnostics, below popups like hover and completion
+const SEARCH_PRIORITY: i32 = 4;
+
+/// Upper bound on highlighted matches, so a pattern like `.` in a huge file stays responsive
+const MAX_HIGHLIGHTS: usize = 10_000;
+
+/// Per-buffer state for an in-progress incremental search
+#[derive(State, Default)]
+pub struct SearchState {
+    /// Cursors and primary index from before the search started, restored on cancel
+    origin: Option<(Vec<Cursor>, usize)>,
+    /// Pattern of the last preview
+    pattern: String,
+}
+
+/// Byte ranges of the non-empty matches of `regex` in `slice`, capped at `limit`
+fn find_matches(regex: &Regex, slice: ropey::RopeSlice<'_>, limit: usize) -> Vec<Range<usize>> {
+    regex
+        .find_iter(regex_cursor::Input::new(RopeyCursor::new(slice)))
+        .filter(|m| m.start() < m.end())
+        .take(limit)
+        .map(|m| m.start()..m.end())
+        .collect()
+}
+
+/// The first match starting at or after `from`, wrapping around to the first match
+fn nearest_match(matches: &[Range<usize>], from: usize) -> Option<&Range<usize>> {
+    matches
+        .iter()
+        .find(|m| m.start >= from)
+        .or_else(|| matches.first())
+}
+
+/// The last match starting before `from`, wrapping around to the last match
+fn nearest_match_before(matches: &[Range<usize>], from: usize) -> Option<&Range<usize>> {
+    matches
+        .iter()
+        .rev()
+        .find(|m| m.start < from)
+        .or_else(|| matches.last())
+}
+
+#[derive(Debug, Clone, Command)]
+#[command(category = "search")]
+pub enum SearchCommand {
+    #[command(name = "search-preview")]
+    /// Highlights every match of the regex and previews a jump to the nearest one.
+    /// The cursors from before the first preview are kept so the search can be cancelled.
+    /// Invalid patterns match nothing, so half-typed regexes are harmless.
+    /// `--backward` previews the nearest match before the cursor instead
+    SearchPreview {
+        pattern: String,
+        #[command(flag)]
+        backward: bool,
+    },
+
+    #[command(name = "search-prev")]
+    /// Selects the last match of the regex before the primary cursor's selection, wrapping
+    /// around to the last match in the buffer
+    SearchPrev(String),
+
+    #[command(name = "search-end")]
+    /// Clears the search highlights. `--restore` moves the cursors back to where they
+    /// were before the search started, and `--quickfix` fills the quickfix list with
+    /// the matches of the last preview
+    SearchEnd {
+        #[command(flag)]
+        restore: bool,
+        #[command(flag)]
+        quickfix: bool,
+    },
+}
+
+#[async_trait::async_trait]
+impl Command<State> for SearchCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        let style = state
+            .lock_state::<Theme>()
+            .await
+            .get_fallback_default(["ui.search", "ui.selection"]);
+
+        let mut buffers = state.lock_state::<Buffers>().await;
+        let Some(mut cur_buffer) = buffers.cur_text_buffer_mut().await else {
+            return false;
+        };
+
+        match self {
+            Self::SearchPreview { pattern, backward } => {
+                let origin = {
+                    let cursors = cur_buffer.cursors.clone();
+                    let primary = cur_buffer.primary_cursor;
+                    let mut search = cur_buffer
+                        .get_or_insert_state_mut(SearchState::default)
+                        .await;
+                    search.pattern = pattern.clone();
+                    search.origin.get_or_insert((cursors, primary)).clone()
+                };
+
+                // Every preview starts from the origin, so editing the pattern never drifts
+                let from = origin.0[origin.1].get_cursor_byte();
+                cur_buffer.cursors = origin.0;
+                cur_buffer.primary_cursor = origin.1;
+
+                let matches = match cached(pattern) {
+                    Ok(regex) => {
+                        let len = cur_buffer.len();
+                        find_matches(&regex, cur_buffer.slice_clamped(0, len), MAX_HIGHLIGHTS)
+                    }
+                    Err(_) => vec![],
+                };
+
+                let marks = matches
+                    .iter()
+                    .map(|m| {
+                        ExtmarkBuilder::new_range(SEARCH_NS, m.clone())
+                            .with_kind(ExtmarkKind::Highlight { style })
+                    })
+                    .collect();
+
+                let version = *cur_buffer.version();
+                cur_buffer.renderer.set_namespace_priority(SEARCH_NS, SEARCH_PRIORITY);
+                cur_buffer.renderer.set_namespace(version, SEARCH_NS, marks);
+
+                let nearest = match backward {
+                    true => nearest_match_before(&matches, from),
+                    false => nearest_match(&matches, from),
+                };
+                if let Some(m) = nearest {
+                    cur_buffer
+                        .primary_cursor_mut()
+                        .set_sel(m.start..=m.end.saturating_sub(1));
+                }
+
+                true
+            }
+
+            Self::SearchPrev(pattern) => {
+                let regex = match cached(pattern) {
+                    Ok(regex) => regex,
+                    Err(e) => {
+                        state
+                            .lock_state::<LogSender>()
+                            .await
+                            .high("command::search_prev", format!("Invalid regex: {e}"));
+                        return false;
+                    }
+                };
+
+                let from = *cur_buffer.primary_cursor().sel().start();
+                match search_prev(&regex, cur_buffer.get_rope().slice(..), from) {
+                    Some(m) => {
+                        cur_buffer
+                            .primary_cursor_mut()
+                            .set_sel(m.start..=m.end.saturating_sub(1));
+                        true
+                    }
+                    None => false,
+                }
+            }
+
+            Self::SearchEnd { restore, quickfix } => {
+                cur_buffer.renderer.clear_extmark_ns(SEARCH_NS);
+
+                let (origin, pattern) = match cur_buffer.get_state_mut::<SearchState>().await {
+                    Some(mut search) => {
+                        (search.origin.take(), std::mem::take(&mut search.pattern))
+                    }
+                    None => (None, String::new()),
+                };
+                cur_buffer.remove_state::<SearchState>();
+
+                if *quickfix && !pattern.is_empty() && let Ok(regex) = cached(&pattern) {
+                    let slice = cur_buffer.slice_clamped(0, cur_buffer.len());
+                    let entries = find_matches(&regex, slice, usize::MAX)
+                        .into_iter()
+                        .map(|m| {
+                            let line = cur_buffer.byte_to_line_clamped(m.start);
+                            let text = cur_buffer.get_rope().line(line).to_string();
+                            QuickfixEntry::from_buffer(&cur_buffer, m, text.trim())
+                        })
+                        .collect();
+                    state
+                        .lock_state::<QuickfixList>()
+                        .await
+                        .set(format!("search {pattern}"), entries);
+                }
+
+                if *restore && let Some((cursors, primary)) = origin {
+                    cur_buffer.cursors = cursors;
+                    cur_buffer.primary_cursor = primary;
+                }
+
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews_nearest_match_with_wraparound() {
+        let rope = ropey::Rope::from_str("foo bar foo baz fo");
+        let regex = cached("fo+").unwrap();
+
+        let matches = find_matches(&regex, rope.slice(..), MAX_HIGHLIGHTS);
+        assert_eq!(matches, [0..3, 8..11, 16..18]);
+
+        assert_eq!(nearest_match(&matches, 4), Some(&(8..11)));
+        assert_eq!(nearest_match(&matches, 17), Some(&(0..3)));
+        assert_eq!(find_matches(&regex, rope.slice(..), 1), vec![0..3]);
+
+        assert_eq!(nearest_match_before(&matches, 8), Some(&(0..3)));
+        assert_eq!(nearest_match_before(&matches, 9), Some(&(8..11)));
+        assert_eq!(nearest_match_before(&matches, 0), Some(&(16..18)));
+
+        // Patterns that only match the empty string highlight nothing
+        let empty = cached("x*").unwrap();
+        assert!(find_matches(&empty, rope.slice(..), MAX_HIGHLIGHTS).is_empty());
+    }
+}
@@ -12,6 +12,7 @@ use ratatui::widgets::Clear;
 use std::process::Stdio;
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "shell")]
 pub enum ShellCommand {
     #[command(drop_ident, name = "shell", name = "sh")]
     /// Executes a shell command, blocking until it completes.
@@ -36,6 +37,32 @@ pub enum ShellCommand {
     /// Tears down and restores the terminal around the subprocess.
     #[command(drop_ident, name = "shell_in_place", name = "ship")]
     InPlace(#[command(name = "cmd", type_name = "[string]")] Vec<String>),
+
+    /// Runs later shell commands in `dir`, relative to where they currently run.
+    /// Without a directory, goes back to following `shell.dir`.
+    #[command(drop_ident, name = "shell_cd", name = "sh-cd")]
+    ChangeDir(#[command(name = "dir", type_name = "string?")] Option<String>),
+
+    /// Sets an environment variable for every shell command
+    #[command(drop_ident, name = "shell_env", name = "sh-env")]
+    Env { key: String, value: String },
+}
+
+/// Path of the current buffer, which `ShellConfig` may run commands beside
+async fn cur_buffer_path(state: &State) -> Option<String> {
+    let bufs = state.lock_state::<Buffers>().await;
+    bufs.cur_buffer_as::<TextBuffer>()
+        .await
+        .map(|buf| buf.path.clone())
+}
+
+/// Builds `args` with the working directory and environment from `ShellConfig`
+async fn shell_command(state: &State, args: &[String]) -> std::process::Command {
+    let buffer_path = cur_buffer_path(state).await;
+    state
+        .lock_state::<ShellConfig>()
+        .await
+        .command(args, buffer_path.as_deref())
 }
 
 #[async_trait::async_trait]
@@ -43,9 +70,7 @@ impl Command<State> for ShellCommand {
     async fn apply(&self, state: &mut State) -> bool {
         match self {
             Self::Execute(args) => {
-                match std::process::Command::new(&args[0])
-                    .args(&args[1..])
-                    .output()
+                match shell_command(state, args).await.output()
                 {
                     Ok(_) => true,
                     Err(e) => {
@@ -55,8 +80,8 @@ impl Command<State> for ShellCommand {
                 }
             }
             Self::Spawn(args) => {
-                match std::process::Command::new(&args[0])
-                    .args(&args[1..])
+                match shell_command(state, args)
+                    .await
                     .stdout(Stdio::piped())
                     .stderr(Stdio::piped())
                     .stdin(Stdio::piped())
@@ -70,10 +95,7 @@ impl Command<State> for ShellCommand {
                 }
             }
             Self::Pipe(pipe, cmd) => {
-                let text = match std::process::Command::new(&pipe[0])
-                    .args(&pipe[1..])
-                    .output()
-                {
+                let text = match shell_command(state, pipe).await.output() {
                     Ok(t) => String::from_utf8_lossy(&t.stdout).to_string(),
 
                     Err(e) => {
@@ -102,6 +124,8 @@ impl Command<State> for ShellCommand {
                 true
             }
             Self::InPlace(args) => {
+                let mut command = shell_command(state, args).await;
+
                 // Tear down terminal
                 execute!(
                     std::io::stdout(),
@@ -111,10 +135,7 @@ impl Command<State> for ShellCommand {
                 .ok();
                 ratatui::try_restore().ok();
 
-                let res = match std::process::Command::new(&args[0])
-                    .args(&args[1..])
-                    .status()
-                {
+                let res = match command.status() {
                     Ok(_) => true,
                     Err(e) => {
                         tracing::error!("Failed to run command: {e}");
@@ -142,9 +163,45 @@ impl Command<State> for ShellCommand {
                     .await
                     .draw(|x| x.render_widget(Clear, x.area()))
                     .expect("terminal should render");
+                state.lock_state::<Chunks>().await.force_redraw();
 
                 res
             }
+            Self::ChangeDir(dir) => {
+                let Some(dir) = dir else {
+                    state.lock_state::<ShellConfig>().await.cwd = None;
+                    return true;
+                };
+
+                let buffer_path = cur_buffer_path(state).await;
+                let target = state
+                    .lock_state::<ShellConfig>()
+                    .await
+                    .working_dir(buffer_path.as_deref())
+                    .join(dir);
+
+                match target.canonicalize() {
+                    Ok(target) if target.is_dir() => {
+                        state.lock_state::<ShellConfig>().await.cwd = Some(target);
+                        true
+                    }
+                    _ => {
+                        state.lock_state::<LogSender>().await.medium(
+                            "command::shell_cd",
+                            format!("No directory at `{}`", target.display()),
+                        );
+                        false
+                    }
+                }
+            }
+            Self::Env { key, value } => {
+                state
+                    .lock_state::<ShellConfig>()
+                    .await
+                    .env
+                    .insert(key.clone(), value.clone());
+                true
+            }
         }
     }
 }
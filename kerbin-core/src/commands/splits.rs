@@ -56,6 +56,7 @@ async fn focus_in_dir(state: &mut State, dx: i16, dy: i16) -> bool {
 }
 
 #[derive(Clone, Debug, Command)]
+#[command(category = "splits")]
 pub enum SplitCommand {
     #[command(name = "spv")]
     /// Splits the focused pane vertically (side by side)
@@ -105,6 +106,17 @@ pub enum SplitCommand {
         amount: i16,
     },
 
+    #[command(drop_ident, name = "split_limit", name = "spl")]
+    /// Keeps the focused pane between `--min` and `--max` cells along its split. Mins are
+    /// met before the other panes share out the rest; a max only holds while another pane
+    /// can take the space. Leaving both out clears the limits
+    LimitSplit {
+        #[command(flag)]
+        min: Option<u16>,
+        #[command(flag)]
+        max: Option<u16>,
+    },
+
     #[command(name = "spd")]
     /// Closes all panes except the focused one and merges all buffer lists into it
     DropSplits,
@@ -249,6 +261,16 @@ impl Command<State> for SplitCommand {
                 true
             }
 
+            SplitCommand::LimitSplit { min, max } => {
+                let mut split = state.lock_state::<SplitState>().await;
+                let Some(pane) = split.focused_pane_mut() else {
+                    return false;
+                };
+                pane.min = *min;
+                pane.max = *max;
+                true
+            }
+
             SplitCommand::DropSplits => {
                 let buf_idx = {
                     let unique_buffers = state.lock_state::<SplitState>().await.unique_buffers;
@@ -283,6 +305,8 @@ impl Command<State> for SplitCommand {
                         selected_local,
                         size: 0,
                         tab_scroll: 0,
+                        min: None,
+                        max: None,
                     });
                     split.focused_id = new_id;
 
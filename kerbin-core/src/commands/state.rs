@@ -1,6 +1,7 @@
 use crate::*;
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "editor")]
 pub enum StateCommand {
     #[command(name = "q")]
     /// Quits the editor. Fails if any buffer has unsaved changes.
@@ -8,33 +9,45 @@ pub enum StateCommand {
     /// See `quit!` to quit without the dirty check.
     Quit,
 
-    #[command(drop_ident, name = "quit!", name = "q!")]
+    #[command(drop_ident, name = "quit!", name = "q!", name = "qa!")]
     /// Quits the editor, ignoring unsaved changes.
     ///
     /// See `quit` to quit with the dirty check.
     QuitForce,
 
+    #[command(drop_ident, name = "write_quit_all", name = "wqa")]
+    /// Writes every buffer with unsaved changes, then quits.
+    /// Stops if any of them can't be written.
+    WriteQuitAll,
+
     #[command(drop_ident, name = "log_session")]
     LogSessionId,
+
+    #[command(drop_ident, name = "set")]
+    /// Sets a setting, like `set scrolloff 5` or `set core.scrolloff 5`.
+    /// A bare name uses the first state with a field of that name.
+    ///
+    /// `set scrolloff?` logs the current value, and a bare `set` lists every setting.
+    /// `set readonly` and `set noreadonly` toggle whether the current buffer refuses edits.
+    Set {
+        #[command(type_name = "string?")]
+        key: Option<String>,
+        #[command(type_name = "string?")]
+        value: Option<String>,
+    },
+
+    #[command(drop_ident, name = "get")]
+    /// Logs the current value of a setting, like `get core.scrolloff`
+    Get { key: String },
 }
 
 #[async_trait::async_trait]
 impl Command<State> for StateCommand {
     async fn apply(&self, state: &mut State) -> bool {
-        match *self {
+        match self {
             Self::Quit => {
-                let bufs = state.lock_state::<Buffers>().await;
-                let log = state.lock_state::<LogSender>().await;
-                for buf in &bufs.buffers {
-                    if buf.read().await.is_dirty() {
-                        log.medium(
-                            "command::quit",
-                            "Unable to quit, can't close unsaved buffers",
-                        );
-
-                        tracing::error!("Unable to quit project, please save buffers");
-                        return false;
-                    }
+                if !quit_allowed(state).await {
+                    return false;
                 }
                 state.lock_state::<Running>().await.0 = false;
             }
@@ -42,6 +55,13 @@ impl Command<State> for StateCommand {
                 state.lock_state::<Running>().await.0 = false;
             }
 
+            Self::WriteQuitAll => {
+                if !write_all(state).await || !quit_allowed(state).await {
+                    return false;
+                }
+                state.lock_state::<Running>().await.0 = false;
+            }
+
             Self::LogSessionId => {
                 let session_uuid = state.lock_state::<SessionUuid>().await.0;
                 state
@@ -49,9 +69,282 @@ impl Command<State> for StateCommand {
                     .await
                     .high("command::log_session", session_uuid);
             }
+
+            Self::Set {
+                key: None,
+                value: _,
+            } => {
+                let fields: Vec<_> = state
+                    .lock_state::<ConfigurableRegistry>()
+                    .await
+                    .fields()
+                    .collect();
+
+                let mut lines = vec![];
+                for field in fields {
+                    let value = field.get(state).await.unwrap_or_default();
+                    lines.push(format!(
+                        "{}.{}: {} = {value}",
+                        field.state, field.field, field.ty
+                    ));
+                }
+
+                state
+                    .lock_state::<LogSender>()
+                    .await
+                    .low("command::set", format!("Settings:\n{}", lines.join("\n")));
+            }
+
+            Self::Set {
+                key: Some(key),
+                value: None,
+            } if key == "readonly" || key == "noreadonly" => {
+                let readonly = key == "readonly";
+                let mut buffers = state.lock_state::<Buffers>().await;
+                let Some(mut buf) = buffers.cur_text_buffer_mut().await else {
+                    return false;
+                };
+                buf.set_readonly(readonly);
+
+                if !readonly && buf.big_file {
+                    state.lock_state::<LogSender>().await.medium(
+                        "command::set",
+                        format!("{} is a big file and stays read-only", buf.path),
+                    );
+                }
+            }
+
+            Self::Set {
+                key: Some(key),
+                value: None,
+            } => match key.strip_suffix('?') {
+                Some(key) => log_setting(state, "command::set", key).await,
+                None => {
+                    state.lock_state::<LogSender>().await.high(
+                        "command::set",
+                        format!("Missing a value for `{key}`, use `set {key}?` to see it"),
+                    );
+                }
+            },
+
+            Self::Set {
+                key: Some(key),
+                value: Some(value),
+            } => {
+                let field = state.lock_state::<ConfigurableRegistry>().await.resolve(key);
+                let res = match field {
+                    Ok(field) => field.set(state, value).await,
+                    Err(e) => Err(e),
+                };
+
+                if let Err(e) = res {
+                    state.lock_state::<LogSender>().await.high("command::set", e);
+                }
+            }
+
+            Self::Get { key } => log_setting(state, "command::get", key).await,
         }
 
         // Always return false, as this command should never be repeated
         false
     }
 }
+
+/// Logs the current value of the setting `key`, or that there's no such setting
+async fn log_setting(state: &State, origin: &str, key: &str) {
+    let field = state.lock_state::<ConfigurableRegistry>().await.resolve(key);
+    let log = state.lock_state::<LogSender>().await;
+    match field {
+        Ok(field) => {
+            let value = field.get(state).await.unwrap_or_default();
+            log.low(origin, format!("{}.{} = {value}", field.state, field.field));
+        }
+        Err(e) => {
+            log.high(origin, e);
+        }
+    }
+}
+
+/// Titles of every buffer with unsaved changes
+async fn dirty_buffers(buffers: &Buffers) -> Vec<String> {
+    let mut dirty = vec![];
+    for buf in &buffers.buffers {
+        let buf = buf.read().await;
+        if buf.is_dirty() {
+            dirty.push(buf.title());
+        }
+    }
+    dirty
+}
+
+/// Returns whether every buffer is saved, logging the dirty ones if not
+async fn quit_allowed(state: &State) -> bool {
+    let dirty = dirty_buffers(&*state.lock_state::<Buffers>().await).await;
+    if dirty.is_empty() {
+        return true;
+    }
+
+    let message = format!("Unable to quit, unsaved buffers: {}", dirty.join(", "));
+    tracing::error!(message);
+    state
+        .lock_state::<LogSender>()
+        .await
+        .medium("command::quit", message);
+    false
+}
+
+/// Writes every dirty text buffer backed by a file, returning false if any write
+/// was vetoed or failed. Special buffers are left for the dirty check to report, and
+/// buffers whose file changed on disk are skipped like `write` would refuse them.
+async fn write_all(state: &mut State) -> bool {
+    let mut targets = vec![];
+    for buf in &state.lock_state::<Buffers>().await.buffers {
+        let guard = buf.read().await;
+        if let Some(text) = guard.downcast::<TextBuffer>()
+            && text.dirty
+            && !(text.path.starts_with('<') && text.path.ends_with('>'))
+        {
+            targets.push((buf.clone(), text.path.clone()));
+        }
+    }
+
+    let mut ok = true;
+    for (buf, path) in targets {
+        // Subscribers may lock the buffers, so the veto runs before the buffer is held
+        if !pre_save_allowed(state, Some(path.clone())).await {
+            ok = false;
+            continue;
+        }
+
+        let mut guard = buf.write().await;
+        let Some(text) = guard.downcast_mut::<TextBuffer>() else {
+            continue;
+        };
+
+        let conflict = if text.stale {
+            Err(format!("File has changed on disk since last read/save: {path}"))
+        } else {
+            text.check_external_change(&path)
+        };
+        if let Err(message) = conflict {
+            tracing::error!(message);
+            state
+                .lock_state::<LogSender>()
+                .await
+                .high("command::write_file", message);
+            ok = false;
+            continue;
+        }
+
+        if let Err(e) = text.write_file(None).await {
+            state
+                .lock_state::<LogSender>()
+                .await
+                .high("command::write_file", format!("{path}: {e}"));
+            ok = false;
+        }
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn state_with_dirty_buffer() -> State {
+        let (_, log_sender) = LogState::new_with_channel();
+
+        let mut buffers = Buffers::default();
+        buffers
+            .push_new(TextBuffer {
+                path: "<dirty>".into(),
+                dirty: true,
+                ..TextBuffer::scratch()
+            })
+            .await;
+
+        let mut state = State::new();
+        state
+            .state(buffers)
+            .state(log_sender)
+            .state(Running(true));
+        state
+    }
+
+    #[tokio::test]
+    async fn quit_is_blocked_by_dirty_buffers() {
+        let mut state = state_with_dirty_buffer().await;
+
+        StateCommand::Quit.apply(&mut state).await;
+        assert!(state.lock_state::<Running>().await.0);
+
+        StateCommand::WriteQuitAll.apply(&mut state).await;
+        assert!(state.lock_state::<Running>().await.0);
+
+        StateCommand::QuitForce.apply(&mut state).await;
+        assert!(!state.lock_state::<Running>().await.0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn write_quit_all_skips_files_changed_on_disk() {
+        let path = std::env::temp_dir().join("kerbin-wqa-changed-on-disk.txt");
+        std::fs::write(&path, "on disk").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let mut buffer = TextBuffer::open(path.clone(), 4).unwrap();
+        buffer.rope = "in buffer".into();
+        buffer.dirty = true;
+        // As if the file was written by something else after it was read
+        buffer.changed = Some(std::time::SystemTime::UNIX_EPOCH);
+
+        let mut state = state_with_dirty_buffer().await;
+        state.lock_state::<Buffers>().await.push_new(buffer).await;
+
+        StateCommand::WriteQuitAll.apply(&mut state).await;
+        assert!(state.lock_state::<Running>().await.0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "on disk");
+    }
+
+    #[derive(State, ConfigurableState)]
+    #[configurable(name = "test")]
+    struct TestConfig {
+        width: usize,
+    }
+
+    #[tokio::test]
+    async fn set_lists_shows_and_reports_unknown_settings() {
+        let (log_state, log_sender) = LogState::new_with_channel();
+        let mut registry = ConfigurableRegistry::default();
+        registry.register::<TestConfig>();
+
+        let mut state = State::new();
+        state
+            .state(log_state)
+            .state(log_sender)
+            .state(registry)
+            .state(TestConfig { width: 4 });
+
+        let set = |key: Option<&str>, value: Option<&str>| StateCommand::Set {
+            key: key.map(str::to_string),
+            value: value.map(str::to_string),
+        };
+        set(Some("width"), Some("8")).apply(&mut state).await;
+        set(None, None).apply(&mut state).await;
+        set(Some("test.width?"), None).apply(&mut state).await;
+        set(Some("height"), Some("2")).apply(&mut state).await;
+        set(Some("width"), None).apply(&mut state).await;
+
+        let mut log = state.lock_state::<LogState>().await;
+        log.poll_messages();
+        let messages: Vec<_> = log.history().iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Settings:\ntest.width: usize = 8",
+                "test.width = 8",
+                "No setting named `height`",
+                "Missing a value for `width`, use `set width?` to see it",
+            ]
+        );
+    }
+}
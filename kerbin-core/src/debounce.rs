@@ -54,6 +54,7 @@ pub async fn update_debounce(
     command_registry: Res<CommandRegistry>,
     prefix_registry: Res<CommandPrefixRegistry>,
     command_sender: Res<CommandSender>,
+    activity: Res<FrameActivity>,
 ) {
     get!(
         mut buffers,
@@ -61,7 +62,8 @@ pub async fn update_debounce(
         modes,
         command_registry,
         prefix_registry,
-        command_sender
+        command_sender,
+        activity
     );
 
     let Some(mut buf) = buffers.cur_text_buffer_mut().await else { return; };
@@ -84,6 +86,7 @@ pub async fn update_debounce(
         (None, false) if debounce.flag() => {
             // Idle after changes - start timer
             debounce.reset(current_mode);
+            activity.mark();
             return;
         }
         (None, _) => return, // No active debounce
@@ -96,6 +99,10 @@ pub async fn update_debounce(
     }
 
     let Some((start, _)) = debounce.state else { return; };
+    if debounce.triggered_events.len() < events.len() {
+        // Keep ticking until every event has had its chance to fire
+        activity.mark();
+    }
     let elapsed = Instant::now().duration_since(start).as_millis();
     let engine = resolver_engine().await;
 
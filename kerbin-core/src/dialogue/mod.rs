@@ -23,13 +23,15 @@ pub struct DialogueState {
     pub commands: Vec<Token>,
     /// Tokens to execute on each input change; each element should be a Token::List
     pub on_change: Vec<Token>,
+    /// Tokens to execute when the dialogue is cancelled; each element should be a Token::List
+    pub on_cancel: Vec<Token>,
     /// Name of the resolver template variable set to the user's input on submit/change
     pub var_name: String,
     pub input_valid: bool,
 }
 
 /// Executes the on-change commands with `%var_name` set to the current input.
-/// Called after each `DialoguePush` / `DialoguePop`.
+/// Called after each `DialoguePush` / `DialoguePop`, and with the on-cancel commands on cancel.
 pub async fn run_dialogue_on_change(
     state: &mut State,
     on_change: &[Token],
@@ -83,7 +85,8 @@ pub async fn update_dialogue_validation(
         Some(&resolver_engine().await.as_resolver()),
         &prefix_registry,
         &modes,
-    );
+    )
+    .is_ok();
 }
 
 pub async fn register_dialogue_chunk(
@@ -111,3 +111,12 @@ impl Hook for ResetState {
         HookInfo::new("reset_state")
     }
 }
+
+/// Runs once after the main loop ends and the terminal is restored, right before the
+/// editor exits. Plugins should stop anything that would outlive the editor here.
+pub struct Shutdown;
+impl Hook for Shutdown {
+    fn info(&self) -> HookInfo {
+        HookInfo::new("shutdown")
+    }
+}
@@ -3,6 +3,7 @@ use crossterm::event::{Event, KeyEvent};
 use ratatui::prelude::*;
 use ratatui::widgets::{Block, BorderType};
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Keybinding {
@@ -31,29 +32,155 @@ pub struct Metadata {
     pub desc: String,
 }
 
+/// Key sequence settings, reachable as `set input.<field>`
+#[derive(State, ConfigurableState, Default)]
+#[configurable(name = "input")]
+pub struct InputConfig {
+    /// Milliseconds to wait for the next key of a sequence before dropping it (0 waits forever)
+    pub timeout_ms: u64,
+}
+
 #[derive(State, Default)]
 pub struct InputState {
     pub repeat_count: String,
 
     pub tree: KeyTree<Vec<String>, Metadata>,
+
+    /// When the last key of a partial sequence was pressed
+    pub last_step: Option<Instant>,
+
+    /// Every binding registered through `bind`, in registration order
+    pub bindings: Vec<Keybinding>,
+}
+
+impl InputState {
+    /// Registers a binding in the key tree, remembering it for listings
+    pub fn bind(
+        &mut self,
+        resolver: &Resolver,
+        keys: Vec<UnresolvedKeyBind>,
+        commands: Vec<String>,
+        metadata: Metadata,
+    ) -> Result<(), kerbin_input::ParseError> {
+        self.tree
+            .register(resolver, keys.clone(), commands.clone(), Some(metadata.clone()))?;
+        self.bindings.push(Keybinding {
+            keys,
+            commands,
+            metadata,
+        });
+        Ok(())
+    }
+
+    /// Bindings that apply in `mode`, in registration order
+    pub fn bindings_for_mode(&self, mode: char) -> Vec<&Keybinding> {
+        self.bindings
+            .iter()
+            .filter(|binding| binding.metadata.applies_in(mode))
+            .collect()
+    }
+
+    /// Bindings that apply in `mode` and continue the typed `partial` sequence, so at
+    /// least one more key is needed to reach them
+    pub fn continuations(&self, mode: char, partial: &[UnresolvedKeyBind]) -> Vec<&Keybinding> {
+        self.bindings_for_mode(mode)
+            .into_iter()
+            .filter(|binding| {
+                binding.keys.len() > partial.len() && binding.keys.starts_with(partial)
+            })
+            .collect()
+    }
+}
+
+impl Metadata {
+    /// Ranks a binding against the mode stack (lower wins), or `None` if it doesn't apply.
+    ///
+    /// The rank is the depth of the binding's highest mode on the stack, so with `v` pushed
+    /// over `n` a `v` binding beats an `n` binding for the same key. Bindings without modes
+    /// rank last, and any invalid mode on the stack disables the binding outright.
+    pub fn stack_rank(&self, modes: &ModeStack) -> Option<u32> {
+        if self.invalid_modes.iter().any(|x| modes.mode_on_stack(*x)) {
+            return None;
+        }
+
+        if self.modes.is_empty() {
+            return Some(u32::MAX);
+        }
+
+        self.modes
+            .iter()
+            .filter_map(|x| modes.where_on_stack(*x))
+            .min()
+            .map(|x| x as u32)
+    }
+
+    /// Whether a binding with this metadata applies when `mode` alone is active
+    pub fn applies_in(&self, mode: char) -> bool {
+        !self.invalid_modes.contains(&mode) && (self.modes.is_empty() || self.modes.contains(&mode))
+    }
+
+    /// Whether a binding with this metadata applies to the current mode stack
+    pub fn modes_allow(&self, modes: &ModeStack) -> bool {
+        self.stack_rank(modes).is_some()
+    }
+}
+
+/// The `append` command a macro replays for pasted `text`, kept on one line since macro
+/// steps are split by lines
+fn paste_command(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '"' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '%' => escaped.push_str("%%"),
+            c => escaped.push(c),
+        }
+    }
+    format!("append \"{escaped}\"")
+}
+
+/// Continuations of the partial key sequence that apply to the current modes
+fn which_key_entries(input: &InputState, modes: &ModeStack) -> Vec<(String, String)> {
+    let metadata = match input
+        .tree
+        .collect_layer_metadata_with(|meta| meta.is_none_or(|m| m.modes_allow(modes)))
+    {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!("which_key_entries: failed to collect layer metadata: {e:?}");
+            return vec![];
+        }
+    };
+
+    metadata
+        .into_iter()
+        .map(|(key, meta)| (key.to_string(), meta.map(|m| m.desc).unwrap_or_default()))
+        .collect()
 }
 
 pub async fn register_help_menu_chunk(
     window: Res<WindowState>,
     chunks: ResMut<Chunks>,
     input: Res<InputState>,
+    modes: Res<ModeStack>,
+    core_config: Res<CoreConfig>,
 ) {
-    get!(input);
+    get!(input, modes, core_config);
 
-    if input.tree.active_tree().is_none() {
+    if input.tree.active_tree().is_none() || !core_config.which_key {
         return;
     }
 
-    let Ok(metadata) = input.tree.collect_layer_metadata() else {
-        tracing::error!("register_help_menu_chunk: failed to collect layer metadata");
+    let entries = which_key_entries(&input, &modes);
+    if entries.is_empty() {
         return;
-    };
-    let menu_height = metadata.len() as u16 + 2;
+    }
+    let menu_height = entries.len() as u16 + 2;
 
     get!(mut chunks, window);
 
@@ -77,8 +204,13 @@ pub async fn register_help_menu_chunk(
     chunks.register_chunk::<HelpChunk>(1, help_area);
 }
 
-pub async fn render_help_menu(chunk: Chunk<HelpChunk>, input: Res<InputState>) {
-    get!(input);
+pub async fn render_help_menu(
+    chunk: Chunk<HelpChunk>,
+    input: Res<InputState>,
+    modes: Res<ModeStack>,
+    theme: Res<Theme>,
+) {
+    get!(input, modes, theme);
     if input.tree.active_tree().is_none() {
         return;
     }
@@ -86,32 +218,43 @@ pub async fn render_help_menu(chunk: Chunk<HelpChunk>, input: Res<InputState>) {
     let Some(mut chunk) = chunk.get().await else { return; };
     let area = chunk.area();
 
+    let border_style = theme.get_fallback_default(["ui.which_key.border", "ui.text"]);
+    let title_style = theme.get_fallback_default(["ui.which_key.title", "ui.text"]);
+    let key_style = theme.get_fallback_default(["ui.which_key.key", "ui.text"]);
+    let desc_style = theme.get_fallback_default(["ui.which_key.desc", "ui.text"]);
+
+    let typed = input
+        .tree
+        .current_sequence()
+        .iter()
+        .map(|k| k.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+
     Block::bordered()
         .border_type(BorderType::Plain)
+        .border_style(border_style)
+        .title(Span::styled(format!(" {typed} "), title_style))
         .render(area, &mut chunk);
 
-    let Ok(metadata) = input.tree.collect_layer_metadata() else {
-        tracing::error!("render_help_menu: failed to collect layer metadata");
-        return;
-    };
+    let entries = which_key_entries(&input, &modes);
+    let key_width = entries.iter().map(|(k, _)| k.width()).max().unwrap_or(0);
 
-    for (i, data) in metadata
+    for (i, (key, desc)) in entries
         .iter()
         .enumerate()
-        .take(metadata.len().min(area.height as usize - 2))
+        .take(area.height.saturating_sub(2) as usize)
     {
-        let key_str = data.0.to_string();
-        let desc_str = data.1.as_ref().map(|x| x.desc.as_str()).unwrap_or_default();
-        let line_text = format!("{}   {}", key_str, desc_str);
-        chunk.set_string(
-            area.x + 1,
-            area.y + 1 + i as u16,
-            &line_text,
-            Style::default(),
-        );
+        let line = Line::from(vec![
+            Span::styled(format!("{key:<key_width$}"), key_style),
+            Span::raw("   "),
+            Span::styled(desc.clone(), desc_style),
+        ]);
+        chunk.set_line(area.x + 1, area.y + 1 + i as u16, &line, area.width.saturating_sub(2));
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_inputs(
     events: Res<CrosstermEvents>,
     input: ResMut<InputState>,
@@ -122,21 +265,52 @@ pub async fn handle_inputs(
     command_sender: ResMut<CommandSender>,
 
     log: Res<LogSender>,
+    core_config: Res<CoreConfig>,
+    input_config: Res<InputConfig>,
+    macros: ResMut<MacroState>,
+    activity: Res<FrameActivity>,
 ) {
-    get!(events, mut input, modes, log);
+    get!(events, mut input, modes, log, core_config, input_config, mut macros, activity);
+
+    // Bindings to run this frame, in the order they fired
+    let mut fired = vec![];
+
+    let overdue = |ms: u64| {
+        ms > 0
+            && input
+                .last_step
+                .is_some_and(|t| t.elapsed() >= Duration::from_millis(ms))
+    };
+
+    if input.tree.has_pending() {
+        // An ambiguous binding stops waiting for a longer one after its own timeout
+        if overdue(core_config.ambiguous_key_timeout_ms) || overdue(input_config.timeout_ms) {
+            fired.extend(input.tree.flush_pending());
+            input.last_step = None;
+        } else {
+            // Keep frames coming so the timeout is noticed on time
+            activity.mark();
+        }
+    } else if input.tree.active_tree().is_some() && overdue(input_config.timeout_ms) {
+        // Drop a partial sequence once the next key is overdue
+        input.tree.reset();
+        input.last_step = None;
+    }
 
-    if events.0.is_empty() {
+    if events.0.is_empty() && fired.is_empty() {
         return;
     }
 
     for event in &events.0 {
-        if let Event::Paste(text) = event
-            && let Err(e) = command_sender.get().await.send(Box::new(BufferCommand::Append {
+        if let Event::Paste(text) = event {
+            macros.record(paste_command(text));
+            if let Err(e) = command_sender.get().await.send(Box::new(BufferCommand::Append {
                 text: text.clone(),
                 extend: false,
             })) {
                 log.high("input", format!("Failed to send paste command: {e}"));
             }
+        }
     }
 
     let resolver_engine = resolver_engine().await;
@@ -147,18 +321,14 @@ pub async fn handle_inputs(
             continue;
         };
         let event: &KeyEvent = event;
-        match input
+        let mut result = input
             .tree
             .step(&resolver, event.code, event.modifiers, |data| {
                 let Some(data) = data else {
                     return Some(u32::MAX);
                 };
 
-                let mode_ok =
-                    data.modes.is_empty() || data.modes.iter().any(|x| modes.mode_on_stack(*x));
-
-                let invalid_mode_present =
-                    data.invalid_modes.iter().any(|x| modes.mode_on_stack(*x));
+                let rank = data.stack_rank(&modes)?;
 
                 let templates_ok = data.required_templates.is_empty()
                     || data
@@ -166,65 +336,23 @@ pub async fn handle_inputs(
                         .iter()
                         .all(|x| resolver_engine.has_template(x));
 
-                if mode_ok && !invalid_mode_present && templates_ok {
-                    Some(
-                        data.modes
-                            .iter()
-                            .filter_map(|x| modes.where_on_stack(*x))
-                            .max()
-                            .map(|x| x as u32)
-                            .unwrap_or(u32::MAX),
-                    )
-                } else {
-                    None
-                }
-            }) {
-            Ok(StepResult::Success(sequence, commands, meta)) => {
-                drop(resolver);
-                drop(resolver_engine);
-
-                let mut resolver = resolver_engine_mut().await;
-                for (i, key) in sequence.iter().enumerate() {
-                    resolver.set_template(i, key.to_string());
-                }
-
-                let mut repeat = 1;
-                if !input.repeat_count.is_empty() && !meta.map(|x| x.deny_repeat).unwrap_or(false) {
-                    repeat = input.repeat_count.parse().unwrap_or(1).max(1);
-                    input.repeat_count.clear();
-                }
-
-                let resolver = resolver.as_resolver();
-                'outer: for _ in 0..repeat {
-                    for command_str in &commands {
-                        let registry = prefix_registry.get().await;
-                        let parsed = command_registry.get().await.parse_command(
-                            tokenize(command_str).unwrap_or_default(),
-                            true,
-                            false,
-                            Some(&resolver),
-                            true,
-                            &registry,
-                            &modes,
-                        );
-                        if let Some(command) = parsed {
-                            let _ = command_sender.get().await.send(command);
-                        } else {
-                            log.critical(
-                                "input",
-                                format!("Invalid command in keybind: {command_str}"),
-                            );
-                            break 'outer;
-                        }
-                    }
-                }
+                templates_ok.then_some(rank)
+            });
 
-                input.tree.reset();
+        // The binding this key interrupted runs first, then the key is handled on its own
+        while let Ok(StepResult::Interrupted(sequence, commands, meta, then)) = result {
+            fired.push((sequence, commands, meta));
+            result = Ok(*then);
+        }
 
+        match result {
+            Ok(StepResult::Success(sequence, commands, meta)) => {
+                fired.push((sequence, commands, meta));
+                input.last_step = None;
                 break;
             }
-            Ok(StepResult::Step) => {}
-            Ok(StepResult::Reset) => {}
+            Ok(StepResult::Step) => input.last_step = Some(Instant::now()),
+            Ok(StepResult::Reset | StepResult::Interrupted(..)) => input.last_step = None,
             Err(e) => {
                 log.critical(
                     "input::step",
@@ -233,4 +361,349 @@ pub async fn handle_inputs(
             }
         }
     }
+
+    drop(resolver);
+    drop(resolver_engine);
+
+    // Cleared once a binding finishes or cancels the operator, so later bindings this
+    // frame run normally
+    let mut operator_pending = modes.get_mode() == OPERATOR_MODE;
+
+    for (sequence, commands, meta) in fired {
+        // Only bindings made for operator-pending mode (motions and text objects) finish
+        // the operator. Counts keep it waiting, and any other key cancels it unrun
+        let is_motion = meta.as_ref().is_some_and(|m| m.modes.contains(&OPERATOR_MODE));
+        if operator_pending && !is_motion && !meta.as_ref().is_some_and(|m| m.deny_repeat) {
+            operator_pending = false;
+            input.repeat_count.clear();
+            macros.record("operator-cancel".to_string());
+            let _ = command_sender.get().await.send(Box::new(OperatorCommand::Cancel));
+            continue;
+        }
+
+        let mut resolver = resolver_engine_mut().await;
+        for (i, key) in sequence.iter().enumerate() {
+            resolver.set_template(i, key.to_string());
+        }
+
+        let mut repeat = 1;
+        if !input.repeat_count.is_empty() && !meta.map(|x| x.deny_repeat).unwrap_or(false) {
+            repeat = input.repeat_count.parse().unwrap_or(1).max(1);
+            input.repeat_count.clear();
+        }
+
+        let resolver = resolver.as_resolver();
+        // A counted bind runs as one sequence, so a single undo reverts every repeat
+        let mut repeated: Vec<Box<dyn Command<State>>> = vec![];
+        'outer: for _ in 0..repeat {
+            for command_str in &commands {
+                let registry = prefix_registry.get().await;
+                let parsed = command_registry.get().await.parse_command(
+                    tokenize(command_str).unwrap_or_default(),
+                    true,
+                    false,
+                    Some(&resolver),
+                    true,
+                    &registry,
+                    &modes,
+                );
+                if let Some(command) = parsed {
+                    // Record with templates filled in, since the keys that set
+                    // them won't be pressed again on replay
+                    if macros.recording().is_some()
+                        && !matches!(
+                            command.as_any().downcast_ref::<MacroCommand>(),
+                            Some(MacroCommand::Record(_))
+                        )
+                    {
+                        let tokens = tokenize(command_str).unwrap_or_default();
+                        macros.record(tokens_to_command_string(
+                            &resolver.expand_tokens(tokens, false),
+                        ));
+                    }
+
+                    if repeat > 1 {
+                        repeated.push(command);
+                    } else {
+                        let _ = command_sender.get().await.send(command);
+                    }
+                } else {
+                    log.critical(
+                        "input",
+                        format!("Invalid command in keybind: {command_str}"),
+                    );
+                    break 'outer;
+                }
+            }
+        }
+        if !repeated.is_empty() {
+            let _ = command_sender.get().await.send(Box::new(CommandSequence(repeated)));
+        }
+
+        // The motion has run, so the operator covers whatever it selected
+        if operator_pending && is_motion {
+            operator_pending = false;
+            macros.record("operator-apply".to_string());
+            let _ = command_sender.get().await.send(Box::new(OperatorCommand::Apply));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+    use std::{collections::HashMap, sync::Arc};
+
+    fn meta(modes: &[char], invalid_modes: &[char]) -> Metadata {
+        Metadata {
+            modes: modes.to_vec(),
+            invalid_modes: invalid_modes.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// Registers each `(key, command, metadata)` and presses `key` with `stack` active
+    fn press(
+        binds: Vec<(&str, &str, Metadata)>,
+        stack: &[char],
+        key: char,
+    ) -> Option<Vec<String>> {
+        press_code(binds, stack, KeyCode::Char(key))
+    }
+
+    /// Like `press`, for keys other than characters
+    fn press_code(
+        binds: Vec<(&str, &str, Metadata)>,
+        stack: &[char],
+        key: KeyCode,
+    ) -> Option<Vec<String>> {
+        let templates = HashMap::new();
+        let resolver = Resolver::new(&templates, Arc::new(|_, _| Ok(vec![])));
+        let modes = ModeStack(stack.to_vec());
+
+        let mut tree = KeyTree::<Vec<String>, Metadata>::default();
+        for (keys, cmd, metadata) in binds {
+            tree.register(
+                &resolver,
+                vec![keys.parse().unwrap()],
+                vec![cmd.to_string()],
+                Some(metadata),
+            )
+            .unwrap();
+        }
+
+        match tree
+            .step(&resolver, key, KeyModifiers::NONE, |data| {
+                data.map_or(Some(u32::MAX), |d| d.stack_rank(&modes))
+            })
+            .unwrap()
+        {
+            StepResult::Success(_, cmds, _) => Some(cmds),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn top_of_stack_wins() {
+        let binds = || {
+            vec![
+                ("d", "delete_line", meta(&['n'], &[])),
+                ("d", "delete_selection", meta(&['v'], &[])),
+            ]
+        };
+
+        assert_eq!(press(binds(), &['n', 'v'], 'd'), Some(vec!["delete_selection".into()]));
+        assert_eq!(press(binds(), &['n'], 'd'), Some(vec!["delete_line".into()]));
+    }
+
+    #[test]
+    fn lower_modes_still_apply_when_unshadowed() {
+        let binds = vec![("x", "cut", meta(&['n'], &[]))];
+        assert_eq!(press(binds, &['n', 'v'], 'x'), Some(vec!["cut".into()]));
+    }
+
+    #[test]
+    fn invalid_mode_anywhere_on_stack_disables() {
+        let binds = vec![("p", "paste", meta(&['n'], &['v']))];
+        assert_eq!(press(binds, &['n', 'v'], 'p'), None);
+
+        let binds = vec![("p", "paste", meta(&['v'], &['n']))];
+        assert_eq!(press(binds, &['n', 'v'], 'p'), None);
+    }
+
+    #[test]
+    fn remapped_insert_keys_replace_the_defaults() {
+        let binds = || {
+            vec![
+                ("tab", "a %tab_unit", meta(&['i'], &[])),
+                ("delete", "d", meta(&['i'], &[])),
+                ("tab", "complete", meta(&['i'], &[])),
+            ]
+        };
+
+        assert_eq!(press_code(binds(), &['n', 'i'], KeyCode::Tab), Some(vec!["complete".into()]));
+        assert_eq!(press_code(binds(), &['n', 'i'], KeyCode::Delete), Some(vec!["d".into()]));
+        // Outside of insert mode, neither applies
+        assert_eq!(press_code(binds(), &['n'], KeyCode::Tab), None);
+    }
+
+    #[test]
+    fn continuations_follow_the_typed_prefix() {
+        let templates = HashMap::new();
+        let resolver = Resolver::new(&templates, Arc::new(|_, _| Ok(vec![])));
+
+        let mut input = InputState::default();
+        for (keys, desc, metadata) in [
+            ("g g", "Goto start", meta(&['n', 'v'], &[])),
+            ("g e", "Goto end", meta(&[], &[])),
+            ("g d", "Goto definition", meta(&['n'], &['v'])),
+            ("g", "Not a continuation", meta(&['n'], &[])),
+            ("d d", "Delete line", meta(&['n'], &[])),
+        ] {
+            let keys = keys.split(' ').map(|k| k.parse().unwrap()).collect();
+            let metadata = Metadata {
+                desc: desc.to_string(),
+                ..metadata
+            };
+            input.bind(&resolver, keys, vec![], metadata).unwrap();
+        }
+
+        let descs = |mode| {
+            let prefix = ["g".parse().unwrap()];
+            input
+                .continuations(mode, &prefix)
+                .iter()
+                .map(|b| b.metadata.desc.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(descs('n'), ["Goto start", "Goto end", "Goto definition"]);
+        assert_eq!(descs('v'), ["Goto start", "Goto end"]);
+        assert_eq!(input.bindings_for_mode('v').len(), 2);
+    }
+
+    /// Presses `key` through `handle_inputs` with `stack` active and names what it sent
+    async fn sent_for_key(stack: &[char], key: char) -> Vec<&'static str> {
+        let templates = HashMap::new();
+        let resolver = Resolver::new(&templates, Arc::new(|_, _| Ok(vec![])));
+
+        let mut input = InputState::default();
+        for (key, command, metadata) in [
+            ("u", "u", meta(&[], &[])),
+            ("w", "sl", meta(&['o'], &[])),
+            ("1", "sc", Metadata { deny_repeat: true, ..Default::default() }),
+        ] {
+            let keys = vec![key.parse().unwrap()];
+            input.bind(&resolver, keys, vec![command.to_string()], metadata).unwrap();
+        }
+
+        let mut registry = CommandRegistry(vec![]);
+        registry.register::<BufferCommand>();
+        registry.register::<MotionCommand>();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (_log_state, log_sender) = LogState::new_with_channel();
+        let key = Event::Key(KeyEvent::new(KeyCode::Char(key), KeyModifiers::NONE));
+
+        let mut state = State::new();
+        state
+            .state(CrosstermEvents(vec![key]))
+            .state(input)
+            .state(ModeStack(stack.to_vec()))
+            .state(registry)
+            .state(CommandPrefixRegistry(vec![]))
+            .state(CommandSender(sender))
+            .state(log_sender)
+            .state(CoreConfig::default())
+            .state(InputConfig::default())
+            .state(MacroState::default())
+            .state(FrameActivity::default());
+        state.call(handle_inputs).await;
+
+        let mut sent = vec![];
+        while let Ok(command) = receiver.try_recv() {
+            let any = command.as_any();
+            sent.push(match any.downcast_ref::<OperatorCommand>() {
+                Some(OperatorCommand::Apply) => "apply",
+                Some(OperatorCommand::Cancel) => "cancel",
+                Some(_) => "operator",
+                None if any.is::<BufferCommand>() => "buffer",
+                None if any.is::<MotionCommand>() => "motion",
+                None => "other",
+            });
+        }
+        sent
+    }
+
+    #[tokio::test]
+    async fn operators_finish_on_motions_and_cancel_on_other_keys() {
+        // `d w` selects, then deletes
+        assert_eq!(sent_for_key(&['n', 'o'], 'w').await, ["motion", "apply"]);
+
+        // `d u` drops the operator without undoing anything
+        assert_eq!(sent_for_key(&['n', 'o'], 'u').await, ["cancel"]);
+
+        // A count keeps the operator waiting for its motion
+        assert_eq!(sent_for_key(&['n', 'o'], '1').await, ["motion"]);
+
+        // Outside operator-pending mode nothing changes
+        assert_eq!(sent_for_key(&['n'], 'u').await, ["buffer"]);
+    }
+
+    #[tokio::test]
+    async fn pastes_are_recorded_into_macros() {
+        let pasted = "fn a() {\n    \"100%\" \\ $(x) %name\r\n}";
+
+        let mut macros = MacroState::default();
+        macros.start('q');
+
+        let mut registry = CommandRegistry(vec![]);
+        registry.register::<BufferCommand>();
+
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (_log_state, log_sender) = LogState::new_with_channel();
+
+        let mut state = State::new();
+        state
+            .state(CrosstermEvents(vec![Event::Paste(pasted.to_string())]))
+            .state(InputState::default())
+            .state(ModeStack(vec!['n', 'i']))
+            .state(registry)
+            .state(CommandPrefixRegistry(vec![]))
+            .state(CommandSender(sender))
+            .state(log_sender)
+            .state(CoreConfig::default())
+            .state(InputConfig::default())
+            .state(macros)
+            .state(FrameActivity::default());
+        state.call(handle_inputs).await;
+
+        let (_, recorded) = state.lock_state::<MacroState>().await.stop().unwrap();
+        assert_eq!(recorded.lines().count(), 1);
+
+        // Replaying the step pastes the same text
+        let templates = HashMap::new();
+        let resolver = Resolver::new(&templates, Arc::new(|_, _| Ok(vec![])));
+        let command = state
+            .lock_state::<CommandRegistry>()
+            .await
+            .parse_command(
+                tokenize(&recorded).unwrap(),
+                true,
+                false,
+                Some(&resolver),
+                true,
+                &CommandPrefixRegistry(vec![]),
+                &ModeStack(vec!['n']),
+            )
+            .unwrap();
+        match command.as_any().downcast_ref::<BufferCommand>() {
+            Some(BufferCommand::Append { text, extend }) => {
+                assert_eq!(text, pasted);
+                assert!(!extend);
+            }
+            _ => panic!("expected `{recorded}` to replay as an append"),
+        }
+    }
 }
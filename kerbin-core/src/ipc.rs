@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use crate::*;
 use ipmpsc::{Receiver, Sender, SharedRingBuffer};
 use serde::{Deserialize, Serialize};
@@ -5,7 +7,22 @@ use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ClientMessage {
-    Command { id: Uuid, command: String },
+    /// Runs `command`. If `reply` is set, it's the path of a ring buffer the client is
+    /// waiting on for a `CommandReply`.
+    Command {
+        id: Uuid,
+        command: String,
+        reply: Option<String>,
+    },
+}
+
+/// Sent back to a client that asked for a reply, once its command has run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommandReply {
+    pub id: Uuid,
+    pub success: bool,
+    /// Messages logged while the command ran, like the text of `echo`
+    pub output: Vec<String>,
 }
 
 pub fn sessions_dir() -> String {
@@ -27,17 +44,23 @@ fn get_queue_path(session_id: &str) -> String {
     format!("{}/{}.in", sessions_dir(), session_id)
 }
 
+fn get_reply_path(id: &Uuid) -> String {
+    format!("{}/{}.reply", sessions_dir(), id)
+}
+
 #[derive(State)]
 pub struct ServerIpc {
     in_queue: Receiver,
     in_file: String,
     pid_file: String,
+    name_file: String,
 }
 
 impl ServerIpc {
     pub fn new(session_id: &str) -> Result<Self, String> {
         let in_file = get_queue_path(session_id);
         let pid_file = session_pid_path(session_id);
+        let name_file = session_name_path(session_id);
 
         let _ = std::fs::create_dir_all(sessions_dir());
         let _ = std::fs::write(&pid_file, std::process::id().to_string());
@@ -49,6 +72,7 @@ impl ServerIpc {
             in_queue,
             in_file,
             pid_file,
+            name_file,
         })
     }
 
@@ -61,13 +85,65 @@ impl Drop for ServerIpc {
     fn drop(&mut self) {
         let _ = std::fs::remove_file(&self.in_file);
         let _ = std::fs::remove_file(&self.pid_file);
+        let _ = std::fs::remove_file(&self.name_file);
     }
 }
 
 pub struct ClientIpc;
 
 impl ClientIpc {
+    /// Sends a command without waiting for it to run
     pub fn send_command(session: &str, command: String) -> Result<(), String> {
+        Self::send(
+            session,
+            ClientMessage::Command {
+                id: Uuid::new_v4(),
+                command,
+                reply: None,
+            },
+        )
+    }
+
+    /// Sends a command and waits up to `timeout` for the editor to run it
+    pub fn run_command(
+        session: &str,
+        command: String,
+        timeout: Duration,
+    ) -> Result<CommandReply, String> {
+        let id = Uuid::new_v4();
+        let reply_file = get_reply_path(&id);
+
+        let _ = std::fs::create_dir_all(sessions_dir());
+        let ring = SharedRingBuffer::create(&reply_file, 16000)
+            .map_err(|e| format!("Failed to create reply buffer at '{reply_file}': {e}"))?;
+        let replies = Receiver::new(ring);
+
+        let res = Self::send(
+            session,
+            ClientMessage::Command {
+                id,
+                command,
+                reply: Some(reply_file.clone()),
+            },
+        )
+        .and_then(|_| {
+            replies
+                .recv_timeout::<CommandReply>(timeout)
+                .map_err(|e| format!("Error reading reply: {:?}", e))?
+                .ok_or_else(|| {
+                    format!(
+                        "Session '{}' didn't answer within {}ms.",
+                        session,
+                        timeout.as_millis()
+                    )
+                })
+        });
+
+        let _ = std::fs::remove_file(&reply_file);
+        res
+    }
+
+    fn send(session: &str, msg: ClientMessage) -> Result<(), String> {
         let in_file = get_queue_path(session);
 
         let ring = SharedRingBuffer::open(&in_file)
@@ -75,17 +151,76 @@ impl ClientIpc {
 
         let in_queue = Sender::new(ring);
 
-        let msg = ClientMessage::Command {
-            id: Uuid::new_v4(),
-            command,
-        };
-
         in_queue
             .send(&msg)
             .map_err(|e| format!("Error sending command: {:?}", e))
     }
 }
 
+/// Runs a command sent over IPC, then writes a `CommandReply` to the client's ring buffer
+struct ReplyingCommand {
+    id: Uuid,
+    reply: String,
+    inner: Box<dyn Command<State>>,
+}
+
+impl CommandAny for ReplyingCommand {
+    fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Command<State> for ReplyingCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        let (success, output) = run_for_reply(self.inner.as_ref(), state).await;
+        send_reply(
+            &self.reply,
+            CommandReply {
+                id: self.id,
+                success,
+                output,
+            },
+        );
+
+        success
+    }
+}
+
+/// Runs `command`, returning whether it succeeded and the messages it logged, oldest first.
+/// A command fails by logging a warning or error; messages from `echo` are its output at
+/// any level
+async fn run_for_reply(command: &dyn Command<State>, state: &mut State) -> (bool, Vec<String>) {
+    let start = state.lock_state::<LogSender>().await.peek_id();
+    dispatch_command(command, state).await;
+    let end = state.lock_state::<LogSender>().await.peek_id();
+
+    let mut log = state.lock_state::<LogState>().await;
+    log.poll_messages();
+    let logged: Vec<&LogEntry> = log
+        .history()
+        .iter()
+        .filter(|entry| (start..end).contains(&entry.id))
+        .collect();
+
+    let success = !logged
+        .iter()
+        .any(|entry| entry.level >= Level::Medium && entry.origin != "echo");
+    let output = logged.iter().map(|entry| entry.message.clone()).collect();
+    (success, output)
+}
+
+fn send_reply(path: &str, reply: CommandReply) {
+    // The client deletes its buffer when it stops waiting, so a missing one isn't an error
+    let Ok(ring) = SharedRingBuffer::open(path) else {
+        return;
+    };
+
+    if let Err(e) = Sender::new(ring).send(&reply) {
+        tracing::error!("Failed to send IPC reply: {e:?}");
+    }
+}
+
 pub async fn handle_ipc_messages(state: &mut State) {
     let log = state.lock_state::<LogSender>().await.clone();
 
@@ -95,25 +230,94 @@ pub async fn handle_ipc_messages(state: &mut State) {
 
     for msg in messages {
         match msg {
-            ClientMessage::Command { id: _, command } => {
+            ClientMessage::Command { id, command, reply } => {
                 let commands = state.lock_state::<CommandRegistry>().await;
                 let command_sender = state.lock_state::<CommandSender>().await;
                 let prefix_registry = state.lock_state::<CommandPrefixRegistry>().await;
                 let modes = state.lock_state::<ModeStack>().await;
 
-                if let Some(cmd) = commands.parse_command(
+                let cmd = commands.try_parse_command(
                     tokenize(&command).unwrap_or_default(),
                     true,
-                    false,
                     Some(&resolver_engine().await.as_resolver()),
                     true,
                     &prefix_registry,
                     &modes,
-                ) && let Err(e) = command_sender.send(cmd)
-                {
+                );
+
+                let cmd = match (cmd, reply) {
+                    (Ok(cmd), None) => cmd,
+                    (Ok(inner), Some(reply)) => Box::new(ReplyingCommand { id, reply, inner }),
+                    (Err(e), reply) => {
+                        tracing::error!("Failed to parse command: {e}");
+                        if let Some(reply) = reply {
+                            send_reply(
+                                &reply,
+                                CommandReply {
+                                    id,
+                                    success: false,
+                                    output: vec![format!("Failed to parse command: {e}")],
+                                },
+                            );
+                        }
+                        continue;
+                    }
+                };
+
+                if let Err(e) = command_sender.send(cmd) {
                     log.medium("IPC", format!("Failed to send command: {:?}", e));
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn reply_state() -> State {
+        let (log_state, log_sender) = LogState::new_with_channel();
+
+        let mut buffers = Buffers::default();
+        buffers
+            .push_new(TextBuffer {
+                path: "<dirty>".into(),
+                dirty: true,
+                ..TextBuffer::scratch()
+            })
+            .await;
+
+        let mut state = State::new();
+        state
+            .state(buffers)
+            .state(CoreConfig::default())
+            .state(CommandInterceptorRegistry::new())
+            .state(Running(true))
+            .state(log_state)
+            .state(log_sender);
+        state
+    }
+
+    #[tokio::test]
+    async fn replies_report_logged_problems_as_failures() {
+        let mut state = reply_state().await;
+
+        // Nothing to move is still a success
+        let goto = BufferCommand::GotoLine {
+            line: "1".to_string(),
+            extend: false,
+        };
+        assert_eq!(run_for_reply(&goto, &mut state).await, (true, vec![]));
+
+        let (success, output) = run_for_reply(&StateCommand::Quit, &mut state).await;
+        assert!(!success && state.lock_state::<Running>().await.0);
+        assert!(output[0].starts_with("Unable to quit"));
+
+        let echo = DebugCommand::Echo {
+            text: vec!["hi".to_string()],
+            level: Some("high".to_string()),
+        };
+        assert_eq!(run_for_reply(&echo, &mut state).await, (true, vec!["hi".to_string()]));
+    }
+}
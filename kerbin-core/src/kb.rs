@@ -126,6 +126,52 @@ pub async fn load_kb(path: &Path, state: &mut State) -> Vec<KbLoadError> {
     errors
 }
 
+/// Resets config-managed state and loads `init.kb` from the config folder again,
+/// returning the errors it hit. Backs `reload_config` and `theme_reload`
+pub async fn reload_config(state: &mut State) -> Vec<KbLoadError> {
+    let config_path = state.lock_state::<ConfigFolder>().await.0.clone();
+    let kb_path = PathBuf::from(format!("{config_path}/init.kb"));
+
+    reset_config_state(state).await;
+
+    let errors = load_kb(&kb_path, state).await;
+    add_default_mouse_binds(state).await;
+
+    // Mirror the startup auto_pairs default logic (auto_pairs is on by default).
+    let disable_auto_pairs = state.lock_state::<CoreConfig>().await.disable_auto_pairs;
+    if !disable_auto_pairs {
+        let mut registry = state.lock_state::<CommandInterceptorRegistry>().await;
+        registry.remove_command_interceptor::<BufferCommand>("core::auto_pairs");
+        registry.on_command_named::<BufferCommand>(
+            "core::auto_pairs",
+            0,
+            |cmd, state| Box::pin(auto_pairs_intercept(cmd, state)),
+        );
+    }
+
+    errors
+}
+
+/// Adds the built-in mouse binds (click to move, wheel to scroll) once the config has
+/// loaded, unless it ran `core default_mouse_binds disable`. Binds from `mouse_bind` in the
+/// config take precedence over them
+pub async fn add_default_mouse_binds(state: &State) {
+    if !state.lock_state::<CoreConfig>().await.default_mouse_binds {
+        return;
+    }
+
+    let mut mb = state.lock_state::<MouseBindings>().await;
+    mb.bindings
+        .entry(MouseTrigger::LeftDown)
+        .or_insert_with(|| vec!["goto %mouse_col %mouse_line".to_string()]);
+    mb.bindings
+        .entry(MouseTrigger::ScrollUp)
+        .or_insert_with(|| vec!["scroll -3".to_string()]);
+    mb.bindings
+        .entry(MouseTrigger::ScrollDown)
+        .or_insert_with(|| vec!["scroll 3".to_string()]);
+}
+
 /// Reset all config-managed state to defaults, in preparation for reloading `.kb` files.
 ///
 /// Fires the `ResetState` hook so plugins can clear their own config-managed state.
@@ -133,10 +179,21 @@ pub async fn reset_config_state(state: &mut State) {
     state.hook(hooks::ResetState).call().await;
 
     *state.lock_state::<InputState>().await = InputState::default();
+    *state.lock_state::<InputConfig>().await = InputConfig::default();
+    *state.lock_state::<MouseBindings>().await = MouseBindings::default();
     *state.lock_state::<PaletteState>().await = PaletteState::default();
     *state.lock_state::<Theme>().await = Theme::default();
     state.lock_state::<CommandPrefixRegistry>().await.clear();
+    state.lock_state::<CommandAliasRegistry>().await.clear();
+    state.lock_state::<CommandRegistry>().await.restore_unregistered();
     *state.lock_state::<CoreConfig>().await = CoreConfig::default();
+    *state.lock_state::<WhitespaceConfig>().await = WhitespaceConfig::default();
+    {
+        // `shell_cd` is set while editing, not by the config, so it survives the reload
+        let mut shell = state.lock_state::<ShellConfig>().await;
+        shell.dir = ShellDir::default();
+        shell.env.clear();
+    }
     *state.lock_state::<DebounceConfig>().await = DebounceConfig::default();
     *state.lock_state::<StatuslineConfig>().await = StatuslineConfig::default();
     state
@@ -145,6 +202,7 @@ pub async fn reset_config_state(state: &mut State) {
         .remove_command_interceptor::<BufferCommand>("core::auto_pairs");
 
     *state.lock_state::<FiletypeRegistry>().await = FiletypeRegistry::default();
+    state.lock_state::<FiletypeConfig>().await.profiles.clear();
 
     // Invalidate cached filetypes so re-detection runs after reload
     let bufs = state.lock_state::<Buffers>().await;
@@ -152,6 +210,7 @@ pub async fn reset_config_state(state: &mut State) {
         let Ok(mut buf) = arc.clone().try_write_owned() else { continue };
         if let Some(tb) = buf.as_any_mut().downcast_mut::<TextBuffer>() {
             tb.filetype = None;
+            tb.remove_state::<AppliedFiletype>();
         }
     }
 }
@@ -2,12 +2,19 @@
 
 extern crate self as kerbin_core;
 
-use tracing::Level;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{Registry, prelude::*, reload};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-/// Initializes the logging system for the core editor
-pub fn init_log() {
+/// Reload handle for the file log level, and whether `--log-level` or `KERBIN_LOG` pinned it
+static LOG_LEVEL: OnceLock<(reload::Handle<LevelFilter, Registry>, bool)> = OnceLock::new();
+
+/// Initializes the logging system for the core editor.
+///
+/// The level is `level` (from `--log-level`), then the `KERBIN_LOG` env var, then `info`.
+/// Setting either of the first two keeps `core log_level` from changing it.
+pub fn init_log(level: Option<LevelFilter>) {
     let mut log_file_path = home_dir().expect("Home Directory Should Exist");
     log_file_path.push(".kerbin/kerbin.log");
 
@@ -18,26 +25,48 @@ pub fn init_log() {
         .open(log_file_path)
         .expect("file should be able to open");
 
-    tracing_subscriber::fmt()
-        .with_ansi(false)
-        .with_max_level(Level::INFO)
-        .with_writer(Mutex::new(log_file))
+    let env_level = std::env::var("KERBIN_LOG").ok().and_then(|l| l.parse().ok());
+    let pinned = level.or(env_level);
+
+    let (filter, handle) = reload::Layer::new(pinned.unwrap_or(LevelFilter::INFO));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(Mutex::new(log_file)),
+        )
         .init();
+
+    let _ = LOG_LEVEL.set((handle, pinned.is_some()));
+}
+
+/// Changes the file log level set by `init_log`.
+/// Returns false if logging isn't initialized or the level was pinned at startup.
+pub fn set_log_level(level: LevelFilter) -> bool {
+    match LOG_LEVEL.get() {
+        Some((handle, false)) => handle.reload(level).is_ok(),
+        _ => false,
+    }
 }
 
 pub extern crate async_trait;
 
 pub use kerbin_macros::*;
 
-use std::{env::home_dir, fs::File, sync::Mutex};
+use std::{
+    env::home_dir,
+    fs::File,
+    sync::{Mutex, OnceLock},
+};
 
 pub use kerbin_state_machine::*;
 
 pub use kerbin_input::*;
 
 pub use kerbin_command_lang::{
-    AsCommandInfo, Command, CommandAny, CommandFromStr, CommandInfo, CommandPrefix, CommandState,
-    Token, tokenize, token_to_string, tokens_to_command_string,
+    ArgCompleter, ArgError, ArgRange, AsCommandInfo, Command, CommandAny, CommandFromStr, CommandInfo, CommandPrefix, CommandState, ParseError, PrefixMatch,
+    Token, UNCATEGORIZED, tokenize, token_to_string, tokens_to_command_string,
 };
 
 pub use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
@@ -63,11 +92,17 @@ pub use theme::*;
 pub mod palette;
 pub use palette::*;
 
+pub mod picker;
+pub use picker::*;
+
 pub mod statusline;
 pub use statusline::*;
 
 pub mod hooks;
 
+pub mod pipeline;
+pub use pipeline::*;
+
 pub mod chunk;
 pub use chunk::*;
 
@@ -77,6 +112,15 @@ pub use chunks::*;
 pub mod rope_exts;
 pub use rope_exts::*;
 
+pub mod rope_diff;
+pub use rope_diff::*;
+
+pub mod style_exts;
+pub use style_exts::*;
+
+pub mod buffer_exts;
+pub use buffer_exts::*;
+
 pub mod logging;
 pub use logging::*;
 
@@ -92,6 +136,20 @@ pub use resolver::*;
 pub mod debounce;
 pub use debounce::*;
 
+pub mod yank_flash;
+pub use yank_flash::*;
+
+pub mod autosave;
+pub use autosave::*;
+
+pub mod mouse;
+pub use mouse::*;
+
+#[cfg(feature = "watcher")]
+pub mod watcher;
+#[cfg(feature = "watcher")]
+pub use watcher::*;
+
 pub mod kb;
 pub use kb::*;
 
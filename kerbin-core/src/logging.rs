@@ -1,4 +1,6 @@
-use std::time::{Duration, SystemTime};
+use std::collections::VecDeque;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::*;
 use ratatui::prelude::*;
@@ -9,12 +11,13 @@ pub async fn register_log_chunk(
     chunks: ResMut<Chunks>,
     window: Res<WindowState>,
     log: ResMut<LogState>,
+    filter: Res<LogFilter>,
 ) {
-    get!(mut log);
+    get!(mut log, filter);
 
     log.poll_messages();
 
-    if log.entries().is_empty() {
+    if log.visible_entries(&filter).next().is_none() {
         return;
     }
 
@@ -25,7 +28,7 @@ pub async fn register_log_chunk(
     let text_width = notification_width.saturating_sub(4);
 
     let mut total_height = 0u16;
-    for msg in log.entries().iter() {
+    for msg in log.visible_entries(&filter) {
         let line_count = Paragraph::new(msg.message.message.as_str())
             .wrap(Wrap { trim: false })
             .line_count(text_width as u16);
@@ -45,20 +48,35 @@ pub async fn register_log_chunk(
     chunks.register_chunk::<LogChunk>(1, chunk_rect);
 }
 
-pub async fn render_log(log_chunk: Chunk<LogChunk>, log: ResMut<LogState>, theme: Res<Theme>) {
+pub async fn render_log(
+    log_chunk: Chunk<LogChunk>,
+    log: ResMut<LogState>,
+    filter: Res<LogFilter>,
+    theme: Res<Theme>,
+) {
     let Some(mut chunk) = log_chunk.get().await else {
         return;
     };
-    get!(mut log, theme);
+    get!(mut log, filter, theme);
 
     log.poll_messages();
 
+    // Only a message's text changes once it's logged, so that and the theme cover the content
+    let mut hasher = DefaultHasher::new();
+    theme.revision().hash(&mut hasher);
+    for msg in log.visible_entries(&filter) {
+        (msg.id, &msg.message.message).hash(&mut hasher);
+    }
+    if !chunk.draw_version(hasher.finish()) {
+        return;
+    }
+
     let area = chunk.area();
     let max_width = area.width as usize;
 
     let mut y_offset = 0u16;
 
-    for msg in log.entries().iter() {
+    for msg in log.visible_entries(&filter) {
         let notification_height =
             render_notification(&mut chunk, msg, &theme, max_width, area, y_offset);
 
@@ -126,10 +144,10 @@ fn render_notification(
     notification_height
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct MessageId(u64);
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Level {
     Low,
     Medium,
@@ -137,6 +155,49 @@ pub enum Level {
     Critical,
 }
 
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Self::Low),
+            "medium" => Ok(Self::Medium),
+            "high" => Ok(Self::High),
+            "critical" => Ok(Self::Critical),
+            _ => Err(format!(
+                "Expected `low`, `medium`, `high`, or `critical`, found: {s}"
+            )),
+        }
+    }
+}
+
+/// Which messages are shown on screen. Hidden messages are still logged to file.
+#[derive(State)]
+pub struct LogFilter {
+    /// The lowest level shown
+    pub level: Level,
+    /// Only show messages whose origin starts with this, like `lsp` or `lsp::*`
+    pub source: Option<String>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self {
+            level: Level::Low,
+            source: None,
+        }
+    }
+}
+
+impl LogFilter {
+    pub fn matches(&self, message: &Message) -> bool {
+        message.level >= self.level
+            && self.source.as_deref().is_none_or(|prefix| {
+                message.origin.starts_with(prefix.trim_end_matches('*'))
+            })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Message {
     pub level: Level,
@@ -216,6 +277,12 @@ impl LogSender {
         )
     }
 
+    /// The id the next logged message will get. Messages logged between two calls have ids
+    /// in the range between the results.
+    pub fn peek_id(&self) -> MessageId {
+        MessageId(self.next_id.load(std::sync::atomic::Ordering::Relaxed))
+    }
+
     pub fn low(&self, origin: impl ToString, message: impl ToString) -> MessageId {
         let id = self.next_id();
         let _ = self.sender.send(LogCommand::Add(
@@ -267,6 +334,19 @@ impl LogSender {
     }
 }
 
+/// Most entries kept in the log history
+const MAX_LOG_HISTORY: usize = 500;
+
+/// A message kept in the log history after its notification expires
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    pub id: MessageId,
+    pub time: Instant,
+    pub level: Level,
+    pub origin: String,
+    pub message: String,
+}
+
 pub struct TimedMessage {
     pub id: MessageId,
     pub inserted: SystemTime,
@@ -277,6 +357,7 @@ pub struct TimedMessage {
 #[derive(State, Default)]
 pub struct LogState {
     messages: Vec<TimedMessage>,
+    history: VecDeque<LogEntry>,
     receiver: Option<UnboundedReceiver<LogCommand>>,
 }
 
@@ -286,6 +367,7 @@ impl LogState {
         (
             LogState {
                 messages: Vec::new(),
+                history: VecDeque::new(),
                 receiver: Some(rx),
             },
             LogSender {
@@ -302,6 +384,17 @@ impl LogState {
             while let Ok(command) = receiver.try_recv() {
                 match command {
                     LogCommand::Add(id, duration, message) => {
+                        if self.history.len() >= MAX_LOG_HISTORY {
+                            self.history.pop_front();
+                        }
+                        self.history.push_back(LogEntry {
+                            id,
+                            time: Instant::now(),
+                            level: message.level,
+                            origin: message.origin.clone(),
+                            message: message.message.clone(),
+                        });
+
                         self.messages.push(TimedMessage {
                             id,
                             inserted: now,
@@ -310,6 +403,9 @@ impl LogState {
                         });
                     }
                     LogCommand::Modify(id, new_message) => {
+                        if let Some(entry) = self.history.iter_mut().rev().find(|e| e.id == id) {
+                            entry.message = new_message.clone();
+                        }
                         if let Some(msg) = self.messages.iter_mut().find(|m| m.id == id) {
                             msg.message.message = new_message;
                             msg.inserted = now;
@@ -326,7 +422,67 @@ impl LogState {
             .retain(|m| now.duration_since(m.inserted).unwrap_or_default() <= m.duration);
     }
 
+    /// Active messages that pass `filter`, oldest first
+    pub fn visible_entries<'a>(
+        &'a self,
+        filter: &'a LogFilter,
+    ) -> impl Iterator<Item = &'a TimedMessage> {
+        self.messages.iter().filter(|m| filter.matches(&m.message))
+    }
+
+    /// Every message logged this session, oldest first, capped at `MAX_LOG_HISTORY`.
+    /// Entries stay here after their notification expires or is removed.
+    pub fn history(&self) -> &VecDeque<LogEntry> {
+        &self.history
+    }
+
     pub fn entries(&self) -> &[TimedMessage] {
         &self.messages
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hides_entries_below_display_level() {
+        let (mut log, sender) = LogState::new_with_channel();
+        sender.low("test", "low");
+        sender.high("test", "high");
+        sender.critical("test", "critical");
+        log.poll_messages();
+
+        let mut filter = LogFilter::default();
+        assert_eq!("high".parse::<Level>(), Ok(Level::High));
+        assert_eq!(log.visible_entries(&filter).count(), 3);
+        filter.level = Level::High;
+        assert_eq!(log.visible_entries(&filter).count(), 2);
+        filter.level = Level::Critical;
+        assert_eq!(log.visible_entries(&filter).count(), 1);
+    }
+
+    #[test]
+    fn filters_by_source_and_keeps_history() {
+        let (mut log, sender) = LogState::new_with_channel();
+        sender.low("lsp::hover", "hover");
+        sender.low("tree-sitter", "parse");
+        let id = sender.low("lsp::diagnostics", "3 errors");
+        sender.modify(id, "2 errors");
+        sender.remove(id);
+        log.poll_messages();
+
+        let filter = LogFilter {
+            source: Some("lsp::*".into()),
+            ..Default::default()
+        };
+        let origins: Vec<_> = log
+            .visible_entries(&filter)
+            .map(|m| m.message.origin.as_str())
+            .collect();
+        assert_eq!(origins, ["lsp::hover"]);
+
+        assert_eq!(log.history().len(), 3);
+        assert_eq!(log.history()[2].message, "2 errors");
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+
+use crossterm::event::{Event, KeyModifiers, MouseEventKind};
+
+use crate::*;
+
+/// Chunks that want mouse events routed to them.
+/// Events over a chunk that isn't registered are dropped, not passed to the chunk below.
+#[derive(State, Default)]
+pub struct MouseRegistry {
+    chunks: HashSet<String>,
+}
+
+impl MouseRegistry {
+    /// Routes mouse events over `C` (and its indexed copies) into `ChunkMouseEvents`
+    pub fn register<C: StateName + StaticState>(&mut self) {
+        self.chunks.insert(C::static_name());
+    }
+
+    pub fn is_registered(&self, chunk: &str) -> bool {
+        self.chunks.contains(chunk)
+    }
+}
+
+/// A mouse event over a chunk, in coordinates local to the chunk's rect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkMouseEvent {
+    /// State name of the chunk type
+    pub chunk: String,
+    /// Pane index for chunks registered with `register_indexed_chunk`
+    pub index: Option<usize>,
+
+    pub kind: MouseEventKind,
+    pub modifiers: KeyModifiers,
+    pub column: u16,
+    pub row: u16,
+}
+
+impl ChunkMouseEvent {
+    /// Whether the event is over a chunk of type `C`
+    pub fn is<C: StateName + StaticState>(&self) -> bool {
+        self.chunk == C::static_name()
+    }
+}
+
+/// Mouse events routed to chunks this frame, filled by `route_mouse_events`
+#[derive(State, Default)]
+pub struct ChunkMouseEvents(pub Vec<ChunkMouseEvent>);
+
+/// Finds the chunk under each mouse event and stores it in `ChunkMouseEvents`.
+/// Chunks are re-registered after `Update`, so events hit the layout that was last drawn.
+pub async fn route_mouse_events(
+    events: Res<CrosstermEvents>,
+    chunks: Res<Chunks>,
+    registry: Res<MouseRegistry>,
+    routed: ResMut<ChunkMouseEvents>,
+) {
+    get!(events, chunks, registry, mut routed);
+
+    routed.0.clear();
+    for event in &events.0 {
+        let Event::Mouse(mouse) = event else {
+            continue;
+        };
+
+        if let Some(event) = route_mouse_event(&chunks, &registry, mouse) {
+            routed.0.push(event);
+        }
+    }
+}
+
+fn route_mouse_event(
+    chunks: &Chunks,
+    registry: &MouseRegistry,
+    mouse: &crossterm::event::MouseEvent,
+) -> Option<ChunkMouseEvent> {
+    let (key, rect) = chunks.chunk_at(mouse.column, mouse.row)?;
+
+    let (chunk, index) = match key
+        .strip_suffix(']')
+        .and_then(|k| k.rsplit_once('['))
+        .and_then(|(name, idx)| Some((name, idx.parse::<usize>().ok()?)))
+    {
+        Some((name, idx)) => (name, Some(idx)),
+        None => (key, None),
+    };
+
+    if !registry.is_registered(chunk) {
+        return None;
+    }
+
+    Some(ChunkMouseEvent {
+        chunk: chunk.to_string(),
+        index,
+        kind: mouse.kind,
+        modifiers: mouse.modifiers,
+        column: mouse.column - rect.x,
+        row: mouse.row - rect.y,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::layout::Rect;
+
+    use super::*;
+
+    fn click(column: u16, row: u16) -> crossterm::event::MouseEvent {
+        crossterm::event::MouseEvent {
+            kind: MouseEventKind::Down(crossterm::event::MouseButton::Left),
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    #[test]
+    fn routes_to_topmost_registered_chunk() {
+        let mut chunks = Chunks::default();
+        chunks.register_indexed_chunk::<BufferChunk>(0, 0, Rect::new(0, 0, 10, 10));
+        chunks.register_indexed_chunk::<BufferChunk>(1, 0, Rect::new(10, 0, 10, 10));
+        chunks.register_chunk::<BufferChunk>(0, Rect::new(10, 0, 10, 10));
+        chunks.register_chunk::<DialogueChunk>(3, Rect::new(0, 0, 5, 5));
+
+        let mut registry = MouseRegistry::default();
+        registry.register::<BufferChunk>();
+
+        let event = route_mouse_event(&chunks, &registry, &click(12, 3)).unwrap();
+        assert!(event.is::<BufferChunk>());
+        assert_eq!((event.index, event.column, event.row), (Some(1), 2, 3));
+
+        // The dialogue covers the buffer, and doesn't take mouse events
+        assert_eq!(route_mouse_event(&chunks, &registry, &click(2, 2)), None);
+        assert_eq!(route_mouse_event(&chunks, &registry, &click(30, 2)), None);
+    }
+}
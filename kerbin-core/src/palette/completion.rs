@@ -0,0 +1,167 @@
+use std::path::Path;
+
+use crate::*;
+
+/// Most argument candidates shown in the palette
+const MAX_CANDIDATES: usize = 50;
+
+/// The argument under the palette cursor
+#[derive(Debug, PartialEq, Eq)]
+pub struct ArgPosition<'a> {
+    /// Positional slot index, excluding the command name and flags
+    pub idx: usize,
+    /// Text typed so far for the argument
+    pub partial: &'a str,
+    /// Input before the partial argument
+    pub head: &'a str,
+}
+
+/// Finds which positional argument of `info` the end of `input` is in.
+/// Returns `None` while the command name or a flag is being typed.
+pub fn arg_position<'a>(input: &'a str, info: &CommandInfo) -> Option<ArgPosition<'a>> {
+    let (head, partial) = match input.rfind(char::is_whitespace) {
+        Some(idx) => {
+            let split = idx + input[idx..].chars().next()?.len_utf8();
+            input.split_at(split)
+        }
+        None => return None,
+    };
+
+    // Quoted partials and flags aren't completed
+    if partial.starts_with(['"', '\'', '-']) {
+        return None;
+    }
+
+    let tokens = tokenize(head).ok()?;
+    let mut idx = 0;
+    let mut skip_value = false;
+
+    for token in tokens.iter().skip(1) {
+        if skip_value {
+            skip_value = false;
+            continue;
+        }
+
+        match token {
+            Token::Word(word) if word.starts_with("--") => {
+                skip_value = info.flag_takes_value(word);
+            }
+            _ => idx += 1,
+        }
+    }
+
+    // The partial is the value of a flag
+    if skip_value {
+        return None;
+    }
+
+    Some(ArgPosition { idx, partial, head })
+}
+
+/// Lists files and directories starting with `partial`'s file name.
+/// Directories end in `/`, and hidden entries only show once a `.` is typed.
+pub fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, file) = match partial.rfind('/') {
+        Some(idx) => partial.split_at(idx + 1),
+        None => ("", partial),
+    };
+
+    let read_from = if dir.is_empty() { Path::new(".") } else { Path::new(dir) };
+    let Ok(entries) = std::fs::read_dir(read_from) else {
+        return vec![];
+    };
+
+    let mut res: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(file) || (name.starts_with('.') && !file.starts_with('.')) {
+                return None;
+            }
+
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            Some(format!("{dir}{name}{}", if is_dir { "/" } else { "" }))
+        })
+        .collect();
+
+    res.sort();
+    res.truncate(MAX_CANDIDATES);
+    res
+}
+
+/// Fuzzy-matches `partial` against the titles of the open buffers
+pub async fn complete_buffer_name(partial: &str, buffers: &Buffers) -> Vec<String> {
+    let mut ranked = vec![];
+    for buf in &buffers.buffers {
+        let title = buf.read().await.title();
+        if let Some(score) = rank(partial, &title) {
+            ranked.push((score, title));
+        }
+    }
+
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.len().cmp(&b.1.len())));
+    ranked.into_iter().map(|(_, t)| t).take(MAX_CANDIDATES).collect()
+}
+
+/// Runs `completer` on a partial argument
+pub async fn complete_arg(completer: ArgCompleter, partial: &str, buffers: &Buffers) -> Vec<String> {
+    match completer {
+        ArgCompleter::Path => complete_path(partial),
+        ArgCompleter::Buffer => complete_buffer_name(partial, buffers).await,
+        ArgCompleter::Custom(func) => func(partial),
+    }
+}
+
+/// Quotes a candidate if it wouldn't tokenize as a single word
+pub fn quote_arg(arg: &str) -> String {
+    if arg.contains(char::is_whitespace) {
+        format!("\"{arg}\"")
+    } else {
+        arg.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> CommandInfo {
+        CommandInfo::new(
+            ["open"],
+            [("path", "String"), ("--filetype", "Option < String >"), ("--force", "bool")],
+            [""],
+        )
+    }
+
+    #[test]
+    fn finds_positional_slot() {
+        let info = info();
+
+        assert_eq!(arg_position("open", &info), None);
+        assert_eq!(
+            arg_position("open src/ma", &info),
+            Some(ArgPosition {
+                idx: 0,
+                partial: "src/ma",
+                head: "open ",
+            })
+        );
+        assert_eq!(arg_position("open --force ", &info).map(|p| p.idx), Some(0));
+        assert_eq!(arg_position("open a.rs ", &info).map(|p| p.idx), Some(1));
+    }
+
+    #[test]
+    fn skips_flags_and_their_values() {
+        let info = info();
+
+        assert_eq!(arg_position("open --filetype ", &info), None);
+        assert_eq!(arg_position("open --filetype rust ", &info).map(|p| p.idx), Some(0));
+        assert_eq!(arg_position("open --file", &info), None);
+    }
+
+    #[test]
+    fn quotes_args_with_spaces() {
+        assert_eq!(quote_arg("a.rs"), "a.rs");
+        assert_eq!(quote_arg("my file.rs"), "\"my file.rs\"");
+    }
+}
@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+
+use crate::*;
+
+/// Most commands kept in the palette history
+const MAX_HISTORY: usize = 200;
+
+/// Recently executed palette commands, oldest first.
+/// Persisted to `.command_history` in the config folder.
+#[derive(Default, State)]
+pub struct CommandHistory {
+    entries: Vec<String>,
+
+    /// Index into `entries` while cycling with up/down
+    cursor: Option<usize>,
+    /// Input the user had typed before they started cycling
+    draft: String,
+
+    path: Option<PathBuf>,
+}
+
+impl CommandHistory {
+    /// Loads history from `path`, starting empty if it doesn't exist yet
+    pub fn load(path: PathBuf) -> Self {
+        let entries = std::fs::read_to_string(&path)
+            .map(|s| s.lines().filter(|l| !l.is_empty()).map(String::from).collect())
+            .unwrap_or_default();
+
+        Self {
+            entries,
+            path: Some(path),
+            ..Default::default()
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Records a command, moving it to the end if it was already present
+    pub fn record(&mut self, command: &str) {
+        self.reset_cycle();
+
+        let command = command.trim();
+        if command.is_empty() {
+            return;
+        }
+
+        self.entries.retain(|e| e != command);
+        self.entries.push(command.to_string());
+
+        if self.entries.len() > MAX_HISTORY {
+            let excess = self.entries.len() - MAX_HISTORY;
+            self.entries.drain(..excess);
+        }
+
+        self.save();
+    }
+
+    /// Steps back to an older command. `current` is saved so cycling forward past
+    /// the newest entry restores it.
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        let idx = match self.cursor {
+            Some(idx) => idx.saturating_sub(1),
+            None => {
+                self.draft = current.to_string();
+                self.entries.len().checked_sub(1)?
+            }
+        };
+
+        self.cursor = Some(idx);
+        Some(&self.entries[idx])
+    }
+
+    /// Steps forward to a newer command, returning to the draft after the newest
+    pub fn newer(&mut self) -> Option<&str> {
+        let idx = self.cursor? + 1;
+
+        if idx >= self.entries.len() {
+            self.cursor = None;
+            return Some(&self.draft);
+        }
+
+        self.cursor = Some(idx);
+        Some(&self.entries[idx])
+    }
+
+    /// Stops cycling, so the next `older` starts from the newest entry again
+    pub fn reset_cycle(&mut self) {
+        self.cursor = None;
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else { return };
+
+        let mut content = self.entries.join("\n");
+        content.push('\n');
+
+        if let Err(e) = std::fs::write(path, content) {
+            tracing::error!("Failed to save command history to {}: {e}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_dedupes_and_caps() {
+        let mut history = CommandHistory::default();
+        history.record("w");
+        history.record("q");
+        history.record("w");
+
+        assert_eq!(history.entries(), ["q", "w"]);
+
+        for i in 0..MAX_HISTORY + 5 {
+            history.record(&format!("goto {i}"));
+        }
+        assert_eq!(history.entries().len(), MAX_HISTORY);
+        assert_eq!(history.entries().last().unwrap(), &format!("goto {}", MAX_HISTORY + 4));
+    }
+
+    #[test]
+    fn cycling_restores_draft() {
+        let mut history = CommandHistory::default();
+        history.record("first");
+        history.record("second");
+
+        assert_eq!(history.older("dra"), Some("second"));
+        assert_eq!(history.older("ignored"), Some("first"));
+        assert_eq!(history.older("ignored"), Some("first"));
+        assert_eq!(history.newer(), Some("second"));
+        assert_eq!(history.newer(), Some("dra"));
+        assert_eq!(history.newer(), None);
+    }
+
+    #[test]
+    fn reset_starts_from_newest() {
+        let mut history = CommandHistory::default();
+        history.record("first");
+        history.record("second");
+
+        history.older("");
+        history.older("");
+        history.reset_cycle();
+
+        assert_eq!(history.older(""), Some("second"));
+    }
+}
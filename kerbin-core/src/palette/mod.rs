@@ -1,11 +1,42 @@
 use crate::*;
 use kerbin_macros::State;
+use ratatui::buffer::Buffer;
 use ratatui::prelude::*;
-use ratatui::widgets::{Block, BorderType, Paragraph};
+use ratatui::widgets::{Block, BorderType};
 
 pub mod ranking;
 pub use ranking::*;
 
+pub mod history;
+pub use history::*;
+
+pub mod completion;
+pub use completion::*;
+
+/// Most suggestions the palette shows at once, the rest are reached by scrolling
+pub const MAX_VISIBLE_SUGGESTIONS: usize = 5;
+
+/// A palette suggestion: a command name, or a candidate for the argument being typed
+pub struct PaletteSuggestion {
+    /// The name or candidate itself
+    pub label: String,
+    pub line: Line<'static>,
+    /// What accepting the suggestion completes the input to, if it completes at all
+    pub completion: Option<String>,
+    /// Description shown while the suggestion is current
+    pub desc: Option<Vec<Line<'static>>>,
+}
+
+impl PickerItem for PaletteSuggestion {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn line(&self, _query: &str, _theme: &Theme) -> Line<'static> {
+        self.line.clone()
+    }
+}
+
 /// Core state for handling command palette
 #[derive(Default, State)]
 pub struct CommandPaletteState {
@@ -13,16 +44,86 @@ pub struct CommandPaletteState {
     pub old_input: String,
     /// Current user input string
     pub input: String,
-    /// Optional auto-completion for current input
-    pub completion: Option<String>,
-    /// List of command suggestions as Lines
-    pub suggestions: Vec<Line<'static>>,
-
-    /// Detailed description of top suggestion as Lines
-    pub desc: Option<Vec<Line<'static>>>,
+    /// Suggestions for the current input, already ranked by the command registry.
+    /// While none is selected, Tab completes the top one and Enter runs the input as typed
+    pub suggestions: PickerState<PaletteSuggestion>,
+    /// Bumped whenever the suggestions or the selected one change. Change them through
+    /// `set_suggestions` and `select_suggestion` to keep it current
+    pub suggestions_version: u64,
+    /// The suggestion list as last composed, with the suggestions version and theme
+    /// revision it was drawn from
+    suggestions_render: Option<((u64, u64), Buffer)>,
 
     /// Whether current input is valid
     pub input_valid: bool,
+    /// Why the current input doesn't parse, shown beneath it
+    pub input_error: Option<ParseError>,
+    /// Name and type of the first positional argument the input is missing
+    pub missing_arg: Option<(String, String)>,
+}
+
+impl CommandPaletteState {
+    /// What accepting the current suggestion completes the input to
+    pub fn completion(&self) -> Option<&str> {
+        self.suggestions.current_item()?.1.completion.as_deref()
+    }
+
+    /// Description of the current suggestion
+    pub fn desc(&self) -> Option<&Vec<Line<'static>>> {
+        self.suggestions.current_item()?.1.desc.as_ref()
+    }
+
+    /// Replaces the suggestions with an already ranked list
+    pub fn set_suggestions(&mut self, suggestions: Vec<PaletteSuggestion>) {
+        self.suggestions.set_ranked(suggestions);
+        self.suggestions_version += 1;
+    }
+
+    /// Moves the suggestion selection by `delta`, scrolling past the visible ones
+    pub fn select_suggestion(&mut self, delta: isize) {
+        self.suggestions.select(delta, MAX_VISIBLE_SUGGESTIONS);
+        self.suggestions_version += 1;
+    }
+
+    /// Draws the bordered suggestion list over all of `buf`. The list is only composed
+    /// again when the suggestions, the selection, the theme or the area changed since the
+    /// last draw; otherwise the last composition is copied in
+    pub fn render_suggestions(&mut self, buf: &mut Buffer, theme: &Theme) {
+        let area = buf.area;
+        let version = (self.suggestions_version, theme.revision());
+        if let Some((drawn, cached)) = &self.suggestions_render
+            && *drawn == version
+            && cached.area == area
+        {
+            buf.content.clone_from_slice(&cached.content);
+            return;
+        }
+
+        let border_style = theme.get_fallback_default(["ui.commandline.border", "ui.text"]);
+        Block::bordered()
+            .border_type(BorderType::Rounded)
+            .border_style(border_style)
+            .render(area, buf);
+
+        let rows = Rect::new(
+            area.x + 1,
+            area.y + 1,
+            area.width.saturating_sub(3),
+            area.height.saturating_sub(2),
+        );
+        self.suggestions.render_rows(rows, buf, theme);
+
+        self.suggestions_render = Some((version, buf.clone()));
+    }
+
+    /// What to show beneath the input for `input_error`
+    pub fn error_message(&self) -> Option<String> {
+        let error = self.input_error.as_ref()?;
+        Some(match &self.missing_arg {
+            Some((name, ty)) => format!("Missing argument `<{name}>: {ty}`"),
+            None => error.to_string(),
+        })
+    }
 }
 
 pub async fn update_palette_suggestions(
@@ -30,9 +131,10 @@ pub async fn update_palette_suggestions(
     palette: ResMut<CommandPaletteState>,
     prefix_registry: Res<CommandPrefixRegistry>,
     commands: Res<CommandRegistry>,
+    buffers: Res<Buffers>,
     theme: Res<Theme>,
 ) {
-    get!(modes, mut palette, prefix_registry, commands, theme);
+    get!(modes, mut palette, prefix_registry, commands, buffers, theme);
 
     if modes.get_mode() != 'c' {
         return;
@@ -40,17 +142,49 @@ pub async fn update_palette_suggestions(
 
     if palette.old_input != palette.input {
         palette.old_input = palette.input.clone();
-        (palette.suggestions, palette.completion, palette.desc) = commands
-            .get_command_suggestions(&palette.input, &theme)
-            .await;
+
+        let suggestions = match commands
+            .get_arg_suggestions(&palette.input, &buffers, &theme)
+            .await
+        {
+            Some(suggestions) => suggestions,
+            None => {
+                commands
+                    .get_command_suggestions(&palette.input, &theme)
+                    .await
+            }
+        };
+        palette.set_suggestions(suggestions);
     }
 
-    palette.input_valid = commands.validate_command(
+    let validity = commands.validate_command(
         &palette.input,
         Some(&resolver_engine().await.as_resolver()),
         &prefix_registry,
         &modes,
     );
+    palette.input_valid = validity.is_ok();
+
+    // An unknown name is only an error once no command matches what's typed so far
+    let error = validity.err().filter(|e| match e {
+        ParseError::Empty => false,
+        ParseError::UnknownCommand(_) => palette.suggestions.matches.is_empty(),
+        _ => true,
+    });
+
+    palette.missing_arg = match &error {
+        Some(ParseError::ArityMismatch {
+            command, min, found, ..
+        }) if found < min => commands.command_info(command).and_then(|info| {
+            info.args
+                .iter()
+                .filter(|(name, _)| !name.starts_with("--"))
+                .nth(*found)
+                .cloned()
+        }),
+        _ => None,
+    };
+    palette.input_error = error;
 }
 
 pub async fn register_command_palette_chunks(
@@ -68,19 +202,34 @@ pub async fn register_command_palette_chunks(
 
     let window_size = window.size();
 
+    let center_constraints = [
+        Constraint::Percentage(20),
+        Constraint::Percentage(60),
+        Constraint::Percentage(20),
+    ];
+
+    // Descriptions wrap inside the box's borders and padding
+    let [_, center, _] = Layout::horizontal(center_constraints).areas(window_size);
+    let desc_width = center.width.saturating_sub(4) as usize;
     let desc_height = palette
-        .desc
-        .as_ref()
-        .map(|lines| lines.len() as u16 + 2)
+        .desc()
+        .map(|lines| {
+            let rows: usize = lines
+                .iter()
+                .map(|line| wrap_text(&line.to_string(), desc_width).len())
+                .sum();
+            rows as u16 + 2
+        })
         .unwrap_or(0);
 
-    let sug_height = if !palette.suggestions.is_empty() {
-        (palette.suggestions.len().min(5) as u16) + 2
+    let sug_height = if !palette.suggestions.matches.is_empty() {
+        (palette.suggestions.matches.len().min(MAX_VISIBLE_SUGGESTIONS) as u16) + 2
     } else {
         0
     };
 
-    let input_height = 3u16;
+    // The error, if any, gets a row beneath the input
+    let input_height = if palette.input_error.is_some() { 4 } else { 3 };
 
     let [_top, desc_row, sug_row, input_row, _bottom] = Layout::vertical([
         Constraint::Fill(1),
@@ -91,12 +240,6 @@ pub async fn register_command_palette_chunks(
     ])
     .areas(window_size);
 
-    let center_constraints = [
-        Constraint::Percentage(20),
-        Constraint::Percentage(60),
-        Constraint::Percentage(20),
-    ];
-
     if desc_height != 0 {
         let [_, desc_area, _] = Layout::horizontal(center_constraints).areas(desc_row);
         chunks.register_chunk::<CommandDescChunk>(2, desc_area);
@@ -113,11 +256,11 @@ pub async fn render_command_palette(
     line_chunk: Chunk<CommandlineChunk>,
     suggestions_chunk: Chunk<CommandSuggestionsChunk>,
     desc_chunk: Chunk<CommandDescChunk>,
-    palette: Res<CommandPaletteState>,
+    palette: ResMut<CommandPaletteState>,
     modes: Res<ModeStack>,
     theme: Res<Theme>,
 ) {
-    get!(palette, modes, theme);
+    get!(mut palette, modes, theme);
 
     if modes.get_mode() != 'c' {
         return;
@@ -165,40 +308,29 @@ pub async fn render_command_palette(
     line_chunk.set_string(inner_x + 2, inner_y, " : ", Style::default());
     line_chunk.set_string(inner_x + 5, inner_y, &palette.input, style);
 
+    if let Some(error) = palette.error_message() {
+        let error_style = theme.get_fallback_default([
+            "ui.commandline.error",
+            "ui.commandline.invalid",
+            "ui.text",
+        ]);
+        let max_width = area.width.saturating_sub(4) as usize;
+        line_chunk.set_stringn(inner_x + 1, inner_y + 1, error, max_width, error_style);
+    }
+
     let cursor_x = area.x + palette.input.len() as u16 + 6;
     let cursor_y = area.y + 1;
     line_chunk.set_cursor(1, cursor_x, cursor_y, CursorShape::BlinkingBar);
 
-    let suggestion_count = palette.suggestions.len();
+    let suggestion_count = palette.suggestions.matches.len();
     if let Some(mut suggestions_chunk) = suggestions_chunk.get().await
         && suggestion_count > 0
     {
-        let sug_area = suggestions_chunk.area();
-
-        Block::bordered()
-            .border_type(BorderType::Rounded)
-            .border_style(border_style)
-            .render(sug_area, &mut suggestions_chunk);
-
-        let max_display = suggestion_count.min((sug_area.height.saturating_sub(2)) as usize);
-        let inner_width = sug_area.width.saturating_sub(6);
-
-        for i in 0..max_display {
-            let row = i as u16 + 1;
-            let sug_x = sug_area.x + 4;
-            let sug_y = sug_area.y + row;
-
-            if i == 0 {
-                suggestions_chunk.set_string(sug_area.x + 1, sug_y, "▶", icon_style);
-            }
-
-            let sug_rect = Rect::new(sug_x, sug_y, inner_width, 1);
-            Paragraph::new(palette.suggestions[i].clone()).render(sug_rect, &mut suggestions_chunk);
-        }
+        palette.render_suggestions(&mut suggestions_chunk, &theme);
     }
 
     if let Some(mut desc_chunk) = desc_chunk.get().await
-        && let Some(desc_lines) = &palette.desc
+        && let Some(desc_lines) = palette.desc()
     {
         let desc_area = desc_chunk.area();
 
@@ -215,6 +347,76 @@ pub async fn render_command_palette(
             desc_area.height.saturating_sub(2),
         );
 
-        Paragraph::new(desc_lines.clone()).render(inner, &mut desc_chunk);
+        let mut row = inner.y;
+        for line in desc_lines {
+            let style = line
+                .spans
+                .first()
+                .map(|span| line.style.patch(span.style))
+                .unwrap_or(line.style);
+            let rect = Rect::new(inner.x, row, inner.width, inner.bottom().saturating_sub(row));
+            row += desc_chunk.write_wrapped(rect, &line.to_string(), style);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suggestion(label: &str) -> PaletteSuggestion {
+        PaletteSuggestion {
+            label: label.to_string(),
+            line: Line::raw(label.to_string()),
+            completion: None,
+            desc: None,
+        }
+    }
+
+    /// Draws the suggestions as a frame would, returning the areas that were damaged
+    async fn draw(
+        chunks: &mut Chunks,
+        palette: &mut CommandPaletteState,
+        theme: &Theme,
+    ) -> Vec<Rect> {
+        chunks.clear();
+        chunks.register_chunk::<CommandSuggestionsChunk>(2, Rect::new(0, 0, 20, 5));
+        let chunk = chunks.get_chunk::<CommandSuggestionsChunk>().unwrap();
+        palette.render_suggestions(&mut *chunk.write().await, theme);
+
+        match chunks.damage().await {
+            Damage::Rects(rects) => rects,
+            Damage::Full => panic!("expected damaged rects"),
+        }
+    }
+
+    #[tokio::test]
+    async fn suggestions_are_only_composed_again_when_they_change() {
+        let mut chunks = Chunks::default();
+        let mut palette = CommandPaletteState::default();
+        let mut theme = Theme::default();
+        palette.set_suggestions(vec![suggestion("write"), suggestion("wrap")]);
+
+        let area = Rect::new(0, 0, 20, 5);
+        assert_eq!(draw(&mut chunks, &mut palette, &theme).await, vec![area]);
+        let composed = palette.suggestions_render.as_ref().unwrap().1.clone();
+
+        // An unchanged list is copied from the cache, and the chunk isn't damaged
+        assert!(draw(&mut chunks, &mut palette, &theme).await.is_empty());
+        assert_eq!(**chunks.get_chunk::<CommandSuggestionsChunk>().unwrap().read().await, composed);
+
+        // Moving the selection off the top row draws the ▶ marker again
+        palette.select_suggestion(1);
+        palette.select_suggestion(1);
+        assert_eq!(draw(&mut chunks, &mut palette, &theme).await, vec![area]);
+        let chunk = chunks.get_chunk::<CommandSuggestionsChunk>().unwrap();
+        assert_eq!(chunk.read().await[(1, 2)].symbol(), "▶");
+
+        // A theme change recolours the border
+        let border = Style::new().red();
+        theme.register("ui.commandline.border".to_string(), border);
+        assert_eq!(draw(&mut chunks, &mut palette, &theme).await, vec![area]);
+        let chunk = chunks.get_chunk::<CommandSuggestionsChunk>().unwrap();
+        assert_eq!(chunk.read().await[(0, 0)].fg, Color::Red);
     }
 }
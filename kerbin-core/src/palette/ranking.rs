@@ -1,6 +1,29 @@
+/// Base score for every matched char
+const MATCH: i32 = 16;
+/// Bonus when a match directly follows the previous one
+const CONSECUTIVE_BONUS: i32 = 15;
+/// Bonus when a match starts a word (`-`, `_`, space, `/`, `.`, or a camelCase hump)
+const BOUNDARY_BONUS: i32 = 10;
+/// Penalty per skipped char between two matches
+const GAP_PENALTY: i32 = 1;
+/// Cap on the penalty for chars skipped before the first match
+const MAX_LEADING_PENALTY: i32 = 3;
+
+fn is_boundary(text: &[char], idx: usize) -> bool {
+    let Some(prev) = idx.checked_sub(1).map(|i| text[i]) else {
+        return true;
+    };
+
+    matches!(prev, '-' | '_' | ' ' | '/' | '.' | ':')
+        || (prev.is_lowercase() && text[idx].is_uppercase())
+}
+
 #[inline]
-/// Fuzzy-ranks `text` against `ranker`. Returns `Some(score)` if all chars of
-/// `ranker` appear in order in `text` (lower is better), or `None` if they don't.
+/// Fuzzy-scores `text` against `ranker`. Returns `Some(score)` if all chars of
+/// `ranker` appear in order in `text` (higher is better), or `None` if they don't.
+///
+/// Consecutive matches and matches at word starts score higher, skipped chars cost a
+/// little, so `bw` prefers `buffer-write` over `below-something`.
 pub fn rank(ranker: &str, text: &str) -> Option<i32> {
     if ranker.is_empty() {
         return Some(0);
@@ -9,29 +32,97 @@ pub fn rank(ranker: &str, text: &str) -> Option<i32> {
         return None;
     }
 
-    let mut score = 0;
-    let mut distance = 0;
-    let mut i = 0;
+    let query: Vec<char> = ranker.to_lowercase().chars().collect();
+    let original: Vec<char> = text.chars().collect();
+    let lowered: Vec<char> = original
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+
+    if query.len() > lowered.len() {
+        return None;
+    }
+
+    // best[j]: best score with the query so far matched and its last char at text[j]
+    let mut best: Vec<Option<i32>> = vec![None; lowered.len()];
 
-    let ranker = ranker.to_lowercase().chars().collect::<Vec<char>>();
-    let text = text.to_lowercase().chars().collect::<Vec<char>>();
+    for (i, &qc) in query.iter().enumerate() {
+        let mut next = vec![None; lowered.len()];
 
-    for chr in text {
-        if chr == ranker[i] {
-            score += distance;
-            i += 1;
-            distance = 0;
-            if i >= ranker.len() {
-                break;
+        for j in 0..lowered.len() {
+            if lowered[j] != qc {
+                continue;
             }
-        } else {
-            distance += 1;
+
+            let bonus = MATCH + if is_boundary(&original, j) { BOUNDARY_BONUS } else { 0 };
+
+            next[j] = if i == 0 {
+                Some(bonus - (j as i32 * GAP_PENALTY).min(MAX_LEADING_PENALTY))
+            } else {
+                (0..j)
+                    .filter_map(|k| {
+                        let prev = best[k]?;
+                        let link = if k + 1 == j {
+                            CONSECUTIVE_BONUS
+                        } else {
+                            -((j - k - 1) as i32 * GAP_PENALTY)
+                        };
+                        Some(prev + link)
+                    })
+                    .max()
+                    .map(|s| s + bonus)
+            };
         }
+
+        best = next;
     }
 
-    if i != ranker.len() {
-        return None;
+    best.into_iter().flatten().max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sorts candidates the way the palette does: by score, then shorter names first
+    fn ordered<'a>(query: &str, candidates: &[&'a str]) -> Vec<&'a str> {
+        let mut ranked: Vec<(i32, &str)> = candidates
+            .iter()
+            .filter_map(|c| rank(query, c).map(|s| (s, *c)))
+            .collect();
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.len().cmp(&b.1.len())));
+        ranked.into_iter().map(|(_, c)| c).collect()
+    }
+
+    #[test]
+    fn rejects_missing_subsequence() {
+        assert_eq!(rank("xyz", "buffer-write"), None);
+        assert_eq!(rank("wb", "buffer-write"), None);
+    }
+
+    #[test]
+    fn word_boundaries_beat_scattered_matches() {
+        assert_eq!(
+            ordered("bw", &["below-something", "buffer-write"]),
+            vec!["buffer-write", "below-something"]
+        );
+    }
+
+    #[test]
+    fn consecutive_matches_win() {
+        assert_eq!(
+            ordered("wri", &["wide-right-indent", "write"]),
+            vec!["write", "wide-right-indent"]
+        );
     }
 
-    Some(score)
+    #[test]
+    fn ties_prefer_shorter_names() {
+        assert_eq!(ordered("w", &["write_file", "w", "write"]), vec!["w", "write", "write_file"]);
+    }
+
+    #[test]
+    fn camel_case_counts_as_boundary() {
+        assert!(rank("gd", "gotoDefinition") > rank("gd", "good"));
+    }
 }
@@ -0,0 +1,142 @@
+use std::path::PathBuf;
+
+use ignore::WalkBuilder;
+use tokio::sync::mpsc::{UnboundedReceiver, error::TryRecvError, unbounded_channel};
+
+use crate::*;
+
+/// Paths sent from the walk at a time
+const BATCH_SIZE: usize = 256;
+
+/// Limits on the directory walk behind `find`
+#[derive(State, ConfigurableState)]
+#[configurable(name = "find")]
+pub struct FinderConfig {
+    /// Deepest directory level walked below the working directory (0 walks all of it)
+    pub max_depth: usize,
+    /// Most files listed; the walk stops once it has found this many
+    pub max_files: usize,
+}
+
+impl Default for FinderConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 16,
+            max_files: 50_000,
+        }
+    }
+}
+
+/// Files under the working directory, kept between `find`s until refreshed
+#[derive(State, Default)]
+pub struct FileFinder {
+    /// Paths relative to the working directory, in walk order
+    pub files: Vec<String>,
+    /// Batches of paths from the walk, while it is running
+    walk: Option<UnboundedReceiver<Vec<String>>>,
+    /// Picker session showing the files, which new batches are streamed into
+    pub session: Option<u64>,
+}
+
+impl FileFinder {
+    /// Drops the cached files and starts walking the working directory again
+    pub fn refresh(&mut self, config: &FinderConfig) {
+        let root = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        self.files.clear();
+        self.walk = Some(walk_files(root, config.max_depth, config.max_files));
+    }
+
+    pub fn walking(&self) -> bool {
+        self.walk.is_some()
+    }
+}
+
+/// Walks `root` on a blocking thread, sending the files found (relative to `root`) in
+/// batches. Hidden files and anything ignored by `.gitignore` or `.ignore` are skipped.
+pub fn walk_files(
+    root: PathBuf,
+    max_depth: usize,
+    max_files: usize,
+) -> UnboundedReceiver<Vec<String>> {
+    let (tx, rx) = unbounded_channel();
+
+    tokio::task::spawn_blocking(move || {
+        let mut builder = WalkBuilder::new(&root);
+        if max_depth != 0 {
+            builder.max_depth(Some(max_depth));
+        }
+
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        let mut found = 0;
+        for entry in builder.build().flatten() {
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+
+            let path = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+            batch.push(path.to_string_lossy().into_owned());
+            found += 1;
+
+            // The receiver is gone once the walk was refreshed again
+            if batch.len() == BATCH_SIZE && tx.send(std::mem::take(&mut batch)).is_err() {
+                return;
+            }
+            if found >= max_files {
+                break;
+            }
+        }
+
+        if !batch.is_empty() {
+            let _ = tx.send(batch);
+        }
+    });
+
+    rx
+}
+
+/// Collects the files the walk found since the last frame, streaming them into the
+/// `find` picker while it is open
+pub async fn update_file_finder(
+    finder: ResMut<FileFinder>,
+    picker: ResMut<Picker>,
+    activity: Res<FrameActivity>,
+) {
+    get!(mut finder, mut picker, activity);
+
+    let showing = picker.active && finder.session == Some(picker.session);
+    if !showing {
+        finder.session = None;
+    }
+
+    let Some(walk) = &mut finder.walk else {
+        return;
+    };
+
+    let mut found = vec![];
+    let done = loop {
+        match walk.try_recv() {
+            Ok(batch) => found.extend(batch),
+            Err(TryRecvError::Empty) => break false,
+            Err(TryRecvError::Disconnected) => break true,
+        }
+    };
+
+    if done {
+        finder.walk = None;
+    }
+    finder.files.extend(found.iter().cloned());
+
+    // Keep frames coming while the walk runs, so files show up as they're found
+    if showing {
+        activity.mark();
+    }
+
+    if showing {
+        if !found.is_empty() {
+            picker.list.extend(found);
+        }
+        if done {
+            picker.title = "Find".to_string();
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use ignore::WalkBuilder;
+use ::regex::{Regex, RegexBuilder};
+use tokio::sync::mpsc::{UnboundedReceiver, error::TryRecvError, unbounded_channel};
+
+use crate::*;
+
+/// Most matches a grep lists; the search stops once it has found this many
+pub const MAX_GREP_MATCHES: usize = 10_000;
+
+/// How long the pattern has to stay unchanged before the search restarts
+const GREP_DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Bytes checked for a NUL to tell binary files apart
+const BINARY_SNIFF_LEN: usize = 8000;
+
+/// The `grep` picker's search, rerun as its query changes
+#[derive(State, Default)]
+pub struct WorkspaceGrep {
+    /// Picker session showing the matches
+    pub session: Option<u64>,
+    /// Pattern of the running (or finished) search
+    pub pattern: String,
+    /// When the picker query last stopped matching `pattern`
+    changed_at: Option<Instant>,
+    /// Batches of matches, while the search is running
+    search: Option<UnboundedReceiver<Vec<String>>>,
+    found: usize,
+}
+
+impl WorkspaceGrep {
+    /// Starts searching for `pattern` in place of the current search, clearing the picker.
+    /// Lowercase patterns match case-insensitively.
+    pub fn restart(&mut self, picker: &mut Picker, pattern: String) {
+        self.pattern = pattern;
+        self.changed_at = None;
+        self.found = 0;
+        self.search = None;
+        picker.list.set_ranked(vec![]);
+
+        if self.pattern.is_empty() {
+            picker.title = "Grep".to_string();
+            return;
+        }
+
+        let regex = RegexBuilder::new(&self.pattern)
+            .case_insensitive(!self.pattern.chars().any(char::is_uppercase))
+            .build();
+        match regex {
+            Ok(regex) => {
+                let root = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+                self.search = Some(grep_files(root, regex));
+                picker.title = "Grep (searching)".to_string();
+            }
+            Err(_) => picker.title = "Grep (invalid pattern)".to_string(),
+        }
+    }
+}
+
+/// Searches the files under `root` on a blocking thread, sending the first match of each
+/// line as `path:line:col: text` (1-based, `col` in chars), a file at a time. Hidden,
+/// ignored, binary and non-UTF-8 files are skipped. Stops once the receiver is dropped.
+pub fn grep_files(root: PathBuf, regex: Regex) -> UnboundedReceiver<Vec<String>> {
+    let (tx, rx) = unbounded_channel();
+
+    tokio::task::spawn_blocking(move || {
+        for entry in WalkBuilder::new(&root).build().flatten() {
+            if tx.is_closed() {
+                return;
+            }
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+
+            let Ok(bytes) = std::fs::read(entry.path()) else {
+                continue;
+            };
+            if bytes.iter().take(BINARY_SNIFF_LEN).any(|b| *b == 0) {
+                continue;
+            }
+            let Ok(text) = String::from_utf8(bytes) else {
+                continue;
+            };
+
+            let path = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+            let path = path.to_string_lossy();
+            let matches: Vec<String> = text
+                .lines()
+                .enumerate()
+                .filter_map(|(i, line)| {
+                    let found = regex.find(line)?;
+                    let col = line[..found.start()].chars().count() + 1;
+                    Some(format!("{path}:{}:{col}: {}", i + 1, line.trim()))
+                })
+                .collect();
+
+            if !matches.is_empty() && tx.send(matches).is_err() {
+                return;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Opens the file of a `path:line:col: text` match with the cursor on the match,
+/// recording a jump first
+pub async fn open_grep_match(state: &mut State, entry: &str) -> bool {
+    match QuickfixEntry::parse(entry) {
+        Some(entry) => open_quickfix_entry(state, &entry).await,
+        None => false,
+    }
+}
+
+/// Restarts the `grep` picker's search once its query has settled, and streams the
+/// matches found since the last frame into it. A finished search fills the quickfix list
+pub async fn update_workspace_grep(
+    grep: ResMut<WorkspaceGrep>,
+    picker: ResMut<Picker>,
+    quickfix: ResMut<QuickfixList>,
+    activity: Res<FrameActivity>,
+) {
+    get!(mut grep, mut picker, activity);
+
+    if grep.session.is_none() {
+        return;
+    }
+    if !picker.active || grep.session != Some(picker.session) {
+        grep.session = None;
+        grep.search = None;
+        grep.changed_at = None;
+        return;
+    }
+
+    if picker.list.query != grep.pattern {
+        let changed_at = *grep.changed_at.get_or_insert_with(Instant::now);
+        if changed_at.elapsed() >= GREP_DEBOUNCE {
+            let query = picker.list.query.clone();
+            grep.restart(&mut picker, query);
+        } else {
+            // Keep frames coming so the search starts once the query settles
+            activity.mark();
+        }
+    }
+
+    let Some(search) = &mut grep.search else {
+        return;
+    };
+    // Matches stream in until the search finishes
+    activity.mark();
+
+    let mut found = vec![];
+    let done = loop {
+        match search.try_recv() {
+            Ok(batch) => found.extend(batch),
+            Err(TryRecvError::Empty) => break false,
+            Err(TryRecvError::Disconnected) => break true,
+        }
+    };
+
+    let room = MAX_GREP_MATCHES - grep.found;
+    let truncated = found.len() > room;
+    found.truncate(room);
+    grep.found += found.len();
+
+    if !found.is_empty() {
+        picker.list.extend(found);
+    }
+
+    if truncated {
+        // Dropping the receiver stops the search
+        grep.search = None;
+        picker.title = format!("Grep (first {MAX_GREP_MATCHES} matches)");
+    } else if done {
+        grep.search = None;
+        picker.title = format!("Grep ({} matches)", grep.found);
+    } else {
+        return;
+    }
+
+    let entries = picker.list.items.iter().filter_map(|m| QuickfixEntry::parse(m)).collect();
+    get!(mut quickfix);
+    quickfix.set(format!("grep {}", grep.pattern), entries);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn greps_files_under_root() {
+        let root = std::env::temp_dir().join(format!("kerbin-grep-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "nothing\n  let Foo = 1;\nfoo again foo\n").unwrap();
+        std::fs::write(root.join("b.bin"), b"foo\0bar").unwrap();
+
+        let regex = RegexBuilder::new("foo")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let mut rx = grep_files(root.clone(), regex);
+
+        let mut matches = vec![];
+        while let Some(batch) = rx.recv().await {
+            matches.extend(batch);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+
+        // One entry per matching line, binary files skipped
+        assert_eq!(matches, vec!["a.txt:2:7: let Foo = 1;", "a.txt:3:1: foo again foo"]);
+    }
+}
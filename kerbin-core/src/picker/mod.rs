@@ -0,0 +1,524 @@
+use std::{future::Future, pin::Pin};
+
+use crate::*;
+use kerbin_macros::State;
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, BorderType, Paragraph};
+
+pub mod files;
+pub use files::*;
+
+pub mod grep;
+pub use grep::*;
+
+/// Most rows the picker overlay shows at once
+pub const MAX_PICKER_ROWS: usize = 10;
+
+/// Something a picker can list and filter
+pub trait PickerItem {
+    /// Text the query is fuzzy-matched against
+    fn label(&self) -> &str;
+
+    /// How the item is drawn, with the chars matching `query` highlighted
+    fn line(&self, query: &str, theme: &Theme) -> Line<'static> {
+        let base = theme.get_fallback_default(["ui.commandline.names", "ui.text"]);
+        let highlight = theme.get_fallback_default([
+            "ui.commandline.match_highlight",
+            "ui.commandline.primary_name",
+            "ui.text",
+        ]);
+        Line::from(highlight_matches(self.label(), query, base, highlight))
+    }
+}
+
+impl PickerItem for String {
+    fn label(&self) -> &str {
+        self
+    }
+}
+
+/// A fuzzy-filtered list: its items, the query filtering them, and the selection
+pub struct PickerState<T> {
+    pub items: Vec<T>,
+    pub query: String,
+    /// Indices into `items` of the matches, best first
+    pub matches: Vec<usize>,
+
+    /// Picked match, as an index into `matches`. While unset, the top match is current
+    pub selected: Option<usize>,
+    /// Index of the first visible match
+    pub scroll: usize,
+
+    /// Whether the query filters the items. When unset, every item is shown in order and
+    /// the query is left to whoever fills the list (like `grep`'s pattern)
+    pub fuzzy: bool,
+
+    /// Group of each item, parallel to `items`. When set, a header names the group above
+    /// each run of rows that share it. Empty for ungrouped lists
+    pub groups: Vec<String>,
+}
+
+/// A row drawn by `PickerState::render_rows`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickerRow {
+    /// Names the group of the item at this index of `items`
+    Header(usize),
+    /// The match at this index of `matches`
+    Match(usize),
+}
+
+impl<T> Default for PickerState<T> {
+    fn default() -> Self {
+        Self {
+            items: vec![],
+            query: String::new(),
+            matches: vec![],
+            selected: None,
+            scroll: 0,
+            fuzzy: true,
+            groups: vec![],
+        }
+    }
+}
+
+impl<T: PickerItem> PickerState<T> {
+    pub fn new(items: Vec<T>) -> Self {
+        let mut picker = Self {
+            items,
+            ..Default::default()
+        };
+        picker.refilter();
+        picker
+    }
+
+    /// Replaces the items with a list that is already ranked, showing all of them in order
+    pub fn set_ranked(&mut self, items: Vec<T>) {
+        self.matches = (0..items.len()).collect();
+        self.items = items;
+        self.reset_selection();
+    }
+
+    pub fn set_query(&mut self, query: impl ToString) {
+        self.query = query.to_string();
+        self.refilter();
+    }
+
+    /// Adds items, ranking them in with the rest. The selection stays on the same item
+    /// and row when that item still matches
+    pub fn extend(&mut self, items: impl IntoIterator<Item = T>) {
+        let picked = self.selected.and_then(|pos| Some((pos, *self.matches.get(pos)?)));
+        let scroll = self.scroll;
+
+        self.items.extend(items);
+        self.refilter();
+
+        if let Some((old_pos, item)) = picked
+            && let Some(pos) = self.matches.iter().position(|&m| m == item)
+        {
+            self.selected = Some(pos);
+            self.scroll = pos.saturating_sub(old_pos - scroll.min(old_pos));
+        }
+    }
+
+    /// Ranks the items against the query. Better scores come first, then shorter labels,
+    /// then the original order. Grouped lists keep their groups together, ranked within
+    /// each. An empty query, or a list that isn't fuzzy, keeps every item in its original
+    /// order
+    pub fn refilter(&mut self) {
+        if self.query.is_empty() || !self.fuzzy {
+            self.matches = (0..self.items.len()).collect();
+            self.reset_selection();
+            return;
+        }
+
+        let mut ranked: Vec<(i32, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| rank(&self.query, item.label()).map(|score| (score, i)))
+            .collect();
+
+        ranked.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(self.items[a.1].label().len().cmp(&self.items[b.1].label().len()))
+                .then(a.1.cmp(&b.1))
+        });
+
+        // Keep each group under a single header, in the order the groups were given
+        if !self.groups.is_empty() {
+            ranked.sort_by_key(|&(_, i)| self.groups.iter().position(|g| *g == self.groups[i]));
+        }
+
+        self.matches = ranked.into_iter().map(|(_, i)| i).collect();
+        self.reset_selection();
+    }
+
+    fn reset_selection(&mut self) {
+        self.selected = None;
+        self.scroll = 0;
+    }
+
+    /// Index into `matches` of the current match
+    pub fn current(&self) -> usize {
+        self.selected.unwrap_or(0)
+    }
+
+    /// Index into `items` and the item of the current match
+    pub fn current_item(&self) -> Option<(usize, &T)> {
+        let idx = *self.matches.get(self.current())?;
+        Some((idx, &self.items[idx]))
+    }
+
+    /// Moves the selection by `delta` matches, wrapping around the ends, and scrolls so it
+    /// stays within the `visible` rows
+    pub fn select(&mut self, delta: isize, visible: usize) {
+        let count = self.matches.len();
+        if count == 0 {
+            return;
+        }
+
+        let selected = match self.selected {
+            Some(idx) => (idx as isize + delta).rem_euclid(count as isize) as usize,
+            None if delta < 0 => count - 1,
+            None => 0,
+        };
+        self.selected = Some(selected);
+
+        if selected < self.scroll {
+            self.scroll = selected;
+        } else if selected >= self.scroll + visible {
+            self.scroll = selected + 1 - visible;
+        }
+    }
+
+    /// The rows shown in `height` lines, starting from the match at `start`
+    fn rows_from(&self, start: usize, height: usize) -> Vec<PickerRow> {
+        let mut rows = vec![];
+        let mut last_group = None;
+
+        for (pos, &item) in self.matches.iter().enumerate().skip(start) {
+            if rows.len() >= height {
+                break;
+            }
+
+            let group = self.groups.get(item);
+            if group.is_some() && group != last_group {
+                rows.push(PickerRow::Header(item));
+                last_group = group;
+            }
+            rows.push(PickerRow::Match(pos));
+        }
+
+        rows.truncate(height);
+        rows
+    }
+
+    /// The rows shown in `height` lines: the matches from `scroll` on, with a header
+    /// above each run of a group. Starts later when the headers would push the current
+    /// match out of view
+    pub fn rows(&self, height: usize) -> Vec<PickerRow> {
+        let current = PickerRow::Match(self.current());
+        let mut start = self.scroll;
+        loop {
+            let rows = self.rows_from(start, height);
+            if rows.contains(&current) || start >= self.current() {
+                return rows;
+            }
+            start += 1;
+        }
+    }
+
+    /// Draws the visible rows into `area`, with a ▶ marker before the current match.
+    /// Matches start two columns in to leave room for the marker
+    pub fn render_rows(&self, area: Rect, buf: &mut Buffer, theme: &Theme) {
+        let marker_style = theme.get_fallback_default(["ui.commandline.icon", "ui.text"]);
+        let header_style =
+            theme.get_fallback_default(["ui.picker.header", "ui.commandline.title", "ui.text"]);
+
+        for (row, kind) in self.rows(area.height as usize).into_iter().enumerate() {
+            let y = area.y + row as u16;
+
+            match kind {
+                PickerRow::Header(item) => {
+                    let rect = Rect::new(area.x, y, area.width, 1);
+                    Paragraph::new(Span::styled(self.groups[item].clone(), header_style))
+                        .render(rect, buf);
+                }
+                PickerRow::Match(pos) => {
+                    if pos == self.current() {
+                        buf.set_string(area.x, y, "▶", marker_style);
+                    }
+
+                    let item = self.matches[pos];
+                    let rect = Rect::new(area.x + 3, y, area.width.saturating_sub(3), 1);
+                    let query = if self.fuzzy { self.query.as_str() } else { "" };
+                    Paragraph::new(self.items[item].line(query, theme)).render(rect, buf);
+                }
+            }
+        }
+    }
+}
+
+/// Runs with the picked item's index into the opened items, and the item itself
+pub type PickerCallback = Box<
+    dyn for<'a> Fn(&'a mut State, usize, String) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>
+        + Send
+        + Sync,
+>;
+
+/// What the picker does with the picked item
+pub enum PickerAction {
+    /// Sets the `var` template to the item and runs `commands` (each a [command] list)
+    Commands { var: String, commands: Vec<Token> },
+    Callback(PickerCallback),
+}
+
+/// The fuzzy picker overlay, shown in picker mode ('p')
+#[derive(Default, State)]
+pub struct Picker {
+    pub active: bool,
+    /// Counts up each time the picker opens, so whoever opened it can tell whether it
+    /// is still showing their items
+    pub session: u64,
+    pub title: String,
+    pub list: PickerState<String>,
+    pub on_select: Option<PickerAction>,
+}
+
+impl Picker {
+    /// Opens the picker over `items`, running `on_select` with the one the user picks
+    pub async fn open(
+        state: &State,
+        title: impl ToString,
+        items: Vec<String>,
+        on_select: PickerAction,
+    ) {
+        let mut picker = state.lock_state::<Picker>().await;
+        picker.active = true;
+        picker.session += 1;
+        picker.title = title.to_string();
+        picker.list = PickerState::new(items);
+        picker.on_select = Some(on_select);
+        drop(picker);
+
+        let mut modes = state.lock_state::<ModeStack>().await;
+        if modes.get_mode() != 'p' {
+            modes.push_mode('p').await;
+        }
+    }
+
+    /// Opens the picker like `open`, listing `items` under a header for their group.
+    /// Items are shown in the order given, so sort them by group first
+    pub async fn open_grouped(
+        state: &State,
+        title: impl ToString,
+        items: Vec<(String, String)>,
+        on_select: PickerAction,
+    ) {
+        let (groups, items) = items.into_iter().unzip();
+        Self::open(state, title, items, on_select).await;
+        state.lock_state::<Picker>().await.list.groups = groups;
+    }
+
+    /// Closes the picker, handing back its action if it had one
+    pub async fn close(state: &State) -> Option<PickerAction> {
+        let mut picker = state.lock_state::<Picker>().await;
+        if !picker.active {
+            return None;
+        }
+        picker.active = false;
+        picker.list = PickerState::default();
+        let action = picker.on_select.take();
+        drop(picker);
+
+        let mut modes = state.lock_state::<ModeStack>().await;
+        if modes.get_mode() == 'p' {
+            modes.pop_mode().await;
+        }
+        action
+    }
+}
+
+pub async fn register_picker_chunk(
+    chunks: ResMut<Chunks>,
+    window: Res<WindowState>,
+    picker: Res<Picker>,
+) {
+    get!(picker);
+    if !picker.active {
+        return;
+    }
+    get!(mut chunks, window);
+
+    // Sized by the unfiltered items so the box doesn't jump while typing. Grouped lists get
+    // room for their headers too
+    let groups = &picker.list.groups;
+    let headers = match groups.is_empty() {
+        true => 0,
+        false => 1 + groups.windows(2).filter(|pair| pair[0] != pair[1]).count(),
+    };
+    let rows = (picker.list.items.len() + headers).clamp(1, MAX_PICKER_ROWS) as u16;
+    let height = rows + 4;
+
+    let [_, center_row, _] = Layout::vertical([
+        Constraint::Fill(2),
+        Constraint::Length(height),
+        Constraint::Fill(3),
+    ])
+    .areas(window.size());
+
+    let [_, area, _] = Layout::horizontal([
+        Constraint::Percentage(20),
+        Constraint::Percentage(60),
+        Constraint::Percentage(20),
+    ])
+    .areas(center_row);
+
+    chunks.register_chunk::<PickerChunk>(3, area);
+}
+
+pub async fn render_picker(chunk: Chunk<PickerChunk>, picker: Res<Picker>, theme: Res<Theme>) {
+    get!(picker, theme);
+
+    if !picker.active {
+        return;
+    }
+
+    let Some(mut chunk) = chunk.get().await else {
+        return;
+    };
+
+    let border_style = theme.get_fallback_default(["ui.commandline.border", "ui.text"]);
+    let title_style = theme.get_fallback_default(["ui.commandline.title", "ui.text"]);
+    let icon_style = theme.get_fallback_default(["ui.commandline.icon", "ui.text"]);
+    let prompt_style = theme.get_fallback_default(["ui.commandline.prompt", "ui.text"]);
+
+    let area = chunk.area();
+
+    Block::bordered()
+        .border_type(BorderType::Rounded)
+        .title(Span::styled(format!(" {} ", picker.title), title_style))
+        .title_bottom(Span::styled(
+            format!(" {}/{} ", picker.list.matches.len(), picker.list.items.len()),
+            title_style,
+        ))
+        .border_style(border_style)
+        .render(area, &mut chunk);
+
+    let inner_x = area.x + 1;
+    let input_y = area.y + 1;
+    chunk.set_string(inner_x + 1, input_y, "●", icon_style);
+    chunk.set_string(inner_x + 2, input_y, " : ", Style::default());
+    chunk.set_string(inner_x + 5, input_y, &picker.list.query, prompt_style);
+
+    let cursor_x = inner_x + 5 + picker.list.query.width() as u16;
+    chunk.set_cursor(1, cursor_x, input_y, CursorShape::BlinkingBar);
+
+    let rows = Rect::new(
+        inner_x + 1,
+        input_y + 2,
+        area.width.saturating_sub(4),
+        area.height.saturating_sub(5),
+    );
+    picker.list.render_rows(rows, &mut chunk, &theme);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn filters_and_ranks_by_query() {
+        let mut picker = PickerState::new(items(&["src/main.rs", "src/lib.rs", "README.md"]));
+        assert_eq!(picker.matches, vec![0, 1, 2]);
+
+        picker.set_query("lib");
+        assert_eq!(picker.matches, vec![1]);
+        assert_eq!(picker.current_item().map(|(i, _)| i), Some(1));
+
+        picker.set_query("zzz");
+        assert!(picker.current_item().is_none());
+    }
+
+    #[test]
+    fn streamed_items_keep_the_selection() {
+        let mut picker = PickerState::new(items(&["b.rs", "c.rs"]));
+        picker.select(1, 5);
+        picker.select(1, 5);
+        assert_eq!(picker.current_item().map(|(_, l)| l.as_str()), Some("c.rs"));
+
+        picker.set_query("rs");
+        picker.select(1, 5);
+        picker.extend(items(&["a.rs", "README.md"]));
+        assert_eq!(picker.matches.len(), 3);
+        assert_eq!(picker.current_item().map(|(_, l)| l.as_str()), Some("b.rs"));
+    }
+
+    #[test]
+    fn selection_scrolls_past_visible_rows() {
+        let mut picker = PickerState::default();
+        picker.set_ranked(items(&["a", "b", "c", "d", "e", "f", "g", "h"]));
+
+        picker.select(1, 5);
+        assert_eq!(picker.selected, Some(0));
+        for _ in 0..6 {
+            picker.select(1, 5);
+        }
+        assert_eq!(picker.selected, Some(6));
+        assert_eq!(picker.scroll, 2);
+        assert_eq!(picker.current_item().map(|(_, l)| l.as_str()), Some("g"));
+
+        // Wraps to the top, scrolling back up
+        picker.select(2, 5);
+        assert_eq!(picker.selected, Some(0));
+        assert_eq!(picker.scroll, 0);
+
+        picker.select(-1, 5);
+        assert_eq!(picker.selected, Some(7));
+        assert_eq!(picker.scroll, 3);
+    }
+
+    #[test]
+    fn groups_get_headers_and_keep_the_selection_visible() {
+        let mut picker = PickerState::new(items(&["w", "o", "hover", "rename"]));
+        picker.groups = items(&["buffer", "buffer", "lsp", "lsp"]);
+
+        use PickerRow::*;
+        assert_eq!(
+            picker.rows(10),
+            vec![Header(0), Match(0), Match(1), Header(2), Match(2), Match(3)]
+        );
+
+        // The headers take rows, so reaching the last match scrolls further than `select`
+        for _ in 0..4 {
+            picker.select(1, 4);
+        }
+        assert_eq!(picker.current(), 3);
+        assert_eq!(picker.rows(4), vec![Header(2), Match(2), Match(3)]);
+    }
+
+    #[test]
+    fn fuzzy_matches_stay_under_one_header_per_group() {
+        let mut picker = PickerState::new(items(&["o", "xxxo", "open"]));
+        picker.groups = items(&["buffer", "buffer", "lsp"]);
+
+        // "open" outranks "xxxo", but stays behind the whole buffer group
+        picker.query = "o".into();
+        picker.refilter();
+        assert_eq!(picker.matches, vec![0, 1, 2]);
+
+        use PickerRow::*;
+        assert_eq!(
+            picker.rows(10),
+            vec![Header(0), Match(0), Match(1), Header(2), Match(2)]
+        );
+
+        picker.groups.clear();
+        picker.refilter();
+        assert_eq!(picker.matches, vec![0, 2, 1]);
+    }
+}
@@ -0,0 +1,235 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use crate::*;
+
+/// Runs one phase of a frame
+pub type PhaseFn = Arc<
+    dyn for<'a> Fn(&'a mut State) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> + Send + Sync,
+>;
+
+/// A named step of the frame pipeline
+#[derive(Clone)]
+pub struct Phase {
+    pub name: String,
+    run: PhaseFn,
+}
+
+impl Phase {
+    /// A phase that runs `run` with the editor state
+    pub fn new(
+        name: impl ToString,
+        run: impl for<'a> Fn(&'a mut State) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>
+        + Send
+        + Sync
+        + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            run: Arc::new(run),
+        }
+    }
+
+    /// A phase that calls the hook made by `hook`, for plugins adding hooks of their own
+    /// to the frame
+    pub fn hook<H: Hook + Send + 'static>(
+        name: impl ToString,
+        hook: impl Fn() -> H + Send + Sync + 'static,
+    ) -> Self {
+        Self::new(name, move |state| {
+            let hook = hook();
+            Box::pin(async move { state.hook(hook).call().await })
+        })
+    }
+}
+
+/// The ordered phases run each frame, after input is read. Plugins add their own phases
+/// relative to the core ones by name, e.g. a `pre_diagnostics` hook after `post_update`:
+/// `update`, `post_update`, `clear_chunks`, `chunk_register`, `update_filetype`,
+/// `update_cleanup`, `pre_lines`, `pre_render`, `render`, `render_chunks`, `ipc`, `events`
+#[derive(State, Clone)]
+pub struct Pipeline {
+    phases: Vec<Phase>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            phases: vec![
+                Phase::hook("update", || hooks::Update),
+                Phase::hook("post_update", || hooks::PostUpdate),
+                Phase::new("clear_chunks", |state| Box::pin(clear_chunks(state))),
+                Phase::new("chunk_register", |state| Box::pin(register_chunks(state))),
+                Phase::new("update_filetype", |state| Box::pin(update_filetype(state))),
+                Phase::hook("update_cleanup", || hooks::UpdateCleanup),
+                Phase::hook("pre_lines", || hooks::PreLines),
+                Phase::hook("pre_render", || hooks::PreRender),
+                Phase::hook("render", || hooks::Render),
+                Phase::hook("render_chunks", || hooks::RenderChunks),
+                Phase::new("ipc", |state| Box::pin(handle_ipc_messages(state))),
+                Phase::new("events", |state| Box::pin(EVENT_BUS.resolve(state))),
+            ],
+        }
+    }
+}
+
+impl Pipeline {
+    /// A pipeline without any phases
+    pub fn empty() -> Self {
+        Self { phases: vec![] }
+    }
+
+    /// The names of the phases, in the order they run
+    pub fn names(&self) -> Vec<&str> {
+        self.phases.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    /// Adds a phase at the end of the frame
+    pub fn push(&mut self, phase: Phase) {
+        self.phases.push(phase);
+    }
+
+    /// Adds `new_phase` right after the phase called `phase`.
+    /// Returns false, leaving the pipeline as it was, if there is no such phase
+    pub fn insert_after(&mut self, phase: &str, new_phase: Phase) -> bool {
+        let Some(idx) = self.phases.iter().position(|p| p.name == phase) else {
+            return false;
+        };
+        self.phases.insert(idx + 1, new_phase);
+        true
+    }
+
+    /// Adds `new_phase` right before the phase called `phase`.
+    /// Returns false, leaving the pipeline as it was, if there is no such phase
+    pub fn insert_before(&mut self, phase: &str, new_phase: Phase) -> bool {
+        let Some(idx) = self.phases.iter().position(|p| p.name == phase) else {
+            return false;
+        };
+        self.phases.insert(idx, new_phase);
+        true
+    }
+
+    /// Removes the phase called `phase`, returning whether there was one
+    pub fn remove(&mut self, phase: &str) -> bool {
+        let len = self.phases.len();
+        self.phases.retain(|p| p.name != phase);
+        self.phases.len() != len
+    }
+
+    /// Runs every phase in order. The phases are copied out first, so a phase may change
+    /// the pipeline; the change applies from the next frame
+    pub async fn run(state: &mut State) {
+        let phases = state.lock_state::<Pipeline>().await.phases.clone();
+        for phase in phases {
+            (phase.run)(state).await;
+        }
+    }
+}
+
+/// Starts the frame's chunks over, filled with the theme's base style
+async fn clear_chunks(state: &mut State) {
+    let base_style = state.lock_state::<Theme>().await.default_style();
+    let mut chunks = state.lock_state::<Chunks>().await;
+    chunks.set_base_style(base_style);
+    chunks.clear();
+}
+
+/// Lays out the frame's chunks. Layouts below the minimum size come out as slivers, so
+/// nothing is laid out and `render_chunks` draws a notice instead
+async fn register_chunks(state: &mut State) {
+    if !state.lock_state::<WindowState>().await.too_small() {
+        state.hook(hooks::ChunkRegister).call().await;
+    }
+}
+
+/// Detects the current buffer's filetype once, caching it on the buffer, and runs the
+/// filetype's hook
+async fn update_filetype(state: &mut State) {
+    let cached = {
+        let bufs = state.lock_state::<Buffers>().await;
+        bufs.cur_buffer_as::<TextBuffer>()
+            .await
+            .and_then(|tb| tb.filetype.clone())
+    };
+
+    let filetype = if let Some(ft) = cached {
+        Some(ft)
+    } else {
+        // Read detection inputs from the buffer
+        let (path, first_line) = {
+            let bufs = state.lock_state::<Buffers>().await;
+            bufs.cur_buffer_as::<TextBuffer>()
+                .await
+                .map(|tb| {
+                    let first_line = tb.get_rope().lines().next().map(|l| l.to_string());
+                    (tb.path.clone(), first_line)
+                })
+                .unwrap_or_default()
+        };
+
+        let detected = state
+            .lock_state::<FiletypeRegistry>()
+            .await
+            .detect(&path, first_line.as_deref());
+
+        // Cache the result on the buffer
+        if detected.is_some() {
+            let mut bufs = state.lock_state::<Buffers>().await;
+            if let Some(mut buf) = bufs.cur_text_buffer_mut().await {
+                buf.filetype = detected.clone();
+            }
+        }
+
+        detected
+    };
+
+    if let Some(ref ft) = filetype {
+        state.hook(hooks::UpdateFiletype::new(ft)).call().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(State, Default)]
+    struct Ran(Vec<&'static str>);
+
+    fn recording(name: &'static str) -> Phase {
+        Phase::new(name, move |state| {
+            Box::pin(async move { state.lock_state::<Ran>().await.0.push(name) })
+        })
+    }
+
+    #[test]
+    fn phases_are_inserted_relative_to_others() {
+        let mut pipeline = Pipeline::default();
+        assert!(pipeline.insert_after("post_update", recording("pre_diagnostics")));
+        assert!(pipeline.insert_before("render", recording("pre_render_late")));
+        assert!(!pipeline.insert_after("missing", recording("lost")));
+
+        let names = pipeline.names();
+        let pos = |name| names.iter().position(|n| *n == name).unwrap();
+        assert_eq!(pos("pre_diagnostics"), pos("post_update") + 1);
+        assert_eq!(pos("clear_chunks"), pos("pre_diagnostics") + 1);
+        assert_eq!(pos("pre_render_late") + 1, pos("render"));
+        assert!(!names.contains(&"lost"));
+
+        assert!(pipeline.remove("pre_diagnostics"));
+        assert!(!pipeline.remove("pre_diagnostics"));
+    }
+
+    #[tokio::test]
+    async fn phases_run_in_order() {
+        let mut pipeline = Pipeline::empty();
+        pipeline.push(recording("first"));
+        pipeline.push(recording("last"));
+        pipeline.insert_after("first", recording("second"));
+        pipeline.insert_before("first", recording("zeroth"));
+
+        let mut state = State::new();
+        state.state(Ran::default()).state(pipeline);
+        Pipeline::run(&mut state).await;
+
+        assert_eq!(state.lock_state::<Ran>().await.0, ["zeroth", "first", "second", "last"]);
+    }
+}
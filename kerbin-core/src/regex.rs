@@ -1,4 +1,84 @@
-use regex_cursor::*;
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use regex_cursor::{engines::meta::Regex, *};
+
+/// Most compiled patterns kept by `cached`
+const REGEX_CACHE_SIZE: usize = 32;
+
+/// Recently compiled patterns, least recently used first
+static REGEX_CACHE: Mutex<Vec<(String, Arc<Regex>)>> = Mutex::new(Vec::new());
+
+/// Compiles `pattern`, reusing the compiled regex if it was used recently.
+/// Flags are part of the pattern (`(?i)`), so the pattern alone identifies a regex.
+/// Patterns that fail to compile aren't cached.
+pub fn cached(pattern: &str) -> Result<Arc<Regex>, String> {
+    let mut cache = REGEX_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Some(idx) = cache.iter().position(|(p, _)| p == pattern) {
+        let entry = cache.remove(idx);
+        let regex = entry.1.clone();
+        cache.push(entry);
+        return Ok(regex);
+    }
+
+    let regex = Arc::new(Regex::new(pattern).map_err(|e| e.to_string())?);
+    if cache.len() >= REGEX_CACHE_SIZE {
+        cache.remove(0);
+    }
+    cache.push((pattern.to_string(), regex.clone()));
+
+    Ok(regex)
+}
+
+/// Bytes before the cursor that `last_match_before` searches first. The window doubles
+/// each time it comes up empty, so a far match costs about twice a forward scan
+const BACKWARD_WINDOW: usize = 4096;
+
+/// The last non-empty match of `regex` in `slice` that ends at or before `before`.
+/// Searches growing windows back from `before` instead of every match from the start,
+/// so nearby matches are found without touching the rest of the buffer
+pub fn last_match_before(
+    regex: &Regex,
+    slice: ropey::RopeSlice<'_>,
+    before: usize,
+) -> Option<Range<usize>> {
+    let before = before.min(slice.len_bytes());
+    if before == 0 {
+        return None;
+    }
+
+    let mut window = BACKWARD_WINDOW;
+    loop {
+        let start = before.saturating_sub(window);
+        let input = Input::new(RopeyCursor::at(slice, start)).range(start..before);
+        let last = regex
+            .find_iter(input)
+            .filter(|m| m.start() < m.end())
+            .last()
+            .map(|m| m.start()..m.end());
+
+        match last {
+            // A match at the edge of the window may really start further back
+            Some(m) if m.start > start || start == 0 => return Some(m),
+            None if start == 0 => return None,
+            _ => window = window.saturating_mul(2),
+        }
+    }
+}
+
+/// The last non-empty match of `regex` that ends at or before `from`, wrapping around to
+/// the last match in the buffer when there is none
+pub fn search_prev(
+    regex: &Regex,
+    slice: ropey::RopeSlice<'_>,
+    from: usize,
+) -> Option<Range<usize>> {
+    last_match_before(regex, slice, from)
+        .or_else(|| last_match_before(regex, slice, slice.len_bytes()))
+}
 
 #[derive(Clone, Copy)]
 enum Pos {
@@ -145,3 +225,51 @@ impl Cursor for RopeyCursor<'_> {
         self.offset
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_compiled_patterns() {
+        let first = cached(r"fn\s+\w+").unwrap();
+        let second = cached(r"fn\s+\w+").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        assert!(!Arc::ptr_eq(&first, &cached(r"(?i)fn\s+\w+").unwrap()));
+        assert!(cached("(unclosed").is_err());
+    }
+
+    #[test]
+    fn searches_backward_from_the_middle() {
+        let text = "foo bar\nfoo baz\nqux foo\n";
+        let rope = ropey::Rope::from_str(text);
+        let regex = cached("foo").unwrap();
+
+        assert_eq!(last_match_before(&regex, rope.slice(..), 12), Some(8..11));
+        assert_eq!(last_match_before(&regex, rope.slice(..), 10), Some(0..3));
+        assert_eq!(last_match_before(&regex, rope.slice(..), 2), None);
+
+        assert_eq!(search_prev(&regex, rope.slice(..), 16), Some(8..11));
+        assert_eq!(search_prev(&regex, rope.slice(..), 8), Some(0..3));
+        // The match under the cursor isn't before it
+        assert_eq!(search_prev(&regex, rope.slice(..), 9), Some(0..3));
+
+        // Nothing before the first match, so it wraps to the end of the buffer
+        assert_eq!(search_prev(&regex, rope.slice(..), 0), Some(20..23));
+        assert_eq!(search_prev(&cached("nope").unwrap(), rope.slice(..), 10), None);
+    }
+
+    #[test]
+    fn backward_windows_grow_past_empty_stretches() {
+        let filler = "x".repeat(BACKWARD_WINDOW * 3);
+        let rope = ropey::Rope::from_str(&format!("ab{filler}"));
+        let regex = cached("ab").unwrap();
+        assert_eq!(last_match_before(&regex, rope.slice(..), rope.len_bytes()), Some(0..2));
+
+        // A match straddling the window edge is found whole
+        let rope = ropey::Rope::from_str(&format!("aaaa{}", &filler[..BACKWARD_WINDOW - 2]));
+        let run = cached("a+").unwrap();
+        assert_eq!(last_match_before(&run, rope.slice(..), rope.len_bytes()), Some(0..4));
+    }
+}
@@ -0,0 +1,219 @@
+use std::ops::Range;
+
+use ropey::Rope;
+
+/// Most line insertions and deletions searched for before a diff gives up and replaces
+/// everything between the common start and end as a single edit
+const MAX_DIFF_COST: usize = 1024;
+
+/// A span of the old text replaced by a span of the new one, both as byte ranges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RopeEdit {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Finds a small set of edits turning `old` into `new`, in ascending order of `old`.
+/// Lines are diffed first (Myers), then each changed block is trimmed to the chars that
+/// actually differ. Applying the edits back to front keeps the earlier ranges valid.
+pub fn diff_ropes(old: &Rope, new: &Rope) -> Vec<RopeEdit> {
+    let (old_lines, new_lines) = (old.len_lines(), new.len_lines());
+
+    let prefix = (0..old_lines.min(new_lines))
+        .take_while(|&i| old.line(i) == new.line(i))
+        .count();
+    let suffix = (0..old_lines.min(new_lines) - prefix)
+        .take_while(|&i| old.line(old_lines - 1 - i) == new.line(new_lines - 1 - i))
+        .count();
+
+    let old_mid = prefix..old_lines - suffix;
+    let new_mid = prefix..new_lines - suffix;
+
+    let hunks = diff_lines(old_mid.len(), new_mid.len(), |i, j| {
+        old.line(old_mid.start + i) == new.line(new_mid.start + j)
+    })
+    .unwrap_or_else(|| vec![(0..old_mid.len(), 0..new_mid.len())]);
+
+    hunks
+        .into_iter()
+        .filter_map(|(a, b)| {
+            let old_bytes =
+                old.line_to_byte(old_mid.start + a.start)..old.line_to_byte(old_mid.start + a.end);
+            let new_bytes =
+                new.line_to_byte(new_mid.start + b.start)..new.line_to_byte(new_mid.start + b.end);
+            trim_edit(old, new, old_bytes, new_bytes)
+        })
+        .collect()
+}
+
+/// Narrows a changed block down to the bytes between the chars it starts and ends with
+/// on both sides. Returns `None` when nothing in it differs
+fn trim_edit(
+    old_rope: &Rope,
+    new_rope: &Rope,
+    old: Range<usize>,
+    new: Range<usize>,
+) -> Option<RopeEdit> {
+    let old_text = old_rope.byte_slice(old.clone()).to_string();
+    let new_text = new_rope.byte_slice(new.clone()).to_string();
+
+    let prefix: usize = old_text
+        .chars()
+        .zip(new_text.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+    let suffix: usize = old_text[prefix..]
+        .chars()
+        .rev()
+        .zip(new_text[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+
+    let edit = RopeEdit {
+        old: old.start + prefix..old.end - suffix,
+        new: new.start + prefix..new.end - suffix,
+    };
+    (!edit.old.is_empty() || !edit.new.is_empty()).then_some(edit)
+}
+
+/// Myers' diff of `n` old against `m` new lines, as the blocks of lines that differ
+/// (old range, new range) in ascending order. Returns `None` once more than
+/// `MAX_DIFF_COST` lines would have to be inserted or deleted
+fn diff_lines(
+    n: usize,
+    m: usize,
+    eq: impl Fn(usize, usize) -> bool,
+) -> Option<Vec<(Range<usize>, Range<usize>)>> {
+    let max = n + m;
+    let offset = max as isize;
+    let mut v = vec![0usize; 2 * max + 2];
+    // Furthest x reached on each diagonal k in -d..=d, after each step d
+    let mut trace: Vec<Vec<usize>> = vec![];
+
+    let snake = |mut x: usize, mut y: usize| {
+        while x < n && y < m && eq(x, y) {
+            x += 1;
+            y += 1;
+        }
+        x
+    };
+
+    for d in 0..=max.min(MAX_DIFF_COST) as isize {
+        for k in (-d..=d).step_by(2) {
+            let idx = (offset + k) as usize;
+            let x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            v[idx] = snake(x, (x as isize - k) as usize);
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+
+        if v[(offset + n as isize - m as isize) as usize] >= n
+            && (n as isize - m as isize).abs() <= d
+        {
+            return Some(backtrack(&trace, n, m));
+        }
+    }
+
+    None
+}
+
+/// Walks the steps of a finished Myers search back from `(n, m)`, collecting the runs
+/// of equal lines, and returns the gaps between them
+fn backtrack(trace: &[Vec<usize>], n: usize, m: usize) -> Vec<(Range<usize>, Range<usize>)> {
+    let furthest = |d: usize, k: isize| trace[d][(k + d as isize) as usize];
+
+    // (old start, new start, len) of each run of equal lines, last first
+    let mut runs = vec![];
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len()).rev() {
+        let k = x as isize - y as isize;
+        let di = d as isize;
+        let prev_k = if k == -di || (k != di && furthest(d - 1, k - 1) < furthest(d - 1, k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = furthest(d - 1, prev_k);
+        let prev_y = (prev_x as isize - prev_k) as usize;
+
+        // Past the inserted or deleted line, the snake runs to (x, y)
+        let (start_x, start_y) = if prev_k == k + 1 {
+            (prev_x, prev_y + 1)
+        } else {
+            (prev_x + 1, prev_y)
+        };
+        runs.push((start_x, start_y, x - start_x));
+        (x, y) = (prev_x, prev_y);
+    }
+    runs.push((0, 0, x));
+
+    let mut hunks = vec![];
+    let (mut old_at, mut new_at) = (0, 0);
+    // Empty runs sit between a deletion and an insertion, which belong to the same block
+    for (run_x, run_y, len) in runs.into_iter().rev().filter(|run| run.2 > 0) {
+        if run_x > old_at || run_y > new_at {
+            hunks.push((old_at..run_x, new_at..run_y));
+        }
+        (old_at, new_at) = (run_x + len, run_y + len);
+    }
+    if old_at < n || new_at < m {
+        hunks.push((old_at..n, new_at..m));
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(old: &Rope, new: &Rope, edits: &[RopeEdit]) -> String {
+        let mut rope = old.clone();
+        for edit in edits.iter().rev() {
+            let start = rope.byte_to_char(edit.old.start);
+            rope.remove(start..rope.byte_to_char(edit.old.end));
+            rope.insert(start, &new.byte_slice(edit.new.clone()).to_string());
+        }
+        rope.to_string()
+    }
+
+    #[test]
+    fn similar_ropes_diff_to_small_edits() {
+        let old = Rope::from_str("fn main() {\n    let x = 1;\n    println!(\"{x}\");\n}\n");
+        let new = Rope::from_str(
+            "// entry\nfn main() {\n    let x = 12;\n    println!(\"{x}\");\n}\n",
+        );
+
+        let edits = diff_ropes(&old, &new);
+        assert_eq!(
+            edits,
+            vec![
+                RopeEdit { old: 0..0, new: 0..9 },
+                RopeEdit { old: 25..25, new: 34..35 },
+            ]
+        );
+        assert_eq!(apply(&old, &new, &edits), new.to_string());
+    }
+
+    #[test]
+    fn diffs_apply_back_to_the_new_text() {
+        let cases = [
+            ("", "abc\n"),
+            ("abc\n", ""),
+            ("a\nb\nc\nd\n", "a\nc\nd\ne\n"),
+            ("x\ny\nz", "z\ny\nx"),
+            ("é🦀\nñ\n", "é🦀!\nñ\n"),
+            ("same\n", "same\n"),
+        ];
+        for (old, new) in cases {
+            let (old, new) = (Rope::from_str(old), Rope::from_str(new));
+            let edits = diff_ropes(&old, &new);
+            assert_eq!(apply(&old, &new, &edits), new.to_string());
+        }
+        assert!(diff_ropes(&Rope::from_str("same\n"), &Rope::from_str("same\n")).is_empty());
+    }
+}
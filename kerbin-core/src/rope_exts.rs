@@ -1,8 +1,44 @@
-use ropey::Rope;
+use std::ops::Range;
+
+use ropey::{Rope, RopeSlice};
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+use crate::{byte_offset_to_display_col, display_col_to_byte_offset};
 
 pub trait RopeExts {
     /// Converts the given byte index into another byte index that sits on a valid char boundary
     fn byte_to_char_boundary_byte(&self, byte: usize) -> usize;
+
+    /// Returns the visual column of `byte` (relative to the start of `line`),
+    /// expanding tabs to `tab_width` columns and counting wide glyphs as 2
+    fn visual_col_of_byte(&self, line: usize, byte: usize, tab_width: usize) -> usize;
+
+    /// Returns the byte offset (relative to the start of `line`) of the grapheme at
+    /// visual column `vcol`. Columns past the end of the line clamp to the line end.
+    fn byte_of_visual_col(&self, line: usize, vcol: usize, tab_width: usize) -> usize;
+
+    /// Returns the byte index of the next extended grapheme cluster boundary after `byte`
+    fn next_grapheme_boundary(&self, byte: usize) -> usize;
+
+    /// Returns the byte index of the previous extended grapheme cluster boundary before `byte`
+    fn prev_grapheme_boundary(&self, byte: usize) -> usize;
+
+    /// Copies `lines` (end exclusive, newlines included) into a single `String`,
+    /// clamping the range to the rope
+    fn lines_to_string(&self, lines: Range<usize>) -> String;
+
+    /// Copies the text in `range` into a `String`, clamping it to the rope and snapping
+    /// both ends back to char boundaries
+    fn byte_range_to_string(&self, range: Range<usize>) -> String;
+}
+
+/// Copies a slice chunk by chunk into a `String` sized up front
+fn slice_to_string(slice: RopeSlice<'_>) -> String {
+    let mut res = String::with_capacity(slice.len_bytes());
+    for chunk in slice.chunks() {
+        res.push_str(chunk);
+    }
+    res
 }
 
 impl RopeExts for Rope {
@@ -10,4 +46,158 @@ impl RopeExts for Rope {
         let char_idx = self.byte_to_char(byte);
         self.char_to_byte(char_idx)
     }
+
+    fn visual_col_of_byte(&self, line: usize, byte: usize, tab_width: usize) -> usize {
+        let Some(line) = self.get_line(line) else {
+            return 0;
+        };
+        let text = line.to_string();
+        byte_offset_to_display_col(&text, byte.min(text.len()), tab_width)
+    }
+
+    fn byte_of_visual_col(&self, line: usize, vcol: usize, tab_width: usize) -> usize {
+        let Some(line) = self.get_line(line) else {
+            return 0;
+        };
+        display_col_to_byte_offset(&line.to_string(), vcol, tab_width)
+    }
+
+    fn next_grapheme_boundary(&self, byte: usize) -> usize {
+        let len = self.len_bytes();
+        let byte = byte.min(len);
+
+        let (mut chunk, mut chunk_start, _, _) = self.chunk_at_byte(byte);
+        let mut cursor = GraphemeCursor::new(byte, len, true);
+        loop {
+            match cursor.next_boundary(chunk, chunk_start) {
+                Ok(None) => return len,
+                Ok(Some(n)) => return n,
+                Err(GraphemeIncomplete::NextChunk) => {
+                    chunk_start += chunk.len();
+                    (chunk, chunk_start, _, _) = self.chunk_at_byte(chunk_start);
+                }
+                Err(GraphemeIncomplete::PreContext(n)) => {
+                    let ctx = self.chunk_at_byte(n - 1).0;
+                    cursor.provide_context(ctx, n - ctx.len());
+                }
+                // Only reachable with an invalid offset, which the clamp above rules out
+                Err(_) => return len,
+            }
+        }
+    }
+
+    fn prev_grapheme_boundary(&self, byte: usize) -> usize {
+        let byte = byte.min(self.len_bytes());
+
+        let (mut chunk, mut chunk_start, _, _) = self.chunk_at_byte(byte);
+        let mut cursor = GraphemeCursor::new(byte, self.len_bytes(), true);
+        loop {
+            match cursor.prev_boundary(chunk, chunk_start) {
+                Ok(None) => return 0,
+                Ok(Some(n)) => return n,
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    (chunk, chunk_start, _, _) = self.chunk_at_byte(chunk_start - 1);
+                }
+                Err(GraphemeIncomplete::PreContext(n)) => {
+                    let ctx = self.chunk_at_byte(n - 1).0;
+                    cursor.provide_context(ctx, n - ctx.len());
+                }
+                Err(_) => return 0,
+            }
+        }
+    }
+
+    fn lines_to_string(&self, lines: Range<usize>) -> String {
+        let end = lines.end.min(self.len_lines());
+        let start = lines.start.min(end);
+        slice_to_string(self.byte_slice(self.line_to_byte(start)..self.line_to_byte(end)))
+    }
+
+    fn byte_range_to_string(&self, range: Range<usize>) -> String {
+        let end = range.end.min(self.len_bytes());
+        let start = range.start.min(end);
+        slice_to_string(self.slice(self.byte_to_char(start)..self.byte_to_char(end)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visual_col_mixed_line() {
+        // "\t" (4 cols) + "ab" (2 cols) + "漢" (2 cols) + "c"
+        let rope = Rope::from_str("first\n\tab漢c\n");
+
+        assert_eq!(rope.visual_col_of_byte(1, 0, 4), 0);
+        assert_eq!(rope.visual_col_of_byte(1, 1, 4), 4);
+        assert_eq!(rope.visual_col_of_byte(1, 3, 4), 6);
+        assert_eq!(rope.visual_col_of_byte(1, 6, 4), 8);
+        assert_eq!(rope.visual_col_of_byte(1, 7, 4), 9);
+    }
+
+    #[test]
+    fn test_byte_of_visual_col_mixed_line() {
+        let rope = Rope::from_str("first\n\tab漢c\n");
+
+        assert_eq!(rope.byte_of_visual_col(1, 0, 4), 0);
+        // Columns inside the tab land after it
+        assert_eq!(rope.byte_of_visual_col(1, 2, 4), 1);
+        assert_eq!(rope.byte_of_visual_col(1, 4, 4), 1);
+        assert_eq!(rope.byte_of_visual_col(1, 6, 4), 3);
+        // Second half of the wide char lands after it
+        assert_eq!(rope.byte_of_visual_col(1, 7, 4), 6);
+        assert_eq!(rope.byte_of_visual_col(1, 8, 4), 6);
+        // Past the end clamps before the newline
+        assert_eq!(rope.byte_of_visual_col(1, 100, 4), 7);
+    }
+
+    #[test]
+    fn test_grapheme_boundaries_cross_clusters() {
+        // "e" + combining acute, then a ZWJ family emoji, then "x"
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let text = format!("e\u{301}{family}x");
+        let rope = Rope::from_str(&text);
+
+        assert_eq!(rope.next_grapheme_boundary(0), 3);
+        assert_eq!(rope.next_grapheme_boundary(3), 3 + family.len());
+        assert_eq!(rope.prev_grapheme_boundary(3 + family.len()), 3);
+        assert_eq!(rope.prev_grapheme_boundary(3), 0);
+        assert_eq!(rope.next_grapheme_boundary(text.len()), text.len());
+        assert_eq!(rope.prev_grapheme_boundary(0), 0);
+    }
+
+    #[test]
+    fn test_range_to_string_clamps() {
+        let rope = Rope::from_str("one\ntwo\nthree");
+
+        assert_eq!(rope.lines_to_string(1..2), "two\n");
+        assert_eq!(rope.lines_to_string(1..100), "two\nthree");
+        assert_eq!(rope.lines_to_string(5..9), "");
+        assert_eq!(rope.byte_range_to_string(4..7), "two");
+        assert_eq!(rope.byte_range_to_string(8..100), "three");
+    }
+
+    #[test]
+    fn lines_to_string_matches_naive_join() {
+        let rope = Rope::from_str(&"let value = f(漢字);\n".repeat(100));
+        let lines = 10..60;
+
+        let naive = lines
+            .clone()
+            .map(|l| rope.line(l).to_string())
+            .collect::<Vec<_>>()
+            .join("");
+        assert_eq!(rope.lines_to_string(lines), naive);
+    }
+
+    #[test]
+    fn test_visual_col_round_trip() {
+        let rope = Rope::from_str("\tab漢c");
+
+        for byte in [0, 1, 2, 3, 6, 7] {
+            let vcol = rope.visual_col_of_byte(0, byte, 4);
+            assert_eq!(rope.byte_of_visual_col(0, vcol, 4), byte);
+        }
+    }
 }
@@ -4,105 +4,376 @@ use kerbin_state_machine::{
 };
 use std::{
     any::{Any, TypeId},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     marker::PhantomData,
-    sync::{Arc, LazyLock},
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 use tokio::sync::{RwLock, RwLockWriteGuard};
 
 use crate::*;
 
+struct Subscriber {
+    id: u64,
+    /// Removed after its first run
+    once: bool,
+    /// Skips the system for events that don't match
+    filter: Option<EventFilter>,
+}
+
+#[derive(Default)]
 struct EventEntry {
     active: bool,
-    subscribers: Vec<NamedSystem>,
-    data: Option<Box<dyn Any + Send + Sync>>,
+    /// Kept parallel to `systems` so the systems can be run as a slice
+    subscribers: Vec<Subscriber>,
+    systems: Vec<NamedSystem>,
+    /// Data of each `emit` since the last `resolve`, dispatched one after another
+    queued: Vec<EventValue>,
+}
+
+impl EventEntry {
+    fn retain(&mut self, mut keep: impl FnMut(&Subscriber) -> bool) {
+        let subscribers = std::mem::take(&mut self.subscribers);
+        let systems = std::mem::take(&mut self.systems);
+
+        for (sub, system) in subscribers.into_iter().zip(systems) {
+            if keep(&sub) {
+                self.subscribers.push(sub);
+                self.systems.push(system);
+            }
+        }
+    }
+}
+
+/// Data of one emitted event, boxed so every event type fits in one queue
+type EventValue = Box<dyn Any + Send + Sync>;
+
+type EventPredicate = dyn Fn(&(dyn Any + Send + Sync)) -> bool + Send + Sync;
+
+/// Predicate over an event's data, built with `EventData::filter` and passed to
+/// `SubscriberBuilder::filter`
+#[derive(Clone)]
+pub struct EventFilter {
+    event: TypeId,
+    pred: Arc<EventPredicate>,
+}
+
+impl EventFilter {
+    /// Events without data never match
+    fn matches(&self, data: Option<&(dyn Any + Send + Sync)>) -> bool {
+        data.is_some_and(|data| (self.pred)(data))
+    }
+}
+
+/// Handle to a system subscribed through `TypedBus::subscribe`.
+/// Dropping it keeps the system subscribed; call `unsubscribe` to remove it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subscription {
+    id: u64,
+    event: TypeId,
+}
+
+impl Subscription {
+    /// Removes this subscription from `EVENT_BUS`, returning whether it was still subscribed
+    pub async fn unsubscribe(self) -> bool {
+        EVENT_BUS.unsubscribe(self).await
+    }
 }
 
 #[derive(Default)]
 pub struct TypedBus {
     map: RwLock<HashMap<TypeId, EventEntry>>,
+    next_id: AtomicU64,
+
+    dispatch: Mutex<DispatchState>,
+}
+
+/// Subscriptions checked out of the map while their event runs
+#[derive(Default)]
+struct DispatchState {
+    checked_out: HashSet<u64>,
+    /// Checked-out subscriptions that were unsubscribed mid-dispatch
+    removed: HashSet<u64>,
 }
 
 impl TypedBus {
-    /// Emit an event with data
+    /// Emit an event with data. Each emit is delivered, so emitting twice in a frame runs
+    /// the subscribers twice, in emit order
     pub async fn emit<T: 'static + Send + Sync>(&self, data: T) {
         let type_id = TypeId::of::<T>();
 
         let mut map = self.map.write().await;
-        let entry = map.entry(type_id).or_insert(EventEntry {
-            active: true,
-            subscribers: Vec::new(),
-            data: None,
-        });
+        let entry = map.entry(type_id).or_default();
 
         entry.active = true;
-        entry.data = Some(Box::new(Arc::new(data)));
+        entry.queued.push(Box::new(Arc::new(data)));
     }
 
     /// Emit an event without data (for marker events)
     pub async fn emit_marker<T: 'static>(&self) {
         let mut map = self.map.write().await;
-        map.entry(TypeId::of::<T>())
-            .or_insert(EventEntry {
-                active: true,
-                subscribers: Vec::new(),
-                data: None,
-            })
-            .active = true;
+        map.entry(TypeId::of::<T>()).or_default().active = true;
     }
 
     /// Add a subscriber system for a specific event type
     pub async fn subscribe<T: 'static>(&self) -> SubscriberBuilder<'_> {
+        self.subscriber::<T>(false).await
+    }
+
+    /// Add a subscriber system that is removed after the next time `T` fires
+    pub async fn subscribe_once<T: 'static>(&self) -> SubscriberBuilder<'_> {
+        self.subscriber::<T>(true).await
+    }
+
+    async fn subscriber<T: 'static>(&self, once: bool) -> SubscriberBuilder<'_> {
         let map = self.map.write().await;
 
         SubscriberBuilder {
             bus_map: map,
+            next_id: &self.next_id,
             entry_type: TypeId::of::<T>(),
+            once,
+            filter: None,
         }
     }
 
+    /// Removes a subscription, returning whether it was still subscribed.
+    /// Safe to call from a system while its event is being dispatched.
+    pub async fn unsubscribe(&self, subscription: Subscription) -> bool {
+        let mut map = self.map.write().await;
+        let Some(entry) = map.get_mut(&subscription.event) else {
+            return false;
+        };
+
+        let len = entry.subscribers.len();
+        entry.retain(|sub| sub.id != subscription.id);
+        if entry.subscribers.len() != len {
+            return true;
+        }
+
+        // Not in the map, so it may be checked out for dispatch right now
+        let mut dispatch = self.dispatch.lock().expect("event bus dispatch state poisoned");
+        dispatch.checked_out.contains(&subscription.id) && dispatch.removed.insert(subscription.id)
+    }
+
     /// Handle events that were emitted
     pub async fn resolve(&self, state: &mut State) {
-        let mut map = self.map.write().await;
-        for (_, entry) in map.iter_mut() {
-            if !entry.active {
-                continue;
+        // Take the queued data of every active event. Markers carry none and run once
+        let pending: Vec<(TypeId, Vec<Option<EventValue>>)> = {
+            let mut map = self.map.write().await;
+            map.iter_mut()
+                .filter(|(_, entry)| entry.active)
+                .map(|(type_id, entry)| {
+                    entry.active = false;
+                    let mut queued: Vec<_> =
+                        std::mem::take(&mut entry.queued).into_iter().map(Some).collect();
+                    if queued.is_empty() {
+                        queued.push(None);
+                    }
+                    (*type_id, queued)
+                })
+                .collect()
+        };
+
+        for (type_id, queued) in pending {
+            for data in queued {
+                // Check out the systems and release the map, so systems can subscribe,
+                // unsubscribe, or emit while they run
+                let entry = {
+                    let mut map = self.map.write().await;
+                    let entry = map.entry(type_id).or_default();
+                    EventEntry {
+                        active: false,
+                        subscribers: std::mem::take(&mut entry.subscribers),
+                        systems: std::mem::take(&mut entry.systems),
+                        queued: vec![],
+                    }
+                };
+
+                self.dispatch
+                    .lock()
+                    .expect("event bus dispatch state poisoned")
+                    .checked_out
+                    .extend(entry.subscribers.iter().map(|s| s.id));
+
+                self.run_entry(type_id, entry, data, state).await;
+            }
+        }
+    }
+
+    /// Emits `data` and runs its subscribers right away, returning every value they sent
+    /// through `EventReply<R>`.
+    ///
+    /// Unlike `emit`, which only queues the event until the next `resolve` (once per frame,
+    /// after all systems), the subscribers have finished by the time this returns, so the
+    /// caller can act on their replies. Subscribers that conflict over state run one after
+    /// another in subscription order, others run concurrently, so replies are only ordered
+    /// between conflicting subscribers. A pending `emit` of the same event is left queued.
+    ///
+    /// Must not be called while holding a lock on state the subscribers use.
+    pub async fn emit_collecting<E: 'static + Send + Sync, R: 'static>(
+        &self,
+        data: E,
+        state: &mut State,
+    ) -> Vec<R> {
+        let type_id = TypeId::of::<E>();
+
+        let entry = {
+            let mut map = self.map.write().await;
+            let Some(entry) = map.get_mut(&type_id) else {
+                return vec![];
+            };
+            EventEntry {
+                active: false,
+                subscribers: std::mem::take(&mut entry.subscribers),
+                systems: std::mem::take(&mut entry.systems),
+                queued: vec![],
             }
-            entry.active = false;
+        };
+
+        self.dispatch
+            .lock()
+            .expect("event bus dispatch state poisoned")
+            .checked_out
+            .extend(entry.subscribers.iter().map(|s| s.id));
+
+        state.lock_state::<EventReplies>().await.replies.clear();
+        self.run_entry(type_id, entry, Some(Box::new(Arc::new(data))), state).await;
+
+        std::mem::take(&mut state.lock_state::<EventReplies>().await.replies)
+            .into_iter()
+            .filter_map(|reply| reply.downcast::<R>().ok().map(|r| *r))
+            .collect()
+    }
+
+    /// Runs the systems of a checked-out entry for one emit of `data`, then returns them to
+    /// the map
+    async fn run_entry(
+        &self,
+        type_id: TypeId,
+        mut entry: EventEntry,
+        data: Option<EventValue>,
+        state: &mut State,
+    ) {
+        self.prune_removed(&mut entry);
+
+        let matched: Vec<bool> = entry
+            .subscribers
+            .iter()
+            .map(|sub| sub.filter.as_ref().is_none_or(|f| f.matches(data.as_deref())))
+            .collect();
+
+        state.lock_state::<EventStorage>().await.set(data);
+
+        // Run the matching systems concurrently, then restore the subscription order
+        if matched.iter().all(|m| *m) {
+            run_system_groups(&entry.systems, &state.storage).await;
+        } else {
+            let (run, skipped): (Vec<_>, Vec<_>) = std::mem::take(&mut entry.systems)
+                .into_iter()
+                .zip(&matched)
+                .partition(|(_, m)| **m);
+            let run: Vec<_> = run.into_iter().map(|(system, _)| system).collect();
+
+            run_system_groups(&run, &state.storage).await;
 
-            state
-                .lock_state::<EventStorage>()
-                .await
-                .set(entry.data.take());
+            let mut run = run.into_iter();
+            let mut skipped = skipped.into_iter().map(|(system, _)| system);
+            entry.systems = matched
+                .iter()
+                .filter_map(|m| if *m { run.next() } else { skipped.next() })
+                .collect();
+        }
+
+        // Return the systems under both locks, so a concurrent unsubscribe always finds
+        // them either checked out or back in the map
+        let mut map = self.map.write().await;
+        let mut dispatch = self.dispatch.lock().expect("event bus dispatch state poisoned");
+        let DispatchState {
+            checked_out,
+            removed,
+        } = &mut *dispatch;
+        let mut matched = matched.into_iter();
+        entry.retain(|sub| {
+            checked_out.remove(&sub.id);
+            // Once subscriptions wait for an event that passes their filter
+            let ran = matched.next().unwrap_or(true);
+            let spent = sub.once && ran;
+            !removed.remove(&sub.id) && !spent
+        });
+        drop(dispatch);
+
+        // Put the systems back ahead of any subscribed during dispatch
+        let current = map.entry(type_id).or_default();
+        entry.subscribers.append(&mut current.subscribers);
+        entry.systems.append(&mut current.systems);
+        current.subscribers = entry.subscribers;
+        current.systems = entry.systems;
+    }
 
-            // Run the systems concurrently
-            run_system_groups(&entry.subscribers, &state.storage).await;
+    fn prune_removed(&self, entry: &mut EventEntry) {
+        let mut dispatch = self.dispatch.lock().expect("event bus dispatch state poisoned");
+        if dispatch.removed.is_empty() {
+            return;
         }
+
+        let DispatchState {
+            checked_out,
+            removed,
+        } = &mut *dispatch;
+        entry.retain(|sub| {
+            if removed.remove(&sub.id) {
+                checked_out.remove(&sub.id);
+                false
+            } else {
+                true
+            }
+        });
     }
 }
 
 pub struct SubscriberBuilder<'a> {
     bus_map: RwLockWriteGuard<'a, HashMap<TypeId, EventEntry>>,
+    next_id: &'a AtomicU64,
     entry_type: TypeId,
+    once: bool,
+    filter: Option<EventFilter>,
 }
 
 impl<'a> SubscriberBuilder<'a> {
+    /// Only runs the following systems for events matching `filter`
+    pub fn filter(&mut self, filter: EventFilter) -> &mut Self {
+        assert!(
+            filter.event == self.entry_type,
+            "EventFilter was built for a different event than the subscription"
+        );
+        self.filter = Some(filter);
+        self
+    }
+
     pub fn system<I, D, S: System + Send + Sync + 'static>(
         &mut self,
         system: impl IntoSystem<I, D, System = S>,
-    ) {
-        self.bus_map
-            .entry(self.entry_type)
-            .or_insert(EventEntry {
-                active: false,
-                subscribers: vec![],
-                data: None,
-            })
-            .subscribers
-            .push(NamedSystem {
-                id: "",
-                inner: Box::new(system.into_system()) as Box<dyn System + Send + Sync>,
-            })
+    ) -> Subscription {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let entry = self.bus_map.entry(self.entry_type).or_default();
+        entry.subscribers.push(Subscriber {
+            id,
+            once: self.once,
+            filter: self.filter.clone(),
+        });
+        entry.systems.push(NamedSystem {
+            id: "",
+            inner: Box::new(system.into_system()) as Box<dyn System + Send + Sync>,
+        });
+
+        Subscription {
+            id,
+            event: self.entry_type,
+        }
     }
 }
 
@@ -128,7 +399,22 @@ pub struct EventData<T: Send + Sync + 'static> {
     phantom_t: PhantomData<T>,
 }
 
+impl<T: Send + Sync + 'static> EventData<T> {
+    /// Builds a filter for `SubscriberBuilder::filter`, so a subscriber only runs for
+    /// events where `pred` returns true (e.g. only saves of `.rs` files)
+    pub fn filter(pred: impl Fn(&T) -> bool + Send + Sync + 'static) -> EventFilter {
+        EventFilter {
+            event: TypeId::of::<T>(),
+            pred: Arc::new(move |data| {
+                data.downcast_ref::<Arc<T>>()
+                    .is_some_and(|data| pred(data))
+            }),
+        }
+    }
+}
+
 #[async_trait::async_trait]
+#[diagnostic::do_not_recommend]
 impl<T: Send + Sync + 'static> SystemParam for EventData<T> {
     type Item<'new> = EventData<T>;
     fn retrieve(resources: &StateStorage) -> Self::Item<'_> {
@@ -153,9 +439,215 @@ impl<T: Send + Sync + 'static> SystemParam for EventData<T> {
         read_inner.get::<T>()
     }
 
-    fn desc() -> SystemParamDesc {
-        SystemParamDesc::new::<EventStorage>(false)
+    fn desc() -> Vec<SystemParamDesc> {
+        vec![SystemParamDesc::new::<EventStorage>(false)]
+    }
+}
+
+/// Values sent back by subscribers of an event run through `TypedBus::emit_collecting`
+#[derive(State, Default)]
+pub struct EventReplies {
+    replies: Vec<Box<dyn Any + Send + Sync>>,
+}
+
+/// Lets a subscriber send a value back to `TypedBus::emit_collecting`.
+/// Replies sent during a plain `emit` are discarded.
+pub struct EventReply<R: Send + Sync + 'static> {
+    value: Arc<RwLock<EventReplies>>,
+    phantom_r: PhantomData<R>,
+}
+
+impl<R: Send + Sync + 'static> EventReply<R> {
+    pub async fn send(&self, reply: R) {
+        self.value.write().await.replies.push(Box::new(reply));
+    }
+}
+
+#[async_trait::async_trait]
+#[diagnostic::do_not_recommend]
+impl<R: Send + Sync + 'static> SystemParam for EventReply<R> {
+    type Item<'new> = EventReply<R>;
+    fn retrieve(resources: &StateStorage) -> Self::Item<'_> {
+        let storage = resources
+            .states
+            .get(&EventReplies::static_name())
+            .expect("EventReplies must be registered before EventReply is used as a SystemParam")
+            .downcast::<EventReplies>()
+            .expect("EventReplies downcast failed: type mismatch")
+            .clone();
+
+        EventReply {
+            value: storage,
+            phantom_r: PhantomData::<R>,
+        }
+    }
+
+    type Inner<'a> = &'a Self;
+    async fn get(&self) -> Self::Inner<'_> {
+        self
+    }
+
+    // Sending only takes the lock briefly, so repliers can still run together
+    fn desc() -> Vec<SystemParamDesc> {
+        vec![SystemParamDesc::new::<EventReplies>(false)]
     }
 }
 
 pub static EVENT_BUS: LazyLock<Arc<TypedBus>> = LazyLock::new(|| Arc::new(TypedBus::default()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kerbin_macros::State;
+
+    struct Ping;
+
+    #[derive(State, Default)]
+    struct Counter(Vec<&'static str>);
+
+    async fn count_always(counter: ResMut<Counter>) {
+        get!(mut counter);
+        counter.0.push("always");
+    }
+
+    async fn count_once(counter: ResMut<Counter>) {
+        get!(mut counter);
+        counter.0.push("once");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn once_and_unsubscribed_systems_stop_running() {
+        let bus = TypedBus::default();
+        let mut state = State::new();
+        state.state(EventStorage::default()).state(Counter::default());
+
+        let always = bus.subscribe::<Ping>().await.system(count_always);
+        bus.subscribe_once::<Ping>().await.system(count_once);
+
+        bus.emit_marker::<Ping>().await;
+        bus.resolve(&mut state).await;
+        bus.emit_marker::<Ping>().await;
+        bus.resolve(&mut state).await;
+
+        assert!(bus.unsubscribe(always).await);
+        assert!(!bus.unsubscribe(always).await);
+
+        bus.emit_marker::<Ping>().await;
+        bus.resolve(&mut state).await;
+
+        let mut counts = state.lock_state::<Counter>().await.0.clone();
+        counts.sort();
+        assert_eq!(counts, vec!["always", "always", "once"]);
+    }
+
+    struct Saved(&'static str);
+
+    async fn count_saved(event: EventData<Saved>, counter: ResMut<Counter>) {
+        get!(Some(event), mut counter);
+        counter.0.push(event.0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn filtered_systems_skip_non_matching_events() {
+        let bus = TypedBus::default();
+        let mut state = State::new();
+        state.state(EventStorage::default()).state(Counter::default());
+
+        let rust_only = EventData::<Saved>::filter(|e| e.0.ends_with(".rs"));
+        bus.subscribe::<Saved>()
+            .await
+            .filter(rust_only.clone())
+            .system(count_saved);
+        bus.subscribe_once::<Saved>()
+            .await
+            .filter(rust_only)
+            .system(count_saved);
+
+        for path in ["a.md", "b.rs", "c.toml", "d.rs"] {
+            bus.emit(Saved(path)).await;
+            bus.resolve(&mut state).await;
+        }
+
+        // The once subscriber outlived the skipped events, then ran for the first match
+        assert_eq!(state.lock_state::<Counter>().await.0, vec!["b.rs", "b.rs", "d.rs"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn once_subscribers_use_their_own_filter_result() {
+        let bus = TypedBus::default();
+        let mut state = State::new();
+        state.state(EventStorage::default()).state(Counter::default());
+
+        bus.subscribe::<Saved>()
+            .await
+            .filter(EventData::<Saved>::filter(|e| e.0.ends_with(".rs")))
+            .system(count_saved);
+        bus.subscribe_once::<Saved>().await.system(count_once);
+        bus.subscribe_once::<Saved>()
+            .await
+            .filter(EventData::<Saved>::filter(|e| e.0.ends_with(".md")))
+            .system(count_saved);
+
+        for path in ["b.rs", "a.md"] {
+            bus.emit(Saved(path)).await;
+            bus.resolve(&mut state).await;
+        }
+
+        // The `.md` once subscriber skipped `b.rs` and was still there for `a.md`
+        let mut counts = state.lock_state::<Counter>().await.0.clone();
+        counts.sort();
+        assert_eq!(counts, vec!["a.md", "b.rs", "once"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn each_emit_in_a_frame_is_delivered() {
+        let bus = TypedBus::default();
+        let mut state = State::new();
+        state.state(EventStorage::default()).state(Counter::default());
+
+        bus.subscribe::<Saved>().await.system(count_saved);
+        bus.subscribe_once::<Saved>().await.system(count_once);
+
+        bus.emit(Saved("a.rs")).await;
+        bus.emit(Saved("b.rs")).await;
+        bus.resolve(&mut state).await;
+
+        // The once subscriber only took the first
+        assert_eq!(state.lock_state::<Counter>().await.0, vec!["a.rs", "once", "b.rs"]);
+    }
+
+    struct Check(&'static str);
+
+    async fn veto_secret(event: EventData<Check>, reply: EventReply<Result<(), String>>) {
+        get!(event, reply);
+        let Some(event) = event else { return };
+
+        match event.0 {
+            "secret" => reply.send(Err("no secrets".to_string())).await,
+            _ => reply.send(Ok(())).await,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn collecting_emit_returns_replies() {
+        let bus = TypedBus::default();
+        let mut state = State::new();
+        state
+            .state(EventStorage::default())
+            .state(EventReplies::default());
+
+        assert!(bus.emit_collecting::<Check, Result<(), String>>(Check("a"), &mut state).await.is_empty());
+
+        bus.subscribe::<Check>().await.system(veto_secret);
+
+        let replies = bus.emit_collecting::<_, Result<(), String>>(Check("a"), &mut state).await;
+        assert_eq!(replies, vec![Ok(())]);
+
+        // Subscribers are still there for the next emit
+        let replies = bus.emit_collecting::<_, Result<(), String>>(Check("secret"), &mut state).await;
+        assert_eq!(replies, vec![Err("no secrets".to_string())]);
+
+        // Replies of another type are dropped
+        assert!(bus.emit_collecting::<_, bool>(Check("a"), &mut state).await.is_empty());
+    }
+}
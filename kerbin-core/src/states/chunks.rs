@@ -1,11 +1,26 @@
 use std::{collections::HashMap, sync::Arc};
 
-use ratatui::{buffer::Buffer, layout::Rect};
+use ratatui::{
+    buffer::{Buffer, Cell},
+    layout::{Position, Rect},
+    style::Style,
+};
 use tokio::sync::RwLock;
 
 use crate::*;
 
 /// State managing and organizing drawing chunks (buffers)
+///
+/// Chunks are grouped into layers by z-index and composited in ascending order,
+/// so higher layers draw over lower ones. The core editor uses:
+/// - `0`: buffers, gutters, bufferline and statusline
+/// - `1`: help menu and log
+/// - `2`: command palette
+/// - `3`: dialogues
+///
+/// Chunks are registered fresh every frame, but only the ones whose content changed since
+/// the last frame are composited again (see [`Chunks::damage`]). Renderers that know the
+/// version of what they show can skip drawing too (see [`InnerChunk::draw_version`]).
 #[derive(State, Default)]
 pub struct Chunks {
     /// Layered storage for drawing chunks, keyed by z-index then slot index
@@ -13,22 +28,170 @@ pub struct Chunks {
     chunk_idx_map: HashMap<String, (usize, usize, Rect)>,
     /// Tracks the count of registered indexed chunks per type name
     indexed_chunk_counts: HashMap<String, usize>,
+
+    /// Chunks of the last frame by key, with their z-index and rect, to find what changed
+    previous: HashMap<String, (usize, Rect, Arc<RwLock<InnerChunk>>)>,
+    /// Set to composite every chunk on the next frame, whether it changed or not
+    force_redraw: bool,
+
+    /// The screen as last composited from the chunks
+    pub composited: Buffer,
+    /// Terminal cursor as last drawn
+    pub drawn_cursor: Option<(u16, u16, CursorShape)>,
+
+    /// Style every chunk, and the screen under them, starts filled with
+    base_style: Style,
+}
+
+/// The parts of the screen to composite again, from [`Chunks::damage`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Damage {
+    /// Everything, as after a resize or a redraw was forced
+    Full,
+    /// The areas of the chunks that changed, appeared or went away
+    Rects(Vec<Rect>),
 }
 
 impl Chunks {
-    /// Clears all registered chunks and their associated buffers
+    /// Clears all registered chunks and their associated buffers. The chunks are kept
+    /// until the next clear to compare the next frame against
     pub fn clear(&mut self) {
+        self.previous = self
+            .chunk_idx_map
+            .iter()
+            .map(|(key, (z, slot, rect))| {
+                (key.clone(), (*z, *rect, self.buffers[*z][*slot].clone()))
+            })
+            .collect();
+
         self.buffers.clear();
         self.chunk_idx_map.clear();
         self.indexed_chunk_counts.clear();
     }
 
+    /// Sets the style new chunks are filled with (see [`Theme::default_style`]).
+    /// Everything is composited again when it changes
+    pub fn set_base_style(&mut self, style: Style) {
+        if self.base_style != style {
+            self.base_style = style;
+            self.force_redraw = true;
+        }
+    }
+
+    /// An empty cell in the base style, for filling areas no chunk covers
+    pub fn base_cell(&self) -> Cell {
+        let mut cell = Cell::default();
+        cell.set_style(self.base_style);
+        cell
+    }
+
+    /// Composites every chunk on the next frame, such as after a resize or once something
+    /// else has drawn over the terminal
+    pub fn force_redraw(&mut self) {
+        self.force_redraw = true;
+    }
+
+    /// Finds what changed since the last frame: chunks whose content differs from their
+    /// last frame's, and chunks that were added, moved or removed. Chunks drawn from a
+    /// version are compared by it instead of cell by cell
+    pub async fn damage(&mut self) -> Damage {
+        if std::mem::take(&mut self.force_redraw) {
+            return Damage::Full;
+        }
+
+        let mut rects = vec![];
+        for (key, (z, slot, rect)) in &self.chunk_idx_map {
+            let Some((prev_z, prev_rect, prev)) = self.previous.get(key) else {
+                rects.push(*rect);
+                continue;
+            };
+
+            let prev = prev.read().await;
+            let cur = self.buffers[*z][*slot].read().await;
+            let unchanged = prev_z == z
+                && prev_rect == rect
+                && match (prev.version(), cur.version()) {
+                    (None, None) => **prev == **cur,
+                    (prev_version, version) => prev_version == version,
+                };
+            if !unchanged {
+                rects.push(*rect);
+                // A chunk that moved leaves its old area behind
+                if prev_rect != rect {
+                    rects.push(*prev_rect);
+                }
+            }
+        }
+        for (key, (_, prev_rect, _)) in &self.previous {
+            if !self.chunk_idx_map.contains_key(key) {
+                rects.push(*prev_rect);
+            }
+        }
+
+        Damage::Rects(rects)
+    }
+
+    /// Clears every chunk registered on the given layer, leaving other layers untouched
+    pub fn clear_layer(&mut self, layer: usize) {
+        let Some(buffers) = self.buffers.get_mut(layer) else {
+            return;
+        };
+        buffers.clear();
+
+        self.chunk_idx_map.retain(|_, (z, _, _)| *z != layer);
+
+        // Indexed counts are derived from the surviving `"TypeName[index]"` keys
+        self.indexed_chunk_counts.clear();
+        for key in self.chunk_idx_map.keys() {
+            let Some((name, index)) = key
+                .strip_suffix(']')
+                .and_then(|k| k.rsplit_once('['))
+                .and_then(|(name, idx)| Some((name, idx.parse::<usize>().ok()?)))
+            else {
+                continue;
+            };
+
+            let count = self
+                .indexed_chunk_counts
+                .entry(name.to_string())
+                .or_insert(0);
+            *count = (*count).max(index + 1);
+        }
+    }
+
+    /// Returns the number of layers that currently have storage allocated
+    pub fn layer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Iterates the chunks registered on a layer, in registration order
+    pub fn layer_chunks(&self, layer: usize) -> impl Iterator<Item = &Arc<RwLock<InnerChunk>>> {
+        self.buffers.get(layer).into_iter().flatten()
+    }
+
+    /// Returns true if the chunk has been registered since the last clear
+    pub fn is_registered<C: StateName + StaticState>(&self) -> bool {
+        self.chunk_idx_map.contains_key(&C::static_name())
+    }
+
     /// Internal helper: registers a chunk by a string key at the given z-index and rect
     fn register_by_key(&mut self, key: String, z_index: usize, rect: Rect) {
         if self.buffers.len() <= z_index {
             self.buffers.resize(z_index + 1, Vec::default());
         }
 
+        let mut chunk = InnerChunk::new(Buffer::filled(rect, self.base_cell()));
+
+        // Last frame's content stays usable while the chunk is in the same place, unless
+        // everything is being drawn again
+        if !self.force_redraw
+            && let Some((prev_z, prev_rect, prev)) = self.previous.get(&key)
+            && (*prev_z, *prev_rect) == (z_index, rect)
+            && let Ok(mut prev) = prev.try_write()
+        {
+            chunk.keep_drawn(&mut prev);
+        }
+
         let slot = self.buffers[z_index].len();
         let coords = self
             .chunk_idx_map
@@ -37,12 +200,10 @@ impl Chunks {
         // Update the stored rect each time the chunk is re-registered (layout may change)
         coords.2 = rect;
 
-        let buffer = Buffer::empty(rect);
-
         if self.buffers[z_index].len() == coords.1 {
-            self.buffers[z_index].push(Arc::new(RwLock::new(InnerChunk::new(buffer))));
+            self.buffers[z_index].push(Arc::new(RwLock::new(chunk)));
         } else {
-            self.buffers[z_index][coords.1] = Arc::new(RwLock::new(InnerChunk::new(buffer)));
+            self.buffers[z_index][coords.1] = Arc::new(RwLock::new(chunk));
         }
     }
 
@@ -63,6 +224,17 @@ impl Chunks {
         self.chunk_idx_map.get(name).map(|(_, _, rect)| *rect)
     }
 
+    /// Returns the key and rect of the topmost chunk covering a screen cell.
+    /// Higher layers win, and within a layer the earliest registered chunk does,
+    /// so indexed pane chunks are found before the focused pane's named copies.
+    pub fn chunk_at(&self, column: u16, row: u16) -> Option<(&str, Rect)> {
+        self.chunk_idx_map
+            .iter()
+            .filter(|(_, (_, _, rect))| rect.contains(Position::new(column, row)))
+            .max_by_key(|(_, (z, slot, _))| (*z, std::cmp::Reverse(*slot)))
+            .map(|(key, (_, _, rect))| (key.as_str(), *rect))
+    }
+
     /// Registers a chunk for a specific pane index at the given z-index and rect.
     /// Indexed chunks use a synthetic key `"TypeName[index]"` separate from named chunks.
     pub fn register_indexed_chunk<C: StateName + StaticState>(
@@ -107,3 +279,98 @@ impl Chunks {
             .unwrap_or(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ratatui::style::Style;
+
+    use super::*;
+
+    async fn damaged(chunks: &mut Chunks) -> Vec<Rect> {
+        let Damage::Rects(mut rects) = chunks.damage().await else {
+            panic!("expected damaged rects");
+        };
+        rects.sort_by_key(|rect| (rect.x, rect.y));
+        rects
+    }
+
+    #[tokio::test]
+    async fn only_changed_chunks_are_damaged() {
+        let mut chunks = Chunks::default();
+        let (left, right) = (Rect::new(0, 0, 10, 5), Rect::new(10, 0, 10, 5));
+
+        chunks.register_indexed_chunk::<BufferChunk>(0, 0, left);
+        chunks.register_indexed_chunk::<BufferChunk>(1, 0, right);
+        assert_eq!(damaged(&mut chunks).await, vec![left, right]);
+
+        // The same content drawn again damages nothing
+        chunks.clear();
+        chunks.register_indexed_chunk::<BufferChunk>(0, 0, left);
+        chunks.register_indexed_chunk::<BufferChunk>(1, 0, right);
+        let chunk = chunks.get_indexed_chunk::<BufferChunk>(1).unwrap();
+        chunk.write().await.set_string(10, 0, "hi", Style::default());
+        assert_eq!(damaged(&mut chunks).await, vec![right]);
+
+        // A chunk going away damages the area it covered
+        chunks.clear();
+        chunks.register_indexed_chunk::<BufferChunk>(0, 0, left);
+        assert_eq!(damaged(&mut chunks).await, vec![right]);
+
+        chunks.force_redraw();
+        assert_eq!(chunks.damage().await, Damage::Full);
+    }
+
+    #[tokio::test]
+    async fn chunks_drawn_from_the_same_version_are_kept() {
+        let mut chunks = Chunks::default();
+        let area = Rect::new(0, 0, 10, 5);
+
+        chunks.register_chunk::<BufferChunk>(0, area);
+        let chunk = chunks.get_chunk::<BufferChunk>().unwrap();
+        assert!(chunk.write().await.draw_version(1));
+        chunk.write().await.set_string(0, 0, "hi", Style::default());
+        assert_eq!(damaged(&mut chunks).await, vec![area]);
+
+        // Nothing is drawn, yet last frame's content is back and damages nothing
+        chunks.clear();
+        chunks.register_chunk::<BufferChunk>(0, area);
+        let chunk = chunks.get_chunk::<BufferChunk>().unwrap();
+        assert!(!chunk.write().await.draw_version(1));
+        assert_eq!(chunk.read().await[(1, 0)].symbol(), "i");
+        assert!(damaged(&mut chunks).await.is_empty());
+
+        // A new version is drawn from scratch
+        chunks.clear();
+        chunks.register_chunk::<BufferChunk>(0, area);
+        let chunk = chunks.get_chunk::<BufferChunk>().unwrap();
+        assert!(chunk.write().await.draw_version(2));
+        assert_eq!(chunk.read().await[(1, 0)].symbol(), " ");
+        assert_eq!(damaged(&mut chunks).await, vec![area]);
+
+        // Moving draws again, and damages the area left behind too
+        let moved = Rect::new(5, 0, 10, 5);
+        chunks.clear();
+        chunks.register_chunk::<BufferChunk>(0, moved);
+        let chunk = chunks.get_chunk::<BufferChunk>().unwrap();
+        assert!(chunk.write().await.draw_version(2));
+        assert_eq!(damaged(&mut chunks).await, vec![area, moved]);
+    }
+
+    #[tokio::test]
+    async fn chunks_start_filled_with_the_base_style() {
+        let mut chunks = Chunks::default();
+        let base = Style::default().bg(ratatui::style::Color::Blue);
+        let area = Rect::new(0, 0, 4, 2);
+
+        chunks.set_base_style(base);
+        assert_eq!(chunks.damage().await, Damage::Full);
+
+        chunks.register_chunk::<BufferChunk>(0, area);
+        let chunk = chunks.get_chunk::<BufferChunk>().unwrap();
+        assert_eq!(chunk.read().await[(3, 1)].bg, ratatui::style::Color::Blue);
+
+        // Setting the same style again doesn't redraw everything
+        chunks.set_base_style(base);
+        assert_ne!(chunks.damage().await, Damage::Full);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::*;
+
+/// Most closed buffers remembered for `reopen`
+const MAX_CLOSED: usize = 50;
+
+/// Files closed this session with their last cursor position, oldest first.
+/// `reopen` pops them like reopening a closed browser tab.
+#[derive(Default, State)]
+pub struct ClosedBuffers {
+    entries: Vec<Jump>,
+}
+
+impl ClosedBuffers {
+    pub fn entries(&self) -> &[Jump] {
+        &self.entries
+    }
+
+    /// Remembers `buf` as just closed. Special buffers like `<scratch>` aren't backed by a
+    /// file, so they're skipped. A file closed again replaces its older entry.
+    pub fn record(&mut self, buf: &TextBuffer) {
+        if buf.path.starts_with('<') && buf.path.ends_with('>') {
+            return;
+        }
+
+        self.entries.retain(|entry| entry.path != buf.path);
+        self.entries.push(Jump::from_buffer(buf));
+
+        if self.entries.len() > MAX_CLOSED {
+            let excess = self.entries.len() - MAX_CLOSED;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// Takes the most recently closed file
+    pub fn pop(&mut self) -> Option<Jump> {
+        self.entries.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, text: &str, byte: usize) -> TextBuffer {
+        let mut buf = TextBuffer {
+            path: path.into(),
+            ..TextBuffer::scratch()
+        };
+        buf.insert(0, text);
+        buf.primary_cursor_mut().move_to(byte);
+        buf
+    }
+
+    /// Path and cursor byte of the selected buffer
+    async fn selected(state: &State) -> (String, usize) {
+        let buffers = state.lock_state::<Buffers>().await;
+        let buf = buffers.cur_buffer_as::<TextBuffer>().await.unwrap();
+        (buf.path.clone(), buf.primary_cursor().get_cursor_byte())
+    }
+
+    #[tokio::test]
+    async fn reopens_closed_files_last_in_first_out() {
+        let dir = std::env::temp_dir().join(format!("kerbin-reopen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut buffers = Buffers::default();
+        for (name, text, byte) in [("a.rs", "fn a() {}", 3), ("b.rs", "fn b() {}", 5)] {
+            let path = dir.join(name);
+            std::fs::write(&path, text).unwrap();
+            buffers.open(path.to_string_lossy().into_owned(), 4, u64::MAX).await.unwrap();
+            buffers.cur_text_buffer_mut().await.unwrap().primary_cursor_mut().move_to(byte);
+        }
+
+        let (_log, log_sender) = LogState::new_with_channel();
+        let mut state = State::new();
+        state
+            .state(buffers)
+            .state(log_sender)
+            .state(CoreConfig::default())
+            .state(SplitState::default())
+            .state(ClosedBuffers::default());
+
+        // Closing the scratch buffer left in their place isn't remembered
+        for _ in 0..3 {
+            assert!(BuffersCommand::CloseBufferOffset(None).apply(&mut state).await);
+        }
+        assert_eq!(state.lock_state::<ClosedBuffers>().await.entries().len(), 2);
+
+        // `b.rs` was selected, so it closed first and reopens last
+        let reopen = BuffersCommand::ReopenBuffer;
+        assert!(reopen.apply(&mut state).await);
+        let (path, byte) = selected(&state).await;
+        assert!(path.ends_with("a.rs") && byte == 3, "{path} at {byte}");
+        assert!(reopen.apply(&mut state).await);
+        let (path, byte) = selected(&state).await;
+        assert!(path.ends_with("b.rs") && byte == 5, "{path} at {byte}");
+        assert!(!reopen.apply(&mut state).await);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn closing_a_file_again_replaces_its_entry() {
+        let mut closed = ClosedBuffers::default();
+        closed.record(&file("a.rs", "", 0));
+        closed.record(&file("b.rs", "", 0));
+        closed.record(&file("a.rs", "", 0));
+
+        let paths: Vec<_> = closed.entries().iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["b.rs", "a.rs"]);
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock as SyncRwLock},
+};
+
+use tokio::sync::RwLock;
+
+use crate::*;
+
+/// User-defined commands that run a list of other commands, set with `alias` in config.
+/// `CommandRegistry::register_aliases` makes them parse ahead of registered commands, so
+/// they can shadow them. Clones share the same aliases
+#[derive(State, Default, Clone)]
+pub struct CommandAliasRegistry(Arc<SyncRwLock<HashMap<String, Vec<String>>>>);
+
+impl CommandAliasRegistry {
+    /// Defines `name` as running `commands` in order, replacing any earlier definition.
+    /// Fails without changing anything if `name` would end up expanding into itself.
+    pub fn register(&mut self, name: impl ToString, commands: Vec<String>) -> Result<(), String> {
+        let name = name.to_string();
+        let mut aliases = self.0.write().unwrap();
+        let old = aliases.insert(name.clone(), commands);
+
+        if expands_into(&aliases, &name, &name, &mut HashSet::new()) {
+            match old {
+                Some(old) => aliases.insert(name.clone(), old),
+                None => aliases.remove(&name),
+            };
+            return Err(format!("Alias `{name}` would expand into itself"));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the commands `name` expands into, if it's an alias
+    pub fn get(&self, name: &str) -> Option<Vec<String>> {
+        self.0.read().unwrap().get(name).cloned()
+    }
+
+    /// Clears all aliases
+    pub fn clear(&mut self) {
+        self.0.write().unwrap().clear();
+    }
+
+    /// A command set parsing these aliases into `AliasCommand`s, following later changes
+    pub fn command_set(&self) -> RegisteredCommandSet {
+        let aliases = self.clone();
+        RegisteredCommandSet {
+            parser: Box::new(move |tokens| {
+                let Some(Token::Word(name)) = tokens.first() else {
+                    return None;
+                };
+                let commands = aliases.get(name)?;
+                if tokens.len() > 1 {
+                    return Some(Err(format!("Alias `{name}` takes no arguments").into()));
+                }
+                Some(Ok(Box::new(AliasCommand {
+                    name: name.clone(),
+                    commands,
+                })))
+            }),
+            infos: vec![],
+            unregistered: vec![],
+        }
+    }
+}
+
+/// Whether expanding `alias` reaches `target`, following nested aliases
+fn expands_into(
+    aliases: &HashMap<String, Vec<String>>,
+    alias: &str,
+    target: &str,
+    seen: &mut HashSet<String>,
+) -> bool {
+    let Some(commands) = aliases.get(alias) else {
+        return false;
+    };
+
+    commands.iter().filter_map(|cmd| command_name(cmd)).any(|name| {
+        name == target
+            || (seen.insert(name.clone()) && expands_into(aliases, &name, target, seen))
+    })
+}
+
+fn command_name(command: &str) -> Option<String> {
+    match tokenize(command).ok()?.into_iter().next()? {
+        Token::Word(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Buffer flag set while a write is held back to finish later, like format-on-save
+/// waiting on its server. Set it when cancelling the write, and clear it once the write
+/// is sent again, so an alias like `wq` waits for the save before running `q`
+pub const SAVE_HELD: &str = "save_held";
+
+/// Rest of the aliases that stopped at a held write, by the path being saved
+#[derive(State, Default)]
+pub struct AfterSave(pub Vec<(String, AliasCommand)>);
+
+/// Runs the rest of an alias once the write it stopped at goes through
+pub async fn run_after_save(
+    event: EventData<SaveEvent>,
+    after: ResMut<AfterSave>,
+    sender: Res<CommandSender>,
+) {
+    get!(Some(event), mut after, sender);
+
+    let (ready, waiting) = std::mem::take(&mut after.0)
+        .into_iter()
+        .partition(|(path, _)| *path == event.path);
+    after.0 = waiting;
+    for (_, rest) in ready {
+        let _ = sender.send(Box::new(rest));
+    }
+}
+
+/// An alias being run, parsing each of its commands just before running it. Undone as one
+/// step, and stops at a command that doesn't parse
+#[derive(Clone)]
+pub struct AliasCommand {
+    pub name: String,
+    pub commands: Vec<String>,
+}
+
+impl CommandAny for AliasCommand {
+    fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Command<State> for AliasCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        let batch = begin_undo_batch(state).await;
+        let mut res = true;
+        for (i, command) in self.commands.iter().enumerate() {
+            let parsed = state.lock_state::<CommandRegistry>().await.try_parse_command(
+                tokenize(command).unwrap_or_default(),
+                false,
+                Some(&resolver_engine().await.as_resolver()),
+                true,
+                &*state.lock_state::<CommandPrefixRegistry>().await,
+                &*state.lock_state::<ModeStack>().await,
+            );
+            let parsed = match parsed {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    state
+                        .lock_state::<LogSender>()
+                        .await
+                        .high("commands::alias", format!("In alias `{}`: {e}", self.name));
+                    res = false;
+                    break;
+                }
+            };
+            res &= dispatch_command(parsed.as_ref(), state).await;
+
+            if let Some(path) = held_save(state).await {
+                let rest = AliasCommand {
+                    name: self.name.clone(),
+                    commands: self.commands[i + 1..].to_vec(),
+                };
+                if !rest.commands.is_empty() {
+                    state.lock_state::<AfterSave>().await.0.push((path, rest));
+                }
+                break;
+            }
+        }
+        end_undo_batch(batch).await;
+        res
+    }
+}
+
+/// Path of the current buffer when its write is being held back
+async fn held_save(state: &State) -> Option<String> {
+    let buffers = state.lock_state::<Buffers>().await;
+    let buf = buffers.cur_text_buffer().await?;
+    buf.flags.contains(SAVE_HELD).then(|| buf.path.clone())
+}
+
+/// Opens an undo batch on the current text buffer (see `TextBuffer::begin_undo_batch`),
+/// returning the buffer to pass to `end_undo_batch`. Holding on to it lets the batch close
+/// even if the commands in between switched or closed buffers
+pub async fn begin_undo_batch(state: &State) -> Option<Arc<RwLock<dyn KerbinBuffer>>> {
+    let buf = {
+        let buffers = state.lock_state::<Buffers>().await;
+        buffers.buffers.get(buffers.selected_buffer)?.clone()
+    };
+    buf.write().await.downcast_mut::<TextBuffer>()?.begin_undo_batch();
+    Some(buf)
+}
+
+/// Closes the batch `begin_undo_batch` opened
+pub async fn end_undo_batch(buf: Option<Arc<RwLock<dyn KerbinBuffer>>>) {
+    let Some(buf) = buf else {
+        return;
+    };
+    if let Some(text) = buf.write().await.downcast_mut::<TextBuffer>() {
+        text.end_undo_batch();
+    }
+}
+
+/// Runs each command in order, undone as one step. Built for counted binds.
+pub struct CommandSequence(pub Vec<Box<dyn Command<State>>>);
+
+impl CommandAny for CommandSequence {
+    fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Command<State> for CommandSequence {
+    async fn apply(&self, state: &mut State) -> bool {
+        let batch = begin_undo_batch(state).await;
+        let mut res = true;
+        for command in &self.0 {
+            res &= dispatch_command(command.as_ref(), state).await;
+        }
+        end_undo_batch(batch).await;
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_recursive_aliases() {
+        let mut aliases = CommandAliasRegistry::default();
+
+        assert!(aliases.register("wq", vec!["w".into(), "q".into()]).is_ok());
+        assert!(aliases.register("loop", vec!["loop".into()]).is_err());
+        assert_eq!(aliases.get("loop"), None);
+
+        assert!(aliases.register("a", vec!["b --flag".into()]).is_ok());
+        assert!(aliases.register("b", vec!["c".into()]).is_ok());
+        assert!(aliases.register("c", vec!["a".into()]).is_err());
+
+        // A rejected redefinition keeps the old one
+        assert!(aliases.register("b", vec!["a".into()]).is_err());
+        assert_eq!(aliases.get("b"), Some(vec!["c".to_string()]));
+    }
+
+    const FORMATTED: &str = "test_formatted";
+
+    /// Holds back writes like format-on-save does, until the buffer is flagged as formatted
+    fn hold_unformatted_writes<'a>(
+        cmd: &'a BufferCommand,
+        state: &'a mut State,
+    ) -> InterceptorFuture<'a> {
+        Box::pin(async move {
+            if !matches!(cmd, BufferCommand::WriteFile { .. }) {
+                return InterceptorResult::Allow;
+            }
+            let mut buffers = state.lock_state::<Buffers>().await;
+            let mut buf = buffers.cur_text_buffer_mut().await.unwrap();
+            if buf.flags.contains(FORMATTED) {
+                return InterceptorResult::Allow;
+            }
+            buf.flags.insert(SAVE_HELD);
+            InterceptorResult::Cancel
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn write_quit_waits_for_a_held_save() {
+        let path = std::env::temp_dir().join("kerbin-alias-held-save.txt");
+        std::fs::write(&path, "old").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let mut buffer = TextBuffer::open(path.clone(), 4).unwrap();
+        buffer.rope = "new".into();
+        buffer.dirty = true;
+        let mut buffers = Buffers::default();
+        buffers.push_new(buffer).await;
+
+        let mut aliases = CommandAliasRegistry::default();
+        aliases.register("wq", vec!["w".into(), "q".into()]).unwrap();
+        let mut commands = CommandRegistry(vec![]);
+        commands.register_aliases(&aliases);
+        commands.register::<BufferCommand>();
+        commands.register::<StateCommand>();
+
+        let mut interceptors = CommandInterceptorRegistry::new();
+        interceptors.on_command::<BufferCommand>(hold_unformatted_writes);
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (_, log_sender) = LogState::new_with_channel();
+        let mut state = State::new();
+        state
+            .state(buffers)
+            .state(commands)
+            .state(aliases)
+            .state(interceptors)
+            .state(CommandPrefixRegistry(vec![]))
+            .state(ModeStack(vec!['n']))
+            .state(CoreConfig::default())
+            .state(AfterSave::default())
+            .state(CommandSender(sender))
+            .state(EventStorage::default())
+            .state(EventReplies::default())
+            .state(Running(true))
+            .state(log_sender);
+
+        let wq = state.lock_state::<CommandRegistry>().await.try_parse_command(
+            tokenize("wq").unwrap(),
+            true,
+            None,
+            false,
+            &CommandPrefixRegistry(vec![]),
+            &ModeStack(vec!['n']),
+        );
+        wq.unwrap().apply(&mut state).await;
+
+        // The write is held, so quitting waits instead of failing on the dirty buffer
+        assert!(state.lock_state::<Running>().await.0);
+        assert_eq!(state.lock_state::<AfterSave>().await.0.len(), 1);
+
+        // The formatter finishes and sends the write again
+        {
+            let mut buffers = state.lock_state::<Buffers>().await;
+            let mut buf = buffers.cur_text_buffer_mut().await.unwrap();
+            buf.flags.remove(SAVE_HELD);
+            buf.flags.insert(FORMATTED);
+        }
+        dispatch_command(&BufferCommand::WriteFile { path: None }, &mut state).await;
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+
+        let after_save = EVENT_BUS.subscribe::<SaveEvent>().await.system(run_after_save);
+        EVENT_BUS.resolve(&mut state).await;
+        after_save.unsubscribe().await;
+
+        let rest = receiver.try_recv().unwrap();
+        rest.apply(&mut state).await;
+        assert!(!state.lock_state::<Running>().await.0);
+        assert!(state.lock_state::<AfterSave>().await.0.is_empty());
+    }
+
+    async fn text(state: &State) -> String {
+        let buffers = state.lock_state::<Buffers>().await;
+        buffers.cur_text_buffer().await.unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn sequences_undo_as_one_step() {
+        let (_, log_sender) = LogState::new_with_channel();
+        let mut state = State::new();
+        state
+            .state(Buffers::default())
+            .state(CoreConfig::default())
+            .state(CommandInterceptorRegistry::default())
+            .state(log_sender);
+
+        let append = |text: &str| {
+            Box::new(BufferCommand::Append {
+                text: text.to_string(),
+                extend: false,
+            }) as Box<dyn Command<State>>
+        };
+        let sequence = CommandSequence(vec![
+            Box::new(BufferCommand::StartChange),
+            append("a"),
+            Box::new(BufferCommand::CommitChange),
+            append("b"),
+            Box::new(BufferCommand::CommitChange),
+        ]);
+        sequence.apply(&mut state).await;
+        sequence.apply(&mut state).await;
+
+        assert_eq!(text(&state).await, "abab");
+
+        BufferCommand::Undo.apply(&mut state).await;
+        assert_eq!(text(&state).await, "ab");
+        BufferCommand::Undo.apply(&mut state).await;
+        assert_eq!(text(&state).await, "");
+    }
+}
@@ -12,10 +12,47 @@ impl CommandRegistry {
         self.0.push(RegisteredCommandSet {
             parser: Box::new(T::from_str),
             infos: T::infos(),
+            unregistered: vec![],
         })
     }
 
-    /// Determines if the input string represents a valid command
+    /// Lets the aliases in `aliases` parse, ahead of every registered command
+    pub fn register_aliases(&mut self, aliases: &CommandAliasRegistry) {
+        self.0.insert(0, aliases.command_set());
+    }
+
+    /// Hides the command called `name` (or any of its aliases) from parsing, the palette
+    /// and listings, so a config can replace it. Returns whether it was registered
+    pub fn unregister(&mut self, name: &str) -> bool {
+        for set in &mut self.0 {
+            if let Some(pos) = set.infos.iter().position(|info| info.check_name(name)) {
+                let info = set.infos.remove(pos);
+                set.unregistered.push((pos, info));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Puts back every command taken out by `unregister`, where it was before
+    pub fn restore_unregistered(&mut self) {
+        for set in &mut self.0 {
+            // Undone latest first, so each index is the one it was removed from
+            while let Some((pos, info)) = set.unregistered.pop() {
+                set.infos.insert(pos, info);
+            }
+        }
+    }
+
+    /// Looks up the metadata of the command registered under `name`
+    pub fn command_info(&self, name: &str) -> Option<&CommandInfo> {
+        self.0
+            .iter()
+            .flat_map(|s| &s.infos)
+            .find(|info| info.check_name(name))
+    }
+
+    /// Determines if the input string represents a valid command, explaining why if not
     pub fn validate_command(
         &self,
         input: &str,
@@ -23,7 +60,7 @@ impl CommandRegistry {
 
         prefix_registry: &CommandPrefixRegistry,
         modes: &ModeStack,
-    ) -> bool {
+    ) -> Result<(), ParseError> {
         let tokens = tokenize(input).unwrap_or_default();
 
         // Expand without running — CommandSubst tokens remain if not yet resolvable.
@@ -36,11 +73,11 @@ impl CommandRegistry {
         // If any dynamic tokens remain unresolved we can't statically validate;
         // optimistically treat the input as valid.
         if has_dynamic_tokens(&expanded) {
-            return true;
+            return Ok(());
         }
 
-        self.parse_command(tokens, false, true, resolver, false, prefix_registry, modes)
-            .is_some()
+        self.try_parse_command(tokens, true, resolver, false, prefix_registry, modes)
+            .map(|_| ())
     }
 
     /// Retrieves command suggestions and theming for the palette
@@ -48,71 +85,103 @@ impl CommandRegistry {
         &self,
         input: &str,
         theme: &Theme,
-    ) -> (
-        Vec<Line<'static>>,
-        Option<String>,
-        Option<Vec<Line<'static>>>,
-    ) {
+    ) -> Vec<PaletteSuggestion> {
         let resolver = resolver_engine().await;
         resolver.as_resolver().expand_str(input, false);
 
         let tokens = tokenize(input).unwrap_or_default();
 
         if tokens.is_empty() {
-            return (vec![], None, None);
+            return vec![];
         }
 
         let first_name = match tokens.first() {
             Some(Token::Word(s)) => s.clone(),
-            _ => return (vec![], None, None),
+            _ => return vec![],
         };
 
         let mut res = vec![];
 
         for registry in &self.0 {
             for info in &registry.infos {
-                for valid_name in &info.valid_names {
-                    let Some(rnk) = rank(&first_name, valid_name) else {
-                        continue;
-                    };
-
+                // Rank each command by whichever of its names matches best
+                let best = info
+                    .valid_names
+                    .iter()
+                    .filter_map(|name| rank(&first_name, name).map(|rnk| (rnk, name)))
+                    .max_by(|a, b| a.0.cmp(&b.0).then(b.1.len().cmp(&a.1.len())));
+
+                if let Some((rnk, valid_name)) = best {
                     res.push((rnk, info, valid_name.to_string()));
-                    break;
                 }
             }
         }
 
-        res.sort_by(|l, r| l.0.cmp(&r.0));
+        res.sort_by(|a, b| b.0.cmp(&a.0).then(a.2.len().cmp(&b.2.len())));
 
-        let desc = res.first().and_then(|x| x.1.desc_buf(theme));
+        // Names only complete while the name is all that's typed
+        let completes = tokens.len() == 1;
 
-        let completion = if tokens.len() == 1 {
-            res.first().map(|x| x.2.clone())
-        } else {
-            None
-        };
+        res.iter()
+            .enumerate()
+            .map(|(i, x)| PaletteSuggestion {
+                label: x.2.clone(),
+                line: x.1.as_suggestion_with_search(i == 0 && completes, input, theme),
+                completion: completes.then(|| x.2.clone()),
+                desc: x.1.desc_buf(theme),
+            })
+            .collect()
+    }
 
-        (
-            res.iter()
+    /// Retrieves completions for the argument being typed, if the named command has a
+    /// completer for that slot. Returns `None` so the palette falls back to command names.
+    pub async fn get_arg_suggestions(
+        &self,
+        input: &str,
+        buffers: &Buffers,
+        theme: &Theme,
+    ) -> Option<Vec<PaletteSuggestion>> {
+        let name = input.split_whitespace().next()?;
+        let info = self
+            .0
+            .iter()
+            .flat_map(|s| &s.infos)
+            .find(|info| info.check_name(name))?;
+
+        let position = arg_position(input, info)?;
+        let completer = info.completer(position.idx)?;
+
+        let candidates = complete_arg(completer, position.partial, buffers).await;
+
+        let desc = info.desc_buf(theme);
+
+        let auto_style = theme.get_fallback_default([
+            "ui.commandline.auto_name",
+            "ui.commandline.primary_name",
+            "ui.text",
+        ]);
+        let style = theme.get_fallback_default(["ui.commandline.names", "ui.text"]);
+
+        Some(
+            candidates
+                .into_iter()
                 .enumerate()
-                .map(|(i, x)| {
-                    if i == 0 && completion.is_some() {
-                        x.1.as_suggestion_with_search(true, input, theme)
-                    } else {
-                        x.1.as_suggestion_with_search(false, input, theme)
-                    }
+                .map(|(i, c)| PaletteSuggestion {
+                    line: Line::styled(c.clone(), if i == 0 { auto_style } else { style }),
+                    completion: Some(format!("{}{}", position.head, quote_arg(&c))),
+                    desc: desc.clone(),
+                    label: c,
                 })
                 .collect(),
-            completion,
-            desc,
         )
     }
 
     #[allow(clippy::too_many_arguments)]
-    /// Parses a list of tokens into a runnable command
+    /// Parses a list of tokens into a runnable command.
+    /// A thin wrapper over `try_parse_command` for callers that don't need the reason.
     pub fn parse_command(
         &self,
-        mut tokens: Vec<Token>,
+        tokens: Vec<Token>,
         log_errors: bool,
         prefix_checked: bool,
 
@@ -122,6 +191,36 @@ impl CommandRegistry {
         prefix_registry: &CommandPrefixRegistry,
         modes: &ModeStack,
     ) -> Option<Box<dyn Command<State>>> {
+        match self.try_parse_command(
+            tokens,
+            prefix_checked,
+            resolver,
+            allow_run,
+            prefix_registry,
+            modes,
+        ) {
+            Ok(cmd) => Some(cmd),
+            Err(e) => {
+                if log_errors {
+                    tracing::error!("Failed to parse command: {e}");
+                }
+                None
+            }
+        }
+    }
+
+    /// Parses a list of tokens into a runnable command, or the reason it couldn't be
+    pub fn try_parse_command(
+        &self,
+        mut tokens: Vec<Token>,
+        prefix_checked: bool,
+
+        resolver: Option<&Resolver<'_>>,
+        allow_run: bool,
+
+        prefix_registry: &CommandPrefixRegistry,
+        modes: &ModeStack,
+    ) -> Result<Box<dyn Command<State>>, ParseError> {
         if let Some(resolver) = resolver {
             // Two-phase expansion: first identify the command by name, then expand
             // only the slots that are not marked `ignore` in that command's metadata.
@@ -162,7 +261,9 @@ impl CommandRegistry {
                         for infos in &self.0 {
                             if infos.infos.iter().any(|x| {
                                 let matches_word0 = x.check_name(&first_word);
-                                let matches_prefix = prefix.list.iter().any(|l| x.check_name(l));
+                                let matches_prefix = prefix.list.iter().any(|l| {
+                                    x.valid_names.iter().any(|n| prefix.match_kind.matches(l, n))
+                                });
                                 matches_word0 && matches_prefix
                             }) {
                                 has_name = true;
@@ -193,23 +294,60 @@ impl CommandRegistry {
         }
 
         if tokens.is_empty() {
-            return None;
+            return Err(ParseError::Empty);
         }
 
-        for registry in &self.0 {
-            if let Some(cmd) = (registry.parser)(&tokens) {
-                match cmd {
-                    Ok(t) => return Some(t),
-                    Err(e) => {
-                        if log_errors {
-                            tracing::error!("Failed to parse command due to: {e:?}");
-                        }
-                        return None;
-                    }
-                }
+        // Sets skip the commands unregistered from them, which an alias may have taken over
+        let name = match tokens.first() {
+            Some(Token::Word(name)) => name.as_str(),
+            _ => "",
+        };
+        match self
+            .0
+            .iter()
+            .filter(|set| !set.unregistered.iter().any(|(_, info)| info.check_name(name)))
+            .find_map(|registry| (registry.parser)(&tokens))
+        {
+            Some(Ok(cmd)) => Ok(cmd),
+            Some(Err(error)) => Err(ParseError::InvalidArgument {
+                command: token_to_string(&tokens[0]),
+                error,
+            }),
+            None => Err(self.explain_rejection(&tokens)),
+        }
+    }
+
+    /// Works out why no parser accepted `tokens`
+    fn explain_rejection(&self, tokens: &[Token]) -> ParseError {
+        let Some(state) = CommandState::parse(tokens) else {
+            return ParseError::UnknownCommand(token_to_string(&tokens[0]));
+        };
+
+        let Some(info) = self.command_info(&state.name) else {
+            return ParseError::UnknownCommand(state.name);
+        };
+
+        // Keyword commands take `key=value` words as flags, not positionals
+        let state = if info.keyword {
+            CommandState::parse_keywords(tokens).unwrap_or(state)
+        } else {
+            state
+        };
+
+        let (min, max) = info.arity;
+        let found = state.positional.len();
+        if found < min || found > max {
+            ParseError::ArityMismatch {
+                command: state.name,
+                min,
+                max,
+                found,
+            }
+        } else {
+            ParseError::BadArguments {
+                command: state.name,
             }
         }
-        None
     }
 }
 
@@ -253,3 +391,241 @@ fn apply_cmd_template(template: Vec<Token>, cmd_tokens: Vec<Token>) -> Vec<Token
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kerbin_macros::Command;
+
+    #[derive(Command)]
+    enum TestCommand {
+        #[command(drop_ident, name = "lsp-hover")]
+        LspHover,
+        #[command(drop_ident, name = "write")]
+        Write,
+        #[command(drop_ident, name = "wrap")]
+        Wrap(#[command(type_name = "[command]", ignore)] Vec<Token>),
+        #[command(drop_ident, name = "repeat")]
+        Repeat(#[command(name = "count", range = "1..=10")] usize),
+        #[command(drop_ident, name = "indent", keyword)]
+        Indent {
+            tabstop: usize,
+            expandtab: bool,
+            shiftwidth: Option<usize>,
+        },
+    }
+
+    #[async_trait::async_trait]
+    impl Command<State> for TestCommand {
+        async fn apply(&self, _state: &mut State) -> bool {
+            true
+        }
+    }
+
+    #[derive(Command)]
+    #[command(category = "buffer")]
+    enum CategorizedCommand {
+        #[command(drop_ident, name = "save")]
+        Save,
+        #[command(drop_ident, name = "hover", category = "lsp")]
+        Hover,
+    }
+
+    #[async_trait::async_trait]
+    impl Command<State> for CategorizedCommand {
+        async fn apply(&self, _state: &mut State) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn categories_come_from_the_variant_then_the_enum() {
+        let category = |infos: Vec<CommandInfo>, name: &str| {
+            let info = infos.into_iter().find(|i| i.valid_names[0] == name).unwrap();
+            info.category
+        };
+
+        assert_eq!(category(CategorizedCommand::infos(), "save"), "buffer");
+        assert_eq!(category(CategorizedCommand::infos(), "hover"), "lsp");
+        assert_eq!(category(TestCommand::infos(), "write"), kerbin_command_lang::UNCATEGORIZED);
+    }
+
+    fn is_wrapped(registry: &CommandRegistry, prefixes: &CommandPrefixRegistry, input: &str) -> bool {
+        let command = registry
+            .parse_command(
+                tokenize(input).unwrap(),
+                false,
+                false,
+                None,
+                false,
+                prefixes,
+                &ModeStack(vec!['n']),
+            )
+            .unwrap();
+
+        matches!(
+            command.as_any().downcast_ref::<TestCommand>(),
+            Some(TestCommand::Wrap(inner)) if !inner.is_empty()
+        )
+    }
+
+    #[test]
+    fn glob_prefix_only_wraps_matching_commands() {
+        let mut registry = CommandRegistry(vec![]);
+        registry.register::<TestCommand>();
+
+        let glob = CommandPrefixRegistry(vec![CommandPrefix {
+            modes: vec!['n'],
+            prefix_cmd: "wrap".to_string(),
+            include: true,
+            list: vec!["lsp-*".to_string()],
+            match_kind: PrefixMatch::Glob,
+        }]);
+        assert!(is_wrapped(&registry, &glob, "lsp-hover"));
+        assert!(!is_wrapped(&registry, &glob, "write"));
+
+        // Exact matching treats the pattern as a plain name
+        let exact = CommandPrefixRegistry(vec![CommandPrefix {
+            match_kind: PrefixMatch::Exact,
+            ..glob.0.into_iter().next().unwrap()
+        }]);
+        assert!(!is_wrapped(&registry, &exact, "lsp-hover"));
+    }
+
+    #[test]
+    fn aliases_parse_ahead_of_commands() {
+        let mut aliases = CommandAliasRegistry::default();
+        let mut registry = CommandRegistry(vec![]);
+        registry.register::<TestCommand>();
+        registry.register_aliases(&aliases);
+        aliases.register("wh", vec!["write".into(), "lsp-hover".into()]).unwrap();
+        aliases.register("write", vec!["lsp-hover".into()]).unwrap();
+        let prefixes = CommandPrefixRegistry(vec![]);
+        let modes = ModeStack(vec!['n']);
+
+        let parse = |input: &str| {
+            registry.try_parse_command(tokenize(input).unwrap(), true, None, false, &prefixes, &modes)
+        };
+        let command = parse("wh").unwrap();
+        let alias = command.as_any().downcast_ref::<AliasCommand>().unwrap();
+        assert_eq!(alias.commands, ["write", "lsp-hover"]);
+
+        let command = parse("write").unwrap();
+        assert!(command.as_any().downcast_ref::<AliasCommand>().is_some());
+
+        assert!(matches!(
+            registry.validate_command("wh extra", None, &prefixes, &modes),
+            Err(ParseError::InvalidArgument { .. })
+        ));
+    }
+
+    #[test]
+    fn validation_reports_out_of_range_args() {
+        let mut registry = CommandRegistry(vec![]);
+        registry.register::<TestCommand>();
+        let prefixes = CommandPrefixRegistry(vec![]);
+        let modes = ModeStack(vec!['n']);
+
+        let command = registry
+            .parse_command(tokenize("repeat 10").unwrap(), false, true, None, false, &prefixes, &modes)
+            .unwrap();
+        assert!(matches!(
+            command.as_any().downcast_ref::<TestCommand>(),
+            Some(TestCommand::Repeat(10))
+        ));
+
+        assert_eq!(
+            registry
+                .validate_command("repeat 11", None, &prefixes, &modes)
+                .map_err(|e| e.to_string()),
+            Err("Invalid `count` for `repeat`: must be between 1 and 10, got 11".to_string())
+        );
+        assert!(matches!(
+            registry.validate_command("repeat -1", None, &prefixes, &modes),
+            Err(ParseError::InvalidArgument { .. })
+        ));
+        assert_eq!(
+            registry.validate_command("nope", None, &prefixes, &modes),
+            Err(ParseError::UnknownCommand("nope".to_string()))
+        );
+        assert_eq!(
+            registry.validate_command("repeat", None, &prefixes, &modes),
+            Err(ParseError::ArityMismatch {
+                command: "repeat".to_string(),
+                min: 1,
+                max: 1,
+                found: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn keyword_args_fill_fields_in_any_order() {
+        let mut registry = CommandRegistry(vec![]);
+        registry.register::<TestCommand>();
+        let prefixes = CommandPrefixRegistry(vec![]);
+        let modes = ModeStack(vec!['n']);
+
+        let parse = |input: &str| {
+            registry.parse_command(tokenize(input).unwrap(), false, true, None, false, &prefixes, &modes)
+        };
+
+        let command = parse("indent expandtab=true shiftwidth=2 tabstop=4").unwrap();
+        assert!(matches!(
+            command.as_any().downcast_ref::<TestCommand>(),
+            Some(TestCommand::Indent { tabstop: 4, expandtab: true, shiftwidth: Some(2) })
+        ));
+
+        // Flags still work, and `=false` turns a switch off
+        let command = parse("indent --tabstop 8 expandtab=false").unwrap();
+        assert!(matches!(
+            command.as_any().downcast_ref::<TestCommand>(),
+            Some(TestCommand::Indent { tabstop: 8, expandtab: false, shiftwidth: None })
+        ));
+
+        assert!(matches!(
+            registry.validate_command("indent tabstop=x", None, &prefixes, &modes),
+            Err(ParseError::InvalidArgument { .. })
+        ));
+        assert!(matches!(
+            registry.validate_command("indent expandtab=true", None, &prefixes, &modes),
+            Err(ParseError::BadArguments { .. })
+        ));
+    }
+
+    #[test]
+    fn unregistered_commands_stop_parsing_until_restored() {
+        let mut registry = CommandRegistry(vec![]);
+        registry.register::<TestCommand>();
+        let prefixes = CommandPrefixRegistry(vec![]);
+        let modes = ModeStack(vec!['n']);
+        let names = |registry: &CommandRegistry| {
+            let infos = registry.0.iter().flat_map(|set| &set.infos);
+            infos.map(|info| info.valid_names[0].clone()).collect::<Vec<_>>()
+        };
+        let registered = names(&registry);
+
+        assert!(registry.unregister("write"));
+        assert!(!registry.unregister("write"));
+        assert!(registry.unregister("repeat"));
+        assert!(registry.unregister("lsp-hover"));
+        assert!(registry.command_info("write").is_none());
+        assert_eq!(
+            registry.validate_command("write", None, &prefixes, &modes),
+            Err(ParseError::UnknownCommand("write".to_string()))
+        );
+
+        // An alias can take over the freed name
+        let mut aliases = CommandAliasRegistry::default();
+        registry.register_aliases(&aliases);
+        aliases.register("write", vec!["lsp-hover".into()]).unwrap();
+        assert!(registry.validate_command("write", None, &prefixes, &modes).is_ok());
+        aliases.clear();
+
+        registry.restore_unregistered();
+        // Each is back at its place in the listings
+        assert_eq!(names(&registry), registered);
+        assert!(registry.command_info("write").is_some());
+        assert!(registry.validate_command("write", None, &prefixes, &modes).is_ok());
+    }
+}
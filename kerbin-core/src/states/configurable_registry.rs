@@ -0,0 +1,177 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use crate::*;
+
+/// A state whose fields can be read and written by name from the command line.
+/// Usually implemented with `#[derive(ConfigurableState)]`.
+pub trait ConfigurableState: StateName + StaticState {
+    /// Name the state is reached by, like `core` in `set core.scrolloff 5`
+    fn config_name() -> &'static str
+    where
+        Self: Sized;
+
+    fn field_names() -> &'static [&'static str]
+    where
+        Self: Sized;
+
+    /// Type of each field, parallel to `field_names`, like `usize` or `Option<String>`
+    fn field_types() -> &'static [&'static str]
+    where
+        Self: Sized;
+
+    /// Parses `value` into the field called `name`
+    fn set_field(&mut self, name: &str, value: &str) -> Result<(), String>;
+
+    /// Formats the field called `name`, or `None` if there isn't one
+    fn get_field(&self, name: &str) -> Option<String>;
+}
+
+#[async_trait::async_trait]
+trait ErasedConfigurable: Send + Sync {
+    fn field_names(&self) -> &'static [&'static str];
+    fn field_types(&self) -> &'static [&'static str];
+    async fn set(&self, state: &State, field: &str, value: &str) -> Result<(), String>;
+    async fn get(&self, state: &State, field: &str) -> Option<String>;
+}
+
+struct TypedConfigurable<T>(PhantomData<fn() -> T>);
+
+#[async_trait::async_trait]
+impl<T: ConfigurableState> ErasedConfigurable for TypedConfigurable<T> {
+    fn field_names(&self) -> &'static [&'static str] {
+        T::field_names()
+    }
+
+    fn field_types(&self) -> &'static [&'static str] {
+        T::field_types()
+    }
+
+    async fn set(&self, state: &State, field: &str, value: &str) -> Result<(), String> {
+        state.lock_state::<T>().await.set_field(field, value)
+    }
+
+    async fn get(&self, state: &State, field: &str) -> Option<String> {
+        state.lock_state::<T>().await.get_field(field)
+    }
+}
+
+/// The states reachable through `set` and `get`, keyed by their `config_name`
+#[derive(State, Default)]
+pub struct ConfigurableRegistry {
+    states: Vec<(&'static str, Arc<dyn ErasedConfigurable>)>,
+}
+
+/// A field found by `ConfigurableRegistry::resolve`
+pub struct ConfigurableField {
+    pub state: &'static str,
+    pub field: String,
+    /// The field's Rust type, like `usize`
+    pub ty: &'static str,
+    inner: Arc<dyn ErasedConfigurable>,
+}
+
+impl ConfigurableField {
+    pub async fn set(&self, state: &State, value: &str) -> Result<(), String> {
+        self.inner.set(state, &self.field, value).await
+    }
+
+    pub async fn get(&self, state: &State) -> Option<String> {
+        self.inner.get(state, &self.field).await
+    }
+}
+
+impl ConfigurableRegistry {
+    /// Makes the fields of `T` reachable through `set` and `get`
+    pub fn register<T: ConfigurableState>(&mut self) {
+        self.states
+            .push((T::config_name(), Arc::new(TypedConfigurable::<T>(PhantomData))));
+    }
+
+    /// Finds the field a key names. `state.field` picks a state explicitly, while a bare
+    /// `field` uses the first registered state that has it. Dashes in the field name are
+    /// read as underscores, so `set diag-virtual-text` works.
+    pub fn resolve(&self, key: &str) -> Result<ConfigurableField, String> {
+        let (state_name, field) = match key.split_once('.') {
+            Some((state, field)) => (Some(state), field),
+            None => (None, key),
+        };
+        let field = field.replace('-', "_");
+        let field = field.as_str();
+
+        self.fields()
+            .find(|f| state_name.is_none_or(|s| s == f.state) && f.field == field)
+            .ok_or_else(|| format!("No setting named `{key}`"))
+    }
+
+    /// Every settable field, in registration order
+    pub fn fields(&self) -> impl Iterator<Item = ConfigurableField> + '_ {
+        self.states.iter().flat_map(|(name, inner)| {
+            inner
+                .field_names()
+                .iter()
+                .zip(inner.field_types())
+                .map(|(field, ty)| ConfigurableField {
+                    state: name,
+                    field: field.to_string(),
+                    ty,
+                    inner: inner.clone(),
+                })
+        })
+    }
+
+    /// Every settable key, as `state.field`
+    pub fn keys(&self) -> Vec<String> {
+        self.states
+            .iter()
+            .flat_map(|(name, inner)| inner.field_names().iter().map(move |f| format!("{name}.{f}")))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(State, ConfigurableState)]
+    #[configurable(name = "test")]
+    struct TestConfig {
+        count: usize,
+        enabled: bool,
+        max_count: usize,
+        #[configurable(skip)]
+        _hidden: Vec<u8>,
+    }
+
+    #[tokio::test]
+    async fn sets_and_gets_fields_by_name() {
+        let mut state = State::new();
+        state.state(TestConfig {
+            count: 1,
+            enabled: false,
+            max_count: 0,
+            _hidden: vec![],
+        });
+
+        let mut registry = ConfigurableRegistry::default();
+        registry.register::<TestConfig>();
+        assert_eq!(registry.keys(), ["test.count", "test.enabled", "test.max_count"]);
+
+        let count = registry.resolve("count").unwrap();
+        count.set(&state, "5").await.unwrap();
+        assert_eq!(count.get(&state).await.as_deref(), Some("5"));
+        assert!(count.set(&state, "five").await.is_err());
+
+        let enabled = registry.resolve("test.enabled").unwrap();
+        enabled.set(&state, "true").await.unwrap();
+        assert!(state.lock_state::<TestConfig>().await.enabled);
+
+        let max_count = registry.resolve("test.max-count").unwrap();
+        assert_eq!(max_count.field, "max_count");
+        assert_eq!(max_count.ty, "usize");
+        max_count.set(&state, "8").await.unwrap();
+        assert_eq!(state.lock_state::<TestConfig>().await.max_count, 8);
+
+        assert!(registry.resolve("_hidden").is_err());
+        assert!(registry.resolve("other.count").is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+/// Settings given to the buffers of a filetype, set with the `filetype` command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FiletypeProfile {
+    /// Indent width in spaces
+    pub tabstop: Option<usize>,
+    /// Whether indents are spaces rather than tabs
+    pub expandtab: Option<bool>,
+    /// Characters besides letters and digits that are part of words
+    pub iskeyword: Option<String>,
+}
+
+impl FiletypeProfile {
+    /// Folds `other` into this profile, the settings it has winning
+    pub fn merge(&mut self, other: FiletypeProfile) {
+        if other.tabstop.is_some() {
+            self.tabstop = other.tabstop;
+        }
+        if other.expandtab.is_some() {
+            self.expandtab = other.expandtab;
+        }
+        if other.iskeyword.is_some() {
+            self.iskeyword = other.iskeyword;
+        }
+    }
+
+    /// The indent style the profile asks for, `None` when it sets neither option
+    pub fn indent_style(&self, default_tab_unit: usize) -> Option<IndentStyle> {
+        match (self.expandtab, self.tabstop) {
+            (None, None) => None,
+            (Some(false), _) => Some(IndentStyle::Tabs),
+            (_, tabstop) => Some(IndentStyle::Spaces(tabstop.unwrap_or(default_tab_unit))),
+        }
+    }
+
+    /// Sets the word characters and indent style of `buf`. Without `iskeyword` the word
+    /// characters go back to the default. Indentation found in the text still wins; the
+    /// profile only replaces the default used when there is none
+    pub fn apply(&self, buf: &mut TextBuffer, default_tab_unit: usize) {
+        buf.word_chars = match &self.iskeyword {
+            Some(iskeyword) => WordChars::new(iskeyword),
+            None => WordChars::default(),
+        };
+
+        let Some(style) = self.indent_style(default_tab_unit) else {
+            return;
+        };
+        buf.indent_style = if buf.big_file {
+            style
+        } else {
+            detect_indent_or(buf.get_rope(), style)
+        };
+    }
+}
+
+/// Marks the filetype whose profile was last applied to a buffer
+#[derive(State)]
+pub struct AppliedFiletype(pub String);
+
+/// Per-filetype settings profiles, keyed by filetype name
+#[derive(State, Default)]
+pub struct FiletypeConfig {
+    pub profiles: HashMap<String, FiletypeProfile>,
+    /// Filetype of the current buffer, as last set in the `filetype` templates
+    active: Option<String>,
+}
+
+impl FiletypeConfig {
+    /// Merges `profile` into the profile of `filetype`
+    pub fn set_profile(&mut self, filetype: impl Into<String>, profile: FiletypeProfile) {
+        self.profiles
+            .entry(filetype.into())
+            .or_default()
+            .merge(profile);
+    }
+
+    /// Points the `filetype` template at the current buffer's filetype, and sets
+    /// `ft_<name>` for it so binds can be limited with `--required [ft_<name>]`
+    pub async fn set_active(&mut self, filetype: Option<String>) {
+        if self.active == filetype {
+            return;
+        }
+
+        let mut engine = resolver_engine_mut().await;
+        if let Some(old) = self.active.take() {
+            engine.remove_template(format!("ft_{old}"));
+        }
+        match &filetype {
+            Some(ft) => {
+                engine.set_template("filetype", ft.clone());
+                engine.set_template(format!("ft_{ft}"), "true");
+            }
+            None => engine.remove_template("filetype"),
+        }
+        self.active = filetype;
+    }
+}
+
+/// Applies the current buffer's filetype profile once its filetype is known, and keeps
+/// the `filetype` templates in step with it
+pub async fn apply_filetype_profile(
+    buffers: ResMut<Buffers>,
+    filetypes: ResMut<FiletypeConfig>,
+    config: Res<CoreConfig>,
+) {
+    get!(mut buffers, mut filetypes, config);
+
+    let Some(mut buf) = buffers.cur_text_buffer_mut().await else {
+        filetypes.set_active(None).await;
+        return;
+    };
+    filetypes.set_active(buf.filetype.clone()).await;
+
+    let Some(ft) = buf.filetype.clone() else {
+        return;
+    };
+    let applied = buf
+        .get_state::<AppliedFiletype>()
+        .await
+        .is_some_and(|applied| applied.0 == ft);
+    if applied {
+        return;
+    }
+
+    // Filetypes without a profile still drop what the previous filetype's profile set
+    let no_profile = FiletypeProfile::default();
+    let profile = filetypes.profiles.get(&ft).unwrap_or(&no_profile);
+    profile.apply(&mut buf, config.default_tab_unit);
+    buf.set_state(AppliedFiletype(ft));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_apply_to_their_filetype_only() {
+        let mut config = FiletypeConfig::default();
+        config.set_profile(
+            "make",
+            FiletypeProfile {
+                tabstop: Some(8),
+                expandtab: Some(false),
+                iskeyword: None,
+            },
+        );
+        config.set_profile(
+            "python",
+            FiletypeProfile {
+                tabstop: Some(2),
+                expandtab: None,
+                iskeyword: None,
+            },
+        );
+        config.set_profile(
+            "python",
+            FiletypeProfile {
+                tabstop: None,
+                expandtab: Some(true),
+                iskeyword: None,
+            },
+        );
+
+        let mut make = TextBuffer::scratch();
+        make.filetype = Some("make".to_string());
+        let mut python = TextBuffer::scratch();
+        python.filetype = Some("python".to_string());
+        python.replace_text("def f():\n    pass\n");
+        let mut rust = TextBuffer::scratch();
+        rust.filetype = Some("rust".to_string());
+
+        for buf in [&mut make, &mut python, &mut rust] {
+            if let Some(profile) = config.profiles.get(buf.filetype.as_deref().unwrap()) {
+                profile.apply(buf, 4);
+            }
+        }
+
+        assert_eq!(make.indent_style, IndentStyle::Tabs);
+        // Indentation in the text wins over the profile's width
+        assert_eq!(python.indent_style, IndentStyle::Spaces(4));
+        assert_eq!(rust.indent_style, IndentStyle::Spaces(4));
+
+        let mut empty_python = TextBuffer::scratch();
+        config.profiles["python"].apply(&mut empty_python, 4);
+        assert_eq!(empty_python.indent_style, IndentStyle::Spaces(2));
+
+        // A profile without iskeyword drops the word characters of the previous filetype
+        let mut retyped = TextBuffer::scratch();
+        retyped.word_chars = WordChars::new("-");
+        config.profiles["python"].apply(&mut retyped, 4);
+        assert_eq!(retyped.word_chars, WordChars::default());
+    }
+
+    #[tokio::test]
+    async fn profiles_apply_once_per_filetype_and_follow_the_current_buffer() {
+        let mut filetypes = FiletypeConfig::default();
+        filetypes.set_profile(
+            "profile_make",
+            FiletypeProfile {
+                tabstop: Some(8),
+                expandtab: Some(false),
+                iskeyword: Some("-".to_string()),
+            },
+        );
+
+        let mut buffers = Buffers::default();
+        for ft in ["profile_make", "profile_text"] {
+            let mut buf = TextBuffer::scratch();
+            buf.filetype = Some(ft.to_string());
+            buffers.push_buffer(buf).await;
+        }
+        buffers.set_selected_buffer(1);
+
+        let mut state = State::new();
+        state
+            .state(buffers)
+            .state(filetypes)
+            .state(CoreConfig::default());
+
+        state.call(apply_filetype_profile).await;
+        {
+            let mut buffers = state.lock_state::<Buffers>().await;
+            let mut make = buffers.cur_text_buffer_mut().await.unwrap();
+            assert_eq!(make.indent_style, IndentStyle::Tabs);
+            assert!(make.word_chars.contains('-'));
+            assert_eq!(make.get_state::<AppliedFiletype>().await.unwrap().0, "profile_make");
+
+            // Applied once, so settings changed afterwards stay
+            make.indent_style = IndentStyle::Spaces(3);
+        }
+        assert!(resolver_engine().await.has_template("ft_profile_make"));
+
+        state.call(apply_filetype_profile).await;
+        {
+            let buffers = state.lock_state::<Buffers>().await;
+            let make = buffers.cur_buffer_as::<TextBuffer>().await.unwrap();
+            assert_eq!(make.indent_style, IndentStyle::Spaces(3));
+        }
+
+        // A buffer without a profile is still marked, and the templates follow it
+        state.lock_state::<Buffers>().await.set_selected_buffer(2);
+        state.call(apply_filetype_profile).await;
+        {
+            let buffers = state.lock_state::<Buffers>().await;
+            let text = buffers.cur_buffer_as::<TextBuffer>().await.unwrap();
+            assert_eq!(text.get_state::<AppliedFiletype>().await.unwrap().0, "profile_text");
+        }
+
+        // Switching to a filetype without a profile resets the word characters
+        {
+            let mut buffers = state.lock_state::<Buffers>().await;
+            buffers.set_selected_buffer(1);
+            buffers.cur_text_buffer_mut().await.unwrap().filetype =
+                Some("profile_text".to_string());
+        }
+        state.call(apply_filetype_profile).await;
+        {
+            let buffers = state.lock_state::<Buffers>().await;
+            let make = buffers.cur_buffer_as::<TextBuffer>().await.unwrap();
+            assert_eq!(make.word_chars, WordChars::default());
+        }
+        let engine = resolver_engine().await;
+        assert!(!engine.has_template("ft_profile_make"));
+        assert!(engine.has_template("ft_profile_text"));
+    }
+}
@@ -7,8 +7,13 @@ pub struct FiletypeRegistry {
     pub ext_map: HashMap<String, String>,
     pub filename_map: HashMap<String, String>,
     pub first_line_patterns: Vec<(String, String)>,
+    /// Line comment token per filetype
+    pub comment_tokens: HashMap<String, String>,
 }
 
+/// Comment token used for filetypes without a registered one
+pub const DEFAULT_COMMENT_TOKEN: &str = "#";
+
 impl FiletypeRegistry {
     /// Map a file extension to a filetype. Existing registrations win.
     pub fn register_ext(&mut self, ext: impl Into<String>, filetype: impl Into<String>) {
@@ -28,6 +33,21 @@ impl FiletypeRegistry {
             .push((pattern.into(), filetype.into()));
     }
 
+    /// Set the line comment token of a filetype. Existing registrations win.
+    pub fn register_comment(&mut self, filetype: impl Into<String>, token: impl Into<String>) {
+        self.comment_tokens
+            .entry(filetype.into())
+            .or_insert(token.into());
+    }
+
+    /// The line comment token of `filetype`, defaulting to `#`
+    pub fn comment_token(&self, filetype: Option<&str>) -> &str {
+        filetype
+            .and_then(|ft| self.comment_tokens.get(ft))
+            .map(|t| t.as_str())
+            .unwrap_or(DEFAULT_COMMENT_TOKEN)
+    }
+
     /// Detect a filetype from a file path and optional first line of content.
     /// Priority: exact filename → extension → first-line regex.
     pub fn detect(&self, path: &str, first_line: Option<&str>) -> Option<String> {
@@ -4,10 +4,22 @@ use ratatui::{buffer::Buffer, layout::Rect};
 
 use crate::CursorShape;
 
+/// Content of last frame's chunk, kept for [`InnerChunk::draw_version`]
+struct Drawn {
+    version: u64,
+    buffer: Buffer,
+    cursor: Option<(usize, u16, u16, CursorShape)>,
+}
+
 /// Internal chunk representing a ratatui buffer and an optional cursor
 pub struct InnerChunk {
     buffer: Buffer,
     cursor: Option<(usize, u16, u16, CursorShape)>,
+
+    /// Version of the state the content was drawn from, if its renderer tracks one
+    version: Option<u64>,
+    /// Last frame's content, when it was drawn from a version in the same place
+    last: Option<Drawn>,
 }
 
 impl Deref for InnerChunk {
@@ -29,6 +41,43 @@ impl InnerChunk {
         Self {
             buffer: buf,
             cursor: None,
+            version: None,
+            last: None,
+        }
+    }
+
+    /// Records that the chunk shows the state at `version`, which must change whenever
+    /// anything drawn into the chunk does. Returns false when last frame's content was
+    /// drawn from the same version, restoring it so drawing can be skipped
+    pub fn draw_version(&mut self, version: u64) -> bool {
+        self.version = Some(version);
+        match self.last.take() {
+            Some(last) if last.version == version => {
+                self.buffer = last.buffer;
+                self.cursor = last.cursor;
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Version of the state the content was drawn from, see [`InnerChunk::draw_version`]
+    pub fn version(&self) -> Option<u64> {
+        self.version
+    }
+
+    /// Moves the content of `previous`, last frame's chunk in the same place, into this
+    /// one for `draw_version` to restore. Only content drawn from a version is kept
+    pub(crate) fn keep_drawn(&mut self, previous: &mut InnerChunk) {
+        // Content already handed to an earlier registration this frame is gone
+        if let Some(version) = previous.version
+            && previous.area() == self.area()
+        {
+            self.last = Some(Drawn {
+                version,
+                buffer: std::mem::take(&mut previous.buffer),
+                cursor: previous.cursor,
+            });
         }
     }
 
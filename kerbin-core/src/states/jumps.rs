@@ -0,0 +1,125 @@
+use crate::*;
+
+/// Most jumps kept in the jump list
+const MAX_JUMPS: usize = 100;
+/// Jumps within this many lines of the previous entry in the same buffer aren't recorded
+const MIN_JUMP_LINES: usize = 3;
+
+/// A position the cursor jumped away from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Jump {
+    pub path: String,
+    pub byte: usize,
+    /// Line of `byte` when recorded, used to skip jumps that barely moved
+    pub line: usize,
+}
+
+impl Jump {
+    /// The primary cursor's position in `buf`
+    pub fn from_buffer(buf: &TextBuffer) -> Self {
+        let byte = buf.primary_cursor().get_cursor_byte().min(buf.len());
+        Self {
+            path: buf.path.clone(),
+            byte,
+            line: buf.byte_to_line_clamped(byte),
+        }
+    }
+
+    fn is_near(&self, other: &Jump) -> bool {
+        self.path == other.path && self.line.abs_diff(other.line) < MIN_JUMP_LINES
+    }
+}
+
+/// Cursor positions recorded before big motions, oldest first.
+/// `jump-back` and `jump-forward` walk through them like browser history.
+#[derive(Default, State)]
+pub struct JumpList {
+    entries: Vec<Jump>,
+    /// Entry the last `back`/`forward` landed on, or `entries.len()` when not walking
+    idx: usize,
+}
+
+impl JumpList {
+    pub fn entries(&self) -> &[Jump] {
+        &self.entries
+    }
+
+    /// Records the position a jump is leaving from, dropping anything ahead of the
+    /// current entry
+    pub fn push(&mut self, jump: Jump) {
+        self.entries.truncate(self.idx);
+
+        if self.entries.last().is_some_and(|last| last.is_near(&jump)) {
+            self.entries.pop();
+        }
+        self.entries.push(jump);
+
+        if self.entries.len() > MAX_JUMPS {
+            let excess = self.entries.len() - MAX_JUMPS;
+            self.entries.drain(..excess);
+        }
+        self.idx = self.entries.len();
+    }
+
+    /// Steps back to an older position. `current` is kept when leaving the newest
+    /// entry so `forward` can return to it.
+    pub fn back(&mut self, current: Jump) -> Option<Jump> {
+        if self.idx >= self.entries.len() {
+            self.push(current);
+            self.idx = self.entries.len() - 1;
+        }
+
+        self.idx = self.idx.checked_sub(1)?;
+        Some(self.entries[self.idx].clone())
+    }
+
+    /// Steps forward to a newer position, undoing `back`
+    pub fn forward(&mut self) -> Option<Jump> {
+        if self.idx + 1 >= self.entries.len() {
+            return None;
+        }
+
+        self.idx += 1;
+        Some(self.entries[self.idx].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jump(path: &str, line: usize) -> Jump {
+        Jump {
+            path: path.into(),
+            byte: line * 10,
+            line,
+        }
+    }
+
+    #[test]
+    fn walks_back_and_forward() {
+        let mut jumps = JumpList::default();
+        jumps.push(jump("a", 0));
+        jumps.push(jump("a", 50));
+
+        assert_eq!(jumps.back(jump("b", 7)), Some(jump("a", 50)));
+        assert_eq!(jumps.back(jump("a", 50)), Some(jump("a", 0)));
+        assert_eq!(jumps.back(jump("a", 0)), None);
+        assert_eq!(jumps.forward(), Some(jump("a", 50)));
+        assert_eq!(jumps.forward(), Some(jump("b", 7)));
+        assert_eq!(jumps.forward(), None);
+    }
+
+    #[test]
+    fn skips_nearby_jumps_and_caps() {
+        let mut jumps = JumpList::default();
+        jumps.push(jump("a", 10));
+        jumps.push(jump("a", 11));
+        assert_eq!(jumps.entries(), [jump("a", 11)]);
+
+        for i in 0..MAX_JUMPS + 5 {
+            jumps.push(jump("a", i * MIN_JUMP_LINES));
+        }
+        assert_eq!(jumps.entries().len(), MAX_JUMPS);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+
+use crate::*;
+
+/// Deepest a macro may play other macros before replay is aborted
+pub const MAX_MACRO_DEPTH: usize = 16;
+
+/// Keyboard macro recording and the queue of commands being replayed
+#[derive(Default, State)]
+pub struct MacroState {
+    recording: Option<char>,
+    steps: Vec<String>,
+
+    /// Commands left to replay, with how many macros deep each was played from
+    queue: VecDeque<(String, usize)>,
+    /// Whether a `MacroReplay` step is already in flight
+    pub replaying: bool,
+}
+
+impl MacroState {
+    /// Returns the register being recorded into, if any
+    pub fn recording(&self) -> Option<char> {
+        self.recording
+    }
+
+    /// Starts recording into `register`, discarding any unfinished recording
+    pub fn start(&mut self, register: char) {
+        self.recording = Some(register);
+        self.steps.clear();
+    }
+
+    /// Appends an executed command string if a recording is active
+    pub fn record(&mut self, command: String) {
+        if self.recording.is_some() {
+            self.steps.push(command);
+        }
+    }
+
+    /// Stops recording, returning the register and its newline-separated commands
+    pub fn stop(&mut self) -> Option<(char, String)> {
+        let register = self.recording.take()?;
+        Some((register, std::mem::take(&mut self.steps).join("\n")))
+    }
+
+    /// Queues a macro's commands `count` times. Nested plays (`depth > 0`) run before
+    /// the rest of the macro that played them.
+    pub fn enqueue(&mut self, macro_text: &str, count: usize, depth: usize) {
+        let lines = macro_text
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| (l.to_string(), depth));
+        let lines: Vec<_> = std::iter::repeat_n(lines, count).flatten().collect();
+
+        if depth == 0 {
+            self.queue.extend(lines);
+        } else {
+            for line in lines.into_iter().rev() {
+                self.queue.push_front(line);
+            }
+        }
+    }
+
+    /// Takes the next command to replay
+    pub fn next_step(&mut self) -> Option<(String, usize)> {
+        self.queue.pop_front()
+    }
+
+    /// Whether commands are left to replay
+    pub fn has_steps(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    /// Drops every queued command, e.g. after a step fails
+    pub fn abort(&mut self) {
+        self.queue.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_plays_run_before_the_rest() {
+        let mut state = MacroState::default();
+        state.enqueue("a\nplay_b\nc", 1, 0);
+
+        assert_eq!(state.next_step(), Some(("a".into(), 0)));
+        assert_eq!(state.next_step(), Some(("play_b".into(), 0)));
+
+        state.enqueue("b1\nb2", 2, 1);
+
+        let rest: Vec<_> = std::iter::from_fn(|| state.next_step()).collect();
+        assert_eq!(
+            rest,
+            vec![
+                ("b1".into(), 1),
+                ("b2".into(), 1),
+                ("b1".into(), 1),
+                ("b2".into(), 1),
+                ("c".into(), 0),
+            ]
+        );
+    }
+}
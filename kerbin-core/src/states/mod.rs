@@ -1,8 +1,8 @@
 pub mod wrappers;
-use std::{path::PathBuf, sync::Arc};
+use std::path::PathBuf;
 
 use ratatui::{Terminal, backend::CrosstermBackend};
-use tokio::sync::{RwLock, mpsc::UnboundedSender};
+use tokio::sync::mpsc::UnboundedSender;
 use uuid::Uuid;
 pub use wrappers::*;
 
@@ -15,6 +15,12 @@ pub use if_check_registry::*;
 pub mod command_prefix_registry;
 pub use command_prefix_registry::*;
 
+pub mod command_alias_registry;
+pub use command_alias_registry::*;
+
+pub mod configurable_registry;
+pub use configurable_registry::*;
+
 pub mod command_interceptor_registry;
 pub use command_interceptor_registry::*;
 
@@ -30,15 +36,33 @@ pub use chunks::*;
 pub mod registers;
 pub use registers::*;
 
+pub mod macros;
+pub use macros::*;
+
+pub mod jumps;
+pub use jumps::*;
+
+pub mod closed_buffers;
+pub use closed_buffers::*;
+
+pub mod quickfix;
+pub use quickfix::*;
+
 pub mod splits;
 pub use splits::*;
 
+pub mod shell;
+pub use shell::*;
+
 pub mod plugin_registry;
 pub use plugin_registry::*;
 
 pub mod filetype_registry;
 pub use filetype_registry::*;
 
+pub mod filetype_config;
+pub use filetype_config::*;
+
 /// Initializes the editor's core state with essential components
 pub fn init_state(
     terminal: Terminal<CrosstermBackend<std::io::Stdout>>,
@@ -51,47 +75,88 @@ pub fn init_state(
 
     let (log_state, log_sender) = LogState::new_with_channel();
 
+    let mut configurable = ConfigurableRegistry::default();
+    configurable.register::<CoreConfig>();
+    configurable.register::<WhitespaceConfig>();
+    configurable.register::<GutterConfig>();
+    configurable.register::<FinderConfig>();
+    configurable.register::<StatuslineConfig>();
+    configurable.register::<ShellConfig>();
+    configurable.register::<InputConfig>();
+
+    let mut mouse = MouseRegistry::default();
+    mouse.register::<BufferChunk>();
+
+    let aliases = CommandAliasRegistry::default();
+    let mut commands = CommandRegistry(vec![]);
+    commands.register_aliases(&aliases);
+
     state
         .state(EventStorage::default())
+        .state(EventReplies::default())
         .state(Registers::default())
+        .state(MacroState::default())
+        .state(JumpList::default())
+        .state(ClosedBuffers::default())
+        .state(QuickfixList::default())
+        .state(PendingOperator::default())
         .state(server_ipc)
         .state(ConfigDir(PathBuf::from(format!("{config_path}/config"))))
         .state(CoreConfig::default())
         .state(PaletteState::default())
+        .state(CommandHistory::load(
+            PathBuf::from(&config_path).join(".command_history"),
+        ))
         .state(ConfigFolder(config_path))
         .state(SessionUuid(uuid))
         .state(Running(true))
+        .state(FrameActivity::default())
         .state(log_state)
         .state(log_sender)
+        .state(LogFilter::default())
         .state(WindowState(terminal))
         .state(CrosstermEvents::default())
         .state(CommandSender(cmd_sender))
-        .state({
-            let mut buffers = Buffers::default();
-            buffers
-                .buffers
-                .push(Arc::new(RwLock::new(TextBuffer::scratch())));
-            buffers
-        })
+        .state(Buffers::default())
         .state(InputState::default())
+        .state(InputConfig::default())
         .state(Theme::default())
         .state(CommandPaletteState::default())
         .state(ModeStack(vec!['n']))
-        .state(CommandRegistry(vec![]))
+        .state(commands)
+        .state(aliases)
+        .state(AfterSave::default())
         .state(CommandPrefixRegistry(vec![]))
         .state(CommandInterceptorRegistry::new())
+        .state(configurable)
         .state(IfCheckRegistry::default())
         .state(AutoPairs::default())
         .state(Chunks::default())
         .state(DebounceConfig::default())
         .state(StatuslineConfig::default())
+        .state(StatuslineSegments::default())
         .state(LayoutConfig::default())
+        .state(GutterConfig::default())
         .state(ConfigErrors::default())
+        .state(WhitespaceConfig::default())
         .state(MouseBindings::default())
+        .state(mouse)
+        .state(ChunkMouseEvents::default())
         .state(SplitState::default())
         .state(PluginRegistry::default())
         .state(DialogueState::default())
-        .state(FiletypeRegistry::default());
+        .state(Picker::default())
+        .state(FileFinder::default())
+        .state(WorkspaceGrep::default())
+        .state(FinderConfig::default())
+        .state(ShellConfig::default())
+        .state(FiletypeRegistry::default())
+        .state(FiletypeConfig::default())
+        .state(Pipeline::default())
+        .state(AutoSave::default());
+
+    #[cfg(feature = "watcher")]
+    state.state(FileWatcher::default());
 
     state
 }
@@ -4,26 +4,45 @@ use crate::*;
 #[derive(State)]
 pub struct ModeStack(pub Vec<char>);
 
+/// Is emitted when a mode is pushed onto the mode stack
+pub struct ModeEnterEvent {
+    pub mode: char,
+}
+
+/// Is emitted when a mode is removed from the mode stack
+pub struct ModeLeaveEvent {
+    pub mode: char,
+}
+
 impl ModeStack {
-    pub fn push_mode(&mut self, mode: char) {
+    /// Pushes `mode` onto the stack and emits a `ModeEnterEvent`
+    pub async fn push_mode(&mut self, mode: char) {
         self.0.push(mode);
+        EVENT_BUS.emit(ModeEnterEvent { mode }).await;
     }
 
-    pub fn pop_mode(&mut self) -> Option<char> {
+    /// Pops the top mode, emitting a `ModeLeaveEvent`. The base mode is never popped.
+    pub async fn pop_mode(&mut self) -> Option<char> {
         if self.0.len() <= 1 {
             return None;
         }
 
-        self.0.pop()
+        let mode = self.0.pop()?;
+        EVENT_BUS.emit(ModeLeaveEvent { mode }).await;
+        Some(mode)
     }
 
-    pub fn set_mode(&mut self, mode: char) {
-        self.0.clear();
-        self.0.push('n');
-        if mode == 'n' {
-            return;
+    /// Resets the stack to normal mode with `mode` on top, emitting a `ModeLeaveEvent` for
+    /// every removed mode and a `ModeEnterEvent` for `mode`.
+    ///
+    /// The bus keeps only the latest event of each type per frame, so when several modes
+    /// are left at once subscribers see the lowest one.
+    pub async fn set_mode(&mut self, mode: char) {
+        while self.pop_mode().await.is_some() {}
+
+        if mode != 'n' {
+            self.push_mode(mode).await;
         }
-        self.0.push(mode);
     }
 
     pub fn get_mode(&self) -> char {
@@ -46,3 +65,55 @@ impl ModeStack {
             .map(|x| x.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(State, Default)]
+    struct Transitions(Vec<String>);
+
+    async fn record_enter(event: EventData<ModeEnterEvent>, transitions: ResMut<Transitions>) {
+        get!(Some(event), mut transitions);
+        transitions.0.push(format!("enter {}", event.mode));
+    }
+
+    async fn record_leave(event: EventData<ModeLeaveEvent>, transitions: ResMut<Transitions>) {
+        get!(Some(event), mut transitions);
+        transitions.0.push(format!("leave {}", event.mode));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mode_changes_emit_transition_events() {
+        let mut state = State::new();
+        state
+            .state(EventStorage::default())
+            .state(Transitions::default());
+
+        let enter = EVENT_BUS.subscribe::<ModeEnterEvent>().await.system(record_enter);
+        let leave = EVENT_BUS.subscribe::<ModeLeaveEvent>().await.system(record_leave);
+
+        let mut modes = ModeStack(vec!['n']);
+
+        modes.push_mode('i').await;
+        EVENT_BUS.resolve(&mut state).await;
+        modes.pop_mode().await;
+        EVENT_BUS.resolve(&mut state).await;
+
+        // The base mode stays put, so nothing is left
+        assert_eq!(modes.pop_mode().await, None);
+        EVENT_BUS.resolve(&mut state).await;
+
+        modes.set_mode('v').await;
+        EVENT_BUS.resolve(&mut state).await;
+
+        enter.unsubscribe().await;
+        leave.unsubscribe().await;
+
+        assert_eq!(
+            state.lock_state::<Transitions>().await.0,
+            ["enter i", "leave i", "enter v"]
+        );
+        assert_eq!(modes.get_mode(), 'v');
+    }
+}
@@ -0,0 +1,199 @@
+use std::ops::Range;
+
+use ropey::Rope;
+
+use crate::*;
+
+/// A 0-based line and column (in chars) of a quickfix entry
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct QuickfixPos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl QuickfixPos {
+    pub fn new(line: usize, col: usize) -> Self {
+        Self { line, col }
+    }
+
+    /// Position of `byte` in `rope`
+    pub fn from_byte(rope: &Rope, byte: usize) -> Self {
+        let char_idx = rope.byte_to_char(byte.min(rope.len_bytes()));
+        let line = rope.char_to_line(char_idx);
+        Self::new(line, char_idx - rope.line_to_char(line))
+    }
+
+    /// Byte offset of the position in `rope`, clamped to the end of its line (before the
+    /// line break)
+    pub fn to_byte(self, rope: &Rope) -> usize {
+        let line = self.line.min(rope.len_lines().saturating_sub(1));
+        let text = rope.line(line);
+        let mut len = text.len_chars();
+        for ending in ['\n', '\r'] {
+            if len > 0 && text.char(len - 1) == ending {
+                len -= 1;
+            }
+        }
+        rope.char_to_byte(rope.line_to_char(line) + self.col.min(len))
+    }
+}
+
+/// A location in the quickfix list. Its file may not be open, so the range is kept in
+/// lines and columns rather than bytes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuickfixEntry {
+    pub path: String,
+    pub range: Range<QuickfixPos>,
+    pub message: String,
+}
+
+impl QuickfixEntry {
+    /// Entry covering the `range` bytes of `buf`
+    pub fn from_buffer(buf: &TextBuffer, range: Range<usize>, message: impl ToString) -> Self {
+        let rope = buf.get_rope();
+        let start = QuickfixPos::from_byte(rope, range.start);
+        Self {
+            path: buf.path.clone(),
+            range: start..QuickfixPos::from_byte(rope, range.end),
+            message: message.to_string(),
+        }
+    }
+
+    /// Parses a `path:line:col: message` entry (1-based, like `grep` lists its matches).
+    /// The range is empty, starting at the column
+    pub fn parse(entry: &str) -> Option<Self> {
+        let mut parts = entry.splitn(4, ':');
+        let path = parts.next()?;
+        let line = parts.next()?.parse::<usize>().ok()?;
+        let col = parts.next()?.parse::<usize>().ok()?;
+        let pos = QuickfixPos::new(line.saturating_sub(1), col.saturating_sub(1));
+
+        Some(Self {
+            path: path.to_string(),
+            range: pos..pos,
+            message: parts.next().unwrap_or_default().trim().to_string(),
+        })
+    }
+
+    /// The entry as `path:line:col: message`, 1-based
+    pub fn label(&self) -> String {
+        let start = self.range.start;
+        format!("{}:{}:{}: {}", self.path, start.line + 1, start.col + 1, self.message)
+    }
+
+    /// Byte range of the entry in `rope`, clamped to its contents
+    pub fn byte_range(&self, rope: &Rope) -> Range<usize> {
+        let start = self.range.start.to_byte(rope);
+        start..self.range.end.to_byte(rope).max(start)
+    }
+}
+
+/// The last list of locations worth walking through, filled by workspace grep, accepted
+/// searches and LSP diagnostics. `cnext` and `cprev` step through it, `copen` shows it in
+/// the picker.
+#[derive(Default, State)]
+pub struct QuickfixList {
+    /// Where the entries came from, used as the picker title
+    pub title: String,
+    entries: Vec<QuickfixEntry>,
+    /// The entry last jumped to
+    idx: Option<usize>,
+}
+
+impl QuickfixList {
+    pub fn entries(&self) -> &[QuickfixEntry] {
+        &self.entries
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.idx
+    }
+
+    /// Replaces the list. Nothing is current until the first jump
+    pub fn set(&mut self, title: impl ToString, entries: Vec<QuickfixEntry>) {
+        self.title = title.to_string();
+        self.entries = entries;
+        self.idx = None;
+    }
+
+    /// Moves `delta` entries from the current one, stopping at either end. Before the
+    /// first jump, moving forward counts from the first entry and back from the last.
+    /// Gives `None` when already at the end being moved towards.
+    pub fn step(&mut self, delta: isize) -> Option<&QuickfixEntry> {
+        let last = self.entries.len().checked_sub(1)? as isize;
+        let target = match self.idx {
+            Some(idx) => idx as isize + delta,
+            None if delta > 0 => delta - 1,
+            None => last + delta + 1,
+        };
+
+        let target = target.clamp(0, last) as usize;
+        if self.idx == Some(target) {
+            return None;
+        }
+        self.select(target)
+    }
+
+    /// Makes the `idx`th (0-based) entry current
+    pub fn select(&mut self, idx: usize) -> Option<&QuickfixEntry> {
+        let entry = self.entries.get(idx)?;
+        self.idx = Some(idx);
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(count: usize) -> QuickfixList {
+        let mut list = QuickfixList::default();
+        let entries = (0..count)
+            .map(|i| QuickfixEntry::parse(&format!("a.rs:{}:1: entry {i}", i + 1)).unwrap())
+            .collect();
+        list.set("test", entries);
+        list
+    }
+
+    fn step(list: &mut QuickfixList, delta: isize) -> Option<String> {
+        list.step(delta).map(|entry| entry.message.clone())
+    }
+
+    #[test]
+    fn steps_through_entries_and_stops_at_the_ends() {
+        let mut list = filled(3);
+
+        assert_eq!(step(&mut list, 1).as_deref(), Some("entry 0"));
+        assert_eq!(step(&mut list, 1).as_deref(), Some("entry 1"));
+        assert_eq!(step(&mut list, 5).as_deref(), Some("entry 2"));
+        assert_eq!(step(&mut list, 1), None);
+        assert_eq!(step(&mut list, -2).as_deref(), Some("entry 0"));
+        assert_eq!(step(&mut list, -1), None);
+
+        // A fresh list walked backwards starts from the last entry
+        let mut list = filled(3);
+        assert_eq!(step(&mut list, -1).as_deref(), Some("entry 2"));
+        assert_eq!(list.current(), Some(2));
+
+        assert_eq!(step(&mut filled(0), 1), None);
+    }
+
+    #[test]
+    fn entries_round_trip_through_labels_and_buffers() {
+        let entry = QuickfixEntry::parse("src/main.rs:3:5: let x = 1;").unwrap();
+        assert_eq!(entry.range.start, QuickfixPos::new(2, 4));
+        assert_eq!(entry.message, "let x = 1;");
+        assert_eq!(entry.label(), "src/main.rs:3:5: let x = 1;");
+        assert_eq!(QuickfixEntry::parse("not an entry"), None);
+
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, "fn é() {}\nlet x = 1;\n");
+        let entry = QuickfixEntry::from_buffer(&buf, 15..16, "x");
+        assert_eq!(entry.range, QuickfixPos::new(1, 4)..QuickfixPos::new(1, 5));
+        assert_eq!(entry.byte_range(buf.get_rope()), 15..16);
+
+        // Positions past the end of a line clamp to it
+        let past = QuickfixPos::new(0, 100);
+        assert_eq!(past.to_byte(buf.get_rope()), 10);
+    }
+}
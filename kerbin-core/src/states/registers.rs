@@ -1,12 +1,44 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range};
+
+use ropey::Rope;
 
 use crate::*;
 
+/// How a register's text was copied, deciding where `paste` puts it back
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RegisterKind {
+    /// Part of a line, pasted inline at the cursor
+    #[default]
+    Charwise,
+    /// Whole lines ending in a line break, pasted as lines below (or above) the cursor's
+    Linewise,
+    /// One line of text per cursor, pasted as a column starting at the cursor
+    Block,
+}
+
+impl RegisterKind {
+    /// Kind of the text copied from `range` of `rope`. Ranges running from the start of
+    /// a line to just past a line break (or the end of the text) are whole lines.
+    pub fn of_range(rope: &Rope, range: Range<usize>) -> Self {
+        if range.is_empty() || range.end > rope.len_bytes() {
+            return Self::Charwise;
+        }
+
+        let starts_line = rope.line_to_byte(rope.byte_to_line(range.start)) == range.start;
+        let ends_line = range.end == rope.len_bytes() || rope.byte(range.end - 1) == b'\n';
+        if starts_line && ends_line {
+            Self::Linewise
+        } else {
+            Self::Charwise
+        }
+    }
+}
+
 /// Registers are char-indexed sets of stored text
 #[derive(State)]
 pub struct Registers {
     last_used: char,
-    registers: HashMap<char, String>,
+    registers: HashMap<char, (String, RegisterKind)>,
 }
 
 impl Default for Registers {
@@ -24,11 +56,20 @@ impl Registers {
         self.last_used
     }
 
-    /// Sets a register's text to the given value
+    /// Sets a register's text to the given value, pasted inline
     pub fn set(&mut self, register: char, text: String) {
+        self.set_with_kind(register, text, RegisterKind::Charwise);
+    }
+
+    /// Sets a register's text along with how it should be pasted. Linewise text always
+    /// ends in a line break
+    pub fn set_with_kind(&mut self, register: char, mut text: String, kind: RegisterKind) {
         self.last_used = register;
 
-        self.registers.insert(register, text);
+        if kind == RegisterKind::Linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.registers.insert(register, (text, kind));
     }
 
     /// Returns a register's text
@@ -37,7 +78,35 @@ impl Registers {
 
         self.registers
             .get(register)
-            .map(|x| x.as_str())
+            .map(|x| x.0.as_str())
             .unwrap_or("")
     }
+
+    /// Returns how a register's text was copied
+    pub fn kind(&self, register: &char) -> RegisterKind {
+        self.registers.get(register).map(|x| x.1).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_lines_are_linewise() {
+        let rope = Rope::from_str("one\ntwo\nend");
+
+        assert_eq!(RegisterKind::of_range(&rope, 0..4), RegisterKind::Linewise);
+        assert_eq!(RegisterKind::of_range(&rope, 4..11), RegisterKind::Linewise);
+        assert_eq!(RegisterKind::of_range(&rope, 0..3), RegisterKind::Charwise);
+        assert_eq!(RegisterKind::of_range(&rope, 1..4), RegisterKind::Charwise);
+        assert_eq!(RegisterKind::of_range(&rope, 4..4), RegisterKind::Charwise);
+
+        // The last line of the text has no break, so it gains one in the register
+        let mut registers = Registers::default();
+        registers.set_with_kind('a', "end".into(), RegisterKind::Linewise);
+        assert_eq!(registers.get(&'a'), "end\n");
+        assert_eq!(registers.kind(&'a'), RegisterKind::Linewise);
+        assert_eq!(registers.kind(&'b'), RegisterKind::Charwise);
+    }
 }
@@ -0,0 +1,120 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::*;
+
+/// Where shell commands run when no `shell_cd` directory is set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShellDir {
+    /// The editor's working directory, usually the project root it was opened in
+    #[default]
+    Workspace,
+    /// The directory of the current buffer's file, or the workspace for unsaved buffers
+    Buffer,
+}
+
+impl FromStr for ShellDir {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "workspace" => Ok(Self::Workspace),
+            "buffer" => Ok(Self::Buffer),
+            _ => Err(format!("Expected `workspace` or `buffer`, found: {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for ShellDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Workspace => "workspace",
+            Self::Buffer => "buffer",
+        })
+    }
+}
+
+/// How `shell` and friends spawn their commands. Set the directory with
+/// `set shell.dir buffer`, extra variables with `shell_env`, and a fixed directory
+/// with `shell_cd`.
+#[derive(State, ConfigurableState, Default)]
+#[configurable(name = "shell")]
+pub struct ShellConfig {
+    /// Where commands run while no `shell_cd` directory is set
+    pub dir: ShellDir,
+    /// Variables added to every command's environment
+    #[configurable(skip)]
+    pub env: BTreeMap<String, String>,
+    /// Set by `shell_cd`, overriding `dir` until cleared. Kept across config reloads.
+    #[configurable(skip)]
+    pub cwd: Option<PathBuf>,
+}
+
+impl ShellConfig {
+    /// Directory a command runs in, given the current buffer's path
+    pub fn working_dir(&self, buffer_path: Option<&str>) -> PathBuf {
+        if let Some(cwd) = &self.cwd {
+            return cwd.clone();
+        }
+
+        let workspace = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let buffer_dir = buffer_path
+            .filter(|path| !(path.starts_with('<') && path.ends_with('>')))
+            .and_then(|path| Path::new(path).parent())
+            .filter(|dir| !dir.as_os_str().is_empty());
+
+        match (self.dir, buffer_dir) {
+            (ShellDir::Buffer, Some(dir)) => workspace.join(dir),
+            _ => workspace,
+        }
+    }
+
+    /// Builds `args` as a process in the working directory with the extra variables set
+    pub fn command(&self, args: &[String], buffer_path: Option<&str>) -> std::process::Command {
+        let mut command = std::process::Command::new(&args[0]);
+        command
+            .args(&args[1..])
+            .current_dir(self.working_dir(buffer_path))
+            .envs(&self.env);
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_run_in_the_resolved_directory() {
+        let root = std::env::temp_dir().join(format!("kerbin-shell-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let root = root.canonicalize().unwrap();
+        let file = root.join("src/main.rs");
+
+        let mut config = ShellConfig {
+            dir: ShellDir::Buffer,
+            ..Default::default()
+        };
+        assert_eq!(config.working_dir(file.to_str()), root.join("src"));
+
+        // Unsaved buffers have no directory of their own
+        let workspace = std::env::current_dir().unwrap();
+        assert_eq!(config.working_dir(Some("<scratch>")), workspace);
+        config.dir = ShellDir::Workspace;
+        assert_eq!(config.working_dir(file.to_str()), workspace);
+
+        config.cwd = Some(root.clone());
+        config.env.insert("KERBIN_SHELL_TEST".into(), "set".into());
+        let args = ["sh", "-c", "pwd; echo $KERBIN_SHELL_TEST"].map(String::from);
+        let output = config.command(&args, file.to_str()).output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("{}\nset\n", root.display())
+        );
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
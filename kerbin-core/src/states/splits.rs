@@ -1,4 +1,4 @@
-use ratatui::layout::Rect;
+use ratatui::layout::{Constraint, Layout, Rect};
 
 use crate::*;
 
@@ -19,6 +19,10 @@ pub struct SplitPane {
     pub selected_local: usize,
     pub size: u16,
     pub tab_scroll: usize,
+    /// Fewest cells the pane takes along its split, before flexible space is shared out
+    pub min: Option<u16>,
+    /// Most cells the pane takes along its split while another pane can take the rest
+    pub max: Option<u16>,
 }
 
 /// A node in the recursive pane tree
@@ -31,7 +35,100 @@ pub enum PaneNode {
     },
 }
 
+/// How a child of a split shares the split's length, in cells: `weight` is its share of
+/// what is left once every `min` is met, kept under `max`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeHint {
+    pub weight: u16,
+    pub min: Option<u16>,
+    pub max: Option<u16>,
+}
+
+/// Splits `total` cells between children sized by `hints`. Mins are met first, then the
+/// rest is shared by weight among the children still under their max. A max only holds
+/// while another child can take the space, so the lengths always add up to `total`;
+/// when even the mins don't fit, the last children give up cells first
+pub fn split_lengths(total: u16, hints: &[SizeHint]) -> Vec<u16> {
+    let n = hints.len();
+    let weight = |i: usize| hints[i].weight.max(1) as u64;
+    let min = |i: usize| hints[i].min.unwrap_or(0) as u64;
+    let max = |i: usize| hints[i].max.map(|max| (max as u64).max(min(i)));
+
+    let mut lens = vec![0u64; n];
+    let mut pinned = vec![false; n];
+    let mut budget = total as u64;
+
+    // Pins the children whose weighted share breaks a limit, mins before maxes, until
+    // every share left fits
+    loop {
+        let free: Vec<usize> = (0..n).filter(|&i| !pinned[i]).collect();
+        let weights: u64 = free.iter().map(|&i| weight(i)).sum();
+
+        let under: Vec<usize> =
+            free.iter().copied().filter(|&i| budget * weight(i) < min(i) * weights).collect();
+        let over: Vec<usize> = free
+            .iter()
+            .copied()
+            .filter(|&i| max(i).is_some_and(|max| budget * weight(i) > max * weights))
+            .collect();
+
+        let pins = match (under.is_empty(), over.is_empty()) {
+            (false, _) => under.into_iter().map(|i| (i, min(i))).collect::<Vec<_>>(),
+            (true, false) => over.into_iter().map(|i| (i, max(i).unwrap_or(0))).collect(),
+            (true, true) => break,
+        };
+        for (i, len) in pins {
+            lens[i] = len;
+            pinned[i] = true;
+            budget = budget.saturating_sub(len);
+        }
+    }
+
+    let free: Vec<usize> = (0..n).filter(|&i| !pinned[i]).collect();
+    let weights: u64 = free.iter().map(|&i| weight(i)).sum();
+    for &i in &free {
+        lens[i] = budget * weight(i) / weights;
+    }
+
+    // Rounding leaves a few cells over: the first flexible children take one each, or the
+    // last child when every one is held at its max
+    let used: u64 = lens.iter().sum();
+    let mut spare = (total as u64).saturating_sub(used);
+    for &i in free.iter().cycle().take(spare as usize) {
+        lens[i] += 1;
+        spare -= 1;
+    }
+    if let Some(last) = lens.last_mut() {
+        *last += spare;
+    }
+
+    // Mins that don't fit are taken back from the end
+    let mut excess = lens.iter().sum::<u64>().saturating_sub(total as u64);
+    for len in lens.iter_mut().rev() {
+        let taken = excess.min(*len);
+        *len -= taken;
+        excess -= taken;
+    }
+
+    lens.into_iter().map(|len| len as u16).collect()
+}
+
 impl PaneNode {
+    /// How this node shares the length of the split holding it
+    pub fn size_hint(&self) -> SizeHint {
+        match self {
+            PaneNode::Pane(p) => SizeHint {
+                weight: p.size,
+                min: p.min,
+                max: p.max,
+            },
+            PaneNode::Container { size, .. } => SizeHint {
+                weight: *size,
+                ..Default::default()
+            },
+        }
+    }
+
     pub fn collect_leaves<'a>(&'a self, out: &mut Vec<&'a SplitPane>) {
         match self {
             PaneNode::Pane(p) => out.push(p),
@@ -216,9 +313,46 @@ fn placeholder_pane() -> PaneNode {
         selected_local: 0,
         size: 0,
         tab_scroll: 0,
+        min: None,
+        max: None,
     })
 }
 
+/// The rect each pane under `node` takes within `rect`, with a cell between siblings
+pub fn collect_leaf_rects(node: &PaneNode, rect: Rect) -> Vec<(PaneId, Rect)> {
+    match node {
+        PaneNode::Pane(pane) => vec![(pane.id, rect)],
+        PaneNode::Container { dir, children, .. } => {
+            let n = children.len().max(1);
+            let length = match dir {
+                SplitDir::Vertical => rect.width,
+                SplitDir::Horizontal => rect.height,
+            };
+            let hints: Vec<SizeHint> = children.iter().map(|c| c.size_hint()).collect();
+            let lengths = split_lengths(length.saturating_sub(n as u16 - 1), &hints);
+
+            let mut constraints = Vec::with_capacity(n * 2);
+            for (i, &len) in lengths.iter().enumerate() {
+                constraints.push(Constraint::Length(len));
+                if i + 1 < n {
+                    constraints.push(Constraint::Length(1));
+                }
+            }
+
+            let areas = match dir {
+                SplitDir::Vertical => Layout::horizontal(constraints).split(rect),
+                SplitDir::Horizontal => Layout::vertical(constraints).split(rect),
+            };
+
+            let mut result = Vec::new();
+            for (i, child) in children.iter().enumerate() {
+                result.extend(collect_leaf_rects(child, areas[i * 2]));
+            }
+            result
+        }
+    }
+}
+
 /// State tracking the split-window layout
 #[derive(State)]
 pub struct SplitState {
@@ -239,6 +373,8 @@ impl Default for SplitState {
                 selected_local: 0,
                 size: 0,
                 tab_scroll: 0,
+                min: None,
+                max: None,
             }),
             focused_id: 0,
             next_id: 1,
@@ -299,6 +435,8 @@ impl SplitState {
                 selected_local: focused.map(|p| p.selected_local).unwrap_or(0),
                 size: 0,
                 tab_scroll: 0,
+                min: None,
+                max: None,
             }
         };
 
@@ -386,3 +524,45 @@ impl SplitState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hint(min: Option<u16>, max: Option<u16>) -> SizeHint {
+        SizeHint { weight: 1, min, max }
+    }
+
+    #[test]
+    fn flexible_children_share_evenly() {
+        assert_eq!(split_lengths(10, &[hint(None, None), hint(None, None)]), vec![5, 5]);
+        assert_eq!(split_lengths(11, &[hint(None, None), hint(None, None)]), vec![6, 5]);
+
+        let weighted = [SizeHint { weight: 3, ..hint(None, None) }, hint(None, None)];
+        assert_eq!(split_lengths(100, &weighted), vec![75, 25]);
+    }
+
+    #[test]
+    fn clamped_sidebar_binds_on_min_and_max() {
+        let sidebar = hint(Some(20), Some(40));
+        let editor = SizeHint { weight: 3, ..hint(None, None) };
+
+        // A quarter of 60 is 15, under the min
+        assert_eq!(split_lengths(60, &[sidebar, editor]), vec![20, 40]);
+        // A quarter of 120 fits the clamp
+        assert_eq!(split_lengths(120, &[sidebar, editor]), vec![30, 90]);
+        // A quarter of 200 is 50, over the max
+        assert_eq!(split_lengths(200, &[sidebar, editor]), vec![40, 160]);
+    }
+
+    #[test]
+    fn limits_give_way_when_nothing_else_can() {
+        // Both held at their max, so the last takes the rest anyway
+        assert_eq!(split_lengths(50, &[hint(None, Some(10)), hint(None, Some(10))]), vec![10, 40]);
+
+        // The mins don't fit, so the last gives up cells first
+        assert_eq!(split_lengths(30, &[hint(Some(20), None), hint(Some(20), None)]), vec![20, 10]);
+        assert_eq!(split_lengths(0, &[hint(Some(5), None)]), vec![0]);
+        assert!(split_lengths(10, &[]).is_empty());
+    }
+}
@@ -2,6 +2,7 @@ use std::{
     collections::HashMap,
     ops::{Deref, DerefMut},
     path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use crossterm::event::Event;
@@ -23,6 +24,24 @@ pub struct SessionUuid(pub Uuid);
 #[derive(State)]
 pub struct Running(pub bool);
 
+/// Whether anything happened this frame. Frames without activity drop the main loop to
+/// `CoreConfig::idle_framerate` until input or a command arrives. Input and commands mark
+/// it automatically; systems with work in flight (animations, timers) call `mark` too.
+#[derive(State, Default)]
+pub struct FrameActivity(AtomicBool);
+
+impl FrameActivity {
+    /// Keeps the editor ticking at full framerate for another frame
+    pub fn mark(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether the frame was marked, resetting it for the next one
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
 /// State for sending commands through an unbounded MPSC sender
 #[derive(State)]
 pub struct CommandSender(pub UnboundedSender<Box<dyn Command<State>>>);
@@ -45,36 +64,138 @@ impl DerefMut for CommandSender {
 pub struct ConfigDir(pub PathBuf);
 
 /// Core runtime settings (framerate, etc.)
-#[derive(State)]
+#[derive(State, ConfigurableState)]
+#[configurable(name = "core")]
 pub struct CoreConfig {
     pub framerate: u64,
+    /// Framerate used while nothing is happening. Input and commands still wake the editor
+    /// immediately. 0 disables idling.
+    pub idle_framerate: u64,
     pub disable_auto_pairs: bool,
     pub tab_display_unit: String,
     pub default_tab_unit: usize,
     /// When true, all conceals on a line are revealed if the cursor is on that line.
     pub reveal_conceal_on_cursor_line: bool,
+    /// Milliseconds a binding that is also the start of a longer one (`d` beside `dd`) waits
+    /// for the next key before firing (0 waits for the next key).
+    pub ambiguous_key_timeout_ms: u64,
+    /// Whether to show the popup listing continuations of a partial key sequence.
+    pub which_key: bool,
+    /// How the gutter numbers lines.
+    pub line_numbers: LineNumbers,
+    /// Lines kept visible above and below the cursor when scrolling.
+    pub scrolloff: usize,
+    /// Columns kept visible left and right of the cursor when scrolling horizontally.
+    pub sidescrolloff: usize,
+    /// Whether scrolling glides over a few frames instead of jumping.
+    pub smooth_scroll: bool,
+    /// Frames a smooth scroll takes to reach its target.
+    pub smooth_scroll_steps: usize,
+    /// Files larger than this many bytes (8 MiB by default) open read-only in big file mode,
+    /// without syntax highlighting or language servers.
+    pub big_file_threshold: u64,
+    /// Key `<leader>` expands to in `bind` and `category`. Binds are expanded when they're
+    /// registered, so it has to be set before them.
+    pub leader: String,
+    /// Whether lines wider than the view wrap onto extra rows instead of scrolling sideways.
+    pub wrap: bool,
+    /// Drawn in the gutter beside rows a wrapped line continues onto. Empty draws nothing.
+    pub wrap_indicator: String,
+    /// Whether tabs, trailing whitespace and line ends are drawn with `WhitespaceConfig`'s glyphs.
+    pub list: bool,
+    /// Whether the primary cursor's line gets the `ui.cursorline` background.
+    pub cursorline: bool,
+    /// Whether the primary cursor's column gets the `ui.cursorcolumn` background.
+    pub cursorcolumn: bool,
+    /// Whether yanked text briefly flashes with the `ui.yank_flash` style.
+    pub yank_flash: bool,
+    /// Milliseconds a yank flash stays on screen.
+    pub yank_flash_ms: u64,
+    /// Whether the built-in mouse binds (click to move, wheel to scroll) are added after the
+    /// config loads. Key binds all come from the sourced `.kb` files instead.
+    pub default_mouse_binds: bool,
+    /// Put between lines joined with `join`.
+    pub join_separator: String,
+    /// Characters that `join` puts no separator before when the next line starts with one.
+    pub join_no_space_before: String,
+    /// Milliseconds after its last change that a dirty buffer is saved on its own. 0 disables.
+    pub autosave_ms: u64,
+    /// Edits after which a dirty buffer is saved on its own. 0 disables.
+    pub autosave_changes: usize,
+    /// Whether unsaved buffers are copied to the recovery folder every `backup_ms`, so
+    /// `recover` can restore them after a crash.
+    pub backup: bool,
+    /// Milliseconds between backups of unsaved buffers.
+    pub backup_ms: u64,
 }
 
 impl Default for CoreConfig {
     fn default() -> Self {
         Self {
             framerate: 60,
+            idle_framerate: 5,
             disable_auto_pairs: false,
             tab_display_unit: "    ".to_string(),
             default_tab_unit: 4,
             reveal_conceal_on_cursor_line: true,
+            ambiguous_key_timeout_ms: 300,
+            which_key: true,
+            line_numbers: LineNumbers::Absolute,
+            scrolloff: 3,
+            sidescrolloff: 5,
+            smooth_scroll: false,
+            smooth_scroll_steps: 4,
+            big_file_threshold: 8 * 1024 * 1024,
+            leader: "space".to_string(),
+            wrap: false,
+            wrap_indicator: "↪".to_string(),
+            list: false,
+            cursorline: false,
+            cursorcolumn: false,
+            yank_flash: true,
+            yank_flash_ms: 150,
+            default_mouse_binds: true,
+            join_separator: " ".to_string(),
+            join_no_space_before: ")]}".to_string(),
+            autosave_ms: 0,
+            autosave_changes: 0,
+            backup: false,
+            backup_ms: 30_000,
         }
     }
 }
 
-/// Layout dimensions for the editor's chrome (gutter, statusline, bufferline).
+/// Glyphs drawn in place of whitespace while `CoreConfig::list` is on, styled with
+/// `ui.whitespace`. Set with `set whitespace.<glyph> <text>`; an empty glyph draws the
+/// whitespace as usual.
+#[derive(State, ConfigurableState)]
+#[configurable(name = "whitespace")]
+pub struct WhitespaceConfig {
+    /// Start of each tab, padded with spaces to the tab's width
+    pub tab: String,
+    /// Each space after the last visible character of a line. Only the first character is used.
+    pub trailing: String,
+    /// Drawn after the last character of each line
+    pub eol: String,
+}
+
+impl Default for WhitespaceConfig {
+    fn default() -> Self {
+        Self {
+            tab: "→".to_string(),
+            trailing: "·".to_string(),
+            eol: String::new(),
+        }
+    }
+}
+
+/// Layout dimensions for the editor's chrome (statusline, bufferline).
 /// Plugins may mutate these during init (before `PostInit`) to resize or hide chrome areas.
+/// The gutter is sized by `GutterConfig`.
 #[derive(State)]
 pub struct LayoutConfig {
     pub bufferline_height: u16,
     pub statusline_height: u16,
-    pub gutter_width: u16,
-    pub gutter_pad: u16,
 }
 
 impl Default for LayoutConfig {
@@ -82,8 +203,31 @@ impl Default for LayoutConfig {
         Self {
             bufferline_height: 1,
             statusline_height: 1,
-            gutter_width: 5,
-            gutter_pad: 2,
+        }
+    }
+}
+
+/// Sizing of the gutter beside each buffer. Fields can be set bare, like `set numberwidth 6`
+#[derive(State, ConfigurableState)]
+#[configurable(name = "gutter")]
+pub struct GutterConfig {
+    /// Whether buffers draw a gutter at all
+    pub enabled: bool,
+    /// Fewest columns given to line numbers. Files with more lines widen it to fit
+    pub numberwidth: u16,
+    /// Blank columns between the gutter and the text
+    pub padding: u16,
+    /// When the sign column is drawn: `auto` (while the buffer has signs), `yes`, or `no`
+    pub signcolumn: SignColumn,
+}
+
+impl Default for GutterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            numberwidth: 5,
+            padding: 2,
+            signcolumn: SignColumn::Auto,
         }
     }
 }
@@ -96,6 +240,10 @@ pub struct DebounceConfig(pub Vec<DebounceEvent>);
 #[derive(State, Default)]
 pub struct PaletteState(pub HashMap<String, Color>);
 
+/// Smallest terminal the editor lays itself out in; below it, only a notice is drawn
+pub const MIN_WINDOW_WIDTH: u16 = 20;
+pub const MIN_WINDOW_HEIGHT: u16 = 5;
+
 /// State wrapper around the ratatui terminal
 #[derive(State)]
 pub struct WindowState(pub Terminal<CrosstermBackend<std::io::Stdout>>);
@@ -106,6 +254,20 @@ impl WindowState {
         let s = self.0.size().unwrap_or_default();
         Rect::new(0, 0, s.width, s.height)
     }
+
+    /// Whether the terminal is below `MIN_WINDOW_WIDTH` by `MIN_WINDOW_HEIGHT`
+    pub fn too_small(&self) -> bool {
+        let size = self.size();
+        size.width < MIN_WINDOW_WIDTH || size.height < MIN_WINDOW_HEIGHT
+    }
+}
+
+/// Is emitted when the terminal is resized. Events resolve at the end of the frame, so
+/// subscribers run once the frame has been laid out and drawn in the new size; the
+/// editor's own handling happens earlier, in `handle_resize`
+pub struct ResizeEvent {
+    pub width: u16,
+    pub height: u16,
 }
 
 impl Deref for WindowState {
@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
 
 use crate::*;
 use ratatui::prelude::*;
@@ -16,9 +19,150 @@ pub struct ModeConfig {
 }
 
 /// Overall configuration for the editor's statusline
-#[derive(Deserialize, Default, Debug, State)]
+#[derive(Deserialize, Default, Debug, State, ConfigurableState)]
+#[configurable(name = "statusline")]
 pub struct StatuslineConfig {
+    #[configurable(skip)]
     pub modes: HashMap<char, ModeConfig>,
+
+    /// Replaces the default layout when set, see `expand_statusline_format`
+    pub format: String,
+}
+
+/// Named statusline segments, shown with `%{name}` in `statusline.format` (as plain text)
+/// and, for those in `DEFAULT_SEGMENTS`, with their styles in the default layout.
+/// Plugins keep their own segments up to date, like `diagnostics` from kerbin-lsp.
+#[derive(State, Default)]
+pub struct StatuslineSegments(pub HashMap<String, Line<'static>>);
+
+/// Segments the default layout shows on its right side when they're set
+pub const DEFAULT_SEGMENTS: &[&str] = &["diagnostics"];
+
+/// What the placeholders of a statusline format expand to
+pub struct StatuslineValues<'a> {
+    pub file: String,
+    pub modified: bool,
+    /// 1-based line of the primary cursor
+    pub line: usize,
+    /// 1-based column of the primary cursor
+    pub col: usize,
+    pub mode: String,
+    pub filetype: String,
+    pub segments: &'a HashMap<String, Line<'static>>,
+}
+
+/// Expands a statusline format into its left and right aligned text.
+///
+/// `%f` file, `%m` `[+]` when modified, `%l` line, `%c` column, `%t` filetype,
+/// `%d` diagnostics counts, `%{mode}` the current mode, `%{name}` a registered segment,
+/// `%=` starts the right aligned part, and `%%` is a literal `%`. Unknown placeholders
+/// are kept as written, and segments that aren't registered expand to nothing.
+pub fn expand_statusline_format(format: &str, values: &StatuslineValues) -> (String, String) {
+    let mut sides = [String::new(), String::new()];
+    let mut side = 0;
+    let segment = |name: &str| values.segments.get(name).map(Line::to_string).unwrap_or_default();
+
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            sides[side].push(c);
+            continue;
+        }
+
+        let text = match chars.next() {
+            Some('f') => values.file.clone(),
+            Some('m') if values.modified => "[+]".to_string(),
+            Some('m') => String::new(),
+            Some('l') => values.line.to_string(),
+            Some('c') => values.col.to_string(),
+            Some('t') => values.filetype.clone(),
+            Some('d') => segment("diagnostics"),
+            Some('%') => "%".to_string(),
+            Some('=') => {
+                side = 1;
+                String::new()
+            }
+            Some('{') => match chars.by_ref().take_while(|c| *c != '}').collect::<String>() {
+                name if name == "mode" => values.mode.clone(),
+                name => segment(&name),
+            },
+            Some(other) => format!("%{other}"),
+            None => "%".to_string(),
+        };
+        sides[side].push_str(&text);
+    }
+
+    let [left, right] = sides;
+    (left, right)
+}
+
+/// Long name of a mode, falling back to its char
+fn mode_name(config: &StatuslineConfig, mode: char) -> String {
+    config
+        .modes
+        .get(&mode)
+        .and_then(|c| c.long_name.clone())
+        .unwrap_or_else(|| mode.to_string())
+}
+
+/// Draws the statusline from `statusline.format`
+async fn render_statusline_format(
+    chunk: &mut InnerChunk,
+    config: &StatuslineConfig,
+    theme: &Theme,
+    mode_stack: &ModeStack,
+    buffers: &Buffers,
+    segments: &StatuslineSegments,
+) {
+    let mut values = StatuslineValues {
+        file: String::new(),
+        modified: false,
+        line: 1,
+        col: 1,
+        mode: mode_stack
+            .0
+            .last()
+            .map(|m| mode_name(config, *m))
+            .unwrap_or_default(),
+        filetype: String::new(),
+        segments: &segments.0,
+    };
+
+    if let Some(tb) = buffers.cur_buffer_as::<TextBuffer>().await {
+        let byte = tb.primary_cursor().get_cursor_byte();
+        let line = tb.byte_to_line_clamped(byte);
+        let line_start = tb.line_to_byte_clamped(line);
+
+        values.file = tb.title();
+        values.modified = tb.dirty;
+        values.line = line + 1;
+        values.col = tb.byte_to_char_clamped(byte) - tb.byte_to_char_clamped(line_start) + 1;
+        values.filetype = tb.filetype.clone().unwrap_or_default();
+    }
+
+    let (left, right) = expand_statusline_format(&config.format, &values);
+
+    let style = theme.get_fallback_default(["statusline.text", "ui.text"]);
+    if !chunk.draw_version(statusline_version(&(&left, &right, style))) {
+        return;
+    }
+
+    let area = chunk.area();
+    chunk.set_string(area.x, area.y, &left, style);
+
+    // Display width, so wide and combining characters line up
+    let right_width = right.width();
+    if right_width + left.width() <= area.width as usize {
+        let x = area.x + area.width - right_width as u16;
+        chunk.set_string(x, area.y, &right, style);
+    }
+}
+
+/// Version of a statusline drawn from `content`, for [`InnerChunk::draw_version`]
+fn statusline_version(content: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
 }
 
 pub async fn render_statusline(
@@ -30,9 +174,24 @@ pub async fn render_statusline(
     input: Res<InputState>,
 
     buffers: Res<Buffers>,
+    segments: Res<StatuslineSegments>,
 ) {
     get!(statusline_config, Some(mut chunk), theme, mode_stack, input);
 
+    if !statusline_config.format.is_empty() {
+        get!(buffers, segments);
+        render_statusline_format(
+            &mut chunk,
+            &statusline_config,
+            &theme,
+            &mode_stack,
+            &buffers,
+            &segments,
+        )
+        .await;
+        return;
+    }
+
     let chunk_width = chunk.area().width;
     let base_x = chunk.area().x;
     let base_y = chunk.area().y;
@@ -76,67 +235,146 @@ pub async fn render_statusline(
         );
     }
 
-    let mut x: u16 = 0;
-
-    for (i, (text, style)) in parts.into_iter().enumerate().take(4) {
-        if x >= chunk_width {
-            break;
-        }
-        let prefix = if i != 0 { " -> " } else { "" };
-        if !prefix.is_empty() {
-            chunk.set_string(base_x + x, base_y, prefix, Style::default());
-            x += prefix.chars().count() as u16;
-        }
-        chunk.set_string(base_x + x, base_y, &text, style);
-        x += text.chars().count() as u16;
-    }
-
-    let (cursor_count, primary_cursor_idx) = buffers
+    let (cursor_count, primary_cursor_idx, stale) = buffers
         .get()
         .await
         .cur_buffer_as::<TextBuffer>()
         .await
-        .map(|tb| (tb.cursors.len(), tb.primary_cursor))
-        .unwrap_or((1, 0));
+        .map(|tb| (tb.cursors.len(), tb.primary_cursor, tb.stale))
+        .unwrap_or((1, 0, false));
 
-    let mut right_parts: Vec<(String, Style)> = vec![];
+    let segments = segments.get().await;
+    let mut right_parts: Vec<Line> = DEFAULT_SEGMENTS
+        .iter()
+        .filter_map(|name| segments.0.get(*name).cloned())
+        .filter(|line| line.width() > 0)
+        .collect();
+
+    if stale {
+        let stale_style = theme.get_fallback_default(["statusline.stale"]);
+        right_parts.push(Line::styled("changed on disk", stale_style));
+    }
 
     if !input.repeat_count.is_empty() {
         let repeat_style = theme.get_fallback_default(["statusline.repeat"]);
-        right_parts.push((input.repeat_count.clone(), repeat_style));
+        right_parts.push(Line::styled(input.repeat_count.clone(), repeat_style));
     }
 
     if cursor_count == 1 {
         let sel_style =
             theme.get_fallback_default(["statusline.selections.one", "statusline.selections"]);
-        right_parts.push(("1 sel".to_string(), sel_style));
+        right_parts.push(Line::styled("1 sel", sel_style));
     } else {
         let sel_style =
             theme.get_fallback_default(["statusline.selections.multi", "statusline.selections"]);
         let primary_cursor = primary_cursor_idx + 1;
-        right_parts.push((
+        right_parts.push(Line::styled(
             format!("{}/{} sels", primary_cursor, cursor_count),
             sel_style,
         ));
     }
 
-    let spacing = if right_parts.len() > 1 { 3 } else { 0 }; // " | " separator width
-    let right_width: usize = right_parts
-        .iter()
-        .map(|(s, _)| s.chars().count())
-        .sum::<usize>()
-        + spacing;
+    // Everything drawn comes from the parts, so they stand in for the mode, file and so on
+    parts.truncate(4);
+    if !chunk.draw_version(statusline_version(&(&parts, &right_parts))) {
+        return;
+    }
+
+    let mut x: u16 = 0;
+
+    for (i, (text, style)) in parts.into_iter().enumerate() {
+        if x >= chunk_width {
+            break;
+        }
+        let prefix = if i != 0 { " -> " } else { "" };
+        if !prefix.is_empty() {
+            chunk.set_string(base_x + x, base_y, prefix, Style::default());
+            x += prefix.width() as u16;
+        }
+        chunk.set_string(base_x + x, base_y, &text, style);
+        x += text.width() as u16;
+    }
+
+    let spacing = right_parts.len().saturating_sub(1) * 3; // " | " separator width
+    let right_width: usize = right_parts.iter().map(Line::width).sum::<usize>() + spacing;
 
     if right_width <= chunk_width as usize {
         let mut right_x = chunk_width.saturating_sub(right_width as u16);
 
-        for (i, (text, style)) in right_parts.into_iter().enumerate() {
+        for (i, line) in right_parts.into_iter().enumerate() {
             if i != 0 {
                 chunk.set_string(base_x + right_x, base_y, " | ", Style::default());
                 right_x += 3;
             }
-            chunk.set_string(base_x + right_x, base_y, &text, style);
-            right_x += text.chars().count() as u16;
+            let width = line.width() as u16;
+            chunk.set_line(base_x + right_x, base_y, &line, width);
+            right_x += width;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_placeholders_and_segments() {
+        let segments = HashMap::from([
+            ("diagnostics".to_string(), Line::from("2")),
+            ("lsp".to_string(), Line::from("rust-analyzer")),
+        ]);
+        let values = StatuslineValues {
+            file: "src/main.rs".to_string(),
+            modified: true,
+            line: 12,
+            col: 4,
+            mode: "NORMAL".to_string(),
+            filetype: "rust".to_string(),
+            segments: &segments,
+        };
+
+        let expand = |format: &str| expand_statusline_format(format, &values);
+
+        assert_eq!(
+            expand("%f %m | %l:%c %{mode}"),
+            ("src/main.rs [+] | 12:4 NORMAL".to_string(), String::new())
+        );
+        assert_eq!(
+            expand("%{mode} %t%=%d errs | %{lsp}"),
+            ("NORMAL rust".to_string(), "2 errs | rust-analyzer".to_string())
+        );
+
+        // Literal, unknown, and missing placeholders
+        assert_eq!(expand("100%% %q %{missing}%"), ("100% %q %".to_string(), String::new()));
+
+        let saved = StatuslineValues {
+            modified: false,
+            ..values
+        };
+        assert_eq!(expand_statusline_format("%f%m", &saved).0, "src/main.rs");
+    }
+
+    #[tokio::test]
+    async fn right_side_aligns_by_display_width() {
+        let config = StatuslineConfig {
+            format: "%=漢字e\u{301}".to_string(),
+            ..Default::default()
+        };
+        let mut chunk = InnerChunk::new(Buffer::empty(Rect::new(0, 0, 10, 1)));
+
+        render_statusline_format(
+            &mut chunk,
+            &config,
+            &Theme::default(),
+            &ModeStack(vec!['n']),
+            &Buffers::default(),
+            &StatuslineSegments::default(),
+        )
+        .await;
+
+        // Two double-width characters and an accented `e` take the last five columns
+        let symbols: Vec<&str> = (5..10).map(|x| chunk[(x, 0)].symbol()).collect();
+        assert_eq!(symbols, ["漢", " ", "字", " ", "e\u{301}"]);
+        assert_eq!(chunk[(4, 0)].symbol(), " ");
+    }
+}
@@ -0,0 +1,136 @@
+use ratatui::style::{Color, Style};
+
+pub trait StyleExts {
+    /// Tints the background with `color` at `alpha` (0.0 keeps the background, 1.0 replaces it).
+    /// Without a background to blend over, `color` is used as is.
+    fn blend_bg(self, color: Color, alpha: f32) -> Style;
+
+    /// Layers `other` over this style like `Style::patch`, except that a background from
+    /// `other` is blended at half strength so the background below still shows through
+    fn overlay(self, other: Style) -> Style;
+}
+
+impl StyleExts for Style {
+    fn blend_bg(self, color: Color, alpha: f32) -> Style {
+        let bg = match self.bg.and_then(color_to_rgb).zip(color_to_rgb(color)) {
+            Some((below, above)) => mix(below, above, alpha),
+            None => color,
+        };
+
+        self.bg(bg)
+    }
+
+    fn overlay(self, other: Style) -> Style {
+        let bg = other.bg;
+        let style = self.patch(Style { bg: None, ..other });
+
+        match bg {
+            Some(bg) => style.blend_bg(bg, 0.5),
+            None => style,
+        }
+    }
+}
+
+/// Interpolates between two RGB colors, `alpha` of the way from `below` to `above`
+fn mix(below: (u8, u8, u8), above: (u8, u8, u8), alpha: f32) -> Color {
+    let alpha = alpha.clamp(0.0, 1.0);
+    let channel = |b: u8, a: u8| (b as f32 + (a as f32 - b as f32) * alpha).round() as u8;
+
+    Color::Rgb(
+        channel(below.0, above.0),
+        channel(below.1, above.1),
+        channel(below.2, above.2),
+    )
+}
+
+/// Converts a color to RGB, using the xterm values for named and indexed colors.
+/// Returns `None` for `Color::Reset`, whose value depends on the terminal.
+pub fn color_to_rgb(color: Color) -> Option<(u8, u8, u8)> {
+    let rgb = match color {
+        Color::Reset => return None,
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Black => (0, 0, 0),
+        Color::Red => (205, 0, 0),
+        Color::Green => (0, 205, 0),
+        Color::Yellow => (205, 205, 0),
+        Color::Blue => (0, 0, 238),
+        Color::Magenta => (205, 0, 205),
+        Color::Cyan => (0, 205, 205),
+        Color::Gray => (229, 229, 229),
+        Color::DarkGray => (127, 127, 127),
+        Color::LightRed => (255, 0, 0),
+        Color::LightGreen => (0, 255, 0),
+        Color::LightYellow => (255, 255, 0),
+        Color::LightBlue => (92, 92, 255),
+        Color::LightMagenta => (255, 0, 255),
+        Color::LightCyan => (0, 255, 255),
+        Color::White => (255, 255, 255),
+        Color::Indexed(i) => return indexed_to_rgb(i),
+    };
+
+    Some(rgb)
+}
+
+fn indexed_to_rgb(index: u8) -> Option<(u8, u8, u8)> {
+    const ANSI: [Color; 16] = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::Gray,
+        Color::DarkGray,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::LightYellow,
+        Color::LightBlue,
+        Color::LightMagenta,
+        Color::LightCyan,
+        Color::White,
+    ];
+
+    match index {
+        0..16 => color_to_rgb(ANSI[index as usize]),
+        16..232 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let i = index - 16;
+            Some((level(i / 36), level(i / 6 % 6), level(i % 6)))
+        }
+        _ => {
+            let v = 8 + (index - 232) * 10;
+            Some((v, v, v))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blends_red_tint_under_blue_text() {
+        let style = Style::default().fg(Color::Blue).bg(Color::Rgb(20, 20, 40));
+
+        let tinted = style.blend_bg(Color::Red, 0.25);
+        assert_eq!(tinted.fg, Some(Color::Blue));
+        assert_eq!(tinted.bg, Some(Color::Rgb(66, 15, 30)));
+
+        // Nothing to blend over, so the tint is used directly
+        let plain = Style::default().fg(Color::Blue).blend_bg(Color::Red, 0.25);
+        assert_eq!(plain.bg, Some(Color::Red));
+
+        let overlaid = style.overlay(Style::default().bg(Color::Rgb(220, 0, 0)));
+        assert_eq!(overlaid.fg, Some(Color::Blue));
+        assert_eq!(overlaid.bg, Some(Color::Rgb(120, 10, 20)));
+    }
+
+    #[test]
+    fn converts_indexed_colors() {
+        assert_eq!(color_to_rgb(Color::Indexed(1)), color_to_rgb(Color::Red));
+        assert_eq!(color_to_rgb(Color::Indexed(196)), Some((255, 0, 0)));
+        assert_eq!(color_to_rgb(Color::Indexed(244)), Some((128, 128, 128)));
+        assert_eq!(color_to_rgb(Color::Reset), None);
+    }
+}
@@ -1,4 +1,11 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
 
 use kerbin_macros::State;
 use kerbin_state_machine::storage::*;
@@ -87,12 +94,115 @@ pub fn build_style(
 pub struct Theme {
     /// The internal hash map storing theme names (strings) to their corresponding `Style`
     map: HashMap<String, Style>,
+    /// Colors and attributes a theme entry named that didn't resolve, keyed by entry
+    unresolved: HashMap<String, Vec<String>>,
+    /// Changes with every registered style, see `Theme::revision`
+    revision: u64,
+    /// Results of `get_fallback_default` by the names looked up, cleared by `register`
+    fallback_cache: Mutex<HashMap<Vec<String>, Style>>,
+}
+
+/// Source of theme revisions, global so a theme that's reset and loaded again never
+/// repeats one
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+/// Describes a style's resolved colors and attributes, e.g. `fg #cba6f7 bg #1e1e2e bold`.
+/// Empty when the style sets nothing
+pub fn describe_style(style: Style) -> String {
+    let color = |c: Color| match c {
+        Color::Rgb(r, g, b) => format!("#{r:02x}{g:02x}{b:02x}"),
+        other => format!("{other:?}").to_lowercase(),
+    };
+
+    let mut parts = vec![];
+    for (label, c) in [("fg", style.fg), ("bg", style.bg), ("ul", style.underline_color)] {
+        if let Some(c) = c {
+            parts.push(format!("{label} {}", color(c)));
+        }
+    }
+    for (name, _) in style.add_modifier.iter_names() {
+        parts.push(name.replace('_', "").to_lowercase());
+    }
+    parts.join(" ")
+}
+
+/// Generated text listing every theme entry, see `Theme::preview`
+pub struct ThemePreview {
+    pub text: String,
+    /// The byte range of each entry's name, with the style to draw it in
+    pub highlights: Vec<(Range<usize>, Style)>,
 }
 
 impl Theme {
     /// Registers a theme, associating a `Style` with a given name
     pub fn register(&mut self, name: String, style: Style) {
+        self.unresolved.remove(&name);
         self.map.insert(name, style);
+        self.revision = NEXT_REVISION.fetch_add(1, Ordering::Relaxed);
+        self.fallback_cache.get_mut().unwrap().clear();
+    }
+
+    /// Identifies the theme's current styles, changing whenever one is registered. For
+    /// renderers tracking what they drew from (see `InnerChunk::draw_version`)
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Records the colors and attributes `name` was given that didn't resolve, so the
+    /// preview can point them out. Cleared when `name` is registered again
+    pub fn mark_unresolved(&mut self, name: String, unresolved: Vec<String>) {
+        if !unresolved.is_empty() {
+            self.unresolved.insert(name, unresolved);
+        }
+    }
+
+    /// Names of every registered entry, sorted
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.map.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// A line per registered entry, grouped under its first scope (`ts`, `ui`, ...), with the
+    /// name drawn in its own style next to the colors it resolved to. Entries that style
+    /// nothing or named unknown colors are marked with a `!`
+    pub fn preview(&self) -> ThemePreview {
+        let keys = self.keys();
+        let width = keys.iter().map(|k| k.chars().count()).max().unwrap_or(0);
+
+        let mut text = String::new();
+        let mut highlights = vec![];
+        let mut group = None;
+        for key in keys {
+            let scope = key.split('.').next().unwrap_or(key);
+            if group != Some(scope) {
+                if group.is_some() {
+                    text.push('\n');
+                }
+                text.push_str(&format!("# {scope}\n"));
+                group = Some(scope);
+            }
+
+            let style = self.map[key];
+            let mut details = vec![describe_style(style)];
+            let mut marked = false;
+            if style == Style::default() {
+                details.push("(unstyled)".to_string());
+                marked = true;
+            }
+            if let Some(unresolved) = self.unresolved.get(key) {
+                details.push(format!("(unknown: {})", unresolved.join(", ")));
+                marked = true;
+            }
+            details.retain(|d| !d.is_empty());
+
+            text.push_str(if marked { "! " } else { "  " });
+            highlights.push((text.len()..text.len() + key.len(), style));
+            let line = format!("{key:width$}  {}", details.join(" "));
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        ThemePreview { text, highlights }
     }
 
     /// Retrieves a `Style` from the system by its name
@@ -100,13 +210,120 @@ impl Theme {
         self.map.get(name).copied()
     }
 
-    /// Retrieves a `Style` based on an iterator of names, falling back to a default style
-    pub fn get_fallback_default(&self, names: impl IntoIterator<Item = impl ToString>) -> Style {
-        for name in names.into_iter().map(|x| x.to_string()) {
-            if let Some(theme) = self.get(&name) {
-                return theme;
+    /// Retrieves a `Style` by a highlight scope, falling back to the longest defined dotted
+    /// prefix. `ts.function.method` resolves to `ts.function` when only that is defined.
+    pub fn get_scope(&self, mut scope: &str) -> Option<Style> {
+        loop {
+            if let Some(style) = self.get(scope) {
+                return Some(style);
             }
+
+            scope = &scope[..scope.rfind('.')?];
         }
-        Style::default()
+    }
+
+    /// Retrieves a `Style` based on an iterator of names, falling back to a default style.
+    /// Each name must be defined exactly; use `get_scope` for dotted scope fallback.
+    ///
+    /// Results are memoized by the list of names until the next `register`, since the
+    /// renderers repeat the same lists for every row (see `benches/theme_lookup.rs`).
+    pub fn get_fallback_default(&self, names: impl IntoIterator<Item = impl ToString>) -> Style {
+        let names: Vec<String> = names.into_iter().map(|x| x.to_string()).collect();
+
+        let mut cache = self.fallback_cache.lock().unwrap();
+        if let Some(style) = cache.get(&names) {
+            return *style;
+        }
+
+        let style = names.iter().find_map(|name| self.get(name)).unwrap_or_default();
+        cache.insert(names, style);
+        style
+    }
+
+    /// The style every cell starts from: `ui.text` patched with `ui.background`.
+    /// Fills the screen each frame, so areas nothing draws over don't show the terminal's
+    /// own colors.
+    pub fn default_style(&self) -> Style {
+        let text = self.get("ui.text").unwrap_or_default();
+        match self.get("ui.background") {
+            Some(background) => text.patch(background),
+            None => text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_first_registered_name() {
+        let mut theme = Theme::default();
+        let keys = ["ui.commandline.alias", "ui.text"];
+        assert_eq!(theme.get_fallback_default(keys), Style::default());
+
+        let text = Style::default().fg(Color::White);
+        theme.register("ui.text".to_string(), text);
+        assert_eq!(theme.get_fallback_default(keys), text);
+
+        let alias = Style::default().fg(Color::Red);
+        theme.register("ui.commandline.alias".to_string(), alias);
+        assert_eq!(theme.get_fallback_default(keys), alias);
+    }
+
+    #[test]
+    fn default_style_layers_background_over_text() {
+        let mut theme = Theme::default();
+        assert_eq!(theme.default_style(), Style::default());
+
+        theme.register("ui.text".to_string(), Style::default().fg(Color::White));
+        assert_eq!(theme.default_style(), Style::default().fg(Color::White));
+
+        theme.register("ui.background".to_string(), Style::default().bg(Color::Black));
+        assert_eq!(
+            theme.default_style(),
+            Style::default().fg(Color::White).bg(Color::Black)
+        );
+    }
+
+    #[test]
+    fn longest_defined_prefix_wins() {
+        let mut theme = Theme::default();
+        let function = Style::default().fg(Color::Blue);
+        let method = Style::default().fg(Color::Cyan);
+        theme.register("ts.function".to_string(), function);
+        theme.register("ts.function.method".to_string(), method);
+
+        assert_eq!(theme.get_scope("ts.function.method.rust"), Some(method));
+        assert_eq!(theme.get_scope("ts.function.macro"), Some(function));
+        assert_eq!(theme.get_scope("ts.function"), Some(function));
+        assert_eq!(theme.get_scope("ts.keyword"), None);
+        assert_eq!(theme.get("ts.function.macro"), None);
+    }
+
+    #[test]
+    fn preview_groups_entries_and_marks_unstyled_ones() {
+        let mut theme = Theme::default();
+        theme.register("ts.keyword".to_string(), Style::default().fg(Color::Rgb(203, 166, 247)));
+        theme.register(
+            "ui.text".to_string(),
+            Style::default().bg(Color::Black).add_modifier(Modifier::BOLD | Modifier::CROSSED_OUT),
+        );
+        theme.register("ui.x".to_string(), Style::default());
+        theme.mark_unresolved("ui.x".to_string(), vec!["lavendr".to_string()]);
+
+        let preview = theme.preview();
+        assert_eq!(
+            preview.text,
+            "# ts\n  ts.keyword  fg #cba6f7\n\n# ui\n  ui.text     bg black bold crossedout\n\
+             ! ui.x        (unstyled) (unknown: lavendr)\n"
+        );
+        let names: Vec<&str> =
+            preview.highlights.iter().map(|(r, _)| &preview.text[r.clone()]).collect();
+        assert_eq!(names, ["ts.keyword", "ui.text", "ui.x"]);
+
+        // Fixing the entry clears its mark
+        theme.register("ui.x".to_string(), Style::default().fg(Color::Red));
+        assert!(!theme.preview().text.contains('!'));
     }
 }
@@ -0,0 +1,228 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+use crate::*;
+
+/// How long a file has to stay quiet before its change is reported.
+/// Editors that save by writing a temp file and renaming it fire several events at once.
+const SETTLE_TIME: Duration = Duration::from_millis(150);
+
+/// Watches the folders of open files so their buffers can be marked stale when the file
+/// changes on disk. Folders are watched instead of files so saves that replace the file
+/// by renaming over it are still seen.
+#[derive(State)]
+pub struct FileWatcher {
+    watcher: Option<RecommendedWatcher>,
+    events: UnboundedReceiver<notify::Result<notify::Event>>,
+
+    files: HashSet<PathBuf>,
+    /// Watched folders, and how many of `files` are in each
+    dirs: HashMap<PathBuf, usize>,
+
+    /// Changed files and when they last changed
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        let (tx, rx) = unbounded_channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })
+        .inspect_err(|e| tracing::error!("Failed to start file watcher: {e}"))
+        .ok();
+
+        Self {
+            watcher,
+            events: rx,
+            files: HashSet::new(),
+            dirs: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl FileWatcher {
+    /// Watches exactly `files`, adding and removing folder watches as needed
+    pub fn sync(&mut self, files: HashSet<PathBuf>) {
+        if files == self.files {
+            return;
+        }
+
+        for dir in self.files.difference(&files).filter_map(|f| f.parent()) {
+            let Some(count) = self.dirs.get_mut(dir) else {
+                continue;
+            };
+
+            *count -= 1;
+            if *count == 0 {
+                self.dirs.remove(dir);
+                if let Some(watcher) = &mut self.watcher {
+                    let _ = watcher.unwatch(dir);
+                }
+            }
+        }
+
+        for dir in files.difference(&self.files).filter_map(|f| f.parent()) {
+            let count = self.dirs.entry(dir.to_path_buf()).or_default();
+            *count += 1;
+
+            if *count == 1
+                && let Some(watcher) = &mut self.watcher
+                && let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive)
+            {
+                tracing::error!("Failed to watch {}: {e}", dir.display());
+            }
+        }
+
+        self.pending.retain(|path, _| files.contains(path));
+        self.files = files;
+    }
+
+    /// Collects change events, returning the files that have settled since they last changed
+    pub fn poll(&mut self, now: Instant) -> Vec<PathBuf> {
+        while let Ok(event) = self.events.try_recv() {
+            let Ok(event) = event else { continue };
+
+            if !matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) {
+                continue;
+            }
+
+            for path in event.paths {
+                if self.files.contains(&path) {
+                    self.pending.insert(path, now);
+                }
+            }
+        }
+
+        let settled: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in &settled {
+            self.pending.remove(path);
+        }
+
+        settled
+    }
+
+    /// Whether any changed files are still waiting to settle
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+
+/// Keeps the watched files in step with the open buffers, and marks a buffer stale once its
+/// file has changed on disk. Emits `FileChangedEvent` for the newly stale buffers.
+pub async fn update_file_watcher(
+    buffers: Res<Buffers>,
+    watcher: ResMut<FileWatcher>,
+    log: Res<LogSender>,
+    activity: Res<FrameActivity>,
+) {
+    get!(buffers, mut watcher, log, activity);
+
+    let mut files = HashSet::new();
+    for buf in &buffers.buffers {
+        if let Some(path) = buf.read().await.downcast::<TextBuffer>().and_then(watch_path) {
+            files.insert(path);
+        }
+    }
+    watcher.sync(files);
+
+    let changed = watcher.poll(Instant::now());
+    if watcher.has_pending() {
+        activity.mark();
+    }
+    if changed.is_empty() {
+        return;
+    }
+
+    let mut paths = vec![];
+    for buf in &buffers.buffers {
+        let mut buf = buf.write().await;
+        let Some(buf) = buf.downcast_mut::<TextBuffer>() else {
+            continue;
+        };
+
+        if buf.stale || !watch_path(buf).is_some_and(|p| changed.contains(&p)) {
+            continue;
+        }
+
+        // Our own saves update `changed`, so they don't count
+        let modified = std::fs::metadata(&buf.path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified == buf.changed {
+            continue;
+        }
+
+        buf.stale = true;
+        paths.push(buf.path.clone());
+    }
+
+    if paths.is_empty() {
+        return;
+    }
+
+    let message = if paths.len() == 1 {
+        format!("{} changed on disk, run `reload` to load it", paths.join(", "))
+    } else {
+        format!("{} files changed on disk, run `reload` to load them", paths.len())
+    };
+    log.medium("watcher", message);
+
+    EVENT_BUS.emit(FileChangedEvent { paths }).await;
+}
+
+/// The absolute path a buffer's file is watched under, or `None` for special buffers
+fn watch_path(buf: &TextBuffer) -> Option<PathBuf> {
+    if buf.path.starts_with('<') && buf.path.ends_with('>') {
+        return None;
+    }
+
+    std::path::absolute(Path::new(&buf.path)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changes_once_settled() {
+        let dir = std::env::temp_dir().join(format!("kerbin-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("watched.txt");
+        std::fs::write(&file, "one").unwrap();
+
+        let mut watcher = FileWatcher::default();
+        watcher.sync(HashSet::from([file.clone()]));
+        assert_eq!(watcher.dirs.get(&dir), Some(&1));
+
+        std::fs::write(&file, "two").unwrap();
+
+        // Events arrive on the watcher's thread, so give them a moment
+        let start = Instant::now();
+        let mut settled = vec![];
+        while settled.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(20));
+            settled = watcher.poll(Instant::now());
+        }
+        assert_eq!(settled, std::slice::from_ref(&file));
+
+        watcher.sync(HashSet::new());
+        assert!(watcher.dirs.is_empty());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use crate::*;
+
+const YANK_FLASH_NS: &str = "core::yank_flash";
+/// Above search matches, so a yanked match still visibly flashes
+const YANK_FLASH_PRIORITY: i32 = 5;
+
+/// Per-buffer deadline of the highlight left by the last yank
+#[derive(State, Default)]
+pub struct YankFlash {
+    deadline: Option<Instant>,
+}
+
+impl YankFlash {
+    /// Whether the flash should have been removed by `now`
+    pub fn expired(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|deadline| now >= deadline)
+    }
+}
+
+/// Highlights `range` of the current buffer with `ui.yank_flash` until
+/// `CoreConfig::yank_flash_ms` passes. A new flash replaces the previous one and its deadline.
+pub async fn flash_yank(state: &mut State, range: Range<usize>) {
+    let (enabled, duration) = {
+        let config = state.lock_state::<CoreConfig>().await;
+        (config.yank_flash, Duration::from_millis(config.yank_flash_ms))
+    };
+    if !enabled || duration.is_zero() || range.is_empty() {
+        return;
+    }
+
+    let style = state
+        .lock_state::<Theme>()
+        .await
+        .get_fallback_default(["ui.yank_flash", "ui.selection"]);
+
+    let mut buffers = state.lock_state::<Buffers>().await;
+    let Some(mut buf) = buffers.cur_text_buffer_mut().await else {
+        return;
+    };
+
+    let mark = ExtmarkBuilder::new_range(YANK_FLASH_NS, range)
+        .with_kind(ExtmarkKind::Highlight { style })
+        .with_adjustment(ExtmarkAdjustment::DeleteOnDelete);
+
+    let version = *buf.version();
+    buf.renderer
+        .set_namespace_priority(YANK_FLASH_NS, YANK_FLASH_PRIORITY);
+    buf.renderer.set_namespace(version, YANK_FLASH_NS, vec![mark]);
+
+    buf.get_or_insert_state_mut(YankFlash::default).await.deadline =
+        Some(Instant::now() + duration);
+}
+
+/// Removes yank flashes whose deadline passed, keeping frames coming until they have
+pub async fn expire_yank_flash(buffers: Res<Buffers>, activity: Res<FrameActivity>) {
+    get!(buffers, activity);
+    let now = Instant::now();
+
+    for buf in &buffers.buffers {
+        let mut buf = buf.write().await;
+        let Some(buf) = buf.downcast_mut::<TextBuffer>() else {
+            continue;
+        };
+
+        let expired = match buf.get_state::<YankFlash>().await {
+            Some(flash) => flash.expired(now),
+            None => continue,
+        };
+
+        if expired {
+            buf.renderer.clear_extmark_ns(YANK_FLASH_NS);
+            buf.remove_state::<YankFlash>();
+        } else {
+            activity.mark();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn later_flashes_push_the_deadline_back() {
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, "let yanked = 1;\n");
+        let mut buffers = Buffers::default();
+        buffers.push_buffer(buf).await;
+
+        let mut state = State::new();
+        state
+            .state(buffers)
+            .state(CoreConfig {
+                yank_flash_ms: 200,
+                ..Default::default()
+            })
+            .state(Theme::default())
+            .state(FrameActivity::default());
+
+        // Whether the current buffer still shows a flash
+        let flashing = async |state: &State| {
+            let buffers = state.lock_state::<Buffers>().await;
+            let buf = buffers.cur_buffer_as::<TextBuffer>().await.unwrap();
+            let marks = buf.renderer.query_extmarks(0..buf.len());
+            let marked = marks.iter().any(|mark| mark.namespace == YANK_FLASH_NS);
+            assert_eq!(marked, buf.has_state::<YankFlash>());
+            marked
+        };
+        let wait = |ms| tokio::time::sleep(Duration::from_millis(ms));
+
+        flash_yank(&mut state, 4..10).await;
+        state.call(expire_yank_flash).await;
+        assert!(flashing(&state).await);
+
+        // A second yank before the first expires replaces the deadline
+        wait(120).await;
+        flash_yank(&mut state, 4..10).await;
+        wait(120).await;
+        state.call(expire_yank_flash).await;
+        assert!(flashing(&state).await);
+
+        wait(150).await;
+        state.call(expire_yank_flash).await;
+        assert!(!flashing(&state).await);
+    }
+}
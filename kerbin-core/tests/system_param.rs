@@ -0,0 +1,6 @@
+#[test]
+fn derive_system_param() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/system_param_bundle.rs");
+    cases.compile_fail("tests/ui/system_param_bad_field.rs");
+}
@@ -0,0 +1,9 @@
+use kerbin_core::*;
+
+#[derive(SystemParam)]
+struct Editor {
+    theme: Res<Theme>,
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,23 @@
+use kerbin_core::*;
+use kerbin_core::system::{System, into_system::IntoSystem};
+
+#[derive(SystemParam)]
+struct Editor {
+    theme: Res<Theme>,
+    buffers: ResMut<Buffers>,
+}
+
+async fn system(editor: Editor, log: Res<LogSender>) {
+    get!(editor, log);
+    let _ = (editor.theme.get().await, editor.buffers.get().await, log);
+}
+
+fn main() {
+    let desc = Editor::desc();
+    let locks: Vec<_> = desc.iter().map(|d| (d.type_name.as_str(), d.write)).collect();
+    let (theme, buffers) = (Theme::static_name(), Buffers::static_name());
+    assert_eq!(locks, [(theme.as_str(), false), (buffers.as_str(), true)]);
+
+    // The bundle's locks count as the system's own
+    assert_eq!(system.into_system().params().len(), 3);
+}
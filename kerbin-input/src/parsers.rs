@@ -143,6 +143,12 @@ impl FromStr for UnresolvedKeyBind {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(inner) = s.strip_prefix('<').and_then(|s| s.strip_suffix('>'))
+            && !inner.is_empty()
+        {
+            return parse_chord(inner);
+        }
+
         let segments = parse_segments(s)?;
 
         if segments.is_empty() {
@@ -219,6 +225,131 @@ impl<'de> Deserialize<'de> for UnresolvedKeyElement<Matchable<KeyModifiers>> {
     }
 }
 
+/// Parses keys written back to back, such as `<C-w>h` or `<leader>ff`.
+///
+/// A word starting with an angle bracket chord is split into keys: each `<...>` chord is one
+/// key, as is every other character, and `<leader>` becomes `leader`. Any other word is a
+/// single key in dash syntax (`ctrl-s`, `esc`, `(a|b)`).
+pub fn parse_key_sequence(s: &str, leader: &str) -> Result<Vec<UnresolvedKeyBind>, String> {
+    if !s.starts_with('<') || s.len() == 1 {
+        return Ok(vec![s.parse()?]);
+    }
+
+    let mut keys = vec![];
+    let mut rest = s;
+
+    while let Some(ch) = rest.chars().next() {
+        if ch != '<' {
+            keys.push(char_key(ch));
+            rest = &rest[ch.len_utf8()..];
+            continue;
+        }
+
+        let Some(end) = rest[1..].find('>').map(|i| i + 1) else {
+            return Err(format!("Unclosed chord `{rest}` in `{s}`, use `<lt>` for a literal `<`"));
+        };
+
+        let inner = &rest[1..end];
+        if inner.is_empty() {
+            return Err(format!("Empty chord `<>` in `{s}`"));
+        }
+
+        if inner.eq_ignore_ascii_case("leader") {
+            keys.push(
+                leader
+                    .parse()
+                    .map_err(|e| format!("Invalid leader key `{leader}`: {e}"))?,
+            );
+        } else {
+            keys.push(parse_chord(inner)?);
+        }
+        rest = &rest[end + 1..];
+    }
+
+    Ok(keys)
+}
+
+/// A plain character key, taken literally even when it means something in dash syntax
+fn char_key(ch: char) -> UnresolvedKeyBind {
+    UnresolvedKeyBind {
+        mods: vec![],
+        code: UnresolvedKeyElement::Literal(ResolvedKeyBind::new_matchable(
+            Matchable::Specific(KeyModifiers::empty()),
+            Matchable::Specific(KeyCode::Char(ch)),
+        )),
+    }
+}
+
+/// Parses the inside of a Vim style chord: modifiers then a key, joined by dashes
+/// (`C-s`, `A-S-x`, `C--`), or a named key on its own (`CR`, `Esc`, `F5`)
+fn parse_chord(inner: &str) -> Result<UnresolvedKeyBind, String> {
+    let (mods_str, key_str) = match inner.strip_suffix("--") {
+        Some(mods) => (mods, "-"),
+        None => inner.rsplit_once('-').unwrap_or(("", inner)),
+    };
+
+    if key_str.is_empty() {
+        return Err(format!("Missing key in chord `<{inner}>`"));
+    }
+
+    let mut mods = vec![];
+    if !mods_str.is_empty() {
+        for m in mods_str.split('-') {
+            let modifier = match m.to_ascii_lowercase().as_str() {
+                "c" | "ctrl" | "control" => KeyModifiers::CONTROL,
+                "a" | "m" | "alt" | "meta" => KeyModifiers::ALT,
+                "s" | "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("Unknown modifier `{m}` in chord `<{inner}>`")),
+            };
+            mods.push(UnresolvedKeyElement::Literal(Matchable::Specific(modifier)));
+        }
+    }
+
+    let code = chord_key(key_str)
+        .ok_or_else(|| format!("Unknown key `{key_str}` in chord `<{inner}>`"))?;
+
+    Ok(UnresolvedKeyBind {
+        mods,
+        code: UnresolvedKeyElement::Literal(ResolvedKeyBind::new_matchable(
+            Matchable::Specific(KeyModifiers::empty()),
+            Matchable::Specific(code),
+        )),
+    })
+}
+
+/// Vim's key names (case insensitive), falling back to a single literal character
+fn chord_key(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(ch));
+    }
+
+    Some(match name.to_ascii_lowercase().as_str() {
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "gt" => KeyCode::Char('>'),
+        "bar" => KeyCode::Char('|'),
+        "bslash" => KeyCode::Char('\\'),
+        "minus" => KeyCode::Char('-'),
+        "del" | "delete" => KeyCode::Delete,
+        "ins" | "insert" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        lower => KeyCode::F(lower.strip_prefix('f')?.parse().ok()?),
+    })
+}
+
 /// Parse a string into segments separated by dashes, but respecting
 /// special constructs like $(), (), %template that may contain dashes
 fn parse_segments(s: &str) -> Result<Vec<String>, String> {
@@ -537,4 +668,58 @@ mod tests {
             _ => panic!("Expected literal '-'"),
         }
     }
+
+    #[test]
+    fn angle_chords_match_dash_syntax() {
+        let table = [
+            ("<C-s>", "ctrl-s"),
+            ("<A-x>", "alt-x"),
+            ("<M-x>", "alt-x"),
+            ("<C-S-a>", "ctrl-shift-a"),
+            ("<c-->", "ctrl--"),
+            ("<F5>", "f5"),
+            ("<S-F12>", "shift-f12"),
+            ("<CR>", "enter"),
+            ("<Esc>", "esc"),
+            ("<BS>", "backspace"),
+            ("<Tab>", "tab"),
+            ("<S-Tab>", "shift-tab"),
+            ("<Space>", "space"),
+            ("<C-Space>", "ctrl-space"),
+            ("<Del>", "delete"),
+            ("<PageDown>", "pagedown"),
+            ("<A-Up>", "alt-up"),
+            ("<lt>", "<"),
+        ];
+
+        for (angle, dash) in table {
+            let angle_bind: UnresolvedKeyBind = angle.parse().unwrap();
+            let dash_bind: UnresolvedKeyBind = dash.parse().unwrap();
+            assert_eq!(angle_bind, dash_bind, "`{angle}` should match `{dash}`");
+        }
+    }
+
+    #[test]
+    fn parses_chained_chords() {
+        let keys = |s: &str| parse_key_sequence(s, "space");
+        let parsed = |s: &str| s.parse::<UnresolvedKeyBind>().unwrap();
+
+        assert_eq!(keys("<C-w>h").unwrap(), [parsed("ctrl-w"), parsed("h")]);
+        assert_eq!(
+            keys("<leader>f%").unwrap(),
+            [parsed("space"), parsed("f"), char_key('%')]
+        );
+
+        // Words without a leading chord keep the dash syntax
+        assert_eq!(keys("ctrl-s").unwrap(), [parsed("ctrl-s")]);
+        assert_eq!(keys("<").unwrap(), [parsed("<")]);
+
+        assert_eq!(
+            keys("<C-w").unwrap_err(),
+            "Unclosed chord `<C-w` in `<C-w`, use `<lt>` for a literal `<`"
+        );
+        assert_eq!(keys("<X-a>").unwrap_err(), "Unknown modifier `X` in chord `<X-a>`");
+        assert_eq!(keys("<C-nope>").unwrap_err(), "Unknown key `nope` in chord `<C-nope>`");
+        assert_eq!(keys("<C->").unwrap_err(), "Missing key in chord `<C->`");
+    }
 }
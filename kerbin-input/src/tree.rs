@@ -33,6 +33,10 @@ pub struct KeyTree<A: Clone, M: Clone> {
     resolved_cache: Option<IndexMap<ResolvedKeyBind, Vec<(usize, usize)>>>,
 
     current_sequence: Vec<ResolvedKeyBind>,
+
+    /// A complete binding that is also the prefix of a longer one, held back until the
+    /// next key shows which was meant (or `flush_pending` gives up waiting)
+    pending: Option<(Vec<ResolvedKeyBind>, A, Option<M>)>,
 }
 
 impl<A: Clone, M: Clone> Default for KeyTree<A, M> {
@@ -45,6 +49,7 @@ impl<A: Clone, M: Clone> Default for KeyTree<A, M> {
             resolved_cache: None,
 
             current_sequence: vec![],
+            pending: None,
         }
     }
 }
@@ -302,6 +307,14 @@ impl<A: Clone, M: Clone> KeyTree<A, M> {
         }
     }
 
+    /// Feeds one key press into the tree.
+    ///
+    /// `check` ranks the metadata of each candidate binding (lower wins) or rejects it.
+    /// When a key completes a binding and also starts a longer one that ranks at least as
+    /// well (`d` next to `dd`), the short binding is held back and `Step` is returned. The
+    /// next key either continues the longer binding, or, if it doesn't, fires the held one
+    /// as `Interrupted` before being handled on its own. Call `flush_pending` to fire it
+    /// once the caller stops waiting.
     pub fn step(
         &mut self,
         resolver: &Resolver,
@@ -310,6 +323,37 @@ impl<A: Clone, M: Clone> KeyTree<A, M> {
         check: impl Fn(Option<&M>) -> Option<u32>,
     ) -> Result<StepResult<A, M>, ParseError> {
         let pressed_key = ResolvedKeyBind::new(key_mods, key_code);
+        let pending = self.pending.take();
+
+        match (self.step_key(resolver, pressed_key.clone(), &check)?, pending) {
+            (StepResult::Reset, Some((sequence, action, meta))) => {
+                let then = self.step_key(resolver, pressed_key, &check)?;
+                Ok(StepResult::Interrupted(sequence, action, meta, Box::new(then)))
+            }
+            (result, _) => Ok(result),
+        }
+    }
+
+    /// Fires the binding held back by an ambiguous key, if any, and resets the tree
+    pub fn flush_pending(&mut self) -> Option<(Vec<ResolvedKeyBind>, A, Option<M>)> {
+        let pending = self.pending.take();
+        if pending.is_some() {
+            self.reset();
+        }
+        pending
+    }
+
+    /// Whether a complete binding is waiting on the next key to be disambiguated
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn step_key(
+        &mut self,
+        resolver: &Resolver,
+        pressed_key: ResolvedKeyBind,
+        check: &impl Fn(Option<&M>) -> Option<u32>,
+    ) -> Result<StepResult<A, M>, ParseError> {
 
         let candidates = [
             pressed_key.clone(),
@@ -342,6 +386,16 @@ impl<A: Clone, M: Clone> KeyTree<A, M> {
 
         let mut best_match: Option<Match<A, M>> = None;
 
+        // The best ranked longer binding this key starts, kept to spot ambiguous keys
+        let mut best_prefix: Option<(u32, PendingResult<A, M>)> = None;
+        let mut consider_prefix = |rank: Option<u32>, result: PendingResult<A, M>| {
+            if let Some(rank) = rank
+                && best_prefix.as_ref().is_none_or(|(best, _)| rank < *best)
+            {
+                best_prefix = Some((rank, result));
+            }
+        };
+
         let mut consider = |rank: u32,
                             candidate_idx: usize,
                             vec_idx: usize,
@@ -424,6 +478,10 @@ impl<A: Clone, M: Clone> KeyTree<A, M> {
                                         usize::MAX,
                                         PendingResult::Step(0, Arc::clone(item), vec_idx),
                                     );
+                                    consider_prefix(
+                                        self.best_rank(item, check),
+                                        PendingResult::Step(0, Arc::clone(item), vec_idx),
+                                    );
                                 }
                             }
                         }
@@ -482,6 +540,14 @@ impl<A: Clone, M: Clone> KeyTree<A, M> {
                                                 vec_idx,
                                             ),
                                         );
+                                        consider_prefix(
+                                            self.best_rank(child, check),
+                                            PendingResult::Step(
+                                                active.0 + 1,
+                                                Arc::clone(child),
+                                                vec_idx,
+                                            ),
+                                        );
                                     }
                                 }
                             }
@@ -491,6 +557,22 @@ impl<A: Clone, M: Clone> KeyTree<A, M> {
             }
         }
 
+        // A complete binding waits if a longer one it starts is at least as specific
+        if let Some(Match {
+            rank,
+            result: PendingResult::Success(action, metadata),
+            ..
+        }) = &best_match
+            && let Some((prefix_rank, PendingResult::Step(depth, node, idx))) = &best_prefix
+            && prefix_rank <= rank
+        {
+            self.current_sequence.push(pressed_key);
+            self.pending = Some((self.current_sequence.clone(), action.clone(), metadata.clone()));
+            self.active_tree = Some((*depth, Arc::clone(node), *idx));
+            self.resolve_current_layer(resolver)?;
+            return Ok(StepResult::Step);
+        }
+
         match best_match {
             Some(Match { result, .. }) => match result {
                 PendingResult::Success(action, metadata) => {
@@ -535,6 +617,21 @@ impl<A: Clone, M: Clone> KeyTree<A, M> {
         self.active_tree = None;
         self.resolved_cache = None;
         self.current_sequence.clear();
+        self.pending = None;
+    }
+
+    /// The best rank `check` gives any binding under `item`, or `None` if it rejects them all
+    fn best_rank(&self, item: &KeyItem<A>, check: &impl Fn(Option<&M>) -> Option<u32>) -> Option<u32> {
+        let meta = |idx: &Option<usize>| idx.and_then(|i| self.metadata.get(i));
+
+        match item {
+            KeyItem::Leaf(actions) => actions.iter().filter_map(|(idx, _)| check(meta(idx))).min(),
+            KeyItem::Tree(_, children, actions, _) => actions
+                .iter()
+                .filter_map(|(idx, _)| check(meta(idx)))
+                .chain(children.iter().filter_map(|child| self.best_rank(child, check)))
+                .min(),
+        }
     }
 
     pub fn current_sequence(&self) -> &[ResolvedKeyBind] {
@@ -542,48 +639,33 @@ impl<A: Clone, M: Clone> KeyTree<A, M> {
     }
 
     pub fn collect_layer_metadata(&self) -> Result<Vec<(ResolvedKeyBind, Option<M>)>, ParseError> {
+        self.collect_layer_metadata_with(|_| true)
+    }
+
+    /// Like `collect_layer_metadata`, but only lists keys that lead to at least one
+    /// binding whose metadata passes `check` (e.g. bindings for the active mode)
+    pub fn collect_layer_metadata_with(
+        &self,
+        check: impl Fn(Option<&M>) -> bool,
+    ) -> Result<Vec<(ResolvedKeyBind, Option<M>)>, ParseError> {
         let mut result = vec![];
 
         if let (Some((_, active, _)), Some(cache)) = (&self.active_tree, &self.resolved_cache) {
             if let KeyItem::Tree(_, children, _, _) = active.as_ref() {
                 for (resolved_key, candidates) in cache {
-                    if let Some(&(_, child_idx)) = candidates.first()
-                        && let Some(child) = children.get(child_idx)
+                    if let Some(child) = candidates
+                        .iter()
+                        .filter_map(|&(_, child_idx)| children.get(child_idx))
+                        .find(|child| self.reachable(child, &check))
                     {
-                        let meta = match child.as_ref() {
-                            KeyItem::Leaf(actions) => actions.last().and_then(|(meta_idx, _)| {
-                                meta_idx.and_then(|i| self.metadata.get(i).cloned())
-                            }),
-                            KeyItem::Tree(_, _, actions, node_meta) => node_meta
-                                .and_then(|i| self.metadata.get(i).cloned())
-                                .or_else(|| {
-                                    actions.last().and_then(|(meta_idx, _)| {
-                                        meta_idx.and_then(|i| self.metadata.get(i).cloned())
-                                    })
-                                }),
-                        };
-
-                        result.push((resolved_key.clone(), meta));
+                        result.push((resolved_key.clone(), self.display_meta(child, &check)));
                     }
                 }
             }
         } else {
             for (resolved_key, items) in &self.tree {
-                if let Some(item) = items.last() {
-                    let meta = match item.as_ref() {
-                        KeyItem::Leaf(actions) => actions.last().and_then(|(meta_idx, _)| {
-                            meta_idx.and_then(|i| self.metadata.get(i).cloned())
-                        }),
-                        KeyItem::Tree(_, _, actions, node_meta) => node_meta
-                            .and_then(|i| self.metadata.get(i).cloned())
-                            .or_else(|| {
-                                actions.last().and_then(|(meta_idx, _)| {
-                                    meta_idx.and_then(|i| self.metadata.get(i).cloned())
-                                })
-                            }),
-                    };
-
-                    result.push((resolved_key.clone(), meta));
+                if let Some(item) = items.iter().rev().find(|item| self.reachable(item, &check)) {
+                    result.push((resolved_key.clone(), self.display_meta(item, &check)));
                 }
             }
         }
@@ -591,6 +673,39 @@ impl<A: Clone, M: Clone> KeyTree<A, M> {
         Ok(result)
     }
 
+    /// Metadata describing `item`: a tree's own metadata, else its last action passing `check`
+    fn display_meta(&self, item: &KeyItem<A>, check: &impl Fn(Option<&M>) -> bool) -> Option<M> {
+        let action_meta = |actions: &[(Option<usize>, A)]| {
+            actions
+                .iter()
+                .rev()
+                .filter_map(|(idx, _)| idx.and_then(|i| self.metadata.get(i)))
+                .find(|meta| check(Some(meta)))
+                .cloned()
+        };
+
+        match item {
+            KeyItem::Leaf(actions) => action_meta(actions),
+            KeyItem::Tree(_, _, actions, node_meta) => node_meta
+                .and_then(|i| self.metadata.get(i).cloned())
+                .or_else(|| action_meta(actions)),
+        }
+    }
+
+    /// Whether any action under `item` (or the node's own metadata) passes `check`
+    fn reachable(&self, item: &KeyItem<A>, check: &impl Fn(Option<&M>) -> bool) -> bool {
+        let meta = |idx: &Option<usize>| idx.and_then(|i| self.metadata.get(i));
+
+        match item {
+            KeyItem::Leaf(actions) => actions.iter().any(|(idx, _)| check(meta(idx))),
+            KeyItem::Tree(_, children, actions, node_meta) => {
+                node_meta.is_some_and(|i| check(self.metadata.get(i)))
+                    || actions.iter().any(|(idx, _)| check(meta(idx)))
+                    || children.iter().any(|child| self.reachable(child, check))
+            }
+        }
+    }
+
     pub fn active_tree(&self) -> Option<&KeyItem<A>> {
         self.active_tree.as_ref().map(|x| x.1.as_ref())
     }
@@ -601,6 +716,9 @@ pub enum StepResult<A: Clone, M> {
     Success(Vec<ResolvedKeyBind>, A, Option<M>),
     Step,
     Reset,
+    /// The key didn't continue the sequence, so the shorter binding held back for it fired.
+    /// The boxed result is the key handled again from the root of the tree.
+    Interrupted(Vec<ResolvedKeyBind>, A, Option<M>, Box<StepResult<A, M>>),
 }
 
 impl<T: Copy> Matchable<T> {
@@ -616,3 +734,109 @@ impl<T: Copy> Matchable<T> {
         self.specific().expect("called unwrap_specific on Matchable::Any")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn tree(resolver: &Resolver) -> KeyTree<&'static str, ()> {
+        let mut tree = KeyTree::default();
+        for (keys, action) in [("d", "delete"), ("d d", "delete_line"), ("d w", "delete_word")] {
+            let sequence = keys.split(' ').map(|k| k.parse().unwrap()).collect();
+            tree.register(resolver, sequence, action, None).unwrap();
+        }
+        tree
+    }
+
+    fn press(
+        tree: &mut KeyTree<&'static str, ()>,
+        resolver: &Resolver,
+        key: char,
+    ) -> StepResult<&'static str, ()> {
+        tree.step(resolver, KeyCode::Char(key), KeyModifiers::NONE, |_| Some(0))
+            .unwrap()
+    }
+
+    fn fired(result: StepResult<&'static str, ()>) -> Option<&'static str> {
+        match result {
+            StepResult::Success(_, action, _) => Some(action),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn short_binding_waits_for_longer_ones() {
+        let templates = HashMap::new();
+        let resolver = Resolver::new(&templates, Arc::new(|_, _| Ok(vec![])));
+        let mut tree = tree(&resolver);
+
+        // `d` is ambiguous, so it waits for the next key
+        assert_eq!(press(&mut tree, &resolver, 'd'), StepResult::Step);
+        assert!(tree.has_pending());
+        assert_eq!(fired(press(&mut tree, &resolver, 'd')), Some("delete_line"));
+
+        press(&mut tree, &resolver, 'd');
+        assert_eq!(fired(press(&mut tree, &resolver, 'w')), Some("delete_word"));
+
+        // Waiting too long fires the short binding
+        press(&mut tree, &resolver, 'd');
+        assert_eq!(tree.flush_pending().map(|(_, action, _)| action), Some("delete"));
+        assert!(tree.active_tree().is_none());
+        assert_eq!(tree.flush_pending(), None);
+
+        // A key that continues nothing fires `d`, then starts over on its own
+        press(&mut tree, &resolver, 'd');
+        match press(&mut tree, &resolver, 'x') {
+            StepResult::Interrupted(_, action, _, then) => {
+                assert_eq!(action, "delete");
+                assert_eq!(*then, StepResult::Reset);
+            }
+            other => panic!("expected the pending binding to fire, got {other:?}"),
+        }
+        assert!(!tree.has_pending());
+    }
+
+    #[test]
+    fn layer_metadata_lists_keys_passing_the_check() {
+        let templates = HashMap::new();
+        let resolver = Resolver::new(&templates, Arc::new(|_, _| Ok(vec![])));
+
+        // Each binding's metadata is the mode it's bound in
+        let mut tree: KeyTree<&'static str, char> = KeyTree::default();
+        for (keys, action, mode) in [
+            ("g g", "goto_top", 'n'),
+            ("g v", "reselect", 'v'),
+            ("x", "select_line", 'v'),
+            ("u", "undo", 'n'),
+        ] {
+            let sequence = keys.split(' ').map(|k| k.parse().unwrap()).collect();
+            tree.register(&resolver, sequence, action, Some(mode)).unwrap();
+        }
+
+        let listed = |tree: &KeyTree<&'static str, char>, mode: char| {
+            let mut keys: Vec<(String, Option<char>)> = tree
+                .collect_layer_metadata_with(|meta| meta == Some(&mode))
+                .unwrap()
+                .into_iter()
+                .map(|(key, meta)| (key.to_string(), meta))
+                .collect();
+            keys.sort();
+            keys
+        };
+
+        // `g` leads to bindings in both modes, `x` and `u` only to one. A prefix has no
+        // metadata of its own to show
+        assert_eq!(listed(&tree, 'n'), [("g".to_string(), None), ("u".to_string(), Some('n'))]);
+        assert_eq!(listed(&tree, 'v'), [("g".to_string(), None), ("x".to_string(), Some('v'))]);
+        assert_eq!(tree.collect_layer_metadata().unwrap().len(), 3);
+
+        // Inside a sequence, only its continuations are listed
+        tree.step(&resolver, KeyCode::Char('g'), KeyModifiers::NONE, |_| Some(0))
+            .unwrap();
+        assert_eq!(listed(&tree, 'n'), [("g".to_string(), Some('n'))]);
+        assert_eq!(listed(&tree, 'v'), [("v".to_string(), Some('v'))]);
+        assert!(listed(&tree, 'i').is_empty());
+    }
+}
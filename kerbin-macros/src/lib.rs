@@ -4,12 +4,13 @@ use darling::{
 };
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
-use quote::quote;
+use quote::{quote, quote_spanned};
 use syn::{
     DeriveInput, Expr, Ident, LitStr, Path, Token, Type, bracketed,
     parse::{Parse, ParseStream},
     parse_macro_input,
     punctuated::Punctuated,
+    spanned::Spanned,
 };
 
 #[proc_macro_derive(State)]
@@ -34,11 +35,191 @@ pub fn derive_state(input: TokenStream) -> TokenStream {
     TokenStream::from(expanded)
 }
 
+#[derive(FromDeriveInput, Debug)]
+#[darling(attributes(configurable), supports(struct_named))]
+struct ConfigurableInfo {
+    ident: Ident,
+    data: Data<(), ConfigurableField>,
+    #[darling(default)]
+    name: Option<String>,
+}
+
+#[derive(FromField, Debug)]
+#[darling(attributes(configurable))]
+struct ConfigurableField {
+    ident: Option<Ident>,
+    ty: Type,
+    #[darling(default)]
+    skip: bool,
+}
+
+/// Implements `ConfigurableState`, exposing each field and its type to `set` and `get` by name.
+/// Field types must implement `FromStr` and `Display`; mark others `#[configurable(skip)]`.
+/// `#[configurable(name = "...")]` sets the state's name, defaulting to the snake_case
+/// struct name.
+#[proc_macro_derive(ConfigurableState, attributes(configurable))]
+pub fn derive_configurable_state(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    let info = ConfigurableInfo::from_derive_input(&ast).unwrap();
+
+    let ident = &info.ident;
+    let config_name = info
+        .name
+        .clone()
+        .unwrap_or_else(|| to_snake_case(&ident.to_string()));
+
+    let fields: Vec<_> = info
+        .data
+        .take_struct()
+        .expect("ConfigurableState can only be derived on structs")
+        .fields
+        .into_iter()
+        .filter(|f| !f.skip)
+        .collect();
+
+    let names: Vec<String> = fields
+        .iter()
+        .map(|f| f.ident.as_ref().expect("named field").to_string())
+        .collect();
+
+    let types: Vec<String> = fields
+        .iter()
+        .map(|f| {
+            let ty = &f.ty;
+            quote!(#ty).to_string().replace(' ', "")
+        })
+        .collect();
+
+    let set_arms = fields.iter().zip(&names).map(|(f, name)| {
+        let id = &f.ident;
+        let ty = &f.ty;
+        quote! {
+            #name => {
+                self.#id = value
+                    .parse::<#ty>()
+                    .map_err(|e| format!("Invalid value `{value}` for `{}`: {e}", #name))?;
+                Ok(())
+            }
+        }
+    });
+
+    let get_arms = fields.iter().zip(&names).map(|(f, name)| {
+        let id = &f.ident;
+        quote! { #name => Some(self.#id.to_string()) }
+    });
+
+    let expanded = quote! {
+        impl ConfigurableState for #ident {
+            fn config_name() -> &'static str {
+                #config_name
+            }
+
+            fn field_names() -> &'static [&'static str] {
+                &[#(#names),*]
+            }
+
+            fn field_types() -> &'static [&'static str] {
+                &[#(#types),*]
+            }
+
+            fn set_field(&mut self, name: &str, value: &str) -> Result<(), String> {
+                match name {
+                    #(#set_arms)*
+                    _ => Err(format!("`{}` has no field `{name}`", #config_name)),
+                }
+            }
+
+            fn get_field(&self, name: &str) -> Option<String> {
+                match name {
+                    #(#get_arms,)*
+                    _ => None,
+                }
+            }
+        }
+    };
+
+    expanded.into()
+}
+
+#[derive(FromDeriveInput, Debug)]
+#[darling(supports(struct_named))]
+struct SystemParamInfo {
+    ident: Ident,
+    generics: syn::Generics,
+    data: Data<(), SystemParamField>,
+}
+
+#[derive(FromField, Debug)]
+struct SystemParamField {
+    ident: Option<Ident>,
+    ty: Type,
+}
+
+/// Implements `SystemParam` for a struct whose fields are all `SystemParam`s, so a system
+/// can take the bundle as one argument. The bundle locks everything its fields lock, and
+/// `get` gives the bundle itself, so each field is still locked with its own `get`.
+#[proc_macro_derive(SystemParam)]
+pub fn derive_system_param(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    let info = match SystemParamInfo::from_derive_input(&ast) {
+        Ok(info) => info,
+        Err(e) => return e.write_errors().into(),
+    };
+
+    let ident = &info.ident;
+    let (impl_generics, ty_generics, where_clause) = info.generics.split_for_impl();
+
+    let fields = info
+        .data
+        .take_struct()
+        .expect("SystemParam can only be derived on structs")
+        .fields;
+    let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+
+    // Spanned on the field so a field that isn't a param is reported there, and only once
+    let retrieves = fields.iter().map(|f| {
+        let (name, ty) = (&f.ident, &f.ty);
+        quote_spanned! {ty.span()=> #name: <#ty as SystemParam>::retrieve(resources) }
+    });
+    let descs = types.iter().map(|ty| {
+        quote_spanned! {ty.span()=> <#ty as SystemParam>::desc() }
+    });
+
+    let expanded = quote! {
+        #[diagnostic::do_not_recommend]
+        #[async_trait::async_trait]
+        impl #impl_generics SystemParam for #ident #ty_generics #where_clause {
+            type Item<'new> = Self;
+            fn retrieve(resources: &StateStorage) -> Self::Item<'_> {
+                Self {
+                    #(#retrieves,)*
+                }
+            }
+
+            type Inner<'a> = &'a Self where Self: 'a;
+            async fn get(&self) -> Self::Inner<'_> {
+                self
+            }
+
+            fn desc() -> Vec<SystemParamDesc> {
+                let mut desc = vec![];
+                #(desc.extend(#descs);)*
+                desc
+            }
+        }
+    };
+
+    expanded.into()
+}
+
 #[derive(FromDeriveInput, Debug)]
 #[darling(attributes(command), forward_attrs(doc))]
 struct CommandInfo {
     ident: Ident,
     data: Data<CommandVariant, CommandField>,
+    /// Category of every variant that doesn't set its own
+    #[darling(default)]
+    category: Option<String>,
 }
 
 #[derive(FromVariant, Debug)]
@@ -52,6 +233,11 @@ struct CommandVariant {
     names: Vec<String>,
     #[darling(default)]
     parser: Option<Path>,
+    /// Every field is a flag, also settable as `name=value` in any order
+    #[darling(default)]
+    keyword: bool,
+    #[darling(default)]
+    category: Option<String>,
     attrs: Vec<syn::Attribute>,
 }
 
@@ -68,6 +254,10 @@ struct CommandField {
     flag: bool,
     #[darling(default)]
     ignore: bool,
+    #[darling(default)]
+    complete: Option<String>,
+    #[darling(default)]
+    range: Option<String>,
 }
 
 impl CommandVariant {
@@ -129,6 +319,83 @@ impl CommandField {
         format!("--{}", self.field_base_name())
     }
 
+    /// The name this field is listed under in `CommandInfo::args`
+    fn arg_name(&self) -> String {
+        if self.flag {
+            self.flag_cli_name()
+        } else {
+            self.field_base_name()
+        }
+    }
+
+    /// Builds the `ArgCompleter` named by `#[command(complete = "...")]`
+    fn completer(&self) -> Option<TokenStream2> {
+        let complete = self.complete.as_ref()?;
+        Some(match complete.as_str() {
+            "path" => quote! { ArgCompleter::Path },
+            "buffer" => quote! { ArgCompleter::Buffer },
+            func => {
+                let func: Path = syn::parse_str(func).unwrap_or_else(|_| {
+                    panic!("`complete` must be \"path\", \"buffer\", or a function path, got `{func}`")
+                });
+                quote! { ArgCompleter::Custom(#func) }
+            }
+        })
+    }
+
+    /// Builds the `ArgRange` from `#[command(range = "...")]`, using Rust range syntax
+    fn range(&self) -> Option<TokenStream2> {
+        let range = self.range.as_ref()?;
+        let (start, end) = range
+            .split_once("..")
+            .unwrap_or_else(|| panic!("`range` must look like `1..=10`, `1..`, or `..10`, got `{range}`"));
+
+        let bound = |s: &str| -> Option<i128> {
+            let s = s.trim();
+            (!s.is_empty()).then(|| {
+                s.parse()
+                    .unwrap_or_else(|_| panic!("`range` bound `{s}` is not an integer"))
+            })
+        };
+
+        let min = bound(start);
+        let max = match end.strip_prefix('=') {
+            Some(end) => bound(end),
+            None => bound(end).map(|end| end - 1),
+        };
+
+        let to_tokens = |b: Option<i128>| match b {
+            Some(b) => quote! { Some(#b) },
+            None => quote! { None },
+        };
+        let (min, max) = (to_tokens(min), to_tokens(max));
+        Some(quote! { ArgRange { min: #min, max: #max } })
+    }
+
+    /// Rejects a parsed value outside the field's `range`
+    fn range_check(&self, var: &Ident) -> TokenStream2 {
+        let name = self.arg_name();
+        let Some(range) = self.range() else {
+            return quote! {};
+        };
+
+        if get_option_inner_type(&self.ty).is_some() {
+            quote! {
+                if let Some(_v) = #var
+                    && let Err(_e) = (#range).check(_v as i128)
+                {
+                    return Some(Err(ArgError::new(#name, _e)));
+                }
+            }
+        } else {
+            quote! {
+                if let Err(_e) = (#range).check(#var as i128) {
+                    return Some(Err(ArgError::new(#name, _e)));
+                }
+            }
+        }
+    }
+
     fn field_assignment(&self, style: Style, var: &Ident) -> TokenStream2 {
         match style {
             Style::Struct => {
@@ -151,17 +418,23 @@ enum FieldSource<'a> {
     Positional(usize),
 }
 
-fn emit_field_parser(var: &Ident, ty: &Type, source: FieldSource<'_>, ignore: bool) -> TokenStream2 {
+fn emit_field_parser(
+    var: &Ident,
+    ty: &Type,
+    name: &str,
+    source: FieldSource<'_>,
+    ignore: bool,
+) -> TokenStream2 {
     if ignore {
-        return emit_field_parser_ignore(var, ty, source);
+        return emit_field_parser_ignore(var, ty, name, source);
     }
     match source {
         FieldSource::Flag(flag_name) => {
             if is_bool_type(ty) {
-                quote! { let #var = _state.flags.contains_key(#flag_name); }
+                quote! { let #var = _state.switch(#flag_name); }
             } else if get_option_inner_type(ty).map(is_bool_type).unwrap_or(false) {
                 quote! {
-                    let #var = if _state.flags.contains_key(#flag_name) { Some(true) } else { None };
+                    let #var = _state.flags.contains_key(#flag_name).then(|| _state.switch(#flag_name));
                 }
             } else if let Some(inner) = get_option_inner_type(ty)
                 && get_vec_inner_type(inner)
@@ -208,7 +481,7 @@ fn emit_field_parser(var: &Ident, ty: &Type, source: FieldSource<'_>, ignore: bo
                     let #var = match _state.flags.get(#flag_name) {
                         Some(Some(Token::Word(_v))) => Some(match _v.parse::<#inner>() {
                             Ok(_t) => _t,
-                            Err(_e) => return Some(Err(_e.to_string())),
+                            Err(_e) => return Some(Err(ArgError::new(#name, _e))),
                         }),
                         _ => None,
                     };
@@ -218,7 +491,7 @@ fn emit_field_parser(var: &Ident, ty: &Type, source: FieldSource<'_>, ignore: bo
                     let #var = match _state.flags.get(#flag_name) {
                         Some(Some(Token::Word(_v))) => match _v.parse::<#ty>() {
                             Ok(_t) => _t,
-                            Err(_e) => return Some(Err(_e.to_string())),
+                            Err(_e) => return Some(Err(ArgError::new(#name, _e))),
                         },
                         _ => return None,
                     };
@@ -266,7 +539,7 @@ fn emit_field_parser(var: &Ident, ty: &Type, source: FieldSource<'_>, ignore: bo
                     let #var = if let Some(Token::Word(s)) = _state.positional.get(#i) {
                         Some(match s.parse::<#inner>() {
                             Ok(t) => t,
-                            Err(e) => return Some(Err(e.to_string())),
+                            Err(e) => return Some(Err(ArgError::new(#name, e))),
                         })
                     } else {
                         None
@@ -277,7 +550,7 @@ fn emit_field_parser(var: &Ident, ty: &Type, source: FieldSource<'_>, ignore: bo
                     let #var = match _state.positional.get(#i) {
                         Some(Token::Word(s)) => match s.parse::<#ty>() {
                             Ok(t) => t,
-                            Err(e) => return Some(Err(e.to_string())),
+                            Err(e) => return Some(Err(ArgError::new(#name, e))),
                         },
                         _ => return None,
                     };
@@ -292,16 +565,21 @@ fn emit_field_parser(var: &Ident, ty: &Type, source: FieldSource<'_>, ignore: bo
 /// The token at this slot is kept raw (unexpanded). For `Token`/`Option<Token>`/`Vec<Token>` types
 /// the token is used directly. For string-like types it is serialized back with `token_to_string`
 /// so the caller receives the original source form (e.g. `%var`, `$(cmd)`).
-fn emit_field_parser_ignore(var: &Ident, ty: &Type, source: FieldSource<'_>) -> TokenStream2 {
+fn emit_field_parser_ignore(
+    var: &Ident,
+    ty: &Type,
+    name: &str,
+    source: FieldSource<'_>,
+) -> TokenStream2 {
     match source {
         FieldSource::Flag(flag_name) => {
             if is_bool_type(ty) || get_option_inner_type(ty).map(is_bool_type).unwrap_or(false) {
-                // bool/Option<bool> flag: presence-only — ignore has no effect on these.
+                // bool/Option<bool> flag: a switch — ignore has no effect on these.
                 if is_bool_type(ty) {
-                    return quote! { let #var = _state.flags.contains_key(#flag_name); };
+                    return quote! { let #var = _state.switch(#flag_name); };
                 } else {
                     return quote! {
-                        let #var = if _state.flags.contains_key(#flag_name) { Some(true) } else { None };
+                        let #var = _state.flags.contains_key(#flag_name).then(|| _state.switch(#flag_name));
                     };
                 }
             }
@@ -348,7 +626,7 @@ fn emit_field_parser_ignore(var: &Ident, ty: &Type, source: FieldSource<'_>) ->
                     let #var = match _state.flags.get(#flag_name) {
                         Some(Some(_t)) => Some(match ::kerbin_core::token_to_string(_t).parse::<#inner>() {
                             Ok(_v) => _v,
-                            Err(_e) => return Some(Err(_e.to_string())),
+                            Err(_e) => return Some(Err(ArgError::new(#name, _e))),
                         }),
                         _ => None,
                     };
@@ -379,7 +657,7 @@ fn emit_field_parser_ignore(var: &Ident, ty: &Type, source: FieldSource<'_>) ->
                 let #var = match _state.flags.get(#flag_name) {
                     Some(Some(_t)) => match ::kerbin_core::token_to_string(_t).parse::<#ty>() {
                         Ok(_v) => _v,
-                        Err(_e) => return Some(Err(_e.to_string())),
+                        Err(_e) => return Some(Err(ArgError::new(#name, _e))),
                     },
                     _ => return None,
                 };
@@ -426,7 +704,7 @@ fn emit_field_parser_ignore(var: &Ident, ty: &Type, source: FieldSource<'_>) ->
                     let #var = if let Some(t) = _state.positional.get(#i) {
                         Some(match ::kerbin_core::token_to_string(t).parse::<#inner>() {
                             Ok(v) => v,
-                            Err(e) => return Some(Err(e.to_string())),
+                            Err(e) => return Some(Err(ArgError::new(#name, e))),
                         })
                     } else {
                         None
@@ -458,7 +736,7 @@ fn emit_field_parser_ignore(var: &Ident, ty: &Type, source: FieldSource<'_>) ->
                 let #var = match _state.positional.get(#i) {
                     Some(t) => match ::kerbin_core::token_to_string(t).parse::<#ty>() {
                         Ok(v) => v,
-                        Err(e) => return Some(Err(e.to_string())),
+                        Err(e) => return Some(Err(ArgError::new(#name, e))),
                     },
                     None => return None,
                 };
@@ -472,11 +750,22 @@ pub fn command_derive(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as syn::DeriveInput);
     let info = CommandInfo::from_derive_input(&ast).unwrap();
 
-    let variants = match info.data {
+    let mut variants = match info.data {
         Data::Enum(variants) => variants,
         _ => panic!("Command can only be derived on enums."),
     };
 
+    for variant in variants.iter_mut().filter(|v| v.keyword) {
+        assert!(
+            variant.fields.style == Style::Struct,
+            "keyword command `{}` must have named fields.",
+            variant.ident
+        );
+        for field in variant.fields.fields.iter_mut() {
+            field.flag = true;
+        }
+    }
+
     let info_matches: Vec<_> = variants
         .iter()
         .map(|v| {
@@ -513,12 +802,50 @@ pub fn command_derive(input: TokenStream) -> TokenStream {
                     if f.ignore { Some(idx) } else { None }
                 })
                 .collect();
+            let mut pos_idx = 0usize;
+            let completers: Vec<TokenStream2> = v
+                .fields
+                .iter()
+                .filter_map(|f| {
+                    if f.flag {
+                        return None;
+                    }
+                    let idx = pos_idx;
+                    pos_idx += 1;
+                    let completer = f.completer()?;
+                    Some(quote! { (#idx, #completer) })
+                })
+                .collect();
+            let ranges: Vec<TokenStream2> = v
+                .fields
+                .iter()
+                .filter_map(|f| {
+                    let range = f.range()?;
+                    let name = if f.flag {
+                        f.flag_cli_name()
+                    } else {
+                        f.field_base_name()
+                    };
+                    Some(quote! { (#name.to_string(), #range) })
+                })
+                .collect();
             let ignore_flag_names: Vec<String> = v
                 .fields
                 .iter()
                 .filter(|f| f.flag && f.ignore)
                 .map(|f| f.flag_cli_name())
                 .collect();
+            let keyword = v.keyword;
+            let category = match v.category.as_ref().or(info.category.as_ref()) {
+                Some(category) => quote! { #category.to_string() },
+                None => quote! { ::kerbin_core::UNCATEGORIZED.to_string() },
+            };
+            let num_pos = v.fields.iter().filter(|f| !f.flag).count();
+            let num_req = v
+                .fields
+                .iter()
+                .filter(|f| !f.flag && get_option_inner_type(&f.ty).is_none())
+                .count();
 
             quote! {
                 CommandInfo {
@@ -527,6 +854,11 @@ pub fn command_derive(input: TokenStream) -> TokenStream {
                     desc: #desc,
                     ignore_positional: vec![#(#ignore_positional),*],
                     ignore_flags: vec![#(#ignore_flag_names.to_string()),*],
+                    completers: vec![#(#completers),*],
+                    ranges: vec![#(#ranges),*],
+                    arity: (#num_req, #num_pos),
+                    keyword: #keyword,
+                    category: #category,
                 }
             }
         })
@@ -541,14 +873,19 @@ pub fn command_derive(input: TokenStream) -> TokenStream {
             // Delegate entirely to a custom parser if provided.
             if let Some(parser_func) = &variant.parser {
                 return quote! {
-                    #(#names)|* => Some(#parser_func(val))
+                    #(#names)|* => Some(#parser_func(val).map_err(ArgError::from))
                 };
             }
 
             variant.validate_fields();
 
+            let parse = if variant.keyword {
+                quote! { parse_keywords }
+            } else {
+                quote! { parse }
+            };
             let prescan = quote! {
-                let _state = match ::kerbin_core::CommandState::parse(val) {
+                let _state = match ::kerbin_core::CommandState::#parse(val) {
                     Some(s) => s,
                     None => return None,
                 };
@@ -591,7 +928,10 @@ pub fn command_derive(input: TokenStream) -> TokenStream {
                         FieldSource::Positional(idx)
                     };
 
-                    let parser = emit_field_parser(&var, ty, source, field.ignore);
+                    let parser =
+                        emit_field_parser(&var, ty, &field.arg_name(), source, field.ignore);
+                    let check = field.range_check(&var);
+                    let parser = quote! { #parser #check };
                     let assignment = field.field_assignment(variant.fields.style, &var);
                     (parser, assignment)
                 })
@@ -626,7 +966,7 @@ pub fn command_derive(input: TokenStream) -> TokenStream {
         where
             #enum_ident: Command<__S>,
         {
-            fn from_str(val: &[Token]) -> Option<Result<Box<dyn Command<__S>>, String>> {
+            fn from_str(val: &[Token]) -> Option<Result<Box<dyn Command<__S>>, ArgError>> {
                 match val.get(0) {
                     Some(Token::Word(s)) => match s.as_str() {
                         #(#match_arms),*
@@ -0,0 +1,54 @@
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use futures::future::BoxFuture;
+use kerbin_state_machine::{
+    NamedSystem, StateStorage, SystemParamDesc, group_concurrent_system_indices, system::System,
+};
+
+/// Stands in for a plugin system, only reporting the params it was given
+struct ParamsOnly(Vec<SystemParamDesc>);
+
+impl System for ParamsOnly {
+    fn call<'a>(&'a self, _storage: &'a StateStorage) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+
+    fn params(&self) -> Vec<SystemParamDesc> {
+        self.0.clone()
+    }
+}
+
+fn param(type_name: String, write: bool, reserved: bool) -> SystemParamDesc {
+    SystemParamDesc {
+        type_name,
+        write,
+        reserved,
+    }
+}
+
+/// A hook's worth of systems over 12 states, mixing reads, writes, two-param systems and
+/// the occasional reserved one
+fn mixed_systems(count: usize) -> Vec<NamedSystem> {
+    (0..count)
+        .map(|i| {
+            let mut params = vec![param(format!("Type{}", i % 7), i % 3 == 0, i % 17 == 16)];
+            if i % 4 == 0 {
+                params.push(param(format!("Type{}", 7 + i % 5), i % 2 == 0, false));
+            }
+
+            NamedSystem {
+                id: "",
+                inner: Box::new(ParamsOnly(params)),
+            }
+        })
+        .collect()
+}
+
+fn grouping(c: &mut Criterion) {
+    let systems = mixed_systems(100);
+    c.bench_function("group 100 mixed systems", |b| {
+        b.iter(|| group_concurrent_system_indices(black_box(&systems)))
+    });
+}
+
+criterion_group!(benches, grouping);
+criterion_main!(benches);
@@ -8,37 +8,92 @@ pub mod storage;
 pub mod system;
 
 pub use storage::*;
-pub use system::param::{SystemParam, SystemParamDesc, res::Res, res_mut::ResMut};
-
+pub use system::param::{
+    SystemParam, SystemParamDesc, res::Res, res_mut::ResMut, state_param::StateParam,
+};
+
+/// Awaits `.get()` on each system param, shadowing it with the result.
+///
+/// Every param is awaited the same way, so this works for `Res`, `ResMut`, and any
+/// async param defined in other crates. Each entry takes one of these forms:
+///
+/// - `name` binds the result immutably
+/// - `mut name` binds it mutably
+/// - `Some(name)` / `Some(mut name)` unwraps an `Option` result, returning from the
+///   system early when it's `None` (e.g. an `EventData` with no event this frame)
+///
+/// ```
+/// # use kerbin_state_machine::get;
+/// struct Param(Option<u32>);
+/// impl Param {
+///     async fn get(&self) -> Option<u32> {
+///         self.0
+///     }
+/// }
+///
+/// async fn system(event: Param, count: Param, hits: &mut Vec<u32>) {
+///     get!(Some(event), mut count);
+///     *count.get_or_insert(0) += event;
+///     hits.push(count.unwrap());
+/// }
+///
+/// let mut hits = vec![];
+/// futures::executor::block_on(async {
+///     system(Param(Some(2)), Param(Some(1)), &mut hits).await;
+///     // No event, so the system returns before touching `hits`
+///     system(Param(None), Param(Some(1)), &mut hits).await;
+/// });
+/// assert_eq!(hits, vec![3]);
+/// ```
+///
+/// `Some(mut name)` gives a mutable binding to the unwrapped value:
+///
+/// ```
+/// # use kerbin_state_machine::get;
+/// # struct Param(Option<u32>);
+/// # impl Param {
+/// #     async fn get(&self) -> Option<u32> {
+/// #         self.0
+/// #     }
+/// # }
+/// async fn double(event: Param, out: &mut u32) {
+///     get!(Some(mut event));
+///     event *= 2;
+///     *out = event;
+/// }
+///
+/// let mut out = 0;
+/// futures::executor::block_on(double(Param(Some(4)), &mut out));
+/// assert_eq!(out, 8);
+/// ```
 #[macro_export]
-/// Automatically calls the get method on all systems provided as arguments
 macro_rules! get {
-    (@inner $name:ident $(, $($t:tt)+)?) => {
-        let $name = $name.get().await;
-        get!(@inner $($($t)+)?)
-    };
     (@inner mut $name:ident $(, $($t:tt)+)?) => {
         let mut $name = $name.get().await;
-        get!(@inner $($($t)*)?)
+        $crate::get!(@inner $($($t)+)?)
     };
     (@inner Some($name:ident) $(, $($t:tt)+)?) => {
         let Some($name) = $name.get().await else {
             return;
         };
-        get!(@inner $($($t)+)?)
+        $crate::get!(@inner $($($t)+)?)
     };
     (@inner Some(mut $name:ident) $(, $($t:tt)+)?) => {
         let Some(mut $name) = $name.get().await else {
             return;
         };
-        get!(@inner $($($t)+)?)
+        $crate::get!(@inner $($($t)+)?)
+    };
+    (@inner $name:ident $(, $($t:tt)+)?) => {
+        let $name = $name.get().await;
+        $crate::get!(@inner $($($t)+)?)
     };
     (@inner $($t:tt)+) => {
         compile_error!("Expected comma-separated list of (mut item), (item), Some(item), or Some(mut item), but got an error while parsing. Make sure you don't have a trailing `,`");
     };
     (@inner) => {};
     ($($t:tt)*) => {
-        get!(@inner $($t)*)
+        $crate::get!(@inner $($t)*)
     };
 }
 
@@ -113,23 +168,27 @@ impl HookInfo {
         }
     }
 
+    /// The rank of this pattern if it matches the hook `path`, compared component by
+    /// component. The lengths have to agree, except that a trailing `*` also takes any
+    /// components past it, so `lsp::*` matches `lsp::hover` and `lsp::hover::done` but
+    /// not `lsp`. A `*` anywhere else matches exactly one component.
     pub fn matches(&self, path: &[HookPathComponent]) -> Option<i8> {
-        let mut matches = true;
+        let open_ended = matches!(self.path.last(), Some(HookPathComponent::Wildcard));
+        if path.len() != self.path.len() && !(open_ended && path.len() > self.path.len()) {
+            return None;
+        }
 
-        for (path, component) in path.iter().zip(self.path.iter()) {
-            matches = match (component, path) {
+        let matches = path
+            .iter()
+            .zip(self.path.iter())
+            .all(|(path, component)| match (component, path) {
                 (HookPathComponent::Wildcard, _) => true,
                 (HookPathComponent::Path(s), HookPathComponent::Path(p)) => p == s,
                 (HookPathComponent::OneOf(options), HookPathComponent::Path(p)) => {
                     options.contains(p)
                 }
                 (_, _) => true,
-            };
-
-            if !matches {
-                break;
-            }
-        }
+            });
 
         if matches { Some(self.rank) } else { None }
     }
@@ -157,19 +216,23 @@ impl State {
     pub fn state<T: StateName + StaticState + 'static>(&mut self, state: T) -> &mut Self {
         self.storage
             .states
-            .insert(T::static_name(), Box::new(Arc::new(RwLock::new(state))));
+            .insert(T::static_name(), Arc::new(Arc::new(RwLock::new(state))));
         self
     }
 
+    /// Registers a state like `state`, also exposing it to `StateStorage::debug_state`
+    pub fn debug_state<T: DebugState>(&mut self, state: T) -> &mut Self {
+        self.storage.register_debug::<T>();
+        self.state(state)
+    }
+
     pub async fn lock_state<'a, S: StateName + StaticState>(&'a self) -> RwLockWriteGuard<'a, S> {
-        self.storage
-            .states
-            .get(&S::static_name())
-            .expect("Type should be in state")
-            .downcast::<S>()
-            .expect("Stored type should be downcastable")
-            .write()
-            .await
+        self.storage.lock_state::<S>().await
+    }
+
+    /// See `StateStorage::with_override`
+    pub async fn with_override<S: StateName + StaticState>(&self, value: S) -> StateOverride<S> {
+        self.storage.with_override(value).await
     }
 
     pub fn set_hook<H: Hook, I, D, S: System + Send + Sync + 'static>(
@@ -329,80 +392,86 @@ impl<'a, H: Hook> HookBuilder<'a, H> {
     }
 }
 
+/// The states a system locks, collected once per grouping instead of on every comparison
+struct SystemAccess {
+    reserved: bool,
+    reads: Vec<String>,
+    writes: Vec<String>,
+}
+
+impl SystemAccess {
+    fn new(system: &NamedSystem) -> Self {
+        let params = system.inner.params();
+        let mut access = Self {
+            reserved: params.iter().any(|p| p.reserved),
+            reads: vec![],
+            writes: vec![],
+        };
+
+        for param in params {
+            if param.write {
+                access.writes.push(param.type_name);
+            } else {
+                access.reads.push(param.type_name);
+            }
+        }
+
+        access
+    }
+}
+
+/// Splits `systems` into groups that can run concurrently, in order. Each group takes
+/// every remaining system that doesn't conflict with the ones already in it, so a system
+/// only waits on the systems before it that it conflicts with. Systems with a reserved
+/// param run alone.
 pub fn group_concurrent_system_indices(systems: &[NamedSystem]) -> Vec<Vec<usize>> {
+    let access: Vec<SystemAccess> = systems.iter().map(SystemAccess::new).collect();
     let mut remaining_indices: Vec<usize> = (0..systems.len()).collect();
     let mut groups: Vec<Vec<usize>> = Vec::new();
 
+    let mut used_types = HashSet::new();
+    let mut write_types = HashSet::new();
+
     while !remaining_indices.is_empty() {
         let mut current_group = Vec::new();
-        let mut used_types = HashSet::new();
-        let mut write_types = HashSet::new();
-        let mut indices_to_remove = Vec::new();
+        used_types.clear();
+        write_types.clear();
 
-        for (pos, &system_idx) in remaining_indices.iter().enumerate() {
-            let system_params = systems[system_idx].inner.params();
+        for &system_idx in &remaining_indices {
+            let system = &access[system_idx];
 
-            let has_reserved = system_params.iter().any(|p| p.reserved);
-
-            if has_reserved {
+            if system.reserved {
                 if current_group.is_empty() {
                     current_group.push(system_idx);
-                    indices_to_remove.push(pos);
                     break;
-                } else {
-                    continue;
-                }
-            }
-
-            if !current_group.is_empty() {
-                let group_has_reserved = current_group
-                    .iter()
-                    .any(|&idx| systems[idx].inner.params().iter().any(|p| p.reserved));
-                if group_has_reserved {
-                    continue;
                 }
+                continue;
             }
 
-            let mut can_add = true;
-            let mut conflicting_types = Vec::new();
-
-            for param in &system_params {
-                if param.write && used_types.contains(&param.type_name) {
-                    can_add = false;
-                    if write_types.contains(&param.type_name) {
-                        conflicting_types.push((&param.type_name, "write", "existing write"));
-                    } else {
-                        conflicting_types.push((&param.type_name, "write", "existing read"));
-                    }
-                    break;
-                } else if write_types.contains(&param.type_name) {
-                    can_add = false;
-                    conflicting_types.push((&param.type_name, "read", "existing write"));
-                    break;
-                }
+            let conflicts = system.writes.iter().any(|ty| used_types.contains(ty.as_str()))
+                || system.reads.iter().any(|ty| write_types.contains(ty.as_str()));
+            if conflicts {
+                continue;
             }
 
-            if can_add {
-                current_group.push(system_idx);
-                indices_to_remove.push(pos);
-
-                for param in &system_params {
-                    used_types.insert(param.type_name.clone());
-                    if param.write {
-                        write_types.insert(param.type_name.clone());
-                    }
-                }
-            }
+            current_group.push(system_idx);
+            used_types.extend(system.reads.iter().map(String::as_str));
+            used_types.extend(system.writes.iter().map(String::as_str));
+            write_types.extend(system.writes.iter().map(String::as_str));
         }
 
-        if !current_group.is_empty() {
-            groups.push(current_group);
-        }
+        // Groups keep the order of `remaining_indices`, so one pass drops them all
+        let mut grouped = current_group.iter().peekable();
+        remaining_indices.retain(|idx| {
+            if grouped.peek() == Some(&idx) {
+                grouped.next();
+                false
+            } else {
+                true
+            }
+        });
 
-        indices_to_remove.sort_by(|a, b| b.cmp(a));
-        for pos in indices_to_remove {
-            remaining_indices.remove(pos);
-        }
+        groups.push(current_group);
     }
 
     groups
@@ -503,6 +572,289 @@ mod tests {
             .collect()
     }
 
+    macro_rules! test_state {
+        ($name:ident) => {
+            impl StaticState for $name {
+                fn static_name() -> String {
+                    format!("tests::{}", stringify!($name))
+                }
+            }
+
+            impl StateName for $name {
+                fn name(&self) -> String {
+                    <Self as StaticState>::static_name()
+                }
+            }
+        };
+    }
+
+    #[derive(Debug)]
+    struct Counter(u32);
+    test_state!(Counter);
+    impl DebugState for Counter {}
+
+    struct Opaque;
+    test_state!(Opaque);
+
+    #[tokio::test]
+    async fn test_state_names_and_debug() {
+        let mut state = State::new();
+        state.state(Opaque).debug_state(Counter(3));
+
+        assert_eq!(
+            state.storage.state_names(),
+            vec!["tests::Counter", "tests::Opaque"]
+        );
+        assert_eq!(
+            state.storage.debug_state("tests::Counter").await.as_deref(),
+            Some("Counter(\n    3,\n)")
+        );
+        assert_eq!(state.storage.debug_state("tests::Opaque").await, None);
+        assert_eq!(state.storage.debug_state("tests::Missing").await, None);
+
+        // Formatting only read the state, so it's still intact and unlocked
+        assert_eq!(state.lock_state::<Counter>().await.0, 3);
+    }
+
+    #[tokio::test]
+    async fn test_override_restores_on_drop() {
+        let mut state = State::new();
+        state.state(Counter(1));
+
+        let outer = state.with_override(Counter(2)).await;
+        assert_eq!(state.lock_state::<Counter>().await.0, 2);
+        {
+            let _inner = state.with_override(Counter(3)).await;
+            assert_eq!(state.lock_state::<Counter>().await.0, 3);
+        }
+        assert_eq!(state.lock_state::<Counter>().await.0, 2);
+
+        // Changes made during the override are dropped with it
+        state.lock_state::<Counter>().await.0 = 10;
+        drop(outer);
+        assert_eq!(state.lock_state::<Counter>().await.0, 1);
+
+        let guard = state.with_override(Counter(4)).await;
+        guard.restore().await;
+        assert_eq!(state.lock_state::<Counter>().await.0, 1);
+    }
+
+    #[tokio::test]
+    async fn test_override_dropped_while_locked_restores_after_unlock() {
+        let mut state = State::new();
+        state.state(Counter(1));
+
+        let guard = state.with_override(Counter(2)).await;
+        let held = state.lock_state::<Counter>().await;
+        drop(guard);
+        assert_eq!(held.0, 2);
+        drop(held);
+
+        // The restore waits behind the lock in a task, so yield until it ran
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(state.lock_state::<Counter>().await.0, 1);
+    }
+
+    #[tokio::test]
+    async fn test_overrides_restore_in_drop_order_around_a_held_lock() {
+        let mut state = State::new();
+        state.state(Counter(1));
+
+        let outer = state.with_override(Counter(2)).await;
+        let inner = state.with_override(Counter(3)).await;
+
+        // The inner restore has to wait for the lock, the outer one doesn't
+        let held = state.lock_state::<Counter>().await;
+        drop(inner);
+        drop(held);
+        drop(outer);
+
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(state.lock_state::<Counter>().await.0, 1);
+
+        // `restore` applies a queued restore before its own
+        let outer = state.with_override(Counter(2)).await;
+        let inner = state.with_override(Counter(3)).await;
+        let held = state.lock_state::<Counter>().await;
+        drop(inner);
+        drop(held);
+        outer.restore().await;
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(state.lock_state::<Counter>().await.0, 1);
+    }
+
+    /// The grouping before access sets were cached, kept to check the faster one against
+    fn reference_grouping(systems: &[NamedSystem]) -> Vec<Vec<usize>> {
+        let mut remaining_indices: Vec<usize> = (0..systems.len()).collect();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+
+        while !remaining_indices.is_empty() {
+            let mut current_group = Vec::new();
+            let mut used_types = HashSet::new();
+            let mut write_types = HashSet::new();
+            let mut indices_to_remove = Vec::new();
+
+            for (pos, &system_idx) in remaining_indices.iter().enumerate() {
+                let system_params = systems[system_idx].inner.params();
+
+                let has_reserved = system_params.iter().any(|p| p.reserved);
+
+                if has_reserved {
+                    if current_group.is_empty() {
+                        current_group.push(system_idx);
+                        indices_to_remove.push(pos);
+                        break;
+                    } else {
+                        continue;
+                    }
+                }
+
+                if !current_group.is_empty() {
+                    let group_has_reserved = current_group
+                        .iter()
+                        .any(|&idx| systems[idx].inner.params().iter().any(|p| p.reserved));
+                    if group_has_reserved {
+                        continue;
+                    }
+                }
+
+                let mut can_add = true;
+                let mut conflicting_types = Vec::new();
+
+                for param in &system_params {
+                    if param.write && used_types.contains(&param.type_name) {
+                        can_add = false;
+                        if write_types.contains(&param.type_name) {
+                            conflicting_types.push((&param.type_name, "write", "existing write"));
+                        } else {
+                            conflicting_types.push((&param.type_name, "write", "existing read"));
+                        }
+                        break;
+                    } else if write_types.contains(&param.type_name) {
+                        can_add = false;
+                        conflicting_types.push((&param.type_name, "read", "existing write"));
+                        break;
+                    }
+                }
+
+                if can_add {
+                    current_group.push(system_idx);
+                    indices_to_remove.push(pos);
+
+                    for param in &system_params {
+                        used_types.insert(param.type_name.clone());
+                        if param.write {
+                            write_types.insert(param.type_name.clone());
+                        }
+                    }
+                }
+            }
+
+            if !current_group.is_empty() {
+                groups.push(current_group);
+            }
+
+            indices_to_remove.sort_by(|a, b| b.cmp(a));
+            for pos in indices_to_remove {
+                remaining_indices.remove(pos);
+            }
+        }
+
+        groups
+    }
+
+    /// `count` systems over 12 states, mixing reads, writes, two-param systems and the
+    /// occasional reserved one
+    fn mixed_systems(count: usize) -> Vec<NamedSystem> {
+        let names: Vec<String> = (0..12).map(|i| format!("Type{i}")).collect();
+        create_systems(
+            (0..count)
+                .map(|i| {
+                    let mut params = vec![(names[i % 7].as_str(), i % 3 == 0, i % 17 == 16)];
+                    if i % 4 == 0 {
+                        params.push((names[7 + i % 5].as_str(), i % 2 == 0, false));
+                    }
+                    params
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_grouping_matches_reference() {
+        let cases = vec![
+            vec![
+                vec![("TypeA", false, false)],
+                vec![("TypeB", false, false)],
+                vec![("TypeA", true, false)],
+                vec![("TypeC", false, false)],
+                vec![("TypeB", false, false)],
+                vec![("TypeD", false, true)],
+            ],
+            vec![
+                vec![("TypeA", false, false), ("TypeB", true, false)],
+                vec![("TypeC", false, false), ("TypeA", false, false)],
+                vec![("TypeB", false, false)],
+            ],
+            vec![
+                vec![("TypeA", false, true)],
+                vec![("TypeB", true, true)],
+                vec![("TypeC", false, false)],
+            ],
+            vec![vec![("TypeA", true, false)], vec![("TypeA", false, false)]],
+            vec![vec![("TypeA", false, true), ("TypeB", false, false)]],
+            vec![],
+        ];
+
+        for case in cases {
+            let systems = create_systems(case);
+            assert_eq!(
+                group_concurrent_system_indices(&systems),
+                reference_grouping(&systems)
+            );
+        }
+
+        for count in [1, 10, 100] {
+            let systems = mixed_systems(count);
+            assert_eq!(
+                group_concurrent_system_indices(&systems),
+                reference_grouping(&systems)
+            );
+        }
+    }
+
+    fn hook_matches(pattern: &str, path: &str) -> bool {
+        HookInfo::new(pattern)
+            .matches(&HookPathComponent::parse(path))
+            .is_some()
+    }
+
+    #[test]
+    fn test_hook_paths_need_matching_lengths() {
+        assert!(hook_matches("update_filetype::rs", "update_filetype::rs"));
+        assert!(hook_matches("update_filetype::rs|toml", "update_filetype::toml"));
+        assert!(hook_matches("*::rs", "update_filetype::rs"));
+
+        // Patterns longer than the path never match
+        assert!(!hook_matches("update_filetype::rs", "update_filetype"));
+        assert!(!hook_matches("update_filetype::*", "update_filetype"));
+        assert!(!hook_matches("*::rs", "rs"));
+
+        // Paths longer than the pattern only match a trailing wildcard
+        assert!(!hook_matches("update_filetype", "update_filetype::rs"));
+        assert!(!hook_matches("*::rs", "update_filetype::rs::extra"));
+        assert!(hook_matches("update_filetype::*", "update_filetype::rs"));
+        assert!(hook_matches("update_filetype::*", "update_filetype::rs::extra"));
+        assert!(hook_matches("*", "update"));
+        assert!(hook_matches("*", "update_filetype::rs"));
+    }
+
     #[test]
     fn test_no_conflicts() {
         let systems = create_systems(vec![
@@ -756,4 +1108,50 @@ mod tests {
 
         guarentee_params(&system);
     }
+
+    struct TestHook;
+    impl Hook for TestHook {
+        fn info(&self) -> HookInfo {
+            HookInfo::new("test")
+        }
+    }
+
+    async fn read_counter(counter: Res<Counter>) {
+        get!(counter);
+        assert!(counter.0 >= 3);
+    }
+
+    async fn bump_counter(state: StateParam) {
+        get!(state);
+        state.lock_state::<Counter>().await.0 += 1;
+        let _opaque = state.lock_state::<Opaque>().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_state_param_runs_alone_and_locks_any_state() {
+        let mut state = State::new();
+        state.state(Opaque).state(Counter(3));
+        state
+            .on_hook(TestHook)
+            .system(read_counter)
+            .system(bump_counter)
+            .system(read_counter);
+
+        let systems = &state.hooks[0].1;
+        assert_eq!(
+            group_concurrent_system_indices(systems),
+            vec![vec![0, 2], vec![1]]
+        );
+
+        state.hook(TestHook).call().await;
+        assert_eq!(state.lock_state::<Counter>().await.0, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "System has too many arguments to have a reserved argument")]
+    fn test_state_param_must_be_the_only_param() {
+        async fn with_res(_state: StateParam, _counter: Res<Counter>) {}
+
+        State::new().on_hook(TestHook).system(with_res);
+    }
 }
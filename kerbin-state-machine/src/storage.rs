@@ -1,11 +1,158 @@
+use futures::future::BoxFuture;
 use std::any::Any;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{RwLock, RwLockWriteGuard};
 
-#[derive(Default)]
+type DebugFn = for<'a> fn(&'a dyn StateName) -> BoxFuture<'a, Option<String>>;
+
+#[derive(Default, Clone)]
 pub struct StateStorage {
-    pub states: HashMap<String, Box<dyn StateName>>,
+    /// Shared so `StateParam` can hand a system every state without borrowing the storage
+    pub states: HashMap<String, Arc<dyn StateName>>,
+    debug_fns: HashMap<String, DebugFn>,
+    /// Per state, the restores of dropped `StateOverride`s still waiting for its lock
+    restores: Arc<Mutex<HashMap<String, Arc<dyn Any + Send + Sync>>>>,
+}
+
+impl StateStorage {
+    /// Names of every registered state, sorted
+    pub fn state_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.states.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Write lock on the state `S`, panicking if it isn't registered
+    pub async fn lock_state<S: StateName + StaticState>(&self) -> RwLockWriteGuard<'_, S> {
+        self.states
+            .get(&S::static_name())
+            .expect("Type should be in state")
+            .downcast::<S>()
+            .expect("Stored type should be downcastable")
+            .write()
+            .await
+    }
+
+    /// Swaps `value` into the state `S` until the returned guard is dropped or restored,
+    /// putting the original back then. Overrides nest, each guard restoring what it
+    /// replaced, so drop them in reverse order. Panics if `S` isn't registered, and waits
+    /// for the state's lock, so don't hold it while calling this
+    pub async fn with_override<S: StateName + StaticState>(&self, value: S) -> StateOverride<S> {
+        let state = self
+            .states
+            .get(&S::static_name())
+            .expect("Type should be in state")
+            .downcast::<S>()
+            .expect("Stored type should be downcastable")
+            .clone();
+        let pending = self
+            .restores
+            .lock()
+            .expect("state restores poisoned")
+            .entry(S::static_name())
+            .or_insert_with(|| Arc::new(RestoreQueue::<S>::default()))
+            .clone()
+            .downcast::<RestoreQueue<S>>()
+            .expect("Stored restores should be downcastable");
+
+        let original = std::mem::replace(&mut *state.write().await, value);
+        StateOverride {
+            state,
+            pending,
+            original: Some(original),
+        }
+    }
+
+    /// Lets `debug_state` format the state `S` once it's registered
+    pub fn register_debug<S: DebugState>(&mut self) {
+        self.debug_fns.insert(S::static_name(), debug_fmt::<S>);
+    }
+
+    /// Pretty `Debug` output of the state called `name`, or `None` if it isn't
+    /// registered or didn't opt in through `register_debug`
+    pub async fn debug_state(&self, name: &str) -> Option<String> {
+        let state = self.states.get(name)?;
+        self.debug_fns.get(name)?(state.as_ref()).await
+    }
+}
+
+/// Restores of a state's overrides waiting for its lock, oldest first
+type RestoreQueue<S> = Mutex<VecDeque<S>>;
+
+/// Holds the value a state had before `StateStorage::with_override`, restoring it when
+/// dropped. A drop while something else holds the state's lock queues the restore for a
+/// task that waits for the lock, and later restores of the same state queue behind it, so
+/// they land in the order the guards went; `restore` waits in place instead
+pub struct StateOverride<S: StateName + StaticState> {
+    state: Arc<RwLock<S>>,
+    pending: Arc<RestoreQueue<S>>,
+    original: Option<S>,
+}
+
+impl<S: StateName + StaticState> StateOverride<S> {
+    /// Puts the original value back once the state's lock is free
+    pub async fn restore(mut self) {
+        if let Some(original) = self.original.take() {
+            let mut state = self.state.write().await;
+            // Earlier restores still queued go first, so this one isn't overwritten by them
+            apply_pending(&mut *state, &self.pending);
+            *state = original;
+        }
+    }
+}
+
+/// Applies the queued restores to the locked `state` in order
+fn apply_pending<S>(state: &mut S, pending: &RestoreQueue<S>) {
+    let mut pending = pending.lock().expect("state restores poisoned");
+    while let Some(value) = pending.pop_front() {
+        *state = value;
+    }
+}
+
+impl<S: StateName + StaticState> Drop for StateOverride<S> {
+    fn drop(&mut self) {
+        let Some(original) = self.original.take() else {
+            return;
+        };
+
+        let mut pending = self.pending.lock().expect("state restores poisoned");
+        if pending.is_empty()
+            && let Ok(mut state) = self.state.try_write()
+        {
+            *state = original;
+            return;
+        }
+
+        pending.push_back(original);
+        if pending.len() > 1 {
+            // A task is already waiting to apply the queue
+            return;
+        }
+        drop(pending);
+
+        let state = self.state.clone();
+        let queue = self.pending.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    apply_pending(&mut *state.write().await, &queue);
+                });
+            }
+            Err(_) => apply_pending(&mut *state.blocking_write(), &queue),
+        }
+    }
+}
+
+/// Marks a state whose contents can be inspected through `StateStorage::debug_state`
+pub trait DebugState: StateName + StaticState + Debug {}
+
+fn debug_fmt<S: DebugState>(state: &dyn StateName) -> BoxFuture<'_, Option<String>> {
+    Box::pin(async move {
+        let state = state.downcast::<S>()?.read().await;
+        Some(format!("{:#?}", &*state))
+    })
 }
 
 pub trait StateName: Any + Send + Sync + 'static {
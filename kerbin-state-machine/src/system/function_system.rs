@@ -43,10 +43,9 @@ macro_rules! impl_for_func {
             #[inline]
             #[allow(unused_mut)]
             fn params(&self) -> Vec<SystemParamDesc> {
-                vec![
-                    $($item::desc()),*
-                ]
-
+                let mut params = vec![];
+                $(params.extend($item::desc());)*
+                params
             }
         }
 
@@ -137,9 +136,9 @@ macro_rules! impl_for_func_fallible {
             #[inline]
             #[allow(unused_mut)]
             fn params(&self) -> Vec<SystemParamDesc> {
-                vec![
-                    $($item::desc()),*
-                ]
+                let mut params = vec![];
+                $(params.extend($item::desc());)*
+                params
             }
         }
 
@@ -2,8 +2,14 @@ use crate::storage::{StateName, StateStorage, StaticState};
 
 pub mod res;
 pub mod res_mut;
+pub mod state_param;
 
 #[async_trait::async_trait]
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a `SystemParam`",
+    label = "not a system param",
+    note = "wrap states in `Res<T>` or `ResMut<T>`"
+)]
 pub trait SystemParam {
     type Item<'new>: Send + Sync;
     fn retrieve(resources: &StateStorage) -> Self::Item<'_>;
@@ -13,7 +19,8 @@ pub trait SystemParam {
         Self: 'a;
     async fn get(&self) -> Self::Inner<'_>;
 
-    fn desc() -> SystemParamDesc;
+    /// The states the param locks. Params bundling several others list each of them
+    fn desc() -> Vec<SystemParamDesc>;
 }
 
 #[derive(Clone, Debug)]
@@ -17,6 +17,7 @@ pub struct Res<T: StateName + StaticState + Send + Sync + 'static> {
 }
 
 #[async_trait::async_trait]
+#[diagnostic::do_not_recommend]
 impl<T: StateName + StaticState> SystemParam for Res<T> {
     type Item<'new> = Res<T>;
     fn retrieve(resources: &StateStorage) -> Self::Item<'_> {
@@ -48,7 +49,7 @@ impl<T: StateName + StaticState> SystemParam for Res<T> {
         self.value.read().await
     }
 
-    fn desc() -> SystemParamDesc {
-        SystemParamDesc::new::<T>(false)
+    fn desc() -> Vec<SystemParamDesc> {
+        vec![SystemParamDesc::new::<T>(false)]
     }
 }
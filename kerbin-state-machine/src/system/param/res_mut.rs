@@ -17,6 +17,7 @@ pub struct ResMut<T: StateName + StaticState + Send + Sync + 'static> {
 }
 
 #[async_trait::async_trait]
+#[diagnostic::do_not_recommend]
 impl<T: StateName + StaticState> SystemParam for ResMut<T> {
     type Item<'new> = ResMut<T>;
     fn retrieve(resources: &StateStorage) -> Self::Item<'_> {
@@ -48,7 +49,7 @@ impl<T: StateName + StaticState> SystemParam for ResMut<T> {
         self.value.write().await
     }
 
-    fn desc() -> SystemParamDesc {
-        SystemParamDesc::new::<T>(true)
+    fn desc() -> Vec<SystemParamDesc> {
+        vec![SystemParamDesc::new::<T>(true)]
     }
 }
@@ -0,0 +1,39 @@
+use crate::storage::StateStorage;
+use crate::system::param::SystemParamDesc;
+
+use super::SystemParam;
+
+/// Reserved param giving a system every state at once, for work that would otherwise need
+/// a `Command` with `&mut State`, like locking whichever states a dynamic action touches.
+///
+/// Systems run while `State` is borrowed, so this can't register states or hooks; queue a
+/// command for those. A reserved param must be the only param of its system (checked by
+/// `guarentee_params`), and the system always runs alone in its group, so locking any
+/// state can't wait on another system of the same hook.
+pub struct StateParam {
+    storage: StateStorage,
+}
+
+#[async_trait::async_trait]
+#[diagnostic::do_not_recommend]
+impl SystemParam for StateParam {
+    type Item<'new> = StateParam;
+    fn retrieve(resources: &StateStorage) -> Self::Item<'_> {
+        StateParam {
+            storage: resources.clone(),
+        }
+    }
+
+    type Inner<'a> = &'a StateStorage;
+    async fn get(&self) -> Self::Inner<'_> {
+        &self.storage
+    }
+
+    fn desc() -> Vec<SystemParamDesc> {
+        vec![SystemParamDesc {
+            type_name: "state".to_string(),
+            write: true,
+            reserved: true,
+        }]
+    }
+}
@@ -9,6 +9,7 @@ use ratatui::{
         execute,
     },
     layout::{Constraint, Layout, Position},
+    style::Style,
 };
 
 use kerbin_core::*;
@@ -32,60 +33,30 @@ pub struct KerbinArgs {
     #[clap(long, num_args = 0..=1, default_missing_value = "text", value_name = "FORMAT")]
     list_commands: Option<String>,
 
+    /// Level written to the log file (trace, debug, info, warn, error, off).
+    /// Overrides the `KERBIN_LOG` env var and `core log_level`
+    #[clap(long, value_name = "LEVEL")]
+    log_level: Option<tracing::level_filters::LevelFilter>,
+
+    /// Name for this session, so `booster exec -s <name>` can reach it
+    #[clap(long, value_name = "NAME")]
+    session_name: Option<String>,
+
     /// Files to open on startup
     #[clap(value_name = "FILE")]
     files: Vec<PathBuf>,
 }
 
-fn collect_leaf_rects(
-    node: &PaneNode,
-    rect: ratatui::layout::Rect,
-) -> Vec<(PaneId, ratatui::layout::Rect)> {
-    match node {
-        PaneNode::Pane(pane) => vec![(pane.id, rect)],
-        PaneNode::Container { dir, children, .. } => {
-            let n = children.len().max(1);
-            let sizes: Vec<u32> = children
-                .iter()
-                .map(|c| {
-                    let s = match c {
-                        PaneNode::Pane(p) => p.size,
-                        PaneNode::Container { size, .. } => *size,
-                    };
-                    if s == 0 { 1 } else { s as u32 }
-                })
-                .collect();
-            let total: u32 = sizes.iter().sum::<u32>().max(1);
-
-            let mut constraints = Vec::with_capacity(n * 2);
-            for (i, &sz) in sizes.iter().enumerate() {
-                constraints.push(Constraint::Ratio(sz, total));
-                if i + 1 < n {
-                    constraints.push(Constraint::Length(1));
-                }
-            }
-
-            let areas = match dir {
-                SplitDir::Vertical => Layout::horizontal(constraints).split(rect),
-                SplitDir::Horizontal => Layout::vertical(constraints).split(rect),
-            };
-
-            let mut result = Vec::new();
-            for (i, child) in children.iter().enumerate() {
-                result.extend(collect_leaf_rects(child, areas[i * 2]));
-            }
-            result
-        }
-    }
-}
-
 pub async fn register_default_chunks(
     chunks: ResMut<Chunks>,
     window: Res<WindowState>,
     layout: Res<LayoutConfig>,
     split: ResMut<SplitState>,
+    buffers: Res<Buffers>,
+    config: Res<CoreConfig>,
+    gutter: Res<GutterConfig>,
 ) {
-    get!(mut chunks, window, layout, mut split);
+    get!(mut chunks, window, layout, mut split, buffers, config, gutter);
 
     let size = window.size();
 
@@ -100,6 +71,13 @@ pub async fn register_default_chunks(
     let leaf_rects = collect_leaf_rects(&split.root, main_area);
     split.leaf_rects = leaf_rects.clone();
 
+    let gutter_pad = if gutter.enabled { gutter.padding } else { 0 };
+    let mut gutter_widths = Vec::with_capacity(leaf_rects.len());
+    for (pane_id, pane_full_rect) in &leaf_rects {
+        let height = pane_full_rect.height.saturating_sub(layout.bufferline_height);
+        gutter_widths.push(pane_gutter_width(&split, &buffers, &gutter, &config, *pane_id, height).await);
+    }
+
     for (idx, (_, pane_full_rect)) in leaf_rects.iter().enumerate() {
         let [bufferline_rect, content_rect] = Layout::vertical([
             Constraint::Length(layout.bufferline_height),
@@ -108,8 +86,8 @@ pub async fn register_default_chunks(
         .areas(*pane_full_rect);
 
         let [gutter_rect, _pad, buffer_rect] = Layout::horizontal([
-            Constraint::Length(layout.gutter_width),
-            Constraint::Length(layout.gutter_pad),
+            Constraint::Length(gutter_widths[idx]),
+            Constraint::Length(gutter_pad),
             Constraint::Fill(1),
         ])
         .areas(content_rect);
@@ -120,8 +98,8 @@ pub async fn register_default_chunks(
     }
 
     // Register the focused pane's named chunks for backward compatibility
-    if let Some((_, focused_full_rect)) = leaf_rects.iter().find(|(id, _)| *id == split.focused_id)
-    {
+    if let Some(focused_idx) = leaf_rects.iter().position(|(id, _)| *id == split.focused_id) {
+        let focused_full_rect = &leaf_rects[focused_idx].1;
         let [focused_bufferline, focused_content] = Layout::vertical([
             Constraint::Length(layout.bufferline_height),
             Constraint::Fill(1),
@@ -129,8 +107,8 @@ pub async fn register_default_chunks(
         .areas(*focused_full_rect);
 
         let [focused_gutter, _pad2, focused_buffer] = Layout::horizontal([
-            Constraint::Length(layout.gutter_width),
-            Constraint::Length(layout.gutter_pad),
+            Constraint::Length(gutter_widths[focused_idx]),
+            Constraint::Length(gutter_pad),
             Constraint::Fill(1),
         ])
         .areas(focused_content);
@@ -141,13 +119,38 @@ pub async fn register_default_chunks(
     }
 }
 
-pub async fn render_chunks(chunks: Res<Chunks>, window: ResMut<WindowState>) {
-    get!(chunks, mut window);
+/// Width of the gutter beside a pane, sized by the buffer it shows
+async fn pane_gutter_width(
+    split: &SplitState,
+    buffers: &Buffers,
+    gutter: &GutterConfig,
+    config: &CoreConfig,
+    pane_id: PaneId,
+    height: u16,
+) -> u16 {
+    if !gutter.enabled {
+        return 0;
+    }
+
+    let Some(buf) = split
+        .root
+        .find_pane(pane_id)
+        .and_then(|pane| pane.buffer_indices.get(pane.selected_local))
+        .and_then(|idx| buffers.buffers.get(*idx))
+    else {
+        return gutter.numberwidth;
+    };
+
+    buf.read().await.gutter_width(height, config, gutter)
+}
+
+pub async fn render_chunks(chunks: ResMut<Chunks>, window: ResMut<WindowState>) {
+    get!(mut chunks, mut window);
 
     let mut best_cursor: Option<(usize, u16, u16, CursorShape)> = None;
 
-    for layer in &chunks.buffers {
-        for chunk_arc in layer {
+    for layer in 0..chunks.layer_count() {
+        for chunk_arc in chunks.layer_chunks(layer) {
             let chunk = chunk_arc.read().await;
             if let Some(cur) = chunk.get_cursor() {
                 let replace = best_cursor
@@ -159,24 +162,74 @@ pub async fn render_chunks(chunks: Res<Chunks>, window: ResMut<WindowState>) {
             }
         }
     }
+    let cursor = best_cursor.map(|(_, x, y, shape)| (x, y, shape));
+
+    let size = window.size();
+    let mut damage = chunks.damage().await;
+    if chunks.composited.area != size || window.too_small() {
+        damage = Damage::Full;
+    }
+
+    match damage {
+        Damage::Rects(rects) if rects.is_empty() && cursor == chunks.drawn_cursor => return,
+        Damage::Rects(rects) => {
+            let mut composited = std::mem::take(&mut chunks.composited);
+            let base_cell = chunks.base_cell();
+            for rect in &rects {
+                for pos in rect.positions() {
+                    if let Some(cell) = composited.cell_mut(pos) {
+                        *cell = base_cell.clone();
+                    }
+                }
+            }
+
+            // Anything over a damaged area is drawn again, so overlays stay on top
+            for layer in 0..chunks.layer_count() {
+                for chunk_arc in chunks.layer_chunks(layer) {
+                    let chunk = chunk_arc.read().await;
+                    if rects.iter().any(|rect| rect.intersects(chunk.area())) {
+                        blit(&chunk, &mut composited);
+                    }
+                }
+            }
+            chunks.composited = composited;
+        }
+        Damage::Full => {
+            let mut composited = ratatui::buffer::Buffer::filled(size, chunks.base_cell());
+            // Layers are composited bottom-up so overlays (palette, dialogues) land on top
+            for layer in 0..chunks.layer_count() {
+                for chunk_arc in chunks.layer_chunks(layer) {
+                    blit(&*chunk_arc.read().await, &mut composited);
+                }
+            }
+            if window.too_small() {
+                let notice = format!("Terminal too small ({MIN_WINDOW_WIDTH}x{MIN_WINDOW_HEIGHT})");
+                let x = size.width.saturating_sub(notice.len() as u16) / 2;
+                let (y, width) = (size.height / 2, size.width as usize);
+                composited.set_stringn(x, y, &notice, width, Style::default());
+            }
+            chunks.composited = composited;
+        }
+    }
 
     tokio::task::block_in_place(|| {
         window.0.draw(|frame| {
-            for layer in &chunks.buffers {
-                for chunk_arc in layer {
-                    let chunk = chunk_arc.blocking_read();
-                    blit(&chunk, frame.buffer_mut());
-                }
+            let screen = frame.buffer_mut();
+            if screen.area == chunks.composited.area {
+                screen.content.clone_from(&chunks.composited.content);
+            } else {
+                blit(&chunks.composited, screen);
             }
 
-            if let Some((_, x, y, _)) = best_cursor {
+            if let Some((x, y, _)) = cursor {
                 frame.set_cursor_position(Position::new(x, y));
             }
         })
     })
     .ok();
+    chunks.drawn_cursor = cursor;
 
-    if let Some((_, _, _, shape)) = best_cursor {
+    if let Some((_, _, shape)) = cursor {
         execute!(std::io::stdout(), shape.to_crossterm_style()).ok();
     }
 }
@@ -200,77 +253,24 @@ async fn update(state: &mut State) {
         events_state.0.clear();
         while ratatui::crossterm::event::poll(Duration::ZERO).unwrap_or(false) {
             if let Ok(event) = ratatui::crossterm::event::read() {
-                if let ratatui::crossterm::event::Event::Resize(_, _) = &event {
-                    // autoresize handled by terminal on next draw
+                if let ratatui::crossterm::event::Event::Resize(width, height) = &event {
+                    EVENT_BUS
+                        .emit(ResizeEvent {
+                            width: *width,
+                            height: *height,
+                        })
+                        .await;
                 }
                 events_state.0.push(event);
             }
         }
-    }
 
-    state.hook(hooks::Update).call().await;
-    state.hook(hooks::PostUpdate).call().await;
-
-    state.lock_state::<Chunks>().await.clear();
-
-    state.hook(hooks::ChunkRegister).call().await;
-
-    // Resolve filetype — detect once and cache on the buffer
-    let cached = {
-        let bufs = state.lock_state::<Buffers>().await;
-        bufs.cur_buffer_as::<TextBuffer>()
-            .await
-            .and_then(|tb| tb.filetype.clone())
-    };
-
-    let filetype = if let Some(ft) = cached {
-        Some(ft)
-    } else {
-        // Read detection inputs from the buffer
-        let (path, first_line) = {
-            let bufs = state.lock_state::<Buffers>().await;
-            bufs.cur_buffer_as::<TextBuffer>()
-                .await
-                .map(|tb| {
-                    let first_line = tb.get_rope().lines().next().map(|l| l.to_string());
-                    (tb.path.clone(), first_line)
-                })
-                .unwrap_or_default()
-        };
-
-        let detected = state
-            .lock_state::<FiletypeRegistry>()
-            .await
-            .detect(&path, first_line.as_deref());
-
-        // Cache the result on the buffer
-        if detected.is_some() {
-            let mut bufs = state.lock_state::<Buffers>().await;
-            if let Some(mut buf) = bufs.cur_text_buffer_mut().await {
-                buf.filetype = detected.clone();
-            }
+        if !events_state.0.is_empty() {
+            state.lock_state::<FrameActivity>().await.mark();
         }
-
-        detected
-    };
-
-    if let Some(ref ft) = filetype {
-        state.hook(hooks::UpdateFiletype::new(ft)).call().await;
     }
 
-    state.hook(hooks::UpdateCleanup).call().await;
-
-    state.hook(hooks::PreLines).call().await;
-
-    state.hook(hooks::PreRender).call().await;
-
-    state.hook(hooks::Render).call().await;
-
-    state.hook(hooks::RenderChunks).call().await;
-
-    handle_ipc_messages(state).await;
-
-    EVENT_BUS.resolve(state).await;
+    Pipeline::run(state).await;
 }
 
 #[tokio::main]
@@ -289,6 +289,7 @@ async fn main() {
                     .map(|info| {
                         serde_json::json!({
                             "names": info.valid_names,
+                            "category": info.category,
                             "args": info.args.iter().map(|(n, t)| serde_json::json!({ "name": n, "type": t })).collect::<Vec<_>>(),
                             "desc": info.desc,
                         })
@@ -300,6 +301,7 @@ async fn main() {
                 println!("# Kerbin Commands\n");
                 for info in &infos {
                     println!("## {}", info.valid_names.join(", "));
+                    println!("\n**Category:** {}", info.category);
                     if !info.args.is_empty() {
                         let args: Vec<String> = info
                             .args
@@ -349,6 +351,9 @@ async fn main() {
         eprintln!("{}", e);
         std::process::exit(1);
     });
+    if let Some(name) = &args.session_name {
+        let _ = std::fs::write(session_name_path(&session_id.to_string()), name);
+    }
 
     let config_path = match args.config {
         Some(t) => {
@@ -395,7 +400,7 @@ async fn main() {
         .await
         .set_template("session", session_id.to_string());
 
-    init_log();
+    init_log(args.log_level);
 
     let terminal = ratatui::init();
     // Chain onto ratatui's panic hook to also clean up what ratatui::restore() doesn't handle.
@@ -448,6 +453,8 @@ async fn main() {
         commands.register::<ShellCommand>();
 
         commands.register::<RegisterCommand>();
+        commands.register::<MacroCommand>();
+        commands.register::<JumpCommand>();
 
         commands.register::<ConfigCommand>();
         commands.register::<DebugCommand>();
@@ -457,6 +464,10 @@ async fn main() {
         commands.register::<SplitCommand>();
         commands.register::<DialogueCommand>();
         commands.register::<RegisterLanguageCommand>();
+        commands.register::<SearchCommand>();
+        commands.register::<OperatorCommand>();
+        commands.register::<PickerCommand>();
+        commands.register::<QuickfixCommand>();
     }
 
     {
@@ -512,11 +523,7 @@ async fn main() {
         );
     }
 
-    let (framerate, disable_auto_pairs) = {
-        let cfg = state.lock_state::<CoreConfig>().await;
-        (cfg.framerate, cfg.disable_auto_pairs)
-    };
-    let ms_per_frame = 1000 / framerate;
+    let disable_auto_pairs = state.lock_state::<CoreConfig>().await.disable_auto_pairs;
 
     if !disable_auto_pairs {
         state
@@ -527,17 +534,7 @@ async fn main() {
             });
     }
 
-    {
-        let mut mb = state.lock_state::<MouseBindings>().await;
-        mb.bindings.insert(
-            MouseTrigger::LeftDown,
-            vec!["goto %mouse_col %mouse_line".to_string()],
-        );
-        mb.bindings
-            .insert(MouseTrigger::ScrollUp, vec!["scroll -3".to_string()]);
-        mb.bindings
-            .insert(MouseTrigger::ScrollDown, vec!["scroll 3".to_string()]);
-    }
+    add_default_mouse_binds(&state).await;
 
     state
         .on_hook(hooks::ChunkRegister)
@@ -548,23 +545,37 @@ async fn main() {
         )
         .system_named("core::log_chunk", register_log_chunk)
         .system_named("core::help_menu_chunk", register_help_menu_chunk)
-        .system_named("core::dialogue_chunk", register_dialogue_chunk);
+        .system_named("core::dialogue_chunk", register_dialogue_chunk)
+        .system_named("core::picker_chunk", register_picker_chunk);
 
     state
         .on_hook(hooks::Update)
+        .system_named("core::handle_resize", handle_resize)
         .system_named("core::update_debounce", update_debounce)
+        .system_named("core::expire_yank_flash", expire_yank_flash)
+        .system_named("core::update_autosave", update_autosave)
         .system_named("core::handle_inputs", handle_inputs)
+        .system_named("core::route_mouse_events", route_mouse_events)
         .system_named("core::handle_mouse_events", handle_mouse_events)
         .system_named(
             "core::update_palette_suggestions",
             update_palette_suggestions,
         )
-        .system_named("core::update_dialogue", update_dialogue_validation);
+        .system_named("core::update_dialogue", update_dialogue_validation)
+        .system_named("core::update_file_finder", update_file_finder)
+        .system_named("core::update_workspace_grep", update_workspace_grep);
+
+    #[cfg(feature = "watcher")]
+    state
+        .on_hook(hooks::Update)
+        .system_named("core::update_file_watcher", update_file_watcher);
 
     state
         .on_hook(hooks::PostUpdate)
         .system_named("core::post_update_buffer", post_update_buffer)
-        .system_named("core::update_tab_width_template", update_tab_width_template);
+        .system_named("core::apply_filetype_profile", apply_filetype_profile)
+        .system_named("core::update_tab_width_template", update_tab_width_template)
+        .system_named("core::warn_readonly_edits", warn_readonly_edits);
 
     state
         .on_hook(hooks::PreLines)
@@ -576,6 +587,7 @@ async fn main() {
             "core::update_buffer_vertical_scroll",
             update_buffer_vertical_scroll,
         )
+        .system_named("core::animate_buffer_scroll", animate_buffer_scroll)
         .system_named("core::update_bufferline_scroll", update_bufferline_scroll);
 
     state.on_hook(hooks::PreRender).system_named(
@@ -588,6 +600,7 @@ async fn main() {
         .system_named("core::render_statusline", render_statusline)
         .system_named("core::render_command_palette", render_command_palette)
         .system_named("core::render_dialogue", render_dialogue)
+        .system_named("core::render_picker", render_picker)
         .system_named("core::render_help_menu", render_help_menu)
         .system_named("core::render_bufferline", render_bufferline)
         .system_named("core::render_log", render_log)
@@ -602,15 +615,28 @@ async fn main() {
         .on_hook(hooks::RenderChunks)
         .system_named("core::render_chunks", render_chunks);
 
+    EVENT_BUS
+        .subscribe::<BufferOpenEvent>()
+        .await
+        .system(offer_recovery);
+
+    EVENT_BUS
+        .subscribe::<SaveEvent>()
+        .await
+        .system(run_after_save);
+
     state.hook(hooks::PostInit).call().await;
 
     for file in args.files {
         let path = file.to_string_lossy().to_string();
-        let default_tab_unit = state.lock_state::<CoreConfig>().await.default_tab_unit;
+        let (default_tab_unit, big_file_threshold) = {
+            let config = state.lock_state::<CoreConfig>().await;
+            (config.default_tab_unit, config.big_file_threshold)
+        };
         state
             .lock_state::<Buffers>()
             .await
-            .open(path, default_tab_unit)
+            .open(path, default_tab_unit, big_file_threshold)
             .await
             .ok();
     }
@@ -620,6 +646,7 @@ async fn main() {
 
         while let Ok(cmd) = command_receiver.try_recv() {
             dispatch_command(cmd.as_ref(), &mut state).await;
+            state.lock_state::<FrameActivity>().await.mark();
         }
 
         update(&mut state).await;
@@ -628,17 +655,53 @@ async fn main() {
             break;
         }
 
-        let target_frame_time = Duration::from_millis(ms_per_frame);
-        let deadline = frame_start + target_frame_time;
-
-        while tokio::time::Instant::now() < deadline {
-            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
-            tokio::select! {
-                Some(cmd) = command_receiver.recv() => {
-                    dispatch_command(cmd.as_ref(), &mut state).await;
+        let (framerate, idle_framerate) = {
+            let cfg = state.lock_state::<CoreConfig>().await;
+            (cfg.framerate.max(1), cfg.idle_framerate)
+        };
+        let target_frame_time = Duration::from_millis(1000 / framerate);
+        let active = state.lock_state::<FrameActivity>().await.take();
+
+        if active || idle_framerate == 0 {
+            let deadline = frame_start + target_frame_time;
+
+            while tokio::time::Instant::now() < deadline {
+                let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+                tokio::select! {
+                    Some(cmd) = command_receiver.recv() => {
+                        dispatch_command(cmd.as_ref(), &mut state).await;
+                        state.lock_state::<FrameActivity>().await.mark();
+                    }
+                    _ = tokio::time::sleep(remaining) => {
+                        break;
+                    }
                 }
-                _ = tokio::time::sleep(remaining) => {
-                    break;
+            }
+        } else {
+            let deadline = frame_start + Duration::from_millis(1000 / idle_framerate);
+
+            // Nothing is happening, so sleep until input or a command arrives, or the idle
+            // tick is due. Input is polled in slices of an active frame, so a poll that
+            // outlives a command wake only holds the event reader for a frame at most.
+            while tokio::time::Instant::now() < deadline {
+                let timeout = deadline
+                    .saturating_duration_since(tokio::time::Instant::now())
+                    .min(target_frame_time);
+                let input = tokio::task::spawn_blocking(move || {
+                    ratatui::crossterm::event::poll(timeout).unwrap_or(false)
+                });
+
+                tokio::select! {
+                    Some(cmd) = command_receiver.recv() => {
+                        dispatch_command(cmd.as_ref(), &mut state).await;
+                        state.lock_state::<FrameActivity>().await.mark();
+                        break;
+                    }
+                    ready = input => {
+                        if ready.unwrap_or(false) {
+                            break;
+                        }
+                    }
                 }
             }
         }
@@ -652,4 +715,6 @@ async fn main() {
     )
     .ok();
     ratatui::restore();
+
+    state.hook(hooks::Shutdown).call().await;
 }
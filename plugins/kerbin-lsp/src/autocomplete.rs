@@ -4,8 +4,8 @@ use kerbin_core::*;
 
 const PRIORITY: i32 = 6;
 use lsp_types::{
-    CompletionItem, CompletionParams, CompletionResponse, Position, TextDocumentIdentifier,
-    TextDocumentPositionParams, WorkDoneProgressParams,
+    CompletionItem, CompletionParams, CompletionResponse, InsertTextFormat,
+    TextDocumentIdentifier, TextDocumentPositionParams, WorkDoneProgressParams,
 };
 use ratatui::{
     layout::Rect,
@@ -13,15 +13,15 @@ use ratatui::{
     widgets::{Block, BorderType, Paragraph},
 };
 
-use ropey::RopeSlice;
-
 use crate::{
     text_edit::{apply_text_edits_inner, cursor_adjustment_for_edits},
-    JsonRpcMessage, LspManager, OpenedFile,
+    JsonRpcMessage, LspManager, OpenedFile, byte_to_lsp_position, lsp_position_to_byte,
+    snippet::{Snippet, start_snippet},
 };
 use kerbin_tree_sitter::{grammar_manager::GrammarManager, state::highlight_text};
 
-struct CompletionWidget(ratatui::buffer::Buffer);
+/// Draws a pre-rendered popup buffer; shared with the signature help popup
+pub(crate) struct CompletionWidget(pub(crate) ratatui::buffer::Buffer);
 
 impl OverlayWidget for CompletionWidget {
     fn dimensions(&self) -> (u16, u16) {
@@ -50,6 +50,10 @@ pub struct CompletionInfo {
     pub position: usize,
     pub selected_index: usize,
     pub cached_doc_buffer: Option<(usize, Arc<ratatui::buffer::Buffer>)>,
+    /// Whether the response to `pending_request` has arrived
+    pub received: bool,
+    /// The server marked its list incomplete, so refining must ask it again
+    pub is_incomplete: bool,
 }
 
 #[derive(State, Default)]
@@ -59,6 +63,7 @@ pub struct CompletionState {
 }
 
 #[derive(Command)]
+#[command(category = "lsp")]
 pub enum CompletionCommand {
     #[command(drop_ident, name = "start_lsp_autocomplete", name = "sla")]
     /// Start requesting completions
@@ -77,27 +82,25 @@ pub enum CompletionCommand {
     SelectPrevious,
 }
 
+/// Completions, their resolves and cancels all go to the first server that completes
+fn supports_completion(caps: &lsp_types::ServerCapabilities) -> bool {
+    caps.completion_provider.is_some()
+}
+
 async fn trigger_completion_request(buf: &mut TextBuffer, lsps: &mut LspManager) -> Option<i32> {
     let file = buf.get_state::<OpenedFile>().await?;
 
-    let client = lsps.get_or_create_client(&file.lang).await.ok().flatten()?;
+    let client = lsps.client_for(&file.lang, supports_completion).await?;
 
     let cursor = buf.primary_cursor();
     let cursor_byte = cursor.get_cursor_byte().min(buf.len());
 
-    let line = buf.byte_to_line_clamped(cursor_byte);
-    let line_start = buf.line_to_byte_clamped(line);
-    let character: usize = buf
-        .slice(line_start, cursor_byte)
-        .map(|s| s.chars().map(|c| c.len_utf16()).sum())
-        .unwrap_or(0);
-
     let params = CompletionParams {
         text_document_position: TextDocumentPositionParams {
             text_document: TextDocumentIdentifier {
                 uri: file.uri.clone(),
             },
-            position: Position::new(line as u32, character as u32),
+            position: byte_to_lsp_position(buf.get_rope(), cursor_byte),
         },
         work_done_progress_params: WorkDoneProgressParams::default(),
         partial_result_params: Default::default(),
@@ -113,7 +116,7 @@ async fn trigger_resolve_request(
     item: &CompletionItem,
 ) -> Option<i32> {
     let file = buf.get_state::<OpenedFile>().await?;
-    let client = lsps.get_or_create_client(&file.lang).await.ok().flatten()?;
+    let client = lsps.client_for(&file.lang, supports_completion).await?;
     client.request("completionItem/resolve", item.clone()).await.ok()
 }
 
@@ -149,18 +152,26 @@ async fn send_resolve_for_selected(buf: &TextBuffer, lsps: &mut LspManager, info
     }
 }
 
+/// Orders items by their server-provided `sort_text`, items without one last
+fn cmp_sort_text(a: &CompletionItem, b: &CompletionItem) -> std::cmp::Ordering {
+    match (&a.sort_text, &b.sort_text) {
+        (Some(x), Some(y)) => x.cmp(y),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    }
+}
+
+/// Filters `items` against the typed `query` and ranks them client-side: exact matches,
+/// then prefix matches, then fuzzy matches, each tier ordered by the palette's fuzzy
+/// score and then by `sort_text`. Returns each item with its fuzzy score.
 fn get_ranked_items<'a>(
     items: &'a [CompletionItem],
     query: &str,
 ) -> Vec<(&'a CompletionItem, i32)> {
     if query.is_empty() {
         let mut all: Vec<_> = items.iter().map(|item| (item, 0)).collect();
-        all.sort_by(|(a, _), (b, _)| match (&a.sort_text, &b.sort_text) {
-            (Some(x), Some(y)) => x.cmp(y),
-            (Some(_), None) => std::cmp::Ordering::Less,
-            (None, Some(_)) => std::cmp::Ordering::Greater,
-            (None, None) => std::cmp::Ordering::Equal,
-        });
+        all.sort_by(|(a, _), (b, _)| cmp_sort_text(a, b));
         return all;
     }
 
@@ -173,41 +184,53 @@ fn get_ranked_items<'a>(
 
     let mut matched_items: Vec<(&CompletionItem, MatchQuality, i32)> = items
         .iter()
-        .enumerate()
-        .filter_map(|(idx, item)| {
+        .filter_map(|item| {
             let text = item.filter_text.as_deref().unwrap_or(&item.label);
+            let score = kerbin_core::palette::ranking::rank(query, text)?;
 
             let quality = if text == query {
                 MatchQuality::Exact
             } else if text.starts_with(query) {
                 MatchQuality::Prefix
-            } else if kerbin_core::palette::ranking::rank(query, text).is_some() {
-                MatchQuality::Fuzzy
             } else {
-                return None;
+                MatchQuality::Fuzzy
             };
 
-            Some((item, quality, idx as i32))
+            Some((item, quality, score))
         })
         .collect();
 
-    matched_items.sort_by(|(item_a, quality_a, _), (item_b, quality_b, _)| {
+    matched_items.sort_by(|(item_a, quality_a, score_a), (item_b, quality_b, score_b)| {
         quality_b
             .cmp(quality_a)
-            .then_with(|| match (&item_a.sort_text, &item_b.sort_text) {
-                (Some(a), Some(b)) => a.cmp(b),
-                (Some(_), None) => std::cmp::Ordering::Less,
-                (None, Some(_)) => std::cmp::Ordering::Greater,
-                (None, None) => std::cmp::Ordering::Equal,
-            })
+            .then(score_b.cmp(score_a))
+            .then_with(|| cmp_sort_text(item_a, item_b))
     });
 
     matched_items
         .into_iter()
-        .map(|(item, _, _)| (item, 0))
+        .map(|(item, _, score)| (item, score))
         .collect()
 }
 
+/// Publishes the labels matching `query` as the `lsp_items` template.
+/// Returns `false` (and drops the template) when nothing matches.
+async fn refresh_items_template(items: &[CompletionItem], query: &str) -> bool {
+    let labels: Vec<String> = get_ranked_items(items, query)
+        .into_iter()
+        .map(|(item, _)| item.label.clone())
+        .collect();
+
+    let mut resolver = resolver_engine_mut().await;
+    if labels.is_empty() {
+        resolver.remove_template("lsp_items");
+        false
+    } else {
+        resolver.set_template("lsp_items", Token::list_from(labels));
+        true
+    }
+}
+
 fn get_match_indices(ranker: &str, text: &str) -> Vec<usize> {
     if ranker.is_empty() || text.is_empty() {
         return vec![];
@@ -315,7 +338,7 @@ fn build_list_popup(
     buf
 }
 
-fn build_doc_popup(
+pub(crate) fn build_doc_popup(
     lines: &[Vec<(String, Style)>],
     doc_height: usize,
     doc_width: usize,
@@ -434,6 +457,8 @@ impl Command<State> for CompletionCommand {
                         position: start_pos,
                         selected_index: 0,
                         cached_doc_buffer: None,
+                        received: false,
+                        is_incomplete: false,
                     });
                 }
             }
@@ -467,41 +492,10 @@ impl Command<State> for CompletionCommand {
                                     if let Some(lsp_types::CompletionTextEdit::Edit(e)) =
                                         &item.text_edit
                                     {
-                                        let line_content_len = |line_slice: &RopeSlice| {
-                                            let mut len = line_slice.len_chars();
-                                            if len > 0 {
-                                                match line_slice.char(len - 1) {
-                                                    '\n' => {
-                                                        len -= 1;
-                                                        if len > 0
-                                                            && line_slice.char(len - 1) == '\r'
-                                                        {
-                                                            len -= 1;
-                                                        }
-                                                    }
-                                                    '\r' => len -= 1,
-                                                    _ => {}
-                                                }
-                                            }
-                                            len
-                                        };
-
-                                        let max_line = buf.len_lines().saturating_sub(1);
-                                        let start_line =
-                                            (e.range.start.line as usize).min(max_line);
-                                        let end_line = (e.range.end.line as usize).min(max_line);
-
-                                        let start_line_slice = buf.line_clamped(start_line);
-                                        let start_char = (e.range.start.character as usize)
-                                            .min(line_content_len(&start_line_slice));
-                                        let start = buf.line_to_byte_clamped(start_line)
-                                            + start_line_slice.char_to_byte(start_char);
-
-                                        let end_line_slice = buf.line_clamped(end_line);
-                                        let end_char = (e.range.end.character as usize)
-                                            .min(line_content_len(&end_line_slice));
-                                        let end = buf.line_to_byte_clamped(end_line)
-                                            + end_line_slice.char_to_byte(end_char);
+                                        let start =
+                                            lsp_position_to_byte(buf.get_rope(), e.range.start);
+                                        let end =
+                                            lsp_position_to_byte(buf.get_rope(), e.range.end);
 
                                         (start, end, e.new_text.clone())
                                     } else {
@@ -515,6 +509,21 @@ impl Command<State> for CompletionCommand {
                                         )
                                     };
 
+                                let mut snippet = (item.insert_text_format
+                                    == Some(InsertTextFormat::SNIPPET))
+                                .then(|| Snippet::parse(&text));
+                                if let Some(snippet) = &mut snippet {
+                                    let line = buf.byte_to_line_clamped(start_byte);
+                                    let indent: String = buf
+                                        .get_rope()
+                                        .line(line)
+                                        .chars()
+                                        .take_while(|c| *c == ' ' || *c == '\t')
+                                        .collect();
+                                    snippet.indent(&indent);
+                                }
+                                let text = snippet.as_ref().map(|s| s.text.clone()).unwrap_or(text);
+
                                 buf.start_change_group();
 
                                 if end_byte > start_byte {
@@ -533,12 +542,20 @@ impl Command<State> for CompletionCommand {
 
                                 let cursor_byte = start_byte + text.len();
 
+                                // Where the inserted text starts once the additional edits land
+                                let mut text_start = start_byte;
                                 if let Some(additional_edits) = item.additional_text_edits.clone() {
                                     let adjustment = cursor_adjustment_for_edits(
                                         &buf,
                                         &additional_edits,
                                         cursor_byte,
                                     );
+                                    text_start = (start_byte as isize
+                                        + cursor_adjustment_for_edits(
+                                            &buf,
+                                            &additional_edits,
+                                            start_byte,
+                                        )) as usize;
                                     apply_text_edits_inner(&mut buf, additional_edits);
                                     let final_cursor = (cursor_byte as isize + adjustment) as usize;
                                     buf.primary_cursor_mut()
@@ -549,6 +566,12 @@ impl Command<State> for CompletionCommand {
                                 }
 
                                 buf.commit_change_group();
+
+                                if let Some(snippet) = &snippet {
+                                    let seen_changes = buf.byte_changes.len();
+                                    start_snippet(&mut buf, snippet, text_start, seen_changes)
+                                        .await;
+                                }
                             }
                         }
                     }
@@ -560,13 +583,10 @@ impl Command<State> for CompletionCommand {
             }
             Self::Trash => {
                 let mut bufs = state.lock_state::<Buffers>().await;
+                let mut lsps = state.lock_state::<LspManager>().await;
                 let Some(mut buf) = bufs.cur_text_buffer_mut().await else { return true; };
 
-                let mut completion_state =
-                    buf.get_or_insert_state_mut(CompletionState::default).await;
-
-                completion_state.info = None;
-                resolver_engine_mut().await.remove_template("lsp_items");
+                trash_completion(&mut buf, &mut lsps).await;
             }
             Self::SelectNext => {
                 let mut bufs = state.lock_state::<Buffers>().await;
@@ -628,23 +648,23 @@ pub async fn handle_completion(state: &State, msg: &JsonRpcMessage) {
         let Some(mut completion_state) = buf.get_state_mut::<CompletionState>().await else { return; };
         let Some(info) = completion_state.info.as_mut() else { return; };
 
-        if let Some(result) = &response.result {
-            if let Ok(response) = serde_json::from_value::<CompletionResponse>(result.clone()) {
-                match response {
-                    CompletionResponse::Array(items) => {
-                        info.items = items;
-                    }
-                    CompletionResponse::List(list) => {
-                        info.items = list.items;
-                    }
+        info.items = vec![];
+        info.is_incomplete = false;
+        if let Some(result) = &response.result
+            && let Ok(response) = serde_json::from_value::<CompletionResponse>(result.clone())
+        {
+            match response {
+                CompletionResponse::Array(items) => {
+                    info.items = items;
+                }
+                CompletionResponse::List(list) => {
+                    info.items = list.items;
+                    info.is_incomplete = list.is_incomplete;
                 }
-            } else {
-                info.items = vec![];
             }
-        } else {
-            info.items = vec![];
         }
 
+        info.received = true;
         info.selected_index = 0;
         info.cached_doc_buffer = None;
 
@@ -657,24 +677,15 @@ pub async fn handle_completion(state: &State, msg: &JsonRpcMessage) {
             String::new()
         };
 
-        let items: Vec<String> = info
-            .items
-            .iter()
-            .filter(|i| kerbin_core::palette::ranking::rank(&query, &i.label).is_some())
-            .map(|i| i.label.clone())
-            .collect();
-
-        resolver_engine_mut().await.set_template("lsp_items", Token::list_from(items));
+        if !refresh_items_template(&info.items, &query).await {
+            completion_state.info = None;
+            return;
+        }
 
-        // Trigger resolve for item 0 so additionalTextEdits are ready before the user accepts.
+        // Resolve the top ranked item so additionalTextEdits are ready before the user accepts.
         // Safe to lock LspManager here: ProcessLspEventsCommand releases it before calling handlers.
-        let first_item = info.items.first().cloned();
-        if let Some(first_item) = first_item {
-            let mut lsps = state.lock_state::<LspManager>().await;
-            if let Some(id) = trigger_resolve_request(buf, &mut lsps, &first_item).await {
-                info.pending_resolve = Some((id, 0));
-            }
-        }
+        let mut lsps = state.lock_state::<LspManager>().await;
+        send_resolve_for_selected(buf, &mut lsps, info).await;
     }
 }
 
@@ -728,6 +739,42 @@ pub async fn handle_completion_resolve(state: &State, msg: &JsonRpcMessage) {
     }
 }
 
+/// Drops the completion popup of `buf` and cancels its outstanding requests
+async fn trash_completion(buf: &mut TextBuffer, lsps: &mut LspManager) {
+    let lang = buf.get_state::<OpenedFile>().await.map(|f| f.lang.clone());
+
+    let mut completion_state = buf.get_or_insert_state_mut(CompletionState::default).await;
+
+    // Don't leave the server working on a popup that's gone
+    if let Some(info) = completion_state.info.take()
+        && let Some(lang) = lang
+        && let Some(client) = lsps.client_for(&lang, supports_completion).await
+    {
+        let _ = client.cancel(info.pending_request).await;
+        if let Some((resolve_id, _)) = info.pending_resolve {
+            let _ = client.cancel(resolve_id).await;
+        }
+    }
+
+    resolver_engine_mut().await.remove_template("lsp_items");
+}
+
+/// Trashes completions when insert mode is left, however it was left
+pub async fn trash_on_insert_leave(
+    event: EventData<ModeLeaveEvent>,
+    bufs: ResMut<Buffers>,
+    lsps: ResMut<LspManager>,
+) {
+    get!(Some(event));
+    if event.mode != 'i' {
+        return;
+    }
+    get!(mut bufs, mut lsps);
+
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else { return; };
+    trash_completion(&mut buf, &mut lsps).await;
+}
+
 pub async fn update_completions(bufs: ResMut<Buffers>, lsps: ResMut<LspManager>) {
     get!(mut bufs, mut lsps);
 
@@ -766,18 +813,47 @@ pub async fn update_completions(bufs: ResMut<Buffers>, lsps: ResMut<LspManager>)
         return;
     }
 
-    let mut pending_id = None;
-    if let Some(state) = buf.get_state::<CompletionState>().await
-        && state.info.is_some()
-    {
-        pending_id = trigger_completion_request(&mut buf, &mut lsps).await;
+    let word_start = buf.char_to_byte_clamped(current_char_idx);
+    let query = buf.slice_to_string(word_start, cursor_byte).unwrap_or_default();
+
+    // Still typing the same word: narrow the items we already have instead of asking the
+    // server again. A new word start (e.g. after `.` or `::`) or an incomplete list re-requests.
+    let refine = {
+        let Some(mut state) = buf.get_state_mut::<CompletionState>().await else { return; };
+        let Some(info) = &mut state.info else { return; };
+
+        if !info.is_incomplete && info.position == word_start {
+            // An in-flight response gets filtered by the query current when it lands
+            if info.received {
+                info.selected_index = 0;
+                info.cached_doc_buffer = None;
+                if !refresh_items_template(&info.items, &query).await {
+                    state.info = None;
+                }
+            }
+            true
+        } else {
+            info.position = word_start;
+            false
+        }
+    };
+
+    if refine {
+        return;
     }
 
-    if let Some(id) = pending_id
-        && let Some(mut state) = buf.get_state_mut::<CompletionState>().await
+    let pending_id = trigger_completion_request(&mut buf, &mut lsps).await;
+
+    if let Some(mut state) = buf.get_state_mut::<CompletionState>().await
         && let Some(info) = &mut state.info
     {
-        info.pending_request = id;
+        match pending_id {
+            Some(id) => {
+                info.pending_request = id;
+                info.received = false;
+            }
+            None => state.info = None,
+        }
     }
 }
 
@@ -984,3 +1060,51 @@ pub async fn render_completions(
             }),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(label: &str, sort_text: Option<&str>) -> CompletionItem {
+        CompletionItem {
+            label: label.to_string(),
+            sort_text: sort_text.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn labels(items: &[CompletionItem], query: &str) -> Vec<String> {
+        get_ranked_items(items, query)
+            .into_iter()
+            .map(|(item, _)| item.label.clone())
+            .collect()
+    }
+
+    #[test]
+    fn filter_ranks_exact_then_prefix_then_fuzzy() {
+        let items = vec![
+            item("xfxxxxb", None),
+            item("foo_bar", None),
+            item("fbx", None),
+            item("fb", None),
+            item("zzz", None),
+        ];
+
+        assert_eq!(labels(&items, "fb"), ["fb", "fbx", "foo_bar", "xfxxxxb"]);
+        // Refining narrows the same items without a new request
+        assert_eq!(labels(&items, "fba"), ["foo_bar"]);
+        assert!(labels(&items, "fbq").is_empty());
+    }
+
+    #[test]
+    fn filter_breaks_ties_with_sort_text() {
+        let items = vec![
+            item("push_back", Some("2")),
+            item("push_front", Some("1")),
+            item("pop", None),
+        ];
+
+        assert_eq!(labels(&items, "push"), ["push_front", "push_back"]);
+        assert_eq!(labels(&items, ""), ["push_front", "push_back", "pop"]);
+    }
+}
@@ -5,18 +5,29 @@ use serde::Serialize;
 use serde_json::Value;
 use std::collections::HashSet;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
-use tokio::process::{ChildStdin, Command};
+use tokio::process::{Child, ChildStdin, Command};
 use tokio::sync::Mutex;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
 
 pub mod facade;
 pub use facade::*;
 
+/// How long a request may go unanswered before it is cancelled
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// JSON-RPC error code the LSP spec reserves for cancelled requests
+const REQUEST_CANCELLED: i64 = -32800;
+
+static NEXT_REQUEST_ID: AtomicI32 = AtomicI32::new(1);
+
 pub struct RequestInfo {
     pub id: i32,
     pub method: String,
     pub params: Value,
+    pub sent_at: Instant,
 }
 
 /// A message drained from a client's channel, with its routing method already resolved.
@@ -34,7 +45,12 @@ pub struct LspClient<W: AsyncWrite + Unpin + Send + 'static> {
     lang_id: String,
 
     writer: Arc<Mutex<W>>,
-    request_id: Arc<Mutex<i32>>,
+
+    /// The server process, killed when the client is dropped
+    process: Option<Child>,
+
+    /// When the client was created
+    started_at: Instant,
 
     /// Map of request IDs to their original request info
     request_info: std::collections::HashMap<i32, RequestInfo>,
@@ -42,6 +58,9 @@ pub struct LspClient<W: AsyncWrite + Unpin + Send + 'static> {
     /// Response IDs to drop without processing
     ignore_ids: Vec<i32>,
 
+    /// Requests older than this are cancelled by `expire_requests`
+    request_timeout: Duration,
+
     message_rx: UnboundedReceiver<JsonRpcMessage>,
 
     /// Server capabilities received from the initialize response
@@ -56,6 +75,7 @@ impl LspClient<ChildStdin> {
     ) -> std::io::Result<Self> {
         let mut process = Command::new(server_cmd)
             .args(args)
+            .kill_on_drop(true)
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
@@ -75,7 +95,9 @@ impl LspClient<ChildStdin> {
             Self::log_errors(stderr).await;
         });
 
-        Self::new(lang.to_string(), stdin, stdout)
+        let mut client = Self::new(lang.to_string(), stdin, stdout)?;
+        client.process = Some(process);
+        Ok(client)
     }
 }
 
@@ -97,9 +119,11 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
 
             lang_id: lang,
             writer: input,
-            request_id: Arc::new(Mutex::new(0)),
+            process: None,
+            started_at: Instant::now(),
             request_info: std::collections::HashMap::new(),
             ignore_ids: vec![],
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
             message_rx,
             server_capabilities: None,
         })
@@ -125,6 +149,21 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
         &self.lang_id
     }
 
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = timeout;
+    }
+
+    /// The exit status of the server process once it has exited. Always `None` for
+    /// clients that don't own a process.
+    pub fn exit_status(&mut self) -> Option<std::process::ExitStatus> {
+        self.process.as_mut()?.try_wait().ok().flatten()
+    }
+
+    /// How long ago the client was created
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
     async fn log_errors(stderr: impl AsyncRead + std::marker::Unpin) {
         let mut reader = BufReader::new(stderr);
 
@@ -224,10 +263,10 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
         }
     }
 
-    async fn get_next_id(&self) -> i32 {
-        let mut id = self.request_id.lock().await;
-        *id += 1;
-        *id
+    /// Ids are unique across clients, so a response can't be mistaken for the answer
+    /// to another server's request when a buffer has several servers
+    fn get_next_id(&self) -> i32 {
+        NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
     }
 
     async fn write_message(&self, message: &str) -> std::io::Result<()> {
@@ -244,7 +283,7 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
         method: impl ToString,
         params: T,
     ) -> std::io::Result<i32> {
-        let id = self.get_next_id().await;
+        let id = self.get_next_id();
         let method_str = method.to_string();
         let params_value = serde_json::to_value(params)
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
@@ -262,6 +301,7 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
                 id,
                 method: method_str,
                 params: params_value,
+                sent_at: Instant::now(),
             },
         );
 
@@ -271,6 +311,60 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
         Ok(id)
     }
 
+    /// Cancels an in-flight request, sending `$/cancelRequest` to the server.
+    /// Any response that still arrives for `id` is dropped.
+    pub async fn cancel(&mut self, id: i32) -> std::io::Result<()> {
+        if self.request_info.remove(&id).is_none() {
+            return Ok(());
+        }
+        self.ignore_ids.push(id);
+
+        self.notification(
+            "$/cancelRequest",
+            lsp_types::CancelParams {
+                id: lsp_types::NumberOrString::Number(id),
+            },
+        )
+        .await
+    }
+
+    /// Cancels every request that has waited longer than the request timeout.
+    /// Each one is returned as a `RequestCancelled` error response, so its handler
+    /// can clear whatever it was waiting with.
+    pub async fn expire_requests(&mut self) -> Vec<DrainedMessage> {
+        let expired: Vec<(i32, String)> = self
+            .request_info
+            .values()
+            // The client isn't usable until initialize answers, so never give up on it
+            .filter(|info| info.method != "initialize")
+            .filter(|info| info.sent_at.elapsed() >= self.request_timeout)
+            .map(|info| (info.id, info.method.clone()))
+            .collect();
+
+        let mut drained = Vec::with_capacity(expired.len());
+        for (id, method) in expired {
+            if let Err(e) = self.cancel(id).await {
+                tracing::error!("Failed to cancel {method} request {id}: {e}");
+            }
+
+            drained.push(DrainedMessage {
+                lang_id: self.lang_id.clone(),
+                method,
+                message: JsonRpcMessage::Response(JsonRpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id,
+                    result: None,
+                    error: Some(serde_json::json!({
+                        "code": REQUEST_CANCELLED,
+                        "message": "Request timed out",
+                    })),
+                }),
+            });
+        }
+
+        drained
+    }
+
     pub(crate) async fn call_matching_handlers<'a>(
         handlers: impl Iterator<Item = &'a HandlerEntry> + 'a,
         method: &str,
@@ -306,6 +400,7 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
             method: method.to_string(),
             params: serde_json::to_value(params)
                 .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            server: String::new(),
         };
 
         let message = serde_json::to_string(&notification)
@@ -314,6 +409,23 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
         Ok(())
     }
 
+    /// Answers a request the server sent, such as `workspace/applyEdit`
+    pub async fn respond<T: Serialize>(&self, id: i32, result: T) -> std::io::Result<()> {
+        let response = JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(
+                serde_json::to_value(result)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            ),
+            error: None,
+        };
+
+        let message = serde_json::to_string(&response)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        self.write_message(&message).await
+    }
+
     /// Process events with the given state, calling all registered handlers
     pub async fn process_events(&mut self, handler_manager: &LspHandlerManager, state: &State) {
         while let Ok(msg) = self.message_rx.try_recv() {
@@ -325,7 +437,7 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
                     }
 
                     // Get the method from request info for pattern matching
-                    if let Some(req_info) = self.request_info.get(&val.id) {
+                    if let Some(req_info) = self.request_info.remove(&val.id) {
                         // Cache server capabilities from the initialize response
                         if req_info.method == "initialize"
                             && let Some(result) = &val.result
@@ -343,12 +455,20 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
                     }
                 }
                 JsonRpcMessage::Notification(notif) => {
+                    let mut notif = notif.clone();
+                    notif.server = self.lang_id.clone();
+                    let method = notif.method.clone();
                     let handlers = handler_manager.iter_notification_handlers(&self.lang_id);
-                    Self::call_matching_handlers(handlers, &notif.method, state, &msg).await;
+                    let msg = JsonRpcMessage::Notification(notif);
+                    Self::call_matching_handlers(handlers, &method, state, &msg).await;
                 }
                 JsonRpcMessage::ServerRequest(req) => {
+                    let mut req = req.clone();
+                    req.server = self.lang_id.clone();
+                    let method = req.method.clone();
                     let handlers = handler_manager.iter_server_request_handlers(&self.lang_id);
-                    Self::call_matching_handlers(handlers, &req.method, state, &msg).await;
+                    let msg = JsonRpcMessage::ServerRequest(req);
+                    Self::call_matching_handlers(handlers, &method, state, &msg).await;
                 }
             }
         }
@@ -367,7 +487,7 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
                         self.ignore_ids.retain(|x| *x != val.id);
                         continue;
                     }
-                    if let Some(req_info) = self.request_info.get(&val.id) {
+                    if let Some(req_info) = self.request_info.remove(&val.id) {
                         if req_info.method == "initialize"
                             && let Some(result) = &val.result
                             && let Ok(init_result) =
@@ -377,23 +497,29 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
                         }
                         drained.push(DrainedMessage {
                             lang_id: self.lang_id.clone(),
-                            method: req_info.method.clone(),
+                            method: req_info.method,
                             message: msg,
                         });
                     }
                 }
                 JsonRpcMessage::Notification(notif) => {
+                    let method = notif.method.clone();
+                    let mut notif = notif.clone();
+                    notif.server = self.lang_id.clone();
                     drained.push(DrainedMessage {
                         lang_id: self.lang_id.clone(),
-                        method: notif.method.clone(),
-                        message: msg,
+                        method,
+                        message: JsonRpcMessage::Notification(notif),
                     });
                 }
                 JsonRpcMessage::ServerRequest(req) => {
+                    let method = req.method.clone();
+                    let mut req = req.clone();
+                    req.server = self.lang_id.clone();
                     drained.push(DrainedMessage {
                         lang_id: self.lang_id.clone(),
-                        method: req.method.clone(),
-                        message: msg,
+                        method,
+                        message: JsonRpcMessage::ServerRequest(req),
                     });
                 }
             }
@@ -401,7 +527,49 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
         drained
     }
 
-    /// Get the original request info for a given request ID
+    /// Whether messages are waiting to be drained or requests are waiting on an answer
+    pub fn has_work(&self) -> bool {
+        !self.message_rx.is_empty() || !self.request_info.is_empty()
+    }
+
+    /// Asks the server to shut down and then exit, waiting up to the request timeout for
+    /// each step. A server that doesn't answer or exit in time has its process killed.
+    pub async fn shutdown(&mut self) -> std::io::Result<()> {
+        let id = self.request("shutdown", Value::Null).await?;
+
+        let message_rx = &mut self.message_rx;
+        let answered = tokio::time::timeout(self.request_timeout, async {
+            while let Some(msg) = message_rx.recv().await {
+                if let JsonRpcMessage::Response(response) = msg
+                    && response.id == id
+                {
+                    return true;
+                }
+            }
+            false
+        })
+        .await
+        .unwrap_or(false);
+        self.request_info.remove(&id);
+
+        if answered {
+            self.notification("exit", Value::Null).await?;
+        }
+
+        if let Some(process) = &mut self.process {
+            let exited = answered
+                && tokio::time::timeout(self.request_timeout, process.wait())
+                    .await
+                    .is_ok();
+            if !exited {
+                process.kill().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the original request info for a request that hasn't been answered yet
     pub fn get_request_info(&self, id: i32) -> Option<&RequestInfo> {
         self.request_info.get(&id)
     }
@@ -411,3 +579,77 @@ impl<W: AsyncWrite + Unpin + Send + 'static> LspClient<W> {
         self.ignore_ids.push(id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn expired_requests_are_cancelled_and_forgotten() {
+        let (input, _server_in) = tokio::io::duplex(4096);
+        let (_server_out, output) = tokio::io::duplex(4096);
+
+        let mut client =
+            LspClient::new("test".to_string(), Arc::new(Mutex::new(input)), output).unwrap();
+        client.set_request_timeout(Duration::ZERO);
+
+        let id = client
+            .request("textDocument/hover", serde_json::json!({}))
+            .await
+            .unwrap();
+
+        let expired = client.expire_requests().await;
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].method, "textDocument/hover");
+        let JsonRpcMessage::Response(response) = &expired[0].message else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.id, id);
+        assert!(response.error.is_some());
+
+        assert!(client.get_request_info(id).is_none());
+        assert!(client.expire_requests().await.is_empty());
+    }
+
+    async fn read_message(reader: &mut (impl AsyncRead + Unpin)) -> Value {
+        let mut reader = BufReader::new(reader);
+        let mut header = String::new();
+        reader.read_line(&mut header).await.unwrap();
+        let len: usize = header.trim().strip_prefix("Content-Length: ").unwrap().parse().unwrap();
+        reader.read_line(&mut String::new()).await.unwrap();
+
+        let mut content = vec![0u8; len];
+        reader.read_exact(&mut content).await.unwrap();
+        serde_json::from_slice(&content).unwrap()
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_the_answer_before_exit() {
+        let (input, mut server_in) = tokio::io::duplex(4096);
+        let (mut server_out, output) = tokio::io::duplex(4096);
+
+        let server = tokio::spawn(async move {
+            let request = read_message(&mut server_in).await;
+            assert_eq!(request["method"], "shutdown");
+
+            let response = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "result": null,
+            })
+            .to_string();
+            let framed = format!("Content-Length: {}\r\n\r\n{response}", response.len());
+            server_out.write_all(framed.as_bytes()).await.unwrap();
+
+            read_message(&mut server_in).await
+        });
+
+        let mut client =
+            LspClient::new("test".to_string(), Arc::new(Mutex::new(input)), output).unwrap();
+        client.shutdown().await.unwrap();
+
+        let exit = server.await.unwrap();
+        assert_eq!(exit["method"], "exit");
+        assert!(!client.has_work());
+    }
+}
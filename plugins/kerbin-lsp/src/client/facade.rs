@@ -9,7 +9,8 @@ use crate::{LspClient, UriExt};
 #[allow(async_fn_in_trait)]
 pub trait ClientFacade {
     async fn init(&mut self, root_uri: Uri) -> io::Result<i32>;
-    async fn open(&self, path: impl ToString) -> io::Result<()>;
+    /// Sends `didOpen` for `path` with `text`, the buffer's contents
+    async fn open(&self, path: impl ToString, text: String) -> io::Result<()>;
 }
 
 impl<W: AsyncWrite + Unpin + Send + 'static> ClientFacade for LspClient<W> {
@@ -55,11 +56,61 @@ impl<W: AsyncWrite + Unpin + Send + 'static> ClientFacade for LspClient<W> {
                     formatting: Some(DynamicRegistrationClientCapabilities {
                         dynamic_registration: Some(false),
                     }),
+                    inlay_hint: Some(InlayHintClientCapabilities {
+                        dynamic_registration: Some(false),
+                        resolve_support: None,
+                    }),
+                    signature_help: Some(SignatureHelpClientCapabilities {
+                        dynamic_registration: Some(false),
+                        signature_information: Some(SignatureInformationSettings {
+                            documentation_format: Some(vec![
+                                MarkupKind::PlainText,
+                                MarkupKind::Markdown,
+                            ]),
+                            parameter_information: Some(ParameterInformationSettings {
+                                label_offset_support: Some(true),
+                            }),
+                            active_parameter_support: Some(true),
+                        }),
+                        context_support: Some(true),
+                    }),
+                    code_action: Some(CodeActionClientCapabilities {
+                        dynamic_registration: Some(false),
+                        code_action_literal_support: Some(CodeActionLiteralSupport {
+                            code_action_kind: CodeActionKindLiteralSupport {
+                                value_set: [
+                                    CodeActionKind::EMPTY,
+                                    CodeActionKind::QUICKFIX,
+                                    CodeActionKind::REFACTOR,
+                                    CodeActionKind::REFACTOR_EXTRACT,
+                                    CodeActionKind::REFACTOR_INLINE,
+                                    CodeActionKind::REFACTOR_REWRITE,
+                                    CodeActionKind::SOURCE,
+                                    CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+                                ]
+                                .iter()
+                                .map(|kind| kind.as_str().to_string())
+                                .collect(),
+                            },
+                        }),
+                        is_preferred_support: Some(true),
+                        disabled_support: Some(true),
+                        data_support: Some(true),
+                        resolve_support: Some(CodeActionCapabilityResolveSupport {
+                            properties: vec!["edit".to_string()],
+                        }),
+                        ..Default::default()
+                    }),
+                    rename: Some(RenameClientCapabilities {
+                        dynamic_registration: Some(false),
+                        prepare_support: Some(true),
+                        ..Default::default()
+                    }),
                     completion: Some(CompletionClientCapabilities {
                         dynamic_registration: None,
 
                         completion_item: Some(CompletionItemCapability {
-                            snippet_support: Some(false),
+                            snippet_support: Some(true),
                             commit_characters_support: Some(true),
                             documentation_format: Some(vec![
                                 MarkupKind::Markdown,
@@ -84,9 +135,18 @@ impl<W: AsyncWrite + Unpin + Send + 'static> ClientFacade for LspClient<W> {
                     ..Default::default()
                 }),
                 workspace: Some(WorkspaceClientCapabilities {
+                    apply_edit: Some(true),
+                    execute_command: Some(DynamicRegistrationClientCapabilities {
+                        dynamic_registration: Some(false),
+                    }),
                     diagnostic: Some(DiagnosticWorkspaceClientCapabilities {
                         refresh_support: Some(true),
                     }),
+                    workspace_edit: Some(WorkspaceEditClientCapabilities {
+                        document_changes: Some(true),
+                        failure_handling: Some(FailureHandlingKind::Undo),
+                        ..Default::default()
+                    }),
                     ..Default::default()
                 }),
                 ..Default::default()
@@ -101,9 +161,8 @@ impl<W: AsyncWrite + Unpin + Send + 'static> ClientFacade for LspClient<W> {
         self.request("initialize", init_params).await
     }
 
-    async fn open(&self, path: impl ToString) -> io::Result<()> {
+    async fn open(&self, path: impl ToString, text: String) -> io::Result<()> {
         let path_str = path.to_string();
-        let text = tokio::fs::read_to_string(&path_str).await?;
 
         self.notification(
             "textDocument/didOpen",
@@ -0,0 +1,612 @@
+use std::sync::Arc;
+
+use kerbin_core::*;
+use lsp_types::{
+    ApplyWorkspaceEditParams, ApplyWorkspaceEditResponse, CodeAction, CodeActionContext,
+    CodeActionOrCommand, CodeActionParams, CodeActionProviderCapability, CodeActionTriggerKind,
+    Diagnostic, ExecuteCommandParams, Position, Range, ServerCapabilities, TextDocumentIdentifier,
+    WorkDoneProgressParams,
+};
+use tokio::sync::RwLock;
+
+use crate::{
+    JsonRpcMessage, LspManager, OpenedFile, byte_to_lsp_position,
+    diagnostics::Diagnostics,
+    navigation::run_multi_commands,
+    rename::{apply_workspace_edit, collect_file_edits},
+};
+
+const NS_LIGHTBULB: &str = "lsp::code_action::lightbulb";
+
+/// Drawn after the cursor's line when the server has actions for it
+const LIGHTBULB: &str = "  💡";
+
+#[derive(State, Default)]
+pub struct CodeActionState {
+    /// The `lsp-code-action` request waiting on an answer, with its `--pick` commands
+    pub pending: Option<(i32, Option<Vec<Token>>)>,
+    /// The `codeAction/resolve` request waiting on an answer
+    pub resolving: Option<i32>,
+
+    /// Server that offered `actions`, which also resolves and executes them
+    pub server: String,
+    /// Actions offered by the last `lsp-code-action`, in `%lsp_code_actions` order
+    pub actions: Vec<CodeActionOrCommand>,
+
+    /// Cursor line and buffer version the lightbulb was last requested for
+    pub checked: Option<(usize, u128)>,
+    pub lightbulb_request: Option<i32>,
+    /// Line the lightbulb is shown on, when the server has actions there
+    pub lightbulb: Option<usize>,
+}
+
+#[derive(Debug, Clone, Command)]
+#[command(category = "lsp")]
+pub enum CodeActionCommand {
+    /// Request code actions (quick fixes, refactors) for the primary selection, sending the
+    /// diagnostics under it along. The actions fill `%lsp_code_actions` as `index: title`
+    /// entries and the `--pick` commands run (e.g. a picker calling `lsp-code-action-apply`).
+    #[command(drop_ident, name = "lsp-code-action")]
+    CodeAction {
+        #[command(flag, name = "pick", type_name = "[command]?")]
+        pick: Option<Vec<Token>>,
+    },
+
+    /// Apply the action at `index` of `%lsp_code_actions`, running its edit and then its command
+    #[command(drop_ident, name = "lsp-code-action-apply")]
+    Apply { index: usize },
+}
+
+#[async_trait::async_trait]
+impl Command<State> for CodeActionCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        match self {
+            Self::CodeAction { pick } => send_code_action_request(state, pick.clone()).await,
+            Self::Apply { index } => {
+                let (server, action) = {
+                    let bufs = state.lock_state::<Buffers>().await;
+                    let Some(buf) = bufs.cur_text_buffer().await else {
+                        return false;
+                    };
+                    let Some(actions) = buf.get_state::<CodeActionState>().await else {
+                        return false;
+                    };
+                    let Some(action) = actions.actions.get(*index).cloned() else {
+                        return false;
+                    };
+                    (actions.server.clone(), action)
+                };
+
+                apply_code_action(state, &server, action, true).await
+            }
+        }
+    }
+}
+
+fn supports_code_actions(caps: &ServerCapabilities) -> bool {
+    !matches!(
+        caps.code_action_provider,
+        None | Some(CodeActionProviderCapability::Simple(false))
+    )
+}
+
+fn supports_resolve(caps: &ServerCapabilities) -> bool {
+    matches!(
+        &caps.code_action_provider,
+        Some(CodeActionProviderCapability::Options(options))
+            if options.resolve_provider == Some(true)
+    )
+}
+
+/// Whether two ranges share a position, counting touching ends
+fn overlaps(a: &Range, b: &Range) -> bool {
+    let key = |p: &Position| (p.line, p.character);
+    key(&a.start) <= key(&b.end) && key(&b.start) <= key(&a.end)
+}
+
+/// Builds the request for `range`, with the buffer's diagnostics that overlap it
+async fn code_action_params(
+    buf: &TextBuffer,
+    uri: lsp_types::Uri,
+    range: Range,
+    trigger_kind: CodeActionTriggerKind,
+) -> CodeActionParams {
+    let diagnostics: Vec<Diagnostic> = match buf.get_state::<Diagnostics>().await {
+        Some(diagnostics) => diagnostics
+            .0
+            .iter()
+            .filter(|d| overlaps(&d.range, &range))
+            .cloned()
+            .collect(),
+        None => vec![],
+    };
+
+    CodeActionParams {
+        text_document: TextDocumentIdentifier { uri },
+        range,
+        context: CodeActionContext {
+            diagnostics,
+            only: None,
+            trigger_kind: Some(trigger_kind),
+        },
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        partial_result_params: Default::default(),
+    }
+}
+
+async fn send_code_action_request(state: &mut State, pick: Option<Vec<Token>>) -> bool {
+    let mut bufs = state.lock_state::<Buffers>().await;
+    let mut lsps = state.lock_state::<LspManager>().await;
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+        return false;
+    };
+
+    let Some(file) = buf.get_state::<OpenedFile>().await else {
+        return false;
+    };
+    let lang = file.lang.clone();
+    let uri = file.uri.clone();
+    drop(file);
+
+    let Some(client) = lsps.client_for(&lang, supports_code_actions).await else {
+        return false;
+    };
+
+    // A collapsed selection asks about the cursor position rather than its character
+    let sel = buf.primary_cursor().sel().clone();
+    let start = (*sel.start()).min(buf.len());
+    let end = if sel.start() == sel.end() {
+        start
+    } else {
+        (*sel.end() + 1).min(buf.len())
+    };
+    let range = Range::new(
+        byte_to_lsp_position(buf.get_rope(), start),
+        byte_to_lsp_position(buf.get_rope(), end),
+    );
+
+    let params = code_action_params(&buf, uri, range, CodeActionTriggerKind::INVOKED).await;
+    let Ok(request_id) = client.request("textDocument/codeAction", params).await else {
+        return false;
+    };
+    let server = client.lang_id().to_string();
+
+    resolver_engine_mut().await.remove_template("lsp_code_actions");
+
+    let mut actions = buf.get_or_insert_state_mut(CodeActionState::default).await;
+    actions.pending = Some((request_id, pick));
+    actions.server = server;
+
+    true
+}
+
+fn action_title(action: &CodeActionOrCommand) -> &str {
+    match action {
+        CodeActionOrCommand::Command(command) => &command.title,
+        CodeActionOrCommand::CodeAction(action) => &action.title,
+    }
+}
+
+/// The `%lsp_code_actions` entry of an action, which `lsp-code-action-apply` reads back
+/// the index from
+fn format_entry(index: usize, action: &CodeActionOrCommand) -> String {
+    let title = action_title(action).lines().next().unwrap_or_default();
+    match action {
+        CodeActionOrCommand::CodeAction(CodeAction {
+            disabled: Some(disabled),
+            ..
+        }) => format!("{index}: {title} (disabled: {})", disabled.reason),
+        _ => format!("{index}: {title}"),
+    }
+}
+
+/// Finds the buffer whose code action state passes `waiting`
+async fn buffer_waiting(
+    state: &State,
+    waiting: impl Fn(&CodeActionState) -> bool,
+) -> Option<Arc<RwLock<dyn KerbinBuffer>>> {
+    let bufs = state.lock_state::<Buffers>().await;
+
+    for buf in &bufs.buffers {
+        let buf_guard = buf.read().await;
+        if let Some(text_buf) = buf_guard.downcast::<TextBuffer>()
+            && let Some(actions) = text_buf.get_state::<CodeActionState>().await
+            && waiting(&actions)
+        {
+            return Some(buf.clone());
+        }
+    }
+
+    None
+}
+
+fn response_error(msg: &crate::JsonRpcResponse) -> Option<String> {
+    let error = msg.error.as_ref()?;
+    Some(
+        error
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("Unknown error")
+            .to_string(),
+    )
+}
+
+pub async fn handle_code_action(state: &State, msg: &JsonRpcMessage) {
+    let JsonRpcMessage::Response(response) = msg else {
+        return;
+    };
+
+    let id = response.id;
+    let Some(buf) = buffer_waiting(state, |a| {
+        a.pending.as_ref().is_some_and(|(p, _)| *p == id) || a.lightbulb_request == Some(id)
+    })
+    .await
+    else {
+        return;
+    };
+
+    let actions: Vec<CodeActionOrCommand> = response
+        .result
+        .as_ref()
+        .and_then(|r| serde_json::from_value::<Option<Vec<_>>>(r.clone()).ok())
+        .flatten()
+        .unwrap_or_default();
+
+    let pick = {
+        let mut buf_guard = buf.write().await;
+        let Some(text_buf) = buf_guard.downcast_mut::<TextBuffer>() else {
+            return;
+        };
+        let Some(mut code_actions) = text_buf.get_state_mut::<CodeActionState>().await else {
+            return;
+        };
+
+        if code_actions.lightbulb_request == Some(id) {
+            code_actions.lightbulb_request = None;
+            code_actions.lightbulb = match code_actions.checked {
+                Some((line, _)) if !actions.is_empty() => Some(line),
+                _ => None,
+            };
+            return;
+        }
+
+        let Some((_, pick)) = code_actions.pending.take() else {
+            return;
+        };
+        code_actions.actions = actions.clone();
+        pick
+    };
+
+    let log = state.lock_state::<LogSender>().await;
+    if let Some(err) = response_error(response) {
+        log.critical("lsp::code_action", format!("Code actions failed: {err}"));
+        return;
+    }
+    if actions.is_empty() {
+        log.low("lsp::code_action", "No code actions");
+        return;
+    }
+    drop(log);
+
+    let entries: Vec<String> = actions
+        .iter()
+        .enumerate()
+        .map(|(i, action)| format_entry(i, action))
+        .collect();
+    resolver_engine_mut()
+        .await
+        .set_template("lsp_code_actions", Token::list_from(entries));
+
+    if let Some(tokens) = pick {
+        run_multi_commands(state, tokens).await;
+    }
+}
+
+/// Applies an action's edit and then runs its command on `server`.
+/// With `resolve`, an action that carries neither is first completed with `codeAction/resolve`.
+async fn apply_code_action(
+    state: &State,
+    server: &str,
+    action: CodeActionOrCommand,
+    resolve: bool,
+) -> bool {
+    let action = match action {
+        CodeActionOrCommand::Command(command) => {
+            return execute_command(state, server, command).await;
+        }
+        CodeActionOrCommand::CodeAction(action) => action,
+    };
+
+    if let Some(disabled) = &action.disabled {
+        state.lock_state::<LogSender>().await.low(
+            "lsp::code_action",
+            format!("`{}` is disabled: {}", action.title, disabled.reason),
+        );
+        return false;
+    }
+
+    if resolve && action.edit.is_none() && action.command.is_none() {
+        return send_resolve(state, server, action).await;
+    }
+
+    if let Some(edit) = action.edit {
+        let result = match collect_file_edits(edit) {
+            Ok(file_edits) => apply_workspace_edit(state, file_edits).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            state
+                .lock_state::<LogSender>()
+                .await
+                .critical("lsp::code_action", format!("`{}` failed: {e}", action.title));
+            return false;
+        }
+    }
+
+    match action.command {
+        Some(command) => execute_command(state, server, command).await,
+        None => true,
+    }
+}
+
+async fn send_resolve(state: &State, server: &str, action: CodeAction) -> bool {
+    let mut bufs = state.lock_state::<Buffers>().await;
+    let mut lsps = state.lock_state::<LspManager>().await;
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+        return false;
+    };
+
+    let Ok(Some(client)) = lsps.get_or_create_server(server).await else {
+        return false;
+    };
+    if !client.server_capabilities.as_ref().is_some_and(supports_resolve) {
+        return false;
+    }
+
+    let Ok(request_id) = client.request("codeAction/resolve", action).await else {
+        return false;
+    };
+
+    buf.get_or_insert_state_mut(CodeActionState::default)
+        .await
+        .resolving = Some(request_id);
+    true
+}
+
+pub async fn handle_code_action_resolve(state: &State, msg: &JsonRpcMessage) {
+    let JsonRpcMessage::Response(response) = msg else {
+        return;
+    };
+
+    let id = response.id;
+    let Some(buf) = buffer_waiting(state, |a| a.resolving == Some(id)).await else {
+        return;
+    };
+
+    let server = {
+        let mut buf_guard = buf.write().await;
+        let Some(text_buf) = buf_guard.downcast_mut::<TextBuffer>() else {
+            return;
+        };
+        let Some(mut code_actions) = text_buf.get_state_mut::<CodeActionState>().await else {
+            return;
+        };
+        code_actions.resolving = None;
+        code_actions.server.clone()
+    };
+
+    if let Some(err) = response_error(response) {
+        state
+            .lock_state::<LogSender>()
+            .await
+            .critical("lsp::code_action", format!("Resolving the action failed: {err}"));
+        return;
+    }
+
+    let Some(action) = response
+        .result
+        .as_ref()
+        .and_then(|r| serde_json::from_value::<CodeAction>(r.clone()).ok())
+    else {
+        return;
+    };
+
+    apply_code_action(state, &server, CodeActionOrCommand::CodeAction(action), false).await;
+}
+
+/// Asks `server` to run a command. Any edits it makes come back as `workspace/applyEdit`.
+async fn execute_command(state: &State, server: &str, command: lsp_types::Command) -> bool {
+    let mut lsps = state.lock_state::<LspManager>().await;
+    let Ok(Some(client)) = lsps.get_or_create_server(server).await else {
+        return false;
+    };
+
+    client
+        .request(
+            "workspace/executeCommand",
+            ExecuteCommandParams {
+                command: command.command,
+                arguments: command.arguments.unwrap_or_default(),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            },
+        )
+        .await
+        .is_ok()
+}
+
+pub async fn handle_execute_command(state: &State, msg: &JsonRpcMessage) {
+    let JsonRpcMessage::Response(response) = msg else {
+        return;
+    };
+
+    if let Some(err) = response_error(response) {
+        state
+            .lock_state::<LogSender>()
+            .await
+            .critical("lsp::code_action", format!("Command failed: {err}"));
+    }
+}
+
+/// Handles `workspace/applyEdit`, which servers send while executing a command
+pub async fn handle_apply_edit(state: &State, msg: &JsonRpcMessage) {
+    let JsonRpcMessage::ServerRequest(request) = msg else {
+        return;
+    };
+
+    let result = match serde_json::from_value::<ApplyWorkspaceEditParams>(request.params.clone())
+    {
+        Ok(params) => match collect_file_edits(params.edit) {
+            Ok(file_edits) => apply_workspace_edit(state, file_edits).await.map(|_| ()),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.to_string()),
+    };
+
+    if let Err(e) = &result {
+        state
+            .lock_state::<LogSender>()
+            .await
+            .critical("lsp::code_action", format!("Server edit failed: {e}"));
+    }
+
+    let mut lsps = state.lock_state::<LspManager>().await;
+    let Ok(Some(client)) = lsps.get_or_create_server(&request.server).await else {
+        return;
+    };
+
+    let response = ApplyWorkspaceEditResponse {
+        applied: result.is_ok(),
+        failure_reason: result.err(),
+        failed_change: None,
+    };
+    if let Err(e) = client.respond(request.id, response).await {
+        tracing::error!("Failed to answer workspace/applyEdit: {e}");
+    }
+}
+
+/// System that asks for the code actions of the cursor's line whenever the line or the
+/// text changes outside of insert mode, showing a lightbulb after the line when there are any
+pub async fn update_code_action_lightbulb(
+    bufs: ResMut<Buffers>,
+    lsps: ResMut<LspManager>,
+    modes: Res<ModeStack>,
+    theme: Res<Theme>,
+) {
+    get!(mut bufs, mut lsps, modes, theme);
+
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+        return;
+    };
+    buf.renderer.clear_extmark_ns(NS_LIGHTBULB);
+
+    let Some(file) = buf.get_state::<OpenedFile>().await else {
+        return;
+    };
+    let lang = file.lang.clone();
+    let uri = file.uri.clone();
+    drop(file);
+
+    let line = buf.byte_to_line_clamped(buf.primary_cursor().get_cursor_byte());
+    let checked = (line, *buf.version());
+
+    let mut code_actions = buf.get_or_insert_state_mut(CodeActionState::default).await;
+    if modes.get_mode() == 'i' {
+        code_actions.checked = None;
+        code_actions.lightbulb = None;
+        return;
+    }
+
+    let lightbulb = code_actions.lightbulb;
+    let stale = code_actions.checked != Some(checked);
+    if stale {
+        code_actions.checked = Some(checked);
+        code_actions.lightbulb = None;
+    }
+    drop(code_actions);
+
+    if stale {
+        let Some(client) = lsps.client_for(&lang, supports_code_actions).await else {
+            return;
+        };
+        // Servers that don't have code actions, or haven't said yet, get no lightbulb
+        if !client.server_capabilities.as_ref().is_some_and(supports_code_actions) {
+            return;
+        }
+
+        let line_end = buf
+            .line_to_byte(line + 1)
+            .map(|b| b.saturating_sub(1))
+            .unwrap_or_else(|| buf.len());
+        let range = Range::new(
+            Position::new(line as u32, 0),
+            byte_to_lsp_position(buf.get_rope(), line_end),
+        );
+        let params = code_action_params(&buf, uri, range, CodeActionTriggerKind::AUTOMATIC).await;
+
+        if let Ok(request_id) = client.request("textDocument/codeAction", params).await {
+            buf.get_or_insert_state_mut(CodeActionState::default)
+                .await
+                .lightbulb_request = Some(request_id);
+        }
+        return;
+    }
+
+    if lightbulb != Some(line) {
+        return;
+    }
+
+    let end = buf
+        .line_to_byte(line + 1)
+        .map(|b| b.saturating_sub(1))
+        .unwrap_or_else(|| buf.len());
+    let style = theme.get_fallback_default(["lsp.code_action.lightbulb", "ui.virtual_text"]);
+
+    buf.add_extmark(
+        ExtmarkBuilder::new(NS_LIGHTBULB, end).with_kind(ExtmarkKind::VirtualText {
+            chunks: vec![StyledChunk {
+                text: LIGHTBULB.to_string(),
+                style,
+            }],
+            pos: VirtTextPos::Eol,
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::CodeActionDisabled;
+
+    #[test]
+    fn formats_picker_entries() {
+        let command = CodeActionOrCommand::Command(lsp_types::Command {
+            title: "Run test".to_string(),
+            command: "rust-analyzer.runSingle".to_string(),
+            arguments: None,
+        });
+        assert_eq!(format_entry(0, &command), "0: Run test");
+
+        let disabled = CodeActionOrCommand::CodeAction(CodeAction {
+            title: "Inline variable\nacross the file".to_string(),
+            disabled: Some(CodeActionDisabled {
+                reason: "not a variable".to_string(),
+            }),
+            ..Default::default()
+        });
+        assert_eq!(
+            format_entry(3, &disabled),
+            "3: Inline variable (disabled: not a variable)"
+        );
+    }
+
+    #[test]
+    fn touching_ranges_overlap() {
+        let range = |a: (u32, u32), b: (u32, u32)| {
+            Range::new(Position::new(a.0, a.1), Position::new(b.0, b.1))
+        };
+
+        assert!(overlaps(&range((1, 0), (1, 5)), &range((1, 5), (1, 5))));
+        assert!(overlaps(&range((0, 0), (3, 0)), &range((1, 2), (1, 4))));
+        assert!(!overlaps(&range((1, 0), (1, 4)), &range((1, 5), (2, 0))));
+    }
+}
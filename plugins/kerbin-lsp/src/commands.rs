@@ -2,6 +2,7 @@ use kerbin_core::*;
 
 use crate::{
     handlers::file_open::OpenedFile,
+    health::reopen_buffers,
     manager::{LangInfo, LspManager},
 };
 
@@ -29,8 +30,10 @@ fn tokens_to_strings(tokens: &[Token]) -> Vec<String> {
 }
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "lsp")]
 pub enum LspCommand {
     /// Register a language server for one or more language names.
+    /// A language can have several servers, the first one registered taking priority.
     #[command(drop_ident, name = "lsp_register")]
     Register {
         /// Server identifier (e.g. rust-analyzer, typescript-ls)
@@ -50,15 +53,25 @@ pub enum LspCommand {
         lsp_format: bool,
         #[command(flag)]
         external_formatter: Option<Vec<Token>>,
+        /// Milliseconds to wait on a request before cancelling it (default 5000)
+        #[command(flag)]
+        timeout_ms: Option<u64>,
+        /// How diagnostics are shown: any of [highlights signs virtual_text] (default all)
+        #[command(flag)]
+        diagnostics: Option<Vec<Token>>,
+        /// Respawn the server with a backoff if it exits on its own
+        #[command(flag)]
+        auto_restart: bool,
     },
 
-    /// Show the status of a language server (defaults to current buffer's language).
+    /// Show the status of a language's servers (defaults to current buffer's language).
     #[command(drop_ident, name = "lsp_status")]
     Status {
         lang: Option<String>,
     },
 
-    /// Kill and respawn a language server (defaults to current buffer's language).
+    /// Kill and respawn the language servers of a language (defaults to current buffer's language),
+    /// opening its buffers with them again.
     #[command(drop_ident, name = "lsp_restart")]
     Restart {
         lang: Option<String>,
@@ -78,6 +91,9 @@ impl Command<State> for LspCommand {
                 format_on_save,
                 lsp_format,
                 external_formatter,
+                timeout_ms,
+                diagnostics,
+                auto_restart,
             } => {
                 let lang_strings = tokens_to_strings(langs);
                 let arg_strings = args.as_deref().map(tokens_to_strings).unwrap_or_default();
@@ -85,7 +101,8 @@ impl Command<State> for LspCommand {
 
                 let info = LangInfo::new(cmd)
                     .with_args(arg_strings)
-                    .with_roots(root_strings);
+                    .with_roots(root_strings)
+                    .with_auto_restart(*auto_restart);
 
                 let info = if *lsp_format {
                     info.with_lsp_format(*format_on_save)
@@ -100,21 +117,46 @@ impl Command<State> for LspCommand {
                     info
                 };
 
+                let info = match timeout_ms {
+                    Some(ms) => info.with_request_timeout(std::time::Duration::from_millis(*ms)),
+                    None => info,
+                };
+
                 {
                     let mut manager = state.lock_state::<LspManager>().await;
                     manager.register_server(name, lang_strings.iter().map(|s| s.as_str()), info);
                 }
 
+                let diagnostic_kinds = diagnostics.as_deref().map(tokens_to_strings);
+                let show = |kind: &str| {
+                    diagnostic_kinds
+                        .as_ref()
+                        .is_none_or(|kinds| kinds.iter().any(|k| k == kind))
+                };
+                let (highlights, signs, virtual_text) =
+                    (show("highlights"), show("signs"), show("virtual_text"));
+
                 for lang in &lang_strings {
-                    state
-                        .on_hook(kerbin_core::hooks::UpdateFiletype::new(lang))
-                        .system(crate::open_files)
-                        .system(crate::apply_changes)
-                        .system(crate::render_diagnostic_highlights)
-                        .system(crate::process_lsp_events)
+                    let mut hook = state.on_hook(kerbin_core::hooks::UpdateFiletype::new(lang));
+                    hook.system(crate::apply_changes);
+                    if highlights {
+                        hook.system(crate::render_diagnostic_highlights);
+                    }
+                    if signs {
+                        hook.system(crate::render_diagnostic_signs);
+                    }
+                    if virtual_text {
+                        hook.system(crate::render_diagnostic_virtual_text);
+                    }
+                    hook.system(crate::process_lsp_events)
                         .system(crate::render_hover)
                         .system(crate::update_completions)
-                        .system(crate::render_completions);
+                        .system(crate::render_completions)
+                        .system(crate::update_snippet)
+                        .system(crate::update_signature_help)
+                        .system(crate::render_signature_help)
+                        .system(crate::update_code_action_lightbulb)
+                        .system(crate::update_inlay_hints);
                 }
             }
 
@@ -147,38 +189,21 @@ impl Command<State> for LspCommand {
                     return false;
                 };
 
-                let (server_name, affected_langs) = {
-                    let mut manager = state.lock_state::<LspManager>().await;
-                    let Some(server_name) = manager.reset_client(&target_lang) else {
-                        state.lock_state::<LogSender>().await.low(
-                            "lsp",
-                            format!("{target_lang}: no running client to restart"),
-                        );
-                        return false;
-                    };
-                    let affected = manager.langs_for_server(&server_name);
-                    (server_name, affected)
-                };
+                let mut manager = state.lock_state::<LspManager>().await;
+                let server_names = manager.reset_clients(&target_lang);
+                if server_names.is_empty() {
+                    state.lock_state::<LogSender>().await.low(
+                        "lsp",
+                        format!("{target_lang}: no running client to restart"),
+                    );
+                    return false;
+                }
 
-                // Clear lsp_opened for all buffers served by the restarted server
                 let bufs = state.lock_state::<Buffers>().await;
-                for buf in &bufs.buffers {
-                    let mut buf_guard = buf.clone().write_owned().await;
-                    if let Some(text_buf) = buf_guard.downcast_mut::<TextBuffer>() {
-                        let is_match = text_buf
-                            .get_state::<OpenedFile>()
-                            .await
-                            .is_some_and(|f| affected_langs.contains(&f.lang));
-                        if is_match {
-                            text_buf.flags.remove("lsp_opened");
-                        }
-                    }
-                }
+                let log = state.lock_state::<LogSender>().await;
+                reopen_buffers(&bufs, &mut manager, &server_names, &log).await;
 
-                state.lock_state::<LogSender>().await.low(
-                    "lsp",
-                    format!("{server_name}: restarted"),
-                );
+                log.low("lsp", format!("{}: restarted", server_names.join(", ")));
             }
         }
         false
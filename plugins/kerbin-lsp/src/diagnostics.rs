@@ -1,13 +1,49 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use crate::*;
 use lsp_types::*;
 
 /// Global store of all diagnostics received via publishDiagnostics,
 /// keyed by file path. Includes files not currently open as buffers.
+/// Holds every server's diagnostics for a file merged by [`merge_diagnostics`].
 #[derive(State, Default)]
 pub struct GlobalDiagnostics(pub std::collections::HashMap<String, Vec<Diagnostic>>);
 
+/// The diagnostics each server last published, keyed by file path and then server name.
+/// A publish only replaces the diagnostics of the server that sent it.
+#[derive(State, Default)]
+pub struct ServerDiagnostics(pub HashMap<String, HashMap<String, Vec<Diagnostic>>>);
+
+/// Merges the diagnostics each server published for one file, in the priority order of
+/// `servers` (others go last). Overlapping diagnostics from different servers are all
+/// kept, as they usually report different problems; only exact duplicates (same range,
+/// severity and message) are dropped, keeping the higher priority server's copy.
+/// Diagnostics without a `source` are tagged with the name of the server that sent them.
+pub fn merge_diagnostics(
+    by_server: &HashMap<String, Vec<Diagnostic>>,
+    servers: &[String],
+) -> Vec<Diagnostic> {
+    let mut names: Vec<&String> = by_server.keys().collect();
+    names.sort_by_key(|name| (servers.iter().position(|s| s == *name).unwrap_or(usize::MAX), *name));
+
+    let mut merged: Vec<Diagnostic> = vec![];
+    for name in names {
+        for diag in &by_server[name] {
+            let duplicate = merged.iter().any(|d| {
+                d.range == diag.range && d.severity == diag.severity && d.message == diag.message
+            });
+            if duplicate {
+                continue;
+            }
+
+            let mut diag = diag.clone();
+            diag.source.get_or_insert_with(|| name.clone());
+            merged.push(diag);
+        }
+    }
+    merged
+}
+
 use ratatui::{
     layout::Rect,
     prelude::*,
@@ -55,6 +91,11 @@ pub fn severity_to_str(severity: Option<DiagnosticSeverity>) -> &'static str {
     }
 }
 
+/// Diagnostics without a severity are treated as errors, matching how they are rendered
+pub fn is_error(diag: &Diagnostic) -> bool {
+    matches!(diag.severity, Some(DiagnosticSeverity::ERROR) | None)
+}
+
 pub fn format_diagnostic(path: &str, diag: &Diagnostic) -> String {
     let message = diag.message.replace('\n', " ");
     format!(
@@ -67,6 +108,21 @@ pub fn format_diagnostic(path: &str, diag: &Diagnostic) -> String {
     )
 }
 
+/// The diagnostic as a quickfix entry. LSP columns count UTF-16 units, which only differ
+/// from chars on lines with characters outside the BMP
+pub fn diagnostic_quickfix_entry(path: &str, diag: &Diagnostic) -> QuickfixEntry {
+    let pos = |p: lsp_types::Position| QuickfixPos::new(p.line as usize, p.character as usize);
+    QuickfixEntry {
+        path: path.to_string(),
+        range: pos(diag.range.start)..pos(diag.range.end),
+        message: format!(
+            "{}: {}",
+            severity_to_str(diag.severity),
+            diag.message.replace('\n', " ")
+        ),
+    }
+}
+
 const NS_HINT: &str = "lsp::diagnostics::hint";
 const NS_INFO: &str = "lsp::diagnostics::info";
 const NS_WARNING: &str = "lsp::diagnostics::warning";
@@ -130,22 +186,6 @@ pub async fn render_diagnostic_highlights(buffers: ResMut<kerbin_core::Buffers>)
         None => return,
     };
 
-    // Takes buf explicitly to avoid capturing it in the closure (it's borrowed mutably below)
-    let to_byte = |buf: &TextBuffer, line: usize, col: usize| -> usize {
-        let total_lines = buf.len_lines();
-        let line = line.min(total_lines.saturating_sub(1));
-
-        let line_start_byte = buf.line_to_byte_clamped(line);
-        let line_start_char = buf.byte_to_char_clamped(line_start_byte);
-
-        let line_len_chars = buf.line_clamped(line).len_chars();
-        let col = col.min(line_len_chars);
-
-        let global_char = (line_start_char + col).min(buf.len_chars());
-
-        buf.char_to_byte_clamped(global_char)
-    };
-
     let cursor_byte = buf.primary_cursor().get_cursor_byte();
 
     // Register namespace priorities (idempotent).
@@ -159,8 +199,8 @@ pub async fn render_diagnostic_highlights(buffers: ResMut<kerbin_core::Buffers>)
         .iter()
         .enumerate()
         .filter_map(|(i, d)| {
-            let start = to_byte(&buf, d.range.start.line as usize, d.range.start.character as usize);
-            let end = to_byte(&buf, d.range.end.line as usize, d.range.end.character as usize);
+            let start = lsp_position_to_byte(buf.get_rope(), d.range.start);
+            let end = lsp_position_to_byte(buf.get_rope(), d.range.end);
             (start..end).contains(&cursor_byte).then_some((i, severity_rank(d.severity)))
         })
         .max_by_key(|(_, rank)| *rank)
@@ -172,16 +212,8 @@ pub async fn render_diagnostic_highlights(buffers: ResMut<kerbin_core::Buffers>)
     buf.renderer.clear_extmark_ns(NS_ERROR);
 
     for (i, diagnostic) in diagnostics.iter().enumerate() {
-        let start_byte = to_byte(
-            &buf,
-            diagnostic.range.start.line as usize,
-            diagnostic.range.start.character as usize,
-        );
-        let end_byte = to_byte(
-            &buf,
-            diagnostic.range.end.line as usize,
-            diagnostic.range.end.character as usize,
-        );
+        let start_byte = lsp_position_to_byte(buf.get_rope(), diagnostic.range.start);
+        let end_byte = lsp_position_to_byte(buf.get_rope(), diagnostic.range.end);
 
         let (style, ns) = severity_to_style_ns(diagnostic.severity);
 
@@ -206,11 +238,242 @@ pub async fn render_diagnostic_highlights(buffers: ResMut<kerbin_core::Buffers>)
     }
 }
 
+const NS_SIGNS: &str = "lsp::diagnostics::signs";
+
+/// Gutter glyph and theme keys for a severity
+fn severity_to_sign(severity: Option<DiagnosticSeverity>) -> (&'static str, &'static str) {
+    match severity {
+        Some(DiagnosticSeverity::WARNING) => ("▲", "ui.gutter.warning"),
+        Some(DiagnosticSeverity::INFORMATION) => ("●", "ui.gutter.info"),
+        Some(DiagnosticSeverity::HINT) => ("·", "ui.gutter.hint"),
+        _ => ("●", "ui.gutter.error"),
+    }
+}
+
+/// System that draws a gutter sign on each line with diagnostics, showing the most
+/// severe one. Independent of `render_diagnostic_highlights`.
+pub async fn render_diagnostic_signs(buffers: ResMut<kerbin_core::Buffers>, theme: Res<Theme>) {
+    get!(mut buffers, theme);
+
+    let Some(mut buf) = buffers.cur_text_buffer_mut().await else { return; };
+
+    let diagnostics: Vec<Diagnostic> = match buf.get_state::<Diagnostics>().await.as_ref() {
+        Some(d) => d.0.clone(),
+        None => return,
+    };
+
+    buf.renderer.clear_extmark_ns(NS_SIGNS);
+
+    let mut worst: HashMap<u32, &Diagnostic> = HashMap::new();
+    for diagnostic in &diagnostics {
+        let line = diagnostic.range.start.line;
+        let rank = severity_rank(diagnostic.severity);
+        if worst.get(&line).is_none_or(|d| severity_rank(d.severity) < rank) {
+            worst.insert(line, diagnostic);
+        }
+    }
+
+    for diagnostic in worst.into_values() {
+        let (glyph, key) = severity_to_sign(diagnostic.severity);
+        let byte = lsp_position_to_byte(buf.get_rope(), diagnostic.range.start);
+
+        buf.add_extmark(
+            ExtmarkBuilder::new(NS_SIGNS, byte).with_kind(ExtmarkKind::Sign {
+                text: glyph.to_string(),
+                style: theme.get_fallback_default([key, "ui.gutter"]),
+            }),
+        );
+    }
+}
+
+/// Settings for how diagnostics and inlay hints are drawn, reachable as `set lsp.<field>`
+#[derive(State, ConfigurableState)]
+#[configurable(name = "lsp")]
+pub struct DiagnosticsConfig {
+    /// Whether the most severe diagnostic of each line is shown after the line's end
+    pub diag_virtual_text: bool,
+
+    /// Whether the server's inlay hints (types, parameter names) are shown inline
+    pub inlay_hints: bool,
+
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            diag_virtual_text: true,
+            inlay_hints: false,
+        }
+    }
+}
+
+const NS_VIRTUAL_TEXT: &str = "lsp::diagnostics::virtual_text";
+
+/// The most severe diagnostic of each line, with how many diagnostics start on that line.
+/// Ties keep the diagnostic that came first.
+fn worst_per_line(diagnostics: &[Diagnostic]) -> Vec<(u32, &Diagnostic, usize)> {
+    let mut lines: HashMap<u32, (&Diagnostic, usize)> = HashMap::new();
+    for diagnostic in diagnostics {
+        let entry = lines
+            .entry(diagnostic.range.start.line)
+            .or_insert((diagnostic, 0));
+        entry.1 += 1;
+        if severity_rank(diagnostic.severity) > severity_rank(entry.0.severity) {
+            entry.0 = diagnostic;
+        }
+    }
+
+    let mut lines: Vec<_> = lines
+        .into_iter()
+        .map(|(line, (diagnostic, count))| (line, diagnostic, count))
+        .collect();
+    lines.sort_by_key(|(line, ..)| *line);
+    lines
+}
+
+/// The text drawn after a line: the first line of the message, and a count of the
+/// other diagnostics on the line
+fn virtual_text(diagnostic: &Diagnostic, count: usize) -> String {
+    let message = diagnostic.message.lines().next().unwrap_or_default();
+    match count {
+        0 | 1 => format!("  {message}"),
+        n => format!("  {message} (+{})", n - 1),
+    }
+}
+
+/// System that shows the most severe diagnostic of each line as virtual text after the
+/// line's end, cut to fit the view. Toggled with `set diag-virtual-text`.
+pub async fn render_diagnostic_virtual_text(
+    buffers: ResMut<kerbin_core::Buffers>,
+    theme: Res<Theme>,
+    config: Res<DiagnosticsConfig>,
+) {
+    get!(mut buffers, theme, config);
+
+    let Some(mut buf) = buffers.cur_text_buffer_mut().await else { return; };
+
+    buf.renderer.clear_extmark_ns(NS_VIRTUAL_TEXT);
+    if !config.diag_virtual_text {
+        return;
+    }
+
+    let diagnostics: Vec<Diagnostic> = match buf.get_state::<Diagnostics>().await.as_ref() {
+        Some(d) => d.0.clone(),
+        None => return,
+    };
+
+    for (line, diagnostic, count) in worst_per_line(&diagnostics) {
+        let line = line as usize;
+        if line >= buf.len_lines() {
+            continue;
+        }
+
+        // Anchor on the line's last byte (its newline when it has one)
+        let end = buf
+            .line_to_byte(line + 1)
+            .map(|b| b.saturating_sub(1))
+            .unwrap_or_else(|| buf.len());
+
+        let severity = severity_to_str(diagnostic.severity);
+        let severity = if severity == "information" { "info" } else { severity };
+        let style = theme.get_fallback_default([
+            format!("ui.virtual_text.{severity}"),
+            format!("ui.gutter.{severity}"),
+            "ui.virtual_text".to_string(),
+        ]);
+
+        buf.add_extmark(
+            ExtmarkBuilder::new(NS_VIRTUAL_TEXT, end).with_kind(ExtmarkKind::VirtualText {
+                chunks: vec![StyledChunk {
+                    text: virtual_text(diagnostic, count),
+                    style,
+                }],
+                pos: VirtTextPos::Eol,
+            }),
+        );
+    }
+}
+
+/// Counts of `diagnostics` per severity, e.g. `E:3 W:5`, each styled with
+/// `statusline.diagnostics.<severity>`. Severities without diagnostics are left out, so the
+/// line is empty when there are none
+pub fn diagnostics_summary(diagnostics: &[Diagnostic], theme: &Theme) -> Line<'static> {
+    let severities = [
+        ("error", "E"),
+        ("warning", "W"),
+        ("information", "I"),
+        ("hint", "H"),
+    ];
+
+    let mut spans = vec![];
+    for (severity, label) in severities {
+        let count = diagnostics
+            .iter()
+            .filter(|d| severity_to_str(d.severity) == severity)
+            .count();
+        if count == 0 {
+            continue;
+        }
+
+        let severity = if severity == "information" { "info" } else { severity };
+        let style = theme.get_fallback_default([
+            format!("statusline.diagnostics.{severity}"),
+            format!("ui.gutter.{severity}"),
+            "statusline.text".to_string(),
+        ]);
+        if !spans.is_empty() {
+            spans.push(Span::raw(" "));
+        }
+        spans.push(Span::styled(format!("{label}:{count}"), style));
+    }
+    Line::from(spans)
+}
+
+/// Keeps the `diagnostics` statusline segment (`%d`) at the current buffer's diagnostic
+/// counts, leaving it empty when there are none
+pub async fn update_diagnostics_segment(
+    buffers: Res<Buffers>,
+    theme: Res<Theme>,
+    segments: ResMut<StatuslineSegments>,
+) {
+    get!(buffers, theme, mut segments);
+
+    let summary = match buffers.cur_buffer_as::<TextBuffer>().await {
+        Some(tb) => tb
+            .get_state::<Diagnostics>()
+            .await
+            .map(|diags| diagnostics_summary(&diags.0, &theme))
+            .unwrap_or_default(),
+        None => Line::default(),
+    };
+
+    if summary.width() == 0 {
+        segments.0.remove("diagnostics");
+    } else {
+        segments.0.insert("diagnostics".to_string(), summary);
+    }
+}
+
 pub async fn publish_diagnostics(state: &State, msg: &JsonRpcMessage) {
     if let crate::JsonRpcMessage::Notification(notif) = msg
         && let Ok(params) = serde_json::from_value::<PublishDiagnosticsParams>(notif.params.clone())
+        && let Some(path) = Uri::to_file_path(&params.uri)
     {
-        let path = params.uri.path().to_string();
+        let servers = state
+            .lock_state::<LspManager>()
+            .await
+            .lang_to_server
+            .values()
+            .find(|servers| servers.contains(&notif.server))
+            .cloned()
+            .unwrap_or_default();
+
+        let merged = {
+            let mut by_path = state.lock_state::<ServerDiagnostics>().await;
+            let by_server = by_path.0.entry(path.clone()).or_default();
+            by_server.insert(notif.server.clone(), params.diagnostics);
+            merge_diagnostics(by_server, &servers)
+        };
 
         // Always store in the global map so workspace diagnostics work for
         // files that are not currently open as buffers.
@@ -218,7 +481,7 @@ pub async fn publish_diagnostics(state: &State, msg: &JsonRpcMessage) {
             .lock_state::<GlobalDiagnostics>()
             .await
             .0
-            .insert(path.clone(), params.diagnostics.clone());
+            .insert(path.clone(), merged.clone());
 
         // Also push onto the open buffer if there is one.
         if let Some(mut buf_guard) = state
@@ -227,8 +490,98 @@ pub async fn publish_diagnostics(state: &State, msg: &JsonRpcMessage) {
             .get_mut_path(&path)
             .await
             && let Some(buf) = buf_guard.downcast_mut::<TextBuffer>() {
-                buf.set_state(Diagnostics(params.diagnostics));
+                buf.set_state(Diagnostics(merged));
             }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(line: u32, severity: DiagnosticSeverity, message: &str) -> Diagnostic {
+        Diagnostic {
+            range: lsp_types::Range::new(
+                lsp_types::Position::new(line, 0),
+                lsp_types::Position::new(line, 1),
+            ),
+            severity: Some(severity),
+            message: message.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn shows_worst_diagnostic_per_line_with_count() {
+        let diagnostics = vec![
+            diagnostic(2, DiagnosticSeverity::WARNING, "unused variable"),
+            diagnostic(2, DiagnosticSeverity::ERROR, "mismatched types\nexpected u32"),
+            diagnostic(2, DiagnosticSeverity::HINT, "consider borrowing"),
+            diagnostic(0, DiagnosticSeverity::INFORMATION, "note"),
+        ];
+
+        let lines = worst_per_line(&diagnostics);
+        assert_eq!(lines.len(), 2);
+
+        let (line, worst, count) = lines[1];
+        assert_eq!((line, count), (2, 3));
+        assert_eq!(virtual_text(worst, count), "  mismatched types (+2)");
+        assert_eq!(virtual_text(lines[0].1, lines[0].2), "  note");
+    }
+
+    #[test]
+    fn merges_diagnostics_from_several_servers() {
+        let mut by_server = HashMap::new();
+        by_server.insert(
+            "linter".to_string(),
+            vec![
+                diagnostic(1, DiagnosticSeverity::WARNING, "unused import"),
+                diagnostic(4, DiagnosticSeverity::ERROR, "mismatched types"),
+            ],
+        );
+        by_server.insert(
+            "analyzer".to_string(),
+            vec![diagnostic(4, DiagnosticSeverity::ERROR, "mismatched types")],
+        );
+
+        let servers = ["analyzer".to_string(), "linter".to_string()];
+        let merged = merge_diagnostics(&by_server, &servers);
+
+        // The duplicate is kept once, from the higher priority server
+        let tagged: Vec<_> = merged
+            .iter()
+            .map(|d| (d.message.as_str(), d.source.as_deref()))
+            .collect();
+        assert_eq!(
+            tagged,
+            [("mismatched types", Some("analyzer")), ("unused import", Some("linter"))]
+        );
+    }
+
+    #[test]
+    fn summarizes_counts_per_severity() {
+        let mut theme = Theme::default();
+        let error = Style::default().fg(Color::Red);
+        theme.register("statusline.diagnostics.error".to_string(), error);
+        let warning = Style::default().fg(Color::Yellow);
+        theme.register("ui.gutter.warning".to_string(), warning);
+
+        let mut diagnostics = vec![
+            diagnostic(0, DiagnosticSeverity::WARNING, "unused"),
+            diagnostic(1, DiagnosticSeverity::ERROR, "mismatched types"),
+            diagnostic(2, DiagnosticSeverity::WARNING, "dead code"),
+            diagnostic(3, DiagnosticSeverity::HINT, "borrow"),
+        ];
+        diagnostics[1].severity = None;
+
+        let summary = diagnostics_summary(&diagnostics, &theme);
+        assert_eq!(summary.to_string(), "E:1 W:2 H:1");
+        let styles: Vec<_> = summary.spans.iter().map(|s| s.style).collect();
+        assert_eq!(styles[0], error);
+        assert_eq!(styles[2], warning);
+
+        // Nothing to report takes no room
+        assert_eq!(diagnostics_summary(&[], &theme).width(), 0);
+    }
+}
@@ -18,6 +18,7 @@ impl kerbin_core::Command<State> for ProcessLspEventsCommand {
             let mut all = Vec::new();
             for (_lang, client) in lsp_manager.client_map.iter_mut() {
                 all.extend(client.drain_messages());
+                all.extend(client.expire_requests().await);
             }
             all
         }; // LspManager lock released here — handlers may now acquire it freely
@@ -48,8 +49,22 @@ impl kerbin_core::Command<State> for ProcessLspEventsCommand {
     }
 }
 
-pub async fn process_lsp_events(bufs: Res<Buffers>, command_sender: Res<CommandSender>) {
-    get!(bufs, command_sender);
+pub async fn process_lsp_events(
+    bufs: Res<Buffers>,
+    lsp_manager: Res<LspManager>,
+    command_sender: Res<CommandSender>,
+    activity: Res<FrameActivity>,
+) {
+    get!(bufs, lsp_manager, command_sender, activity);
+
+    // Sending the command wakes the editor, so only send it when there's something to do
+    if !lsp_manager.client_map.values().any(|client| client.has_work()) {
+        return;
+    }
+
+    // Completions, hovers and diagnostics are on their way, so keep frames coming to
+    // show them as soon as they land instead of on the next idle tick
+    activity.mark();
 
     for buf in &bufs.buffers {
         let buf_guard = buf.read().await;
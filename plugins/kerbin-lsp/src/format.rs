@@ -1,6 +1,8 @@
+use std::{convert::Infallible, fmt, path::Path, str::FromStr};
+
 use kerbin_core::*;
 use lsp_types::{
-    DocumentFormattingParams, FormattingOptions, TextDocumentIdentifier, TextEdit,
+    DocumentFormattingParams, FormattingOptions, OneOf, TextDocumentIdentifier, TextEdit,
     WorkDoneProgressParams,
 };
 use tokio::io::AsyncWriteExt;
@@ -10,9 +12,58 @@ use crate::{
     FormatterKind, JsonRpcMessage, LspManager, OpenedFile,
 };
 
+/// Buffer flag letting a write that follows format-on-save skip formatting again
+const FORMATTED_FOR_SAVE: &str = "lsp_formatted_for_save";
+
+/// Per-filetype format-on-save overrides, like `set format.on_save "rust !go"`.
+/// Listed filetypes format on save and `!`-prefixed ones never do, whatever their server
+/// was registered with. Entries match the language, filetype or file extension
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormatOnSave(pub Vec<String>);
+
+impl FormatOnSave {
+    /// Whether a file known by `names` formats on save, or `None` to leave it to the server.
+    /// Later entries win
+    pub fn decide(&self, names: &[&str]) -> Option<bool> {
+        self.0.iter().rev().find_map(|entry| match entry.strip_prefix('!') {
+            Some(name) => names.contains(&name).then_some(false),
+            None => names.contains(&entry.as_str()).then_some(true),
+        })
+    }
+}
+
+impl FromStr for FormatOnSave {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let list = s.trim().trim_start_matches('[').trim_end_matches(']');
+        Ok(Self(
+            list.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|entry| !entry.is_empty())
+                .map(str::to_string)
+                .collect(),
+        ))
+    }
+}
+
+impl fmt::Display for FormatOnSave {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}]", self.0.join(" "))
+    }
+}
+
+/// Settings for formatting, reachable as `set format.<field>`
+#[derive(State, ConfigurableState, Default)]
+#[configurable(name = "format")]
+pub struct FormatConfig {
+    /// Filetypes overriding their server's `--format_on_save`
+    pub on_save: FormatOnSave,
+}
+
 pub struct FormatPending {
     pub request_id: i32,
-    pub format_on_save: bool,
+    /// The write command held back until the formatting edits are applied
+    pub save_command: Option<BufferCommand>,
 }
 
 #[derive(State, Default)]
@@ -21,6 +72,7 @@ pub struct FormatState {
 }
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "lsp")]
 pub enum FormatCommand {
     #[command(drop_ident, name = "lsp-format")]
     Format,
@@ -47,12 +99,12 @@ pub async fn format_current_buffer(state: &mut State) -> bool {
     let lang = file.lang.clone();
     let uri = file.uri.clone();
 
-    let Some(fmt_config) = lsps.info_for_lang(&lang).and_then(|i| i.format.clone()) else {
+    let Some((server, fmt_config)) = lsps.formatter_for_lang(&lang) else {
         return false;
     };
 
     match fmt_config.kind {
-        FormatterKind::Lsp => send_lsp_format_request(&mut buf, &mut lsps, &lang, uri, false).await,
+        FormatterKind::Lsp => send_lsp_format_request(&mut buf, &mut lsps, &server, uri, None).await,
         FormatterKind::External(cmd, args) => {
             send_external_format_request(&mut buf, &cmd, &args).await
         }
@@ -62,14 +114,14 @@ pub async fn format_current_buffer(state: &mut State) -> bool {
 pub(crate) async fn send_lsp_format_request(
     buf: &mut TextBuffer,
     lsps: &mut LspManager,
-    lang: &str,
+    server: &str,
     uri: lsp_types::Uri,
-    format_on_save: bool,
+    save_command: Option<BufferCommand>,
 ) -> bool {
     let tab_size = buf.indent_style.tab_width() as u32;
     let insert_spaces = matches!(buf.indent_style, IndentStyle::Spaces(_));
 
-    let Some(client) = lsps.get_or_create_client(lang).await.ok().flatten() else {
+    let Some(client) = lsps.get_or_create_server(server).await.ok().flatten() else {
         return false;
     };
 
@@ -88,11 +140,91 @@ pub(crate) async fn send_lsp_format_request(
     };
 
     let mut fmt_state = buf.get_or_insert_state_mut(FormatState::default).await;
-    fmt_state.pending = Some(FormatPending { request_id, format_on_save });
+    fmt_state.pending = Some(FormatPending {
+        request_id,
+        save_command,
+    });
 
     true
 }
 
+/// Formats the buffer before a `write` when its server has format-on-save enabled.
+///
+/// External formatters run inline and the write goes ahead. LSP formatting is async,
+/// so the write is held back and re-sent by `handle_format` once the edits are applied.
+pub async fn format_on_save_intercept(cmd: &BufferCommand, state: &mut State) -> InterceptorResult {
+    if !matches!(
+        cmd,
+        BufferCommand::WriteFile { .. } | BufferCommand::WriteFileForce { .. }
+    ) {
+        return InterceptorResult::Allow;
+    }
+
+    let mut bufs = state.lock_state::<Buffers>().await;
+    let mut lsps = state.lock_state::<LspManager>().await;
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+        return InterceptorResult::Allow;
+    };
+
+    if buf.flags.remove(FORMATTED_FOR_SAVE) {
+        return InterceptorResult::Allow;
+    }
+
+    let Some(file) = buf.get_state::<OpenedFile>().await else {
+        return InterceptorResult::Allow;
+    };
+    let lang = file.lang.clone();
+    let uri = file.uri.clone();
+    drop(file);
+
+    let Some((server, fmt_config)) = lsps.formatter_for_lang(&lang) else {
+        return InterceptorResult::Allow;
+    };
+
+    let ext = Path::new(&buf.path).extension().and_then(|e| e.to_str());
+    let names = [Some(lang.as_str()), buf.filetype.as_deref(), ext];
+    let names: Vec<&str> = names.into_iter().flatten().collect();
+    let format_on_save = state
+        .lock_state::<FormatConfig>()
+        .await
+        .on_save
+        .decide(&names)
+        .unwrap_or(fmt_config.format_on_save);
+    if !format_on_save {
+        return InterceptorResult::Allow;
+    }
+
+    match fmt_config.kind {
+        FormatterKind::External(fmt_cmd, args) => {
+            send_external_format_request(&mut buf, &fmt_cmd, &args).await;
+            InterceptorResult::Allow
+        }
+        FormatterKind::Lsp => {
+            let supports_formatting = lsps
+                .get_or_create_server(&server)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|c| c.server_capabilities.as_ref())
+                .is_some_and(|c| {
+                    matches!(
+                        c.document_formatting_provider,
+                        Some(OneOf::Left(true) | OneOf::Right(_))
+                    )
+                });
+
+            if supports_formatting
+                && send_lsp_format_request(&mut buf, &mut lsps, &server, uri, Some(cmd.clone())).await
+            {
+                buf.flags.insert(SAVE_HELD);
+                InterceptorResult::Cancel
+            } else {
+                InterceptorResult::Allow
+            }
+        }
+    }
+}
+
 pub(crate) async fn send_external_format_request(
     buf: &mut TextBuffer,
     cmd: &str,
@@ -138,10 +270,8 @@ pub(crate) async fn send_external_format_request(
         return true;
     }
 
-    let total_chars = buf.len_chars();
     buf.start_change_group();
-    buf.action(Delete { byte: 0, len: total_chars });
-    buf.action(Insert { byte: 0, content: formatted });
+    buf.replace_text(&formatted);
     buf.commit_change_group();
 
     true
@@ -155,19 +285,19 @@ pub async fn handle_format(state: &State, msg: &JsonRpcMessage) {
     let bufs = state.lock_state::<Buffers>().await;
 
     let mut buffer = None;
-    for buf in &bufs.buffers {
+    for (i, buf) in bufs.buffers.iter().enumerate() {
         let buf_guard = buf.read().await;
         if let Some(text_buf) = buf_guard.downcast::<TextBuffer>()
             && let Some(fmt_state) = text_buf.get_state::<FormatState>().await
             && let Some(pending) = &fmt_state.pending
             && pending.request_id == response.id
         {
-            buffer = Some(buf.clone());
+            buffer = Some((buf.clone(), i == bufs.selected_buffer));
             break;
         }
     }
 
-    let Some(buf_arc) = buffer else {
+    let Some((buf_arc, is_current)) = buffer else {
         return;
     };
 
@@ -176,50 +306,79 @@ pub async fn handle_format(state: &State, msg: &JsonRpcMessage) {
     let mut buf_guard = buf_arc.write_owned().await;
     let Some(buf) = buf_guard.downcast_mut::<TextBuffer>() else { return; };
 
-    let format_on_save = buf
-        .get_state::<FormatState>()
-        .await
-        .and_then(|s| s.pending.as_ref().map(|p| p.format_on_save))
-        .unwrap_or(false);
-
-    if let Some(mut fmt_state) = buf.get_state_mut::<FormatState>().await {
-        fmt_state.pending = None;
+    let save_command = match buf.get_state_mut::<FormatState>().await {
+        Some(mut fmt_state) => fmt_state.pending.take().and_then(|p| p.save_command),
+        None => None,
+    };
+    buf.flags.remove(SAVE_HELD);
+
+    let edits: Vec<TextEdit> = response
+        .result
+        .as_ref()
+        .and_then(|r| serde_json::from_value(r.clone()).ok())
+        .unwrap_or_default();
+
+    if !edits.is_empty() {
+        let cursor_bytes: Vec<usize> = buf.cursors.iter().map(|c| c.get_cursor_byte()).collect();
+        let adjustments: Vec<isize> = cursor_bytes
+            .iter()
+            .map(|&byte| cursor_adjustment_for_edits(buf, &edits, byte))
+            .collect();
+
+        apply_text_edits(buf, edits);
+
+        for ((cursor, &cursor_byte), adjustment) in buf
+            .cursors
+            .iter_mut()
+            .zip(&cursor_bytes)
+            .zip(adjustments)
+        {
+            let new_byte = (cursor_byte as isize + adjustment).max(0) as usize;
+            cursor.set_sel(new_byte..=new_byte);
+        }
     }
 
-    let Some(result) = &response.result else {
+    // Save even when formatting failed or timed out, the user asked for a write
+    let Some(save_command) = save_command else {
         return;
     };
 
-    let edits: Vec<TextEdit> = match serde_json::from_value(result.clone()) {
-        Ok(e) => e,
-        Err(_) => return,
-    };
-
-    if edits.is_empty() {
-        return;
+    if is_current {
+        // Going back through the command keeps the external-change check
+        buf.flags.insert(FORMATTED_FOR_SAVE);
+        drop(buf_guard);
+        let _ = state
+            .lock_state::<CommandSender>()
+            .await
+            .send(Box::new(save_command));
+    } else {
+        let (BufferCommand::WriteFile { path } | BufferCommand::WriteFileForce { path }) =
+            save_command
+        else {
+            return;
+        };
+
+        if let Err(e) = buf.write_file(path).await {
+            tracing::error!("Failed to write formatted file: {e}");
+        }
     }
+}
 
-    let cursor_bytes: Vec<usize> = buf.cursors.iter().map(|c| c.get_cursor_byte()).collect();
-    let adjustments: Vec<isize> = cursor_bytes
-        .iter()
-        .map(|&byte| cursor_adjustment_for_edits(buf, &edits, byte))
-        .collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    apply_text_edits(buf, edits);
+    #[test]
+    fn format_on_save_overrides_by_filetype() {
+        let overrides: FormatOnSave = "[rs, !go python]".parse().unwrap();
+        assert_eq!(overrides.to_string(), "[rs !go python]");
 
-    for ((cursor, &cursor_byte), adjustment) in buf
-        .cursors
-        .iter_mut()
-        .zip(&cursor_bytes)
-        .zip(adjustments)
-    {
-        let new_byte = (cursor_byte as isize + adjustment).max(0) as usize;
-        cursor.set_sel(new_byte..=new_byte);
-    }
+        assert_eq!(overrides.decide(&["rust", "rust", "rs"]), Some(true));
+        assert_eq!(overrides.decide(&["go", "go", "go"]), Some(false));
+        assert_eq!(overrides.decide(&["typescript", "ts"]), None);
 
-    if format_on_save
-        && let Err(e) = buf.write_file_bare() {
-            tracing::error!("Failed to write formatted file: {e}");
-        }
+        // Later entries win
+        let overrides: FormatOnSave = "rs !rs".parse().unwrap();
+        assert_eq!(overrides.decide(&["rs"]), Some(false));
+    }
 }
-
@@ -1,40 +1,183 @@
+use ropey::Rope;
+
 use crate::*;
 
 pub async fn apply_changes(buffers: ResMut<Buffers>, lsp_manager: ResMut<LspManager>) {
-    get!(mut buffers, mut lsp_manager);
+    get!(buffers, mut lsp_manager);
 
-    let Some(mut buf) = buffers.cur_text_buffer_mut().await else { return; };
+    // Every open buffer is synced, not just the focused one, since commands like rename
+    // edit buffers in the background and their changes are cleared at the end of the frame
+    for buf in &buffers.buffers {
+        let mut buf_guard = buf.write().await;
+        let Some(buf) = buf_guard.downcast_mut::<TextBuffer>() else { continue };
 
-    if buf.byte_changes.is_empty() {
-        return;
+        if buf.byte_changes.is_empty() {
+            continue;
+        }
+
+        sync_buffer(buf, &mut lsp_manager).await;
     }
+}
 
+async fn sync_buffer(buf: &mut TextBuffer, lsp_manager: &mut LspManager) {
     let Some(mut file) = buf.get_state_mut::<OpenedFile>().await else {
         // File hasn't been opened yet anyways
         return;
     };
 
-    let Some(client) = lsp_manager.get_or_create_client(&file.lang).await.ok().flatten() else { return; };
+    let mut clients = lsp_manager.clients_named(&file.servers);
+    if clients.is_empty() {
+        return;
+    }
+
+    let rope = buf.get_rope().clone();
+    let Some(uri) = Uri::file_path(buf.path.as_str()).ok() else { return; };
+
+    // Built once and shared by every server that syncs incrementally
+    let incremental = file
+        .synced
+        .as_ref()
+        .and_then(|synced| incremental_change(synced, &rope, &buf.byte_changes));
 
     file.change_id += 1;
+    file.synced = Some(rope.clone());
+
+    for client in clients.iter_mut() {
+        let sync_kind = match client
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.text_document_sync.as_ref())
+        {
+            Some(TextDocumentSyncCapability::Kind(kind)) => *kind,
+            Some(TextDocumentSyncCapability::Options(options)) => {
+                options.change.unwrap_or(TextDocumentSyncKind::NONE)
+            }
+            None => TextDocumentSyncKind::FULL,
+        };
+
+        if sync_kind == TextDocumentSyncKind::NONE {
+            continue;
+        }
+
+        let change = incremental
+            .clone()
+            .filter(|_| sync_kind == TextDocumentSyncKind::INCREMENTAL)
+            .unwrap_or_else(|| TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: rope.to_string(),
+            });
+
+        let change = DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: file.change_id,
+            },
+
+            content_changes: vec![change],
+        };
+
+        let _ = client.notification("textDocument/didChange", change).await;
+    }
+}
+
+/// Folds a frame's byte changes into one edited region, returned as
+/// `(start, old_end, new_end)`: bytes `start..old_end` of the previous text became
+/// `start..new_end` of the current text. Each change is relative to the text left
+/// by the ones before it.
+pub(crate) fn merge_byte_changes(
+    changes: &[[((usize, usize), usize); 3]],
+) -> Option<(usize, usize, usize)> {
+    let mut region: Option<(usize, usize, isize)> = None;
+
+    for [(_, start), (_, old_end), (_, new_end)] in changes {
+        let (start, old_end, new_end) = (*start, *old_end, *new_end);
+        let growth = new_end as isize - old_end as isize;
+
+        region = Some(match region {
+            None => (start, new_end, growth),
+            Some((lo, hi, delta)) => {
+                let mapped_hi = if hi >= old_end {
+                    (hi as isize + growth) as usize
+                } else if hi > start {
+                    new_end
+                } else {
+                    hi
+                };
 
-    // Avoid race conditions from incremental updates by sending the full document
-    let changes = vec![TextDocumentContentChangeEvent {
-        range: None,
+                (lo.min(start), mapped_hi.max(new_end), delta + growth)
+            }
+        });
+    }
+
+    let (lo, hi, delta) = region?;
+    let old_hi = usize::try_from(hi as isize - delta).ok()?;
+
+    (old_hi >= lo).then_some((lo, old_hi, hi))
+}
+
+/// Builds a ranged change event covering everything edited since `synced`, or `None`
+/// if the changes don't line up with the two texts and a full sync is needed
+fn incremental_change(
+    synced: &Rope,
+    rope: &Rope,
+    changes: &[[((usize, usize), usize); 3]],
+) -> Option<TextDocumentContentChangeEvent> {
+    let (start, old_end, new_end) = merge_byte_changes(changes)?;
+
+    if old_end > synced.len_bytes()
+        || new_end > rope.len_bytes()
+        || synced.len_bytes() - old_end != rope.len_bytes() - new_end
+    {
+        return None;
+    }
+
+    let text = rope.get_byte_slice(start..new_end)?.to_string();
+
+    Some(TextDocumentContentChangeEvent {
+        range: Some(Range::new(
+            byte_to_lsp_position(synced, start),
+            byte_to_lsp_position(synced, old_end),
+        )),
         range_length: None,
-        text: buf.to_string(),
-    }];
+        text,
+    })
+}
 
-    let Some(uri) = Uri::file_path(buf.path.as_str()).ok() else { return; };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let change = DidChangeTextDocumentParams {
-        text_document: VersionedTextDocumentIdentifier {
-            uri,
-            version: file.change_id,
-        },
+    fn change(start: usize, old_end: usize, new_end: usize) -> [((usize, usize), usize); 3] {
+        [((0, start), start), ((0, old_end), old_end), ((0, new_end), new_end)]
+    }
 
-        content_changes: changes,
-    };
+    #[test]
+    fn merges_separate_edits_into_one_region() {
+        // Insert 2 bytes at 4, then delete 3 bytes at 20 (in the shifted text)
+        let merged = merge_byte_changes(&[change(4, 4, 6), change(20, 23, 20)]);
+        assert_eq!(merged, Some((4, 21, 20)));
+    }
+
+    #[test]
+    fn incremental_change_uses_utf16_range() {
+        let synced = Rope::from_str("let 🦀 = 1;\nfoo\n");
+        let rope = Rope::from_str("let 🦀 = 12;\nfoo\n");
 
-    let _ = client.notification("textDocument/didChange", change).await;
+        // "1" starts at byte 11 on the first line; insert "2" after it
+        let event = incremental_change(&synced, &rope, &[change(12, 12, 13)]).unwrap();
+        let range = event.range.unwrap();
+
+        assert_eq!(range.start, Position::new(0, 10));
+        assert_eq!(range.end, Position::new(0, 10));
+        assert_eq!(event.text, "2");
+    }
+
+    #[test]
+    fn mismatched_lengths_fall_back_to_full_sync() {
+        let synced = Rope::from_str("abc");
+        let rope = Rope::from_str("abcdef");
+
+        assert!(incremental_change(&synced, &rope, &[change(3, 3, 4)]).is_none());
+    }
 }
@@ -1,25 +1,23 @@
 use kerbin_core::*;
-use lsp_types::{DidCloseTextDocumentParams, TextDocumentIdentifier};
+use lsp_types::{DidCloseTextDocumentParams, TextDocumentIdentifier, Uri};
 
-use crate::{LspManager, OpenedFile};
+use crate::{LspManager, UriExt};
 
-pub async fn file_close(event_data: EventData<CloseEvent>, manager: ResMut<LspManager>) {
-    get!(Some(event_data), mut manager);
+/// Closes files in their servers once their buffer is closed
+pub async fn file_close(event: EventData<BufferCloseEvent>, manager: ResMut<LspManager>) {
+    get!(Some(event), mut manager);
 
-    let locked = event_data.buffer.read().await;
-    let Some(text_buf) = locked.downcast::<TextBuffer>() else { return; };
-    let Some(file) = text_buf.get_state::<OpenedFile>().await else {
-        return;
-    };
+    let Some(servers) = manager.open_documents.remove(&event.path) else { return; };
+    let Some(uri) = Uri::file_path(&event.path).ok() else { return; };
 
-    let Some(lsp) = manager.get_or_create_client(&file.lang).await.ok().flatten() else { return; };
-
-    let _ = lsp
-        .notification(
-            "textDocument/didClose",
-            DidCloseTextDocumentParams {
-                text_document: TextDocumentIdentifier::new(file.uri.clone()),
-            },
-        )
-        .await;
+    for lsp in manager.clients_named(&servers) {
+        let _ = lsp
+            .notification(
+                "textDocument/didClose",
+                DidCloseTextDocumentParams {
+                    text_document: TextDocumentIdentifier::new(uri.clone()),
+                },
+            )
+            .await;
+    }
 }
@@ -1,6 +1,7 @@
 use kerbin_core::*;
 
 use lsp_types::Uri;
+use ropey::Rope;
 
 use crate::*;
 
@@ -9,71 +10,101 @@ pub struct OpenedFile {
     pub lang: String,
     pub uri: Uri,
     pub change_id: i32,
+
+    /// The servers the file was opened in. Servers that couldn't find a workspace root
+    /// never saw it, so they aren't sent its changes, saves or close
+    pub servers: Vec<String>,
+
+    /// The text the server last received, used to build incremental changes.
+    /// `None` when the server's copy is unknown, so the next change is sent in full.
+    pub synced: Option<Rope>,
 }
 
 impl OpenedFile {
-    pub fn new(lang: String, uri: Uri) -> Self {
+    /// A file whose `didOpen` sent `synced` as its text to `servers`
+    pub fn new(lang: String, uri: Uri, servers: Vec<String>, synced: Rope) -> Self {
         Self {
             lang,
             uri,
             change_id: 0,
+            servers,
+            synced: Some(synced),
         }
     }
 }
 
-/// System that automatically opens files in LSP when they're accessed
-pub async fn open_files(
+/// Opens newly opened files in the servers for their filetype, detecting it if the
+/// buffer hasn't been shown yet
+pub async fn file_opened(
+    event: EventData<BufferOpenEvent>,
     buffers: ResMut<Buffers>,
     lsp_manager: ResMut<LspManager>,
+    filetypes: Res<FiletypeRegistry>,
     log: Res<LogSender>,
 ) {
-    get!(mut buffers, mut lsp_manager, log);
+    get!(Some(event), mut buffers, mut lsp_manager, filetypes, log);
+
+    let Some(mut buf_guard) = buffers.get_mut_path(&event.path).await else { return; };
+    drop(buffers);
+    let Some(current_buffer) = buf_guard.downcast_mut::<TextBuffer>() else { return; };
+
+    if current_buffer.filetype.is_none() {
+        let first_line = current_buffer.get_rope().lines().next().map(|l| l.to_string());
+        current_buffer.filetype = filetypes.detect(&current_buffer.path, first_line.as_deref());
+    }
 
-    let Some(mut current_buffer) = buffers.cur_text_buffer_mut().await else { return; };
     let file_path = current_buffer.path.clone();
+    let rope = current_buffer.get_rope().clone();
     let filetype = current_buffer.filetype.clone();
-    drop(buffers);
 
-    if current_buffer.flags.contains("lsp_opened") {
+    if current_buffer.big_file || current_buffer.flags.contains("lsp_opened") {
         return;
     }
 
     let Some(lang) = filetype else { return };
 
-    if !lsp_manager.lang_to_server.contains_key(&lang) {
+    if lsp_manager.servers_for_lang(&lang).is_empty() {
         return;
     }
 
-    let lang_info = lsp_manager.info_for_lang(&lang).cloned();
+    for (server, e) in lsp_manager.spawn_clients(&lang).await {
+        log.high("lsp", format!("failed to spawn {server} for {lang}: {e}"));
+    }
 
-    let client = match lsp_manager.get_or_create_client(&lang).await {
-        Ok(Some(client)) => client,
-        Ok(None) => return,
-        Err(e) => {
-            log.high("lsp", format!("failed to spawn {lang} server: {e}"));
-            return;
-        }
-    };
+    // Each server finds its own workspace root from its root markers
+    let roots: Vec<(String, Option<Uri>)> = lsp_manager
+        .servers_for_lang(&lang)
+        .iter()
+        .map(|server| (server.clone(), lsp_manager.root_for(server, &file_path)))
+        .collect();
 
-    let Some(root_uri) = find_workspace_root(&file_path, lang_info.as_ref()).or_else(|| {
-        std::env::current_dir()
-            .ok()
-            .and_then(|d| Uri::file_path(&d.to_string_lossy()).ok())
-    }) else {
-        return;
-    };
+    let mut opened_by = vec![];
+    for client in lsp_manager.clients_for_lang(&lang) {
+        let Some(root_uri) = roots
+            .iter()
+            .find(|(server, _)| server == client.lang_id())
+            .and_then(|(_, root)| root.clone())
+        else {
+            continue;
+        };
 
-    if !client.is_flag_set("init") && client.init(root_uri).await.is_ok() {
-        let _ = client
-            .notification("initialized", serde_json::json!({}))
-            .await;
+        if !client.is_flag_set("init") && client.init(root_uri).await.is_ok() {
+            let _ = client
+                .notification("initialized", serde_json::json!({}))
+                .await;
 
-        client.set_flag("init");
+            client.set_flag("init");
+        }
+
+        if client.open(&file_path, rope.to_string()).await.is_ok() {
+            opened_by.push(client.lang_id().to_string());
+        }
     }
 
-    if client.open(&file_path).await.is_ok() {
+    if !opened_by.is_empty() {
         let Some(uri) = Uri::file_path(&file_path).ok() else { return; };
+        lsp_manager.open_documents.insert(file_path, opened_by.clone());
         current_buffer.flags.insert("lsp_opened");
-        current_buffer.set_state(OpenedFile::new(lang, uri));
+        current_buffer.set_state(OpenedFile::new(lang, uri, opened_by, rope));
     }
 }
@@ -21,33 +21,18 @@ pub async fn file_saved(
         return;
     };
 
-    let lang = client_info.lang.clone();
+    let servers = client_info.servers.clone();
     let Some(uri) = Uri::file_path(&cur_buf.path).ok() else { return; };
 
-    let Some(client) = lsp_manager.get_or_create_client(&lang).await.ok().flatten() else { return; };
-
-    let _ = client
-        .notification(
-            "textDocument/didSave",
-            DidSaveTextDocumentParams {
-                text_document: TextDocumentIdentifier { uri: uri.clone() },
-                text: None,
-            },
-        )
-        .await;
-
-    let fmt_config = lsp_manager.info_for_lang(&lang).and_then(|i| i.format.clone());
-
-    if let Some(fmt) = fmt_config
-        && fmt.format_on_save
-    {
-        match fmt.kind {
-            FormatterKind::Lsp => {
-                send_lsp_format_request(cur_buf, &mut lsp_manager, &lang, uri, true).await;
-            }
-            FormatterKind::External(cmd, args) => {
-                send_external_format_request(cur_buf, &cmd, &args).await;
-            }
-        }
+    for client in lsp_manager.clients_named(&servers) {
+        let _ = client
+            .notification(
+                "textDocument/didSave",
+                DidSaveTextDocumentParams {
+                    text_document: TextDocumentIdentifier { uri: uri.clone() },
+                    text: None,
+                },
+            )
+            .await;
     }
 }
@@ -0,0 +1,153 @@
+use kerbin_core::*;
+use lsp_types::{DidOpenTextDocumentParams, TextDocumentItem};
+
+use crate::{ClientFacade, LspManager, OpenedFile};
+
+/// Is emitted when a language server's process exits without being asked to
+pub struct ServerExitedEvent {
+    /// The name the server was registered with
+    pub server: String,
+
+    /// The process's exit code, `None` when it was killed by a signal
+    pub code: Option<i32>,
+
+    /// Whether the server will be restarted automatically
+    pub restarting: bool,
+}
+
+/// Opens every buffer served by `servers` with them again, spawning and initializing the
+/// servers first. Used after a restart, since the new processes know nothing of the files.
+/// Buffers are sent as they are in the editor, unsaved changes included.
+pub async fn reopen_buffers(
+    buffers: &Buffers,
+    manager: &mut LspManager,
+    servers: &[String],
+    log: &LogSender,
+) {
+    for buf in &buffers.buffers {
+        let mut buf_guard = buf.write().await;
+        let Some(text_buf) = buf_guard.downcast_mut::<TextBuffer>() else { continue };
+
+        let path = text_buf.path.clone();
+        let rope = text_buf.get_rope().clone();
+        let Some(mut file) = text_buf.get_state_mut::<OpenedFile>().await else { continue };
+
+        let lang_servers = manager.servers_for_lang(&file.lang).to_vec();
+        let mut reopened = false;
+        for server in servers.iter().filter(|s| lang_servers.contains(s)) {
+            let root = manager.root_for(server, &path);
+            let client = match manager.get_or_create_server(server).await {
+                Ok(Some(client)) => client,
+                Ok(None) => continue,
+                Err(e) => {
+                    log.high("lsp", format!("failed to spawn {server}: {e}"));
+                    continue;
+                }
+            };
+
+            if !client.is_initialized() {
+                let Some(root) = root else { continue };
+                if client.init(root).await.is_err() {
+                    continue;
+                }
+                let _ = client
+                    .notification("initialized", serde_json::json!({}))
+                    .await;
+                client.set_flag("init");
+            }
+
+            reopened |= client
+                .notification(
+                    "textDocument/didOpen",
+                    DidOpenTextDocumentParams {
+                        text_document: TextDocumentItem {
+                            uri: file.uri.clone(),
+                            language_id: file.lang.clone(),
+                            version: file.change_id,
+                            text: rope.to_string(),
+                        },
+                    },
+                )
+                .await
+                .is_ok();
+        }
+
+        if reopened {
+            file.synced = Some(rope);
+        }
+    }
+}
+
+/// System that notices language servers whose process exited, emitting a
+/// `ServerExitedEvent` for each, and respawns the ones whose automatic restart is due
+pub async fn monitor_servers(
+    buffers: Res<Buffers>,
+    lsp_manager: ResMut<LspManager>,
+    log: Res<LogSender>,
+) {
+    get!(buffers, mut lsp_manager, log);
+
+    for server in lsp_manager.reap_exited() {
+        let Some(exited) = lsp_manager.exited.get(&server).cloned() else { continue };
+        let how = match exited.code {
+            Some(code) => format!("exited with code {code}"),
+            None => "was killed".to_string(),
+        };
+        let message = match exited.restart_at {
+            Some(at) => format!(
+                "{server} {how}; restarting in {}s",
+                at.saturating_duration_since(std::time::Instant::now()).as_secs()
+            ),
+            None => format!("{server} {how}"),
+        };
+        log.high("lsp", message);
+
+        EVENT_BUS
+            .emit(ServerExitedEvent {
+                server,
+                code: exited.code,
+                restarting: exited.restart_at.is_some(),
+            })
+            .await;
+    }
+
+    let due = lsp_manager.take_due_restarts();
+    if due.is_empty() {
+        return;
+    }
+
+    reopen_buffers(&buffers, &mut lsp_manager, &due, &log).await;
+    log.low("lsp", format!("{}: restarted", due.join(", ")));
+}
+
+/// Keeps the `lsp` statusline segment (`%{lsp}`) at the status of the current buffer's
+/// language servers, leaving it empty when none were started
+pub async fn update_lsp_segment(
+    buffers: Res<Buffers>,
+    lsp_manager: Res<LspManager>,
+    segments: ResMut<StatuslineSegments>,
+) {
+    get!(buffers, lsp_manager, mut segments);
+
+    let filetype = match buffers.cur_buffer_as::<TextBuffer>().await {
+        Some(tb) => tb.filetype.clone(),
+        None => None,
+    };
+
+    let summary = filetype
+        .map(|lang| {
+            lsp_manager
+                .servers_for_lang(&lang)
+                .iter()
+                .filter_map(|server| lsp_manager.server_summary(server))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default();
+
+    if summary.is_empty() {
+        segments.0.remove("lsp");
+    } else {
+        segments.0.insert("lsp".to_string(), summary.into());
+    }
+}
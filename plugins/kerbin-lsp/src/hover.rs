@@ -4,7 +4,7 @@ use kerbin_core::*;
 
 const PRIORITY: i32 = 5;
 use lsp_types::{
-    Hover, HoverContents, HoverParams, LanguageString, MarkedString, Position,
+    Hover, HoverContents, HoverParams, LanguageString, MarkedString,
     TextDocumentIdentifier, TextDocumentPositionParams, WorkDoneProgressParams,
 };
 use ratatui::{
@@ -13,7 +13,7 @@ use ratatui::{
     widgets::{Block, BorderType, Paragraph},
 };
 
-use crate::{JsonRpcMessage, LspManager, OpenedFile};
+use crate::{JsonRpcMessage, LspManager, OpenedFile, byte_to_lsp_position};
 use kerbin_tree_sitter::{grammar_manager::GrammarManager, state::highlight_text};
 
 struct HoverWidget {
@@ -72,6 +72,7 @@ pub struct HoverState {
 }
 
 #[derive(Command)]
+#[command(category = "lsp")]
 pub enum HoverCommand {
     #[command]
     /// Request the display of a hover at the cursor's position
@@ -97,22 +98,21 @@ impl Command<State> for HoverCommand {
                     return false;
                 };
 
-                let Some(client) = lsps.get_or_create_client(&file.lang).await.ok().flatten() else {
+                let Some(client) =
+                    lsps.client_for(&file.lang, |caps| caps.hover_provider.is_some()).await
+                else {
                     return false;
                 };
 
                 let cursor = buf.primary_cursor();
                 let cursor_byte = cursor.get_cursor_byte().min(buf.len());
 
-                let line = buf.byte_to_line_clamped(cursor_byte);
-                let character = cursor_byte - buf.line_to_byte_clamped(line);
-
                 let params = HoverParams {
                     text_document_position_params: TextDocumentPositionParams {
                         text_document: TextDocumentIdentifier {
                             uri: file.uri.clone(),
                         },
-                        position: Position::new(line as u32, character as u32),
+                        position: byte_to_lsp_position(buf.get_rope(), cursor_byte),
                     },
                     work_done_progress_params: WorkDoneProgressParams::default(),
                 };
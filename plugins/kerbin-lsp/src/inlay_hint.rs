@@ -0,0 +1,239 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use kerbin_core::*;
+use lsp_types::{
+    InlayHint, InlayHintLabel, InlayHintParams, OneOf, Position, Range, ServerCapabilities,
+    TextDocumentIdentifier, WorkDoneProgressParams,
+};
+use ratatui::style::Style;
+use ropey::Rope;
+use tokio::sync::RwLock;
+
+use crate::{
+    DiagnosticsConfig, JsonRpcMessage, LspManager, OpenedFile, byte_to_lsp_position,
+    lsp_position_to_byte,
+};
+
+const NS_INLAY_HINTS: &str = "lsp::inlay_hints";
+
+/// How long the buffer has to go unchanged before hints are requested again after an edit
+const EDIT_DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(State, Default)]
+pub struct InlayHintState {
+    /// Line range and buffer version hints were last requested for
+    pub requested: Option<(usize, usize, u128)>,
+    /// The `textDocument/inlayHint` request waiting on an answer
+    pub pending: Option<i32>,
+    /// The buffer version last seen and when it was first seen, used to wait out typing
+    pub seen: Option<(u128, Instant)>,
+}
+
+fn supports_inlay_hints(caps: &ServerCapabilities) -> bool {
+    !matches!(caps.inlay_hint_provider, None | Some(OneOf::Left(false)))
+}
+
+/// The text a hint is drawn as: its label with the padding it asked for
+fn hint_text(hint: &InlayHint) -> String {
+    let label = match &hint.label {
+        InlayHintLabel::String(label) => label.clone(),
+        InlayHintLabel::LabelParts(parts) => parts.iter().map(|p| p.value.as_str()).collect(),
+    };
+
+    let left = if hint.padding_left == Some(true) { " " } else { "" };
+    let right = if hint.padding_right == Some(true) { " " } else { "" };
+    format!("{left}{}{right}", label.replace('\n', " "))
+}
+
+/// Inline virtual text for each hint, anchored at the byte its position points to.
+/// Being virtual, hints shift how the line is drawn without touching the text columns
+/// the cursor moves through.
+fn hint_marks(rope: &Rope, hints: &[InlayHint], style: Style) -> Vec<ExtmarkBuilder> {
+    hints
+        .iter()
+        .map(|hint| {
+            ExtmarkBuilder::new(NS_INLAY_HINTS, lsp_position_to_byte(rope, hint.position))
+                .with_kind(ExtmarkKind::VirtualText {
+                    chunks: vec![StyledChunk {
+                        text: hint_text(hint),
+                        style,
+                    }],
+                    pos: VirtTextPos::Inline,
+                })
+        })
+        .collect()
+}
+
+/// System that requests inlay hints for the lines in view, again whenever the view scrolls
+/// or the text settles after an edit. Toggled with `set inlay-hints`.
+pub async fn update_inlay_hints(
+    bufs: ResMut<Buffers>,
+    lsps: ResMut<LspManager>,
+    config: Res<DiagnosticsConfig>,
+) {
+    get!(mut bufs, mut lsps, config);
+
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+        return;
+    };
+
+    if !config.inlay_hints {
+        let shown = buf
+            .get_state::<InlayHintState>()
+            .await
+            .is_some_and(|hints| hints.requested.is_some());
+        if shown {
+            buf.renderer.clear_extmark_ns(NS_INLAY_HINTS);
+            buf.set_state(InlayHintState::default());
+        }
+        return;
+    }
+
+    let Some(file) = buf.get_state::<OpenedFile>().await else {
+        return;
+    };
+    let lang = file.lang.clone();
+    let uri = file.uri.clone();
+    drop(file);
+
+    let version = *buf.version();
+    let start_line = buf.renderer.byte_scroll;
+    let end_line = (start_line + buf.renderer.screen_rows.len().max(1)).min(buf.len_lines());
+    let wanted = (start_line, end_line, version);
+
+    let mut hints = buf.get_or_insert_state_mut(InlayHintState::default).await;
+    if hints.seen.is_none_or(|(seen, _)| seen != version) {
+        hints.seen = Some((version, Instant::now()));
+    }
+    if hints.requested == Some(wanted) {
+        return;
+    }
+
+    // Scrolling asks right away, but edits wait until typing stops
+    let edited = hints.requested.is_some_and(|(_, _, v)| v != version);
+    if edited && hints.seen.is_some_and(|(_, at)| at.elapsed() < EDIT_DEBOUNCE) {
+        return;
+    }
+    drop(hints);
+
+    let Some(client) = lsps.client_for(&lang, supports_inlay_hints).await else {
+        return;
+    };
+    if !client.server_capabilities.as_ref().is_some_and(supports_inlay_hints) {
+        return;
+    }
+
+    let end = buf.line_to_byte(end_line).unwrap_or_else(|| buf.len());
+    let params = InlayHintParams {
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        text_document: TextDocumentIdentifier::new(uri),
+        range: Range::new(
+            Position::new(start_line as u32, 0),
+            byte_to_lsp_position(buf.get_rope(), end),
+        ),
+    };
+
+    if let Ok(request_id) = client.request("textDocument/inlayHint", params).await {
+        let mut hints = buf.get_or_insert_state_mut(InlayHintState::default).await;
+        hints.requested = Some(wanted);
+        hints.pending = Some(request_id);
+    }
+}
+
+async fn buffer_waiting(state: &State, id: i32) -> Option<Arc<RwLock<dyn KerbinBuffer>>> {
+    let bufs = state.lock_state::<Buffers>().await;
+
+    for buf in &bufs.buffers {
+        let buf_guard = buf.read().await;
+        if let Some(text_buf) = buf_guard.downcast::<TextBuffer>()
+            && let Some(hints) = text_buf.get_state::<InlayHintState>().await
+            && hints.pending == Some(id)
+        {
+            return Some(buf.clone());
+        }
+    }
+
+    None
+}
+
+pub async fn handle_inlay_hints(state: &State, msg: &JsonRpcMessage) {
+    let JsonRpcMessage::Response(response) = msg else {
+        return;
+    };
+
+    let Some(buf) = buffer_waiting(state, response.id).await else {
+        return;
+    };
+
+    let hints: Vec<InlayHint> = response
+        .result
+        .as_ref()
+        .and_then(|r| serde_json::from_value::<Option<Vec<_>>>(r.clone()).ok())
+        .flatten()
+        .unwrap_or_default();
+
+    let style = state
+        .lock_state::<Theme>()
+        .await
+        .get_fallback_default(["ui.inlay_hint", "ui.virtual_text"]);
+
+    let mut buf_guard = buf.write().await;
+    let Some(text_buf) = buf_guard.downcast_mut::<TextBuffer>() else {
+        return;
+    };
+    let version = *text_buf.version();
+    let Some(mut hint_state) = text_buf.get_state_mut::<InlayHintState>().await else {
+        return;
+    };
+    hint_state.pending = None;
+
+    // An answer for text that has changed since is dropped, the next request replaces it.
+    // A failed or cancelled request keeps the hints already shown.
+    let current = hint_state.requested.is_some_and(|(_, _, v)| v == version);
+    if !current || response.error.is_some() {
+        return;
+    }
+    drop(hint_state);
+
+    let marks = hint_marks(text_buf.get_rope(), &hints, style);
+    text_buf.renderer.set_namespace(version, NS_INLAY_HINTS, marks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::InlayHintLabelPart;
+
+    #[test]
+    fn hints_are_labels_with_padding() {
+        let hint = |label: InlayHintLabel, left, right| InlayHint {
+            position: Position::new(0, 0),
+            label,
+            kind: None,
+            text_edits: None,
+            tooltip: None,
+            padding_left: left,
+            padding_right: right,
+            data: None,
+        };
+
+        assert_eq!(hint_text(&hint(": i32".to_string().into(), None, None)), ": i32");
+        assert_eq!(
+            hint_text(&hint("count:".to_string().into(), Some(false), Some(true))),
+            "count: "
+        );
+
+        let parts = vec![
+            InlayHintLabelPart {
+                value: "-> ".to_string(),
+                ..Default::default()
+            },
+            InlayHintLabelPart {
+                value: "Vec<u8>".to_string(),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(hint_text(&hint(parts.into(), Some(true), None)), " -> Vec<u8>");
+    }
+}
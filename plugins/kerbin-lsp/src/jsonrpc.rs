@@ -14,6 +14,10 @@ pub struct JsonRpcNotification {
     pub jsonrpc: String,
     pub method: String,
     pub params: Value,
+
+    /// Name of the server that sent this, filled in when it is drained from the client
+    #[serde(skip)]
+    pub server: String,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -32,6 +36,10 @@ pub struct JsonRpcServerRequest {
     pub id: i32,
     pub method: String,
     pub params: Value,
+
+    /// Name of the server that sent this, filled in when it is drained from the client
+    #[serde(skip)]
+    pub server: String,
 }
 
 #[derive(Debug, Clone)]
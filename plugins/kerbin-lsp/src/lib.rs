@@ -21,6 +21,9 @@ pub use events::*;
 pub mod manager;
 pub use manager::*;
 
+pub mod health;
+pub use health::*;
+
 pub mod diagnostics;
 pub use diagnostics::*;
 
@@ -39,12 +42,33 @@ pub use text_edit::apply_text_edits;
 pub mod format;
 pub use format::*;
 
+pub mod rename;
+pub use rename::*;
+
+pub mod signature;
+pub use signature::*;
+
+pub mod snippet;
+pub use snippet::*;
+
+pub mod code_action;
+pub use code_action::*;
+
+pub mod inlay_hint;
+pub use inlay_hint::*;
+
 pub use lsp_types::*;
 
 async fn reset_config_state(lsp_manager: ResMut<LspManager>) {
     let mut manager = lsp_manager.get().await;
     manager.server_map.clear();
     manager.lang_to_server.clear();
+    manager.exited.clear();
+    manager.restart_counts.clear();
+}
+
+async fn shutdown_servers(lsp_manager: ResMut<LspManager>) {
+    lsp_manager.get().await.shutdown().await;
 }
 
 define_plugin! {
@@ -55,6 +79,10 @@ define_plugin! {
         LspHandlerManager,
         LspManager,
         GlobalDiagnostics,
+        ServerDiagnostics,
+        DiagnosticsConfig,
+        FormatConfig,
+        SnippetState,
     ],
 
     commands: [
@@ -62,22 +90,50 @@ define_plugin! {
         HoverCommand,
         CompletionCommand,
         NavigationCommand,
+        DiagnosticCommand,
         FormatCommand,
+        RenameCommand,
+        SignatureHelpCommand,
+        SnippetCommand,
+        CodeActionCommand,
     ],
 
     hooks: [
         hooks::ResetState => reset_config_state,
+        hooks::Shutdown => shutdown_servers,
+        hooks::PostUpdate => diagnostics::update_diagnostics_segment,
+        hooks::PostUpdate => health::monitor_servers,
+        hooks::PostUpdate => health::update_lsp_segment,
     ],
 
     events: [
         SaveEvent => file_save::file_saved,
-        CloseEvent => file_close::file_close,
+        BufferOpenEvent => file_open::file_opened,
+        BufferCloseEvent => file_close::file_close,
+        ModeLeaveEvent => autocomplete::trash_on_insert_leave,
+        ModeLeaveEvent => snippet::end_snippet_on_insert_leave,
     ],
 }
 
 pub async fn init(state: &mut State) {
     plugin_init(state).await;
 
+    state
+        .lock_state::<ConfigurableRegistry>()
+        .await
+        .register::<DiagnosticsConfig>();
+
+    state
+        .lock_state::<ConfigurableRegistry>()
+        .await
+        .register::<FormatConfig>();
+
+    // Format-on-save has to run before the write, so it hooks the write command itself
+    state
+        .lock_state::<CommandInterceptorRegistry>()
+        .await
+        .on_command::<BufferCommand>(|cmd, state| Box::pin(format_on_save_intercept(cmd, state)));
+
     let mut handler_manager = state.lock_state::<LspHandlerManager>().await;
 
     handler_manager.on_global_notify("textDocument/publishDiagnostics", |state, msg| {
@@ -110,7 +166,31 @@ pub async fn init(state: &mut State) {
     handler_manager.on_global_response("textDocument/declaration", |state, msg| {
         Box::pin(handle_navigation(state, msg))
     });
+    handler_manager.on_global_response("textDocument/signatureHelp", |state, msg| {
+        Box::pin(handle_signature_help(state, msg))
+    });
     handler_manager.on_global_response("textDocument/formatting", |state, msg| {
         Box::pin(handle_format(state, msg))
     });
+    handler_manager.on_global_response("textDocument/prepareRename", |state, msg| {
+        Box::pin(handle_prepare_rename(state, msg))
+    });
+    handler_manager.on_global_response("textDocument/rename", |state, msg| {
+        Box::pin(handle_rename(state, msg))
+    });
+    handler_manager.on_global_response("textDocument/codeAction", |state, msg| {
+        Box::pin(handle_code_action(state, msg))
+    });
+    handler_manager.on_global_response("textDocument/inlayHint", |state, msg| {
+        Box::pin(handle_inlay_hints(state, msg))
+    });
+    handler_manager.on_global_response("codeAction/resolve", |state, msg| {
+        Box::pin(handle_code_action_resolve(state, msg))
+    });
+    handler_manager.on_global_response("workspace/executeCommand", |state, msg| {
+        Box::pin(handle_execute_command(state, msg))
+    });
+    handler_manager.on_global_server_request("workspace/applyEdit", |state, msg| {
+        Box::pin(handle_apply_edit(state, msg))
+    });
 }
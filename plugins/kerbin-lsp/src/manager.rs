@@ -1,7 +1,11 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use kerbin_core::*;
-use lsp_types::Uri;
+use lsp_types::{ServerCapabilities, Uri};
 use serde::Deserialize;
 use tokio::process::ChildStdin;
 
@@ -30,6 +34,14 @@ pub struct LangInfo {
 
     #[serde(skip)]
     pub format: Option<FormatterConfig>,
+
+    /// Overrides the client's default request timeout
+    #[serde(skip)]
+    pub request_timeout: Option<Duration>,
+
+    /// Respawn the server with a backoff when its process exits on its own
+    #[serde(skip)]
+    pub auto_restart: bool,
 }
 
 impl LangInfo {
@@ -39,6 +51,8 @@ impl LangInfo {
             args: vec![],
             roots: vec![],
             format: None,
+            request_timeout: None,
+            auto_restart: false,
         }
     }
 
@@ -63,6 +77,16 @@ impl LangInfo {
         self
     }
 
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_auto_restart(mut self, auto_restart: bool) -> Self {
+        self.auto_restart = auto_restart;
+        self
+    }
+
     pub fn with_arg(mut self, arg: impl ToString) -> Self {
         self.args.push(arg.to_string());
         self
@@ -89,17 +113,53 @@ pub struct LspManager {
     /// Server name → language server config (command, args, roots, formatter)
     pub server_map: HashMap<String, LangInfo>,
 
-    /// Language name → server name (many languages can share one server)
-    pub lang_to_server: HashMap<String, String>,
+    /// Language name → server names in priority order (many languages can share one
+    /// server, and one language can have several, e.g. an analyzer and a linter)
+    pub lang_to_server: HashMap<String, Vec<String>>,
 
     /// Running clients keyed by server name
     pub client_map: HashMap<String, LspClient<ChildStdin>>,
 
+    /// Path → the servers each file was sent to with `didOpen`, so they can be closed
+    /// once their buffer is gone
+    pub open_documents: HashMap<String, Vec<String>>,
+
     /// Server names whose process failed to spawn; won't retry until lsp-restart
     pub spawn_failed: std::collections::HashSet<String>,
+
+    /// Servers whose process exited while the editor was running, by server name
+    pub exited: HashMap<String, ExitedServer>,
+
+    /// Automatic restarts of each server since it last ran long enough or was restarted
+    /// by hand, used to give up on servers that crash in a loop
+    pub restart_counts: HashMap<String, u32>,
+}
+
+/// A server whose process exited on its own
+#[derive(Clone, Debug)]
+pub struct ExitedServer {
+    /// The process's exit code, `None` when it was killed by a signal
+    pub code: Option<i32>,
+
+    /// When the server is due to be respawned, `None` if it won't be automatically
+    pub restart_at: Option<Instant>,
+}
+
+/// How many times in a row a server is automatically restarted before giving up
+pub const MAX_AUTO_RESTARTS: u32 = 5;
+
+/// How long a server has to stay up before its automatic restarts are forgotten
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+
+/// The wait before the `attempt`th automatic restart (counting from 0): one second,
+/// doubling each attempt, capped at 30 seconds
+pub fn restart_backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.min(5)).min(Duration::from_secs(30))
 }
 
 impl LspManager {
+    /// Registers a server for `langs`. Registering another server for a language adds
+    /// it after the ones already there, so the first registered has the highest priority.
     pub fn register_server(
         &mut self,
         server_name: impl Into<String>,
@@ -109,63 +169,167 @@ impl LspManager {
         let server_name = server_name.into();
         self.server_map.insert(server_name.clone(), info);
         for lang in langs {
-            self.lang_to_server.insert(lang.into(), server_name.clone());
+            let servers = self.lang_to_server.entry(lang.into()).or_default();
+            if !servers.contains(&server_name) {
+                servers.push(server_name.clone());
+            }
         }
     }
 
-    /// Resolve a language name to its server name, if registered.
+    /// Resolve a language name to its highest priority server name, if registered.
     pub fn server_for_lang(&self, lang: &str) -> Option<&str> {
-        self.lang_to_server.get(lang).map(|s| s.as_str())
+        self.servers_for_lang(lang).first().map(|s| s.as_str())
     }
 
-    /// Retrieves a running client for the given language, creating it if needed.
-    ///
-    /// Returns `None` (no error) if the language has no registered server.
-    /// Returns `Err` if the server is registered but failed to spawn.
-    pub async fn get_or_create_client(
+    /// All servers registered for a language, in priority order.
+    pub fn servers_for_lang(&self, lang: &str) -> &[String] {
+        self.lang_to_server.get(lang).map(|s| s.as_slice()).unwrap_or_default()
+    }
+
+    /// Retrieves the running client for the given server, creating it if needed.
+    pub async fn get_or_create_server(
         &mut self,
-        lang: &str,
+        server_name: &str,
     ) -> Result<Option<&mut LspClient<ChildStdin>>, std::io::Error> {
-        let Some(server_name) = self.lang_to_server.get(lang).cloned() else {
-            return Ok(None);
-        };
-
-        if self.spawn_failed.contains(&server_name) {
+        // Exited servers wait for their scheduled restart (or lsp-restart)
+        if self.spawn_failed.contains(server_name) || self.exited.contains_key(server_name) {
             return Ok(None);
         }
 
-        if !self.client_map.contains_key(&server_name) {
-            let Some(info) = self.server_map.get(&server_name) else {
+        if !self.client_map.contains_key(server_name) {
+            let Some(info) = self.server_map.get(server_name) else {
                 return Ok(None);
             };
 
-            let client =
-                match LspClient::spawned(&server_name, &info.command, info.args.clone()).await {
+            let mut client =
+                match LspClient::spawned(server_name, &info.command, info.args.clone()).await {
                     Ok(c) => c,
                     Err(e) => {
-                        self.spawn_failed.insert(server_name);
+                        self.spawn_failed.insert(server_name.to_string());
                         return Err(e);
                     }
                 };
 
-            self.client_map.insert(server_name.clone(), client);
+            if let Some(timeout) = info.request_timeout {
+                client.set_request_timeout(timeout);
+            }
+
+            self.client_map.insert(server_name.to_string(), client);
         }
 
-        Ok(self.client_map.get_mut(&server_name))
+        Ok(self.client_map.get_mut(server_name))
     }
 
-    /// Returns a human-readable status string for the server handling the given language.
+    /// Retrieves a running client for the given language's highest priority server,
+    /// creating it if needed.
+    ///
+    /// Returns `None` (no error) if the language has no registered server.
+    /// Returns `Err` if the server is registered but failed to spawn.
+    pub async fn get_or_create_client(
+        &mut self,
+        lang: &str,
+    ) -> Result<Option<&mut LspClient<ChildStdin>>, std::io::Error> {
+        let Some(server_name) = self.server_for_lang(lang).map(str::to_string) else {
+            return Ok(None);
+        };
+        self.get_or_create_server(&server_name).await
+    }
+
+    /// Starts every server registered for the language that isn't running yet.
+    /// Returns the servers that failed to spawn along with their errors.
+    pub async fn spawn_clients(&mut self, lang: &str) -> Vec<(String, std::io::Error)> {
+        let mut errors = vec![];
+        for server_name in self.servers_for_lang(lang).to_vec() {
+            if let Err(e) = self.get_or_create_server(&server_name).await {
+                errors.push((server_name, e));
+            }
+        }
+        errors
+    }
+
+    /// The running clients of every server registered for the language, in priority order.
+    pub fn clients_for_lang(&mut self, lang: &str) -> Vec<&mut LspClient<ChildStdin>> {
+        let servers = self.lang_to_server.get(lang).cloned().unwrap_or_default();
+        self.clients_named(&servers)
+    }
+
+    /// The running clients of the named servers, in the order given. For files, which
+    /// only sync with the servers that opened them
+    pub fn clients_named(&mut self, servers: &[String]) -> Vec<&mut LspClient<ChildStdin>> {
+        let mut clients: Vec<(usize, &mut LspClient<ChildStdin>)> = self
+            .client_map
+            .iter_mut()
+            .filter_map(|(name, client)| {
+                servers.iter().position(|s| s == name).map(|idx| (idx, client))
+            })
+            .collect();
+        clients.sort_by_key(|(idx, _)| *idx);
+        clients.into_iter().map(|(_, client)| client).collect()
+    }
+
+    /// Picks the client for a request on the given language: the highest priority server
+    /// whose capabilities pass `supports`. Servers that haven't reported capabilities yet
+    /// are only used when no server is known to support the request.
+    pub async fn client_for(
+        &mut self,
+        lang: &str,
+        supports: impl Fn(&ServerCapabilities) -> bool,
+    ) -> Option<&mut LspClient<ChildStdin>> {
+        self.spawn_clients(lang).await;
+
+        let servers = self.servers_for_lang(lang);
+        let pick = servers
+            .iter()
+            .find(|name| {
+                self.client_map
+                    .get(*name)
+                    .and_then(|c| c.server_capabilities.as_ref())
+                    .is_some_and(&supports)
+            })
+            .or_else(|| servers.iter().find(|name| self.client_map.contains_key(*name)))?
+            .clone();
+
+        self.client_map.get_mut(&pick)
+    }
+
+    /// Returns a human-readable status string for the servers handling the given language.
     pub fn lang_status(&self, lang: &str) -> String {
-        let Some(server_name) = self.lang_to_server.get(lang) else {
+        let servers = self.servers_for_lang(lang);
+        if servers.is_empty() {
             return "unknown language".to_string();
-        };
+        }
+
+        servers
+            .iter()
+            .map(|server_name| self.server_status(server_name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
 
+    fn server_status(&self, server_name: &str) -> String {
         if let Some(client) = self.client_map.get(server_name) {
             if client.is_initialized() {
                 format!("{server_name}: running (initialized)")
             } else {
                 format!("{server_name}: running (not yet initialized)")
             }
+        } else if let Some(exited) = self.exited.get(server_name) {
+            let how = match exited.code {
+                Some(code) => format!("exited with code {code}"),
+                None => "was killed".to_string(),
+            };
+            let restarts = self.restart_counts.get(server_name).copied().unwrap_or(0);
+            match exited.restart_at {
+                Some(at) => format!(
+                    "{server_name}: {how}, restarting in {}s (attempt {}/{MAX_AUTO_RESTARTS})",
+                    at.saturating_duration_since(Instant::now()).as_secs(),
+                    restarts + 1,
+                ),
+                None if restarts >= MAX_AUTO_RESTARTS => format!(
+                    "{server_name}: {how}, gave up after {restarts} restarts (use lsp-restart to retry)"
+                ),
+                None => format!("{server_name}: {how} (use lsp-restart to restart)"),
+            }
         } else if self.spawn_failed.contains(server_name) {
             format!("{server_name}: spawn failed (use lsp-restart to retry)")
         } else {
@@ -173,33 +337,144 @@ impl LspManager {
         }
     }
 
-    /// Removes the running/failed client for the language's server so it can be respawned.
+    /// A short status of the server for the statusline: its name while it's up, with what's
+    /// wrong appended otherwise. `None` when it was never started.
+    pub fn server_summary(&self, server_name: &str) -> Option<String> {
+        if let Some(client) = self.client_map.get(server_name) {
+            return Some(if client.is_initialized() {
+                server_name.to_string()
+            } else {
+                format!("{server_name} (starting)")
+            });
+        }
+
+        if let Some(exited) = self.exited.get(server_name) {
+            let what = if exited.restart_at.is_some() { "restarting" } else { "exited" };
+            return Some(format!("{server_name} ({what})"));
+        }
+
+        self.spawn_failed
+            .contains(server_name)
+            .then(|| format!("{server_name} (failed)"))
+    }
+
+    /// Removes the running/failed/exited clients of every server for the language so they
+    /// can be respawned, forgetting their automatic restarts. Dropping a client kills its
+    /// server process.
     ///
-    /// Returns the server name if anything was removed, `None` otherwise.
-    pub fn reset_client(&mut self, lang: &str) -> Option<String> {
-        let server_name = self.lang_to_server.get(lang)?.clone();
-        let was_running = self.client_map.remove(&server_name).is_some();
-        let was_failed = self.spawn_failed.remove(&server_name);
-        if was_running || was_failed {
-            Some(server_name)
-        } else {
-            None
+    /// Returns the names of the servers that had anything removed.
+    pub fn reset_clients(&mut self, lang: &str) -> Vec<String> {
+        let mut reset = vec![];
+        for server_name in self.servers_for_lang(lang).to_vec() {
+            let was_running = self.client_map.remove(&server_name).is_some();
+            let was_failed = self.spawn_failed.remove(&server_name);
+            let was_exited = self.exited.remove(&server_name).is_some();
+            self.restart_counts.remove(&server_name);
+            if was_running || was_failed || was_exited {
+                reset.push(server_name);
+            }
+        }
+        reset
+    }
+
+    /// Shuts every running server down at once, so exiting waits on the slowest server
+    /// rather than all of them in turn. The clients are dropped afterwards, which kills
+    /// any process that's somehow still around.
+    pub async fn shutdown(&mut self) {
+        let mut shutdowns = tokio::task::JoinSet::new();
+        for (name, mut client) in self.client_map.drain() {
+            shutdowns.spawn(async move {
+                if let Err(e) = client.shutdown().await {
+                    tracing::error!("Failed to shut down {name}: {e}");
+                }
+            });
+        }
+        shutdowns.join_all().await;
+    }
+
+    /// Removes the clients whose server process has exited, recording them in `exited` and
+    /// scheduling a restart for servers registered with `auto_restart` that haven't used up
+    /// their `MAX_AUTO_RESTARTS`. Servers that stayed up for a while have their restart
+    /// count forgotten first.
+    ///
+    /// Returns the names of the servers that exited.
+    pub fn reap_exited(&mut self) -> Vec<String> {
+        let exited: Vec<(String, Option<i32>, Duration)> = self
+            .client_map
+            .iter_mut()
+            .filter_map(|(name, client)| {
+                let status = client.exit_status()?;
+                Some((name.clone(), status.code(), client.uptime()))
+            })
+            .collect();
+
+        let mut names = Vec::with_capacity(exited.len());
+        for (name, code, uptime) in exited {
+            self.client_map.remove(&name);
+
+            if uptime >= STABLE_UPTIME {
+                self.restart_counts.remove(&name);
+            }
+            let restarts = self.restart_counts.get(&name).copied().unwrap_or(0);
+            let auto_restart = self.server_map.get(&name).is_some_and(|info| info.auto_restart);
+            let restart_at = (auto_restart && restarts < MAX_AUTO_RESTARTS)
+                .then(|| Instant::now() + restart_backoff(restarts));
+
+            self.exited.insert(name.clone(), ExitedServer { code, restart_at });
+            names.push(name);
+        }
+        names
+    }
+
+    /// Takes the exited servers whose automatic restart is due, counting the restart.
+    /// They're ready to be spawned again with `get_or_create_server`.
+    pub fn take_due_restarts(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let due: Vec<String> = self
+            .exited
+            .iter()
+            .filter(|(_, exited)| exited.restart_at.is_some_and(|at| at <= now))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for name in &due {
+            self.exited.remove(name);
+            *self.restart_counts.entry(name.clone()).or_default() += 1;
         }
+        due
+    }
+
+    /// The workspace root the server should use for a file, found from its root markers
+    /// and falling back to the working directory
+    pub fn root_for(&self, server_name: &str, file_path: &str) -> Option<Uri> {
+        find_workspace_root(file_path, self.server_map.get(server_name)).or_else(|| {
+            std::env::current_dir()
+                .ok()
+                .and_then(|d| Uri::file_path(&d.to_string_lossy()).ok())
+        })
     }
 
     /// Returns all language names served by the given server.
     pub fn langs_for_server(&self, server_name: &str) -> Vec<String> {
         self.lang_to_server
             .iter()
-            .filter(|(_, sn)| sn.as_str() == server_name)
+            .filter(|(_, servers)| servers.iter().any(|s| s == server_name))
             .map(|(lang, _)| lang.clone())
             .collect()
     }
 
-    /// Retrieve the LangInfo for the server that handles the given language.
+    /// Retrieve the LangInfo for the highest priority server of the given language.
     pub fn info_for_lang(&self, lang: &str) -> Option<&LangInfo> {
-        let server_name = self.lang_to_server.get(lang)?;
-        self.server_map.get(server_name)
+        self.server_map.get(self.server_for_lang(lang)?)
+    }
+
+    /// The formatter of the highest priority server for the language that configures one,
+    /// along with that server's name.
+    pub fn formatter_for_lang(&self, lang: &str) -> Option<(String, FormatterConfig)> {
+        self.servers_for_lang(lang).iter().find_map(|name| {
+            let format = self.server_map.get(name)?.format.clone()?;
+            Some((name.clone(), format))
+        })
     }
 }
 
@@ -220,3 +495,99 @@ pub fn find_workspace_root(file_path: &str, lang_info: Option<&LangInfo>) -> Opt
         current = current.parent()?;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_keep_every_server_in_priority_order() {
+        let mut manager = LspManager::default();
+        manager.register_server("analyzer", ["rust"], LangInfo::new("ra"));
+        manager.register_server(
+            "linter",
+            ["rust", "toml"],
+            LangInfo::new("lint").with_lsp_format(true),
+        );
+        // Registering again (e.g. on config reload) doesn't duplicate
+        manager.register_server("analyzer", ["rust"], LangInfo::new("ra"));
+
+        assert_eq!(manager.servers_for_lang("rust"), ["analyzer", "linter"]);
+        assert_eq!(manager.server_for_lang("toml"), Some("linter"));
+        assert_eq!(manager.formatter_for_lang("rust").map(|(s, _)| s).as_deref(), Some("linter"));
+
+        let mut langs = manager.langs_for_server("linter");
+        langs.sort();
+        assert_eq!(langs, ["rust", "toml"]);
+    }
+
+    async fn wait_for_exit(manager: &mut LspManager) -> Vec<String> {
+        for _ in 0..200 {
+            let exited = manager.reap_exited();
+            if !exited.is_empty() {
+                return exited;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("server never exited");
+    }
+
+    #[tokio::test]
+    async fn exited_servers_are_restarted_until_they_give_up() {
+        let mut manager = LspManager::default();
+        manager.register_server(
+            "crashy",
+            ["rust"],
+            LangInfo::new("sh").with_args(["-c", "exit 3"]).with_auto_restart(true),
+        );
+
+        for attempt in 0..MAX_AUTO_RESTARTS {
+            manager.get_or_create_server("crashy").await.unwrap().unwrap();
+            assert_eq!(wait_for_exit(&mut manager).await, ["crashy"]);
+
+            let exited = &manager.exited["crashy"];
+            assert_eq!(exited.code, Some(3));
+            assert!(exited.restart_at.is_some());
+            assert_eq!(manager.server_summary("crashy").as_deref(), Some("crashy (restarting)"));
+
+            // Nothing respawns the server before its backoff runs out
+            assert!(manager.get_or_create_server("crashy").await.unwrap().is_none());
+            manager.exited.get_mut("crashy").unwrap().restart_at = Some(Instant::now());
+            assert_eq!(manager.take_due_restarts(), ["crashy"]);
+            assert_eq!(manager.restart_counts["crashy"], attempt + 1);
+        }
+
+        manager.get_or_create_server("crashy").await.unwrap().unwrap();
+        wait_for_exit(&mut manager).await;
+        assert!(manager.exited["crashy"].restart_at.is_none());
+        assert!(manager.take_due_restarts().is_empty());
+        assert!(manager.lang_status("rust").contains("gave up after 5 restarts"));
+
+        // Restarting by hand starts over
+        assert_eq!(manager.reset_clients("rust"), ["crashy"]);
+        assert!(manager.restart_counts.is_empty());
+        assert!(manager.get_or_create_server("crashy").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn files_only_reach_the_servers_that_opened_them() {
+        let mut manager = LspManager::default();
+        manager.register_server("analyzer", ["rust"], LangInfo::new("cat"));
+        manager.register_server("linter", ["rust"], LangInfo::new("cat"));
+        assert!(manager.spawn_clients("rust").await.is_empty());
+        assert_eq!(manager.clients_for_lang("rust").len(), 2);
+
+        // Only the linter found a workspace root and opened the file
+        let opened_by = ["linter".to_string()];
+        let names: Vec<_> =
+            manager.clients_named(&opened_by).iter().map(|c| c.lang_id().to_string()).collect();
+        assert_eq!(names, ["linter"]);
+    }
+
+    #[test]
+    fn restart_backoff_doubles_up_to_a_cap() {
+        assert_eq!(restart_backoff(0), Duration::from_secs(1));
+        assert_eq!(restart_backoff(2), Duration::from_secs(4));
+        assert_eq!(restart_backoff(10), Duration::from_secs(30));
+    }
+}
@@ -1,11 +1,15 @@
 use kerbin_core::*;
 use lsp_types::{
     GotoDefinitionParams, GotoDefinitionResponse, Location, LocationLink, Position,
-    ReferenceContext, ReferenceParams, TextDocumentIdentifier, TextDocumentPositionParams,
-    WorkDoneProgressParams,
+    ReferenceContext, ReferenceParams, ServerCapabilities, TextDocumentIdentifier,
+    TextDocumentPositionParams, WorkDoneProgressParams,
 };
 
-use crate::{diagnostics::Diagnostics, JsonRpcMessage, LspManager, OpenedFile, UriExt};
+use crate::{
+    JsonRpcMessage, LspManager, OpenedFile, UriExt, byte_to_lsp_position,
+    diagnostics::{Diagnostics, is_error},
+    lsp_position_to_byte,
+};
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum NavigationKind {
@@ -16,6 +20,19 @@ pub enum NavigationKind {
     Declaration,
 }
 
+impl NavigationKind {
+    /// Whether a server advertising `caps` answers this kind of request
+    fn supported_by(self, caps: &ServerCapabilities) -> bool {
+        match self {
+            Self::Definition => caps.definition_provider.is_some(),
+            Self::References => caps.references_provider.is_some(),
+            Self::Implementation => caps.implementation_provider.is_some(),
+            Self::TypeDefinition => caps.type_definition_provider.is_some(),
+            Self::Declaration => caps.declaration_provider.is_some(),
+        }
+    }
+}
+
 pub struct NavigationPending {
     pub request_id: i32,
     pub kind: NavigationKind,
@@ -28,7 +45,10 @@ pub struct NavigationState {
 }
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "lsp")]
 pub enum NavigationCommand {
+    /// Jump to the definition of the symbol under the primary cursor.
+    /// With several results, `%lsp_locations` is filled and the `--multi` commands run (e.g. a picker).
     #[command(drop_ident, name = "lsp-goto-definition")]
     GotoDefinition {
         #[command(flag, name = "multi", type_name = "[command]?")]
@@ -58,15 +78,56 @@ pub enum NavigationCommand {
     #[command(drop_ident, name = "lsp-goto-location")]
     GotoLocation { location: String },
     /// Aggregate all buffer diagnostics and open a picker.
-    #[command(drop_ident, name = "lsp-goto-diagnostics")]
+    /// `--errors` leaves out warnings, info and hints.
+    #[command(drop_ident, name = "lsp-goto-diagnostics", name = "diag-list")]
     GotoDiagnostics {
         #[command(flag, name = "multi", type_name = "[command]?")]
         multi: Option<Vec<Token>>,
         #[command(flag, name = "workspace")]
         workspace: bool,
+        #[command(flag)]
+        errors: bool,
     },
 }
 
+/// Parses and sends the commands given to a `--multi`/`--pick` flag, once the template they
+/// read is set. Supports [[cmd1] [cmd2]] (all-list) or [cmd] (single command).
+pub(crate) async fn run_multi_commands(state: &State, tokens: Vec<Token>) {
+    let token_lists: Vec<Vec<Token>> = if tokens.iter().all(|t| matches!(t, Token::List(_))) {
+        tokens
+            .into_iter()
+            .filter_map(|t| {
+                if let Token::List(items) = t {
+                    Some(tokenize(&tokens_to_command_string(&items)).unwrap_or_default())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    } else {
+        vec![tokens]
+    };
+
+    for token_list in token_lists {
+        let command = state.lock_state::<CommandRegistry>().await.parse_command(
+            token_list,
+            true,
+            false,
+            Some(&resolver_engine().await.as_resolver()),
+            true,
+            &*state.lock_state::<CommandPrefixRegistry>().await,
+            &*state.lock_state::<ModeStack>().await,
+        );
+        if let Some(command) = command {
+            state
+                .lock_state::<CommandSender>()
+                .await
+                .send(command)
+                .unwrap();
+        }
+    }
+}
+
 async fn send_goto_request(
     state: &mut State,
     kind: NavigationKind,
@@ -81,21 +142,18 @@ async fn send_goto_request(
         return false;
     };
 
-    let Some(client) = lsps.get_or_create_client(&file.lang).await.ok().flatten() else {
+    let Some(client) = lsps.client_for(&file.lang, |caps| kind.supported_by(caps)).await else {
         return false;
     };
 
     let cursor = buf.primary_cursor();
     let cursor_byte = cursor.get_cursor_byte().min(buf.len());
 
-    let line = buf.byte_to_line_clamped(cursor_byte);
-    let character = cursor_byte - buf.line_to_byte_clamped(line);
-
     let uri = file.uri.clone();
 
     let text_doc_pos = TextDocumentPositionParams {
         text_document: TextDocumentIdentifier { uri },
-        position: Position::new(line as u32, character as u32),
+        position: byte_to_lsp_position(buf.get_rope(), cursor_byte),
     };
 
     resolver_engine_mut().await.remove_template("lsp_locations");
@@ -160,17 +218,23 @@ impl Command<State> for NavigationCommand {
             Self::GotoDeclaration { multi } => {
                 send_goto_request(state, NavigationKind::Declaration, multi.clone()).await
             }
-            Self::GotoDiagnostics { multi, workspace } => {
+            Self::GotoDiagnostics {
+                multi,
+                workspace,
+                errors,
+            } => {
                 if *workspace {
                     // Workspace-wide diagnostics from the global push-model store.
                     // publishDiagnostics notifications are stored for all files,
                     // including those not currently open as buffers.
                     let global = state.lock_state::<crate::GlobalDiagnostics>().await;
                     let mut entries: Vec<String> = Vec::new();
+                    let mut quickfix = Vec::new();
 
                     for (path, diags) in &global.0 {
-                        for diag in diags {
+                        for diag in diags.iter().filter(|d| !*errors || is_error(d)) {
                             entries.push(crate::diagnostics::format_diagnostic(path, diag));
+                            quickfix.push(crate::diagnostics::diagnostic_quickfix_entry(path, diag));
                         }
                     }
                     drop(global);
@@ -180,39 +244,15 @@ impl Command<State> for NavigationCommand {
                         return false;
                     }
 
+                    state
+                        .lock_state::<QuickfixList>()
+                        .await
+                        .set("workspace diagnostics", quickfix);
+
                     resolver_engine_mut().await.set_template("lsp_diagnostics", Token::list_from(entries));
 
                     if let Some(tokens) = multi.clone() {
-                        let token_lists: Vec<Vec<Token>> =
-                            if tokens.iter().all(|t| matches!(t, Token::List(_))) {
-                                tokens
-                                    .into_iter()
-                                    .filter_map(|t| {
-                                        if let Token::List(items) = t {
-                                            Some(tokenize(&tokens_to_command_string(&items)).unwrap_or_default())
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                    .collect()
-                            } else {
-                                vec![tokens]
-                            };
-
-                        for token_list in token_lists {
-                            let command = state.lock_state::<CommandRegistry>().await.parse_command(
-                                token_list,
-                                true,
-                                false,
-                                Some(&resolver_engine().await.as_resolver()),
-                                true,
-                                &*state.lock_state::<CommandPrefixRegistry>().await,
-                                &*state.lock_state::<ModeStack>().await,
-                            );
-                            if let Some(command) = command {
-                                state.lock_state::<CommandSender>().await.send(command).unwrap();
-                            }
-                        }
+                        run_multi_commands(state, tokens).await;
                     }
 
                     true
@@ -220,15 +260,17 @@ impl Command<State> for NavigationCommand {
                     // Open-buffer diagnostics (existing behaviour)
                     let bufs = state.lock_state::<Buffers>().await;
                     let mut entries: Vec<String> = Vec::new();
+                    let mut quickfix = Vec::new();
 
                     for buf in &bufs.buffers {
                         let buf_guard = buf.read().await;
                         let Some(buf) = buf_guard.downcast::<TextBuffer>() else { continue };
                         let Some(file) = buf.get_state::<OpenedFile>().await else { continue };
-                        let path = file.uri.path().to_string();
+                        let Some(path) = lsp_types::Uri::to_file_path(&file.uri) else { continue };
                         let Some(diagnostics) = buf.get_state::<Diagnostics>().await else { continue };
-                        for diag in &diagnostics.0 {
+                        for diag in diagnostics.0.iter().filter(|d| !*errors || is_error(d)) {
                             entries.push(crate::diagnostics::format_diagnostic(&path, diag));
+                            quickfix.push(crate::diagnostics::diagnostic_quickfix_entry(&path, diag));
                         }
                     }
                     drop(bufs);
@@ -238,46 +280,12 @@ impl Command<State> for NavigationCommand {
                         return false;
                     }
 
+                    state.lock_state::<QuickfixList>().await.set("diagnostics", quickfix);
+
                     resolver_engine_mut().await.set_template("lsp_diagnostics", Token::list_from(entries));
 
                     if let Some(tokens) = multi.clone() {
-                        let token_lists: Vec<Vec<Token>> =
-                            if tokens.iter().all(|t| matches!(t, Token::List(_))) {
-                                tokens
-                                    .into_iter()
-                                    .filter_map(|t| {
-                                        if let Token::List(items) = t {
-                                            Some(
-                                                tokenize(&tokens_to_command_string(&items))
-                                                    .unwrap_or_default(),
-                                            )
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                    .collect()
-                            } else {
-                                vec![tokens]
-                            };
-
-                        for token_list in token_lists {
-                            let command = state.lock_state::<CommandRegistry>().await.parse_command(
-                                token_list,
-                                true,
-                                false,
-                                Some(&resolver_engine().await.as_resolver()),
-                                true,
-                                &*state.lock_state::<CommandPrefixRegistry>().await,
-                                &*state.lock_state::<ModeStack>().await,
-                            );
-                            if let Some(command) = command {
-                                state
-                                    .lock_state::<CommandSender>()
-                                    .await
-                                    .send(command)
-                                    .unwrap();
-                            }
-                        }
+                        run_multi_commands(state, tokens).await;
                     }
 
                     true
@@ -304,35 +312,19 @@ impl Command<State> for NavigationCommand {
                 let line = line_1indexed.saturating_sub(1);
                 let col = col_1indexed.saturating_sub(1);
 
-                let default_tab_unit = state.lock_state::<CoreConfig>().await.default_tab_unit;
+                record_jump(state).await;
+
+                let (default_tab_unit, big_file_threshold) = {
+                    let config = state.lock_state::<CoreConfig>().await;
+                    (config.default_tab_unit, config.big_file_threshold)
+                };
                 let mut bufs = state.lock_state::<Buffers>().await;
-                if bufs.open(path.to_string(), default_tab_unit).await.is_err() {
+                if bufs.open(path.to_string(), default_tab_unit, big_file_threshold).await.is_err() {
                     return false;
                 }
 
                 let Some(mut buf) = bufs.cur_text_buffer_mut().await else { return false; };
-                let line_byte = buf.line_to_byte_clamped(line);
-                let line_end_byte = buf.line_to_byte_clamped(line + 1);
-                let byte = buf
-                    .slice(line_byte, line_end_byte)
-                    .map(|s| {
-                        let mut utf16_rem = col;
-                        let mut byte_off = 0usize;
-                        for ch in s.chars() {
-                            if utf16_rem == 0 {
-                                break;
-                            }
-                            let w = ch.len_utf16();
-                            if utf16_rem < w {
-                                break;
-                            }
-                            utf16_rem -= w;
-                            byte_off += ch.len_utf8();
-                        }
-                        line_byte + byte_off
-                    })
-                    .unwrap_or(line_byte)
-                    .min(buf.len());
+                let byte = lsp_position_to_byte(buf.get_rope(), Position::new(line as u32, col as u32));
                 buf.primary_cursor_mut().set_sel(byte..=byte);
 
                 true
@@ -433,17 +425,19 @@ pub async fn handle_navigation(state: &State, msg: &JsonRpcMessage) {
         let Some(path) = lsp_types::Uri::to_file_path(&loc.uri) else {
             return;
         };
-        let line = loc.range.start.line as usize;
-        let col = loc.range.start.character as usize;
+        record_jump(state).await;
 
-        let default_tab_unit = state.lock_state::<CoreConfig>().await.default_tab_unit;
+        let (default_tab_unit, big_file_threshold) = {
+            let config = state.lock_state::<CoreConfig>().await;
+            (config.default_tab_unit, config.big_file_threshold)
+        };
         let mut bufs = state.lock_state::<Buffers>().await;
-        if bufs.open(path, default_tab_unit).await.is_err() {
+        if bufs.open(path, default_tab_unit, big_file_threshold).await.is_err() {
             return;
         }
+        // `open` focuses the existing buffer when the target is already open
         let Some(mut buf) = bufs.cur_text_buffer_mut().await else { return; };
-        let line_byte = buf.line_to_byte_clamped(line);
-        let byte = (line_byte + col).min(buf.len());
+        let byte = lsp_position_to_byte(buf.get_rope(), loc.range.start);
         buf.primary_cursor_mut().set_sel(byte..=byte);
     } else {
         let formatted: Vec<String> = locations.iter().map(format_location).collect();
@@ -451,43 +445,198 @@ pub async fn handle_navigation(state: &State, msg: &JsonRpcMessage) {
             .await
             .set_template("lsp_locations", Token::list_from(formatted));
 
-        // If --multi was provided, parse and send those commands.
-        // Supports [[cmd1] [cmd2]] (all-list) or [cmd] (single command).
         if let Some(tokens) = pending_multi {
-            let token_lists: Vec<Vec<Token>> = if tokens.iter().all(|t| matches!(t, Token::List(_)))
-            {
-                tokens
-                    .into_iter()
-                    .filter_map(|t| {
-                        if let Token::List(items) = t {
-                            Some(tokenize(&tokens_to_command_string(&items)).unwrap_or_default())
-                        } else {
-                            None
-                        }
-                    })
+            run_multi_commands(state, tokens).await;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Command)]
+#[command(category = "lsp")]
+pub enum DiagnosticCommand {
+    /// Move the primary cursor to the next diagnostic in the buffer, wrapping at the end.
+    /// `--errors` skips warnings, info and hints.
+    #[command(drop_ident, name = "diag-next")]
+    Next {
+        #[command(flag)]
+        errors: bool,
+    },
+
+    /// Move the primary cursor to the previous diagnostic in the buffer, wrapping at the start.
+    /// `--errors` skips warnings, info and hints.
+    #[command(drop_ident, name = "diag-prev")]
+    Prev {
+        #[command(flag)]
+        errors: bool,
+    },
+
+    /// Move the primary cursor to the first error in the buffer, or to the first
+    /// diagnostic when there are no errors.
+    #[command(drop_ident, name = "diag-first")]
+    First,
+}
+
+#[async_trait::async_trait]
+impl Command<State> for DiagnosticCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        let (forward, errors) = match self {
+            DiagnosticCommand::Next { errors } => (true, *errors),
+            DiagnosticCommand::Prev { errors } => (false, *errors),
+            DiagnosticCommand::First => (true, false),
+        };
+
+        let mut bufs = state.lock_state::<Buffers>().await;
+        let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+            return false;
+        };
+
+        let mut starts: Vec<usize> = match buf.get_state::<Diagnostics>().await {
+            Some(diagnostics) => {
+                let errors = errors
+                    || (matches!(self, DiagnosticCommand::First)
+                        && diagnostics.0.iter().any(is_error));
+                diagnostics
+                    .0
+                    .iter()
+                    .filter(|d| !errors || is_error(d))
+                    .map(|d| lsp_position_to_byte(buf.get_rope(), d.range.start))
                     .collect()
-            } else {
-                vec![tokens]
-            };
-
-            for token_list in token_lists {
-                let command = state.lock_state::<CommandRegistry>().await.parse_command(
-                    token_list,
-                    true,
-                    false,
-                    Some(&resolver_engine().await.as_resolver()),
-                    true,
-                    &*state.lock_state::<CommandPrefixRegistry>().await,
-                    &*state.lock_state::<ModeStack>().await,
-                );
-                if let Some(command) = command {
-                    state
-                        .lock_state::<CommandSender>()
-                        .await
-                        .send(command)
-                        .unwrap();
-                }
             }
+            None => vec![],
+        };
+
+        if starts.is_empty() {
+            drop(buf);
+            drop(bufs);
+            state
+                .lock_state::<LogSender>()
+                .await
+                .low("command::diag", "No Diagnostics");
+            return false;
         }
+
+        starts.sort_unstable();
+        starts.dedup();
+
+        let cursor_byte = buf.primary_cursor().get_cursor_byte();
+        let target = if matches!(self, DiagnosticCommand::First) {
+            &starts[0]
+        } else if forward {
+            starts
+                .iter()
+                .find(|&&b| b > cursor_byte)
+                .unwrap_or(&starts[0])
+        } else {
+            starts
+                .iter()
+                .rev()
+                .find(|&&b| b < cursor_byte)
+                .unwrap_or(&starts[starts.len() - 1])
+        };
+
+        let target = *target;
+        let cursor = buf.primary_cursor_mut();
+        cursor.set_sel(target..=target);
+        cursor.set_at_start(false);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Diagnostic, DiagnosticSeverity, Uri};
+
+    use super::*;
+
+    fn diagnostic(line: u32, severity: Option<DiagnosticSeverity>) -> Diagnostic {
+        Diagnostic {
+            range: lsp_types::Range::new(Position::new(line, 0), Position::new(line, 1)),
+            severity,
+            message: format!("problem on line {line}"),
+            ..Default::default()
+        }
+    }
+
+    /// A state whose current buffer has a warning on line 1, and errors on lines 3 and 5
+    async fn state_with_diagnostics() -> State {
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, "a\nb\nc\nd\ne\nf\n");
+        let uri = Uri::file_path("/tmp/kerbin-diagnostics.rs").unwrap();
+        buf.set_state(OpenedFile::new("rust".into(), uri, vec![], buf.get_rope().clone()));
+        buf.set_state(Diagnostics(vec![
+            diagnostic(5, None),
+            diagnostic(1, Some(DiagnosticSeverity::WARNING)),
+            diagnostic(3, Some(DiagnosticSeverity::ERROR)),
+        ]));
+
+        let mut buffers = Buffers::default();
+        buffers.push_buffer(buf).await;
+
+        let (_log, log_sender) = LogState::new_with_channel();
+        let mut state = State::new();
+        state
+            .state(buffers)
+            .state(log_sender)
+            .state(QuickfixList::default());
+        state
+    }
+
+    async fn cursor_line(state: &State) -> usize {
+        let buffers = state.lock_state::<Buffers>().await;
+        let buf = buffers.cur_buffer_as::<TextBuffer>().await.unwrap();
+        buf.byte_to_line_clamped(buf.primary_cursor().get_cursor_byte())
+    }
+
+    #[tokio::test]
+    async fn diagnostic_jumps_wrap_and_filter_errors() {
+        let mut state = state_with_diagnostics().await;
+        let next = DiagnosticCommand::Next { errors: false };
+        let prev = DiagnosticCommand::Prev { errors: false };
+
+        let mut lines = vec![];
+        for _ in 0..4 {
+            assert!(next.apply(&mut state).await);
+            lines.push(cursor_line(&state).await);
+        }
+        assert_eq!(lines, [1, 3, 5, 1]);
+
+        assert!(prev.apply(&mut state).await);
+        assert_eq!(cursor_line(&state).await, 5);
+
+        // Errors only, where a diagnostic without a severity counts as one
+        assert!(DiagnosticCommand::Next { errors: true }.apply(&mut state).await);
+        assert_eq!(cursor_line(&state).await, 3);
+        assert!(DiagnosticCommand::Prev { errors: true }.apply(&mut state).await);
+        assert_eq!(cursor_line(&state).await, 5);
+
+        // The first error wins over an earlier warning
+        assert!(DiagnosticCommand::First.apply(&mut state).await);
+        assert_eq!(cursor_line(&state).await, 3);
+
+        {
+            let mut buffers = state.lock_state::<Buffers>().await;
+            let mut buf = buffers.cur_text_buffer_mut().await.unwrap();
+            buf.set_state(Diagnostics(vec![]));
+        }
+        assert!(!next.apply(&mut state).await);
+    }
+
+    #[tokio::test]
+    async fn diagnostic_list_fills_the_quickfix_list() {
+        let mut state = state_with_diagnostics().await;
+
+        let list = NavigationCommand::GotoDiagnostics {
+            multi: None,
+            workspace: false,
+            errors: true,
+        };
+        assert!(list.apply(&mut state).await);
+
+        let quickfix = state.lock_state::<QuickfixList>().await;
+        assert_eq!(quickfix.title, "diagnostics");
+        let lines: Vec<_> = quickfix.entries().iter().map(|e| e.range.start.line).collect();
+        assert_eq!(lines, [5, 3]);
+        assert!(quickfix.entries().iter().all(|e| e.path == "/tmp/kerbin-diagnostics.rs"));
     }
 }
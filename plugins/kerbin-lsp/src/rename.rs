@@ -0,0 +1,572 @@
+use std::sync::Arc;
+
+use kerbin_core::*;
+use lsp_types::{
+    DocumentChangeOperation, DocumentChanges, OneOf, Position, PrepareRenameResponse, RenameParams,
+    TextDocumentIdentifier, TextDocumentPositionParams, TextEdit, WorkDoneProgressParams,
+    WorkspaceEdit,
+};
+use tokio::sync::RwLock;
+
+use crate::{
+    JsonRpcMessage, LspManager, OpenedFile, UriExt, byte_to_lsp_position,
+    text_edit::{apply_text_edits_inner, cursor_adjustment_for_edits, text_edits_are_valid},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RenameStage {
+    Prepare,
+    Rename,
+}
+
+pub struct RenamePending {
+    pub request_id: i32,
+    pub stage: RenameStage,
+    pub lang: String,
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+    pub new_name: String,
+}
+
+#[derive(State, Default)]
+pub struct RenameState {
+    pub pending: Option<RenamePending>,
+}
+
+#[derive(Debug, Clone, Command)]
+#[command(category = "lsp")]
+pub enum RenameCommand {
+    /// Rename the symbol under the primary cursor across the workspace.
+    /// Files touched by the rename are opened as buffers; nothing is saved.
+    #[command(drop_ident, name = "lsp-rename")]
+    Rename { name: String },
+}
+
+#[async_trait::async_trait]
+impl Command<State> for RenameCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        match self {
+            Self::Rename { name } => send_rename_request(state, name.clone()).await,
+        }
+    }
+}
+
+async fn send_rename_request(state: &mut State, new_name: String) -> bool {
+    let mut bufs = state.lock_state::<Buffers>().await;
+    let mut lsps = state.lock_state::<LspManager>().await;
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+        return false;
+    };
+
+    let Some(file) = buf.get_state::<OpenedFile>().await else {
+        return false;
+    };
+    let lang = file.lang.clone();
+    let uri = file.uri.clone();
+    drop(file);
+
+    let Some(client) = lsps.client_for(&lang, |caps| caps.rename_provider.is_some()).await else {
+        return false;
+    };
+
+    let position = byte_to_lsp_position(buf.get_rope(), buf.primary_cursor().get_cursor_byte());
+    let text_document = TextDocumentIdentifier { uri };
+
+    // Only ask for a prepare step when the server advertises one
+    let supports_prepare = matches!(
+        client
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.rename_provider.as_ref()),
+        Some(OneOf::Right(options)) if options.prepare_provider == Some(true)
+    );
+
+    let (stage, request) = if supports_prepare {
+        (
+            RenameStage::Prepare,
+            client
+                .request(
+                    "textDocument/prepareRename",
+                    TextDocumentPositionParams {
+                        text_document: text_document.clone(),
+                        position,
+                    },
+                )
+                .await,
+        )
+    } else {
+        (
+            RenameStage::Rename,
+            client
+                .request(
+                    "textDocument/rename",
+                    RenameParams {
+                        text_document_position: TextDocumentPositionParams {
+                            text_document: text_document.clone(),
+                            position,
+                        },
+                        new_name: new_name.clone(),
+                        work_done_progress_params: WorkDoneProgressParams::default(),
+                    },
+                )
+                .await,
+        )
+    };
+
+    let Ok(request_id) = request else {
+        return false;
+    };
+
+    let mut rename_state = buf.get_or_insert_state_mut(RenameState::default).await;
+    rename_state.pending = Some(RenamePending {
+        request_id,
+        stage,
+        lang,
+        text_document,
+        position,
+        new_name,
+    });
+
+    true
+}
+
+/// Finds the buffer waiting on `request_id` and takes its pending rename
+async fn take_pending(
+    state: &State,
+    request_id: i32,
+    stage: RenameStage,
+) -> Option<(Arc<RwLock<dyn KerbinBuffer>>, RenamePending)> {
+    let bufs = state.lock_state::<Buffers>().await;
+
+    for buf in &bufs.buffers {
+        let mut buf_guard = buf.write().await;
+        if let Some(text_buf) = buf_guard.downcast_mut::<TextBuffer>()
+            && let Some(mut rename_state) = text_buf.get_state_mut::<RenameState>().await
+            && rename_state
+                .pending
+                .as_ref()
+                .is_some_and(|p| p.request_id == request_id && p.stage == stage)
+        {
+            let pending = rename_state.pending.take()?;
+            return Some((buf.clone(), pending));
+        }
+    }
+
+    None
+}
+
+fn response_error(msg: &crate::JsonRpcResponse) -> Option<String> {
+    let error = msg.error.as_ref()?;
+    Some(
+        error
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("Unknown error")
+            .to_string(),
+    )
+}
+
+pub async fn handle_prepare_rename(state: &State, msg: &JsonRpcMessage) {
+    let JsonRpcMessage::Response(response) = msg else {
+        return;
+    };
+
+    let Some((buf, mut pending)) = take_pending(state, response.id, RenameStage::Prepare).await
+    else {
+        return;
+    };
+
+    if let Some(err) = response_error(response) {
+        state
+            .lock_state::<LogSender>()
+            .await
+            .critical("lsp::rename", format!("Rename failed: {err}"));
+        return;
+    }
+
+    let renameable = response
+        .result
+        .as_ref()
+        .and_then(|r| serde_json::from_value::<Option<PrepareRenameResponse>>(r.clone()).ok())
+        .flatten()
+        .is_some();
+
+    if !renameable {
+        state
+            .lock_state::<LogSender>()
+            .await
+            .low("lsp::rename", "Nothing to rename here");
+        return;
+    }
+
+    let mut lsps = state.lock_state::<LspManager>().await;
+    let Some(client) = lsps
+        .client_for(&pending.lang, |caps| caps.rename_provider.is_some())
+        .await
+    else {
+        return;
+    };
+
+    let Ok(request_id) = client
+        .request(
+            "textDocument/rename",
+            RenameParams {
+                text_document_position: TextDocumentPositionParams {
+                    text_document: pending.text_document.clone(),
+                    position: pending.position,
+                },
+                new_name: pending.new_name.clone(),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            },
+        )
+        .await
+    else {
+        return;
+    };
+    drop(lsps);
+
+    pending.request_id = request_id;
+    pending.stage = RenameStage::Rename;
+
+    let mut buf_guard = buf.write().await;
+    if let Some(text_buf) = buf_guard.downcast_mut::<TextBuffer>() {
+        text_buf
+            .get_or_insert_state_mut(RenameState::default)
+            .await
+            .pending = Some(pending);
+    }
+}
+
+pub async fn handle_rename(state: &State, msg: &JsonRpcMessage) {
+    let JsonRpcMessage::Response(response) = msg else {
+        return;
+    };
+
+    if take_pending(state, response.id, RenameStage::Rename)
+        .await
+        .is_none()
+    {
+        return;
+    }
+
+    let log = state.lock_state::<LogSender>().await;
+
+    if let Some(err) = response_error(response) {
+        log.critical("lsp::rename", format!("Rename failed: {err}"));
+        return;
+    }
+
+    let Some(edit) = response
+        .result
+        .as_ref()
+        .and_then(|r| serde_json::from_value::<Option<WorkspaceEdit>>(r.clone()).ok())
+        .flatten()
+    else {
+        log.low("lsp::rename", "Nothing to rename");
+        return;
+    };
+
+    let file_edits = match collect_file_edits(edit) {
+        Ok(e) => e,
+        Err(e) => {
+            log.critical("lsp::rename", e);
+            return;
+        }
+    };
+    drop(log);
+
+    let result = apply_workspace_edit(state, file_edits).await;
+
+    let log = state.lock_state::<LogSender>().await;
+    match result {
+        Ok(files) => log.low("lsp::rename", format!("Renamed across {files} file(s)")),
+        Err(e) => log.critical("lsp::rename", format!("Rename rolled back: {e}")),
+    };
+}
+
+/// Flattens a `WorkspaceEdit` into per-file edit lists.
+/// Resource operations (create, rename, delete) aren't supported and reject the whole edit.
+pub(crate) fn collect_file_edits(
+    edit: WorkspaceEdit,
+) -> Result<Vec<(String, Vec<TextEdit>)>, String> {
+    let mut files: Vec<(String, Vec<TextEdit>)> = vec![];
+
+    let mut push = |uri: &lsp_types::Uri, edits: Vec<TextEdit>| -> Result<(), String> {
+        let path = lsp_types::Uri::to_file_path(uri)
+            .ok_or_else(|| format!("Unsupported uri `{}`", uri.as_str()))?;
+        match files.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, existing)) => existing.extend(edits),
+            None => files.push((path, edits)),
+        }
+        Ok(())
+    };
+
+    if let Some(changes) = edit.document_changes {
+        let doc_edits = match changes {
+            DocumentChanges::Edits(edits) => edits,
+            DocumentChanges::Operations(ops) => ops
+                .into_iter()
+                .map(|op| match op {
+                    DocumentChangeOperation::Edit(e) => Ok(e),
+                    DocumentChangeOperation::Op(_) => {
+                        Err("File operations aren't supported".to_string())
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        for doc_edit in doc_edits {
+            let edits = doc_edit
+                .edits
+                .into_iter()
+                .map(|e| match e {
+                    OneOf::Left(e) => e,
+                    OneOf::Right(annotated) => annotated.text_edit,
+                })
+                .collect();
+            push(&doc_edit.text_document.uri, edits)?;
+        }
+    } else if let Some(changes) = edit.changes {
+        for (uri, edits) in changes {
+            push(&uri, edits)?;
+        }
+    }
+
+    files.retain(|(_, edits)| !edits.is_empty());
+    Ok(files)
+}
+
+/// Applies per-file edits as one change group per buffer, opening files that aren't open yet.
+/// Every file is checked before anything is written; if a file can't be opened or an edit
+/// fails, applied groups are undone and buffers opened for the rename are closed again.
+pub(crate) async fn apply_workspace_edit(
+    state: &State,
+    file_edits: Vec<(String, Vec<TextEdit>)>,
+) -> Result<usize, String> {
+    let (default_tab_unit, big_file_threshold) = {
+        let config = state.lock_state::<CoreConfig>().await;
+        (config.default_tab_unit, config.big_file_threshold)
+    };
+    let mut bufs = state.lock_state::<Buffers>().await;
+
+    let selected = bufs.selected_buffer;
+    let initial_count = bufs.buffers.len();
+
+    let mut targets = vec![];
+    let mut failure = None;
+
+    for (path, edits) in file_edits {
+        match bufs.open(path.clone(), default_tab_unit, big_file_threshold).await {
+            Ok(idx) => targets.push((bufs.buffers[idx].clone(), path, edits)),
+            Err(e) => {
+                failure = Some(format!("couldn't open {path}: {e}"));
+                break;
+            }
+        }
+    }
+    bufs.set_selected_buffer(selected);
+
+    if failure.is_none() {
+        for (buf, path, edits) in &targets {
+            let buf_guard = buf.read().await;
+            let valid = buf_guard
+                .downcast::<TextBuffer>()
+                .is_some_and(|b| text_edits_are_valid(b, edits));
+            if !valid {
+                failure = Some(format!("edits for {path} don't match the buffer"));
+                break;
+            }
+        }
+    }
+
+    let mut applied: Vec<(Arc<RwLock<dyn KerbinBuffer>>, usize)> = vec![];
+
+    if failure.is_none() {
+        for (buf, path, edits) in &targets {
+            let mut buf_guard = buf.write().await;
+            let Some(buf_inner) = buf_guard.downcast_mut::<TextBuffer>() else {
+                continue;
+            };
+
+            let undo_len = buf_inner.undo_stack.len();
+
+            let cursor_bytes: Vec<usize> = buf_inner
+                .cursors
+                .iter()
+                .map(|c| c.get_cursor_byte())
+                .collect();
+            let adjustments: Vec<isize> = cursor_bytes
+                .iter()
+                .map(|&byte| cursor_adjustment_for_edits(buf_inner, edits, byte))
+                .collect();
+
+            buf_inner.start_change_group();
+            let ok = apply_text_edits_inner(buf_inner, edits.clone());
+            buf_inner.commit_change_group();
+
+            applied.push((buf.clone(), undo_len));
+
+            if !ok {
+                failure = Some(format!("failed to apply edits to {path}"));
+                break;
+            }
+
+            for ((cursor, &cursor_byte), adjustment) in buf_inner
+                .cursors
+                .iter_mut()
+                .zip(&cursor_bytes)
+                .zip(adjustments)
+            {
+                let new_byte = (cursor_byte as isize + adjustment).max(0) as usize;
+                cursor.set_sel(new_byte..=new_byte);
+            }
+        }
+    }
+
+    let Some(failure) = failure else {
+        return Ok(targets.len());
+    };
+
+    for (buf, undo_len) in applied.into_iter().rev() {
+        let mut buf_guard = buf.write().await;
+        if let Some(buf) = buf_guard.downcast_mut::<TextBuffer>() {
+            while buf.undo_stack.len() > undo_len {
+                buf.undo();
+            }
+        }
+    }
+
+    for idx in (initial_count..bufs.buffers.len()).rev() {
+        bufs.close_buffer(idx).await;
+    }
+    bufs.set_selected_buffer(selected);
+
+    Err(failure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{
+        AnnotatedTextEdit, CreateFile, OptionalVersionedTextDocumentIdentifier, Range, ResourceOp,
+        TextDocumentEdit, Uri,
+    };
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    fn edit(line: u32, start: u32, end: u32, text: &str) -> TextEdit {
+        TextEdit {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            new_text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn collects_changes_map() {
+        let uri = Uri::from_str("file:///tmp/a.rs").unwrap();
+        let workspace_edit = WorkspaceEdit {
+            changes: Some(HashMap::from([(uri, vec![edit(0, 0, 3, "bar")])])),
+            ..Default::default()
+        };
+
+        let files = collect_file_edits(workspace_edit).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "/tmp/a.rs");
+        assert_eq!(files[0].1[0].new_text, "bar");
+    }
+
+    #[test]
+    fn merges_document_changes_per_file() {
+        let uri = Uri::from_str("file:///tmp/a.rs").unwrap();
+        let doc = |edits| TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: None,
+            },
+            edits,
+        };
+        let workspace_edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![
+                doc(vec![OneOf::Left(edit(0, 0, 3, "bar"))]),
+                doc(vec![OneOf::Right(AnnotatedTextEdit {
+                    text_edit: edit(2, 4, 7, "bar"),
+                    annotation_id: "rename".to_string(),
+                })]),
+            ])),
+            ..Default::default()
+        };
+
+        let files = collect_file_edits(workspace_edit).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].1.len(), 2);
+    }
+
+    #[test]
+    fn rejects_file_operations() {
+        let workspace_edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+                    uri: Uri::from_str("file:///tmp/b.rs").unwrap(),
+                    options: None,
+                    annotation_id: None,
+                })),
+            ])),
+            ..Default::default()
+        };
+
+        assert!(collect_file_edits(workspace_edit).is_err());
+    }
+
+    async fn buffer_text(state: &State, idx: usize) -> String {
+        let buffers = state.lock_state::<Buffers>().await;
+        let buf = buffers.buffers[idx].read().await;
+        buf.downcast::<TextBuffer>().unwrap().get_rope().to_string()
+    }
+
+    #[tokio::test]
+    async fn failed_rename_rolls_buffers_back() {
+        let dir = std::env::temp_dir().join(format!("kerbin-rename-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        for name in ["a.rs", "b.rs", "c.rs"] {
+            std::fs::write(path(name), "foo\n").unwrap();
+        }
+
+        let config = CoreConfig::default();
+        let mut buffers = Buffers::default();
+        for name in ["a.rs", "b.rs"] {
+            buffers
+                .open(path(name), config.default_tab_unit, config.big_file_threshold)
+                .await
+                .unwrap();
+        }
+        // Edits for `b.rs` pass the checks but can't be applied
+        buffers
+            .cur_text_buffer_mut()
+            .await
+            .unwrap()
+            .set_readonly(true);
+        buffers.set_selected_buffer(1);
+
+        let mut state = State::new();
+        state.state(buffers).state(config);
+
+        let file_edits = ["a.rs", "c.rs", "b.rs"]
+            .map(|name| (path(name), vec![edit(0, 0, 3, "bar")]))
+            .to_vec();
+        let err = apply_workspace_edit(&state, file_edits).await.unwrap_err();
+        assert!(err.contains("b.rs"), "{err}");
+
+        // `a.rs` was edited before the failure and is undone, `c.rs` was opened for
+        // the rename and is closed again
+        assert_eq!(buffer_text(&state, 1).await, "foo\n");
+        assert_eq!(buffer_text(&state, 2).await, "foo\n");
+        {
+            let buffers = state.lock_state::<Buffers>().await;
+            assert_eq!(buffers.buffers.len(), 3);
+            assert_eq!(buffers.selected_buffer, 1);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
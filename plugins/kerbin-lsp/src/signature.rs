@@ -0,0 +1,435 @@
+use std::sync::Arc;
+
+use kerbin_core::*;
+
+const PRIORITY: i32 = 5;
+use lsp_types::{
+    Documentation, ParameterLabel, SignatureHelp, SignatureHelpContext, SignatureHelpParams,
+    SignatureHelpTriggerKind, SignatureInformation, TextDocumentIdentifier,
+    TextDocumentPositionParams, WorkDoneProgressParams,
+};
+use ratatui::style::Style;
+
+use crate::{
+    JsonRpcMessage, LspManager, OpenedFile,
+    autocomplete::{CompletionWidget, build_doc_popup},
+    byte_to_lsp_position,
+};
+
+pub struct SignatureHelpInfo {
+    pub pending_request: i32,
+
+    pub help: Option<SignatureHelp>,
+
+    /// Cursor byte the latest request was sent from
+    pub position: usize,
+}
+
+/// The signature help stored in each text buffer.
+/// Cleared when leaving insert mode or typing the closing paren.
+#[derive(State, Default)]
+pub struct SignatureHelpState {
+    pub info: Option<SignatureHelpInfo>,
+}
+
+#[derive(Command)]
+#[command(category = "lsp")]
+pub enum SignatureHelpCommand {
+    #[command(drop_ident, name = "lsp-signature-help")]
+    /// Request parameter hints for the call under the cursor
+    Request,
+}
+
+#[async_trait::async_trait]
+impl Command<State> for SignatureHelpCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        match self {
+            Self::Request => {
+                let mut bufs = state.lock_state::<Buffers>().await;
+                let mut lsps = state.lock_state::<LspManager>().await;
+
+                let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+                    return true;
+                };
+
+                send_signature_help_request(&mut buf, &mut lsps, None).await;
+            }
+        }
+
+        true
+    }
+}
+
+/// Sends `textDocument/signatureHelp` from the primary cursor.
+/// `trigger` is the typed character that caused the request, if any.
+async fn send_signature_help_request(
+    buf: &mut TextBuffer,
+    lsps: &mut LspManager,
+    trigger: Option<String>,
+) {
+    let Some(file) = buf.get_state::<OpenedFile>().await else {
+        return;
+    };
+
+    let Some(client) = lsps
+        .client_for(&file.lang, |caps| caps.signature_help_provider.is_some())
+        .await
+    else {
+        return;
+    };
+
+    let cursor_byte = buf.primary_cursor().get_cursor_byte().min(buf.len());
+
+    let active_help = buf
+        .get_state::<SignatureHelpState>()
+        .await
+        .and_then(|s| s.info.as_ref().and_then(|i| i.help.clone()));
+
+    let params = SignatureHelpParams {
+        context: Some(SignatureHelpContext {
+            trigger_kind: if trigger.is_some() {
+                SignatureHelpTriggerKind::TRIGGER_CHARACTER
+            } else {
+                SignatureHelpTriggerKind::INVOKED
+            },
+            trigger_character: trigger,
+            is_retrigger: active_help.is_some(),
+            active_signature_help: active_help,
+        }),
+        text_document_position_params: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: file.uri.clone(),
+            },
+            position: byte_to_lsp_position(buf.get_rope(), cursor_byte),
+        },
+        work_done_progress_params: WorkDoneProgressParams::default(),
+    };
+
+    let Ok(id) = client.request("textDocument/signatureHelp", params).await else {
+        return;
+    };
+    drop(file);
+
+    let mut state = buf
+        .get_or_insert_state_mut(SignatureHelpState::default)
+        .await;
+
+    // Keep showing the previous signature until the new one arrives
+    match state.info.as_mut() {
+        Some(info) => {
+            info.pending_request = id;
+            info.position = cursor_byte;
+        }
+        None => {
+            state.info = Some(SignatureHelpInfo {
+                pending_request: id,
+                help: None,
+                position: cursor_byte,
+            })
+        }
+    }
+}
+
+/// Requests signature help when the server's trigger characters are typed in insert mode
+pub async fn update_signature_help(
+    bufs: ResMut<Buffers>,
+    lsps: ResMut<LspManager>,
+    modes: Res<ModeStack>,
+) {
+    get!(mut bufs, mut lsps, modes);
+
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else {
+        return;
+    };
+
+    let active = buf
+        .get_state::<SignatureHelpState>()
+        .await
+        .is_some_and(|s| s.info.is_some());
+
+    if modes.get_mode() != 'i' {
+        if active && let Some(mut state) = buf.get_state_mut::<SignatureHelpState>().await {
+            state.info = None;
+        }
+        return;
+    }
+
+    if buf.byte_changes.is_empty() {
+        return;
+    }
+
+    let cursor_byte = buf.primary_cursor().get_cursor_byte().min(buf.len());
+    let cursor_char_idx = buf.byte_to_char_clamped(cursor_byte);
+    if cursor_char_idx == 0 {
+        return;
+    }
+    let typed = buf.char_clamped(cursor_char_idx - 1);
+
+    if active && typed == ')' {
+        if let Some(mut state) = buf.get_state_mut::<SignatureHelpState>().await {
+            state.info = None;
+        }
+        return;
+    }
+
+    let Some(file) = buf.get_state::<OpenedFile>().await else {
+        return;
+    };
+    let lang = file.lang.clone();
+    drop(file);
+
+    let Some(client) = lsps
+        .client_for(&lang, |caps| caps.signature_help_provider.is_some())
+        .await
+    else {
+        return;
+    };
+    let Some(options) = client
+        .server_capabilities
+        .as_ref()
+        .and_then(|c| c.signature_help_provider.as_ref())
+    else {
+        return;
+    };
+
+    let typed = typed.to_string();
+    let triggers = options.trigger_characters.iter().flatten();
+    let retriggers = options
+        .retrigger_characters
+        .iter()
+        .flatten()
+        .filter(|_| active);
+    if !triggers.chain(retriggers).any(|c| *c == typed) {
+        return;
+    }
+
+    send_signature_help_request(&mut buf, &mut lsps, Some(typed)).await;
+}
+
+/// Splits a signature label into (before, active parameter, after)
+fn split_active_parameter(sig: &SignatureInformation, active: u32) -> (String, String, String) {
+    let label = &sig.label;
+
+    let range = sig
+        .parameters
+        .as_ref()
+        .and_then(|p| p.get(active as usize))
+        .and_then(|param| match &param.label {
+            ParameterLabel::Simple(s) => label.find(s.as_str()).map(|start| start..start + s.len()),
+            ParameterLabel::LabelOffsets([start, end]) => {
+                // Offsets count UTF-16 code units
+                let mut units = 0u32;
+                let mut start_byte = None;
+                let mut end_byte = None;
+                for (byte, ch) in label.char_indices() {
+                    if units == *start {
+                        start_byte = Some(byte);
+                    }
+                    if units == *end {
+                        end_byte = Some(byte);
+                    }
+                    units += ch.len_utf16() as u32;
+                }
+                if units == *end {
+                    end_byte = Some(label.len());
+                }
+                Some(start_byte?..end_byte?)
+            }
+        });
+
+    match range {
+        Some(r) if r.start <= r.end => (
+            label[..r.start].to_string(),
+            label[r.clone()].to_string(),
+            label[r.end..].to_string(),
+        ),
+        _ => (label.clone(), String::new(), String::new()),
+    }
+}
+
+pub async fn render_signature_help(buffers: ResMut<Buffers>, theme: Res<Theme>) {
+    get!(mut buffers, theme);
+
+    let Some(mut buf) = buffers.cur_text_buffer_mut().await else {
+        return;
+    };
+
+    buf.renderer.clear_extmark_ns("lsp::signature");
+
+    let Some(state) = buf.get_state::<SignatureHelpState>().await else {
+        return;
+    };
+
+    let Some(help) = state.info.as_ref().and_then(|i| i.help.as_ref()) else {
+        return;
+    };
+
+    let sig_idx = help.active_signature.unwrap_or(0) as usize;
+    let Some(sig) = help.signatures.get(sig_idx).or(help.signatures.first()) else {
+        return;
+    };
+
+    let active = sig.active_parameter.or(help.active_parameter).unwrap_or(0);
+    let (before, param, after) = split_active_parameter(sig, active);
+
+    let window_style = theme.get_fallback_default(["lsp.signature.window", "ui.window"]);
+    let param_style = theme.get_fallback_default(["lsp.signature.active_parameter", "ui.match"]);
+
+    const MAX_WIDTH: usize = 80;
+
+    let mut lines: Vec<Vec<(String, Style)>> = vec![vec![
+        (before, window_style),
+        (param, param_style),
+        (after, window_style),
+    ]];
+
+    let param_doc = sig
+        .parameters
+        .as_ref()
+        .and_then(|p| p.get(active as usize))
+        .and_then(|p| p.documentation.as_ref());
+
+    if let Some(doc) = param_doc {
+        let text = match doc {
+            Documentation::String(s) => s.clone(),
+            Documentation::MarkupContent(m) => m.value.clone(),
+        };
+        for line in text.lines().filter(|l| !l.trim().is_empty()).take(4) {
+            lines.push(vec![(line.chars().take(MAX_WIDTH).collect(), window_style)]);
+        }
+    }
+
+    if help.signatures.len() > 1 {
+        lines.push(vec![(
+            format!("({}/{})", sig_idx + 1, help.signatures.len()),
+            window_style,
+        )]);
+    }
+
+    let width = lines
+        .iter()
+        .map(|l| l.iter().map(|(s, _)| s.chars().count()).sum::<usize>())
+        .max()
+        .unwrap_or(0)
+        .clamp(1, MAX_WIDTH);
+    let height = lines.len();
+
+    let popup = build_doc_popup(&lines, height, width, window_style);
+    let popup_h = popup.area.height as i32;
+
+    let cursor_byte = buf.primary_cursor().get_cursor_byte().min(buf.len());
+    drop(state);
+
+    buf.renderer
+        .set_namespace_priority("lsp::signature", PRIORITY);
+    buf.add_extmark(
+        ExtmarkBuilder::new("lsp::signature", cursor_byte).with_kind(ExtmarkKind::Overlay {
+            widget: Arc::new(CompletionWidget(popup)),
+            // Sit above the cursor so completions can use the space below
+            position: OverlayPosition::Fixed {
+                offset_x: 0,
+                offset_y: -popup_h,
+            },
+        }),
+    );
+}
+
+pub async fn handle_signature_help(state: &State, msg: &JsonRpcMessage) {
+    let JsonRpcMessage::Response(response) = msg else {
+        return;
+    };
+
+    let bufs = state.lock_state::<Buffers>().await;
+
+    let mut buffer = None;
+    for buf in &bufs.buffers {
+        let buf_guard = buf.read().await;
+        if let Some(text_buf) = buf_guard.downcast::<TextBuffer>()
+            && let Some(state) = text_buf.get_state::<SignatureHelpState>().await
+            && let Some(info) = &state.info
+            && info.pending_request == response.id
+        {
+            buffer = Some(buf.clone());
+            break;
+        }
+    }
+
+    let Some(buf) = buffer else {
+        return;
+    };
+
+    drop(bufs);
+
+    let mut buf_guard = buf.write_owned().await;
+    let Some(buf) = buf_guard.downcast_mut::<TextBuffer>() else {
+        return;
+    };
+    let Some(mut sig_state) = buf.get_state_mut::<SignatureHelpState>().await else {
+        return;
+    };
+
+    let help = response
+        .result
+        .as_ref()
+        .and_then(|r| serde_json::from_value::<Option<SignatureHelp>>(r.clone()).ok())
+        .flatten()
+        .filter(|h| !h.signatures.is_empty());
+
+    // An empty answer means the cursor left the call
+    match help {
+        Some(help) => {
+            if let Some(info) = sig_state.info.as_mut() {
+                info.help = Some(help);
+            }
+        }
+        None => sig_state.info = None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::ParameterInformation;
+
+    fn sig(label: &str, params: Vec<ParameterLabel>) -> SignatureInformation {
+        SignatureInformation {
+            label: label.to_string(),
+            documentation: None,
+            parameters: Some(
+                params
+                    .into_iter()
+                    .map(|label| ParameterInformation {
+                        label,
+                        documentation: None,
+                    })
+                    .collect(),
+            ),
+            active_parameter: None,
+        }
+    }
+
+    #[test]
+    fn splits_simple_label() {
+        let s = sig(
+            "fn add(a: i32, b: i32)",
+            vec![
+                ParameterLabel::Simple("a: i32".into()),
+                ParameterLabel::Simple("b: i32".into()),
+            ],
+        );
+        let (before, param, after) = split_active_parameter(&s, 1);
+        assert_eq!(before, "fn add(a: i32, ");
+        assert_eq!(param, "b: i32");
+        assert_eq!(after, ")");
+    }
+
+    #[test]
+    fn splits_utf16_offsets() {
+        // "🦀" is two UTF-16 units, so `x` starts at unit 6
+        let s = sig("f(🦀, x)", vec![ParameterLabel::LabelOffsets([6, 7])]);
+        let (before, param, after) = split_active_parameter(&s, 0);
+        assert_eq!(before, "f(🦀, ");
+        assert_eq!(param, "x");
+        assert_eq!(after, ")");
+    }
+}
@@ -0,0 +1,519 @@
+use std::{iter::Peekable, ops::Range, str::Chars};
+
+use kerbin_core::*;
+
+/// A tab-stop of a parsed snippet, as a byte range into [`Snippet::text`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TabStop {
+    pub index: u32,
+    pub range: Range<usize>,
+}
+
+/// An LSP snippet expanded to the literal text it inserts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    /// One stop per distinct index in jump order: `$1`, `$2`, ... then the final `$0`,
+    /// which is added at the end of the text when the snippet has none
+    pub stops: Vec<TabStop>,
+}
+
+impl Snippet {
+    /// Parses `$1`, `${1}`, `${1:placeholder}` (placeholders may nest), `${1|one,two|}`
+    /// (the first choice is inserted) and `$0`. `\$`, `\}` and `\\` are escapes.
+    /// Variables expand to their default, or to nothing, and transforms are dropped.
+    pub fn parse(src: &str) -> Self {
+        let mut parser = Parser {
+            chars: src.chars().peekable(),
+            text: String::new(),
+            stops: vec![],
+        };
+        parser.parse_any(false);
+
+        let mut stops: Vec<TabStop> = vec![];
+        for stop in parser.stops {
+            if !stops.iter().any(|s| s.index == stop.index) {
+                stops.push(stop);
+            }
+        }
+        stops.sort_by_key(|s| if s.index == 0 { u32::MAX } else { s.index });
+        if stops.last().is_none_or(|s| s.index != 0) {
+            let end = parser.text.len();
+            stops.push(TabStop {
+                index: 0,
+                range: end..end,
+            });
+        }
+
+        Self {
+            text: parser.text,
+            stops,
+        }
+    }
+
+    /// Continues every line after the first with `indent`, like the line the snippet
+    /// is inserted on, shifting the stops to match
+    pub fn indent(&mut self, indent: &str) {
+        if indent.is_empty() {
+            return;
+        }
+
+        let newlines: Vec<usize> = self.text.match_indices('\n').map(|(i, _)| i).collect();
+        let shift = |byte: usize| byte + newlines.iter().filter(|&&i| i < byte).count() * indent.len();
+        for stop in &mut self.stops {
+            stop.range = shift(stop.range.start)..shift(stop.range.end);
+        }
+        self.text = self.text.replace('\n', &format!("\n{indent}"));
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    text: String,
+    /// Every stop in source order, mirrors included
+    stops: Vec<TabStop>,
+}
+
+impl Parser<'_> {
+    /// Parses text up to the end of input, or the `}` closing a placeholder when `nested`
+    fn parse_any(&mut self, nested: bool) {
+        while let Some(ch) = self.chars.next() {
+            match ch {
+                '\\' => match self.chars.peek() {
+                    Some(&escaped @ ('$' | '}' | '\\')) => {
+                        self.chars.next();
+                        self.text.push(escaped);
+                    }
+                    _ => self.text.push('\\'),
+                },
+                '}' if nested => return,
+                '$' => self.parse_dollar(),
+                ch => self.text.push(ch),
+            }
+        }
+    }
+
+    fn parse_dollar(&mut self) {
+        match self.chars.peek() {
+            Some(ch) if ch.is_ascii_digit() => {
+                let index = self.number();
+                self.mirror(index);
+            }
+            Some('{') => {
+                self.chars.next();
+                self.parse_braced();
+            }
+            Some(&ch) if ch.is_alphabetic() || ch == '_' => {
+                // Variables aren't resolved, so they expand to nothing
+                self.name();
+            }
+            _ => self.text.push('$'),
+        }
+    }
+
+    /// Parses the inside of `${...}`, the `{` already consumed
+    fn parse_braced(&mut self) {
+        let start = self.text.len();
+
+        if self.chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+            let index = self.number();
+            match self.chars.next() {
+                Some('}') => self.mirror(index),
+                Some(':') => {
+                    self.parse_any(true);
+                    self.push_stop(index, start..self.text.len());
+                }
+                Some('|') => {
+                    let choice = self.choice();
+                    self.text.push_str(&choice);
+                    self.push_stop(index, start..self.text.len());
+                }
+                _ => self.skip_to_close(),
+            }
+        } else {
+            self.name();
+            match self.chars.next() {
+                Some('}') | None => {}
+                Some(':') => self.parse_any(true),
+                _ => self.skip_to_close(),
+            }
+        }
+    }
+
+    /// A bare `$n` repeats the placeholder of an earlier `${n:...}`, if there was one
+    fn mirror(&mut self, index: u32) {
+        let start = self.text.len();
+        if let Some(original) = self.stops.iter().find(|s| s.index == index) {
+            let placeholder = self.text[original.range.clone()].to_string();
+            self.text.push_str(&placeholder);
+        }
+        self.push_stop(index, start..self.text.len());
+    }
+
+    fn push_stop(&mut self, index: u32, range: Range<usize>) {
+        self.stops.push(TabStop { index, range });
+    }
+
+    fn number(&mut self) -> u32 {
+        let mut num = 0u32;
+        while let Some(digit) = self.chars.peek().and_then(|c| c.to_digit(10)) {
+            self.chars.next();
+            num = num.saturating_mul(10).saturating_add(digit);
+        }
+        num
+    }
+
+    fn name(&mut self) {
+        while self
+            .chars
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+        {
+            self.chars.next();
+        }
+    }
+
+    /// Reads `one,two|}` and returns the first choice
+    fn choice(&mut self) -> String {
+        let mut first = String::new();
+        let mut in_first = true;
+        while let Some(ch) = self.chars.next() {
+            match ch {
+                '\\' => {
+                    if let Some(escaped) = self.chars.next()
+                        && in_first
+                    {
+                        first.push(escaped);
+                    }
+                }
+                ',' => in_first = false,
+                '|' => {
+                    if self.chars.peek() == Some(&'}') {
+                        self.chars.next();
+                    }
+                    break;
+                }
+                ch if in_first => first.push(ch),
+                _ => {}
+            }
+        }
+        first
+    }
+
+    /// Drops everything up to the `}` closing the current `${`, such as a transform
+    fn skip_to_close(&mut self) {
+        let mut depth = 0usize;
+        while let Some(ch) = self.chars.next() {
+            match ch {
+                '\\' => {
+                    self.chars.next();
+                }
+                '{' => depth += 1,
+                '}' if depth == 0 => return,
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Moves `range` to account for the bytes `start..old_end` being replaced, with the
+/// replacement ending at `new_end`. Edits touching the range grow or shrink it, so
+/// typing into a placeholder (or an empty stop) keeps the text inside the stop.
+fn shift_range(range: Range<usize>, start: usize, old_end: usize, new_end: usize) -> Range<usize> {
+    let shift = |byte: usize| (byte + new_end).saturating_sub(old_end);
+
+    if start > range.end {
+        range
+    } else if old_end < range.start || (old_end == range.start && start < range.start) {
+        shift(range.start)..shift(range.end)
+    } else {
+        range.start.min(start)..shift(range.end.max(old_end))
+    }
+}
+
+/// The tab-stops of the snippet being filled in, tracked through edits
+pub struct SnippetSession {
+    /// Stops in jump order, the last one being `$0`
+    pub stops: Vec<Range<usize>>,
+    pub current: usize,
+    /// The whole inserted snippet, the session ends once the cursor leaves it
+    pub span: Range<usize>,
+    /// Entries of `byte_changes` made before the session started, which its ranges
+    /// already account for
+    pub seen_changes: usize,
+}
+
+impl SnippetSession {
+    fn apply_edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+        for stop in &mut self.stops {
+            *stop = shift_range(stop.clone(), start, old_end, new_end);
+        }
+        self.span = shift_range(self.span.clone(), start, old_end, new_end);
+    }
+
+    fn is_final(&self) -> bool {
+        self.current + 1 >= self.stops.len()
+    }
+}
+
+#[derive(State, Default)]
+pub struct SnippetState {
+    pub session: Option<SnippetSession>,
+}
+
+#[derive(Command)]
+#[command(category = "lsp")]
+pub enum SnippetCommand {
+    #[command(drop_ident, name = "snippet-next", name = "sn")]
+    /// Jumps to the next tab-stop of the active snippet, ending it at the final stop
+    Next,
+    #[command(drop_ident, name = "snippet-prev", name = "sp")]
+    /// Jumps to the previous tab-stop of the active snippet
+    Previous,
+    #[command(drop_ident, name = "snippet-clear-placeholder", name = "scp")]
+    /// Deletes the placeholder of the current tab-stop if it is still selected,
+    /// so typed text replaces it
+    ClearPlaceholder,
+}
+
+/// Selects `range` with the primary cursor, collapsing it at the start when empty
+fn select_stop(buf: &mut TextBuffer, range: &Range<usize>) {
+    let cursor = buf.primary_cursor_mut();
+    if range.is_empty() {
+        cursor.set_sel(range.start..=range.start);
+    } else {
+        cursor.set_sel(range.start..=range.end - 1);
+    }
+    cursor.set_at_start(true);
+}
+
+/// Whether the primary selection is exactly the (non-empty) placeholder of the current stop
+fn placeholder_selected(buf: &TextBuffer, session: &SnippetSession) -> bool {
+    let range = &session.stops[session.current];
+    let sel = buf.primary_cursor().sel();
+    !range.is_empty() && *sel.start() == range.start && *sel.end() + 1 == range.end
+}
+
+/// Starts a session for `snippet`, already inserted at `start`, and selects its first stop.
+/// `seen_changes` is the length of `byte_changes` once the insertion is done.
+pub(crate) async fn start_snippet(
+    buf: &mut TextBuffer,
+    snippet: &Snippet,
+    start: usize,
+    seen_changes: usize,
+) {
+    let session = SnippetSession {
+        stops: snippet
+            .stops
+            .iter()
+            .map(|s| s.range.start + start..s.range.end + start)
+            .collect(),
+        current: 0,
+        span: start..start + snippet.text.len(),
+        seen_changes,
+    };
+
+    select_stop(buf, &session.stops[0]);
+
+    // A snippet whose only stop is the final one has nothing to fill in
+    let mut state = buf.get_or_insert_state_mut(SnippetState::default).await;
+    state.session = (!session.is_final()).then_some(session);
+}
+
+async fn end_snippet(buf: &mut TextBuffer) {
+    if let Some(mut state) = buf.get_state_mut::<SnippetState>().await {
+        state.session = None;
+    }
+    buf.renderer.clear_extmark_ns("lsp::snippet");
+
+    let mut resolver = resolver_engine_mut().await;
+    resolver.remove_template("lsp_snippet");
+    resolver.remove_template("lsp_snippet_placeholder");
+}
+
+#[async_trait::async_trait]
+impl Command<State> for SnippetCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        let mut bufs = state.lock_state::<Buffers>().await;
+        let Some(mut buf) = bufs.cur_text_buffer_mut().await else { return false; };
+
+        match self {
+            Self::Next | Self::Previous => {
+                let (range, done) = {
+                    let Some(mut snippet) = buf.get_state_mut::<SnippetState>().await else {
+                        return false;
+                    };
+                    let Some(session) = &mut snippet.session else { return false; };
+
+                    if matches!(self, Self::Next) {
+                        session.current = (session.current + 1).min(session.stops.len() - 1);
+                    } else {
+                        session.current = session.current.saturating_sub(1);
+                    }
+                    (session.stops[session.current].clone(), session.is_final())
+                };
+
+                select_stop(&mut buf, &range);
+                if done {
+                    end_snippet(&mut buf).await;
+                }
+                true
+            }
+
+            Self::ClearPlaceholder => {
+                let range = {
+                    let Some(snippet) = buf.get_state::<SnippetState>().await else {
+                        return false;
+                    };
+                    let Some(session) = &snippet.session else { return false; };
+                    if !placeholder_selected(&buf, session) {
+                        return false;
+                    }
+                    session.stops[session.current].clone()
+                };
+
+                let len = buf.byte_to_char_clamped(range.end) - buf.byte_to_char_clamped(range.start);
+                buf.action(kerbin_core::buffer::action::Delete {
+                    byte: range.start,
+                    len,
+                });
+                buf.primary_cursor_mut().set_sel(range.start..=range.start);
+                true
+            }
+        }
+    }
+}
+
+/// Moves the stops through this update's edits and ends the session once the cursor
+/// leaves the snippet, keeping the `lsp_snippet` templates in sync for keybindings
+pub async fn update_snippet(bufs: ResMut<Buffers>, theme: Res<Theme>) {
+    get!(mut bufs, theme);
+
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else { return; };
+
+    let changes = buf.byte_changes.clone();
+    let cursor_byte = buf.primary_cursor().get_cursor_byte();
+
+    let placeholder = {
+        let Some(mut snippet) = buf.get_state_mut::<SnippetState>().await else { return; };
+        let Some(session) = &mut snippet.session else {
+            return;
+        };
+
+        for [start, old_end, new_end] in changes.iter().skip(session.seen_changes) {
+            session.apply_edit(start.1, old_end.1, new_end.1);
+        }
+        session.seen_changes = 0;
+
+        if !session.span.contains(&cursor_byte) && session.span.end != cursor_byte {
+            None
+        } else {
+            Some(placeholder_selected(&buf, session))
+        }
+    };
+
+    let Some(placeholder) = placeholder else {
+        end_snippet(&mut buf).await;
+        return;
+    };
+
+    {
+        let mut resolver = resolver_engine_mut().await;
+        resolver.set_template("lsp_snippet", "true");
+        if placeholder {
+            resolver.set_template("lsp_snippet_placeholder", "true");
+        } else {
+            resolver.remove_template("lsp_snippet_placeholder");
+        }
+    }
+
+    let style = theme.get_fallback_default(["lsp.snippet.tabstop", "ui.virtual_text"]);
+    let marks: Vec<_> = {
+        let Some(snippet) = buf.get_state::<SnippetState>().await else { return; };
+        let Some(session) = &snippet.session else { return; };
+        session.stops[session.current + 1..session.stops.len() - 1]
+            .iter()
+            .filter(|range| !range.is_empty())
+            .map(|range| {
+                ExtmarkBuilder::new_range("lsp::snippet", range.clone())
+                    .with_kind(ExtmarkKind::Highlight { style })
+            })
+            .collect()
+    };
+
+    buf.renderer.clear_extmark_ns("lsp::snippet");
+    for mark in marks {
+        buf.add_extmark(mark);
+    }
+}
+
+/// Ends the snippet once insert mode is left
+pub async fn end_snippet_on_insert_leave(event: EventData<ModeLeaveEvent>, bufs: ResMut<Buffers>) {
+    get!(Some(event));
+    if event.mode != 'i' {
+        return;
+    }
+    get!(mut bufs);
+
+    let Some(mut buf) = bufs.cur_text_buffer_mut().await else { return; };
+    end_snippet(&mut buf).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stops(snippet: &Snippet) -> Vec<(u32, &str)> {
+        snippet
+            .stops
+            .iter()
+            .map(|s| (s.index, &snippet.text[s.range.clone()]))
+            .collect()
+    }
+
+    #[test]
+    fn parses_placeholders_in_jump_order() {
+        let snippet = Snippet::parse("fn ${2:name}(${1:arg}: ${3}) {\n\t$0\n}");
+
+        assert_eq!(snippet.text, "fn name(arg: ) {\n\t\n}");
+        assert_eq!(stops(&snippet), [(1, "arg"), (2, "name"), (3, ""), (0, "")]);
+        assert_eq!(snippet.stops[3].range.start, 18);
+    }
+
+    #[test]
+    fn handles_nesting_escapes_and_choices() {
+        let snippet = Snippet::parse(r"${1:vec![${2:x}]} \$5 \} ${3|a,b|} $TM_FILENAME${4}");
+
+        assert_eq!(snippet.text, "vec![x] $5 } a ");
+        assert_eq!(stops(&snippet), [(1, "vec![x]"), (2, "x"), (3, "a"), (4, ""), (0, "")]);
+        // The final stop defaults to the end of the text
+        assert_eq!(snippet.stops[4].range, 15..15);
+    }
+
+    #[test]
+    fn mirrors_repeat_the_placeholder() {
+        let snippet = Snippet::parse("let ${1:a} = $1;");
+        assert_eq!(snippet.text, "let a = a;");
+        assert_eq!(stops(&snippet), [(1, "a"), (0, "")]);
+    }
+
+    #[test]
+    fn indent_shifts_later_lines() {
+        let mut snippet = Snippet::parse("{\n\t$1\n}");
+        snippet.indent("    ");
+        assert_eq!(snippet.text, "{\n    \t\n    }");
+        assert_eq!(snippet.stops[0].range, 7..7);
+    }
+
+    #[test]
+    fn stops_follow_edits() {
+        // Typing into an empty stop grows it
+        assert_eq!(shift_range(4..4, 4, 4, 7), 4..7);
+        // Replacing the placeholder keeps the new text inside the stop
+        assert_eq!(shift_range(4..7, 4, 7, 4), 4..4);
+        // Edits before move the stop, edits after leave it alone
+        assert_eq!(shift_range(4..7, 0, 0, 2), 6..9);
+        assert_eq!(shift_range(4..7, 9, 9, 12), 4..7);
+    }
+}
@@ -1,15 +1,16 @@
 use kerbin_core::*;
 use lsp_types::TextEdit;
-use ropey::RopeSlice;
+
+use crate::lsp_position_to_byte;
 
 /// Applies a list of LSP text edits to a buffer without wrapping in a change group.
 /// The caller is responsible for calling `start_change_group` / `commit_change_group`.
 ///
 /// Edits are sorted descending by start position so earlier byte offsets remain valid
-/// as edits are applied sequentially.
-pub(crate) fn apply_text_edits_inner(buf: &mut TextBuffer, mut edits: Vec<TextEdit>) {
+/// as edits are applied sequentially. Returns false if any edit failed to apply.
+pub(crate) fn apply_text_edits_inner(buf: &mut TextBuffer, mut edits: Vec<TextEdit>) -> bool {
     if edits.is_empty() {
-        return;
+        return true;
     }
 
     edits.sort_by(|a, b| {
@@ -17,55 +18,57 @@ pub(crate) fn apply_text_edits_inner(buf: &mut TextBuffer, mut edits: Vec<TextEd
             .cmp(&(a.range.start.line, a.range.start.character))
     });
 
-    let line_content_len = |line_slice: &RopeSlice<'_>| {
-        let mut len = line_slice.len_chars();
-        if len > 0 {
-            match line_slice.char(len - 1) {
-                '\n' => {
-                    len -= 1;
-                    if len > 0 && line_slice.char(len - 1) == '\r' {
-                        len -= 1;
-                    }
-                }
-                '\r' => len -= 1,
-                _ => {}
-            }
-        }
-        len
-    };
-
     for edit in &edits {
-        let max_line = buf.len_lines().saturating_sub(1);
-        let start_line = (edit.range.start.line as usize).min(max_line);
-        let end_line = (edit.range.end.line as usize).min(max_line);
-
-        let start_line_slice = buf.line_clamped(start_line);
-        let start_char =
-            (edit.range.start.character as usize).min(line_content_len(&start_line_slice));
-        let start_byte =
-            buf.line_to_byte_clamped(start_line) + start_line_slice.char_to_byte(start_char);
-
-        let end_line_slice = buf.line_clamped(end_line);
-        let end_char =
-            (edit.range.end.character as usize).min(line_content_len(&end_line_slice));
-        let end_byte =
-            buf.line_to_byte_clamped(end_line) + end_line_slice.char_to_byte(end_char);
+        let start_byte = lsp_position_to_byte(buf.get_rope(), edit.range.start);
+        let end_byte = lsp_position_to_byte(buf.get_rope(), edit.range.end);
 
         let del_chars =
             buf.byte_to_char_clamped(end_byte) - buf.byte_to_char_clamped(start_byte);
-        if del_chars > 0 {
-            buf.action(Delete {
+        if del_chars > 0
+            && !buf.action(Delete {
                 byte: start_byte,
                 len: del_chars,
-            });
+            })
+        {
+            return false;
         }
-        if !edit.new_text.is_empty() {
-            buf.action(Insert {
+        if !edit.new_text.is_empty()
+            && !buf.action(Insert {
                 byte: start_byte,
                 content: edit.new_text.clone(),
-            });
+            })
+        {
+            return false;
         }
     }
+
+    true
+}
+
+/// Checks that every edit lies within the buffer and that no two edits overlap,
+/// so a set of edits can be rejected before any of them touch the buffer.
+pub(crate) fn text_edits_are_valid(buf: &TextBuffer, edits: &[TextEdit]) -> bool {
+    let line_count = buf.get_rope().len_lines() as u32;
+
+    let mut ranges: Vec<_> = edits
+        .iter()
+        .map(|e| {
+            (
+                (e.range.start.line, e.range.start.character),
+                (e.range.end.line, e.range.end.character),
+            )
+        })
+        .collect();
+
+    if ranges
+        .iter()
+        .any(|(start, end)| start > end || end.0 >= line_count)
+    {
+        return false;
+    }
+
+    ranges.sort();
+    ranges.windows(2).all(|w| w[0].1 <= w[1].0)
 }
 
 /// Computes the net byte offset adjustment to a cursor position caused by applying
@@ -77,41 +80,12 @@ pub(crate) fn cursor_adjustment_for_edits(
     edits: &[TextEdit],
     cursor_byte: usize,
 ) -> isize {
-    let line_content_len = |line_slice: &RopeSlice<'_>| {
-        let mut len = line_slice.len_chars();
-        if len > 0 {
-            match line_slice.char(len - 1) {
-                '\n' => {
-                    len -= 1;
-                    if len > 0 && line_slice.char(len - 1) == '\r' {
-                        len -= 1;
-                    }
-                }
-                '\r' => len -= 1,
-                _ => {}
-            }
-        }
-        len
-    };
-
     let mut adjustment: isize = 0;
     for edit in edits {
-        let max_line = buf.len_lines().saturating_sub(1);
-        let start_line = (edit.range.start.line as usize).min(max_line);
-        let end_line = (edit.range.end.line as usize).min(max_line);
-
-        let start_line_slice = buf.line_clamped(start_line);
-        let start_char =
-            (edit.range.start.character as usize).min(line_content_len(&start_line_slice));
-        let edit_start_byte =
-            buf.line_to_byte_clamped(start_line) + start_line_slice.char_to_byte(start_char);
+        let edit_start_byte = lsp_position_to_byte(buf.get_rope(), edit.range.start);
 
         if edit_start_byte < cursor_byte {
-            let end_line_slice = buf.line_clamped(end_line);
-            let end_char =
-                (edit.range.end.character as usize).min(line_content_len(&end_line_slice));
-            let edit_end_byte =
-                buf.line_to_byte_clamped(end_line) + end_line_slice.char_to_byte(end_char);
+            let edit_end_byte = lsp_position_to_byte(buf.get_rope(), edit.range.end);
 
             adjustment +=
                 edit.new_text.len() as isize - (edit_end_byte as isize - edit_start_byte as isize);
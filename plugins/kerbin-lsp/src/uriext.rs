@@ -1,6 +1,7 @@
 use std::str::FromStr;
 
-use lsp_types::Uri;
+use lsp_types::{Position, Uri};
+use ropey::Rope;
 
 pub trait UriExt {
     fn file_path(path: &str) -> Result<Uri, String>;
@@ -8,11 +9,143 @@ pub trait UriExt {
 }
 
 impl UriExt for Uri {
+    /// Builds a `file://` URI, percent-encoding everything but unreserved characters and
+    /// separators so paths with spaces or non-ASCII names reach the server intact.
     fn file_path(path: &str) -> Result<Uri, String> {
-        Uri::from_str(&format!("file://{path}")).map_err(|x| x.to_string())
+        Uri::from_str(&format!("file://{}", percent_encode_path(path))).map_err(|x| x.to_string())
     }
 
+    /// Decodes a `file://` URI back into a filesystem path.
+    /// Remote schemes (`jdt://`, `untitled:`, ...) have no path and give `None`.
     fn to_file_path(uri: &Uri) -> Option<String> {
-        uri.as_str().strip_prefix("file://").map(|s| s.to_string())
+        let rest = uri.as_str().strip_prefix("file://")?;
+        let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+        percent_decode(rest)
+    }
+}
+
+fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Gives `None` for malformed escapes or if the decoded bytes aren't UTF-8
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Converts a byte offset into an LSP `Position`.
+///
+/// LSP counts `character` in UTF-16 code units, so anything outside the BMP (most emoji)
+/// takes two units while still being a single char in the rope.
+pub fn byte_to_lsp_position(rope: &Rope, byte: usize) -> Position {
+    let char_idx = rope.byte_to_char(byte.min(rope.len_bytes()));
+    let line = rope.char_to_line(char_idx);
+    let line_start = rope.line_to_char(line);
+
+    let character: usize = rope
+        .slice(line_start..char_idx)
+        .chars()
+        .map(char::len_utf16)
+        .sum();
+
+    Position::new(line as u32, character as u32)
+}
+
+/// Converts an LSP `Position` into a byte offset.
+///
+/// Lines past the end clamp to the last line, and characters past the end of a line
+/// clamp to just before its line break. A position inside a surrogate pair rounds
+/// down to the start of that char.
+pub fn lsp_position_to_byte(rope: &Rope, pos: Position) -> usize {
+    let line = (pos.line as usize).min(rope.len_lines().saturating_sub(1));
+    let line_start = rope.line_to_byte(line);
+
+    let mut utf16_rem = pos.character as usize;
+    let mut byte_off = 0usize;
+    for ch in rope.line(line).chars() {
+        if utf16_rem == 0 || ch == '\n' || ch == '\r' {
+            break;
+        }
+        let w = ch.len_utf16();
+        if utf16_rem < w {
+            break;
+        }
+        utf16_rem -= w;
+        byte_off += ch.len_utf8();
+    }
+
+    line_start + byte_off
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_uris_are_percent_encoded() {
+        let uri = Uri::file_path("/tmp/my dir/ñ#1.rs").unwrap();
+        assert_eq!(uri.as_str(), "file:///tmp/my%20dir/%C3%B1%231.rs");
+        assert_eq!(Uri::to_file_path(&uri).as_deref(), Some("/tmp/my dir/ñ#1.rs"));
+    }
+
+    #[test]
+    fn test_non_file_uris_have_no_path() {
+        let uri = Uri::from_str("jdt://contents/rt.jar/java.lang/String.class").unwrap();
+        assert_eq!(Uri::to_file_path(&uri), None);
+
+        let uri = Uri::from_str("file://localhost/tmp/a%20b.rs").unwrap();
+        assert_eq!(Uri::to_file_path(&uri).as_deref(), Some("/tmp/a b.rs"));
+    }
+
+    #[test]
+    fn test_position_counts_utf16_units() {
+        // "é" is 2 bytes / 1 unit, "😀" is 4 bytes / 2 units
+        let rope = Rope::from_str("fn main() {}\nlet é = \"😀\"; x\n");
+
+        let x_byte = rope.to_string().find(" x").unwrap() + 1;
+        assert_eq!(byte_to_lsp_position(&rope, x_byte), Position::new(1, 14));
+        assert_eq!(lsp_position_to_byte(&rope, Position::new(1, 14)), x_byte);
+    }
+
+    #[test]
+    fn test_round_trip_every_char_boundary() {
+        let rope = Rope::from_str("café 👍🏽 ok\r\n\tñandú 🇯🇵\nend");
+
+        for char_idx in 0..=rope.len_chars() {
+            let byte = rope.char_to_byte(char_idx);
+            if char_idx < rope.len_chars() && rope.char(char_idx) == '\n' {
+                // The byte after "\r" isn't addressable as a column; skip the line break
+                continue;
+            }
+            let pos = byte_to_lsp_position(&rope, byte);
+            assert_eq!(lsp_position_to_byte(&rope, pos), byte, "char {char_idx} at {pos:?}");
+        }
+    }
+
+    #[test]
+    fn test_position_clamps_to_line_content() {
+        let rope = Rope::from_str("aé\nb");
+
+        assert_eq!(lsp_position_to_byte(&rope, Position::new(0, 100)), 3);
+        assert_eq!(lsp_position_to_byte(&rope, Position::new(9, 1)), 5);
     }
 }
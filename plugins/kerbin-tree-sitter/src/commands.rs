@@ -2,6 +2,7 @@ use kerbin_core::*;
 
 use crate::grammar::{GrammarDefinition, GrammarInstallDefinition, normalize_lang_name};
 use crate::grammar_manager::GrammarManager;
+use crate::state::{TreeSitterState, refresh_highlights};
 
 fn tokens_to_strings(tokens: &[Token]) -> Vec<String> {
     tokens
@@ -17,6 +18,7 @@ fn tokens_to_strings(tokens: &[Token]) -> Vec<String> {
 }
 
 #[derive(Debug, Clone, Command)]
+#[command(category = "tree-sitter")]
 pub enum TreeSitterCommand {
     /// Define a tree-sitter grammar and the language names it serves.
     #[command(drop_ident, name = "tree_sitter_define")]
@@ -32,6 +34,12 @@ pub enum TreeSitterCommand {
         #[command(flag)]
         build_name: Option<String>,
     },
+
+    /// Re-reads the queries (highlights, injections, locals...) of loaded grammars from
+    /// disk and re-highlights the buffers using them. Queries that fail to compile keep
+    /// their previous version.
+    #[command(name = "ts-reload")]
+    Reload,
 }
 
 fn register_ts_hook(state: &mut State, lang: &str) {
@@ -43,7 +51,8 @@ fn register_ts_hook(state: &mut State, lang: &str) {
         .system_named("tree-sitter::open_files", crate::state::open_files)
         .system_named("tree-sitter::update_trees", crate::state::update_trees)
         .system_named("tree-sitter::highlight_file", crate::highlighter::highlight_file)
-        .system_named("tree-sitter::update_locals", crate::locals::update_locals);
+        .system_named("tree-sitter::update_locals", crate::locals::update_locals)
+        .system_named("tree-sitter::update_folds", crate::folds::update_folds);
 }
 
 #[async_trait::async_trait]
@@ -89,6 +98,44 @@ impl Command<State> for TreeSitterCommand {
                     register_ts_hook(state, lang);
                 }
             }
+
+            TreeSitterCommand::Reload => {
+                let config_path = state.lock_state::<ConfigFolder>().await.0.clone();
+                let log = state.lock_state::<LogSender>().await;
+                let mut manager = state.lock_state::<GrammarManager>().await;
+
+                let (reloaded, errors) = manager.reload_queries(&config_path);
+                for error in &errors {
+                    log.critical(
+                        "tree-sitter::reload",
+                        format!("Kept previous query, failed to reload {error}"),
+                    );
+                }
+
+                let bufs = state.lock_state::<Buffers>().await;
+                for arc in &bufs.buffers {
+                    let mut buf = arc.write().await;
+                    let Some(tb) = buf.as_any_mut().downcast_mut::<TextBuffer>() else {
+                        continue;
+                    };
+
+                    let uses_reloaded = match tb.get_state_mut::<TreeSitterState>().await {
+                        Some(ts) => std::iter::once(&ts.lang)
+                            .chain(ts.injected_trees.iter().map(|t| &t.lang))
+                            .any(|lang| reloaded.contains(&manager.grammar_name(lang))),
+                        None => false,
+                    };
+                    if uses_reloaded {
+                        refresh_highlights(tb, &mut manager, &config_path).await;
+                    }
+                }
+
+                log.low(
+                    "tree-sitter::reload",
+                    format!("Reloaded queries for {} grammars", reloaded.len()),
+                );
+                return errors.is_empty();
+            }
         }
         false
     }
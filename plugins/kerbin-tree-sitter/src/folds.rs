@@ -0,0 +1,241 @@
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+
+use kerbin_core::*;
+use ropey::Rope;
+use tree_sitter::{Node, Query};
+
+use crate::{
+    grammar_manager::GrammarManager, query_walker::QueryWalkerBuilder, state::TreeSitterState,
+};
+
+/// Suffixes of the node kinds that fold in languages without a `folds.scm` query
+const FALLBACK_FOLD_KINDS: &[&str] = &["block", "body"];
+
+/// Converts the byte ranges of foldable nodes to the lines they span. Nodes ending with
+/// their newline end on the line before it, and nodes within one line are dropped
+pub fn fold_lines(
+    rope: &Rope,
+    byte_ranges: impl IntoIterator<Item = Range<usize>>,
+) -> Vec<RangeInclusive<usize>> {
+    let len = rope.len_bytes();
+    byte_ranges
+        .into_iter()
+        .filter_map(|range| {
+            let start = rope.byte_to_line(range.start.min(len));
+            let end = rope.byte_to_line(range.end.saturating_sub(1).max(range.start).min(len));
+            (end > start).then_some(start..=end)
+        })
+        .collect()
+}
+
+/// Line ranges of the `@fold` captures of `query` in the buffer's main tree
+pub fn query_fold_ranges(
+    state: &TreeSitterState,
+    rope: &Rope,
+    query: Arc<Query>,
+) -> Vec<RangeInclusive<usize>> {
+    let mut byte_ranges = vec![];
+
+    let mut walker = QueryWalkerBuilder::new(state, rope, query).build();
+    walker.walk(|entry| {
+        if entry.is_injected {
+            return true;
+        }
+
+        for capture in entry.query_match.captures {
+            if entry.query.capture_names()[capture.index as usize] == "fold" {
+                byte_ranges.push(capture.node.byte_range());
+            }
+        }
+        true
+    });
+
+    fold_lines(rope, byte_ranges)
+}
+
+/// Line ranges of the named nodes under `root` whose kind ends in one of
+/// `FALLBACK_FOLD_KINDS`, for grammars that ship no `folds.scm`
+pub fn node_kind_fold_ranges(root: Node, rope: &Rope) -> Vec<RangeInclusive<usize>> {
+    let mut byte_ranges = vec![];
+
+    let mut cursor = root.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if node.is_named() && FALLBACK_FOLD_KINDS.iter().any(|kind| node.kind().ends_with(kind))
+        {
+            byte_ranges.push(node.byte_range());
+        }
+
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+
+    fold_lines(rope, byte_ranges)
+}
+
+/// Recomputes the foldable ranges of the current buffer after its tree changed
+pub async fn update_folds(
+    buffers: ResMut<Buffers>,
+    grammars: ResMut<GrammarManager>,
+    config_path: Res<ConfigFolder>,
+) {
+    get!(mut buffers, mut grammars, config_path);
+
+    let Some(mut buf) = buffers.cur_text_buffer_mut().await else { return; };
+
+    let Some(mut state) = buf.get_state_mut::<TreeSitterState>().await else {
+        return;
+    };
+
+    if state.folds_computed {
+        return;
+    }
+    state.folds_computed = true;
+
+    let lang = state.lang.clone();
+    let ranges = match grammars.get_query(&config_path.0, &lang, "folds") {
+        Some(query) => query_fold_ranges(&state, buf.get_rope(), query),
+        None => match &state.tree {
+            Some(tree) => node_kind_fold_ranges(tree.root_node(), buf.get_rope()),
+            None => vec![],
+        },
+    };
+
+    drop(state);
+    buf.folds.set_available(ranges);
+}
+
+#[derive(Debug, Clone, Command)]
+#[command(category = "tree-sitter")]
+pub enum FoldCommand {
+    /// Closes the innermost open fold around the cursor
+    #[command(name = "fold")]
+    Fold,
+
+    /// Opens the outermost closed fold at the cursor
+    #[command(name = "unfold")]
+    Unfold,
+
+    /// Closes every fold in the buffer
+    #[command(name = "fold-all")]
+    FoldAll,
+
+    /// Opens every fold in the buffer
+    #[command(name = "unfold-all")]
+    UnfoldAll,
+}
+
+#[async_trait::async_trait]
+impl Command<State> for FoldCommand {
+    async fn apply(&self, state: &mut State) -> bool {
+        let mut buffers = state.lock_state::<Buffers>().await;
+        let Some(mut buf) = buffers.cur_text_buffer_mut().await else {
+            return false;
+        };
+
+        let line = buf.byte_to_line_clamped(buf.primary_cursor().get_cursor_byte());
+        let changed = match self {
+            FoldCommand::Fold => buf.folds.close_at(line).is_some(),
+            FoldCommand::Unfold => buf.folds.open_at(line),
+            FoldCommand::FoldAll => {
+                buf.folds.close_all();
+                buf.folds.has_closed()
+            }
+            FoldCommand::UnfoldAll => {
+                let had_closed = buf.folds.has_closed();
+                buf.folds.open_all();
+                had_closed
+            }
+        };
+
+        // Cursors inside a closed fold move to its first line, which stands in for it
+        let targets: Vec<Option<usize>> = buf
+            .cursors
+            .iter()
+            .map(|cursor| {
+                let line = buf.byte_to_line_clamped(cursor.get_cursor_byte());
+                buf.folds
+                    .is_hidden(line)
+                    .then(|| buf.line_to_byte_clamped(buf.folds.visible_line(line)))
+            })
+            .collect();
+        for (cursor, target) in buf.cursors.iter_mut().zip(targets) {
+            if let Some(byte) = target {
+                cursor.move_to(byte);
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "fn add(a: i32, b: i32) -> i32 {
+    let sum = a + b;
+    sum
+}
+
+fn one() -> i32 { 1 }
+";
+
+    /// Bytes of `SAMPLE` from the first `start` through the next `end`
+    fn span(start: &str, end: &str) -> Range<usize> {
+        let start = SAMPLE.find(start).unwrap();
+        let end = SAMPLE[start..].find(end).unwrap() + start + end.len();
+        start..end
+    }
+
+    #[test]
+    fn function_folds_from_its_first_to_last_line() {
+        let rope = Rope::from_str(SAMPLE);
+
+        let function = span("fn add", "}");
+        let body = span("{", "}");
+        assert_eq!(fold_lines(&rope, [function, body]), [0..=3, 0..=3]);
+    }
+
+    #[test]
+    fn single_line_nodes_do_not_fold() {
+        let rope = Rope::from_str(SAMPLE);
+
+        let one = span("fn one", "}");
+        assert!(fold_lines(&rope, [one]).is_empty());
+    }
+
+    #[test]
+    fn trailing_newline_stays_off_the_fold() {
+        let rope = Rope::from_str(SAMPLE);
+
+        // Nodes that take their newline with them, like line comments
+        let one_line = span("    let sum", "b;\n");
+        let two_lines = span("    let sum", "sum\n");
+        assert!(fold_lines(&rope, [one_line]).is_empty());
+        assert_eq!(fold_lines(&rope, [two_lines]), [1..=2]);
+    }
+
+    #[test]
+    fn parsed_function_folds_by_query_and_node_kind() {
+        let language = tree_sitter_rust::LANGUAGE.into();
+        let state = TreeSitterState::parse("rust", &language, SAMPLE);
+        let rope = Rope::from_str(SAMPLE);
+
+        // The function and its block, but not the one line function
+        let folds = include_str!("../../../config/runtime/queries/rust/folds.scm");
+        let query = Arc::new(Query::new(&language, folds).unwrap());
+        assert_eq!(query_fold_ranges(&state, &rope, query), [0..=3, 0..=3]);
+
+        // Without the query only the block folds
+        let root = state.tree.as_ref().unwrap().root_node();
+        assert_eq!(node_kind_fold_ranges(root, &rope), [0..=3]);
+    }
+}
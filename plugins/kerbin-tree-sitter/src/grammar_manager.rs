@@ -1,4 +1,7 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use kerbin_core::*;
 use tree_sitter::Query;
@@ -16,6 +19,9 @@ pub enum GrammarManagerError {
 
     #[error("No grammar registered for language '{lang}'")]
     MissingDefinition { lang: String },
+
+    #[error("Grammar '{grammar}' failed to load earlier, reload the config to retry")]
+    PreviouslyFailed { grammar: String },
 }
 
 #[derive(State, Default)]
@@ -24,23 +30,17 @@ pub struct GrammarManager {
     pub grammar_map: HashMap<String, GrammarDefinition>,
     pub loaded_grammars: HashMap<String, Arc<Grammar>>,
     pub query_map: HashMap<String, HashMap<String, Arc<Query>>>,
-    pub failed_queries: std::collections::HashSet<(String, String)>,
+    pub failed_queries: HashSet<(String, String)>,
     /// Language name → grammar name (many languages can share one grammar)
     pub lang_to_grammar: HashMap<String, String>,
+    /// File extension → filetype, mirrored from the `FiletypeRegistry` so injections
+    /// written as an extension (e.g. ```` ```rs ````) still resolve
+    pub ext_to_lang: HashMap<String, String>,
+    /// Grammars that failed to load, so injections don't retry them on every reparse
+    pub failed_grammars: HashSet<String>,
 }
 
 impl GrammarManager {
-    pub async fn register_filetype_handlers(&self, state: &mut State) {
-        for lang in self.lang_to_grammar.keys() {
-            state
-                .on_hook(hooks::UpdateFiletype::new(lang))
-                .system(crate::state::open_files)
-                .system(crate::state::update_trees)
-                .system(crate::highlighter::highlight_file)
-                .system(crate::locals::update_locals);
-        }
-    }
-
     pub async fn install_all_grammars(&self, state: &State) {
         let config_path = state.lock_state::<ConfigFolder>().await.0.clone();
         let log = state.lock_state::<LogSender>().await.clone();
@@ -110,6 +110,10 @@ impl GrammarManager {
 
     /// Resolve a language or grammar name to a loaded Grammar.
     ///
+    /// Grammars are only loaded the first time a language asks for them, then cached.
+    /// A grammar that fails to load isn't retried until the config is reloaded, so its
+    /// error is only reported once.
+    ///
     /// Lookup order:
     /// 1. `lang_to_grammar[name]` → grammar_name (explicit language mapping)
     /// 2. `grammar_map[name]` directly (grammar name used as-is, e.g. from injection queries)
@@ -131,6 +135,12 @@ impl GrammarManager {
             return Ok(grammar.clone());
         }
 
+        if self.failed_grammars.contains(&grammar_name) {
+            return Err(GrammarManagerError::PreviouslyFailed {
+                grammar: grammar_name,
+            });
+        }
+
         let def = self
             .grammar_map
             .get(&grammar_name)
@@ -139,7 +149,9 @@ impl GrammarManager {
             })?
             .clone();
 
-        let grammar = Grammar::from_def(config_path, &def)?;
+        let grammar = Grammar::from_def(config_path, &def).inspect_err(|_| {
+            self.failed_grammars.insert(grammar_name.clone());
+        })?;
         self.loaded_grammars
             .insert(grammar_name.clone(), Arc::new(grammar));
 
@@ -150,6 +162,97 @@ impl GrammarManager {
             .expect("Just inserted"))
     }
 
+    /// Resolve a language name as written in an injection (a fence info string,
+    /// `injection.language` capture, or `#set!` property) to a known language.
+    ///
+    /// Attributes after the name (`rust,ignore`, `python {.numberLines}`) are dropped,
+    /// then the name is looked up as a language, a grammar, and finally a file extension.
+    /// Returns `None` if nothing matches or the grammar previously failed to load.
+    pub fn resolve_injection_lang(&self, name: &str) -> Option<String> {
+        let name = name
+            .trim()
+            .trim_matches('"')
+            .split(|c: char| c.is_whitespace() || c == ',' || c == '{')
+            .next()?
+            .to_lowercase();
+        if name.is_empty() {
+            return None;
+        }
+
+        let normalized = normalize_lang_name(&name);
+        let lang = if self.lang_to_grammar.contains_key(&normalized)
+            || self.grammar_map.contains_key(&normalized)
+        {
+            normalized
+        } else {
+            normalize_lang_name(self.ext_to_lang.get(&name)?)
+        };
+
+        let grammar_name = self.lang_to_grammar.get(&lang).unwrap_or(&lang);
+        if self.failed_grammars.contains(grammar_name) {
+            return None;
+        }
+        Some(lang)
+    }
+
+    /// The grammar that serves `lang`, falling back to `lang` itself as a grammar name
+    pub fn grammar_name(&self, lang: &str) -> String {
+        let normalized = normalize_lang_name(lang);
+        self.lang_to_grammar
+            .get(&normalized)
+            .cloned()
+            .unwrap_or(normalized)
+    }
+
+    /// Re-reads every cached query from disk and recompiles it.
+    ///
+    /// A query that no longer compiles keeps its previous version and is reported in the
+    /// returned errors. Queries that failed before are retried on their next use.
+    /// Returns the grammars whose queries may have changed.
+    pub fn reload_queries(&mut self, config_path: &str) -> (HashSet<String>, Vec<String>) {
+        let mut reloaded: HashSet<String> = self
+            .failed_queries
+            .drain()
+            .map(|(grammar, _)| grammar)
+            .collect();
+        let mut errors = vec![];
+
+        let cached: Vec<(String, Vec<String>)> = self
+            .query_map
+            .iter()
+            .map(|(grammar, queries)| (grammar.clone(), queries.keys().cloned().collect()))
+            .collect();
+
+        for (grammar_name, query_names) in cached {
+            let Some(grammar) = self.loaded_grammars.get(&grammar_name).cloned() else {
+                continue;
+            };
+
+            for query_name in query_names {
+                let mut visited = HashSet::new();
+                let Some(source) =
+                    self.get_query_source(config_path, &grammar.name, &query_name, &mut visited)
+                else {
+                    errors.push(format!("{grammar_name}/{query_name}.scm: query file not found"));
+                    continue;
+                };
+
+                match Query::new(&grammar.lang, &source) {
+                    Ok(query) => {
+                        self.query_map
+                            .entry(grammar_name.clone())
+                            .or_default()
+                            .insert(query_name, Arc::new(query));
+                        reloaded.insert(grammar_name.clone());
+                    }
+                    Err(e) => errors.push(format!("{grammar_name}/{query_name}.scm: {e}")),
+                }
+            }
+        }
+
+        (reloaded, errors)
+    }
+
     #[allow(clippy::type_complexity)]
     pub fn get_query_set(
         &mut self,
@@ -174,7 +277,7 @@ impl GrammarManager {
         config_path: &str,
         grammar_name: &str,
         query_name: &str,
-        visited: &mut std::collections::HashSet<String>,
+        visited: &mut HashSet<String>,
     ) -> Option<String> {
         if !visited.insert(grammar_name.to_string()) {
             return Some(String::new());
@@ -274,7 +377,7 @@ impl GrammarManager {
 
         let grammar = self.get_grammar(config_path, lang).ok()?;
 
-        let mut visited = std::collections::HashSet::new();
+        let mut visited = HashSet::new();
         let query_source =
             self.get_query_source(config_path, &grammar.name, query_name, &mut visited)?;
 
@@ -314,3 +417,126 @@ fn get_name_variants(name: &str) -> Vec<String> {
     variants.dedup();
     variants
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_grammar_is_not_reloaded() {
+        let mut manager = GrammarManager::default();
+        manager.grammar_map.insert(
+            "missing".to_string(),
+            GrammarDefinition {
+                entry: None,
+                location: Some("/nonexistent/tree-sitter-missing".to_string()),
+                name: "missing".to_string(),
+                install: None,
+            },
+        );
+        manager
+            .lang_to_grammar
+            .insert("missing_lang".to_string(), "missing".to_string());
+
+        assert!(matches!(
+            manager.get_grammar("/nonexistent", "missing_lang"),
+            Err(GrammarManagerError::LoadError(_))
+        ));
+        assert!(matches!(
+            manager.get_grammar("/nonexistent", "missing"),
+            Err(GrammarManagerError::PreviouslyFailed { .. })
+        ));
+        assert!(manager.loaded_grammars.is_empty());
+    }
+
+    #[test]
+    fn injection_names_resolve_to_languages() {
+        let mut manager = GrammarManager::default();
+        for name in ["rust", "typescript"] {
+            manager.grammar_map.insert(
+                name.to_string(),
+                GrammarDefinition {
+                    entry: None,
+                    location: None,
+                    name: name.to_string(),
+                    install: None,
+                },
+            );
+        }
+        manager
+            .lang_to_grammar
+            .insert("typescriptreact".to_string(), "typescript".to_string());
+        manager.ext_to_lang.insert("rs".to_string(), "rust".to_string());
+        manager
+            .ext_to_lang
+            .insert("tsx".to_string(), "typescriptreact".to_string());
+
+        let resolve = |manager: &GrammarManager, name| manager.resolve_injection_lang(name);
+
+        // Languages and grammars by name, with attributes after the name dropped
+        assert_eq!(resolve(&manager, "rust").as_deref(), Some("rust"));
+        assert_eq!(resolve(&manager, "Rust,ignore").as_deref(), Some("rust"));
+        assert_eq!(resolve(&manager, "\"typescriptreact\"").as_deref(), Some("typescriptreact"));
+        assert_eq!(resolve(&manager, "typescript {.numberLines}").as_deref(), Some("typescript"));
+
+        // Falls back to the name as a file extension
+        assert_eq!(resolve(&manager, "rs").as_deref(), Some("rust"));
+        assert_eq!(resolve(&manager, "tsx").as_deref(), Some("typescriptreact"));
+        assert_eq!(resolve(&manager, "python"), None);
+        assert_eq!(resolve(&manager, "  "), None);
+
+        // Languages of a grammar that failed to load aren't injected
+        manager.failed_grammars.insert("typescript".to_string());
+        assert_eq!(resolve(&manager, "tsx"), None);
+        assert_eq!(resolve(&manager, "typescript"), None);
+        assert_eq!(resolve(&manager, "rs").as_deref(), Some("rust"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reloaded_queries_are_read_from_disk() {
+        let config = std::env::temp_dir().join(format!("kerbin-queries-{}", std::process::id()));
+        let query_dir = config.join("runtime/queries/rust");
+        std::fs::create_dir_all(&query_dir).unwrap();
+        let config_path = config.to_string_lossy().into_owned();
+        let write_query = |source: &str| {
+            std::fs::write(query_dir.join("highlights.scm"), source).unwrap();
+        };
+
+        // The rust grammar is linked into the test binary, so it's loaded up front
+        let mut manager = GrammarManager::default();
+        manager.loaded_grammars.insert(
+            "rust".to_string(),
+            Arc::new(Grammar {
+                name: "rust".to_string(),
+                lang: tree_sitter_rust::LANGUAGE.into(),
+                lib: libloading::os::unix::Library::this().into(),
+            }),
+        );
+        let captures = |manager: &mut GrammarManager| {
+            let query = manager.get_query(&config_path, "rust", "highlights").unwrap();
+            query.capture_names().to_vec().join(" ")
+        };
+
+        write_query("(identifier) @variable");
+        assert_eq!(captures(&mut manager), "variable");
+
+        // Cached until reloaded
+        write_query("(identifier) @function");
+        assert_eq!(captures(&mut manager), "variable");
+
+        let (reloaded, errors) = manager.reload_queries(&config_path);
+        assert_eq!(reloaded, HashSet::from(["rust".to_string()]));
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(captures(&mut manager), "function");
+
+        // A query that no longer compiles keeps its previous version
+        write_query("(identifier @broken");
+        let (_, errors) = manager.reload_queries(&config_path);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("rust/highlights.scm"), "{errors:?}");
+        assert_eq!(captures(&mut manager), "function");
+
+        let _ = std::fs::remove_dir_all(&config);
+    }
+}
@@ -77,20 +77,25 @@ fn capture_specificity(name: &str) -> usize {
     name.matches('.').count()
 }
 
-pub fn translate_name_to_style(theme: &Theme, mut name: &str) -> Style {
-    loop {
-        if let Some(value) = theme.get(&format!("ts.{name}")) {
-            return value;
-        }
-
-        if let Some(last_dot_index) = name.rfind('.') {
-            name = &name[..last_dot_index];
-        } else {
-            break;
-        }
-    }
+/// The priority a capture is drawn with when spans overlap, highest wins. Explicit
+/// `#set! priority` comes first, then more specific capture names, and injected
+/// languages always draw over their host.
+pub fn span_priority(
+    query: &tree_sitter::Query,
+    pattern_index: usize,
+    capture_name: &str,
+    is_injected: bool,
+) -> i64 {
+    let base_priority = get_capture_priority(query, pattern_index);
+    let injected_bonus = if is_injected { 500 } else { 0 };
+    base_priority * 10 + capture_specificity(capture_name) as i64 + injected_bonus
+}
 
-    theme.get("ui.text").unwrap_or_default()
+pub fn translate_name_to_style(theme: &Theme, name: &str) -> Style {
+    theme
+        .get_scope(&format!("ts.{name}"))
+        .or_else(|| theme.get("ui.text"))
+        .unwrap_or_default()
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -123,6 +128,22 @@ impl<'tree, 'rope> Highlighter<'tree, 'rope> {
         Some(Self { walker })
     }
 
+    /// Creates a highlighter whose query only visits nodes intersecting `byte_range`
+    pub fn for_byte_range(
+        config_path: &str,
+        grammar_manager: &mut GrammarManager,
+        state: &'tree TreeSitterState,
+        rope: &'rope ropey::Rope,
+        byte_range: Range<usize>,
+    ) -> Option<Self> {
+        let (query, injected) = grammar_manager.get_query_set(config_path, "highlights", state)?;
+        let walker = QueryWalkerBuilder::new(state, rope, query)
+            .with_injected_queries(injected)
+            .byte_range(byte_range)
+            .build();
+        Some(Self { walker })
+    }
+
     pub fn collect_spans(mut self) -> Vec<HighlightSpan> {
         let mut spans = Vec::new();
 
@@ -137,14 +158,12 @@ impl<'tree, 'rope> Highlighter<'tree, 'rope> {
                 let range = capture.node.byte_range().start + entry.byte_offset
                     ..capture.node.byte_range().end + entry.byte_offset;
 
-                let base_priority = get_capture_priority(query, entry.query_match.pattern_index);
-                let specificity = capture_specificity(capture_name);
-                let priority = base_priority * 10
-                    + specificity as i64
-                    + match entry.is_injected {
-                        true => 500,
-                        false => 0,
-                    };
+                let priority = span_priority(
+                    query,
+                    entry.query_match.pattern_index,
+                    capture_name,
+                    entry.is_injected,
+                );
 
                 spans.push(HighlightSpan {
                     byte_range: range,
@@ -243,34 +262,314 @@ pub fn merge_overlapping_spans(mut spans: Vec<HighlightSpan>) -> Vec<HighlightSp
 }
 
 
+/// Lines above and below the viewport that are highlighted ahead of scrolling
+const VIEWPORT_MARGIN: usize = 50;
+
+/// Tracks which lines of a buffer carry up-to-date highlight extmarks,
+/// so only lines that were edited or scrolled into view get re-queried
+#[derive(State, Default)]
+pub struct HighlightCache {
+    valid: Vec<bool>,
+}
+
+impl HighlightCache {
+    /// Shifts cached lines for an edit that replaced lines `start..=old_end` with
+    /// `start..=new_end`, marking every touched line as stale
+    pub fn apply_edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+        if start >= self.valid.len() {
+            return;
+        }
+        let old_end = (old_end + 1).min(self.valid.len());
+        let new_len = new_end.saturating_sub(start) + 1;
+        self.valid
+            .splice(start..old_end, std::iter::repeat_n(false, new_len));
+    }
+
+    /// Marks the given lines as needing a re-highlight
+    pub fn invalidate(&mut self, lines: Range<usize>) {
+        let end = lines.end.min(self.valid.len());
+        if lines.start < end {
+            self.valid[lines.start..end].fill(false);
+        }
+    }
+
+    /// Marks the given lines as highlighted
+    pub fn mark_valid(&mut self, lines: Range<usize>) {
+        if self.valid.len() < lines.end {
+            self.valid.resize(lines.end, false);
+        }
+        self.valid[lines].fill(true);
+    }
+
+    /// Returns the contiguous runs of stale lines within `lines`
+    pub fn stale_runs(&self, lines: Range<usize>) -> Vec<Range<usize>> {
+        let mut runs = vec![];
+        let mut run_start = None;
+
+        for line in lines.clone() {
+            let valid = self.valid.get(line).copied().unwrap_or(false);
+            match (valid, run_start) {
+                (false, None) => run_start = Some(line),
+                (true, Some(start)) => {
+                    runs.push(start..line);
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(start) = run_start {
+            runs.push(start..lines.end);
+        }
+        runs
+    }
+}
+
+/// Grows `lines` until no highlight extmark crosses its boundary, so the marks
+/// it covers can be removed and re-emitted without losing parts of multi-line spans
+fn expand_over_marks(buf: &TextBuffer, namespace: &str, mut lines: Range<usize>) -> Range<usize> {
+    loop {
+        let bytes = buf.line_to_byte_clamped(lines.start)..buf.line_to_byte_clamped(lines.end);
+
+        let (lo, hi) = buf
+            .renderer
+            .query_extmarks(bytes.clone())
+            .into_iter()
+            .filter(|e| {
+                e.namespace == namespace
+                    && e.byte_range.start < bytes.end
+                    && e.byte_range.end > bytes.start
+            })
+            .fold((bytes.start, bytes.end), |(lo, hi), e| {
+                (lo.min(e.byte_range.start), hi.max(e.byte_range.end))
+            });
+
+        let expanded = buf.byte_to_line_clamped(lo).min(lines.start)
+            ..(buf.byte_to_line_clamped(hi.saturating_sub(1)) + 1).max(lines.end);
+
+        if expanded == lines {
+            return lines;
+        }
+        lines = expanded;
+    }
+}
+
+/// The line runs to re-highlight so the viewport of `viewport_height` rows, and where a
+/// smooth scroll is heading, are covered with `VIEWPORT_MARGIN` lines on either side.
+/// Each run is stale in `cache` and grown over the highlight marks crossing it
+fn viewport_runs(
+    cache: &HighlightCache,
+    buf: &TextBuffer,
+    namespace: &str,
+    viewport_height: usize,
+) -> Vec<Range<usize>> {
+    let (first, last) = (
+        buf.renderer.visual_scroll.min(buf.renderer.byte_scroll),
+        buf.renderer.visual_scroll.max(buf.renderer.byte_scroll),
+    );
+    let visible = first.saturating_sub(VIEWPORT_MARGIN)
+        ..(last + viewport_height + VIEWPORT_MARGIN).min(buf.len_lines());
+
+    cache
+        .stale_runs(visible)
+        .into_iter()
+        .map(|run| expand_over_marks(buf, namespace, run))
+        .collect()
+}
+
+/// Restricts spans to `bytes`. Highlights are clipped; conceals are kept whole
+/// when they start inside the range, since a partial conceal would change its meaning.
+fn clip_spans(spans: Vec<HighlightSpan>, bytes: &Range<usize>) -> Vec<HighlightSpan> {
+    spans
+        .into_iter()
+        .filter_map(|mut span| {
+            if span.is_conceal {
+                return bytes.contains(&span.byte_range.start).then_some(span);
+            }
+            let start = span.byte_range.start.max(bytes.start);
+            let end = span.byte_range.end.min(bytes.end);
+            if start >= end {
+                return None;
+            }
+            span.byte_range = start..end;
+            Some(span)
+        })
+        .collect()
+}
+
 pub async fn highlight_file(
     buffers: ResMut<Buffers>,
     grammars: ResMut<GrammarManager>,
     config_path: Res<ConfigFolder>,
     theme: Res<Theme>,
+    chunks: Res<Chunks>,
+    split: Res<SplitState>,
 ) {
-    get!(mut buffers, mut grammars, config_path, theme);
+    get!(mut buffers, mut grammars, config_path, theme, chunks, split);
     let Some(mut buf) = buffers.cur_text_buffer_mut().await else {
         return;
     };
 
-    if buf.byte_changes.is_empty() {
-        return;
-    }
-
-    let Some(state) = buf.get_state_mut::<TreeSitterState>().await else {
+    let Some(mut state) = buf.get_state_mut::<TreeSitterState>().await else {
         return;
     };
 
     let namespace = "tree-sitter::highlights";
-    buf.renderer.clear_extmark_ns(namespace);
 
-    let Some(highlighter) =
-        Highlighter::new(&config_path.0, &mut grammars, &state, buf.get_rope())
-    else {
-        return;
-    };
+    if !buf.has_state::<HighlightCache>() {
+        buf.renderer.clear_extmark_ns(namespace);
+        buf.set_state(HighlightCache::default());
+    }
+    let mut cache = buf
+        .get_state_mut::<HighlightCache>()
+        .await
+        .expect("State just inserted");
+
+    for [start, old_end, new_end] in &buf.byte_changes {
+        cache.apply_edit(start.0.0, old_end.0.0, new_end.0.0);
+    }
+    for range in state.changed_ranges.drain(..) {
+        let first = buf.byte_to_line_clamped(range.start);
+        let last = buf.byte_to_line_clamped(range.end);
+        cache.invalidate(first..last + 1);
+    }
 
-    emit_spans(highlighter.collect_spans(), namespace, &mut buf, &theme);
+    let viewport_height = split
+        .focused_leaf_idx()
+        .and_then(|i| chunks.rect_for_indexed_chunk::<BufferChunk>(i))
+        .or_else(|| chunks.rect_for_chunk(&BufferChunk::static_name()))
+        .map(|r| r.height as usize)
+        .unwrap_or(0);
+
+    for lines in viewport_runs(&cache, &buf, namespace, viewport_height) {
+        let bytes = buf.line_to_byte_clamped(lines.start)..buf.line_to_byte_clamped(lines.end);
+
+        let Some(highlighter) = Highlighter::for_byte_range(
+            &config_path.0,
+            &mut grammars,
+            &state,
+            buf.get_rope(),
+            bytes.clone(),
+        ) else {
+            return;
+        };
+        let spans = clip_spans(highlighter.collect_spans(), &bytes);
+
+        buf.renderer.remove_extmarks_in_range(namespace, &bytes);
+        emit_spans(spans, namespace, &mut buf, &theme);
+        cache.mark_valid(lines);
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tree_sitter::{Language, Query};
+
+    use super::*;
+
+    /// Syntax nodes in the large file the viewport is highlighted in
+    const SYNTAX_NODES: usize = 200_000;
+
+    /// A function of `let` statements, sized to have about `SYNTAX_NODES` syntax nodes
+    fn large_file(language: &Language) -> String {
+        let file = |statements: usize| {
+            format!("fn main() {{\n{}}}\n", "    let x = 1;\n".repeat(statements))
+        };
+        let nodes = |statements| {
+            let state = TreeSitterState::parse("rust", language, &file(statements));
+            state.tree.unwrap().root_node().descendant_count()
+        };
+
+        file(SYNTAX_NODES / (nodes(2) - nodes(1)))
+    }
+
+    #[test]
+    fn test_viewport_highlights_fraction_of_large_file() {
+        let language = tree_sitter_rust::LANGUAGE.into();
+        let text = large_file(&language);
+        let state = TreeSitterState::parse("rust", &language, &text);
+        assert!(state.tree.as_ref().unwrap().root_node().descendant_count() >= SYNTAX_NODES);
+
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, &text);
+        let middle = buf.len_lines() / 2;
+        buf.renderer.byte_scroll = middle;
+        buf.renderer.visual_scroll = middle;
+
+        let mut cache = HighlightCache::default();
+        let runs = viewport_runs(&cache, &buf, "test", 40);
+        assert_eq!(runs, vec![middle - VIEWPORT_MARGIN..middle + 40 + VIEWPORT_MARGIN]);
+
+        // Nodes the highlight query captures, in the whole file and around the viewport
+        let highlights = include_str!("../../../config/runtime/queries/rust/highlights.scm");
+        let query = Arc::new(Query::new(&language, highlights).unwrap());
+        let captured = |byte_range: Option<Range<usize>>| {
+            let mut walker = QueryWalkerBuilder::new(&state, buf.get_rope(), query.clone());
+            if let Some(byte_range) = byte_range {
+                walker = walker.byte_range(byte_range);
+            }
+            Highlighter { walker: walker.build() }.collect_spans().len()
+        };
+        let run = &runs[0];
+        let bytes = buf.line_to_byte_clamped(run.start)..buf.line_to_byte_clamped(run.end);
+        let (full, viewport) = (captured(None), captured(Some(bytes)));
+        assert!(viewport * 50 < full, "viewport captured {viewport} of {full} nodes");
+
+        for run in runs {
+            cache.mark_valid(run);
+        }
+
+        // Scrolling down only queries the lines that came into reach
+        buf.renderer.byte_scroll = middle + 10;
+        buf.renderer.visual_scroll = middle + 10;
+        let runs = viewport_runs(&cache, &buf, "test", 40);
+        assert_eq!(runs, vec![middle + 40 + VIEWPORT_MARGIN..middle + 50 + VIEWPORT_MARGIN]);
+    }
+
+    #[test]
+    fn test_viewport_runs_grow_over_crossing_marks() {
+        let mut buf = TextBuffer::scratch();
+        buf.insert(0, &"let x = 1;\n".repeat(200));
+
+        // A multi-line span, like a block comment, from line 80 into line 120
+        let bytes = buf.line_to_byte_clamped(80)..buf.line_to_byte_clamped(120) + 3;
+        buf.add_extmark(ExtmarkBuilder::new_range("test", bytes));
+
+        // Lines 100..140 are stale, and the span pulls in the valid lines it starts on
+        let mut cache = HighlightCache::default();
+        cache.mark_valid(0..100);
+        assert_eq!(viewport_runs(&cache, &buf, "test", 90), vec![80..140]);
+    }
+
+    #[test]
+    fn test_scrolled_viewport_only_requeries_new_lines() {
+        let mut cache = HighlightCache::default();
+        cache.mark_valid(0..100);
+
+        assert_eq!(cache.stale_runs(50..150), vec![100..150]);
+        assert!(cache.stale_runs(0..100).is_empty());
+    }
+
+    #[test]
+    fn test_edit_invalidates_only_touched_lines() {
+        let mut cache = HighlightCache::default();
+        cache.mark_valid(0..10_000);
+
+        // Replace line 10 with three lines
+        cache.apply_edit(10, 10, 12);
+
+        assert_eq!(cache.stale_runs(0..10_002), vec![10..13]);
+    }
+
+    #[test]
+    fn test_invalidate_changed_ranges() {
+        let mut cache = HighlightCache::default();
+        cache.mark_valid(0..20);
+        cache.invalidate(5..8);
+        cache.invalidate(15..30);
+
+        assert_eq!(cache.stale_runs(0..20), vec![5..8, 15..20]);
+    }
+}
@@ -8,6 +8,26 @@ use crate::{
     state::TreeSitterState,
 };
 
+/// Settings for tree-sitter auto-indent, reachable as `set indent.<field>`
+#[derive(State, ConfigurableState, Default)]
+#[configurable(name = "indent")]
+pub struct IndentConfig {
+    /// Spaces per level auto-indent inserts in buffers indented with spaces.
+    /// 0 follows the width detected for the buffer, or `core.default_tab_unit`
+    pub width: usize,
+}
+
+impl IndentConfig {
+    /// The indent style auto-indent uses in a buffer indented with `style`.
+    /// Tab-indented buffers keep their tabs
+    pub fn style_for(&self, style: &IndentStyle) -> IndentStyle {
+        match style {
+            IndentStyle::Spaces(_) if self.width > 0 => IndentStyle::Spaces(self.width),
+            style => style.clone(),
+        }
+    }
+}
+
 pub async fn newline_intercept(cmd: &BufferCommand, state: &mut State) -> InterceptorResult {
     match cmd {
         BufferCommand::Append { text, .. } if text == "\n" => {}
@@ -23,6 +43,7 @@ async fn newline_and_indent(state: &mut State) {
     let mut grammars = state.lock_state::<GrammarManager>().await;
     let config_path = state.lock_state::<ConfigFolder>().await.0.clone();
     let auto_pairs = state.lock_state::<AutoPairs>().await;
+    let indent_config = state.lock_state::<IndentConfig>().await;
 
     let Some(mut buf) = buffers.cur_text_buffer_mut().await else {
         return;
@@ -83,15 +104,10 @@ async fn newline_and_indent(state: &mut State) {
         return;
     };
 
-    let indent_str = calculate_indent(
-        &ts_state,
-        &buf,
-        query,
-        injected,
-        cursor_byte,
-        query_byte,
-        &current_line_indent,
-    );
+    let style = indent_config.style_for(&buf.indent_style);
+    let indent_str = compute_indent(&ts_state, &buf, query, &injected, cursor_byte, query_byte)
+        .map(|indent| indent.to_indent_string(&style))
+        .unwrap_or(current_line_indent);
 
     if !indent_str.is_empty() {
         buf.action(Insert {
@@ -141,25 +157,47 @@ struct IndentCapture {
     scope: Scope,
 }
 
-fn calculate_indent(
+/// Indentation expected at an insertion point, as derived from `indents.scm` captures
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComputedIndent {
+    /// Number of indent units, rendered with the buffer's `IndentStyle`
+    Levels(usize),
+    /// Exact column to align to, from an `@align` capture's `@anchor`
+    Align(usize),
+}
+
+impl ComputedIndent {
+    /// Renders the indent as the whitespace to insert at the start of a line
+    pub fn to_indent_string(self, style: &IndentStyle) -> String {
+        match self {
+            ComputedIndent::Levels(levels) => style.tab_string().repeat(levels),
+            ComputedIndent::Align(col) => " ".repeat(col),
+        }
+    }
+}
+
+/// Walks from the syntax node at `cursor_byte` to the root, summing the
+/// indent/outdent captures that apply to a new line inserted there.
+///
+/// `query_byte` bounds which captures are considered (captures starting after it are
+/// ignored), which lets callers treat the inside of a just-typed pair as the insertion point.
+/// Returns `None` when no capture applies, so callers can fall back to copying indent.
+pub fn compute_indent(
     state: &TreeSitterState,
     buf: &TextBuffer,
     query: Arc<Query>,
-    injected: HashMap<String, Arc<Query>>,
+    injected: &HashMap<String, Arc<Query>>,
     cursor_byte: usize,
     query_byte: usize,
-    fallback_indent: &str,
-) -> String {
+) -> Option<ComputedIndent> {
+    let tree = state.tree.as_ref()?;
+
     let query_end_byte = query_byte
         .saturating_add(1)
         .min(buf.len().saturating_add(1));
 
-    let captures = collect_indent_captures(state, buf.get_rope(), query, &injected, query_end_byte);
+    let captures = collect_indent_captures(state, buf.get_rope(), query, injected, query_end_byte);
 
-    let tree = match state.tree.as_ref() {
-        Some(t) => t,
-        None => return fallback_indent.to_string(),
-    };
     let root = tree.root_node();
 
     let deepest = root
@@ -232,18 +270,10 @@ fn calculate_indent(
     }
 
     if let Some(col) = align_col {
-        return " ".repeat(col);
+        return Some(ComputedIndent::Align(col));
     }
 
-    let tab_str = buf.indent_style.tab_string();
-
-    if !any_captures {
-        fallback_indent.to_string()
-    } else if indent_delta > 0 {
-        tab_str.repeat(indent_delta as usize)
-    } else {
-        String::new()
-    }
+    any_captures.then_some(ComputedIndent::Levels(indent_delta.max(0) as usize))
 }
 
 fn collect_indent_captures(
@@ -422,3 +452,19 @@ fn check_not_same_line(predicate: &QueryPredicate, entry: &QueryMatchEntry) -> b
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indent_width_overrides_space_indent_only() {
+        let config = IndentConfig { width: 2 };
+        assert_eq!(config.style_for(&IndentStyle::Spaces(4)), IndentStyle::Spaces(2));
+        assert_eq!(config.style_for(&IndentStyle::Tabs), IndentStyle::Tabs);
+
+        let detected = IndentConfig::default();
+        assert_eq!(detected.style_for(&IndentStyle::Spaces(4)), IndentStyle::Spaces(4));
+        assert_eq!(ComputedIndent::Levels(2).to_indent_string(&IndentStyle::Spaces(2)), "    ");
+    }
+}
@@ -3,6 +3,7 @@ use kerbin_core::*;
 use crate::grammar_manager::GrammarManager;
 
 #[derive(Command)]
+#[command(category = "tree-sitter")]
 pub enum InstallCommand {
     /// Installs all non-installed grammars onto your system in parallel
     #[command]
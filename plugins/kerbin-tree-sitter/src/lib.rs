@@ -1,6 +1,8 @@
 use kerbin_core::*;
 
 use crate::{
+    folds::FoldCommand,
+    indent::IndentConfig,
     install_command::InstallCommand,
     motions::TreeSitterMotion,
     scope_info::ScopeInfoCommand,
@@ -33,13 +35,23 @@ pub mod motions;
 
 pub mod locals;
 
-async fn reset_config_state(grammar_manager: ResMut<GrammarManager>, buffers: ResMut<Buffers>) {
+pub mod folds;
+
+async fn reset_config_state(
+    grammar_manager: ResMut<GrammarManager>,
+    buffers: ResMut<Buffers>,
+    indent_config: ResMut<IndentConfig>,
+) {
+    *indent_config.get().await = IndentConfig::default();
+
     let mut manager = grammar_manager.get().await;
     manager.grammar_map.clear();
     manager.loaded_grammars.clear();
     manager.query_map.clear();
     manager.failed_queries.clear();
     manager.lang_to_grammar.clear();
+    manager.ext_to_lang.clear();
+    manager.failed_grammars.clear();
     drop(manager);
 
     let bufs = buffers.get().await;
@@ -50,6 +62,7 @@ async fn reset_config_state(grammar_manager: ResMut<GrammarManager>, buffers: Re
         if let Some(tb) = buf.as_any_mut().downcast_mut::<TextBuffer>() {
             tb.flags.remove("tree-sitter-checked");
             tb.remove_state::<TreeSitterState>();
+            tb.remove_state::<highlighter::HighlightCache>();
         }
     }
 }
@@ -60,6 +73,7 @@ define_plugin! {
 
     state: [
         GrammarManager,
+        IndentConfig,
     ],
 
     commands: [
@@ -67,6 +81,7 @@ define_plugin! {
         InstallCommand,
         ScopeInfoCommand,
         TreeSitterMotion,
+        FoldCommand,
     ],
 
     hooks: [
@@ -77,6 +92,11 @@ define_plugin! {
 pub async fn init(state: &mut State) {
     plugin_init(state).await;
 
+    state
+        .lock_state::<ConfigurableRegistry>()
+        .await
+        .register::<IndentConfig>();
+
     // Extra: newline interceptor can't be expressed in define_plugin!
     state
         .lock_state::<CommandInterceptorRegistry>()
@@ -3,6 +3,7 @@ use kerbin_core::*;
 use crate::{locals::find_highlight_ranges, state::TreeSitterState};
 
 #[derive(Command)]
+#[command(category = "tree-sitter")]
 pub enum TreeSitterMotion {
     #[command(drop_ident, name = "ts_select_node", name = "tssn")]
     /// Select the smallest named AST node covering the cursor.
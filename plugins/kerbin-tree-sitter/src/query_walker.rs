@@ -24,6 +24,7 @@ pub struct QueryWalker<'tree, 'rope> {
     rope: &'rope Rope,
     main_query: Arc<Query>,
     injected_queries: HashMap<String, Arc<Query>>,
+    byte_range: Option<std::ops::Range<usize>>,
     cursor: QueryCursor,
 }
 
@@ -33,7 +34,11 @@ impl<'tree, 'rope> QueryWalker<'tree, 'rope> {
     where
         F: FnMut(QueryMatchEntry) -> bool,
     {
-        let text_provider = TextProviderRope(self.rope);
+        let text_provider = TextProviderRope(self.rope, 0);
+
+        if let Some(range) = &self.byte_range {
+            self.cursor.set_byte_range(range.clone());
+        }
 
         let mut matches = self.cursor.matches(
             &self.main_query,
@@ -61,12 +66,24 @@ impl<'tree, 'rope> QueryWalker<'tree, 'rope> {
         }
 
         for (idx, injected) in self.state.injected_trees.iter().enumerate() {
+            if let Some(range) = &self.byte_range {
+                if injected.byte_range.end <= range.start || injected.byte_range.start >= range.end
+                {
+                    continue;
+                }
+                // Injected trees are parsed from offset 0, so shift the range into their coordinates
+                let offset = injected.byte_range.start;
+                self.cursor
+                    .set_byte_range(range.start.saturating_sub(offset)..range.end - offset);
+            }
+
             // Use language-specific query if available, otherwise use main query
             let query = self
                 .injected_queries
                 .get(&injected.lang)
                 .unwrap_or(&self.main_query);
 
+            let text_provider = TextProviderRope(self.rope, injected.byte_range.start);
             let mut matches = self
                 .cursor
                 .matches(query, injected.tree.root_node(), &text_provider);
@@ -218,6 +235,7 @@ impl<'tree, 'rope> QueryWalkerBuilder<'tree, 'rope> {
             rope: self.rope,
             main_query: self.main_query,
             injected_queries: self.injected_queries,
+            byte_range: self.byte_range,
             cursor,
         }
     }
@@ -1,12 +1,17 @@
 use kerbin_core::*;
 
 use crate::{
-    grammar_manager::GrammarManager, query_walker::QueryWalkerBuilder, state::TreeSitterState,
+    grammar_manager::GrammarManager,
+    highlighter::{is_conceal_pattern, span_priority},
+    query_walker::QueryWalkerBuilder,
+    state::TreeSitterState,
 };
 
 #[derive(Command)]
+#[command(category = "tree-sitter")]
 pub enum ScopeInfoCommand {
-    /// Shows tree-sitter capture information at cursor position
+    /// Logs the tree-sitter captures under the cursor in the order they are drawn, with
+    /// their node type, byte range and the theme key that styles each one
     #[command]
     TreeSitterScopeInfo,
 }
@@ -28,6 +33,7 @@ async fn tree_sitter_scope_info(state: &mut State) {
     let mut grammars = state.lock_state::<GrammarManager>().await;
     let config_path = state.lock_state::<ConfigFolder>().await.0.clone();
     let log = state.lock_state::<LogSender>().await.clone();
+    let theme = state.lock_state::<Theme>().await;
 
     let Some(mut buf) = buffers.cur_text_buffer_mut().await else { return; };
 
@@ -55,6 +61,7 @@ async fn tree_sitter_scope_info(state: &mut State) {
 
     let mut walker = QueryWalkerBuilder::new(&ts_state, buf.get_rope(), highlights_query)
         .with_injected_queries(injected_queries)
+        .byte_range(cursor_pos..cursor_pos + 1)
         .build();
 
     walker.walk(|entry| {
@@ -64,25 +71,32 @@ async fn tree_sitter_scope_info(state: &mut State) {
                 (node_range.start + entry.byte_offset)..(node_range.end + entry.byte_offset);
 
             if cursor_pos >= adjusted_range.start && cursor_pos < adjusted_range.end {
+                let pattern_index = entry.query_match.pattern_index;
                 let capture_name = entry.query.capture_names()[capture.index as usize];
-                let node_kind = capture.node.kind();
                 let node_text = buf
                     .slice_to_string(adjusted_range.start, adjusted_range.end)
                     .unwrap_or_default();
 
-                let display_text = if node_text.len() > 50 {
-                    format!("{}...", &node_text[..47])
-                } else {
-                    node_text
+                let display_text = match node_text.char_indices().nth(47) {
+                    Some((cut, _)) => format!("{}...", &node_text[..cut]),
+                    None => node_text,
                 };
 
                 captures_at_cursor.push(CaptureInfo {
                     capture_name: capture_name.to_string(),
-                    node_kind: node_kind.to_string(),
-                    node_text: display_text,
+                    node_kind: capture.node.kind().to_string(),
+                    node_text: display_text.replace('\n', "\\n"),
+                    byte_range: adjusted_range,
                     lang: entry.lang.clone(),
                     is_injected: entry.is_injected,
-                    specificity: capture_name.matches('.').count(),
+                    is_conceal: is_conceal_pattern(&entry.query, pattern_index),
+                    priority: span_priority(
+                        &entry.query,
+                        pattern_index,
+                        capture_name,
+                        entry.is_injected,
+                    ),
+                    capture_index: capture.index,
                 });
             }
         }
@@ -94,39 +108,40 @@ async fn tree_sitter_scope_info(state: &mut State) {
         return;
     }
 
+    // Same order the highlighter resolves overlaps in, so the first capture is the one drawn
     captures_at_cursor.sort_by(|a, b| {
-        b.specificity
-            .cmp(&a.specificity)
-            .then_with(|| a.capture_name.cmp(&b.capture_name))
+        b.priority
+            .cmp(&a.priority)
+            .then_with(|| b.capture_index.cmp(&a.capture_index))
     });
 
-    let mut output = "Tree-Sitter Scope Info at cursor:\n\n".to_string();
+    let mut output = format!("Tree-Sitter Scope Info at byte {cursor_pos}:\n\n");
+    output.push_str(&format!("Language: {}\n\n", ts_state.lang));
 
-    let primary_capture = &captures_at_cursor[0];
-    output.push_str(&format!(
-        "Primary Language: {}\n",
-        if primary_capture.is_injected {
-            format!("{} (injected)", primary_capture.lang)
-        } else {
-            primary_capture.lang.clone()
-        }
-    ));
-    output.push_str(&format!("Node Type: {}\n", primary_capture.node_kind));
-    output.push_str(&format!("Node Text: \"{}\"\n\n", primary_capture.node_text));
-
-    output.push_str("Captures (most specific first):\n");
+    output.push_str("Captures (drawn first to last):\n");
     for (idx, capture) in captures_at_cursor.iter().enumerate() {
         let lang_marker = if capture.is_injected {
-            format!(" [{}]", capture.lang)
+            format!(" [{} injected]", capture.lang)
         } else {
             String::new()
         };
+        let conceal_marker = if capture.is_conceal { " (conceal)" } else { "" };
 
         output.push_str(&format!(
-            "  {}. @{}{}\n",
+            "  {}. @{}{}{} priority {}\n",
             idx + 1,
             capture.capture_name,
-            lang_marker
+            lang_marker,
+            conceal_marker,
+            capture.priority,
+        ));
+        output.push_str(&format!(
+            "     node: {} {}..{} \"{}\"\n",
+            capture.node_kind, capture.byte_range.start, capture.byte_range.end, capture.node_text
+        ));
+        output.push_str(&format!(
+            "     theme: {}\n",
+            theme_key_chain(&theme, &capture.capture_name)
         ));
     }
 
@@ -161,12 +176,63 @@ async fn tree_sitter_scope_info(state: &mut State) {
     log.low("tree-sitter::scope_info", output);
 }
 
+/// The theme keys tried for a capture, ending at the first one that is set.
+/// Unset keys are marked with `(unset)`, mirroring `translate_name_to_style`.
+fn theme_key_chain(theme: &Theme, capture_name: &str) -> String {
+    let mut chain = vec![];
+    let mut name = format!("ts.{capture_name}");
+
+    loop {
+        if theme.get(&name).is_some() {
+            chain.push(name);
+            return chain.join(" -> ");
+        }
+        chain.push(format!("{name} (unset)"));
+
+        match name.rfind('.') {
+            Some(dot) => name.truncate(dot),
+            None => break,
+        }
+    }
+
+    let fallback = match theme.get("ui.text") {
+        Some(_) => "ui.text",
+        None => "default style",
+    };
+    chain.push(fallback.to_string());
+    chain.join(" -> ")
+}
+
 #[derive(Debug, Clone)]
 struct CaptureInfo {
     capture_name: String,
     node_kind: String,
     node_text: String,
+    byte_range: std::ops::Range<usize>,
     lang: String,
     is_injected: bool,
-    specificity: usize,
+    is_conceal: bool,
+    priority: i64,
+    capture_index: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::style::Style;
+
+    #[test]
+    fn theme_chain_stops_at_first_set_key() {
+        let mut theme = Theme::default();
+        theme.register("ts.keyword".to_string(), Style::default());
+
+        assert_eq!(
+            theme_key_chain(&theme, "keyword.control.rust"),
+            "ts.keyword.control.rust (unset) -> ts.keyword.control (unset) -> ts.keyword"
+        );
+        assert_eq!(
+            theme_key_chain(&theme, "string"),
+            "ts.string (unset) -> ts (unset) -> default style"
+        );
+    }
 }
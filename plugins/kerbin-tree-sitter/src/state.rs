@@ -4,28 +4,15 @@ use ratatui::style::Style;
 use tree_sitter::{Parser, Tree};
 
 use crate::{
-    grammar_manager::GrammarManager,
-    highlighter::{HighlightSpan, Highlighter, merge_overlapping_spans},
+    grammar_manager::{GrammarManager, GrammarManagerError},
+    highlighter::{
+        HighlightCache, HighlightSpan, Highlighter, merge_overlapping_spans,
+        translate_name_to_style,
+    },
     locals::LocalsAnalysis,
     query_walker::QueryWalkerBuilder,
 };
 
-fn translate_name_to_style(theme: &Theme, mut name: &str) -> Style {
-    loop {
-        if let Some(value) = theme.get(&format!("ts.{name}")) {
-            return value;
-        }
-
-        if let Some(last_dot_index) = name.rfind('.') {
-            name = &name[..last_dot_index];
-        } else {
-            break;
-        }
-    }
-
-    theme.get("ui.text").unwrap_or_default()
-}
-
 pub struct InjectedTree {
     pub lang: String,
     pub tree: Tree,
@@ -40,6 +27,10 @@ pub struct TreeSitterState {
     pub injected_trees: Vec<InjectedTree>,
     pub locals_analysis: Option<LocalsAnalysis>,
     pub locals_cursor_byte: Option<usize>,
+    /// Whether the buffer's fold ranges match the current tree
+    pub folds_computed: bool,
+    /// Byte ranges whose syntax changed in the last reparse, consumed by the highlighter
+    pub changed_ranges: Vec<std::ops::Range<usize>>,
 }
 
 pub async fn update_trees(
@@ -105,6 +96,13 @@ pub async fn update_trees(
     );
 
     if let Some(new_tree) = new_tree {
+        if let Some(old_tree) = &tree {
+            state.changed_ranges.extend(
+                old_tree
+                    .changed_ranges(&new_tree)
+                    .map(|r| r.start_byte..r.end_byte),
+            );
+        }
         state.tree = Some(new_tree);
     } else {
         log.critical("tree-sitter::update_trees", "Failed to reparse main tree");
@@ -121,8 +119,11 @@ pub async fn update_trees(
         injected_trees: vec![],
         locals_analysis: None,
         locals_cursor_byte: None,
+        folds_computed: false,
+        changed_ranges: vec![],
     };
     state.locals_analysis = None;
+    state.folds_computed = false;
 
     let injected_trees =
         load_injected_trees(&temp_state, &mut grammars, &config_path.0, buf.get_rope());
@@ -134,18 +135,25 @@ pub async fn open_files(
     buffers: ResMut<Buffers>,
     grammars: ResMut<GrammarManager>,
     config_path: Res<ConfigFolder>,
-    theme: Res<Theme>,
+    filetypes: Res<FiletypeRegistry>,
 
     log: Res<LogSender>,
 ) {
-    get!(mut buffers, mut grammars, config_path, theme, log);
+    get!(mut buffers, mut grammars, config_path, filetypes, log);
 
     let Some(mut buf) = buffers.cur_text_buffer_mut().await else { return; };
 
-    if buf.flags.contains("tree-sitter-checked") || buf.has_state::<TreeSitterState>() {
+    if buf.big_file
+        || buf.flags.contains("tree-sitter-checked")
+        || buf.has_state::<TreeSitterState>()
+    {
         return;
     }
 
+    if grammars.ext_to_lang.is_empty() {
+        grammars.ext_to_lang = filetypes.ext_map.clone();
+    }
+
     buf.flags.insert("tree-sitter-checked");
 
     let Some(lang) = buf.filetype.clone() else {
@@ -154,10 +162,12 @@ pub async fn open_files(
 
     let grammar = match grammars.get_grammar(&config_path.0, &lang) {
         Ok(g) => g,
+        // Already reported when the grammar first failed
+        Err(GrammarManagerError::PreviouslyFailed { .. }) => return,
         Err(e) => {
             log.critical(
                 "tree-sitter::open_file",
-                format!("Failed to load grammar due to error: {e}"),
+                format!("Failed to load grammar for {lang}: {e}"),
             );
             return;
         }
@@ -203,6 +213,8 @@ pub async fn open_files(
         injected_trees: vec![],
         locals_analysis: None,
         locals_cursor_byte: None,
+        folds_computed: false,
+        changed_ranges: vec![],
     };
 
     // Load injected trees using the initial state
@@ -221,23 +233,34 @@ pub async fn open_files(
         injected_trees,
         locals_analysis: None,
         locals_cursor_byte: None,
+        folds_computed: false,
+        changed_ranges: vec![],
     });
 
-    let state = buf
-        .get_state_mut::<TreeSitterState>()
-        .await
-        .expect("State just inserted");
+    // Highlighting is left to `highlight_file`, which only queries the visible lines
+    buf.remove_state::<HighlightCache>();
+}
 
-    let Some(highlighter) = Highlighter::new(&config_path.0, &mut grammars, &state, buf.get_rope())
-    else {
+/// Rebuilds the injected trees, highlights and locals of `buf` from the current queries,
+/// for after the queries were reloaded from disk
+pub async fn refresh_highlights(
+    buf: &mut TextBuffer,
+    grammars: &mut GrammarManager,
+    config_path: &str,
+) {
+    let rope = buf.get_rope().clone();
+    let Some(mut state) = buf.get_state_mut::<TreeSitterState>().await else {
         return;
     };
 
-    let spans = highlighter.collect_spans();
-
-    buf.renderer.clear_extmark_ns("tree-sitter::highlights");
+    state.injected_trees = load_injected_trees(&state, grammars, config_path, &rope);
+    state.locals_analysis = None;
+    state.locals_cursor_byte = None;
+    state.folds_computed = false;
+    drop(state);
 
-    emit_spans(spans, "tree-sitter::highlights", &mut buf, &theme);
+    // Without a cache, `highlight_file` clears the old marks and starts over
+    buf.remove_state::<HighlightCache>();
 }
 
 pub fn emit_spans(spans: Vec<HighlightSpan>, namespace: &str, buf: &mut TextBuffer, theme: &Theme) {
@@ -321,15 +344,23 @@ fn load_injected_trees(
             }
         }
 
-        if let Some(inj_lang) = injection_lang {
-            for content_node in content_nodes {
-                match parse_injection(grammars, config_path, &inj_lang, content_node, rope) {
-                    Ok(injected_tree) => {
-                        injected_trees.push(injected_tree);
-                    }
-                    Err(e) => {
-                        tracing::error!("Failed to parse {} injection: {}", inj_lang, e);
-                    }
+        let Some(inj_lang) = injection_lang else {
+            return true;
+        };
+
+        // Injections for languages without an installed grammar are left unhighlighted
+        let Some(resolved_lang) = grammars.resolve_injection_lang(&inj_lang) else {
+            tracing::debug!("tree-sitter: no grammar for injected language '{inj_lang}'");
+            return true;
+        };
+
+        for content_node in content_nodes {
+            match parse_injection(grammars, config_path, &resolved_lang, content_node, rope) {
+                Ok(injected_tree) => {
+                    injected_trees.push(injected_tree);
+                }
+                Err(e) => {
+                    tracing::debug!("Skipping {} injection: {}", resolved_lang, e);
                 }
             }
         }
@@ -440,6 +471,8 @@ pub fn highlight_text(
         injected_trees: vec![],
         locals_analysis: None,
         locals_cursor_byte: None,
+        folds_computed: false,
+        changed_ranges: vec![],
     };
 
     // Load injected trees (using the existing private function)
@@ -508,3 +541,24 @@ pub fn highlight_text(
 
     result
 }
+
+#[cfg(test)]
+impl TreeSitterState {
+    /// Parses `text` with `language`, without injections
+    pub(crate) fn parse(lang: &str, language: &tree_sitter::Language, text: &str) -> Self {
+        let mut parser = Parser::new();
+        parser.set_language(language).unwrap();
+        let tree = parser.parse(text, None);
+
+        Self {
+            lang: lang.to_string(),
+            parser,
+            tree,
+            injected_trees: vec![],
+            locals_analysis: None,
+            locals_cursor_byte: None,
+            folds_computed: false,
+            changed_ranges: vec![],
+        }
+    }
+}
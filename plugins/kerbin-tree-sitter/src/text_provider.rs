@@ -1,12 +1,14 @@
 use ropey::Rope;
 use tree_sitter::TextProvider;
 
-pub struct TextProviderRope<'a>(pub &'a Rope);
+/// Supplies node text from a rope. The offset is added to node positions, which is
+/// needed for injected trees since they are parsed starting at byte 0.
+pub struct TextProviderRope<'a>(pub &'a Rope, pub usize);
 
 impl<'a> TextProvider<&'a [u8]> for &'a TextProviderRope<'a> {
     type I = ChunksBytes<'a>;
     fn text(&mut self, node: tree_sitter::Node) -> Self::I {
-        let mut byte_range = node.byte_range();
+        let mut byte_range = node.start_byte() + self.1..node.end_byte() + self.1;
 
         if self.0.len_bytes() <= byte_range.start {
             return ChunksBytes(None);
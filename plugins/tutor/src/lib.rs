@@ -3,6 +3,7 @@ use kerbin_core::*;
 pub mod load;
 
 #[derive(Command)]
+#[command(category = "tutor")]
 pub enum TutorCommands {
     /// Create a tutor buffer
     #[command]